@@ -0,0 +1,301 @@
+//! Drawing documents whether or not there's a usable GPU.
+//!
+//! [`RenderBackend`] covers what the viewport needs: uploading images,
+//! blending them, drawing a document's layers as quads and reading the
+//! result back. [`Renderer`] and [`HeadlessRenderer`] implement it with
+//! wgpu, and [`SoftwareBackend`] on the CPU for CI, servers and VMs without
+//! a GPU. [`create_backend`] and [`create_headless_backend`] pick one at
+//! runtime.
+
+use std::collections::HashMap;
+use image::DynamicImage;
+use meridian_document::{BlendMode, DocumentSnapshot};
+use crate::blend::TextureHandle;
+use crate::headless::read_texture;
+use crate::{HeadlessRenderer, RenderError, Renderer, RendererOptions, SoftwareBackend, ViewTransform};
+
+/// The environment variable [`BackendPreference::from_env`] reads.
+pub const BACKEND_ENV: &str = "ASTRIA_BACKEND";
+
+/// An image uploaded to a [`RenderBackend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageId(u64);
+
+/// What a [`RenderBackend`] draws with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Gpu,
+    Software,
+}
+
+impl BackendKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackendKind::Gpu => "GPU",
+            BackendKind::Software => "software",
+        }
+    }
+}
+
+/// Which backend to create.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackendPreference {
+    /// The GPU, or software if no device can be opened.
+    #[default]
+    Auto,
+    /// The GPU only; creating the backend fails without one.
+    Gpu,
+    Software,
+}
+
+impl BackendPreference {
+    /// `auto`, `gpu` or `software`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "auto" => Some(BackendPreference::Auto),
+            "gpu" => Some(BackendPreference::Gpu),
+            "software" => Some(BackendPreference::Software),
+            _ => None,
+        }
+    }
+
+    /// The preference named by [`BACKEND_ENV`], or `Auto` if it's unset or
+    /// not a name [`BackendPreference::from_name`] knows.
+    pub fn from_env() -> Self {
+        std::env::var(BACKEND_ENV)
+            .ok()
+            .and_then(|name| Self::from_name(&name))
+            .unwrap_or_default()
+    }
+}
+
+/// What the document viewport draws with.
+pub trait RenderBackend {
+    fn kind(&self) -> BackendKind;
+
+    /// The target size in pixels.
+    fn size(&self) -> (u32, u32);
+
+    /// Zero sizes are ignored.
+    fn resize(&mut self, width: u32, height: u32);
+
+    /// Fills the area outside the canvas.
+    fn set_background_color(&mut self, color: wgpu::Color);
+
+    /// Fills the canvas under the layers.
+    fn set_document_background(&mut self, color: wgpu::Color);
+
+    /// `image` must not be empty.
+    fn upload_image(&mut self, image: &DynamicImage) -> ImageId;
+
+    /// Frees an uploaded image. Unknown ids are ignored.
+    fn release_image(&mut self, image: ImageId);
+
+    /// Blends `top` over `bottom` like meridian_document's `blend_images`
+    /// into a new image. `None` if either isn't an uploaded image.
+    fn blend(&mut self, bottom: ImageId, top: ImageId, mode: BlendMode, opacity: f32) -> Option<ImageId>;
+
+    fn read_image(&self, image: ImageId) -> Option<DynamicImage>;
+
+    /// Draws each shown layer of `snapshot` as a quad over the document
+    /// background, placed by `view`.
+    fn render_document(&mut self, snapshot: &DocumentSnapshot, view: ViewTransform) -> Result<(), RenderError>;
+
+    /// The last frame drawn, sRGB encoded.
+    fn read_back(&mut self) -> Result<DynamicImage, RenderError>;
+}
+
+/// Images uploaded to a backend, by id.
+#[derive(Debug)]
+pub(crate) struct ImageStore<T> {
+    next: u64,
+    images: HashMap<ImageId, T>,
+}
+
+impl<T> Default for ImageStore<T> {
+    fn default() -> Self {
+        Self {
+            next: 0,
+            images: HashMap::new(),
+        }
+    }
+}
+
+impl<T> ImageStore<T> {
+    pub(crate) fn insert(&mut self, image: T) -> ImageId {
+        let id = ImageId(self.next);
+        self.next += 1;
+        self.images.insert(id, image);
+        id
+    }
+
+    pub(crate) fn get(&self, id: ImageId) -> Option<&T> {
+        self.images.get(&id)
+    }
+
+    pub(crate) fn remove(&mut self, id: ImageId) {
+        self.images.remove(&id);
+    }
+}
+
+/// A backend drawing into `window`. Software backends draw into an image,
+/// which the caller shows from [`RenderBackend::read_back`].
+pub async fn create_backend(
+    window: &winit::window::Window,
+    options: RendererOptions,
+    preference: BackendPreference,
+) -> Result<Box<dyn RenderBackend>, RenderError> {
+    let size = window.inner_size();
+    let software = || Box::new(SoftwareBackend::new(size.width, size.height)) as Box<dyn RenderBackend>;
+    match preference {
+        BackendPreference::Software => Ok(software()),
+        BackendPreference::Gpu => Ok(Box::new(Renderer::new_with_options(window, options).await?)),
+        BackendPreference::Auto => match Renderer::new_with_options(window, options).await {
+            Ok(renderer) => Ok(Box::new(renderer)),
+            Err(_) => Ok(software()),
+        },
+    }
+}
+
+/// Same as [`create_backend`] without a window. The target starts out 1×1.
+pub fn create_headless_backend(options: RendererOptions, preference: BackendPreference) -> anyhow::Result<Box<dyn RenderBackend>> {
+    let software = || Box::new(SoftwareBackend::new(1, 1)) as Box<dyn RenderBackend>;
+    match preference {
+        BackendPreference::Software => Ok(software()),
+        BackendPreference::Gpu => Ok(Box::new(HeadlessRenderer::new_with_options(options)?)),
+        BackendPreference::Auto => match HeadlessRenderer::new_with_options(options) {
+            Ok(renderer) => Ok(Box::new(renderer)),
+            Err(_) => Ok(software()),
+        },
+    }
+}
+
+impl RenderBackend for Renderer {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Gpu
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.size.width, self.size.height)
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        Renderer::resize(self, winit::dpi::PhysicalSize::new(width, height));
+    }
+
+    fn set_background_color(&mut self, color: wgpu::Color) {
+        Renderer::set_background_color(self, color);
+    }
+
+    fn set_document_background(&mut self, color: wgpu::Color) {
+        Renderer::set_document_background(self, color);
+    }
+
+    fn upload_image(&mut self, image: &DynamicImage) -> ImageId {
+        let texture = self.upload_texture(image);
+        self.images.insert(texture)
+    }
+
+    fn release_image(&mut self, image: ImageId) {
+        self.images.remove(image);
+    }
+
+    fn blend(&mut self, bottom: ImageId, top: ImageId, mode: BlendMode, opacity: f32) -> Option<ImageId> {
+        let blended = self.blend_on_gpu(self.images.get(bottom)?, self.images.get(top)?, mode, opacity);
+        Some(self.images.insert(blended))
+    }
+
+    fn read_image(&self, image: ImageId) -> Option<DynamicImage> {
+        let texture: &TextureHandle = self.images.get(image)?;
+        let pixels = read_texture(&self.device, &self.queue, &self.staging, texture.texture(), texture.size());
+        Some(DynamicImage::ImageRgba8(pixels))
+    }
+
+    /// Moves the camera to `view` and presents the frame, with the overlay.
+    fn render_document(&mut self, snapshot: &DocumentSnapshot, view: ViewTransform) -> Result<(), RenderError> {
+        self.camera.set_view(view);
+        Renderer::render_document(self, snapshot)
+    }
+
+    fn read_back(&mut self) -> Result<DynamicImage, RenderError> {
+        self.capture_frame()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use meridian_document::blend::blend_images;
+    use meridian_document::{Document, LayerTransform};
+
+    fn assert_close(actual: &Rgba<u8>, expected: [u8; 4], what: &str) {
+        let close = actual.0.iter().zip(expected).all(|(a, e)| (*a as i32 - e as i32).abs() <= 2);
+        assert!(close, "{}: {:?} != {:?}", what, actual.0, expected);
+    }
+
+    /// What every backend has to get right, within rounding.
+    fn conformance(backend: &mut dyn RenderBackend) {
+        let bottom = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 4, |x, y| Rgba([40 * x as u8, 50 * y as u8, 200, 255])));
+        let top = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([120, 240, 60, 200])));
+        let bottom_id = backend.upload_image(&bottom);
+        let top_id = backend.upload_image(&top);
+        assert_eq!(backend.read_image(bottom_id).unwrap().to_rgba8(), bottom.to_rgba8());
+
+        let blended = backend.blend(bottom_id, top_id, BlendMode::Multiply, 0.5).unwrap();
+        let expected = blend_images(&bottom, &top, BlendMode::Multiply, 0.5).to_rgba8();
+        for (actual, expected) in backend.read_image(blended).unwrap().to_rgba8().pixels().zip(expected.pixels()) {
+            assert_close(actual, expected.0, "blend");
+        }
+        backend.release_image(blended);
+        assert!(backend.read_image(blended).is_none());
+        assert!(backend.blend(blended, top_id, BlendMode::Normal, 1.0).is_none());
+
+        // An 8×6 white canvas with a red layer at (2, 1), drawn at 2× into
+        // a black viewport
+        let mut doc = Document::with_size(8, 6);
+        let red = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 255]))));
+        let layer = doc.add_asset_layer("Red", red);
+        doc.get_layer(&layer).unwrap().write().set_transform(LayerTransform::translation(2.0, 1.0));
+        backend.resize(24, 20);
+        assert_eq!(backend.size(), (24, 20));
+        backend.set_background_color(wgpu::Color::BLACK);
+        backend.set_document_background(wgpu::Color::WHITE);
+        backend.render_document(&doc.snapshot(), ViewTransform { pan: [3.0, 2.0], zoom: 2.0 }).unwrap();
+
+        let frame = backend.read_back().unwrap().to_rgba8();
+        assert_eq!(frame.dimensions(), (24, 20));
+        assert_close(frame.get_pixel(1, 1), [0, 0, 0, 255], "outside the canvas");
+        assert_close(frame.get_pixel(22, 18), [0, 0, 0, 255], "outside the canvas");
+        assert_close(frame.get_pixel(4, 3), [255, 255, 255, 255], "canvas");
+        assert_close(frame.get_pixel(17, 13), [255, 255, 255, 255], "canvas");
+        assert_close(frame.get_pixel(8, 5), [255, 0, 0, 255], "layer");
+        assert_close(frame.get_pixel(13, 10), [255, 0, 0, 255], "layer");
+    }
+
+    #[test]
+    fn test_software_conformance() {
+        let mut backend = SoftwareBackend::new(1, 1);
+        conformance(&mut backend);
+    }
+
+    #[test]
+    fn test_gpu_conformance() {
+        if std::env::var_os("ASTRIA_SKIP_GPU_TESTS").is_some() {
+            return;
+        }
+        match HeadlessRenderer::new() {
+            Ok(mut renderer) => conformance(&mut renderer),
+            Err(e) => eprintln!("Skipping GPU test: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_preference_names() {
+        assert_eq!(BackendPreference::from_name("Software"), Some(BackendPreference::Software));
+        assert_eq!(BackendPreference::from_name("gpu"), Some(BackendPreference::Gpu));
+        assert_eq!(BackendPreference::from_name("vulkan"), None);
+        let backend = create_headless_backend(RendererOptions::default(), BackendPreference::Software).unwrap();
+        assert_eq!(backend.kind(), BackendKind::Software);
+    }
+}
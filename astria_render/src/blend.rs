@@ -0,0 +1,278 @@
+//! Blending images with the document's blend modes on the GPU.
+
+use bytemuck::{Pod, Zeroable};
+use image::DynamicImage;
+use meridian_document::BlendMode;
+use crate::staging::{StagingKind, StagingPool};
+
+/// An 8-bit RGBA image on the GPU, e.g. the result of
+/// [`crate::Renderer::blend_on_gpu`].
+#[derive(Debug)]
+pub struct TextureHandle {
+    texture: wgpu::Texture,
+    size: (u32, u32),
+}
+
+impl TextureHandle {
+    /// An empty, transparent texture.
+    pub(crate) fn new(device: &wgpu::Device, size: (u32, u32)) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Blend Texture"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        Self { texture, size }
+    }
+
+    /// `image` must not be empty.
+    pub(crate) fn upload(device: &wgpu::Device, queue: &wgpu::Queue, staging: &StagingPool, image: &DynamicImage) -> Self {
+        let rgba = image.to_rgba8();
+        let handle = Self::new(device, rgba.dimensions());
+        staging.upload_texture(device, queue, &handle.texture, &rgba);
+        handle
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub(crate) fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    pub(crate) fn into_texture(self) -> wgpu::Texture {
+        self.texture
+    }
+
+    pub(crate) fn view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct BlendParams {
+    mode: u32,
+    opacity: f32,
+    _padding: [f32; 2],
+}
+
+/// The shader's index for `mode`.
+fn mode_index(mode: BlendMode) -> u32 {
+    match mode {
+        BlendMode::Normal => 0,
+        BlendMode::Multiply => 1,
+        BlendMode::Screen => 2,
+        BlendMode::Overlay => 3,
+    }
+}
+
+pub(crate) struct BlendPass {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    staging: StagingPool,
+}
+
+impl BlendPass {
+    pub(crate) fn new(device: &wgpu::Device, staging: StagingPool) -> Self {
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Blend Bind Group Layout"),
+            entries: &[
+                texture(0),
+                texture(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blend Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Blend Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!("blend.wgsl"))),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Blend Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                // The shader does the blending
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: wgpu::TextureFormat::Rgba8Unorm,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        Self { layout, pipeline, staging }
+    }
+
+    /// Blends `top` over `bottom` like meridian_document's `blend_images`:
+    /// the result covers the area both images share, from their top-left
+    /// corners. The work is submitted before returning.
+    pub(crate) fn blend(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bottom: &TextureHandle,
+        top: &TextureHandle,
+        mode: BlendMode,
+        opacity: f32,
+    ) -> TextureHandle {
+        let size = (bottom.size.0.min(top.size.0), bottom.size.1.min(top.size.1));
+        let output = TextureHandle::new(device, size);
+
+        let params = BlendParams {
+            mode: mode_index(mode),
+            opacity,
+            _padding: [0.0; 2],
+        };
+        let params = self.staging.write(device, queue, StagingKind::Uniform, bytemuck::cast_slice(&[params]));
+        let (bottom_view, top_view, target) = (bottom.view(), top.view(), output.view());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Blend Bind Group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&bottom_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&top_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: params.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Blend Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Blend Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        self.staging.submitted(queue);
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use meridian_document::blend::blend_images;
+    use crate::HeadlessRenderer;
+
+    #[test]
+    fn test_gpu_blend_matches_cpu() {
+        if std::env::var_os("ASTRIA_SKIP_GPU_TESTS").is_some() {
+            return;
+        }
+        let renderer = match HeadlessRenderer::new() {
+            Ok(renderer) => renderer,
+            Err(e) => {
+                eprintln!("Skipping GPU test: {}", e);
+                return;
+            }
+        };
+
+        // Every pairing of these pixels, one pair per column
+        let values = [0u8, 37, 100, 128, 191, 255];
+        let alphas = [0u8, 64, 200, 255];
+        let pixels: Vec<Rgba<u8>> = values
+            .iter()
+            .flat_map(|&value| alphas.iter().map(move |&alpha| Rgba([value, 255 - value, value / 2, alpha])))
+            .collect();
+        let pairs: Vec<(Rgba<u8>, Rgba<u8>)> = pixels
+            .iter()
+            .flat_map(|&bottom| pixels.iter().map(move |&top| (bottom, top)))
+            .collect();
+        let width = pairs.len() as u32;
+        let bottom = DynamicImage::ImageRgba8(RgbaImage::from_fn(width, 1, |x, _| pairs[x as usize].0));
+        let top = DynamicImage::ImageRgba8(RgbaImage::from_fn(width, 1, |x, _| pairs[x as usize].1));
+        let (bottom_texture, top_texture) = (renderer.upload_texture(&bottom), renderer.upload_texture(&top));
+
+        for &mode in BlendMode::all() {
+            for opacity in [1.0, 0.6] {
+                let expected = blend_images(&bottom, &top, mode, opacity).to_rgba8();
+                let blended = renderer.blend_on_gpu(&bottom_texture, &top_texture, mode, opacity);
+                assert_eq!(blended.size(), (width, 1));
+                let actual = renderer.read_texture(&blended).to_rgba8();
+                for (x, (actual, expected)) in actual.pixels().zip(expected.pixels()).enumerate() {
+                    for channel in 0..4 {
+                        assert!(
+                            actual[channel].abs_diff(expected[channel]) <= 1,
+                            "{:?} at {}: {:?} over {:?} gave {:?}, expected {:?}",
+                            mode,
+                            opacity,
+                            pairs[x].1,
+                            pairs[x].0,
+                            actual,
+                            expected
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+struct Params {
+    mode: u32,
+    opacity: f32,
+}
+
+@group(0) @binding(0)
+var bottom: texture_2d<f32>;
+@group(0) @binding(1)
+var top: texture_2d<f32>;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+// One triangle covering the target
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+fn overlay(b: f32, t: f32) -> f32 {
+    if (b < 0.5) {
+        return 2.0 * b * t;
+    }
+    return 1.0 - 2.0 * (1.0 - b) * (1.0 - t);
+}
+
+// Same as meridian_document's blend_f32: the mode's color, composited over
+// the bottom with straight alpha
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let b = textureLoad(bottom, pixel, 0);
+    let t = textureLoad(top, pixel, 0);
+
+    var color = t.rgb;
+    switch params.mode {
+        case 1u: {
+            color = b.rgb * t.rgb;
+        }
+        case 2u: {
+            color = 1.0 - (1.0 - b.rgb) * (1.0 - t.rgb);
+        }
+        case 3u: {
+            color = vec3<f32>(overlay(b.r, t.r), overlay(b.g, t.g), overlay(b.b, t.b));
+        }
+        default: {}
+    }
+
+    let a = t.a * params.opacity;
+    let alpha = a + b.a * (1.0 - a);
+    if (alpha > 0.0) {
+        color = (color * a + b.rgb * b.a * (1.0 - a)) / alpha;
+    }
+    return vec4<f32>(color, alpha);
+}
@@ -0,0 +1,51 @@
+struct Params {
+    radius: i32,
+    sigma: f32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+// Written by the vertical pass, read by the horizontal one
+@group(0) @binding(1)
+var intermediate: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(2)
+var output: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(3)
+var<uniform> params: Params;
+
+// Taps outside the image are skipped and the rest renormalized
+fn blur(position: vec2<i32>, direction: vec2<i32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(source));
+    var total = vec4<f32>(0.0);
+    var weights = 0.0;
+    for (var offset = -params.radius; offset <= params.radius; offset = offset + 1) {
+        let tap = position + direction * offset;
+        if (all(tap >= vec2<i32>(0)) && all(tap < size)) {
+            let x = f32(offset);
+            let weight = exp(-x * x / (2.0 * params.sigma * params.sigma));
+            total = total + textureLoad(source, tap, 0) * weight;
+            weights = weights + weight;
+        }
+    }
+    return total / weights;
+}
+
+// Dispatches round up to whole workgroups, so invocations past the edge
+// return early
+@compute @workgroup_size(8, 8)
+fn blur_vertical(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(source);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+    textureStore(intermediate, vec2<i32>(id.xy), blur(vec2<i32>(id.xy), vec2<i32>(0, 1)));
+}
+
+@compute @workgroup_size(8, 8)
+fn blur_horizontal(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(source);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+    textureStore(output, vec2<i32>(id.xy), blur(vec2<i32>(id.xy), vec2<i32>(1, 0)));
+}
@@ -0,0 +1,232 @@
+//! Panning and zooming the viewport.
+
+use crate::ViewTransform;
+
+/// Zooms within this of 100% snap to it.
+pub const ZOOM_SNAP_EPSILON: f32 = 0.02;
+
+/// The viewport's view of the document. Screen points are physical viewport
+/// pixels and document points canvas pixels, both with y pointing down.
+/// Logical points, as UI toolkits report them, are converted with the scale
+/// factor first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Camera {
+    /// Where the canvas origin is on screen.
+    pub offset: [f32; 2],
+    zoom: f32,
+    zoom_range: (f32, f32),
+    viewport: (u32, u32),
+    scale_factor: f64,
+}
+
+impl Camera {
+    pub fn new(viewport: (u32, u32)) -> Self {
+        Self {
+            offset: [0.0, 0.0],
+            zoom: 1.0,
+            zoom_range: (0.01, 64.0),
+            viewport,
+            scale_factor: 1.0,
+        }
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    /// Sets the zoom, clamped to the zoom range and snapped to 100% when
+    /// close. The canvas origin stays put.
+    pub fn set_zoom(&mut self, zoom: f32) {
+        let zoom = zoom.clamp(self.zoom_range.0, self.zoom_range.1);
+        self.zoom = if (zoom - 1.0).abs() < ZOOM_SNAP_EPSILON { 1.0 } else { zoom };
+    }
+
+    pub fn zoom_range(&self) -> (f32, f32) {
+        self.zoom_range
+    }
+
+    /// Limits zooming to `min..=max`, clamping the current zoom.
+    pub fn set_zoom_range(&mut self, min: f32, max: f32) {
+        assert!(min > 0.0 && min <= max, "invalid zoom range {}..={}", min, max);
+        self.zoom_range = (min, max);
+        self.set_zoom(self.zoom);
+    }
+
+    pub fn viewport(&self) -> (u32, u32) {
+        self.viewport
+    }
+
+    pub fn set_viewport(&mut self, viewport: (u32, u32)) {
+        self.viewport = viewport;
+    }
+
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    /// Sets the physical pixels per logical pixel. The canvas origin keeps
+    /// its logical position; the zoom, in canvas pixels per physical pixel,
+    /// is unchanged.
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        let ratio = (scale_factor / self.scale_factor) as f32;
+        self.offset = [self.offset[0] * ratio, self.offset[1] * ratio];
+        self.scale_factor = scale_factor;
+    }
+
+    /// The screen point at logical point `point`.
+    pub fn logical_to_screen(&self, point: [f32; 2]) -> [f32; 2] {
+        let scale = self.scale_factor as f32;
+        [point[0] * scale, point[1] * scale]
+    }
+
+    pub fn screen_to_logical(&self, point: [f32; 2]) -> [f32; 2] {
+        let scale = self.scale_factor as f32;
+        [point[0] / scale, point[1] / scale]
+    }
+
+    /// The document point under logical point `point`, e.g. the cursor.
+    pub fn logical_to_document(&self, point: [f32; 2]) -> [f32; 2] {
+        self.screen_to_document(self.logical_to_screen(point))
+    }
+
+    pub fn document_to_logical(&self, point: [f32; 2]) -> [f32; 2] {
+        self.screen_to_logical(self.document_to_screen(point))
+    }
+
+    /// Moves the view by `delta` screen pixels.
+    pub fn pan_by(&mut self, delta: [f32; 2]) {
+        self.offset[0] += delta[0];
+        self.offset[1] += delta[1];
+    }
+
+    /// Multiplies the zoom by `factor`, keeping the document point under
+    /// `screen_point` where it is.
+    pub fn zoom_at(&mut self, screen_point: [f32; 2], factor: f32) {
+        let anchor = self.screen_to_document(screen_point);
+        self.set_zoom(self.zoom * factor);
+        self.offset = [
+            screen_point[0] - anchor[0] * self.zoom,
+            screen_point[1] - anchor[1] * self.zoom,
+        ];
+    }
+
+    /// Zooms to show all of a `canvas_size` document, centered.
+    pub fn fit_to(&mut self, canvas_size: (u32, u32)) {
+        if canvas_size.0 == 0 || canvas_size.1 == 0 {
+            return;
+        }
+        let zoom = (self.viewport.0 as f32 / canvas_size.0 as f32).min(self.viewport.1 as f32 / canvas_size.1 as f32);
+        self.set_zoom(zoom);
+        self.offset = ViewTransform::centered(canvas_size, self.viewport, self.zoom).pan;
+    }
+
+    pub fn screen_to_document(&self, point: [f32; 2]) -> [f32; 2] {
+        [(point[0] - self.offset[0]) / self.zoom, (point[1] - self.offset[1]) / self.zoom]
+    }
+
+    pub fn document_to_screen(&self, point: [f32; 2]) -> [f32; 2] {
+        [point[0] * self.zoom + self.offset[0], point[1] * self.zoom + self.offset[1]]
+    }
+
+    /// The transform the document is drawn with.
+    pub fn view(&self) -> ViewTransform {
+        ViewTransform {
+            pan: self.offset,
+            zoom: self.zoom,
+        }
+    }
+
+    /// Shows the document as `view` does, keeping its zoom as it is rather
+    /// than clamping or snapping it.
+    pub(crate) fn set_view(&mut self, view: ViewTransform) {
+        self.offset = view.pan;
+        self.zoom = view.zoom;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f32; 2], expected: [f32; 2]) {
+        assert!(
+            (actual[0] - expected[0]).abs() < 1e-3 && (actual[1] - expected[1]).abs() < 1e-3,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_coordinates_round_trip() {
+        let mut camera = Camera::new((800, 600));
+        camera.pan_by([35.0, -12.5]);
+        camera.set_zoom(2.5);
+        for point in [[0.0, 0.0], [17.0, 4.25], [-30.0, 512.0]] {
+            assert_close(camera.screen_to_document(camera.document_to_screen(point)), point);
+            assert_close(camera.document_to_screen(camera.screen_to_document(point)), point);
+        }
+        assert_close(camera.document_to_screen([10.0, 10.0]), [60.0, 12.5]);
+        assert_close(camera.view().apply(10.0, 10.0), [60.0, 12.5]);
+    }
+
+    #[test]
+    fn test_zoom_at_keeps_point_fixed() {
+        let mut camera = Camera::new((800, 600));
+        camera.pan_by([100.0, 50.0]);
+        let cursor = [420.0, 310.0];
+        let under_cursor = camera.screen_to_document(cursor);
+        for factor in [1.5, 0.25, 3.0] {
+            camera.zoom_at(cursor, factor);
+            assert_close(camera.screen_to_document(cursor), under_cursor);
+        }
+    }
+
+    #[test]
+    fn test_zoom_clamps_and_snaps() {
+        let mut camera = Camera::new((800, 600));
+        camera.set_zoom_range(0.5, 4.0);
+        camera.zoom_at([0.0, 0.0], 100.0);
+        assert_eq!(camera.zoom(), 4.0);
+        camera.set_zoom(0.1);
+        assert_eq!(camera.zoom(), 0.5);
+        camera.set_zoom(1.01);
+        assert_eq!(camera.zoom(), 1.0);
+        camera.set_zoom(1.05);
+        assert_eq!(camera.zoom(), 1.05);
+    }
+
+    #[test]
+    fn test_fit_to_centers_canvas() {
+        let mut camera = Camera::new((800, 600));
+        camera.fit_to((400, 100));
+        assert_eq!(camera.zoom(), 2.0);
+        assert_close(camera.document_to_screen([0.0, 0.0]), [0.0, 200.0]);
+        assert_close(camera.document_to_screen([400.0, 100.0]), [800.0, 400.0]);
+    }
+
+    #[test]
+    fn test_logical_coordinates_follow_scale_factor() {
+        for scale_factor in [1.0, 2.0] {
+            let mut camera = Camera::new((800, 600));
+            camera.set_scale_factor(scale_factor);
+            camera.pan_by([40.0, 20.0]);
+            camera.set_zoom(2.0);
+            let scale = scale_factor as f32;
+
+            assert_close(camera.logical_to_screen([10.0, 5.0]), [10.0 * scale, 5.0 * scale]);
+            assert_close(camera.logical_to_document([30.0, 15.0]), [(30.0 * scale - 40.0) / 2.0, (15.0 * scale - 20.0) / 2.0]);
+            for point in [[0.0, 0.0], [12.5, 7.0], [400.0, 300.0]] {
+                assert_close(camera.document_to_logical(camera.logical_to_document(point)), point);
+            }
+        }
+
+        // Moving to a denser display keeps the canvas where it was
+        let mut camera = Camera::new((800, 600));
+        camera.pan_by([40.0, 20.0]);
+        let origin = camera.document_to_logical([0.0, 0.0]);
+        camera.set_scale_factor(2.0);
+        assert_close(camera.document_to_logical([0.0, 0.0]), origin);
+        assert_eq!(camera.zoom(), 1.0);
+    }
+}
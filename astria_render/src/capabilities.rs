@@ -0,0 +1,119 @@
+//! What the opened device can do, so features can fall back to the CPU.
+
+use crate::LimitsProfile;
+
+/// What the device supports, e.g. for deciding whether a node computes on
+/// the GPU or for showing in the UI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RendererCapabilities {
+    /// Largest texture width or height.
+    pub max_texture_dimension: u32,
+    /// Compute shaders writing to storage textures, as GPU blur needs.
+    pub supports_compute: bool,
+    /// Filtered sampling of 32-bit float textures.
+    pub supports_float_filtering: bool,
+    /// Viewing a texture in another format than its own, as documents that
+    /// don't blend in linear light need for their non-sRGB view of an sRGB
+    /// target.
+    pub supports_view_formats: bool,
+    /// The same for window surfaces.
+    pub supports_surface_view_formats: bool,
+    /// The limits the device was opened with, which may be lower than
+    /// requested.
+    pub limits_profile: LimitsProfile,
+}
+
+impl RendererCapabilities {
+    /// Capabilities from the device's limits alone. Float filtering and
+    /// view formats are assumed unsupported, as they depend on the adapter.
+    pub fn from_limits(limits: &wgpu::Limits, limits_profile: LimitsProfile) -> Self {
+        Self {
+            max_texture_dimension: limits.max_texture_dimension_2d,
+            // The blur shader runs 8×8 workgroups and writes two storage
+            // textures
+            supports_compute: limits.max_compute_workgroups_per_dimension > 0
+                && limits.max_compute_workgroup_size_x >= 8
+                && limits.max_compute_workgroup_size_y >= 8
+                && limits.max_storage_textures_per_shader_stage >= 2,
+            supports_float_filtering: false,
+            supports_view_formats: false,
+            supports_surface_view_formats: false,
+            limits_profile,
+        }
+    }
+
+    pub(crate) fn detect(adapter: &wgpu::Adapter, device: &wgpu::Device, limits_profile: LimitsProfile) -> Self {
+        let adapter_specific = device.features().contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        let float_flags = adapter.get_texture_format_features(wgpu::TextureFormat::Rgba32Float).flags;
+        let downlevel = adapter.get_downlevel_capabilities().flags;
+        Self {
+            supports_float_filtering: adapter_specific
+                && float_flags.contains(wgpu::TextureFormatFeatureFlags::FILTERABLE),
+            supports_view_formats: downlevel.contains(wgpu::DownlevelFlags::VIEW_FORMATS),
+            supports_surface_view_formats: downlevel.contains(wgpu::DownlevelFlags::SURFACE_VIEW_FORMATS),
+            ..Self::from_limits(&device.limits(), limits_profile)
+        }
+    }
+
+    /// Whether a `size` texture fits on the device.
+    pub fn fits(&self, size: (u32, u32)) -> bool {
+        size.0 > 0 && size.1 > 0 && size.0 <= self.max_texture_dimension && size.1 <= self.max_texture_dimension
+    }
+
+    /// Whether a `size` image can be blurred with compute shaders.
+    pub fn can_blur_on_gpu(&self, size: (u32, u32)) -> bool {
+        self.supports_compute && self.fits(size)
+    }
+}
+
+/// The first of `requested` and the profiles below it that `fits`.
+/// [`LimitsProfile::Adapter`] has nothing below it.
+pub(crate) fn negotiate_limits(requested: LimitsProfile, fits: impl Fn(LimitsProfile) -> bool) -> Option<LimitsProfile> {
+    let fallbacks: &[LimitsProfile] = match requested {
+        LimitsProfile::Adapter => &[LimitsProfile::Adapter],
+        LimitsProfile::Default => &[LimitsProfile::Default, LimitsProfile::Downlevel, LimitsProfile::DownlevelWebGl2],
+        LimitsProfile::Downlevel => &[LimitsProfile::Downlevel, LimitsProfile::DownlevelWebGl2],
+        LimitsProfile::DownlevelWebGl2 => &[LimitsProfile::DownlevelWebGl2],
+    };
+    fallbacks.iter().copied().find(|&profile| fits(profile))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_limits_falls_back() {
+        let all = |_: LimitsProfile| true;
+        assert_eq!(negotiate_limits(LimitsProfile::Default, all), Some(LimitsProfile::Default));
+
+        // A WebGL-class adapter
+        let webgl = |profile: LimitsProfile| profile == LimitsProfile::DownlevelWebGl2;
+        assert_eq!(negotiate_limits(LimitsProfile::Default, webgl), Some(LimitsProfile::DownlevelWebGl2));
+        assert_eq!(negotiate_limits(LimitsProfile::Downlevel, webgl), Some(LimitsProfile::DownlevelWebGl2));
+        assert_eq!(negotiate_limits(LimitsProfile::Adapter, webgl), None);
+        assert_eq!(negotiate_limits(LimitsProfile::Default, |_| false), None);
+    }
+
+    #[test]
+    fn test_capabilities_decide_gpu_blur() {
+        let desktop = RendererCapabilities::from_limits(&wgpu::Limits::default(), LimitsProfile::Default);
+        assert!(desktop.supports_compute);
+        assert!(desktop.can_blur_on_gpu((1024, 768)));
+        assert!(!desktop.can_blur_on_gpu((0, 768)));
+        assert!(!desktop.can_blur_on_gpu((desktop.max_texture_dimension + 1, 1)));
+
+        let webgl = RendererCapabilities::from_limits(&wgpu::Limits::downlevel_webgl2_defaults(), LimitsProfile::DownlevelWebGl2);
+        assert!(!webgl.supports_compute);
+        assert!(!webgl.can_blur_on_gpu((16, 16)));
+        assert!(webgl.fits((16, 16)));
+        assert!(!webgl.supports_view_formats);
+
+        let small = RendererCapabilities {
+            max_texture_dimension: 256,
+            ..desktop
+        };
+        assert!(small.can_blur_on_gpu((256, 256)));
+        assert!(!small.can_blur_on_gpu((257, 256)));
+    }
+}
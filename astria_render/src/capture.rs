@@ -0,0 +1,124 @@
+//! Rendering the viewport into an image, e.g. for copying it.
+
+use std::path::Path;
+use image::{DynamicImage, RgbaImage};
+use crate::document::Frame;
+use crate::headless::read_texture;
+use crate::msaa::MsaaTarget;
+use crate::{RenderError, Renderer};
+
+/// A texture like the frame's that can be copied from.
+pub(crate) fn capture_texture(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    view_formats: &[wgpu::TextureFormat],
+    size: (u32, u32),
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Capture Target"),
+        size: wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats,
+    })
+}
+
+/// Reorders `pixels`, read back from a `format` texture, to RGBA.
+pub(crate) fn to_rgba(format: wgpu::TextureFormat, pixels: &mut RgbaImage) -> Result<(), RenderError> {
+    match format {
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => Ok(()),
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => {
+            for pixel in pixels.pixels_mut() {
+                pixel.0.swap(0, 2);
+            }
+            Ok(())
+        }
+        format => Err(RenderError::UnsupportedCaptureFormat(format)),
+    }
+}
+
+impl Renderer {
+    /// Renders the viewport into an image: the last document drawn by
+    /// [`Renderer::render_document`], with the current camera, or the shapes
+    /// [`Renderer::render`] draws if no document has been. Drawing happens
+    /// offscreen, so the surface isn't presented.
+    pub fn capture_frame(&mut self) -> Result<DynamicImage, RenderError> {
+        let size = (self.size.width.max(1), self.size.height.max(1));
+        let format = self.config.format;
+        let texture = capture_texture(&self.device, format, &self.config.view_formats, size);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Capture Encoder"),
+        });
+        match self.document.last_snapshot().cloned() {
+            Some(snapshot) => {
+                let frame = Frame {
+                    texture: &texture,
+                    format,
+                    view_formats: &self.config.view_formats,
+                    size,
+                    clear: self.background_color,
+                    background: self.document_background,
+                    msaa: self.msaa.as_ref().map(MsaaTarget::texture),
+                    damage: None,
+                };
+                let view = self.camera.view();
+                self.document.encode(&self.device, &self.queue, &mut encoder, frame, &snapshot, view)?;
+            }
+            None => {
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                let msaa = self.msaa.as_ref().map(|msaa| msaa.texture().create_view(&wgpu::TextureViewDescriptor::default()));
+                self.shapes.draw(
+                    &mut encoder,
+                    &view,
+                    msaa.as_ref(),
+                    self.background_color,
+                    self.fill_color.is_some(),
+                    self.vertex_buffer.as_ref(),
+                );
+            }
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.staging.submitted(&self.queue);
+
+        let mut pixels = read_texture(&self.device, &self.queue, &self.staging, &texture, size);
+        to_rgba(format, &mut pixels)?;
+        Ok(DynamicImage::ImageRgba8(pixels))
+    }
+
+    /// Same as [`Renderer::capture_frame`], saved as a PNG at `path`.
+    pub fn capture_to_png(&mut self, path: impl AsRef<Path>) -> Result<(), RenderError> {
+        let path = path.as_ref();
+        self.capture_frame()?
+            .save_with_format(path, image::ImageFormat::Png)
+            .map_err(|source| RenderError::Capture {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_to_rgba_swaps_bgra() {
+        let mut pixels = RgbaImage::from_pixel(2, 1, Rgba([1, 2, 3, 4]));
+        to_rgba(wgpu::TextureFormat::Rgba8UnormSrgb, &mut pixels).unwrap();
+        assert_eq!(pixels.get_pixel(0, 0).0, [1, 2, 3, 4]);
+        to_rgba(wgpu::TextureFormat::Bgra8UnormSrgb, &mut pixels).unwrap();
+        assert_eq!(pixels.get_pixel(1, 0).0, [3, 2, 1, 4]);
+        assert!(matches!(
+            to_rgba(wgpu::TextureFormat::Rgba16Float, &mut pixels),
+            Err(RenderError::UnsupportedCaptureFormat(wgpu::TextureFormat::Rgba16Float))
+        ));
+    }
+}
@@ -0,0 +1,222 @@
+//! Redrawing only the parts of the viewport that changed.
+//!
+//! With damage tracking on, documents are drawn into a texture kept between
+//! frames, as swapchain contents can't be relied on, and copied into each
+//! frame. While the camera, viewport and canvas stay the same, only the areas
+//! marked with [`Renderer::mark_damaged`] are redrawn, clipped with scissor
+//! rects; anything else about the frame changing redraws all of it.
+
+use meridian_document::{CanvasRect, DocumentSnapshot};
+use crate::{Renderer, ViewTransform};
+
+/// Damage rects beyond this many are merged into their union, which is
+/// redrawn as one.
+const MAX_DAMAGE_RECTS: usize = 16;
+
+/// Target pixels to redraw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ScissorRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The target pixels `rect` covers when placed by `view`, clipped to a
+/// `size` target. `None` if it's off the target.
+pub(crate) fn scissor_rect(rect: &CanvasRect, view: &ViewTransform, size: (u32, u32)) -> Option<ScissorRect> {
+    let [left, top] = view.apply(rect.x as f64, rect.y as f64);
+    let [right, bottom] = view.apply(rect.right() as f64, rect.bottom() as f64);
+    // A pixel more on each side, as filtering reaches across the edges
+    let (left, top) = ((left.floor() - 1.0).max(0.0) as u32, (top.floor() - 1.0).max(0.0) as u32);
+    let right = ((right.ceil() + 1.0).max(0.0) as u32).min(size.0);
+    let bottom = ((bottom.ceil() + 1.0).max(0.0) as u32).min(size.1);
+    (right > left && bottom > top).then(|| ScissorRect {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    })
+}
+
+/// Everything a document frame looks like besides the document's content.
+/// The preserved frame is only redrawn in part while this stays the same.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FrameKey {
+    pub format: wgpu::TextureFormat,
+    pub size: (u32, u32),
+    pub view: ViewTransform,
+    pub canvas: (u32, u32),
+    pub clear: wgpu::Color,
+    pub background: wgpu::Color,
+}
+
+/// The damage marked since the last frame and the frame it applies to.
+#[derive(Default)]
+pub(crate) struct DamageTracker {
+    enabled: bool,
+    damage: Vec<CanvasRect>,
+    texture: Option<wgpu::Texture>,
+    // What the preserved frame shows
+    last: Option<(FrameKey, DocumentSnapshot)>,
+}
+
+impl DamageTracker {
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turning tracking off frees the preserved frame.
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.damage.clear();
+            self.texture = None;
+            self.last = None;
+        }
+    }
+
+    pub(crate) fn mark(&mut self, rect: CanvasRect) {
+        if self.enabled && !rect.is_empty() {
+            self.damage.push(rect);
+        }
+    }
+
+    /// Redraws the whole frame next time, e.g. after drawing differently.
+    pub(crate) fn invalidate(&mut self) {
+        self.last = None;
+    }
+
+    /// The texture to draw `snapshot` into and the regions of it to redraw,
+    /// `None` for all of it. Takes the damage marked since the last frame.
+    ///
+    /// The frame is drawn in full when `key` changed, or when `snapshot` is
+    /// a different one and no damage was marked, since what changed isn't
+    /// known. The same snapshot without damage redraws nothing.
+    pub(crate) fn begin(
+        &mut self,
+        device: &wgpu::Device,
+        key: FrameKey,
+        view_formats: &[wgpu::TextureFormat],
+        snapshot: &DocumentSnapshot,
+    ) -> (&wgpu::Texture, Option<Vec<ScissorRect>>) {
+        let damage = std::mem::take(&mut self.damage);
+        let regions = match &self.last {
+            Some((last_key, last_snapshot)) if *last_key == key => {
+                if damage.is_empty() && !last_snapshot.ptr_eq(snapshot) {
+                    None
+                } else {
+                    Some(damage_regions(&damage, &key))
+                }
+            }
+            _ => None,
+        };
+
+        let stale = self.texture.as_ref().map_or(true, |texture| {
+            texture.format() != key.format || (texture.width(), texture.height()) != key.size
+        });
+        if stale {
+            self.texture = Some(create_preserved(device, key.format, view_formats, key.size));
+        }
+        self.last = Some((key, snapshot.clone()));
+        (self.texture.as_ref().unwrap(), regions)
+    }
+
+    /// Records copying the preserved frame into `target`, which has its
+    /// size and format.
+    pub(crate) fn copy_to(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::Texture) {
+        let Some(texture) = &self.texture else {
+            return;
+        };
+        encoder.copy_texture_to_texture(texture.as_image_copy(), target.as_image_copy(), texture.size());
+    }
+}
+
+/// The target regions to redraw for `damage`.
+fn damage_regions(damage: &[CanvasRect], key: &FrameKey) -> Vec<ScissorRect> {
+    if damage.len() > MAX_DAMAGE_RECTS {
+        let union = damage.iter().fold(damage[0], |union, rect| union.union(rect));
+        return scissor_rect(&union, &key.view, key.size).into_iter().collect();
+    }
+    damage.iter().filter_map(|rect| scissor_rect(rect, &key.view, key.size)).collect()
+}
+
+fn create_preserved(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    view_formats: &[wgpu::TextureFormat],
+    size: (u32, u32),
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Preserved Frame"),
+        size: wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats,
+    })
+}
+
+impl Renderer {
+    pub fn damage_tracking(&self) -> bool {
+        self.damage.enabled()
+    }
+
+    /// Turns on redrawing only the damaged parts of the viewport in
+    /// [`Renderer::render_document`]. Has no effect with MSAA, or if the
+    /// surface can't be copied into; frames are then always drawn in full.
+    pub fn set_damage_tracking(&mut self, enabled: bool) {
+        self.damage.set_enabled(enabled);
+    }
+
+    /// Marks `rect` of the document as changed, to be redrawn by the next
+    /// [`Renderer::render_document`]. Ignored unless damage tracking is on.
+    pub fn mark_damaged(&mut self, rect: CanvasRect) {
+        self.damage.mark(rect);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(view: ViewTransform) -> FrameKey {
+        FrameKey {
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            size: (100, 80),
+            view,
+            canvas: (50, 40),
+            clear: wgpu::Color::BLACK,
+            background: wgpu::Color::WHITE,
+        }
+    }
+
+    #[test]
+    fn test_scissor_rect_follows_view() {
+        let view = ViewTransform { pan: [10.0, 5.0], zoom: 2.0 };
+        let rect = scissor_rect(&CanvasRect::new(4, 6, 3, 2), &view, (100, 80));
+        assert_eq!(rect, Some(ScissorRect { x: 17, y: 16, width: 8, height: 6 }));
+
+        // Clipped to the target
+        let rect = scissor_rect(&CanvasRect::new(-20, 30, 100, 100), &view, (100, 80));
+        assert_eq!(rect, Some(ScissorRect { x: 0, y: 64, width: 100, height: 16 }));
+        assert_eq!(scissor_rect(&CanvasRect::new(60, 0, 5, 5), &view, (100, 80)), None);
+    }
+
+    #[test]
+    fn test_many_rects_merge() {
+        let key = key(ViewTransform::IDENTITY);
+        let few: Vec<CanvasRect> = (0..4).map(|i| CanvasRect::new(i * 10, 0, 2, 2)).collect();
+        assert_eq!(damage_regions(&few, &key).len(), 4);
+
+        let many: Vec<CanvasRect> = (0..MAX_DAMAGE_RECTS as i64 + 1).map(|i| CanvasRect::new(i * 2, 0, 2, 2)).collect();
+        let regions = damage_regions(&many, &key);
+        assert_eq!(regions, vec![ScissorRect { x: 0, y: 0, width: 35, height: 3 }]);
+    }
+}
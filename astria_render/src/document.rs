@@ -0,0 +1,974 @@
+//! Drawing a [`DocumentSnapshot`] into the viewport.
+//!
+//! Each shown layer's output is uploaded once and drawn as a textured quad,
+//! and the view is applied in the vertex shader, so panning and zooming only
+//! update a uniform. Normal and Multiply layers blend with GPU blend states.
+//! Other blend modes are composited offscreen with [`BlendPass`], or on the
+//! CPU for documents blending in linear light, and so are documents that
+//! composite through a graph; the result is drawn as a single quad.
+//!
+//! Colors are blended the way the CPU compositor blends them for the
+//! document's [`meridian_document::ColorProfile`]. For documents blending in
+//! linear light, layers are uploaded as `Rgba8UnormSrgb`, so sampling yields
+//! linear values, and drawn through an sRGB view of the frame, which blends
+//! in linear light and encodes the result. Other documents are uploaded as
+//! `Rgba8Unorm` and drawn through a non-sRGB view, so blending works on the
+//! encoded values as on the CPU. [`ColorDebugMode`] helps compare the two.
+//! Devices that can't view a texture in another format have only the sRGB
+//! view, so there every document blends in linear light.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use image::DynamicImage;
+use meridian_document::{BlendMode, DocumentError, DocumentSnapshot, LayerId, LayerSnapshot, LayerTransform};
+use wgpu::util::DeviceExt;
+use crate::blend::{BlendPass, TextureHandle};
+use crate::damage::{FrameKey, ScissorRect};
+use crate::egui_layer::EguiLayer;
+use crate::mipmap::{mip_level_count, MipmapPass};
+use crate::msaa::{color_attachment, MsaaTarget};
+use crate::staging::{StagingKind, StagingPool};
+use crate::surface::acquire_with_retry;
+use crate::texture_cache::{CachedTexture, TextureCache};
+use crate::{RenderError, Renderer};
+
+/// Maps canvas pixels to viewport pixels: scaled by `zoom` about the canvas
+/// origin, then offset by `pan` viewport pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewTransform {
+    pub pan: [f32; 2],
+    pub zoom: f32,
+}
+
+impl ViewTransform {
+    pub const IDENTITY: Self = Self { pan: [0.0, 0.0], zoom: 1.0 };
+
+    /// Shows a `canvas`-sized document centered in a `viewport` at `zoom`.
+    pub fn centered(canvas: (u32, u32), viewport: (u32, u32), zoom: f32) -> Self {
+        Self {
+            pan: [
+                (viewport.0 as f32 - canvas.0 as f32 * zoom) / 2.0,
+                (viewport.1 as f32 - canvas.1 as f32 * zoom) / 2.0,
+            ],
+            zoom,
+        }
+    }
+
+    /// The viewport position of canvas point `(x, y)`.
+    pub fn apply(&self, x: f64, y: f64) -> [f32; 2] {
+        [x as f32 * self.zoom + self.pan[0], y as f32 * self.zoom + self.pan[1]]
+    }
+}
+
+impl Default for ViewTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+struct QuadVertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+}
+
+impl QuadVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<QuadVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+            ],
+        }
+    }
+}
+
+/// The viewport size and the view, applied in the vertex shader so quads
+/// don't change as the view does.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ViewUniforms {
+    viewport_size: [f32; 2],
+    pan: [f32; 2],
+    zoom: f32,
+    _padding: [f32; 3],
+}
+
+impl ViewUniforms {
+    fn new(viewport_size: (u32, u32), view: &ViewTransform) -> Self {
+        Self {
+            viewport_size: [viewport_size.0 as f32, viewport_size.1 as f32],
+            pan: view.pan,
+            zoom: view.zoom,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct QuadUniforms {
+    tint: [f32; 4],
+}
+
+/// The canvas quad of a `width`×`height` layer output placed by
+/// `transform`, as two triangles.
+fn layer_quad(transform: &LayerTransform, width: u32, height: u32) -> [QuadVertex; 6] {
+    let affine = transform.to_affine(width, height);
+    let corner = |u: f32, v: f32| {
+        let (x, y) = affine.apply(u as f64 * width as f64, v as f64 * height as f64);
+        QuadVertex { position: [x as f32, y as f32], uv: [u, v] }
+    };
+    let (top_left, top_right) = (corner(0.0, 0.0), corner(1.0, 0.0));
+    let (bottom_left, bottom_right) = (corner(0.0, 1.0), corner(1.0, 1.0));
+    [top_left, bottom_left, top_right, top_right, bottom_left, bottom_right]
+}
+
+/// A quad covering a `size` viewport, in canvas pixels under `view`.
+fn viewport_quad(view: &ViewTransform, size: (u32, u32)) -> [QuadVertex; 6] {
+    let corner = |x: f32, y: f32| QuadVertex {
+        position: [(x - view.pan[0]) / view.zoom, (y - view.pan[1]) / view.zoom],
+        uv: [0.0, 0.0],
+    };
+    let (width, height) = (size.0 as f32, size.1 as f32);
+    let (top_left, top_right) = (corner(0.0, 0.0), corner(width, 0.0));
+    let (bottom_left, bottom_right) = (corner(0.0, height), corner(width, height));
+    [top_left, bottom_left, top_right, top_right, bottom_left, bottom_right]
+}
+
+/// A blend mode with a GPU blend state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum GpuBlend {
+    Normal,
+    Multiply,
+    /// Overwrites the target, for clearing damaged regions.
+    Replace,
+}
+
+impl GpuBlend {
+    fn for_mode(mode: BlendMode) -> Option<Self> {
+        match mode {
+            BlendMode::Normal => Some(GpuBlend::Normal),
+            BlendMode::Multiply => Some(GpuBlend::Multiply),
+            BlendMode::Screen | BlendMode::Overlay => None,
+        }
+    }
+
+    /// For premultiplied source colors.
+    fn state(self) -> wgpu::BlendState {
+        let color = match self {
+            GpuBlend::Normal => wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            // dst * src * a + dst * (1 - a)
+            GpuBlend::Multiply => wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Dst,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            GpuBlend::Replace => return wgpu::BlendState::REPLACE,
+        };
+        wgpu::BlendState {
+            color,
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+        }
+    }
+}
+
+/// Draws documents differently to check the GPU compositor against the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorDebugMode {
+    #[default]
+    Off,
+    /// Always draw [`DocumentSnapshot::render_composite`], the reference
+    /// the GPU compositor should match. Toggling shows any differences.
+    CpuReference,
+    /// Draw layers sampled and blended in the other color space than the
+    /// document's, to show what getting the encoding wrong looks like.
+    SwapEncoding,
+}
+
+/// How a snapshot is composited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompositeMethod {
+    /// Each layer drawn as a quad with a GPU blend state.
+    Quads,
+    /// Layers blended offscreen by [`BlendPass`], which works on sRGB values.
+    BlendPass,
+    /// [`DocumentSnapshot::render_composite`].
+    Cpu,
+}
+
+fn composite_method(snapshot: &DocumentSnapshot) -> CompositeMethod {
+    if snapshot.composites_through_graph() {
+        CompositeMethod::Cpu
+    } else if snapshot.shown_layers().all(|layer| GpuBlend::for_mode(layer.blend_mode()).is_some()) {
+        CompositeMethod::Quads
+    } else if snapshot.color_profile().blends_in_linear_light() {
+        CompositeMethod::Cpu
+    } else {
+        CompositeMethod::BlendPass
+    }
+}
+
+/// What an uploaded texture is known to hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextureSource {
+    /// A layer output with this content hash.
+    Content(u64),
+    /// Something only known to be current for the snapshot it came from.
+    Snapshot,
+}
+
+struct QuadTexture {
+    /// Canvas size of the image, which may have been scaled down to fit.
+    size: (u32, u32),
+    _texture: Arc<wgpu::Texture>,
+    uniforms: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+struct CachedOutput {
+    source: TextureSource,
+    linear: bool,
+    /// `None` for layers without output.
+    texture: Option<QuadTexture>,
+}
+
+/// What a draw call samples.
+enum DrawTarget {
+    /// The clear color over a damaged region.
+    Clear,
+    Background,
+    Composite,
+    Layer(LayerId),
+}
+
+struct Draw {
+    target: DrawTarget,
+    blend: GpuBlend,
+    tint: [f32; 4],
+    vertices: [QuadVertex; 6],
+}
+
+/// GPU state for drawing documents, see [`DocumentPass::encode`].
+pub(crate) struct DocumentPass {
+    viewport: wgpu::Buffer,
+    viewport_bind_group: wgpu::BindGroup,
+    quad_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    sampler: wgpu::Sampler,
+    // Keyed by target format, blend and sample count
+    pipelines: HashMap<(wgpu::TextureFormat, GpuBlend, u32), wgpu::RenderPipeline>,
+    background: QuadTexture,
+    clear: QuadTexture,
+    layers: HashMap<LayerId, CachedOutput>,
+    // With the method and whether it was blended in linear light
+    composite: Option<(CompositeMethod, bool, QuadTexture)>,
+    pub(crate) blend: BlendPass,
+    pub(crate) cache: TextureCache,
+    pub(crate) debug: ColorDebugMode,
+    staging: StagingPool,
+    mipmaps: MipmapPass,
+    // The last snapshot drawn
+    snapshot: Option<DocumentSnapshot>,
+    /// Images uploaded while drawing, for tests.
+    pub(crate) texture_writes: u64,
+}
+
+impl DocumentPass {
+    pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue, staging: StagingPool) -> Self {
+        let viewport = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Document Viewport Buffer"),
+            contents: bytemuck::cast_slice(&[ViewUniforms::new((1, 1), &ViewTransform::IDENTITY)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let viewport_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Document Viewport Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let viewport_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Document Viewport Bind Group"),
+            layout: &viewport_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: viewport.as_entire_binding(),
+            }],
+        });
+
+        let quad_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Document Quad Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Document Pipeline Layout"),
+            bind_group_layouts: &[&viewport_layout, &quad_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Document Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!("document.wgsl"))),
+        });
+        // Pixels stay sharp when zoomed in, and blend between mip levels
+        // when zoomed out
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Document Sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let mut mipmaps = MipmapPass::new(device);
+        let white = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4])));
+        let white = Arc::new(upload_texture(device, queue, &staging, &mut mipmaps, &white, false));
+        let background = quad_texture(device, &quad_layout, &sampler, white.clone(), (1, 1));
+        // Tinted separately from the background in the same pass
+        let clear = quad_texture(device, &quad_layout, &sampler, white, (1, 1));
+        Self {
+            viewport,
+            viewport_bind_group,
+            quad_layout,
+            pipeline_layout,
+            shader,
+            sampler,
+            pipelines: HashMap::new(),
+            background,
+            clear,
+            layers: HashMap::new(),
+            composite: None,
+            blend: BlendPass::new(device, staging.clone()),
+            cache: TextureCache::default(),
+            debug: ColorDebugMode::Off,
+            staging,
+            mipmaps,
+            snapshot: None,
+            texture_writes: 0,
+        }
+    }
+
+    fn ensure_pipeline(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat, blend: GpuBlend, samples: u32) {
+        if self.pipelines.contains_key(&(format, blend, samples)) {
+            return;
+        }
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Document Pipeline"),
+            layout: Some(&self.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &self.shader,
+                entry_point: "vs_main",
+                buffers: &[QuadVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(blend.state()),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Flipped layers wind the other way
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+        self.pipelines.insert((format, blend, samples), pipeline);
+    }
+
+    /// The snapshot [`DocumentPass::encode`] last drew.
+    pub(crate) fn last_snapshot(&self) -> Option<&DocumentSnapshot> {
+        self.snapshot.as_ref()
+    }
+
+    /// The size of the layer's output as last uploaded, if it has one.
+    pub(crate) fn layer_size(&self, id: &LayerId) -> Option<(u32, u32)> {
+        Some(self.layers.get(id)?.texture.as_ref()?.size)
+    }
+
+    fn bind_group(&self, target: &DrawTarget) -> Option<&QuadTexture> {
+        match target {
+            DrawTarget::Clear => Some(&self.clear),
+            DrawTarget::Background => Some(&self.background),
+            DrawTarget::Composite => self.composite.as_ref().map(|(_, _, texture)| texture),
+            DrawTarget::Layer(id) => self.layers.get(id)?.texture.as_ref(),
+        }
+    }
+}
+
+/// Uploads `image` as a texture sampled in linear light if `linear`, scaling
+/// it down if it exceeds the device's texture size limit. The texture has a
+/// full mip chain, averaged in the same encoding it's sampled in.
+fn upload_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    staging: &StagingPool,
+    mipmaps: &mut MipmapPass,
+    image: &DynamicImage,
+    linear: bool,
+) -> wgpu::Texture {
+    let max = device.limits().max_texture_dimension_2d;
+    let rgba = if image.width() > max || image.height() > max {
+        image.resize(max, max, image::imageops::FilterType::Triangle).to_rgba8()
+    } else {
+        image.to_rgba8()
+    };
+    let extent = wgpu::Extent3d {
+        width: rgba.width(),
+        height: rgba.height(),
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Document Layer Texture"),
+        size: extent,
+        mip_level_count: mip_level_count(extent.width, extent.height),
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: if linear { wgpu::TextureFormat::Rgba8UnormSrgb } else { wgpu::TextureFormat::Rgba8Unorm },
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Upload Encoder"),
+    });
+    staging.write_texture(device, &mut encoder, &texture, &rgba);
+    mipmaps.generate(device, &mut encoder, &texture);
+    queue.submit(std::iter::once(encoder.finish()));
+    staging.submitted(queue);
+    texture
+}
+
+/// Binds `texture`, showing a `size` canvas area, for drawing as a quad.
+fn quad_texture(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sampler: &wgpu::Sampler,
+    texture: Arc<wgpu::Texture>,
+    size: (u32, u32),
+) -> QuadTexture {
+    let uniforms = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Document Quad Buffer"),
+        contents: bytemuck::cast_slice(&[QuadUniforms { tint: [1.0; 4] }]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Document Quad Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: uniforms.as_entire_binding(),
+            },
+        ],
+    });
+    QuadTexture {
+        size,
+        _texture: texture,
+        uniforms,
+        bind_group,
+    }
+}
+
+/// A texture [`DocumentPass::encode`] draws into.
+pub(crate) struct Frame<'a> {
+    pub texture: &'a wgpu::Texture,
+    pub format: wgpu::TextureFormat,
+    /// The texture's view formats. Without the non-sRGB counterpart of an
+    /// sRGB `format` among them, documents are blended in linear light
+    /// whatever their color profile.
+    pub view_formats: &'a [wgpu::TextureFormat],
+    pub size: (u32, u32),
+    /// Fills the area outside the canvas.
+    pub clear: wgpu::Color,
+    /// Fills the canvas under the layers.
+    pub background: wgpu::Color,
+    /// Drawn into instead, then resolved into `texture`. Has the same
+    /// format and view formats.
+    pub msaa: Option<&'a wgpu::Texture>,
+    /// Only these regions are redrawn, over what `texture` holds, if set.
+    /// Not used with `msaa`.
+    pub damage: Option<&'a [ScissorRect]>,
+}
+
+impl DocumentPass {
+    /// Records drawing `snapshot` into `frame`, placed by `view`.
+    pub(crate) fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: Frame<'_>,
+        snapshot: &DocumentSnapshot,
+        view: ViewTransform,
+    ) -> Result<(), DocumentError> {
+        let same_snapshot = self.snapshot.as_ref().map_or(false, |last| last.ptr_eq(snapshot));
+        // Without a non-sRGB view of an sRGB frame, every document has to
+        // blend in linear light
+        let srgb_only = frame.format.is_srgb() && !frame.view_formats.contains(&frame.format.remove_srgb_suffix());
+        let linear =
+            srgb_only || snapshot.color_profile().blends_in_linear_light() != (self.debug == ColorDebugMode::SwapEncoding);
+        let canvas = layer_quad(&LayerTransform::IDENTITY, snapshot.width(), snapshot.height());
+        let tint = |color: wgpu::Color| [color.r as f32, color.g as f32, color.b as f32, color.a as f32];
+        let mut draws = Vec::new();
+        if frame.damage.is_some() {
+            // Damaged regions are cleared like the whole target otherwise is
+            draws.push(Draw {
+                target: DrawTarget::Clear,
+                blend: GpuBlend::Replace,
+                tint: tint(frame.clear),
+                vertices: viewport_quad(&view, frame.size),
+            });
+        }
+        draws.push(Draw {
+            target: DrawTarget::Background,
+            blend: GpuBlend::Normal,
+            tint: tint(frame.background),
+            vertices: canvas,
+        });
+
+        let ids: HashSet<&LayerId> = snapshot.layers().iter().map(|layer| layer.id()).collect();
+        self.layers.retain(|id, _| ids.contains(id));
+
+        let method = match self.debug {
+            ColorDebugMode::CpuReference => CompositeMethod::Cpu,
+            _ => composite_method(snapshot),
+        };
+        if method == CompositeMethod::Quads {
+            self.composite = None;
+            for layer in snapshot.shown_layers() {
+                self.update_layer_texture(device, queue, snapshot, layer, linear, same_snapshot)?;
+                let Some(texture) = self.layers.get(layer.id()).and_then(|cached| cached.texture.as_ref()) else {
+                    continue;
+                };
+                draws.push(Draw {
+                    target: DrawTarget::Layer(layer.id().clone()),
+                    blend: GpuBlend::for_mode(layer.blend_mode()).unwrap_or(GpuBlend::Normal),
+                    tint: [1.0, 1.0, 1.0, layer.opacity()],
+                    vertices: layer_quad(&layer.transform(), texture.size.0, texture.size.1),
+                });
+            }
+        } else {
+            if !same_snapshot || self.composite.as_ref().map_or(true, |(cached, cached_linear, _)| (*cached, *cached_linear) != (method, linear)) {
+                let texture = if method == CompositeMethod::BlendPass {
+                    self.composite_with_blend_pass(device, queue, snapshot, same_snapshot)?
+                } else {
+                    let composite = snapshot.render_composite()?;
+                    self.texture_writes += 1;
+                    let texture = upload_texture(device, queue, &self.staging, &mut self.mipmaps, &composite, linear);
+                    let size = (composite.width(), composite.height());
+                    quad_texture(device, &self.quad_layout, &self.sampler, Arc::new(texture), size)
+                };
+                self.composite = Some((method, linear, texture));
+            }
+            draws.push(Draw {
+                target: DrawTarget::Composite,
+                blend: GpuBlend::Normal,
+                tint: [1.0; 4],
+                vertices: canvas,
+            });
+        }
+        self.snapshot = Some(snapshot.clone());
+
+        // Blending happens in the view's encoding: linear light through an
+        // sRGB view, sRGB values otherwise
+        let format = if linear { frame.format } else { frame.format.remove_srgb_suffix() };
+        let samples = frame.msaa.map_or(1, |msaa| msaa.sample_count());
+        for draw in &draws {
+            self.ensure_pipeline(device, format, draw.blend, samples);
+        }
+        let uniforms = ViewUniforms::new(frame.size, &view);
+        queue.write_buffer(&self.viewport, 0, bytemuck::cast_slice(&[uniforms]));
+        for draw in &draws {
+            if let Some(texture) = self.bind_group(&draw.target) {
+                queue.write_buffer(&texture.uniforms, 0, bytemuck::cast_slice(&[QuadUniforms { tint: draw.tint }]));
+            }
+        }
+        let vertices: Vec<QuadVertex> = draws.iter().flat_map(|draw| draw.vertices).collect();
+        let vertex_bytes: &[u8] = bytemuck::cast_slice(&vertices);
+        let vertex_buffer = self.staging.write(device, queue, StagingKind::Vertex, vertex_bytes);
+
+        let view_descriptor = wgpu::TextureViewDescriptor {
+            format: Some(format),
+            ..Default::default()
+        };
+        let target = frame.texture.create_view(&view_descriptor);
+        let msaa = frame.msaa.map(|msaa| msaa.create_view(&view_descriptor));
+        let attachment = match frame.damage {
+            None => color_attachment(&target, msaa.as_ref(), frame.clear),
+            Some(_) => wgpu::RenderPassColorAttachment {
+                view: &target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            },
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Document Pass"),
+            color_attachments: &[Some(attachment)],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_bind_group(0, &self.viewport_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..vertex_bytes.len() as u64));
+        let regions: Vec<Option<&ScissorRect>> = match frame.damage {
+            Some(damage) => damage.iter().map(Some).collect(),
+            None => vec![None],
+        };
+        for region in regions {
+            if let Some(region) = region {
+                render_pass.set_scissor_rect(region.x, region.y, region.width, region.height);
+            }
+            for (index, draw) in draws.iter().enumerate() {
+                let Some(texture) = self.bind_group(&draw.target) else {
+                    continue;
+                };
+                render_pass.set_pipeline(&self.pipelines[&(format, draw.blend, samples)]);
+                render_pass.set_bind_group(1, &texture.bind_group, &[]);
+                let first = index as u32 * 6;
+                render_pass.draw(first..first + 6, 0..1);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DocumentPass {
+    /// Uploads the layer's output unless the cached texture is current or
+    /// its content is in the [`TextureCache`].
+    fn update_layer_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        snapshot: &DocumentSnapshot,
+        layer: &LayerSnapshot,
+        linear: bool,
+        same_snapshot: bool,
+    ) -> Result<(), DocumentError> {
+        let source = layer.content_hash().map_or(TextureSource::Snapshot, TextureSource::Content);
+        let current = self.layers.get(layer.id()).map_or(false, |cached| {
+            cached.linear == linear && cached.source == source && (source != TextureSource::Snapshot || same_snapshot)
+        });
+        if current {
+            return Ok(());
+        }
+
+        let cached = match source {
+            TextureSource::Content(hash) => self.cache.get(hash, linear),
+            TextureSource::Snapshot => None,
+        };
+        let cached = match cached {
+            Some(cached) => Some(cached),
+            None => match snapshot.layer_output(layer)? {
+                Some(output) if output.width() > 0 && output.height() > 0 => {
+                    self.texture_writes += 1;
+                    let cached = CachedTexture {
+                        texture: Arc::new(upload_texture(device, queue, &self.staging, &mut self.mipmaps, &output, linear)),
+                        size: (output.width(), output.height()),
+                    };
+                    if let TextureSource::Content(hash) = source {
+                        self.cache.insert(hash, linear, cached.clone());
+                    }
+                    Some(cached)
+                }
+                _ => None,
+            },
+        };
+        let texture = cached.map(|cached| quad_texture(device, &self.quad_layout, &self.sampler, cached.texture, cached.size));
+        self.layers.insert(layer.id().clone(), CachedOutput { source, linear, texture });
+        Ok(())
+    }
+
+    /// Composites the shown layers offscreen: each is placed on a
+    /// canvas-sized texture and blended into the layers below by
+    /// [`BlendPass`].
+    fn composite_with_blend_pass(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        snapshot: &DocumentSnapshot,
+        same_snapshot: bool,
+    ) -> Result<QuadTexture, DocumentError> {
+        let size = (snapshot.width(), snapshot.height());
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        self.ensure_pipeline(device, format, GpuBlend::Normal, 1);
+        let uniforms = ViewUniforms::new(size, &ViewTransform::IDENTITY);
+
+        let mut canvas = TextureHandle::new(device, size);
+        for layer in snapshot.shown_layers() {
+            self.update_layer_texture(device, queue, snapshot, layer, false, same_snapshot)?;
+            let Some(texture) = self.layers.get(layer.id()).and_then(|cached| cached.texture.as_ref()) else {
+                continue;
+            };
+            let vertices = layer_quad(&layer.transform(), texture.size.0, texture.size.1);
+            let vertex_bytes: &[u8] = bytemuck::cast_slice(&vertices);
+            let vertex_buffer = self.staging.write(device, queue, StagingKind::Vertex, vertex_bytes);
+            queue.write_buffer(&self.viewport, 0, bytemuck::cast_slice(&[uniforms]));
+            queue.write_buffer(&texture.uniforms, 0, bytemuck::cast_slice(&[QuadUniforms { tint: [1.0; 4] }]));
+
+            let placed = TextureHandle::new(device, size);
+            let target = placed.view();
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Placement Encoder"),
+            });
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Placement Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &target,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                render_pass.set_pipeline(&self.pipelines[&(format, GpuBlend::Normal, 1)]);
+                render_pass.set_bind_group(0, &self.viewport_bind_group, &[]);
+                render_pass.set_bind_group(1, &texture.bind_group, &[]);
+                render_pass.set_vertex_buffer(0, vertex_buffer.slice(..vertex_bytes.len() as u64));
+                render_pass.draw(0..6, 0..1);
+            }
+            // Submitted now, as the uniforms are rewritten for the next layer
+            queue.submit(std::iter::once(encoder.finish()));
+            self.staging.submitted(queue);
+            canvas = self.blend.blend(device, queue, &canvas, &placed, layer.blend_mode(), layer.opacity());
+        }
+        Ok(quad_texture(device, &self.quad_layout, &self.sampler, Arc::new(canvas.into_texture()), size))
+    }
+}
+
+impl Renderer {
+    /// Sets the color drawn under the document's canvas, which is otherwise
+    /// white.
+    pub fn set_document_background(&mut self, color: wgpu::Color) {
+        self.document_background = color;
+    }
+
+    pub fn color_debug_mode(&self) -> ColorDebugMode {
+        self.document.debug
+    }
+
+    pub fn set_color_debug_mode(&mut self, mode: ColorDebugMode) {
+        self.document.debug = mode;
+        self.damage.invalidate();
+    }
+
+    /// The layer textures [`Renderer::render_document`] reuses.
+    pub fn texture_cache(&self) -> &TextureCache {
+        &self.document.cache
+    }
+
+    /// For setting the cache's budget or invalidating textures.
+    pub fn texture_cache_mut(&mut self) -> &mut TextureCache {
+        &mut self.document.cache
+    }
+
+    /// Uploads `image` for [`Renderer::blend_on_gpu`].
+    pub fn upload_texture(&self, image: &DynamicImage) -> TextureHandle {
+        TextureHandle::upload(&self.device, &self.queue, &self.staging, image)
+    }
+
+    /// Blends `top` over `bottom` like meridian_document's `blend_images`,
+    /// over the area both share from their top-left corners.
+    pub fn blend_on_gpu(&self, bottom: &TextureHandle, top: &TextureHandle, mode: BlendMode, opacity: f32) -> TextureHandle {
+        self.document.blend.blend(&self.device, &self.queue, bottom, top, mode, opacity)
+    }
+
+    /// Draws `snapshot` over its background and presents it, placed by the
+    /// [`crate::Camera`], with the [`crate::Overlay`] and any text queued by
+    /// [`Renderer::draw_text`] over it.
+    ///
+    /// Layer outputs are uploaded when their content hash changes, so
+    /// redrawing after moving the camera evaluates no graphs. Outputs that
+    /// can't be hashed, and CPU composites, are reused while the same
+    /// snapshot is drawn. With damage tracking, see
+    /// [`Renderer::set_damage_tracking`], only damaged regions are redrawn.
+    pub fn render_document(&mut self, snapshot: &DocumentSnapshot) -> Result<(), RenderError> {
+        self.render_document_frame(snapshot, None)
+    }
+
+    pub(crate) fn render_document_frame(
+        &mut self,
+        snapshot: &DocumentSnapshot,
+        ui: Option<(&mut EguiLayer, &winit::window::Window)>,
+    ) -> Result<(), RenderError> {
+        let Some(output) = acquire_with_retry(
+            || self.surface.get_current_texture(),
+            || self.surface.configure(&self.device, &self.config),
+        )?
+        else {
+            return Ok(());
+        };
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Document Encoder"),
+        });
+        let timing = self.profiler.begin(&self.device, &mut encoder);
+        let size = (self.size.width, self.size.height);
+        let view = self.camera.view();
+        let copies = self.config.usage.contains(wgpu::TextureUsages::COPY_DST);
+        if self.damage.enabled() && self.msaa.is_none() && copies {
+            let key = FrameKey {
+                format: self.config.format,
+                size,
+                view,
+                canvas: (snapshot.width(), snapshot.height()),
+                clear: self.background_color,
+                background: self.document_background,
+            };
+            let (texture, regions) = self.damage.begin(&self.device, key, &self.config.view_formats, snapshot);
+            let frame = Frame {
+                texture,
+                format: self.config.format,
+                view_formats: &self.config.view_formats,
+                size,
+                clear: self.background_color,
+                background: self.document_background,
+                msaa: None,
+                damage: regions.as_deref(),
+            };
+            if let Err(err) = self.document.encode(&self.device, &self.queue, &mut encoder, frame, snapshot, view) {
+                self.damage.invalidate();
+                return Err(err.into());
+            }
+            self.damage.copy_to(&mut encoder, &output.texture);
+        } else {
+            let frame = Frame {
+                texture: &output.texture,
+                format: self.config.format,
+                view_formats: &self.config.view_formats,
+                size,
+                clear: self.background_color,
+                background: self.document_background,
+                msaa: self.msaa.as_ref().map(MsaaTarget::texture),
+                damage: None,
+            };
+            self.document.encode(&self.device, &self.queue, &mut encoder, frame, snapshot, view)?;
+        }
+        self.queue_overlay(snapshot);
+        self.text.encode(&self.device, &self.queue, &mut encoder, &output.texture, self.config.format, size);
+        self.paint_ui(ui, &mut encoder, &output);
+
+        self.profiler.submit(&self.queue, encoder, timing);
+        self.staging.submitted(&self.queue);
+        output.present();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use meridian_document::{ColorProfile, Document};
+
+    #[test]
+    fn test_layer_quad_follows_transform() {
+        let quad = layer_quad(&LayerTransform::translation(3.0, 5.0), 8, 4);
+        assert_eq!(quad[0], QuadVertex { position: [3.0, 5.0], uv: [0.0, 0.0] });
+        assert_eq!(quad[5], QuadVertex { position: [11.0, 9.0], uv: [1.0, 1.0] });
+
+        let centered = ViewTransform::centered((100, 50), (300, 250), 2.0);
+        assert_eq!(centered.apply(0.0, 0.0), [50.0, 75.0]);
+        assert_eq!(centered.apply(100.0, 50.0), [250.0, 175.0]);
+    }
+
+    #[test]
+    fn test_composite_method() {
+        let mut doc = Document::with_size(16, 16);
+        let multiply = doc.add_layer();
+        doc.get_layer(&multiply).unwrap().write().set_blend_mode(BlendMode::Multiply);
+        assert_eq!(composite_method(&doc.snapshot()), CompositeMethod::Quads);
+
+        let screen = doc.add_layer();
+        {
+            let layer = doc.get_layer(&screen).unwrap();
+            let mut layer = layer.write();
+            layer.set_blend_mode(BlendMode::Screen);
+            layer.set_visible(false);
+        }
+        assert_eq!(composite_method(&doc.snapshot()), CompositeMethod::Quads);
+        doc.get_layer(&screen).unwrap().write().set_visible(true);
+        assert_eq!(composite_method(&doc.snapshot()), CompositeMethod::BlendPass);
+        doc.set_color_profile(ColorProfile::LinearSrgb);
+        assert_eq!(composite_method(&doc.snapshot()), CompositeMethod::Cpu);
+    }
+}
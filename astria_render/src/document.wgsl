@@ -0,0 +1,43 @@
+struct Viewport {
+    size: vec2<f32>,
+    pan: vec2<f32>,
+    zoom: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> viewport: Viewport;
+
+struct Quad {
+    tint: vec4<f32>,
+}
+
+@group(1) @binding(0)
+var quad_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var quad_sampler: sampler;
+@group(1) @binding(2)
+var<uniform> quad: Quad;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// Positions are in canvas pixels, y pointing down, and are moved into the
+// viewport by the view
+@vertex
+fn vs_main(@location(0) position: vec2<f32>, @location(1) uv: vec2<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    let screen = position * viewport.zoom + viewport.pan;
+    let ndc = screen / viewport.size * 2.0 - vec2<f32>(1.0, 1.0);
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(quad_texture, quad_sampler, in.uv) * quad.tint;
+    // Textures hold straight alpha; both blend states expect premultiplied
+    return vec4<f32>(color.rgb * color.a, color.a);
+}
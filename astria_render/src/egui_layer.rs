@@ -0,0 +1,207 @@
+//! egui drawn over the viewport, e.g. for panels and the node editor.
+//!
+//! Window events go through [`EguiLayer::handle_event`] first, which says
+//! whether the UI, the viewport or both should handle them: pointer events
+//! go to the UI while the pointer is over it or dragging something in it,
+//! keys while a text field has focus, and everything else to both. UI is
+//! built between [`EguiLayer::begin_frame`] and
+//! [`EguiLayer::end_frame_and_paint`], which draws it over the frame.
+
+use winit::event::WindowEvent;
+use winit::window::Window;
+use crate::{RenderError, Renderer};
+
+/// Which layer should handle a window event, see
+/// [`EguiLayer::handle_event`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventRoute {
+    /// Only the UI; the viewport should ignore it.
+    Ui,
+    /// Only the viewport, e.g. panning or zooming.
+    Viewport,
+    /// Both, e.g. resizes and focus changes.
+    Both,
+}
+
+/// Whether `event` comes from the pointer or the keyboard, `None` for
+/// window events.
+fn input_kind(event: &WindowEvent<'_>) -> Option<InputKind> {
+    match event {
+        WindowEvent::CursorMoved { .. }
+        | WindowEvent::MouseInput { .. }
+        | WindowEvent::MouseWheel { .. }
+        | WindowEvent::TouchpadMagnify { .. }
+        | WindowEvent::TouchpadRotate { .. }
+        | WindowEvent::Touch(_) => Some(InputKind::Pointer),
+        WindowEvent::KeyboardInput { .. } | WindowEvent::ReceivedCharacter(_) | WindowEvent::Ime(_) => {
+            Some(InputKind::Keyboard)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputKind {
+    Pointer,
+    Keyboard,
+}
+
+/// egui state for a window and the renderer that paints it.
+pub struct EguiLayer {
+    context: egui::Context,
+    state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    in_frame: bool,
+}
+
+impl EguiLayer {
+    /// Paints into `format` targets, like the surface of a
+    /// [`crate::Renderer`] on `window`.
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, window: &Window) -> Self {
+        let context = egui::Context::default();
+        let mut state = egui_winit::State::new(window);
+        state.set_pixels_per_point(window.scale_factor() as f32);
+        Self {
+            context,
+            state,
+            renderer: egui_wgpu::Renderer::new(device, format, None, 1),
+            in_frame: false,
+        }
+    }
+
+    pub fn context(&self) -> &egui::Context {
+        &self.context
+    }
+
+    /// Passes `event` to egui and returns which layer should handle it.
+    /// Routing follows the last frame's UI.
+    pub fn handle_event(&mut self, event: &WindowEvent<'_>) -> EventRoute {
+        if let WindowEvent::ScaleFactorChanged { scale_factor, .. } = event {
+            self.state.set_pixels_per_point(*scale_factor as f32);
+        }
+        let response = self.state.on_event(&self.context, event);
+        match input_kind(event) {
+            Some(InputKind::Pointer) => {
+                let over_ui = self.context.wants_pointer_input() || self.context.is_pointer_over_area();
+                if response.consumed || over_ui {
+                    EventRoute::Ui
+                } else {
+                    EventRoute::Viewport
+                }
+            }
+            Some(InputKind::Keyboard) => {
+                if self.context.wants_keyboard_input() {
+                    EventRoute::Ui
+                } else {
+                    EventRoute::Viewport
+                }
+            }
+            None => EventRoute::Both,
+        }
+    }
+
+    /// Starts a frame with the input handled since the last one. Build UI
+    /// with the returned context before [`EguiLayer::end_frame_and_paint`].
+    pub fn begin_frame(&mut self, window: &Window) -> egui::Context {
+        let input = self.state.take_egui_input(window);
+        self.context.begin_frame(input);
+        self.in_frame = true;
+        self.context.clone()
+    }
+
+    /// Ends the frame and records painting it over `view`, a `size` target.
+    /// Does nothing without a [`EguiLayer::begin_frame`].
+    pub fn end_frame_and_paint(
+        &mut self,
+        window: &Window,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        size: (u32, u32),
+    ) {
+        if !std::mem::take(&mut self.in_frame) {
+            return;
+        }
+        let output = self.context.end_frame();
+        self.state.handle_platform_output(window, &self.context, output.platform_output);
+        let primitives = self.context.tessellate(output.shapes);
+        let screen = egui_wgpu::renderer::ScreenDescriptor {
+            size_in_pixels: [size.0, size.1],
+            pixels_per_point: self.context.pixels_per_point(),
+        };
+
+        for (id, delta) in &output.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        // Work for paint callbacks, which has to run before the frame's
+        let callbacks = self.renderer.update_buffers(device, queue, encoder, &primitives, &screen);
+        if !callbacks.is_empty() {
+            queue.submit(callbacks);
+        }
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Egui Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.renderer.render(&mut render_pass, &primitives, &screen);
+        }
+        for id in &output.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
+
+impl Renderer {
+    /// Same as [`Renderer::render`], with `ui`'s frame painted over the
+    /// shapes and text.
+    pub fn render_with_ui(&mut self, ui: &mut EguiLayer, window: &Window) -> Result<(), RenderError> {
+        self.render_frame(Some((ui, window)))
+    }
+
+    /// Same as [`Renderer::render_document`], with `ui`'s frame painted over
+    /// the document, overlay and text.
+    pub fn render_document_with_ui(
+        &mut self,
+        snapshot: &meridian_document::DocumentSnapshot,
+        ui: &mut EguiLayer,
+        window: &Window,
+    ) -> Result<(), RenderError> {
+        self.render_document_frame(snapshot, Some((ui, window)))
+    }
+
+    /// Records painting `ui` over the frame in `output`.
+    pub(crate) fn paint_ui(
+        &self,
+        ui: Option<(&mut EguiLayer, &Window)>,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::SurfaceTexture,
+    ) {
+        if let Some((ui, window)) = ui {
+            let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+            let size = (self.size.width, self.size.height);
+            ui.end_frame_and_paint(window, &self.device, &self.queue, encoder, &view, size);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_kinds() {
+        assert_eq!(input_kind(&WindowEvent::ReceivedCharacter('a')), Some(InputKind::Keyboard));
+        assert_eq!(input_kind(&WindowEvent::CursorLeft { device_id: unsafe { winit::event::DeviceId::dummy() } }), None);
+        assert_eq!(input_kind(&WindowEvent::Focused(true)), None);
+        assert_eq!(input_kind(&WindowEvent::Resized(winit::dpi::PhysicalSize::new(8, 8))), None);
+    }
+}
@@ -0,0 +1,1015 @@
+//! Rendering without a window, into a texture that can be read back.
+
+use std::sync::Arc;
+use image::{DynamicImage, RgbaImage};
+use meridian_document::{BlendMode, CanvasRect, DocumentSnapshot};
+use crate::backend::{BackendKind, ImageId, ImageStore, RenderBackend};
+use crate::blend::TextureHandle;
+use crate::capture::capture_texture;
+use crate::damage::{DamageTracker, FrameKey};
+use crate::document::{DocumentPass, Frame};
+use crate::msaa::{self, MsaaTarget};
+use crate::options::request_device;
+use crate::staging::{padded_row_bytes, StagingKind, StagingPool, StagingStats};
+use crate::stats::{FrameProfiler, FrameStats};
+use crate::text::TextPass;
+use crate::thumbnail::ThumbnailPass;
+use crate::{
+    create_vertex_buffer, ColorDebugMode, RenderError, RendererCapabilities, RendererOptions, ShapePass, TextureCache,
+    ViewTransform,
+};
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+fn create_target(device: &wgpu::Device, view_formats: &[wgpu::TextureFormat], width: u32, height: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST,
+        view_formats,
+    })
+}
+
+/// A [`crate::Renderer`] that draws into an offscreen texture instead of a
+/// window, for tests and exports. The target starts out 1×1; see
+/// [`HeadlessRenderer::resize`].
+pub struct HeadlessRenderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    target: wgpu::Texture,
+    // Empty where the device can't view textures in other formats
+    view_formats: Vec<wgpu::TextureFormat>,
+    size: (u32, u32),
+    shapes: ShapePass,
+    vertex_buffer: Option<(wgpu::Buffer, u32)>,
+    background_color: wgpu::Color,
+    fill_color: Option<wgpu::Color>,
+    document: DocumentPass,
+    document_background: wgpu::Color,
+    msaa_samples: u32,
+    msaa: Option<MsaaTarget>,
+    capabilities: RendererCapabilities,
+    text: TextPass,
+    thumbnails: ThumbnailPass,
+    profiler: FrameProfiler,
+    staging: StagingPool,
+    damage: DamageTracker,
+    // Uploaded through RenderBackend
+    images: ImageStore<TextureHandle>,
+    // What the last document was drawn with, for captures
+    last_view: ViewTransform,
+}
+
+impl HeadlessRenderer {
+    /// Fails if no GPU adapter is available.
+    pub fn new() -> anyhow::Result<Self> {
+        Self::new_with_options(RendererOptions::default())
+    }
+
+    /// Same as [`crate::Renderer::new_with_options`]; the present mode is
+    /// ignored.
+    pub fn new_with_options(options: RendererOptions) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+        let (adapter, device, queue, capabilities) = pollster::block_on(request_device(&instance, None, &options))?;
+
+        // Documents that don't blend in linear light draw through a
+        // non-sRGB view
+        let view_formats = if capabilities.supports_view_formats {
+            vec![FORMAT.remove_srgb_suffix()]
+        } else {
+            Vec::new()
+        };
+        let target = create_target(&device, &view_formats, 1, 1);
+        let msaa_samples = msaa::sample_count(&adapter, &device, FORMAT, options.msaa_samples);
+        let msaa = MsaaTarget::new(&device, FORMAT, &view_formats, (1, 1), msaa_samples);
+        let shapes = ShapePass::new(&device, FORMAT, msaa_samples, 1, 1);
+        let staging = StagingPool::new();
+        let document = DocumentPass::new(&device, &queue, staging.clone());
+        let text = TextPass::new(&device, &queue, staging.clone());
+        let thumbnails = ThumbnailPass::new(&device, staging.clone());
+        let profiler = FrameProfiler::new(&device, &queue);
+        Ok(Self {
+            device,
+            queue,
+            target,
+            view_formats,
+            size: (1, 1),
+            shapes,
+            vertex_buffer: None,
+            background_color: wgpu::Color::TRANSPARENT,
+            fill_color: None,
+            document,
+            document_background: wgpu::Color::WHITE,
+            msaa_samples,
+            msaa,
+            capabilities,
+            text,
+            thumbnails,
+            profiler,
+            staging,
+            damage: DamageTracker::default(),
+            images: ImageStore::default(),
+            last_view: ViewTransform::IDENTITY,
+        })
+    }
+
+    /// Replaces the target with a `width`×`height` one, clamped to the
+    /// device's texture size limit. Zero sizes are ignored.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let max = self.device.limits().max_texture_dimension_2d;
+        let (width, height) = (width.min(max), height.min(max));
+        if width > 0 && height > 0 && (width, height) != self.size {
+            self.size = (width, height);
+            self.target = create_target(&self.device, &self.view_formats, width, height);
+            self.msaa = MsaaTarget::new(
+                &self.device,
+                FORMAT,
+                &self.view_formats,
+                (width, height),
+                self.msaa_samples,
+            );
+            self.shapes.set_viewport(&self.queue, width, height);
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn msaa_samples(&self) -> u32 {
+        self.msaa_samples
+    }
+
+    pub fn capabilities(&self) -> &RendererCapabilities {
+        &self.capabilities
+    }
+
+    pub fn staging_stats(&self) -> StagingStats {
+        self.staging.stats()
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn set_vertex_buffer(&mut self, vertices: &[f32]) {
+        self.vertex_buffer = Some(create_vertex_buffer(&self.device, vertices));
+    }
+
+    pub fn set_background_color(&mut self, color: wgpu::Color) {
+        self.background_color = color;
+    }
+
+    /// Same as [`crate::Renderer::set_fill_color`]. There's no fill until
+    /// one is set.
+    pub fn set_fill_color(&mut self, color: Option<wgpu::Color>) {
+        if let Some(color) = color {
+            self.shapes.set_fill(&self.queue, color);
+        }
+        self.fill_color = color;
+    }
+
+    pub fn set_document_background(&mut self, color: wgpu::Color) {
+        self.document_background = color;
+    }
+
+    /// Same as [`crate::Renderer::draw_text`], with `pos` and `size_px` in
+    /// target pixels.
+    pub fn draw_text(&mut self, text: &str, pos: [f32; 2], size_px: f32, color: wgpu::Color) {
+        self.text.queue(text, pos, size_px, color);
+    }
+
+    /// Same as [`crate::Renderer::render`], into the target.
+    pub fn render(&mut self) {
+        let view = self.target.create_view(&wgpu::TextureViewDescriptor::default());
+        let msaa = self.msaa.as_ref().map(|msaa| msaa.texture().create_view(&wgpu::TextureViewDescriptor::default()));
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Encoder"),
+        });
+        let timing = self.profiler.begin(&self.device, &mut encoder);
+        self.shapes.draw(
+            &mut encoder,
+            &view,
+            msaa.as_ref(),
+            self.background_color,
+            self.fill_color.is_some(),
+            self.vertex_buffer.as_ref(),
+        );
+        self.text.encode(&self.device, &self.queue, &mut encoder, &self.target, FORMAT, self.size);
+        self.profiler.submit(&self.queue, encoder, timing);
+        self.staging.submitted(&self.queue);
+    }
+
+    /// Same as [`crate::Renderer::last_frame_stats`], for
+    /// [`HeadlessRenderer::render`] and [`HeadlessRenderer::render_document`].
+    pub fn last_frame_stats(&self) -> Option<FrameStats> {
+        self.profiler.last()
+    }
+
+    pub fn average_frame_stats(&self) -> Option<FrameStats> {
+        self.profiler.average()
+    }
+
+    pub fn set_color_debug_mode(&mut self, mode: ColorDebugMode) {
+        self.document.debug = mode;
+        self.damage.invalidate();
+    }
+
+    /// Same as [`crate::Renderer::set_damage_tracking`].
+    pub fn set_damage_tracking(&mut self, enabled: bool) {
+        self.damage.set_enabled(enabled);
+    }
+
+    /// Same as [`crate::Renderer::mark_damaged`].
+    pub fn mark_damaged(&mut self, rect: CanvasRect) {
+        self.damage.mark(rect);
+    }
+
+    /// Same as [`crate::Renderer::texture_cache`].
+    pub fn texture_cache(&self) -> &TextureCache {
+        &self.document.cache
+    }
+
+    pub fn texture_cache_mut(&mut self) -> &mut TextureCache {
+        &mut self.document.cache
+    }
+
+    /// Same as [`crate::Renderer::upload_texture`].
+    pub fn upload_texture(&self, image: &DynamicImage) -> TextureHandle {
+        TextureHandle::upload(&self.device, &self.queue, &self.staging, image)
+    }
+
+    /// Same as [`crate::Renderer::blend_on_gpu`].
+    pub fn blend_on_gpu(&self, bottom: &TextureHandle, top: &TextureHandle, mode: BlendMode, opacity: f32) -> TextureHandle {
+        self.document.blend.blend(&self.device, &self.queue, bottom, top, mode, opacity)
+    }
+
+    /// Same as [`crate::Renderer::render_thumbnail`].
+    pub fn render_thumbnail(&mut self, image: &DynamicImage, max_dim: u32) -> DynamicImage {
+        self.render_thumbnails(&[image], max_dim).remove(0)
+    }
+
+    /// Same as [`crate::Renderer::render_thumbnails`].
+    pub fn render_thumbnails(&mut self, images: &[&DynamicImage], max_dim: u32) -> Vec<DynamicImage> {
+        self.thumbnails.render(&self.device, &self.queue, images, max_dim)
+    }
+
+    /// Copies `texture` back from the GPU.
+    pub fn read_texture(&self, texture: &TextureHandle) -> DynamicImage {
+        DynamicImage::ImageRgba8(read_texture(&self.device, &self.queue, &self.staging, texture.texture(), texture.size()))
+    }
+
+    /// Same as [`crate::Renderer::render_document`], into the target and
+    /// placed by `view`. Only queued text is drawn over it; there's no
+    /// overlay.
+    pub fn render_document(&mut self, snapshot: &DocumentSnapshot, view: ViewTransform) -> Result<(), RenderError> {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Document Encoder"),
+        });
+        let timing = self.profiler.begin(&self.device, &mut encoder);
+        if self.damage.enabled() && self.msaa.is_none() {
+            let key = FrameKey {
+                format: FORMAT,
+                size: self.size,
+                view,
+                canvas: (snapshot.width(), snapshot.height()),
+                clear: self.background_color,
+                background: self.document_background,
+            };
+            let (texture, regions) = self.damage.begin(&self.device, key, &self.view_formats, snapshot);
+            let frame = Frame {
+                texture,
+                format: FORMAT,
+                view_formats: &self.view_formats,
+                size: self.size,
+                clear: self.background_color,
+                background: self.document_background,
+                msaa: None,
+                damage: regions.as_deref(),
+            };
+            if let Err(err) = self.document.encode(&self.device, &self.queue, &mut encoder, frame, snapshot, view) {
+                self.damage.invalidate();
+                return Err(err.into());
+            }
+            self.damage.copy_to(&mut encoder, &self.target);
+        } else {
+            let frame = Frame {
+                texture: &self.target,
+                format: FORMAT,
+                view_formats: &self.view_formats,
+                size: self.size,
+                clear: self.background_color,
+                background: self.document_background,
+                msaa: self.msaa.as_ref().map(MsaaTarget::texture),
+                damage: None,
+            };
+            self.document.encode(&self.device, &self.queue, &mut encoder, frame, snapshot, view)?;
+        }
+        self.text.encode(&self.device, &self.queue, &mut encoder, &self.target, FORMAT, self.size);
+        self.profiler.submit(&self.queue, encoder, timing);
+        self.staging.submitted(&self.queue);
+        self.last_view = view;
+        Ok(())
+    }
+
+    /// Same as [`crate::Renderer::capture_frame`]: draws the last document
+    /// rendered, or the shapes, into a new texture and reads it back. The
+    /// target is left alone.
+    pub fn capture_frame(&mut self) -> Result<DynamicImage, RenderError> {
+        let texture = capture_texture(&self.device, FORMAT, &self.view_formats, self.size);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Capture Encoder"),
+        });
+        match self.document.last_snapshot().cloned() {
+            Some(snapshot) => {
+                let frame = Frame {
+                    texture: &texture,
+                    format: FORMAT,
+                    view_formats: &self.view_formats,
+                    size: self.size,
+                    clear: self.background_color,
+                    background: self.document_background,
+                    msaa: self.msaa.as_ref().map(MsaaTarget::texture),
+                    damage: None,
+                };
+                let view = self.last_view;
+                self.document.encode(&self.device, &self.queue, &mut encoder, frame, &snapshot, view)?;
+            }
+            None => {
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                let msaa = self.msaa.as_ref().map(|msaa| msaa.texture().create_view(&wgpu::TextureViewDescriptor::default()));
+                self.shapes.draw(
+                    &mut encoder,
+                    &view,
+                    msaa.as_ref(),
+                    self.background_color,
+                    self.fill_color.is_some(),
+                    self.vertex_buffer.as_ref(),
+                );
+            }
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.staging.submitted(&self.queue);
+        Ok(DynamicImage::ImageRgba8(read_texture(&self.device, &self.queue, &self.staging, &texture, self.size)))
+    }
+
+    /// Copies the target back from the GPU, waiting for rendering to finish.
+    /// Pixels are sRGB encoded.
+    pub fn read_back(&self) -> DynamicImage {
+        DynamicImage::ImageRgba8(read_texture(&self.device, &self.queue, &self.staging, &self.target, self.size))
+    }
+}
+
+impl RenderBackend for HeadlessRenderer {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Gpu
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        HeadlessRenderer::resize(self, width, height);
+    }
+
+    fn set_background_color(&mut self, color: wgpu::Color) {
+        HeadlessRenderer::set_background_color(self, color);
+    }
+
+    fn set_document_background(&mut self, color: wgpu::Color) {
+        HeadlessRenderer::set_document_background(self, color);
+    }
+
+    fn upload_image(&mut self, image: &DynamicImage) -> ImageId {
+        let texture = self.upload_texture(image);
+        self.images.insert(texture)
+    }
+
+    fn release_image(&mut self, image: ImageId) {
+        self.images.remove(image);
+    }
+
+    fn blend(&mut self, bottom: ImageId, top: ImageId, mode: BlendMode, opacity: f32) -> Option<ImageId> {
+        let blended = self.blend_on_gpu(self.images.get(bottom)?, self.images.get(top)?, mode, opacity);
+        Some(self.images.insert(blended))
+    }
+
+    fn read_image(&self, image: ImageId) -> Option<DynamicImage> {
+        Some(self.read_texture(self.images.get(image)?))
+    }
+
+    fn render_document(&mut self, snapshot: &DocumentSnapshot, view: ViewTransform) -> Result<(), RenderError> {
+        HeadlessRenderer::render_document(self, snapshot, view)
+    }
+
+    fn read_back(&mut self) -> Result<DynamicImage, RenderError> {
+        Ok(HeadlessRenderer::read_back(self))
+    }
+}
+
+/// Copies a `size` texture in an 8-bit RGBA format back from the GPU,
+/// waiting for queued work to finish.
+pub(crate) fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    staging: &StagingPool,
+    texture: &wgpu::Texture,
+    size: (u32, u32),
+) -> RgbaImage {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    let readback = Readback::encode(device, staging, &mut encoder, texture, size);
+    queue.submit(std::iter::once(encoder.finish()));
+    readback.map(device).into_image()
+}
+
+/// A texture copied into a buffer, for reading back several textures with
+/// one submission: encode each, submit, then [`Readback::map`] them.
+pub(crate) struct Readback {
+    staging: StagingPool,
+    buffer: Arc<wgpu::Buffer>,
+    size: (u32, u32),
+    padded_row_bytes: u32,
+    receiver: Option<std::sync::mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+impl Readback {
+    /// Records copying a `size` texture in an 8-bit RGBA format into a
+    /// buffer from `staging`.
+    pub(crate) fn encode(
+        device: &wgpu::Device,
+        staging: &StagingPool,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        size: (u32, u32),
+    ) -> Self {
+        let (width, height) = size;
+        let padded_row_bytes = padded_row_bytes(width);
+        let buffer = staging.acquire(device, StagingKind::Readback, padded_row_bytes as u64 * height as u64);
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        Self {
+            staging: staging.clone(),
+            buffer,
+            size,
+            padded_row_bytes,
+            receiver: None,
+        }
+    }
+
+    /// The part of the buffer the texture was copied into, which may be
+    /// larger.
+    fn copied(&self) -> wgpu::BufferSlice<'_> {
+        self.buffer.slice(..self.padded_row_bytes as u64 * self.size.1 as u64)
+    }
+
+    /// Starts mapping the buffer without waiting, so several can be waited
+    /// for with one poll.
+    pub(crate) fn request_map(&mut self) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.copied().map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.receiver = Some(receiver);
+    }
+
+    /// Maps the buffer, waiting for the copy to finish.
+    pub(crate) fn map(mut self, device: &wgpu::Device) -> Self {
+        self.request_map();
+        device.poll(wgpu::Maintain::Wait);
+        self
+    }
+
+    /// The copied pixels. The buffer must have been mapped and the device
+    /// polled; it goes back to the pool.
+    pub(crate) fn into_image(self) -> RgbaImage {
+        self.receiver
+            .as_ref()
+            .expect("Readback buffer wasn't mapped")
+            .recv()
+            .expect("Readback callback dropped")
+            .expect("Failed to map readback buffer");
+
+        let (width, height) = self.size;
+        let row_bytes = 4 * width;
+        let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+        {
+            let data = self.copied().get_mapped_range();
+            for row in data.chunks(self.padded_row_bytes as usize) {
+                pixels.extend_from_slice(&row[..row_bytes as usize]);
+            }
+        }
+        self.buffer.unmap();
+        self.staging.release(self.buffer);
+        RgbaImage::from_raw(width, height, pixels).expect("Readback size mismatch")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use meridian_document::{BlendMode, ColorProfile, Document, LayerTransform};
+
+    /// A renderer, or `None` if GPU tests are disabled with
+    /// `ASTRIA_SKIP_GPU_TESTS` or no adapter is available.
+    fn renderer() -> Option<HeadlessRenderer> {
+        if std::env::var_os("ASTRIA_SKIP_GPU_TESTS").is_some() {
+            return None;
+        }
+        match HeadlessRenderer::new() {
+            Ok(renderer) => Some(renderer),
+            Err(e) => {
+                eprintln!("Skipping GPU test: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Like [`renderer`], but also `None` on devices that can't view the
+    /// target in another format, where every document blends in linear
+    /// light whatever its profile.
+    fn profiled_renderer() -> Option<HeadlessRenderer> {
+        renderer().filter(|renderer| renderer.capabilities().supports_view_formats)
+    }
+
+    fn pixel(image: &DynamicImage, x: u32, y: u32) -> [u8; 4] {
+        image.to_rgba8().get_pixel(x, y).0
+    }
+
+    #[test]
+    fn test_quad_readback() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        // Odd width, so rows need padding
+        renderer.resize(37, 20);
+        renderer.set_background_color(wgpu::Color::BLACK);
+        renderer.set_vertex_buffer(&[-0.5, -0.5, 0.5, -0.5, 0.5, 0.5, -0.5, -0.5, 0.5, 0.5, -0.5, 0.5]);
+        renderer.render();
+
+        let image = renderer.read_back();
+        assert_eq!((image.width(), image.height()), (37, 20));
+        assert_eq!(pixel(&image, 1, 1), [0, 0, 0, 255]);
+        assert_eq!(pixel(&image, 36, 19), [0, 0, 0, 255]);
+        let [r, g, b, a] = pixel(&image, 18, 10);
+        assert_eq!((r, a), (0, 255));
+        assert!(g > 150 && b > 200, "{:?}", (g, b));
+    }
+
+    #[test]
+    fn test_fill_covers_viewport() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        renderer.resize(16, 9);
+        renderer.set_background_color(wgpu::Color::BLACK);
+        renderer.render();
+        assert_eq!(pixel(&renderer.read_back(), 8, 4), [0, 0, 0, 255]);
+
+        renderer.set_fill_color(Some(wgpu::Color { r: 1.0, g: 0.0, b: 1.0, a: 1.0 }));
+        renderer.render();
+        let image = renderer.read_back();
+        for (x, y) in [(0, 0), (15, 0), (0, 8), (15, 8), (8, 4)] {
+            assert_eq!(pixel(&image, x, y), [255, 0, 255, 255]);
+        }
+
+        // Shapes draw over the fill
+        renderer.set_vertex_buffer(&[-1.0, -1.0, 1.0, -1.0, 1.0, 1.0]);
+        renderer.render();
+        assert_ne!(pixel(&renderer.read_back(), 15, 8), [255, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_document_matches_cpu_composite() {
+        let Some(mut renderer) = profiled_renderer() else {
+            return;
+        };
+        let mut doc = Document::with_size(32, 24);
+        let bottom = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(32, 24, Rgba([220, 120, 40, 255]))));
+        doc.add_asset_layer("Bottom", bottom);
+        let top = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(12, 12, Rgba([60, 200, 250, 255]))));
+        let top = doc.add_asset_layer("Top", top);
+        doc.set_layer_transform(&top, LayerTransform::translation(8.0, 6.0)).unwrap();
+        doc.get_layer(&top).unwrap().write().set_opacity(0.5);
+        renderer.resize(32, 24);
+
+        // Multiply blends with a blend state, Screen and Overlay offscreen
+        for mode in [BlendMode::Multiply, BlendMode::Screen, BlendMode::Overlay] {
+            doc.get_layer(&top).unwrap().write().set_blend_mode(mode);
+            let snapshot = doc.snapshot();
+            let expected = snapshot.render_composite().unwrap().to_rgba8();
+            renderer.render_document(&snapshot, ViewTransform::IDENTITY).unwrap();
+            let image = renderer.read_back().to_rgba8();
+            for (x, y) in [(2, 2), (10, 10), (19, 17), (25, 20)] {
+                let (actual, expected) = (image.get_pixel(x, y).0, expected.get_pixel(x, y).0);
+                for channel in 0..4 {
+                    assert!(
+                        actual[channel].abs_diff(expected[channel]) <= 2,
+                        "{:?}: {:?} != {:?} at {:?}",
+                        mode,
+                        actual,
+                        expected,
+                        (x, y)
+                    );
+                }
+            }
+
+            // Panned by 4 pixels; the area uncovered shows the clear color
+            renderer.render_document(&snapshot, ViewTransform { pan: [4.0, 0.0], zoom: 1.0 }).unwrap();
+            let panned = renderer.read_back().to_rgba8();
+            assert_eq!(panned.get_pixel(1, 1).0, [0, 0, 0, 0]);
+            assert_eq!(panned.get_pixel(14, 10), image.get_pixel(10, 10));
+        }
+    }
+
+    #[test]
+    fn test_unchanged_layers_are_not_uploaded_again() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        let mut doc = Document::with_size(16, 16);
+        for color in [[200, 30, 30, 255], [30, 30, 200, 128]] {
+            let asset = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba(color))));
+            doc.add_asset_layer("Layer", asset);
+        }
+        renderer.resize(16, 16);
+
+        renderer.render_document(&doc.snapshot(), ViewTransform::IDENTITY).unwrap();
+        let writes = renderer.document.texture_writes;
+        assert_eq!(writes, 2);
+        let stats = renderer.texture_cache().stats();
+        // Both textures with their 16×16 to 1×1 mip levels
+        let bytes = 2 * crate::mipmap::mip_chain_bytes(16, 16, 5);
+        assert_eq!((stats.hits, stats.misses, stats.resident_bytes), (0, 2, bytes));
+
+        // A new snapshot of the same content, then a new view
+        renderer.render_document(&doc.snapshot(), ViewTransform::IDENTITY).unwrap();
+        renderer.render_document(&doc.snapshot(), ViewTransform { pan: [2.0, 2.0], zoom: 2.0 }).unwrap();
+        assert_eq!(renderer.document.texture_writes, writes);
+    }
+
+    #[test]
+    fn test_texture_cache_evicts_over_budget() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        let mut doc = Document::with_size(16, 16);
+        let asset = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba([9, 9, 9, 255]))));
+        doc.add_asset_layer("Layer", asset);
+        let hash = doc.snapshot().layers()[0].content_hash().unwrap();
+
+        renderer.render_document(&doc.snapshot(), ViewTransform::IDENTITY).unwrap();
+        assert!(renderer.texture_cache().contains(hash));
+        assert!(renderer.texture_cache_mut().invalidate(hash));
+        assert_eq!(renderer.texture_cache().stats().resident_bytes, 0);
+
+        // The layer keeps drawing with the texture it has
+        renderer.render_document(&doc.snapshot(), ViewTransform::IDENTITY).unwrap();
+        assert_eq!(renderer.document.texture_writes, 1);
+
+        // A second layer with its own content is uploaded and cached, but
+        // doesn't fit a budget of less than one texture
+        let other = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba([7, 7, 7, 255]))));
+        doc.add_asset_layer("Other", other);
+        renderer.render_document(&doc.snapshot(), ViewTransform::IDENTITY).unwrap();
+        assert_eq!(renderer.document.texture_writes, 2);
+        assert_eq!(renderer.texture_cache().len(), 1);
+        renderer.texture_cache_mut().set_budget(16 * 16 * 4 - 1);
+        assert!(renderer.texture_cache().is_empty());
+    }
+
+    #[test]
+    fn test_msaa_smooths_edges() {
+        if std::env::var_os("ASTRIA_SKIP_GPU_TESTS").is_some() {
+            return;
+        }
+        // Pixels along the diagonal that are neither background nor shape
+        let edge_pixels = |samples: u32| -> Option<usize> {
+            let mut renderer = match HeadlessRenderer::new_with_options(RendererOptions {
+                msaa_samples: samples,
+                ..Default::default()
+            }) {
+                Ok(renderer) => renderer,
+                Err(e) => {
+                    eprintln!("Skipping GPU test: {}", e);
+                    return None;
+                }
+            };
+            assert_eq!(renderer.msaa_samples(), samples);
+            renderer.resize(32, 32);
+            renderer.set_background_color(wgpu::Color::BLACK);
+            // The lower right half of the viewport
+            renderer.set_vertex_buffer(&[-1.0, -1.0, 1.0, -1.0, 1.0, 1.0]);
+            renderer.render();
+            let image = renderer.read_back().to_rgba8();
+            let (background, shape) = (image.get_pixel(0, 0).0, image.get_pixel(31, 31).0);
+            Some(image.pixels().filter(|pixel| pixel.0 != background && pixel.0 != shape).count())
+        };
+
+        let Some(aliased) = edge_pixels(1) else {
+            return;
+        };
+        assert_eq!(aliased, 0);
+        assert!(edge_pixels(4).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_gray_over_white_matches_cpu_in_both_profiles() {
+        let Some(mut renderer) = profiled_renderer() else {
+            return;
+        };
+        let mut doc = Document::with_size(4, 4);
+        let white = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([255; 4]))));
+        doc.add_asset_layer("White", white);
+        let gray = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([128, 128, 128, 255]))));
+        let gray = doc.add_asset_layer("Gray", gray);
+        doc.get_layer(&gray).unwrap().write().set_opacity(0.5);
+        renderer.resize(4, 4);
+
+        // The profiles disagree, so a GPU result can only match one
+        let mut composites = Vec::new();
+        for profile in [ColorProfile::Srgb, ColorProfile::LinearSrgb] {
+            doc.set_color_profile(profile.clone());
+            let snapshot = doc.snapshot();
+            let expected = snapshot.render_composite().unwrap().to_rgba8().get_pixel(2, 2).0;
+            composites.push(expected);
+            for mode in [ColorDebugMode::Off, ColorDebugMode::CpuReference] {
+                renderer.set_color_debug_mode(mode);
+                renderer.render_document(&snapshot, ViewTransform::IDENTITY).unwrap();
+                let actual = pixel(&renderer.read_back(), 2, 2);
+                for channel in 0..4 {
+                    assert!(
+                        actual[channel].abs_diff(expected[channel]) <= 1,
+                        "{:?} {:?}: {:?} != {:?}",
+                        profile,
+                        mode,
+                        actual,
+                        expected
+                    );
+                }
+            }
+
+            // Blending in the wrong space visibly changes the result
+            renderer.set_color_debug_mode(ColorDebugMode::SwapEncoding);
+            renderer.render_document(&snapshot, ViewTransform::IDENTITY).unwrap();
+            assert!(pixel(&renderer.read_back(), 2, 2)[0].abs_diff(expected[0]) > 10, "{:?}", profile);
+        }
+        assert_ne!(composites[0], composites[1]);
+    }
+
+    #[test]
+    fn test_text_draws_in_glyph_cell() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        renderer.resize(32, 24);
+        renderer.set_background_color(wgpu::Color::BLACK);
+        // One physical pixel per font pixel, so the cell is 6×8 at (8, 8)
+        renderer.draw_text("A", [8.0, 8.0], 8.0, wgpu::Color::WHITE);
+        renderer.render();
+
+        let image = renderer.read_back().to_rgba8();
+        let in_cell = |x: u32, y: u32| (8..14).contains(&x) && (8..16).contains(&y);
+        let mut covered = 0;
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if pixel.0 != [0, 0, 0, 255] {
+                assert!(in_cell(x, y), "{:?} drawn at {:?}", pixel, (x, y));
+                covered += 1;
+            }
+        }
+        assert!(covered > 0);
+        // The left stroke of 'A'
+        assert_eq!(image.get_pixel(8, 10).0, [255, 255, 255, 255]);
+
+        // Text is drawn once
+        renderer.render();
+        assert_eq!(renderer.read_back().to_rgba8().get_pixel(8, 10).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_thumbnails() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        let solid = DynamicImage::ImageRgba8(RgbaImage::from_pixel(300, 120, Rgba([40, 180, 90, 200])));
+        let thumbnail = renderer.render_thumbnail(&solid, 64).to_rgba8();
+        assert_eq!(thumbnail.dimensions(), (64, 26));
+        assert!(thumbnail.pixels().all(|pixel| pixel.0 == [40, 180, 90, 200]));
+
+        // Matches the CPU box filter; small images are left alone
+        let mut stripes = RgbaImage::new(90, 45);
+        for (x, _, pixel) in stripes.enumerate_pixels_mut() {
+            *pixel = if x % 2 == 0 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) };
+        }
+        let stripes = DynamicImage::ImageRgba8(stripes);
+        let small = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 4, Rgba([1, 2, 3, 4])));
+        let thumbnails = renderer.render_thumbnails(&[&stripes, &small, &solid], 30);
+        assert_eq!(thumbnails.len(), 3);
+        let expected = aurion_std_nodes::resample::box_downsample(&stripes, 30).to_rgba8();
+        let actual = thumbnails[0].to_rgba8();
+        assert_eq!(actual.dimensions(), (30, 15));
+        for (actual, expected) in actual.pixels().zip(expected.pixels()) {
+            for channel in 0..4 {
+                assert!(actual[channel].abs_diff(expected[channel]) <= 1, "{:?} != {:?}", actual, expected);
+            }
+        }
+        assert_eq!(thumbnails[1].to_rgba8(), small.to_rgba8());
+        assert_eq!((thumbnails[2].width(), thumbnails[2].height()), (30, 12));
+    }
+
+    #[test]
+    fn test_frame_stats_after_render() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        assert!(renderer.last_frame_stats().is_none());
+        renderer.resize(8, 8);
+        renderer.render();
+        let stats = renderer.last_frame_stats().unwrap();
+        assert!(stats.encode > std::time::Duration::ZERO);
+        assert_eq!(renderer.average_frame_stats(), Some(stats));
+
+        // GPU times arrive once earlier frames have finished
+        let timestamps = renderer.device().features().contains(wgpu::Features::TIMESTAMP_QUERY);
+        renderer.device().poll(wgpu::Maintain::Wait);
+        renderer.render();
+        assert_eq!(renderer.last_frame_stats().unwrap().gpu.is_some(), timestamps);
+    }
+
+    #[test]
+    fn test_repeated_frames_reuse_buffers() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        let mut doc = Document::with_size(16, 16);
+        let asset = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba([90, 60, 30, 255]))));
+        doc.add_asset_layer("Layer", asset);
+        let snapshot = doc.snapshot();
+        renderer.resize(16, 16);
+
+        let mut warm = StagingStats::default();
+        for frame in 0..100 {
+            renderer.draw_text("A", [1.0, 1.0], 8.0, wgpu::Color::WHITE);
+            renderer.render_document(&snapshot, ViewTransform::IDENTITY).unwrap();
+            // As if waiting for the next vsync
+            renderer.device().poll(wgpu::Maintain::Wait);
+            if frame == 4 {
+                warm = renderer.staging_stats();
+            }
+        }
+        let stats = renderer.staging_stats();
+        assert!(warm.allocations > 0);
+        assert_eq!(stats.allocations, warm.allocations);
+        assert_eq!(stats.allocated_bytes, warm.allocated_bytes);
+        assert!(stats.reuses >= 2 * 95, "{:?}", stats);
+    }
+
+    #[test]
+    fn test_damage_redraws_only_damaged_pixels() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        if renderer.msaa_samples() > 1 {
+            return;
+        }
+        let mut doc = Document::with_size(32, 32);
+        let asset = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(32, 32, Rgba([200, 40, 40, 255]))));
+        let layer = doc.add_asset_layer("Layer", asset);
+        renderer.resize(48, 40);
+        renderer.set_background_color(wgpu::Color::BLACK);
+        renderer.set_damage_tracking(true);
+        let view = ViewTransform { pan: [4.0, 2.0], zoom: 1.0 };
+        renderer.render_document(&doc.snapshot(), view).unwrap();
+        let before = renderer.read_back().to_rgba8();
+
+        // Changes the whole layer, but only the damaged part is redrawn
+        doc.get_layer(&layer).unwrap().write().set_opacity(0.5);
+        let changed = doc.snapshot();
+        let damaged = CanvasRect::new(8, 8, 6, 4);
+        renderer.mark_damaged(damaged);
+        renderer.render_document(&changed, view).unwrap();
+        let partial = renderer.read_back().to_rgba8();
+
+        renderer.set_damage_tracking(false);
+        renderer.render_document(&changed, view).unwrap();
+        let full = renderer.read_back().to_rgba8();
+
+        let region = crate::damage::scissor_rect(&damaged, &view, renderer.size()).unwrap();
+        let inside = |x: u32, y: u32| {
+            x >= region.x && x < region.x + region.width && y >= region.y && y < region.y + region.height
+        };
+        let mut redrawn = 0;
+        for (x, y, pixel) in partial.enumerate_pixels() {
+            if inside(x, y) {
+                assert_eq!(pixel, full.get_pixel(x, y), "({}, {})", x, y);
+                redrawn += 1;
+            } else {
+                assert_eq!(pixel, before.get_pixel(x, y), "({}, {})", x, y);
+            }
+        }
+        assert_eq!(redrawn, region.width * region.height);
+        assert_ne!(partial, before);
+
+        // Moving the view redraws everything
+        renderer.set_damage_tracking(true);
+        renderer.render_document(&changed, view).unwrap();
+        let moved = ViewTransform { pan: [6.0, 2.0], zoom: 1.0 };
+        renderer.render_document(&changed, moved).unwrap();
+        let after_move = renderer.read_back().to_rgba8();
+        renderer.set_damage_tracking(false);
+        renderer.render_document(&changed, moved).unwrap();
+        assert_eq!(after_move, renderer.read_back().to_rgba8());
+    }
+
+    #[test]
+    fn test_zoomed_out_checker_is_mid_gray() {
+        let Some(mut renderer) = profiled_renderer() else {
+            return;
+        };
+        let checker = RgbaImage::from_fn(256, 256, |x, y| if (x + y) % 2 == 0 { Rgba([255; 4]) } else { Rgba([0, 0, 0, 255]) });
+        let mut doc = Document::with_size(256, 256);
+        let asset = doc.add_asset(DynamicImage::ImageRgba8(checker));
+        doc.add_asset_layer("Checker", asset);
+        let snapshot = doc.snapshot();
+        renderer.resize(32, 32);
+        // Pixel centers land on texel centers, where a single level would
+        // show one texel: black or white
+        renderer.render_document(&snapshot, ViewTransform { pan: [-0.0625, -0.0625], zoom: 0.125 }).unwrap();
+        let frame = renderer.read_back().to_rgba8();
+
+        // Half white in linear light is 188 encoded
+        let gray = if snapshot.color_profile().blends_in_linear_light() { 188 } else { 128 };
+        for y in 1..31 {
+            for x in 1..31 {
+                let pixel = frame.get_pixel(x, y);
+                let close = pixel.0[..3].iter().all(|value| (*value as i32 - gray).abs() <= 12);
+                assert!(close, "({}, {}): {:?}", x, y, pixel.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_capture_frame() {
+        let Some(mut renderer) = renderer() else {
+            return;
+        };
+        renderer.resize(20, 10);
+        renderer.set_background_color(wgpu::Color { r: 1.0, g: 0.0, b: 0.0, a: 1.0 });
+        // The right half of the viewport
+        renderer.set_vertex_buffer(&[0.0, -1.0, 1.0, -1.0, 1.0, 1.0, 0.0, -1.0, 1.0, 1.0, 0.0, 1.0]);
+        let image = renderer.capture_frame().unwrap();
+        assert_eq!((image.width(), image.height()), (20, 10));
+        assert_eq!(pixel(&image, 2, 5), [255, 0, 0, 255]);
+        let [r, g, b, a] = pixel(&image, 17, 5);
+        assert_eq!(a, 255);
+        assert!(r < 150 && g > 150 && b > 200, "{:?}", (r, g, b));
+        // Nothing was drawn into the target
+        assert_eq!(pixel(&renderer.read_back(), 2, 5), [0, 0, 0, 0]);
+
+        // After drawing a document, that's what is captured
+        let mut doc = Document::with_size(20, 10);
+        let asset = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(20, 10, Rgba([10, 200, 30, 255]))));
+        doc.add_asset_layer("Green", asset);
+        renderer.render_document(&doc.snapshot(), ViewTransform { pan: [10.0, 0.0], zoom: 1.0 }).unwrap();
+        let image = renderer.capture_frame().unwrap();
+        assert_eq!(pixel(&image, 2, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 15, 5), [10, 200, 30, 255]);
+    }
+}
@@ -1,6 +1,97 @@
 use raw_window_handle::HasWindowHandle;
 use wgpu::util::DeviceExt;
 use bytemuck::{Pod, Zeroable};
+use meridian_document::DocumentError;
+use thiserror::Error;
+
+pub mod backend;
+pub mod blend;
+pub mod camera;
+pub mod capabilities;
+mod capture;
+mod damage;
+pub mod document;
+pub mod egui_layer;
+pub mod headless;
+mod mipmap;
+mod msaa;
+pub mod nodes;
+pub mod options;
+pub mod overlay;
+pub mod software;
+pub mod staging;
+pub mod stats;
+pub mod surface;
+pub mod text;
+pub mod texture_cache;
+mod thumbnail;
+
+pub use backend::{create_backend, create_headless_backend, BackendKind, BackendPreference, ImageId, RenderBackend};
+pub use blend::TextureHandle;
+pub use camera::Camera;
+pub use capabilities::RendererCapabilities;
+pub use document::{ColorDebugMode, ViewTransform};
+pub use egui_layer::{EguiLayer, EventRoute};
+pub use headless::HeadlessRenderer;
+pub use options::{LimitsProfile, RendererOptions};
+pub use overlay::Overlay;
+pub use software::SoftwareBackend;
+pub use staging::{StagingKind, StagingPool, StagingStats};
+pub use stats::FrameStats;
+pub use texture_cache::{TextureCache, TextureCacheStats};
+
+use backend::ImageStore;
+use damage::DamageTracker;
+use msaa::{color_attachment, MsaaTarget};
+use options::{choose_present_mode, request_device};
+use overlay::FpsCounter;
+use stats::FrameProfiler;
+use surface::acquire_with_retry;
+use text::TextPass;
+use thumbnail::ThumbnailPass;
+
+/// Errors that stop the renderer from starting or drawing a frame. Surface
+/// errors the renderer recovers from aren't reported.
+#[derive(Error, Debug)]
+pub enum RenderError {
+    #[error(
+        "No GPU adapter found matching {}; available: {}",
+        requested.as_deref().unwrap_or("the default options"),
+        if available.is_empty() { "none".to_string() } else { available.join(", ") }
+    )]
+    NoAdapter {
+        requested: Option<String>,
+        available: Vec<String>,
+    },
+    #[error("Adapter {adapter} lacks required features {missing:?}")]
+    MissingFeatures {
+        adapter: String,
+        missing: wgpu::Features,
+    },
+    #[error("Adapter {adapter} doesn't support the {profile:?} limits or any lower ones")]
+    UnsupportedLimits {
+        adapter: String,
+        profile: LimitsProfile,
+    },
+    #[error("Failed to create surface: {0}")]
+    CreateSurface(#[from] wgpu::CreateSurfaceError),
+    #[error("Surface isn't supported by adapter {0}")]
+    IncompatibleSurface(String),
+    #[error("Failed to open device: {0}")]
+    RequestDevice(#[from] wgpu::RequestDeviceError),
+    #[error("Can't capture frames in {0:?}")]
+    UnsupportedCaptureFormat(wgpu::TextureFormat),
+    #[error("Failed to save capture to {}: {source}", path.display())]
+    Capture {
+        path: std::path::PathBuf,
+        source: image::ImageError,
+    },
+    /// The GPU ran out of memory; the renderer can't continue.
+    #[error("GPU out of memory")]
+    OutOfMemory,
+    #[error(transparent)]
+    Document(#[from] DocumentError),
+}
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
@@ -15,6 +106,20 @@ struct Uniforms {
     _padding: [f32; 2],
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct FillUniforms {
+    color: [f32; 4],
+}
+
+impl FillUniforms {
+    fn new(color: wgpu::Color) -> Self {
+        Self {
+            color: [color.r as f32, color.g as f32, color.b as f32, color.a as f32],
+        }
+    }
+}
+
 impl Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
@@ -31,78 +136,22 @@ impl Vertex {
     }
 }
 
-pub struct Renderer {
-    pub surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
-    size: winit::dpi::PhysicalSize<u32>,
+/// The pipelines drawing the fill color and [`Renderer::set_vertex_buffer`]'s
+/// shapes, shared by the windowed and headless renderers.
+struct ShapePass {
     pipeline: wgpu::RenderPipeline,
-    vertex_buffer: Option<wgpu::Buffer>,
     uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
-    background_color: wgpu::Color,
+    fill_pipeline: wgpu::RenderPipeline,
+    fill_buffer: wgpu::Buffer,
+    fill_bind_group: wgpu::BindGroup,
 }
 
-impl Renderer {
-    pub async fn new(window: &winit::window::Window) -> Self {
-        let size = window.inner_size();
-
-        // Create instance
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            ..Default::default()
-        });
-
-        // Create surface
-        let surface = unsafe {
-            instance.create_surface(window).expect("Failed to create surface")
-        };
-
-        // Get adapter
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .expect("Failed to find an appropriate adapter");
-
-        // Create device and queue
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
-                },
-                None,
-            )
-            .await
-            .expect("Failed to create device");
-
-        // Configure surface
-        let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps.formats.iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
-
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
-        };
-        surface.configure(&device, &config);
-
+impl ShapePass {
+    fn new(device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32, width: u32, height: u32) -> Self {
         // Create uniform buffer and bind group
         let uniforms = Uniforms {
-            viewport_size: [size.width as f32, size.height as f32],
+            viewport_size: [width as f32, height as f32],
             _padding: [0.0; 2],
         };
 
@@ -159,7 +208,7 @@ impl Renderer {
                 module: &shader,
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
+                    format,
                     blend: Some(wgpu::BlendState {
                         color: wgpu::BlendComponent {
                             src_factor: wgpu::BlendFactor::SrcAlpha,
@@ -186,13 +235,263 @@ impl Renderer {
             },
             depth_stencil: None,
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        // Create fill pipeline from the embedded shaders
+        let fill_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fill Buffer"),
+            contents: bytemuck::cast_slice(&[FillUniforms::new(wgpu::Color::TRANSPARENT)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let fill_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Fill Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let fill_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Fill Bind Group"),
+            layout: &fill_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: fill_buffer.as_entire_binding(),
+            }],
+        });
+
+        let vertex_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Fill Vertex Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(VERTEX_SHADER)),
+        });
+
+        let fragment_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Fill Fragment Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(FRAGMENT_SHADER)),
+        });
+
+        let fill_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fill Pipeline Layout"),
+            bind_group_layouts: &[&fill_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let fill_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Fill Pipeline"),
+            layout: Some(&fill_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vertex_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fragment_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: samples,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
         });
 
+        Self {
+            pipeline,
+            uniform_buffer,
+            uniform_bind_group,
+            fill_pipeline,
+            fill_buffer,
+            fill_bind_group,
+        }
+    }
+
+    fn set_fill(&self, queue: &wgpu::Queue, color: wgpu::Color) {
+        queue.write_buffer(&self.fill_buffer, 0, bytemuck::cast_slice(&[FillUniforms::new(color)]));
+    }
+
+    fn set_viewport(&self, queue: &wgpu::Queue, width: u32, height: u32) {
+        let uniforms = Uniforms {
+            viewport_size: [width as f32, height as f32],
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    }
+
+    /// Clears `target` to `background`, covers it with the fill color if
+    /// `fill`, and draws `vertices`, a buffer with its vertex count. With
+    /// MSAA, drawing happens in `msaa` and is resolved into `target`.
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        msaa: Option<&wgpu::TextureView>,
+        background: wgpu::Color,
+        fill: bool,
+        vertices: Option<&(wgpu::Buffer, u32)>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(color_attachment(target, msaa, background))],
+            depth_stencil_attachment: None,
+        });
+
+        if fill {
+            render_pass.set_pipeline(&self.fill_pipeline);
+            render_pass.set_bind_group(0, &self.fill_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+        if let Some((vertex_buffer, count)) = vertices {
+            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            render_pass.draw(0..*count, 0..1);
+        }
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, vertices: &[f32]) -> (wgpu::Buffer, u32) {
+    let vertex_data: Vec<Vertex> = vertices
+        .chunks_exact(2)
+        .map(|pos| Vertex {
+            position: [pos[0], pos[1]],
+        })
+        .collect();
+
+    let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Vertex Buffer"),
+        contents: bytemuck::cast_slice(&vertex_data),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+    (buffer, vertex_data.len() as u32)
+}
+
+pub struct Renderer {
+    pub surface: wgpu::Surface,
+    adapter: wgpu::Adapter,
+    adapter_info: wgpu::AdapterInfo,
+    capabilities: RendererCapabilities,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+    size: winit::dpi::PhysicalSize<u32>,
+    shapes: ShapePass,
+    vertex_buffer: Option<(wgpu::Buffer, u32)>,
+    background_color: wgpu::Color,
+    fill_color: Option<wgpu::Color>,
+    document: document::DocumentPass,
+    document_background: wgpu::Color,
+    camera: Camera,
+    msaa_samples: u32,
+    msaa: Option<MsaaTarget>,
+    text: TextPass,
+    thumbnails: ThumbnailPass,
+    overlay: Overlay,
+    fps: FpsCounter,
+    profiler: FrameProfiler,
+    staging: StagingPool,
+    damage: DamageTracker,
+    // Uploaded through RenderBackend
+    images: ImageStore<TextureHandle>,
+}
+
+impl Renderer {
+    pub async fn new(window: &winit::window::Window) -> Result<Self, RenderError> {
+        Self::new_with_options(window, RendererOptions::default()).await
+    }
+
+    /// Fails if no adapter matches `options`, or it can't provide the
+    /// requested features or limits. An unsupported present mode falls back
+    /// to `Fifo`.
+    pub async fn new_with_options(window: &winit::window::Window, options: RendererOptions) -> Result<Self, RenderError> {
+        let size = window.inner_size();
+        let scale_factor = window.scale_factor();
+
+        // Create instance
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+
+        // Create surface
+        let surface = unsafe { instance.create_surface(window)? };
+
+        // Get adapter, device and queue
+        let (adapter, device, queue, capabilities) = request_device(&instance, Some(&surface), &options).await?;
+        let adapter_info = adapter.get_info();
+
+        // Configure surface
+        let surface_caps = surface.get_capabilities(&adapter);
+        let surface_format = surface_caps.formats.iter()
+            .copied()
+            .find(|f| f.is_srgb())
+            .or_else(|| surface_caps.formats.first().copied())
+            .ok_or_else(|| RenderError::IncompatibleSurface(adapter_info.name.clone()))?;
+
+        let config = wgpu::SurfaceConfiguration {
+            // Frames drawn with damage tracking are copied in
+            usage: if surface_caps.usages.contains(wgpu::TextureUsages::COPY_DST) {
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_DST
+            } else {
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            },
+            format: surface_format,
+            width: size.width,
+            height: size.height,
+            present_mode: choose_present_mode(&surface_caps.present_modes, options.present_mode),
+            alpha_mode: surface_caps.alpha_modes[0],
+            // Documents that don't blend in linear light draw through a
+            // non-sRGB view, where the device has them. The MSAA, damage and
+            // capture textures take the same view formats.
+            view_formats: if surface_format.is_srgb()
+                && capabilities.supports_view_formats
+                && capabilities.supports_surface_view_formats
+            {
+                vec![surface_format.remove_srgb_suffix()]
+            } else {
+                vec![]
+            },
+        };
+        surface.configure(&device, &config);
+
+        let msaa_samples = msaa::sample_count(&adapter, &device, surface_format, options.msaa_samples);
+        let msaa = MsaaTarget::new(
+            &device,
+            surface_format,
+            &config.view_formats,
+            (size.width.max(1), size.height.max(1)),
+            msaa_samples,
+        );
+        let shapes = ShapePass::new(&device, config.format, msaa_samples, size.width, size.height);
+
         let background_color = wgpu::Color {
             r: 0.1,
             g: 0.1,
@@ -200,67 +499,124 @@ impl Renderer {
             a: 1.0,
         };
 
-        Self {
+        let fill_color = wgpu::Color {
+            r: 0.14,
+            g: 0.14,
+            b: 0.16,
+            a: 1.0,
+        };
+        shapes.set_fill(&queue, fill_color);
+
+        let staging = StagingPool::new();
+        let document = document::DocumentPass::new(&device, &queue, staging.clone());
+        let text = TextPass::new(&device, &queue, staging.clone());
+        let thumbnails = ThumbnailPass::new(&device, staging.clone());
+        let profiler = FrameProfiler::new(&device, &queue);
+        let mut camera = Camera::new((size.width, size.height));
+        camera.set_scale_factor(scale_factor);
+
+        Ok(Self {
             surface,
+            adapter,
+            adapter_info,
+            capabilities,
             device,
             queue,
             config,
             size,
-            pipeline,
+            shapes,
             vertex_buffer: None,
-            uniform_buffer,
-            uniform_bind_group,
             background_color,
-        }
+            fill_color: Some(fill_color),
+            document,
+            document_background: wgpu::Color::WHITE,
+            camera,
+            msaa_samples,
+            msaa,
+            text,
+            thumbnails,
+            overlay: Overlay::default(),
+            fps: FpsCounter::default(),
+            profiler,
+            staging,
+            damage: DamageTracker::default(),
+            images: ImageStore::default(),
+        })
     }
 
+    /// Resizes the surface to `new_size` physical pixels, clamped to the
+    /// device's texture size limit.
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        let max = self.device.limits().max_texture_dimension_2d;
+        let new_size = winit::dpi::PhysicalSize::new(new_size.width.min(max), new_size.height.min(max));
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
+            self.msaa = MsaaTarget::new(
+                &self.device,
+                self.config.format,
+                &self.config.view_formats,
+                (new_size.width, new_size.height),
+                self.msaa_samples,
+            );
 
             // Update uniform buffer with new size
-            let uniforms = Uniforms {
-                viewport_size: [new_size.width as f32, new_size.height as f32],
-                _padding: [0.0; 2],
-            };
-            self.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+            self.shapes.set_viewport(&self.queue, new_size.width, new_size.height);
+            self.camera.set_viewport((new_size.width, new_size.height));
         }
     }
 
-    pub fn render(&self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+    /// Physical pixels per logical pixel of the window.
+    pub fn scale_factor(&self) -> f64 {
+        self.camera.scale_factor()
+    }
+
+    /// Handles the window moving to a display with a different scale factor:
+    /// resizes to `new_size`, in physical pixels, and keeps the document at
+    /// the same logical position.
+    pub fn on_scale_factor_changed(&mut self, scale_factor: f64, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.camera.set_scale_factor(scale_factor);
+        self.resize(new_size);
+    }
+
+    /// Draws a frame, with any text queued by [`Renderer::draw_text`]. A
+    /// lost or outdated surface is reconfigured and the frame retried once;
+    /// frames that still can't be acquired are skipped.
+    pub fn render(&mut self) -> Result<(), RenderError> {
+        self.render_frame(None)
+    }
+
+    fn render_frame(&mut self, ui: Option<(&mut EguiLayer, &winit::window::Window)>) -> Result<(), RenderError> {
+        let Some(output) = acquire_with_retry(
+            || self.surface.get_current_texture(),
+            || self.surface.configure(&self.device, &self.config),
+        )?
+        else {
+            return Ok(());
+        };
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let msaa = self.msaa.as_ref().map(|msaa| msaa.texture().create_view(&wgpu::TextureViewDescriptor::default()));
 
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
-
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.background_color),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
-
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
-            if let Some(vertex_buffer) = &self.vertex_buffer {
-                render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
-                render_pass.draw(0..3, 0..1);
-            }
-        }
-
-        self.queue.submit(std::iter::once(encoder.finish()));
+        let timing = self.profiler.begin(&self.device, &mut encoder);
+        self.shapes.draw(
+            &mut encoder,
+            &view,
+            msaa.as_ref(),
+            self.background_color,
+            self.fill_color.is_some(),
+            self.vertex_buffer.as_ref(),
+        );
+        let size = (self.size.width, self.size.height);
+        self.text.encode(&self.device, &self.queue, &mut encoder, &output.texture, self.config.format, size);
+        self.paint_ui(ui, &mut encoder, &output);
+
+        self.profiler.submit(&self.queue, encoder, timing);
+        self.staging.submitted(&self.queue);
         output.present();
 
         Ok(())
@@ -274,20 +630,61 @@ impl Renderer {
         &self.queue
     }
 
+    /// The format frames are drawn in, e.g. for [`EguiLayer::new`].
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    /// The camera [`Renderer::render_document`] draws with; moving it takes
+    /// effect on the next frame.
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    /// The adapter's name, backend and driver, e.g. for an about dialog.
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    /// Samples per pixel, 1 without MSAA. The highest the surface format
+    /// supports up to [`RendererOptions::msaa_samples`].
+    pub fn msaa_samples(&self) -> u32 {
+        self.msaa_samples
+    }
+
+    /// Buffers allocated versus reused for per-frame data.
+    pub fn staging_stats(&self) -> StagingStats {
+        self.staging.stats()
+    }
+
+    /// What the device supports, e.g. for the UI or for choosing between
+    /// GPU and CPU paths.
+    pub fn capabilities(&self) -> &RendererCapabilities {
+        &self.capabilities
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    /// Switches to `mode`, or to `Fifo` if the surface doesn't support it.
+    /// Returns the mode now in use.
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) -> wgpu::PresentMode {
+        let supported = self.surface.get_capabilities(&self.adapter).present_modes;
+        let mode = choose_present_mode(&supported, mode);
+        if mode != self.config.present_mode {
+            self.config.present_mode = mode;
+            self.surface.configure(&self.device, &self.config);
+        }
+        mode
+    }
+
     pub fn set_vertex_buffer(&mut self, vertices: &[f32]) {
-        let vertex_data: Vec<Vertex> = vertices
-            .chunks_exact(2)
-            .map(|pos| Vertex {
-                position: [pos[0], pos[1]],
-            })
-            .collect();
-
-        let buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&vertex_data),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        self.vertex_buffer = Some(buffer);
+        self.vertex_buffer = Some(create_vertex_buffer(&self.device, vertices));
     }
 
     pub fn get_current_texture(&self) -> Result<wgpu::SurfaceTexture, wgpu::SurfaceError> {
@@ -297,23 +694,41 @@ impl Renderer {
     pub fn set_background_color(&mut self, color: wgpu::Color) {
         self.background_color = color;
     }
+
+    /// Sets the color [`Renderer::render`] covers the viewport with before
+    /// drawing shapes, alpha blended over the background. `None` leaves the
+    /// background showing.
+    pub fn set_fill_color(&mut self, color: Option<wgpu::Color>) {
+        if let Some(color) = color {
+            self.shapes.set_fill(&self.queue, color);
+        }
+        self.fill_color = color;
+    }
 }
 
-// Vertex shader
+// Vertex shader: one triangle covering the viewport, (-1, -1), (3, -1) and
+// (-1, 3) in clip space
 const VERTEX_SHADER: &str = r#"
 @vertex
 fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
-    let x = f32(i32(in_vertex_index) - 1);
-    let y = f32(i32(in_vertex_index & 1u) * 2 - 1);
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
     return vec4<f32>(x, y, 0.0, 1.0);
 }
 "#;
 
 // Fragment shader
 const FRAGMENT_SHADER: &str = r#"
+struct Fill {
+    color: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> fill: Fill;
+
 @fragment
 fn fs_main() -> @location(0) vec4<f32> {
-    return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+    return fill.color;
 }
 "#;
 
@@ -0,0 +1,104 @@
+//! Multisampled color targets, resolved into the frame after drawing.
+
+/// The highest sample count no more than `requested` that `supported`
+/// accepts, or 1.
+pub(crate) fn choose_sample_count(requested: u32, supported: impl Fn(u32) -> bool) -> u32 {
+    [16, 8, 4, 2]
+        .into_iter()
+        .find(|&count| count <= requested && supported(count))
+        .unwrap_or(1)
+}
+
+/// The sample count to render `format` with on `device`. Counts other than
+/// 1 and 4 need the adapter-specific format features.
+pub(crate) fn sample_count(adapter: &wgpu::Adapter, device: &wgpu::Device, format: wgpu::TextureFormat, requested: u32) -> u32 {
+    let flags = adapter.get_texture_format_features(format).flags;
+    let adapter_specific = device.features().contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    choose_sample_count(requested, |count| {
+        flags.sample_count_supported(count) && (adapter_specific || count == 4)
+    })
+}
+
+/// What a frame is drawn into before being resolved into the frame's
+/// texture.
+pub(crate) struct MsaaTarget {
+    texture: wgpu::Texture,
+}
+
+impl MsaaTarget {
+    /// `None` if `samples` is 1, when frames are drawn directly.
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        view_formats: &[wgpu::TextureFormat],
+        size: (u32, u32),
+        samples: u32,
+    ) -> Option<Self> {
+        if samples <= 1 {
+            return None;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Multisampled Target"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: samples,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats,
+        });
+        Some(Self { texture })
+    }
+
+    pub(crate) fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+}
+
+/// An attachment drawing to `target`, through `msaa` if given.
+pub(crate) fn color_attachment<'a>(
+    target: &'a wgpu::TextureView,
+    msaa: Option<&'a wgpu::TextureView>,
+    clear: wgpu::Color,
+) -> wgpu::RenderPassColorAttachment<'a> {
+    let ops = wgpu::Operations {
+        load: wgpu::LoadOp::Clear(clear),
+        store: true,
+    };
+    match msaa {
+        Some(msaa) => wgpu::RenderPassColorAttachment {
+            view: msaa,
+            resolve_target: Some(target),
+            ops,
+        },
+        None => wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_sample_count() {
+        let common = |count: u32| count == 4;
+        assert_eq!(choose_sample_count(4, common), 4);
+        assert_eq!(choose_sample_count(8, common), 4);
+        assert_eq!(choose_sample_count(2, common), 1);
+        assert_eq!(choose_sample_count(1, common), 1);
+        assert_eq!(choose_sample_count(0, common), 1);
+
+        let all = |_: u32| true;
+        assert_eq!(choose_sample_count(16, all), 16);
+        assert_eq!(choose_sample_count(12, all), 8);
+        assert_eq!(choose_sample_count(3, all), 2);
+    }
+}
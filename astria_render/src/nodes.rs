@@ -0,0 +1,434 @@
+//! Nodes that run on the GPU when the host provides a [`GpuContext`].
+//!
+//! Hosts insert a [`GpuContext`] into the [`EvalContext`] used for graph
+//! evaluation; without one, these nodes compute on the CPU like their
+//! `aurion_std_nodes` counterparts.
+
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, OnceLock};
+use aurion_core::{EvalContext, NodeData, NodeError, NodeValue, PortSpec};
+use aurion_std_nodes::filters::BlurNode;
+use bytemuck::{Pod, Zeroable};
+use image::{DynamicImage, RgbaImage};
+use serde_json::Value;
+use crate::headless::read_texture;
+use crate::options::request_device;
+use crate::staging::{StagingKind, StagingPool};
+use crate::{LimitsProfile, RendererCapabilities, RendererOptions};
+
+/// A GPU device for nodes to compute on, shared through an [`EvalContext`].
+/// Cloning is cheap.
+#[derive(Clone)]
+pub struct GpuContext {
+    pub device: Arc<wgpu::Device>,
+    pub queue: Arc<wgpu::Queue>,
+    capabilities: RendererCapabilities,
+    staging: StagingPool,
+    // Built on first use
+    blur: Arc<OnceLock<BlurPipelines>>,
+}
+
+impl std::fmt::Debug for GpuContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GpuContext").finish_non_exhaustive()
+    }
+}
+
+impl GpuContext {
+    /// Capabilities are judged from the device's limits; see
+    /// [`RendererCapabilities::from_limits`].
+    pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>) -> Self {
+        let capabilities = RendererCapabilities::from_limits(&device.limits(), LimitsProfile::Adapter);
+        Self::with_capabilities(device, queue, capabilities)
+    }
+
+    /// For a device opened by a renderer, with its
+    /// [`crate::Renderer::capabilities`].
+    pub fn with_capabilities(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>, capabilities: RendererCapabilities) -> Self {
+        Self {
+            device,
+            queue,
+            capabilities,
+            staging: StagingPool::new(),
+            blur: Arc::new(OnceLock::new()),
+        }
+    }
+
+    pub fn capabilities(&self) -> &RendererCapabilities {
+        &self.capabilities
+    }
+
+    /// Opens a device on the default adapter. Fails if there is none.
+    pub fn request() -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+        let (_, device, queue, capabilities) = pollster::block_on(request_device(&instance, None, &RendererOptions::default()))?;
+        Ok(Self::with_capabilities(Arc::new(device), Arc::new(queue), capabilities))
+    }
+
+    fn blur_pipelines(&self) -> &BlurPipelines {
+        self.blur.get_or_init(|| BlurPipelines::new(&self.device))
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct BlurParams {
+    radius: i32,
+    sigma: f32,
+    _padding: [f32; 2],
+}
+
+const WORKGROUP_SIZE: u32 = 8;
+
+struct BlurPipelines {
+    vertical_layout: wgpu::BindGroupLayout,
+    horizontal_layout: wgpu::BindGroupLayout,
+    vertical: wgpu::ComputePipeline,
+    horizontal: wgpu::ComputePipeline,
+}
+
+impl BlurPipelines {
+    fn new(device: &wgpu::Device) -> Self {
+        let source = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let storage = |binding, format| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let params = wgpu::BindGroupLayoutEntry {
+            binding: 3,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        // The passes write different targets, so each has its own layout
+        let vertical_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Vertical Blur Bind Group Layout"),
+            entries: &[source, storage(1, wgpu::TextureFormat::Rgba32Float), params],
+        });
+        let horizontal_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Horizontal Blur Bind Group Layout"),
+            entries: &[source, storage(2, wgpu::TextureFormat::Rgba8Unorm), params],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Blur Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!("blur.wgsl"))),
+        });
+        let pipeline = |layout: &wgpu::BindGroupLayout, entry_point: &str| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Blur Pipeline Layout"),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Blur Pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        let vertical = pipeline(&vertical_layout, "blur_vertical");
+        let horizontal = pipeline(&horizontal_layout, "blur_horizontal");
+        Self {
+            vertical_layout,
+            horizontal_layout,
+            vertical,
+            horizontal,
+        }
+    }
+}
+
+fn create_texture(device: &wgpu::Device, label: &str, size: wgpu::Extent3d, format: wgpu::TextureFormat, usage: wgpu::TextureUsages) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage,
+        view_formats: &[],
+    })
+}
+
+/// Gaussian blur matching [`BlurNode`], computed with two separable compute
+/// passes when the context holds a [`GpuContext`] and by [`BlurNode`]
+/// otherwise.
+///
+/// The GPU path works in 8-bit RGBA, so other inputs come out as RGBA8.
+/// Images larger than the device's texture limit, and devices without
+/// compute support (see [`RendererCapabilities`]), blur on the CPU.
+#[derive(Debug, Clone)]
+pub struct GpuGaussianBlurNode {
+    sigma: f32,
+}
+
+impl GpuGaussianBlurNode {
+    pub fn new(sigma: f32) -> Self {
+        Self { sigma }
+    }
+
+    /// Non-positive sigmas blur with 1, as [`BlurNode`] does.
+    fn effective_sigma(&self) -> f32 {
+        if self.sigma <= 0.0 {
+            1.0
+        } else {
+            self.sigma
+        }
+    }
+
+    fn input<'a>(&self, inputs: &'a [NodeValue]) -> Result<&'a DynamicImage, NodeError> {
+        if inputs.len() != 1 {
+            return Err(NodeError::InvalidInputType {
+                expected: "one image input".to_string(),
+                actual: format!("{} inputs", inputs.len()),
+            });
+        }
+        inputs[0].try_image()
+    }
+
+    fn blur_on_gpu(&self, gpu: &GpuContext, input: &DynamicImage) -> RgbaImage {
+        let (device, queue) = (&*gpu.device, &*gpu.queue);
+        let pipelines = gpu.blur_pipelines();
+        let rgba = input.to_rgba8();
+        let size = wgpu::Extent3d {
+            width: rgba.width(),
+            height: rgba.height(),
+            depth_or_array_layers: 1,
+        };
+
+        let source = create_texture(
+            device,
+            "Blur Source",
+            size,
+            wgpu::TextureFormat::Rgba8Unorm,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        );
+        gpu.staging.upload_texture(device, queue, &source, &rgba);
+        let intermediate = create_texture(
+            device,
+            "Blur Intermediate",
+            size,
+            wgpu::TextureFormat::Rgba32Float,
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        );
+        let output = create_texture(
+            device,
+            "Blur Output",
+            size,
+            wgpu::TextureFormat::Rgba8Unorm,
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        );
+
+        let sigma = self.effective_sigma();
+        let params = BlurParams {
+            radius: (2.0 * sigma).ceil() as i32,
+            sigma,
+            _padding: [0.0; 2],
+        };
+        let params = gpu.staging.write(device, queue, StagingKind::Uniform, bytemuck::cast_slice(&[params]));
+        let view = |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let (source_view, intermediate_view, output_view) = (view(&source), view(&intermediate), view(&output));
+        let bind_group = |layout: &wgpu::BindGroupLayout, input: &wgpu::TextureView, binding: u32, target: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Blur Bind Group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(input),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding,
+                        resource: wgpu::BindingResource::TextureView(target),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: params.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let vertical = bind_group(&pipelines.vertical_layout, &source_view, 1, &intermediate_view);
+        let horizontal = bind_group(&pipelines.horizontal_layout, &intermediate_view, 2, &output_view);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Blur Encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Blur Pass"),
+            });
+            let groups = (size.width.div_ceil(WORKGROUP_SIZE), size.height.div_ceil(WORKGROUP_SIZE));
+            pass.set_pipeline(&pipelines.vertical);
+            pass.set_bind_group(0, &vertical, &[]);
+            pass.dispatch_workgroups(groups.0, groups.1, 1);
+            pass.set_pipeline(&pipelines.horizontal);
+            pass.set_bind_group(0, &horizontal, &[]);
+            pass.dispatch_workgroups(groups.0, groups.1, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        gpu.staging.submitted(queue);
+
+        read_texture(device, queue, &gpu.staging, &output, (size.width, size.height))
+    }
+}
+
+impl NodeData for GpuGaussianBlurNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        "GpuGaussianBlurNode"
+    }
+
+    fn input_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_input()]
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        self.type_name().hash(&mut hasher);
+        self.sigma.to_bits().hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<Value> {
+        (name == "sigma").then(|| Value::from(self.sigma))
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &["sigma"]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: Value) -> Result<(), NodeError> {
+        match name {
+            "sigma" => {
+                self.sigma = value.as_f64().ok_or_else(|| NodeError::InvalidParameter {
+                    name: name.to_string(),
+                    reason: format!("expected a number, got {}", value),
+                })? as f32;
+                Ok(())
+            }
+            _ => Err(NodeError::InvalidParameter {
+                name: name.to_string(),
+                reason: format!("{} has no such parameter", self.type_name()),
+            }),
+        }
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+        BlurNode::new(self.sigma).compute(inputs)
+    }
+
+    fn compute_with_context(&self, inputs: &[NodeValue], context: &EvalContext) -> Result<NodeValue, NodeError> {
+        let input = self.input(inputs)?;
+        let Some(gpu) = context.get::<GpuContext>() else {
+            return self.compute(inputs);
+        };
+        if !gpu.capabilities.can_blur_on_gpu((input.width(), input.height())) {
+            return self.compute(inputs);
+        }
+        Ok(NodeValue::image(DynamicImage::ImageRgba8(self.blur_on_gpu(gpu, input))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// A GPU context, or `None` if GPU tests are disabled with
+    /// `ASTRIA_SKIP_GPU_TESTS` or no adapter is available.
+    fn gpu() -> Option<GpuContext> {
+        if std::env::var_os("ASTRIA_SKIP_GPU_TESTS").is_some() {
+            return None;
+        }
+        match GpuContext::request() {
+            Ok(gpu) => Some(gpu),
+            Err(e) => {
+                eprintln!("Skipping GPU test: {}", e);
+                None
+            }
+        }
+    }
+
+    fn blur(node: &GpuGaussianBlurNode, image: &DynamicImage, context: &EvalContext) -> RgbaImage {
+        let inputs: Vec<NodeValue> = vec![NodeValue::image(image.clone())];
+        let output = node.compute_with_context(&inputs, context).unwrap();
+        output.as_image().unwrap().to_rgba8()
+    }
+
+    #[test]
+    fn test_falls_back_to_cpu_without_device() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(9, 7, |x, y| Rgba([(x * 25) as u8, (y * 30) as u8, 90, 255])));
+        let node = GpuGaussianBlurNode::new(1.5);
+        assert_eq!(blur(&node, &image, &EvalContext::new()), image.blur(1.5).to_rgba8());
+    }
+
+    #[test]
+    fn test_gpu_blur_matches_cpu() {
+        let Some(gpu) = gpu() else {
+            return;
+        };
+        // Not a multiple of the workgroup size
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(37, 21, |x, y| {
+            let on = (x / 5 + y / 4) % 2 == 0;
+            Rgba(if on { [240, 30, 60, 255] } else { [20, 180, 220, 128] })
+        }));
+        let context = EvalContext::new().with(gpu);
+        for sigma in [0.0, 1.0, 3.5] {
+            let node = GpuGaussianBlurNode::new(sigma);
+            let expected = image.blur(node.effective_sigma()).to_rgba8();
+            let actual = blur(&node, &image, &context);
+            for (actual, expected) in actual.pixels().zip(expected.pixels()) {
+                for channel in 0..4 {
+                    assert!(
+                        actual[channel].abs_diff(expected[channel]) <= 3,
+                        "sigma {}: {:?} != {:?}",
+                        sigma,
+                        actual,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+}
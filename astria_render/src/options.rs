@@ -0,0 +1,165 @@
+//! Choosing the adapter, device limits and present mode.
+
+use crate::capabilities::{negotiate_limits, RendererCapabilities};
+use crate::RenderError;
+
+/// Which [`wgpu::Limits`] the device is opened with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LimitsProfile {
+    /// wgpu's defaults, supported by most desktop GPUs.
+    #[default]
+    Default,
+    /// Lower limits that older and mobile GPUs support.
+    Downlevel,
+    /// Limits that WebGL2 supports.
+    DownlevelWebGl2,
+    /// Everything the adapter supports.
+    Adapter,
+}
+
+impl LimitsProfile {
+    fn limits(self, adapter: &wgpu::Adapter) -> wgpu::Limits {
+        match self {
+            LimitsProfile::Default => wgpu::Limits::default(),
+            LimitsProfile::Downlevel => wgpu::Limits::downlevel_defaults(),
+            LimitsProfile::DownlevelWebGl2 => wgpu::Limits::downlevel_webgl2_defaults(),
+            LimitsProfile::Adapter => adapter.limits(),
+        }
+    }
+}
+
+/// How [`crate::Renderer::new_with_options`] sets up the GPU.
+#[derive(Debug, Clone)]
+pub struct RendererOptions {
+    /// Used if the surface supports it, otherwise `Fifo`.
+    pub present_mode: wgpu::PresentMode,
+    pub power_preference: wgpu::PowerPreference,
+    /// Picks the first adapter whose name contains this, ignoring case.
+    pub preferred_adapter_name: Option<String>,
+    pub required_features: wgpu::Features,
+    /// Lowered to the first of the more conservative profiles the adapter
+    /// supports; see [`crate::Renderer::capabilities`].
+    pub limits_profile: LimitsProfile,
+    /// Samples per pixel for antialiasing, lowered to what the surface
+    /// format supports. 1 disables MSAA.
+    pub msaa_samples: u32,
+}
+
+impl Default for RendererOptions {
+    fn default() -> Self {
+        Self {
+            present_mode: wgpu::PresentMode::Fifo,
+            power_preference: wgpu::PowerPreference::default(),
+            preferred_adapter_name: None,
+            required_features: wgpu::Features::empty(),
+            limits_profile: LimitsProfile::Default,
+            msaa_samples: 1,
+        }
+    }
+}
+
+/// `requested` if it's among `supported`, otherwise `Fifo`, which every
+/// surface supports.
+pub(crate) fn choose_present_mode(supported: &[wgpu::PresentMode], requested: wgpu::PresentMode) -> wgpu::PresentMode {
+    if supported.contains(&requested) {
+        requested
+    } else {
+        wgpu::PresentMode::Fifo
+    }
+}
+
+fn name_matches(name: &str, preferred: &str) -> bool {
+    name.to_lowercase().contains(&preferred.to_lowercase())
+}
+
+/// Finds an adapter matching `options`, compatible with `surface` if given,
+/// and opens a device on it with the best limits the adapter supports.
+pub(crate) async fn request_device(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface>,
+    options: &RendererOptions,
+) -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue, RendererCapabilities), RenderError> {
+    let compatible = |adapter: &wgpu::Adapter| surface.map_or(true, |surface| adapter.is_surface_supported(surface));
+    let available = || -> Vec<String> {
+        instance
+            .enumerate_adapters(wgpu::Backends::all())
+            .filter(|adapter| compatible(adapter))
+            .map(|adapter| adapter.get_info().name)
+            .collect()
+    };
+
+    let adapter = match &options.preferred_adapter_name {
+        Some(preferred) => instance
+            .enumerate_adapters(wgpu::Backends::all())
+            .find(|adapter| compatible(adapter) && name_matches(&adapter.get_info().name, preferred)),
+        None => {
+            instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: options.power_preference,
+                    compatible_surface: surface,
+                    force_fallback_adapter: false,
+                })
+                .await
+        }
+    };
+    let adapter = adapter.ok_or_else(|| RenderError::NoAdapter {
+        requested: options.preferred_adapter_name.clone(),
+        available: available(),
+    })?;
+
+    let adapter_name = adapter.get_info().name;
+    let missing = options.required_features.difference(adapter.features());
+    if !missing.is_empty() {
+        return Err(RenderError::MissingFeatures {
+            adapter: adapter_name,
+            missing,
+        });
+    }
+    let adapter_limits = adapter.limits();
+    let Some(profile) = negotiate_limits(options.limits_profile, |profile| {
+        profile.limits(&adapter).check_limits(&adapter_limits)
+    }) else {
+        return Err(RenderError::UnsupportedLimits {
+            adapter: adapter_name,
+            profile: options.limits_profile,
+        });
+    };
+    // Documents may be larger than the profile's texture size
+    let limits = profile.limits(&adapter).using_resolution(adapter_limits);
+
+    // Timestamp queries are used for frame stats when available
+    let optional = adapter.features() & wgpu::Features::TIMESTAMP_QUERY;
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: options.required_features | optional,
+                limits,
+            },
+            None,
+        )
+        .await?;
+    let capabilities = RendererCapabilities::detect(&adapter, &device, profile);
+    Ok((adapter, device, queue, capabilities))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::PresentMode;
+
+    #[test]
+    fn test_choose_present_mode_falls_back_to_fifo() {
+        let supported = [PresentMode::Fifo, PresentMode::Mailbox];
+        assert_eq!(choose_present_mode(&supported, PresentMode::Mailbox), PresentMode::Mailbox);
+        assert_eq!(choose_present_mode(&supported, PresentMode::Immediate), PresentMode::Fifo);
+        assert_eq!(choose_present_mode(&[], PresentMode::Immediate), PresentMode::Fifo);
+    }
+
+    #[test]
+    fn test_name_matches_ignores_case() {
+        assert!(name_matches("NVIDIA GeForce RTX 3080", "geforce"));
+        assert!(name_matches("Intel(R) UHD Graphics 620", "Intel"));
+        assert!(!name_matches("llvmpipe (LLVM 15.0.7, 256 bits)", "nvidia"));
+    }
+}
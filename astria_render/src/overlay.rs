@@ -0,0 +1,131 @@
+//! Labels drawn over the document: layer names, frame rate and zoom.
+
+use std::time::{Duration, Instant};
+use meridian_document::DocumentSnapshot;
+use crate::text::measure_text;
+use crate::Renderer;
+
+/// What [`Renderer::render_document`] labels. Nothing is shown by default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Overlay {
+    /// Each shown layer's name above the top-left corner of its bounds.
+    pub layer_names: bool,
+    /// Frames per second in the top-left corner.
+    pub fps: bool,
+    /// Average CPU and GPU frame times under the frame rate, see
+    /// [`Renderer::average_frame_stats`].
+    pub frame_stats: bool,
+    /// The zoom percentage in the bottom-left corner.
+    pub zoom: bool,
+    /// Text height in logical pixels.
+    pub text_size: f32,
+    pub color: wgpu::Color,
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self {
+            layer_names: false,
+            fps: false,
+            frame_stats: false,
+            zoom: false,
+            text_size: 12.0,
+            color: wgpu::Color::WHITE,
+        }
+    }
+}
+
+/// Frames per second, smoothed over recent frames.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FpsCounter {
+    last_frame: Option<Instant>,
+    fps: Option<f32>,
+}
+
+impl FpsCounter {
+    /// Records a frame at `now` and returns the frame rate, `None` until two
+    /// frames have been drawn.
+    pub(crate) fn tick(&mut self, now: Instant) -> Option<f32> {
+        if let Some(last) = self.last_frame.replace(now) {
+            let elapsed = now.saturating_duration_since(last).max(Duration::from_micros(1));
+            let current = 1.0 / elapsed.as_secs_f32();
+            self.fps = Some(self.fps.map_or(current, |fps| fps * 0.9 + current * 0.1));
+        }
+        self.fps
+    }
+}
+
+impl Renderer {
+    pub fn overlay(&self) -> &Overlay {
+        &self.overlay
+    }
+
+    pub fn set_overlay(&mut self, overlay: Overlay) {
+        self.overlay = overlay;
+    }
+
+    /// Queues the overlay's labels for `snapshot`, drawn with the current
+    /// camera.
+    pub(crate) fn queue_overlay(&mut self, snapshot: &DocumentSnapshot) {
+        let fps = self.fps.tick(Instant::now());
+        let overlay = self.overlay;
+        // In physical pixels
+        let line_height = measure_text("", overlay.text_size * self.camera.scale_factor() as f32).1 as f32;
+        if overlay.layer_names {
+            let view = self.camera.view();
+            for layer in snapshot.shown_layers() {
+                let Some((width, height)) = self.document.layer_size(layer.id()) else {
+                    continue;
+                };
+                let bounds = layer.transform().bounds(width, height);
+                let [x, y] = view.apply(bounds.x as f64, bounds.y as f64);
+                let screen = [x, (y - line_height).max(0.0)];
+                self.label(layer.name(), self.camera.screen_to_logical(screen), overlay);
+            }
+        }
+        if overlay.fps {
+            if let Some(fps) = fps {
+                self.label(&format!("{:.0} fps", fps), [4.0, 4.0], overlay);
+            }
+        }
+        if overlay.frame_stats {
+            if let Some(stats) = self.average_frame_stats() {
+                let gpu = stats.gpu.map_or("-".to_string(), |gpu| format!("{:.2}", gpu.as_secs_f64() * 1000.0));
+                let text = format!("cpu {:.2} ms gpu {} ms", stats.cpu().as_secs_f64() * 1000.0, gpu);
+                let top = 4.0 + self.camera.screen_to_logical([0.0, line_height])[1];
+                self.label(&text, [4.0, top], overlay);
+            }
+        }
+        if overlay.zoom {
+            let [_, top] = self.camera.screen_to_logical([0.0, self.size.height as f32 - line_height]);
+            let text = format!("{:.0}%", self.camera.zoom() * 100.0);
+            self.label(&text, [4.0, top - 4.0], overlay);
+        }
+    }
+
+    /// Draws `text` with a dark shadow so it reads over any document.
+    fn label(&mut self, text: &str, pos: [f32; 2], overlay: Overlay) {
+        let shadow = 1.0 / self.camera.scale_factor() as f32;
+        let black = wgpu::Color { a: overlay.color.a * 0.75, ..wgpu::Color::BLACK };
+        self.draw_text(text, [pos[0] + shadow, pos[1] + shadow], overlay.text_size, black);
+        self.draw_text(text, pos, overlay.text_size, overlay.color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fps_counter_smooths() {
+        let mut counter = FpsCounter::default();
+        let start = Instant::now();
+        assert_eq!(counter.tick(start), None);
+        let fps = counter.tick(start + Duration::from_millis(20)).unwrap();
+        assert!((fps - 50.0).abs() < 0.01, "{}", fps);
+
+        // One slow frame only moves the rate part of the way
+        let fps = counter.tick(start + Duration::from_millis(120)).unwrap();
+        assert!(fps > 40.0 && fps < 50.0, "{}", fps);
+    }
+}
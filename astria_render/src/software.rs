@@ -0,0 +1,136 @@
+//! A [`RenderBackend`] that draws on the CPU, for machines without a usable
+//! GPU.
+
+use image::{DynamicImage, Rgba, Rgba32FImage, RgbaImage};
+use meridian_document::blend::{blend_images, composite_onto, composite_onto_linear};
+use meridian_document::color::linear_to_srgb;
+use meridian_document::{BlendMode, DocumentSnapshot};
+use crate::backend::{BackendKind, ImageId, ImageStore, RenderBackend};
+use crate::{RenderError, ViewTransform};
+
+/// `color` as 8-bit RGBA. Colors are linear for documents blending in
+/// linear light, like on the GPU, and encoded to sRGB here.
+fn to_rgba8(color: wgpu::Color, linear: bool) -> Rgba<u8> {
+    let encode = |value: f64| if linear { linear_to_srgb(value as f32) } else { value as f32 };
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    Rgba([
+        channel(encode(color.r)),
+        channel(encode(color.g)),
+        channel(encode(color.b)),
+        channel(color.a as f32),
+    ])
+}
+
+/// The canvas of `snapshot` over `background`, sRGB encoded.
+fn composite_over(snapshot: &DocumentSnapshot, background: wgpu::Color) -> Result<RgbaImage, RenderError> {
+    let composite = snapshot.render_composite()?;
+    let (width, height) = (snapshot.width(), snapshot.height());
+    if !snapshot.color_profile().blends_in_linear_light() {
+        let mut canvas = RgbaImage::from_pixel(width, height, to_rgba8(background, false));
+        composite_onto(&mut canvas, &composite, 0, 0, BlendMode::Normal, 1.0);
+        return Ok(canvas);
+    }
+    let linear = [background.r as f32, background.g as f32, background.b as f32, background.a as f32];
+    let mut canvas = Rgba32FImage::from_pixel(width, height, Rgba(linear));
+    composite_onto_linear(&mut canvas, &composite, 0, 0, BlendMode::Normal, 1.0);
+    let encoded = RgbaImage::from_fn(width, height, |x, y| {
+        let [r, g, b, a] = canvas.get_pixel(x, y).0;
+        let color = wgpu::Color { r: r as f64, g: g as f64, b: b as f64, a: a as f64 };
+        to_rgba8(color, true)
+    });
+    Ok(encoded)
+}
+
+/// Draws into an image in memory, see [`RenderBackend::read_back`]. Layers
+/// are flattened with [`DocumentSnapshot::render_composite`] and the canvas
+/// sampled at each pixel's center, without filtering.
+#[derive(Debug)]
+pub struct SoftwareBackend {
+    frame: RgbaImage,
+    background_color: wgpu::Color,
+    document_background: wgpu::Color,
+    images: ImageStore<DynamicImage>,
+}
+
+impl SoftwareBackend {
+    /// Zero sizes are raised to 1.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            frame: RgbaImage::new(width.max(1), height.max(1)),
+            background_color: wgpu::Color::TRANSPARENT,
+            document_background: wgpu::Color::WHITE,
+            images: ImageStore::default(),
+        }
+    }
+}
+
+impl RenderBackend for SoftwareBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Software
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.frame.dimensions()
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 && (width, height) != self.size() {
+            self.frame = RgbaImage::new(width, height);
+        }
+    }
+
+    fn set_background_color(&mut self, color: wgpu::Color) {
+        self.background_color = color;
+    }
+
+    fn set_document_background(&mut self, color: wgpu::Color) {
+        self.document_background = color;
+    }
+
+    fn upload_image(&mut self, image: &DynamicImage) -> ImageId {
+        self.images.insert(DynamicImage::ImageRgba8(image.to_rgba8()))
+    }
+
+    fn release_image(&mut self, image: ImageId) {
+        self.images.remove(image);
+    }
+
+    fn blend(&mut self, bottom: ImageId, top: ImageId, mode: BlendMode, opacity: f32) -> Option<ImageId> {
+        let blended = blend_images(self.images.get(bottom)?, self.images.get(top)?, mode, opacity);
+        Some(self.images.insert(blended))
+    }
+
+    fn read_image(&self, image: ImageId) -> Option<DynamicImage> {
+        self.images.get(image).cloned()
+    }
+
+    fn render_document(&mut self, snapshot: &DocumentSnapshot, view: ViewTransform) -> Result<(), RenderError> {
+        let canvas = composite_over(snapshot, self.document_background)?;
+        let linear = snapshot.color_profile().blends_in_linear_light();
+        let clear = to_rgba8(self.background_color, linear);
+        let (width, height) = canvas.dimensions();
+        for (x, y, pixel) in self.frame.enumerate_pixels_mut() {
+            let u = ((x as f32 + 0.5) - view.pan[0]) / view.zoom;
+            let v = ((y as f32 + 0.5) - view.pan[1]) / view.zoom;
+            let inside = u >= 0.0 && v >= 0.0 && u < width as f32 && v < height as f32;
+            *pixel = if inside { *canvas.get_pixel(u as u32, v as u32) } else { clear };
+        }
+        Ok(())
+    }
+
+    fn read_back(&mut self) -> Result<DynamicImage, RenderError> {
+        Ok(DynamicImage::ImageRgba8(self.frame.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors_encode_for_linear_documents() {
+        let gray = wgpu::Color { r: 0.5, g: 0.5, b: 0.5, a: 0.5 };
+        assert_eq!(to_rgba8(gray, false), Rgba([128, 128, 128, 128]));
+        assert_eq!(to_rgba8(gray, true), Rgba([188, 188, 188, 128]));
+    }
+}
@@ -0,0 +1,270 @@
+//! Reusable GPU buffers, so drawing the same scene frame after frame
+//! doesn't allocate.
+//!
+//! Buffers are bucketed by size, rounded up to a power of two, and go back
+//! to the pool once the GPU has finished the submission that used them.
+//! Upload buffers are mapped again before they're handed out.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use image::RgbaImage;
+
+/// Smallest bucket, in bytes.
+const MIN_BUCKET: u64 = 256;
+
+/// What a [`StagingPool`] buffer is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StagingKind {
+    /// Mapped for writing, copied from into textures.
+    Upload,
+    /// Copied into from textures, mapped for reading.
+    Readback,
+    /// Vertices written with [`wgpu::Queue::write_buffer`].
+    Vertex,
+    /// Uniforms written with [`wgpu::Queue::write_buffer`].
+    Uniform,
+}
+
+impl StagingKind {
+    fn usage(self) -> wgpu::BufferUsages {
+        match self {
+            StagingKind::Upload => wgpu::BufferUsages::MAP_WRITE | wgpu::BufferUsages::COPY_SRC,
+            StagingKind::Readback => wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            StagingKind::Vertex => wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            StagingKind::Uniform => wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            StagingKind::Upload => "Staging Upload Buffer",
+            StagingKind::Readback => "Staging Readback Buffer",
+            StagingKind::Vertex => "Staging Vertex Buffer",
+            StagingKind::Uniform => "Staging Uniform Buffer",
+        }
+    }
+}
+
+/// Buffers a [`StagingPool`] created versus handed out again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StagingStats {
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+    pub reuses: u64,
+    pub reused_bytes: u64,
+}
+
+/// The bucket a `size`-byte request is served from.
+fn bucket_size(size: u64) -> u64 {
+    size.max(MIN_BUCKET).next_power_of_two()
+}
+
+type Pooled = (StagingKind, u64, Arc<wgpu::Buffer>);
+
+struct FreeBuffer {
+    buffer: Arc<wgpu::Buffer>,
+    /// Upload buffers being mapped; ready once this yields.
+    mapping: Option<Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    free: HashMap<(StagingKind, u64), Vec<FreeBuffer>>,
+    /// Handed out since the last [`StagingPool::submitted`].
+    in_flight: Vec<Pooled>,
+    /// Finished with by the GPU, to be put back in `free`.
+    returned: Vec<Pooled>,
+    stats: StagingStats,
+}
+
+impl PoolState {
+    fn reclaim(&mut self) {
+        for (kind, bucket, buffer) in self.returned.drain(..) {
+            let mapping = (kind == StagingKind::Upload).then(|| {
+                let (sender, receiver) = mpsc::channel();
+                buffer.slice(..).map_async(wgpu::MapMode::Write, move |result| {
+                    let _ = sender.send(result);
+                });
+                receiver
+            });
+            self.free.entry((kind, bucket)).or_default().push(FreeBuffer { buffer, mapping });
+        }
+    }
+
+    /// A free buffer from the bucket that's ready to use.
+    fn take(&mut self, kind: StagingKind, bucket: u64) -> Option<Arc<wgpu::Buffer>> {
+        self.reclaim();
+        let free = self.free.get_mut(&(kind, bucket))?;
+        let mut index = 0;
+        while index < free.len() {
+            match free[index].mapping.as_ref().map(Receiver::try_recv) {
+                None | Some(Ok(Ok(()))) => return Some(free.swap_remove(index).buffer),
+                Some(Err(mpsc::TryRecvError::Empty)) => index += 1,
+                // Failed to map; the buffer is dropped
+                Some(_) => {
+                    free.swap_remove(index);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Hands out reusable buffers for per-frame data. Cloning is cheap and
+/// clones share the pool.
+///
+/// Buffers other than [`StagingKind::Readback`] ones are recycled after
+/// the next [`StagingPool::submitted`], which has to be called after the
+/// submission that uses them. Readback buffers go back with
+/// [`StagingPool::release`] once they've been read and unmapped.
+#[derive(Clone, Default)]
+pub struct StagingPool {
+    state: Arc<Mutex<PoolState>>,
+}
+
+impl std::fmt::Debug for StagingPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StagingPool").field("stats", &self.stats()).finish_non_exhaustive()
+    }
+}
+
+impl StagingPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> StagingStats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// A buffer of at least `size` bytes. Upload buffers are mapped.
+    pub fn acquire(&self, device: &wgpu::Device, kind: StagingKind, size: u64) -> Arc<wgpu::Buffer> {
+        let bucket = bucket_size(size);
+        let mut reused = self.state.lock().unwrap().take(kind, bucket);
+        if reused.is_none() {
+            // Returns buffers whose submissions have finished. Callbacks
+            // lock the state, so it mustn't be held.
+            device.poll(wgpu::Maintain::Poll);
+            reused = self.state.lock().unwrap().take(kind, bucket);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let buffer = match reused {
+            Some(buffer) => {
+                state.stats.reuses += 1;
+                state.stats.reused_bytes += bucket;
+                buffer
+            }
+            None => {
+                state.stats.allocations += 1;
+                state.stats.allocated_bytes += bucket;
+                Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(kind.label()),
+                    size: bucket,
+                    usage: kind.usage(),
+                    mapped_at_creation: kind == StagingKind::Upload,
+                }))
+            }
+        };
+        if kind != StagingKind::Readback {
+            state.in_flight.push((kind, bucket, buffer.clone()));
+        }
+        buffer
+    }
+
+    /// A buffer holding `contents`, which must be a multiple of 4 bytes
+    /// long.
+    pub fn write(&self, device: &wgpu::Device, queue: &wgpu::Queue, kind: StagingKind, contents: &[u8]) -> Arc<wgpu::Buffer> {
+        let buffer = self.acquire(device, kind, contents.len() as u64);
+        queue.write_buffer(&buffer, 0, contents);
+        buffer
+    }
+
+    /// Recycles the buffers handed out so far once the GPU finishes the
+    /// work submitted to `queue`.
+    pub fn submitted(&self, queue: &wgpu::Queue) {
+        let batch = std::mem::take(&mut self.state.lock().unwrap().in_flight);
+        if batch.is_empty() {
+            return;
+        }
+        let state = self.state.clone();
+        queue.on_submitted_work_done(move || {
+            state.lock().unwrap().returned.extend(batch);
+        });
+    }
+
+    /// Returns an unmapped readback buffer from [`StagingPool::acquire`].
+    pub fn release(&self, buffer: Arc<wgpu::Buffer>) {
+        let bucket = buffer.size();
+        self.state.lock().unwrap().returned.push((StagingKind::Readback, bucket, buffer));
+    }
+
+    /// Records copying `image` into `texture` through an upload buffer.
+    pub fn write_texture(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture, image: &RgbaImage) {
+        let (width, height) = image.dimensions();
+        let row_bytes = 4 * width as usize;
+        let padded_row_bytes = padded_row_bytes(width);
+        let buffer = self.acquire(device, StagingKind::Upload, padded_row_bytes as u64 * height as u64);
+        {
+            let mut data = buffer.slice(..).get_mapped_range_mut();
+            for (row, pixels) in data.chunks_mut(padded_row_bytes as usize).zip(image.chunks(row_bytes)) {
+                row[..row_bytes].copy_from_slice(pixels);
+            }
+        }
+        buffer.unmap();
+        encoder.copy_buffer_to_texture(
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Same as [`StagingPool::write_texture`], submitted right away.
+    pub fn upload_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture, image: &RgbaImage) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Upload Encoder"),
+        });
+        self.write_texture(device, &mut encoder, texture, image);
+        queue.submit(std::iter::once(encoder.finish()));
+        self.submitted(queue);
+    }
+}
+
+/// Bytes per row of a `width`-pixel RGBA image in a buffer copied to or
+/// from a texture, which have to be aligned.
+pub(crate) fn padded_row_bytes(width: u32) -> u32 {
+    (4 * width).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_sizes() {
+        assert_eq!(bucket_size(0), 256);
+        assert_eq!(bucket_size(256), 256);
+        assert_eq!(bucket_size(257), 512);
+        assert_eq!(bucket_size(3000), 4096);
+        assert_eq!(padded_row_bytes(1), 256);
+        assert_eq!(padded_row_bytes(64), 256);
+        assert_eq!(padded_row_bytes(65), 512);
+    }
+}
@@ -0,0 +1,299 @@
+//! Per-frame timings, for telling whether the viewport is CPU- or GPU-bound.
+//!
+//! CPU times are measured around encoding and submission. GPU times come
+//! from timestamp queries written around a frame's passes, when the device
+//! has [`wgpu::Features::TIMESTAMP_QUERY`]. They're read back without
+//! waiting, so each arrives a frame or two after it was recorded.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+use crate::Renderer;
+
+/// Frames [`Renderer::average_frame_stats`] averages over.
+pub const FRAME_STATS_WINDOW: usize = 60;
+
+/// Frames whose GPU timings can be awaited at once. Frames beyond that
+/// aren't timed on the GPU.
+const TIMER_SLOTS: usize = 3;
+
+/// How long drawing a frame took.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FrameStats {
+    /// Recording the frame's commands.
+    pub encode: Duration,
+    /// Submitting them to the queue.
+    pub submit: Duration,
+    /// The GPU executing the passes of the latest frame timed so far, a
+    /// frame or two behind. `None` without timestamp queries.
+    pub gpu: Option<Duration>,
+}
+
+impl FrameStats {
+    /// Time spent on the CPU.
+    pub fn cpu(&self) -> Duration {
+        self.encode + self.submit
+    }
+}
+
+/// The stats of the last [`FRAME_STATS_WINDOW`] frames.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FrameHistory {
+    frames: VecDeque<FrameStats>,
+}
+
+impl FrameHistory {
+    pub(crate) fn push(&mut self, stats: FrameStats) {
+        if self.frames.len() == FRAME_STATS_WINDOW {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(stats);
+    }
+
+    pub(crate) fn last(&self) -> Option<FrameStats> {
+        self.frames.back().copied()
+    }
+
+    /// The mean of each timing, GPU times over the frames that have one.
+    pub(crate) fn average(&self) -> Option<FrameStats> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        let count = self.frames.len() as u32;
+        let gpu: Vec<Duration> = self.frames.iter().filter_map(|frame| frame.gpu).collect();
+        Some(FrameStats {
+            encode: self.frames.iter().map(|frame| frame.encode).sum::<Duration>() / count,
+            submit: self.frames.iter().map(|frame| frame.submit).sum::<Duration>() / count,
+            gpu: (!gpu.is_empty()).then(|| gpu.iter().sum::<Duration>() / gpu.len() as u32),
+        })
+    }
+}
+
+/// The time between two timestamps `period` nanoseconds per tick apart.
+fn ticks_to_duration(start: u64, end: u64, period: f32) -> Duration {
+    Duration::from_nanos((end.wrapping_sub(start) as f64 * period as f64) as u64)
+}
+
+enum SlotState {
+    Idle,
+    /// Written this frame, to be mapped once submitted.
+    Encoded,
+    Mapping(Receiver<Result<(), wgpu::BufferAsyncError>>),
+}
+
+struct TimerSlot {
+    resolve: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    state: SlotState,
+}
+
+/// Timestamp queries around each frame's passes, read back without
+/// blocking.
+struct GpuTimer {
+    queries: wgpu::QuerySet,
+    slots: Vec<TimerSlot>,
+    period: f32,
+    last: Option<Duration>,
+}
+
+impl GpuTimer {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let queries = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Frame Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2 * TIMER_SLOTS as u32,
+        });
+        let size = 2 * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+        let slots = (0..TIMER_SLOTS)
+            .map(|_| TimerSlot {
+                resolve: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Timestamp Resolve Buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                }),
+                readback: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Timestamp Readback Buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                }),
+                state: SlotState::Idle,
+            })
+            .collect();
+        Self {
+            queries,
+            slots,
+            period: queue.get_timestamp_period(),
+            last: None,
+        }
+    }
+
+    /// Reads back the timings that have arrived, keeping the latest.
+    fn collect(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Poll);
+        for slot in &mut self.slots {
+            let SlotState::Mapping(receiver) = &slot.state else {
+                continue;
+            };
+            match receiver.try_recv() {
+                Ok(Ok(())) => {
+                    {
+                        let data = slot.readback.slice(..).get_mapped_range();
+                        let timestamps: &[u64] = bytemuck::cast_slice(&data);
+                        self.last = Some(ticks_to_duration(timestamps[0], timestamps[1], self.period));
+                    }
+                    slot.readback.unmap();
+                    slot.state = SlotState::Idle;
+                }
+                Ok(Err(_)) | Err(mpsc::TryRecvError::Disconnected) => slot.state = SlotState::Idle,
+                Err(mpsc::TryRecvError::Empty) => {}
+            }
+        }
+    }
+
+    /// Writes the frame's start timestamp into a free slot, if there's one.
+    fn begin(&mut self, encoder: &mut wgpu::CommandEncoder) -> Option<usize> {
+        let index = self.slots.iter().position(|slot| matches!(slot.state, SlotState::Idle))?;
+        encoder.write_timestamp(&self.queries, 2 * index as u32);
+        self.slots[index].state = SlotState::Encoded;
+        Some(index)
+    }
+
+    fn end(&self, encoder: &mut wgpu::CommandEncoder, index: usize) {
+        let first = 2 * index as u32;
+        let slot = &self.slots[index];
+        encoder.write_timestamp(&self.queries, first + 1);
+        encoder.resolve_query_set(&self.queries, first..first + 2, &slot.resolve, 0);
+        encoder.copy_buffer_to_buffer(&slot.resolve, 0, &slot.readback, 0, slot.readback.size());
+    }
+
+    /// Starts reading back the slot's timestamps once the frame is
+    /// submitted.
+    fn submitted(&mut self, index: usize) {
+        let (sender, receiver) = mpsc::channel();
+        self.slots[index].readback.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.slots[index].state = SlotState::Mapping(receiver);
+    }
+}
+
+/// Started by [`FrameProfiler::begin`] for each frame.
+pub(crate) struct FrameTiming {
+    start: Instant,
+    slot: Option<usize>,
+}
+
+/// Records [`FrameStats`] for the frames a renderer draws.
+pub(crate) struct FrameProfiler {
+    history: FrameHistory,
+    gpu: Option<GpuTimer>,
+}
+
+impl FrameProfiler {
+    /// Times frames on the GPU if the device has timestamp queries.
+    pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let gpu = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| GpuTimer::new(device, queue));
+        Self {
+            history: FrameHistory::default(),
+            gpu,
+        }
+    }
+
+    /// Call before recording the frame's passes into `encoder`.
+    pub(crate) fn begin(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) -> FrameTiming {
+        let start = Instant::now();
+        let slot = self.gpu.as_mut().and_then(|gpu| {
+            gpu.collect(device);
+            gpu.begin(encoder)
+        });
+        FrameTiming { start, slot }
+    }
+
+    /// Finishes `encoder`, after the frame's passes, and submits it.
+    pub(crate) fn submit(&mut self, queue: &wgpu::Queue, mut encoder: wgpu::CommandEncoder, timing: FrameTiming) {
+        if let (Some(gpu), Some(slot)) = (self.gpu.as_mut(), timing.slot) {
+            gpu.end(&mut encoder, slot);
+        }
+        let commands = encoder.finish();
+        let encoded = Instant::now();
+        queue.submit(std::iter::once(commands));
+        let submitted = Instant::now();
+        if let (Some(gpu), Some(slot)) = (self.gpu.as_mut(), timing.slot) {
+            gpu.submitted(slot);
+        }
+        self.history.push(FrameStats {
+            encode: encoded - timing.start,
+            submit: submitted - encoded,
+            gpu: self.gpu.as_ref().and_then(|gpu| gpu.last),
+        });
+    }
+
+    pub(crate) fn last(&self) -> Option<FrameStats> {
+        self.history.last()
+    }
+
+    pub(crate) fn average(&self) -> Option<FrameStats> {
+        self.history.average()
+    }
+}
+
+impl Renderer {
+    /// Timings of the last frame drawn, `None` before the first.
+    pub fn last_frame_stats(&self) -> Option<FrameStats> {
+        self.profiler.last()
+    }
+
+    /// Timings averaged over the last [`FRAME_STATS_WINDOW`] frames.
+    pub fn average_frame_stats(&self) -> Option<FrameStats> {
+        self.profiler.average()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(encode_ms: u64, gpu_ms: Option<u64>) -> FrameStats {
+        FrameStats {
+            encode: Duration::from_millis(encode_ms),
+            submit: Duration::from_millis(1),
+            gpu: gpu_ms.map(Duration::from_millis),
+        }
+    }
+
+    #[test]
+    fn test_average_over_window() {
+        let mut history = FrameHistory::default();
+        assert_eq!(history.average(), None);
+
+        history.push(frame(2, None));
+        history.push(frame(4, Some(6)));
+        history.push(frame(6, Some(10)));
+        let average = history.average().unwrap();
+        assert_eq!(average.encode, Duration::from_millis(4));
+        assert_eq!(average.submit, Duration::from_millis(1));
+        // Only frames with a GPU time count towards it
+        assert_eq!(average.gpu, Some(Duration::from_millis(8)));
+        assert_eq!(history.last(), Some(frame(6, Some(10))));
+        assert_eq!(average.cpu(), Duration::from_millis(5));
+
+        // Older frames drop out of the window
+        for _ in 0..FRAME_STATS_WINDOW {
+            history.push(frame(10, None));
+        }
+        assert_eq!(history.average(), Some(frame(10, None)));
+    }
+
+    #[test]
+    fn test_ticks_to_duration() {
+        assert_eq!(ticks_to_duration(100, 1100, 1.0), Duration::from_micros(1));
+        assert_eq!(ticks_to_duration(0, 1000, 83.333), Duration::from_nanos(83_333));
+        // The counter wrapping between the two
+        assert_eq!(ticks_to_duration(u64::MAX, 9, 1.0), Duration::from_nanos(10));
+    }
+}
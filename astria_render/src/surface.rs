@@ -0,0 +1,93 @@
+//! Recovering from surface errors between frames.
+
+use crate::RenderError;
+
+/// What to do when acquiring a surface texture fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SurfaceRecovery {
+    /// Configure the surface again and retry.
+    Reconfigure,
+    /// Draw nothing this frame.
+    SkipFrame,
+    Fatal,
+}
+
+pub(crate) fn classify(error: &wgpu::SurfaceError) -> SurfaceRecovery {
+    match error {
+        wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => SurfaceRecovery::Reconfigure,
+        wgpu::SurfaceError::Timeout => SurfaceRecovery::SkipFrame,
+        wgpu::SurfaceError::OutOfMemory => SurfaceRecovery::Fatal,
+    }
+}
+
+/// Runs `acquire`, calling `reconfigure` and retrying once if the surface
+/// was lost or outdated. Returns `None` if the frame should be skipped.
+pub(crate) fn acquire_with_retry<T>(
+    mut acquire: impl FnMut() -> Result<T, wgpu::SurfaceError>,
+    reconfigure: impl FnOnce(),
+) -> Result<Option<T>, RenderError> {
+    let error = match acquire() {
+        Ok(frame) => return Ok(Some(frame)),
+        Err(error) => error,
+    };
+    match classify(&error) {
+        SurfaceRecovery::Reconfigure => {}
+        SurfaceRecovery::SkipFrame => return Ok(None),
+        SurfaceRecovery::Fatal => return Err(RenderError::OutOfMemory),
+    }
+
+    reconfigure();
+    match acquire() {
+        Ok(frame) => Ok(Some(frame)),
+        Err(error) if classify(&error) == SurfaceRecovery::Fatal => Err(RenderError::OutOfMemory),
+        // Still unusable, e.g. while the window is minimized
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use wgpu::SurfaceError;
+
+    /// Acquires from `results` in turn, returning the result and how many
+    /// times the surface was reconfigured.
+    fn acquire(results: Vec<Result<u32, SurfaceError>>) -> (Result<Option<u32>, RenderError>, u32) {
+        let mut results = results.into_iter();
+        let reconfigured = Cell::new(0);
+        let result = acquire_with_retry(
+            || results.next().expect("acquired too often"),
+            || reconfigured.set(reconfigured.get() + 1),
+        );
+        (result, reconfigured.get())
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(&SurfaceError::Lost), SurfaceRecovery::Reconfigure);
+        assert_eq!(classify(&SurfaceError::Outdated), SurfaceRecovery::Reconfigure);
+        assert_eq!(classify(&SurfaceError::Timeout), SurfaceRecovery::SkipFrame);
+        assert_eq!(classify(&SurfaceError::OutOfMemory), SurfaceRecovery::Fatal);
+    }
+
+    #[test]
+    fn test_acquire_retries_once_after_reconfiguring() {
+        let (result, reconfigured) = acquire(vec![Ok(1)]);
+        assert_eq!((result.unwrap(), reconfigured), (Some(1), 0));
+
+        let (result, reconfigured) = acquire(vec![Err(SurfaceError::Lost), Ok(2)]);
+        assert_eq!((result.unwrap(), reconfigured), (Some(2), 1));
+
+        let (result, reconfigured) = acquire(vec![Err(SurfaceError::Outdated), Err(SurfaceError::Outdated)]);
+        assert_eq!((result.unwrap(), reconfigured), (None, 1));
+
+        let (result, reconfigured) = acquire(vec![Err(SurfaceError::Timeout)]);
+        assert_eq!((result.unwrap(), reconfigured), (None, 0));
+
+        let (result, _) = acquire(vec![Err(SurfaceError::OutOfMemory)]);
+        assert!(matches!(result, Err(RenderError::OutOfMemory)));
+        let (result, _) = acquire(vec![Err(SurfaceError::Lost), Err(SurfaceError::OutOfMemory)]);
+        assert!(matches!(result, Err(RenderError::OutOfMemory)));
+    }
+}
@@ -0,0 +1,491 @@
+//! Text drawn over the viewport, e.g. layer names and debug readouts.
+//!
+//! Glyphs come from a 5×7 bitmap font, rasterized into an atlas once at
+//! startup. Text is laid out in physical pixels and glyphs are scaled by
+//! whole pixels, so it stays crisp at any scale factor.
+
+use std::collections::HashMap;
+use bytemuck::{Pod, Zeroable};
+use image::RgbaImage;
+use wgpu::util::DeviceExt;
+use crate::staging::{StagingKind, StagingPool};
+use crate::Renderer;
+
+/// Glyph cell in font pixels: the 5×7 glyph with a column and a row of
+/// spacing.
+pub const CELL_SIZE: (u32, u32) = (6, 8);
+
+const FIRST_GLYPH: char = ' ';
+const ATLAS_COLUMNS: u32 = 16;
+
+/// Columns of the printable ASCII glyphs from `' '` to `'~'`, least
+/// significant bit at the top.
+const GLYPHS: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // '#'
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // '$'
+    [0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+    [0x36, 0x49, 0x55, 0x22, 0x50], // '&'
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '\''
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // '('
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // ')'
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08], // '*'
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // '+'
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ','
+    [0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+    [0x00, 0x60, 0x60, 0x00, 0x00], // '.'
+    [0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // '0'
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // '1'
+    [0x42, 0x61, 0x51, 0x49, 0x46], // '2'
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // '3'
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // '4'
+    [0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // '6'
+    [0x01, 0x71, 0x09, 0x05, 0x03], // '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // '9'
+    [0x00, 0x36, 0x36, 0x00, 0x00], // ':'
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ';'
+    [0x08, 0x14, 0x22, 0x41, 0x00], // '<'
+    [0x14, 0x14, 0x14, 0x14, 0x14], // '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+    [0x02, 0x01, 0x51, 0x09, 0x06], // '?'
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // '@'
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // 'A'
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // 'B'
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // 'C'
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // 'D'
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // 'E'
+    [0x7F, 0x09, 0x09, 0x09, 0x01], // 'F'
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], // 'G'
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // 'H'
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // 'I'
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // 'J'
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // 'K'
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // 'L'
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], // 'M'
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // 'N'
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // 'O'
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // 'P'
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // 'Q'
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // 'R'
+    [0x46, 0x49, 0x49, 0x49, 0x31], // 'S'
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // 'T'
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // 'U'
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // 'V'
+    [0x3F, 0x40, 0x38, 0x40, 0x3F], // 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+    [0x07, 0x08, 0x70, 0x08, 0x07], // 'Y'
+    [0x61, 0x51, 0x49, 0x45, 0x43], // 'Z'
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // '['
+    [0x02, 0x04, 0x08, 0x10, 0x20], // '\\'
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ']'
+    [0x04, 0x02, 0x01, 0x02, 0x04], // '^'
+    [0x40, 0x40, 0x40, 0x40, 0x40], // '_'
+    [0x00, 0x01, 0x02, 0x04, 0x00], // '`'
+    [0x20, 0x54, 0x54, 0x54, 0x78], // 'a'
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // 'b'
+    [0x38, 0x44, 0x44, 0x44, 0x20], // 'c'
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // 'd'
+    [0x38, 0x54, 0x54, 0x54, 0x18], // 'e'
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // 'f'
+    [0x0C, 0x52, 0x52, 0x52, 0x3E], // 'g'
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // 'h'
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // 'i'
+    [0x20, 0x40, 0x44, 0x3D, 0x00], // 'j'
+    [0x7F, 0x10, 0x28, 0x44, 0x00], // 'k'
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // 'l'
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // 'm'
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // 'n'
+    [0x38, 0x44, 0x44, 0x44, 0x38], // 'o'
+    [0x7C, 0x14, 0x14, 0x14, 0x08], // 'p'
+    [0x08, 0x14, 0x14, 0x18, 0x7C], // 'q'
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // 'r'
+    [0x48, 0x54, 0x54, 0x54, 0x20], // 's'
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // 't'
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // 'u'
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // 'v'
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // 'w'
+    [0x44, 0x28, 0x10, 0x28, 0x44], // 'x'
+    [0x0C, 0x50, 0x50, 0x50, 0x3C], // 'y'
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // 'z'
+    [0x00, 0x08, 0x36, 0x41, 0x00], // '{'
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // '|'
+    [0x00, 0x41, 0x36, 0x08, 0x00], // '}'
+    [0x08, 0x04, 0x08, 0x10, 0x08], // '~'
+];
+
+/// The index of `c`'s glyph. Characters outside printable ASCII show as
+/// `'?'`.
+fn glyph_index(c: char) -> u32 {
+    match c {
+        ' '..='~' => c as u32 - FIRST_GLYPH as u32,
+        _ => '?' as u32 - FIRST_GLYPH as u32,
+    }
+}
+
+/// The font rasterized into cells, white with the glyphs in alpha.
+fn atlas_image() -> RgbaImage {
+    let rows = (GLYPHS.len() as u32).div_ceil(ATLAS_COLUMNS);
+    let mut atlas = RgbaImage::from_pixel(ATLAS_COLUMNS * CELL_SIZE.0, rows * CELL_SIZE.1, image::Rgba([255, 255, 255, 0]));
+    for (index, columns) in GLYPHS.iter().enumerate() {
+        let (cell_x, cell_y) = cell_origin(index as u32);
+        for (x, column) in columns.iter().enumerate() {
+            for y in 0..7 {
+                if column & (1 << y) != 0 {
+                    atlas.get_pixel_mut(cell_x + x as u32, cell_y + y).0[3] = 255;
+                }
+            }
+        }
+    }
+    atlas
+}
+
+/// The atlas pixel of the top-left corner of glyph `index`'s cell.
+fn cell_origin(index: u32) -> (u32, u32) {
+    ((index % ATLAS_COLUMNS) * CELL_SIZE.0, (index / ATLAS_COLUMNS) * CELL_SIZE.1)
+}
+
+/// Physical pixels per font pixel for text `size_px` physical pixels tall:
+/// the nearest whole number, at least 1.
+pub fn glyph_scale(size_px: f32) -> u32 {
+    (size_px / CELL_SIZE.1 as f32).round().max(1.0) as u32
+}
+
+/// The physical size of `text` drawn `size_px` tall, for placing it.
+pub fn measure_text(text: &str, size_px: f32) -> (u32, u32) {
+    let scale = glyph_scale(size_px);
+    let width = text.lines().map(|line| line.chars().count()).max().unwrap_or(0) as u32;
+    (width * CELL_SIZE.0 * scale, text.lines().count().max(1) as u32 * CELL_SIZE.1 * scale)
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+struct TextVertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+impl TextVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TextVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: 2 * std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Two triangles per visible glyph of `text`, with its top-left corner at
+/// `pos` physical pixels, snapped to whole pixels. Lines break at `'\n'`.
+fn layout_text(text: &str, pos: [f32; 2], size_px: f32, color: wgpu::Color, atlas_size: (u32, u32)) -> Vec<TextVertex> {
+    let scale = glyph_scale(size_px) as f32;
+    let color = [color.r as f32, color.g as f32, color.b as f32, color.a as f32];
+    let (cell_width, cell_height) = (CELL_SIZE.0 as f32 * scale, CELL_SIZE.1 as f32 * scale);
+    let origin = [pos[0].round(), pos[1].round()];
+    let mut vertices = Vec::new();
+    for (row, line) in text.lines().enumerate() {
+        for (column, c) in line.chars().enumerate() {
+            if c == ' ' {
+                continue;
+            }
+            let (u, v) = cell_origin(glyph_index(c));
+            let (u0, v0) = (u as f32 / atlas_size.0 as f32, v as f32 / atlas_size.1 as f32);
+            let (u1, v1) = (
+                (u + CELL_SIZE.0) as f32 / atlas_size.0 as f32,
+                (v + CELL_SIZE.1) as f32 / atlas_size.1 as f32,
+            );
+            let (x0, y0) = (origin[0] + column as f32 * cell_width, origin[1] + row as f32 * cell_height);
+            let (x1, y1) = (x0 + cell_width, y0 + cell_height);
+            let corner = |x: f32, y: f32, u: f32, v: f32| TextVertex { position: [x, y], uv: [u, v], color };
+            let (top_left, top_right) = (corner(x0, y0, u0, v0), corner(x1, y0, u1, v0));
+            let (bottom_left, bottom_right) = (corner(x0, y1, u0, v1), corner(x1, y1, u1, v1));
+            vertices.extend([top_left, bottom_left, top_right, top_right, bottom_left, bottom_right]);
+        }
+    }
+    vertices
+}
+
+/// Text queued with [`TextPass::queue`] and drawn over the frame by
+/// [`TextPass::encode`].
+pub(crate) struct TextPass {
+    viewport: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+    atlas_size: (u32, u32),
+    vertices: Vec<TextVertex>,
+    staging: StagingPool,
+}
+
+impl TextPass {
+    pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue, staging: StagingPool) -> Self {
+        let atlas = atlas_image();
+        let atlas_size = (atlas.width(), atlas.height());
+        let extent = wgpu::Extent3d {
+            width: atlas_size.0,
+            height: atlas_size.1,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Glyph Atlas"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &atlas,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * extent.width),
+                rows_per_image: Some(extent.height),
+            },
+            extent,
+        );
+        // Glyphs are scaled by whole pixels, so nearest keeps their edges
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Glyph Sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let viewport = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Text Viewport Buffer"),
+            contents: bytemuck::cast_slice(&[[1.0f32, 1.0, 0.0, 0.0]]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Text Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Text Bind Group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: viewport.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Text Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Text Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!("text.wgsl"))),
+        });
+
+        Self {
+            viewport,
+            bind_group,
+            pipeline_layout,
+            shader,
+            pipelines: HashMap::new(),
+            atlas_size,
+            vertices: Vec::new(),
+            staging,
+        }
+    }
+
+    fn ensure_pipeline(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        if self.pipelines.contains_key(&format) {
+            return;
+        }
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Text Pipeline"),
+            layout: Some(&self.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &self.shader,
+                entry_point: "vs_main",
+                buffers: &[TextVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        self.pipelines.insert(format, pipeline);
+    }
+
+    /// Queues `text` at `pos`, both in physical pixels, for the next
+    /// [`TextPass::encode`].
+    pub(crate) fn queue(&mut self, text: &str, pos: [f32; 2], size_px: f32, color: wgpu::Color) {
+        self.vertices.extend(layout_text(text, pos, size_px, color, self.atlas_size));
+    }
+
+    /// Records drawing the queued text over `target`, a `size` texture in
+    /// `format`, and empties the queue. Text isn't multisampled, so it's
+    /// drawn after any MSAA target is resolved.
+    pub(crate) fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        size: (u32, u32),
+    ) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        self.ensure_pipeline(device, format);
+        queue.write_buffer(&self.viewport, 0, bytemuck::cast_slice(&[[size.0 as f32, size.1 as f32, 0.0, 0.0]]));
+        let vertices = std::mem::take(&mut self.vertices);
+        let vertex_bytes: &[u8] = bytemuck::cast_slice(&vertices);
+        let vertex_buffer = self.staging.write(device, queue, StagingKind::Vertex, vertex_bytes);
+
+        let view = target.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(format),
+            ..Default::default()
+        });
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Text Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipelines[&format]);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..vertex_bytes.len() as u64));
+        render_pass.draw(0..vertices.len() as u32, 0..1);
+    }
+}
+
+impl Renderer {
+    /// Queues `text` for the next frame, with its top-left corner at `pos`
+    /// and `size_px` tall, both in logical pixels. It's drawn over
+    /// everything else at the nearest whole multiple of the font's physical
+    /// size.
+    pub fn draw_text(&mut self, text: &str, pos: [f32; 2], size_px: f32, color: wgpu::Color) {
+        let scale = self.camera.scale_factor() as f32;
+        self.text.queue(text, self.camera.logical_to_screen(pos), size_px * scale, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atlas_holds_glyphs() {
+        let atlas = atlas_image();
+        let (x, y) = cell_origin(glyph_index('A'));
+        // The left stroke of 'A' starts on the second row
+        assert_eq!(atlas.get_pixel(x, y).0[3], 0);
+        assert_eq!(atlas.get_pixel(x, y + 1).0[3], 255);
+        // The spacing column and row stay empty
+        for i in 0..CELL_SIZE.1 {
+            assert_eq!(atlas.get_pixel(x + 5, y + i).0[3], 0);
+        }
+        assert_eq!(glyph_index('\u{e9}'), glyph_index('?'));
+    }
+
+    #[test]
+    fn test_layout_snaps_to_whole_pixels() {
+        assert_eq!(glyph_scale(4.0), 1);
+        assert_eq!(glyph_scale(16.0), 2);
+        assert_eq!(glyph_scale(27.0), 3);
+        assert_eq!(measure_text("ab\nc", 16.0), (24, 32));
+
+        let vertices = layout_text("a b", [10.4, 3.6], 16.0, wgpu::Color::WHITE, (96, 48));
+        // The space is skipped
+        assert_eq!(vertices.len(), 12);
+        assert_eq!(vertices[0].position, [10.0, 4.0]);
+        assert_eq!(vertices[5].position, [22.0, 20.0]);
+        assert_eq!(vertices[6].position, [34.0, 4.0]);
+    }
+}
@@ -0,0 +1,37 @@
+struct Viewport {
+    size: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> viewport: Viewport;
+@group(0) @binding(1)
+var atlas: texture_2d<f32>;
+@group(0) @binding(2)
+var atlas_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+// Positions are in physical viewport pixels, y pointing down
+@vertex
+fn vs_main(
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let ndc = position / viewport.size * 2.0 - vec2<f32>(1.0, 1.0);
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.uv = uv;
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(atlas, atlas_sampler, in.uv).a * in.color.a;
+    return vec4<f32>(in.color.rgb * coverage, coverage);
+}
@@ -0,0 +1,155 @@
+//! Layer textures shared by content hash.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::mipmap::mip_chain_bytes;
+
+/// Counters for a [`TextureCache`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextureCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Bytes of the textures the cache holds, with their mip levels.
+    pub resident_bytes: u64,
+}
+
+/// An uploaded layer output.
+#[derive(Debug, Clone)]
+pub(crate) struct CachedTexture {
+    pub(crate) texture: Arc<wgpu::Texture>,
+    /// Canvas size of the image, which may have been scaled down to fit.
+    pub(crate) size: (u32, u32),
+}
+
+struct Entry {
+    texture: CachedTexture,
+    bytes: u64,
+    last_used: u64,
+}
+
+/// Uploaded layer outputs keyed by their content hash, so a layer whose
+/// output hasn't changed is never uploaded again, even under a new id.
+///
+/// The least recently used textures are evicted when the cache holds more
+/// than its byte budget. Evicted textures stay alive while a layer is still
+/// drawn with them.
+pub struct TextureCache {
+    // Keyed by content hash and whether the texture is sampled in linear light
+    entries: HashMap<(u64, bool), Entry>,
+    budget: u64,
+    clock: u64,
+    stats: TextureCacheStats,
+}
+
+impl TextureCache {
+    pub const DEFAULT_BUDGET: u64 = 512 * 1024 * 1024;
+
+    pub fn new(budget: u64) -> Self {
+        Self {
+            entries: HashMap::new(),
+            budget,
+            clock: 0,
+            stats: TextureCacheStats::default(),
+        }
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Sets the byte budget, evicting textures until the cache fits.
+    pub fn set_budget(&mut self, budget: u64) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    pub fn stats(&self) -> TextureCacheStats {
+        self.stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, hash: u64) -> bool {
+        self.entries.keys().any(|(key, _)| *key == hash)
+    }
+
+    /// Drops the textures for `hash`. Returns whether there were any.
+    pub fn invalidate(&mut self, hash: u64) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|(key, _), _| *key != hash);
+        self.recount();
+        self.entries.len() != before
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recount();
+    }
+
+    pub(crate) fn get(&mut self, hash: u64, linear: bool) -> Option<CachedTexture> {
+        self.clock += 1;
+        match self.entries.get_mut(&(hash, linear)) {
+            Some(entry) => {
+                entry.last_used = self.clock;
+                self.stats.hits += 1;
+                Some(entry.texture.clone())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub(crate) fn insert(&mut self, hash: u64, linear: bool, texture: CachedTexture) {
+        self.clock += 1;
+        let size = texture.texture.size();
+        let bytes = mip_chain_bytes(size.width, size.height, texture.texture.mip_level_count());
+        self.entries.insert(
+            (hash, linear),
+            Entry {
+                texture,
+                bytes,
+                last_used: self.clock,
+            },
+        );
+        self.recount();
+        self.evict();
+    }
+
+    fn recount(&mut self) {
+        self.stats.resident_bytes = self.entries.values().map(|entry| entry.bytes).sum();
+    }
+
+    fn evict(&mut self) {
+        while self.stats.resident_bytes > self.budget {
+            let Some(oldest) = self.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| *key) else {
+                break;
+            };
+            let entry = self.entries.remove(&oldest).expect("key was just found");
+            self.stats.resident_bytes -= entry.bytes;
+        }
+    }
+}
+
+impl Default for TextureCache {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_BUDGET)
+    }
+}
+
+impl std::fmt::Debug for TextureCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TextureCache")
+            .field("len", &self.entries.len())
+            .field("budget", &self.budget)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
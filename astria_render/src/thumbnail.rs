@@ -0,0 +1,193 @@
+//! Downsampling images on the GPU, e.g. for node previews.
+
+use aurion_std_nodes::resample::{box_downsample, fit_within};
+use bytemuck::{Pod, Zeroable};
+use image::DynamicImage;
+use crate::blend::TextureHandle;
+use crate::headless::Readback;
+use crate::staging::{StagingKind, StagingPool};
+use crate::Renderer;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ThumbnailParams {
+    source_size: [u32; 2],
+    output_size: [u32; 2],
+}
+
+/// Box-filters images down like `box_downsample`, see
+/// [`ThumbnailPass::render`].
+pub(crate) struct ThumbnailPass {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    staging: StagingPool,
+}
+
+impl ThumbnailPass {
+    pub(crate) fn new(device: &wgpu::Device, staging: StagingPool) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Thumbnail Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Thumbnail Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Thumbnail Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!("thumbnail.wgsl"))),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Thumbnail Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: wgpu::TextureFormat::Rgba8Unorm,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        Self { layout, pipeline, staging }
+    }
+
+    /// Downsamples each image to fit within `max_dim`, with one submission
+    /// and one wait for all of them. Images that already fit are returned
+    /// as they are, and ones too large to upload are downsampled on the CPU.
+    pub(crate) fn render(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[&DynamicImage],
+        max_dim: u32,
+    ) -> Vec<DynamicImage> {
+        let max_dim = max_dim.max(1);
+        let max_texture = device.limits().max_texture_dimension_2d;
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Thumbnail Encoder"),
+        });
+
+        enum Pending {
+            Done(DynamicImage),
+            Gpu(Readback),
+        }
+        let mut pending = Vec::with_capacity(images.len());
+        for image in images {
+            let source_size = (image.width(), image.height());
+            let output_size = fit_within(source_size.0, source_size.1, max_dim);
+            if output_size == source_size {
+                pending.push(Pending::Done((*image).clone()));
+                continue;
+            }
+            if source_size.0 > max_texture || source_size.1 > max_texture {
+                pending.push(Pending::Done(box_downsample(image, max_dim)));
+                continue;
+            }
+
+            let source = TextureHandle::new(device, source_size);
+            self.staging.write_texture(device, &mut encoder, source.texture(), &image.to_rgba8());
+            let output = TextureHandle::new(device, output_size);
+            let params = ThumbnailParams {
+                source_size: [source_size.0, source_size.1],
+                output_size: [output_size.0, output_size.1],
+            };
+            let params = self.staging.write(device, queue, StagingKind::Uniform, bytemuck::cast_slice(&[params]));
+            let (source_view, target) = (source.view(), output.view());
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Thumbnail Bind Group"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: params.as_entire_binding(),
+                    },
+                ],
+            });
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Thumbnail Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &target,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                render_pass.set_pipeline(&self.pipeline);
+                render_pass.set_bind_group(0, &bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+            pending.push(Pending::Gpu(Readback::encode(device, &self.staging, &mut encoder, output.texture(), output_size)));
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        self.staging.submitted(queue);
+
+        for item in &mut pending {
+            if let Pending::Gpu(readback) = item {
+                readback.request_map();
+            }
+        }
+        device.poll(wgpu::Maintain::Wait);
+        pending
+            .into_iter()
+            .map(|item| match item {
+                Pending::Done(image) => image,
+                Pending::Gpu(readback) => DynamicImage::ImageRgba8(readback.into_image()),
+            })
+            .collect()
+    }
+}
+
+impl Renderer {
+    /// Downsamples `image` on the GPU to fit within `max_dim`, averaging the
+    /// pixels each output pixel covers like the CPU thumbnails do.
+    pub fn render_thumbnail(&mut self, image: &DynamicImage, max_dim: u32) -> DynamicImage {
+        self.render_thumbnails(&[image], max_dim).remove(0)
+    }
+
+    /// Same as [`Renderer::render_thumbnail`] for several images, submitted
+    /// together. Thumbnails are in the order of `images`.
+    pub fn render_thumbnails(&mut self, images: &[&DynamicImage], max_dim: u32) -> Vec<DynamicImage> {
+        self.thumbnails.render(&self.device, &self.queue, images, max_dim)
+    }
+}
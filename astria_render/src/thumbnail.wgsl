@@ -0,0 +1,35 @@
+struct Params {
+    source_size: vec2<u32>,
+    output_size: vec2<u32>,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> params: Params;
+
+// One triangle covering the target
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let x = f32(i32(in_vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(in_vertex_index >> 1u) * 4 - 1);
+    return vec4<f32>(x, y, 0.0, 1.0);
+}
+
+// Same as aurion_std_nodes' box_downsample: the average of the source
+// pixels the output pixel covers
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<u32>(position.xy);
+    let start = pixel * params.source_size / params.output_size;
+    let end = min(max((pixel + 1u) * params.source_size / params.output_size, start + 1u), params.source_size);
+
+    var sum = vec4<f32>(0.0);
+    for (var y = start.y; y < end.y; y++) {
+        for (var x = start.x; x < end.x; x++) {
+            sum += textureLoad(source, vec2<i32>(i32(x), i32(y)), 0);
+        }
+    }
+    let count = f32((end.x - start.x) * (end.y - start.y));
+    return sum / max(count, 1.0);
+}
@@ -0,0 +1,70 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use crate::report::{ProgressEvent, ProgressReporter};
+
+/// Shared resources made available to nodes while a graph is evaluated.
+///
+/// Resources are keyed by type, so a host inserts e.g. an asset resolver once
+/// and any node that knows the type can look it up.
+#[derive(Default)]
+pub struct EvalContext {
+    resources: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl EvalContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert<T: Any + Send + Sync>(&mut self, resource: T) {
+        self.resources.insert(TypeId::of::<T>(), Box::new(resource));
+    }
+
+    pub fn with<T: Any + Send + Sync>(mut self, resource: T) -> Self {
+        self.insert(resource);
+        self
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.resources
+            .get(&TypeId::of::<T>())
+            .and_then(|resource| resource.downcast_ref::<T>())
+    }
+
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Calls `callback` before and after each node computes, e.g. to show
+    /// how far a long render has got. Replaces any earlier callback.
+    pub fn on_progress(self, callback: Box<dyn Fn(ProgressEvent) + Send + Sync>) -> Self {
+        self.with(ProgressReporter::new(callback))
+    }
+}
+
+impl fmt::Debug for EvalContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EvalContext")
+            .field("resources", &self.resources.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_typed_resources() {
+        let context = EvalContext::new()
+            .with(42u32)
+            .with(Arc::new("shared".to_string()));
+
+        assert_eq!(context.get::<u32>(), Some(&42));
+        assert_eq!(context.get::<Arc<String>>().map(|s| s.as_str()), Some("shared"));
+        assert!(context.get::<i64>().is_none());
+        assert!(context.contains::<u32>());
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use uuid::Uuid;
+use crate::NodeId;
+
+/// Where a [`crate::NodeGraph`] gets the ids of nodes it creates, see
+/// [`crate::NodeGraph::with_id_generator`].
+pub trait NodeIdGenerator: Send + Sync + Debug {
+    fn next_id(&self) -> NodeId;
+}
+
+/// Random ids, as [`NodeId::new`] gives. Graphs use it unless given another.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomIds;
+
+impl NodeIdGenerator for RandomIds {
+    fn next_id(&self) -> NodeId {
+        NodeId::new()
+    }
+}
+
+/// Ids numbered from 1 under a seed, so building the same graph again gives
+/// the same ids, e.g. for golden files. Graphs built under different seeds
+/// don't share ids.
+#[derive(Debug)]
+pub struct SequentialIds {
+    seed: u64,
+    next: AtomicU64,
+}
+
+impl SequentialIds {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, next: AtomicU64::new(1) }
+    }
+}
+
+impl NodeIdGenerator for SequentialIds {
+    fn next_id(&self) -> NodeId {
+        let number = self.next.fetch_add(1, Ordering::SeqCst);
+        NodeId::from_uuid(Uuid::from_u128((u128::from(self.seed) << 64) | u128::from(number)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequential_ids_repeat_per_seed() {
+        let ids = |seed| {
+            let generator = SequentialIds::new(seed);
+            (0..3).map(|_| generator.next_id()).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(7), ids(7));
+        assert_ne!(ids(7), ids(8));
+        assert_eq!(ids(0)[0].to_string(), "00000000-0000-0000-0000-000000000001");
+        assert_ne!(RandomIds.next_id(), RandomIds.next_id());
+    }
+}
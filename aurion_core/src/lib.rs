@@ -1,15 +1,48 @@
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use thiserror::Error;
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 use petgraph::graph::{DiGraph, NodeIndex};
-use petgraph::visit::EdgeRef;
-use parking_lot::RwLock;
+use petgraph::visit::{Dfs, DfsPostOrder, EdgeRef, Reversed};
+use petgraph::Direction;
+use rayon::prelude::*;
+use parking_lot::{Mutex, RwLock};
 use std::sync::Arc;
 use std::fmt::Debug;
+use std::time::Instant;
 use tracing::{debug, error, instrument};
 
+pub mod context;
+pub mod ids;
+pub mod node_factory;
+pub mod observer;
+pub mod plugin;
+pub mod ports;
+pub mod report;
+pub mod schema;
+pub mod serialization;
+pub mod spec;
+pub mod subgraph;
+pub mod value;
+
+pub use context::EvalContext;
+pub use ids::{NodeIdGenerator, RandomIds, SequentialIds};
+pub use observer::{GraphObserver, SubscriptionId};
+use observer::Observers;
+pub use report::{EvaluationProfile, EvaluationReport, NodeFailure, NodeTiming, OutputObserver, ProgressEvent, ProgressStage, ReportRecorder};
+use report::ProgressReporter;
+pub use ports::PortSpec;
+pub use schema::{ParameterKind, ParameterSchema, ParameterSpec};
+pub use node_factory::{create_node, create_node_with_id, load_plugin, register_node_factory, NodeFactory, NodeRegistry};
+pub use plugin::{NodeOrigin, PluginError, PluginHandle};
+pub use serialization::SerializedGraph;
+pub use spec::GraphSpec;
+pub use subgraph::{ExposedInput, ExposedOutput, SubgraphNode};
+pub use value::{NodeOutputs, NodeValue};
+
 #[derive(Error, Debug)]
 pub enum NodeError {
     #[error("Invalid input type: expected {expected}, got {actual}")]
@@ -38,6 +71,18 @@ pub enum NodeError {
     },
     #[error("Node validation error: {0}")]
     ValidationError(String),
+    /// Evaluating `node_id` failed, because its own computation did, or,
+    /// with `input_name`, because evaluating that input did. Then `source`
+    /// is the input node's failure in turn, so the chain leads from the
+    /// requested node down to the one that failed (see
+    /// [`NodeError::root_cause`]).
+    #[error("{}", describe_failure(.node_id, .node_type, .input_name, .source))]
+    EvaluationFailed {
+        node_id: NodeId,
+        node_type: String,
+        input_name: Option<String>,
+        source: Box<NodeError>,
+    },
     #[error("Debug info: {message}\nContext: {context}")]
     Debug {
         message: String,
@@ -47,14 +92,50 @@ pub enum NodeError {
     Other(#[from] anyhow::Error),
 }
 
+impl NodeError {
+    /// The error at the end of an [`NodeError::EvaluationFailed`] chain,
+    /// or this one if it isn't one.
+    pub fn root_cause(&self) -> &NodeError {
+        match self {
+            NodeError::EvaluationFailed { source, .. } => source.root_cause(),
+            other => other,
+        }
+    }
+
+    /// The node whose own computation failed, at the end of an
+    /// [`NodeError::EvaluationFailed`] chain.
+    pub fn failed_node(&self) -> Option<&NodeId> {
+        match self {
+            NodeError::EvaluationFailed { source, input_name: Some(_), .. } => source.failed_node(),
+            NodeError::EvaluationFailed { node_id, .. } => Some(node_id),
+            _ => None,
+        }
+    }
+}
+
+// e.g. `OutputNode(1b4e28ba…) -> input 'image' -> BlurNode(6ecd8c99…): …`
+fn describe_failure(node_id: &NodeId, node_type: &str, input_name: &Option<String>, source: &NodeError) -> String {
+    let short: String = node_id.to_string().chars().take(8).collect();
+    match input_name {
+        Some(input) => format!("{}({}…) -> input '{}' -> {}", node_type, short, input, source),
+        None => format!("{}({}…): {}", node_type, short, source),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
 pub struct NodeId(pub Uuid);
 
 impl NodeId {
+    /// A random id.
     pub fn new() -> Self {
         Self(Uuid::new_v4())
     }
 
+    /// The id `uuid`, e.g. one saved earlier.
+    pub fn from_uuid(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+
     pub fn to_string(&self) -> String {
         self.0.to_string()
     }
@@ -64,8 +145,94 @@ pub trait NodeData: Send + Sync + Debug + 'static {
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
     fn type_name(&self) -> &'static str;
-    fn compute(&self, inputs: &[Box<dyn Any>]) -> Result<Box<dyn Any>, NodeError>;
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError>;
+
+    /// Computes with access to resources provided by the host. Nodes that need
+    /// shared resources override this; the default ignores the context.
+    fn compute_with_context(&self, inputs: &[NodeValue], _context: &EvalContext) -> Result<NodeValue, NodeError> {
+        self.compute(inputs)
+    }
+
+    /// The inputs the node reads, in slot order. [`NodeGraph::connect`]
+    /// rejects other names, unless the node declares no ports at all, as
+    /// nodes written before ports existed don't.
+    fn input_ports(&self) -> Vec<PortSpec> {
+        Vec::new()
+    }
+
+    /// What the node produces, in slot order.
+    fn output_ports(&self) -> Vec<PortSpec> {
+        Vec::new()
+    }
+
+    /// The output [`NodeGraph::connect`] and [`NodeGraph::evaluate`] use:
+    /// the first declared output port, or [`PortSpec::PRIMARY_OUTPUT`].
+    fn primary_output(&self) -> String {
+        self.output_ports().into_iter().next().map_or_else(|| PortSpec::PRIMARY_OUTPUT.to_string(), |port| port.name)
+    }
+
+    /// Computes every output by name. Nodes with several outputs override
+    /// this; the default gives `compute_with_context`'s result as the
+    /// primary output.
+    fn compute_outputs(&self, inputs: &[NodeValue], context: &EvalContext) -> Result<NodeOutputs, NodeError> {
+        Ok(NodeOutputs::from([(self.primary_output(), self.compute_with_context(inputs, context)?)]))
+    }
     
+    /// A hash of the node's parameters, used to fingerprint graphs for
+    /// caching. Nodes returning `None` make any graph containing them
+    /// uncacheable.
+    fn content_hash(&self) -> Option<u64> {
+        None
+    }
+
+    /// Current value of a named parameter, e.g. for an undoable edit.
+    fn get_parameter(&self, _name: &str) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Names accepted by [`NodeData::get_parameter`], e.g. for saving a node.
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// The parameters [`NodeGraph::to_serialized`] saves, which the node's
+    /// factory recreates it from. The default collects
+    /// [`NodeData::parameter_names`].
+    fn serialize_parameters(&self) -> serde_json::Value {
+        let parameters = self
+            .parameter_names()
+            .iter()
+            .filter_map(|name| Some((name.to_string(), self.get_parameter(name)?)))
+            .collect();
+        serde_json::Value::Object(parameters)
+    }
+
+    /// The parameters [`NodeData::set_parameter`] accepts, as
+    /// [`NodeFactory::parameter_schema`] describes them.
+    /// [`NodeGraph::set_node_parameter`] checks values against it first;
+    /// the default is empty, checking nothing.
+    fn parameter_schema(&self) -> ParameterSchema {
+        ParameterSchema::new()
+    }
+
+    /// Sets a named parameter. The default rejects every name.
+    fn set_parameter(&mut self, name: &str, _value: serde_json::Value) -> Result<(), NodeError> {
+        Err(NodeError::InvalidParameter {
+            name: name.to_string(),
+            reason: format!("{} has no such parameter", self.type_name()),
+        })
+    }
+
+    /// An independent copy of the node's data, used by [`Node::duplicate`]
+    /// and [`NodeGraph::deep_clone`].
+    fn clone_box(&self) -> Box<dyn NodeData>;
+
+    /// Approximate memory held by the node, for memory reports. Nodes owning
+    /// large buffers (e.g. images) add them to the default.
+    fn estimated_memory(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+
     fn get_debug_info(&self) -> String {
         format!("Node type: {}", self.type_name())
     }
@@ -79,17 +246,59 @@ pub trait NodeData: Send + Sync + Debug + 'static {
 pub struct Node {
     id: NodeId,
     data: Box<dyn NodeData>,
-    inputs: HashMap<String, NodeId>,
+    // Ordered by name; compute receives them as `Node::ordered_inputs` lists
+    inputs: BTreeMap<String, NodeId>,
+    // The source's output each input reads, where it isn't the primary one
+    source_outputs: BTreeMap<String, String>,
+    // Inputs whose source was removed from the graph, until reconnected
+    missing_inputs: BTreeSet<String>,
+    // Shown instead of the type name where set
+    label: Option<String>,
+    // Notes the user keeps on the node
+    comment: Option<String>,
+    // Tag the editor draws the node in, as RGB
+    color: Option<[u8; 3]>,
+    // Passes its first input through instead of computing
+    bypassed: bool,
     #[allow(dead_code)]
     debug_info: HashMap<String, String>, // Store debug information
 }
 
+/// A copy with the same id and inputs, see [`NodeData::clone_box`].
+impl Clone for Node {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id.clone(),
+            data: self.data.clone_box(),
+            inputs: self.inputs.clone(),
+            source_outputs: self.source_outputs.clone(),
+            missing_inputs: self.missing_inputs.clone(),
+            label: self.label.clone(),
+            comment: self.comment.clone(),
+            color: self.color,
+            bypassed: self.bypassed,
+            debug_info: self.debug_info.clone(),
+        }
+    }
+}
+
 impl Node {
     pub fn new(data: Box<dyn NodeData>) -> Self {
+        Self::new_with_id(data, NodeId::new())
+    }
+
+    /// A node with a known id, e.g. one being recreated by redo.
+    pub fn new_with_id(data: Box<dyn NodeData>, id: NodeId) -> Self {
         Self {
-            id: NodeId::new(),
+            id,
             data,
-            inputs: HashMap::new(),
+            inputs: BTreeMap::new(),
+            source_outputs: BTreeMap::new(),
+            missing_inputs: BTreeSet::new(),
+            label: None,
+            comment: None,
+            color: None,
+            bypassed: false,
             debug_info: HashMap::new(),
         }
     }
@@ -98,6 +307,59 @@ impl Node {
         &self.id
     }
 
+    /// The same node under `id`, e.g. to restore a saved id.
+    pub fn with_id(mut self, id: NodeId) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// An unconnected copy under a new id, e.g. for pasting. It keeps the
+    /// original's label and other settings until changed.
+    pub fn duplicate(&self) -> Node {
+        let mut copy = self.clone().with_id(NodeId::new());
+        copy.inputs.clear();
+        copy.source_outputs.clear();
+        copy.missing_inputs.clear();
+        copy
+    }
+
+    /// The name the user gave the node, if any.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn set_label(&mut self, label: Option<String>) {
+        self.label = label;
+    }
+
+    /// Free-form notes the user keeps on the node.
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    pub fn set_comment(&mut self, comment: Option<String>) {
+        self.comment = comment;
+    }
+
+    /// The RGB color the editor tags the node with, if any.
+    pub fn color(&self) -> Option<[u8; 3]> {
+        self.color
+    }
+
+    pub fn set_color(&mut self, color: Option<[u8; 3]>) {
+        self.color = color;
+    }
+
+    /// Whether evaluation skips the node, passing its first input (see
+    /// [`Node::ordered_inputs`]) through unchanged. Bypassed nodes without inputs compute as usual.
+    pub fn is_bypassed(&self) -> bool {
+        self.bypassed
+    }
+
+    pub fn set_bypassed(&mut self, bypassed: bool) {
+        self.bypassed = bypassed;
+    }
+
     pub fn data(&self) -> &Box<dyn NodeData> {
         &self.data
     }
@@ -109,13 +371,67 @@ impl Node {
     #[instrument(skip(self), fields(node_id = %self.id.to_string()))]
     pub fn connect_input(&mut self, input_name: &str, source_id: NodeId) {
         debug!("Connecting input '{}' from node {}", input_name, source_id.to_string());
+        self.missing_inputs.remove(input_name);
+        self.source_outputs.remove(input_name);
         self.inputs.insert(input_name.to_string(), source_id);
     }
 
+    // How an input reading `output_name` of this node records it: `None`
+    // for the primary output. Fails if the node has no such output
+    pub(crate) fn output_slot(&self, output_name: &str) -> Result<Option<String>, NodeError> {
+        let ports = self.data.output_ports();
+        let known = if ports.is_empty() {
+            output_name == PortSpec::PRIMARY_OUTPUT
+        } else {
+            ports.iter().any(|port| port.name == output_name)
+        };
+        if !known {
+            let names: Vec<_> = ports.iter().map(|port| port.name.as_str()).collect();
+            let only = if names.is_empty() { PortSpec::PRIMARY_OUTPUT.to_string() } else { names.join(", ") };
+            return Err(NodeError::InvalidParameter {
+                name: output_name.to_string(),
+                reason: format!("{} has no such output, only {}", self.data.type_name(), only),
+            });
+        }
+        Ok((output_name != self.data.primary_output()).then(|| output_name.to_string()))
+    }
+
     pub fn get_input(&self, name: &str) -> Option<&NodeId> {
         self.inputs.get(name)
     }
 
+    /// The output of its source an input reads, when it isn't the source's
+    /// primary one (see [`NodeGraph::connect_output`]).
+    pub fn source_output(&self, input_name: &str) -> Option<&str> {
+        self.source_outputs.get(input_name).map(String::as_str)
+    }
+
+    /// Connected inputs by name.
+    pub fn inputs(&self) -> &BTreeMap<String, NodeId> {
+        &self.inputs
+    }
+
+    /// Connected inputs in the order compute receives their values: the
+    /// node's declared input ports in order, then any undeclared names
+    /// alphabetically. Unconnected ports are left out rather than filled.
+    pub fn ordered_inputs(&self) -> Vec<(&str, &NodeId)> {
+        let ports = self.data.input_ports();
+        let mut ordered: Vec<_> = ports
+            .iter()
+            .filter_map(|port| self.inputs.get_key_value(&port.name))
+            .map(|(name, id)| (name.as_str(), id))
+            .collect();
+        let undeclared = self.inputs.iter().filter(|(name, _)| !ports.iter().any(|port| &port.name == *name));
+        ordered.extend(undeclared.map(|(name, id)| (name.as_str(), id)));
+        ordered
+    }
+
+    /// Inputs left without a source by [`NodeGraph::remove_node`], until
+    /// something is connected to them again.
+    pub fn missing_inputs(&self) -> &BTreeSet<String> {
+        &self.missing_inputs
+    }
+
     #[instrument(skip(self), fields(node_id = %self.id.to_string()))]
     pub fn validate(&self) -> Result<(), NodeError> {
         debug!("Validating node");
@@ -124,6 +440,21 @@ impl Node {
             // For node validation, we just check if the input is registered
             // The graph validation will check if the input node exists
         }
+        if let Some(input_name) = self.missing_inputs.iter().next() {
+            return Err(NodeError::MissingInput(format!(
+                "'{}' of {}, whose source was removed",
+                input_name,
+                self.id.to_string()
+            )));
+        }
+        if let Some(port) = self.data.input_ports().into_iter().find(|port| port.required && !self.inputs.contains_key(&port.name)) {
+            return Err(NodeError::MissingInput(format!(
+                "'{}' of {} {}",
+                port.name,
+                self.data.type_name(),
+                self.id.to_string()
+            )));
+        }
         debug!("Node validation successful");
         Ok(())
     }
@@ -139,6 +470,15 @@ impl Node {
 
     pub fn dump_debug_info(&self) -> String {
         let mut info = format!("Node {} ({}):\n", self.id.to_string(), self.data.type_name());
+        if let Some(label) = &self.label {
+            info.push_str(&format!("Label: {}\n", label));
+        }
+        if let Some(comment) = &self.comment {
+            info.push_str(&format!("Comment: {}\n", comment));
+        }
+        if let Some([r, g, b]) = self.color {
+            info.push_str(&format!("Color: #{:02x}{:02x}{:02x}\n", r, g, b));
+        }
         info.push_str("Inputs:\n");
         for (name, id) in &self.inputs {
             info.push_str(&format!("  {} -> {}\n", name, id.to_string()));
@@ -152,20 +492,83 @@ impl Node {
     }
 }
 
+/// A directed acyclic graph of nodes.
+///
+/// Cloning copies the graph's structure but shares its nodes: adding or
+/// removing nodes in one copy leaves the other alone, while edits made
+/// through a node handle (including the input recorded by
+/// [`NodeGraph::connect`]) show up in both. Copies start without
+/// observers (see [`NodeGraph::subscribe`]).
+#[derive(Clone)]
 pub struct NodeGraph {
     nodes: HashMap<NodeId, Arc<RwLock<Node>>>,
+    // Node ids in the order the nodes were added
+    order: Vec<NodeId>,
     graph: DiGraph<NodeId, ()>,
     node_indices: HashMap<NodeId, NodeIndex>,
+    // The node whose result is the graph's output, when it isn't left to
+    // the sinks
+    designated_output: Option<NodeId>,
+    cache: EvalCache,
+    // Shared by copies, so they keep numbering where the original left off
+    ids: Arc<dyn NodeIdGenerator>,
+    observers: Observers,
     #[allow(dead_code)]
     debug_mode: bool,
 }
 
+/// Results kept between [`NodeGraph::evaluate_cached`] calls, each with the
+/// [`NodeGraph::node_hash`] it was computed at. Copies of a graph start
+/// with an empty cache.
+#[derive(Default)]
+struct EvalCache {
+    entries: Mutex<HashMap<NodeId, CachedValue>>,
+}
+
+struct CachedValue {
+    hash: Option<u64>,
+    value: Computed,
+}
+
+/// Everything a node produced in one evaluation, shared by the nodes it
+/// feeds and the cache.
+#[derive(Clone)]
+struct Computed {
+    primary: String,
+    outputs: Arc<NodeOutputs>,
+}
+
+impl Computed {
+    // `output` is `None` for the primary output
+    fn output(&self, node_id: &NodeId, output: Option<&str>) -> Result<NodeValue, NodeError> {
+        let name = output.unwrap_or(&self.primary);
+        self.outputs.get(name).cloned().ok_or_else(|| {
+            NodeError::ValidationError(format!("Node {} produced no '{}' output", node_id.to_string(), name))
+        })
+    }
+
+    fn into_outputs(self) -> NodeOutputs {
+        Arc::try_unwrap(self.outputs).unwrap_or_else(|outputs| (*outputs).clone())
+    }
+}
+
+impl Clone for EvalCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
 impl NodeGraph {
     pub fn new() -> Self {
         Self {
             nodes: HashMap::new(),
+            order: Vec::new(),
             graph: DiGraph::new(),
             node_indices: HashMap::new(),
+            designated_output: None,
+            cache: EvalCache::default(),
+            ids: Arc::new(RandomIds),
+            observers: Observers::default(),
             debug_mode: false,
         }
     }
@@ -173,28 +576,194 @@ impl NodeGraph {
     pub fn with_debug(debug: bool) -> Self {
         Self {
             nodes: HashMap::new(),
+            order: Vec::new(),
             graph: DiGraph::new(),
             node_indices: HashMap::new(),
+            designated_output: None,
+            cache: EvalCache::default(),
+            ids: Arc::new(RandomIds),
+            observers: Observers::default(),
             debug_mode: debug,
         }
     }
 
+    /// A copy of the graph sharing no nodes with this one, unlike `clone`.
+    pub fn deep_clone(&self) -> NodeGraph {
+        let mut copy = self.clone();
+        for node in copy.nodes.values_mut() {
+            let cloned = node.read().clone();
+            *node = Arc::new(RwLock::new(cloned));
+        }
+        copy
+    }
+
+    /// Copies the nodes `ids` of `other` into this graph under new ids, with
+    /// the connections among them, and returns the new id of each copied
+    /// node. Connections from nodes left out are dropped. Fails, copying
+    /// nothing, if a node is missing.
+    pub fn import_subgraph(&mut self, other: &NodeGraph, ids: &[NodeId]) -> Result<HashMap<NodeId, NodeId>, NodeError> {
+        let mut copies = Vec::with_capacity(ids.len());
+        let mut renamed = HashMap::with_capacity(ids.len());
+        for id in ids {
+            if renamed.contains_key(id) {
+                continue;
+            }
+            let node = other.get_node(id).ok_or(NodeError::NodeNotFound(id.0))?;
+            // Keeps the inputs, which are rewired to the copies below
+            let copy = node.read().clone().with_id(self.next_node_id());
+            renamed.insert(id.clone(), copy.id.clone());
+            copies.push(copy);
+        }
+
+        let mut wiring = Vec::new();
+        for mut copy in copies {
+            for (input, source) in std::mem::take(&mut copy.inputs) {
+                if let Some(from) = renamed.get(&source) {
+                    wiring.push((from.clone(), copy.source_outputs.get(&input).cloned(), copy.id.clone(), input));
+                }
+            }
+            copy.source_outputs.clear();
+            self.add_node(copy);
+        }
+        for (from, output, to, input) in wiring {
+            self.connect_slot(&from, output.as_deref(), &to, &input)?;
+        }
+        Ok(renamed)
+    }
+
+    /// Sum of [`NodeData::estimated_memory`] over the graph's nodes.
+    pub fn estimated_memory(&self) -> usize {
+        self.nodes.values().map(|node| node.read().data.estimated_memory()).sum()
+    }
+
+    /// The node marked as the graph's output, see
+    /// [`NodeGraph::set_designated_output`].
+    pub fn designated_output(&self) -> Option<&NodeId> {
+        self.designated_output.as_ref()
+    }
+
+    /// Marks the node whose result is the graph's output, or with `None`
+    /// leaves it to the nodes nothing consumes.
+    pub fn set_designated_output(&mut self, id: Option<NodeId>) -> Result<(), NodeError> {
+        if let Some(id) = &id {
+            if !self.nodes.contains_key(id) {
+                return Err(NodeError::NodeNotFound(id.0));
+            }
+        }
+        self.designated_output = id;
+        Ok(())
+    }
+
+    /// The graph's node ids in the order the nodes were added.
     pub fn get_node_ids(&self) -> Vec<NodeId> {
-        self.nodes.keys().cloned().collect()
+        self.order.clone()
+    }
+
+    /// The graph's nodes with their ids, in the order they were added.
+    pub fn iter_nodes(&self) -> impl Iterator<Item = (NodeId, Arc<RwLock<Node>>)> + '_ {
+        self.order.iter().map(|id| (id.clone(), self.nodes[id].clone()))
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Connections between nodes, counting each connected input once.
+    pub fn edge_count(&self) -> usize {
+        self.graph.edge_count()
+    }
+
+    /// Nodes nothing consumes, in the order they were added.
+    pub fn sink_nodes(&self) -> Vec<NodeId> {
+        self.nodes_without_edges(Direction::Outgoing)
+    }
+
+    /// Nodes with no inputs connected, in the order they were added.
+    pub fn source_nodes(&self) -> Vec<NodeId> {
+        self.nodes_without_edges(Direction::Incoming)
+    }
+
+    fn nodes_without_edges(&self, direction: Direction) -> Vec<NodeId> {
+        self.order
+            .iter()
+            .filter(|id| self.graph.edges_directed(self.node_indices[*id], direction).next().is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// The nodes giving the graph's result: its designated output or,
+    /// without one, its sinks.
+    pub fn output_nodes(&self) -> Vec<NodeId> {
+        match &self.designated_output {
+            Some(output) => vec![output.clone()],
+            None => self.sink_nodes(),
+        }
+    }
+
+    /// Nodes whose [`NodeData::type_name`] is `type_name`, in the order they
+    /// were added.
+    pub fn find_nodes_by_type(&self, type_name: &str) -> Vec<NodeId> {
+        self.iter_nodes().filter(|(_, node)| node.read().data.type_name() == type_name).map(|(id, _)| id).collect()
+    }
+
+    /// The graph with `generator` giving the ids of nodes it creates, see
+    /// [`NodeGraph::add_node_data`].
+    pub fn with_id_generator(mut self, generator: impl NodeIdGenerator + 'static) -> Self {
+        self.ids = Arc::new(generator);
+        self
+    }
+
+    /// A new id from the graph's [`NodeIdGenerator`].
+    pub fn next_node_id(&self) -> NodeId {
+        self.ids.next_id()
+    }
+
+    /// Adds a node for `data` under an id from the graph's
+    /// [`NodeIdGenerator`].
+    pub fn add_node_data(&mut self, data: Box<dyn NodeData>) -> NodeId {
+        let id = self.next_node_id();
+        self.add_node_with_id(data, id)
+    }
+
+    /// Adds a node for `data` under `id`, e.g. one saved earlier.
+    pub fn add_node_with_id(&mut self, data: Box<dyn NodeData>, id: NodeId) -> NodeId {
+        self.add_node(Node::new_with_id(data, id))
     }
 
     #[instrument(skip(self, node), fields(node_id = %node.id().to_string()))]
     pub fn add_node(&mut self, node: Node) -> NodeId {
-        let id = node.id().clone();
+        self.insert_node(Arc::new(RwLock::new(node)))
+    }
+
+    /// Adds a node by handle, e.g. one taken out by [`NodeGraph::remove_node`].
+    /// Its recorded inputs are not connected; use [`NodeGraph::connect`].
+    pub fn insert_node(&mut self, node: Arc<RwLock<Node>>) -> NodeId {
+        let id = node.read().id().clone();
         let node_idx = self.graph.add_node(id.clone());
         self.node_indices.insert(id.clone(), node_idx);
-        self.nodes.insert(id.clone(), Arc::new(RwLock::new(node)));
+        if self.nodes.insert(id.clone(), node).is_none() {
+            self.order.push(id.clone());
+        }
         debug!("Added node to graph");
+        self.observers.notify(|observer| observer.node_added(&id));
         id
     }
 
-    #[instrument(skip(self), fields(from_id = %from.to_string(), to_id = %to.to_string()))]
+    /// Connects `from`'s primary output (see [`NodeData::primary_output`])
+    /// to `to`'s `input_name`.
     pub fn connect(&mut self, from: &NodeId, to: &NodeId, input_name: &str) -> Result<(), NodeError> {
+        self.connect_slot(from, None, to, input_name)
+    }
+
+    /// Connects `from`'s output named `output_name` to `to`'s `input_name`.
+    /// Fails if `from` declares no such output; nodes declaring none have
+    /// only [`PortSpec::PRIMARY_OUTPUT`].
+    pub fn connect_output(&mut self, from: &NodeId, output_name: &str, to: &NodeId, input_name: &str) -> Result<(), NodeError> {
+        self.connect_slot(from, Some(output_name), to, input_name)
+    }
+
+    #[instrument(skip(self), fields(from_id = %from.to_string(), to_id = %to.to_string()))]
+    fn connect_slot(&mut self, from: &NodeId, output_name: Option<&str>, to: &NodeId, input_name: &str) -> Result<(), NodeError> {
         let from_idx = self.node_indices.get(from)
             .ok_or_else(|| {
                 error!("Source node not found: {}", from.to_string());
@@ -205,138 +774,692 @@ impl NodeGraph {
                 error!("Target node not found: {}", to.to_string());
                 NodeError::NodeNotFound(to.0)
             })?;
-        
-        debug!("Adding edge between nodes");
-        self.graph.add_edge(*from_idx, *to_idx, ());
-        
-        if petgraph::algo::is_cyclic_directed(&self.graph) {
+        if let Some(to_node) = self.nodes.get(to) {
+            let to_node = to_node.read();
+            let ports = to_node.data().input_ports();
+            let declared = !ports.is_empty() || !to_node.data().output_ports().is_empty();
+            if declared && !ports.iter().any(|port| port.name == input_name) {
+                let names: Vec<_> = ports.iter().map(|port| port.name.as_str()).collect();
+                let reason = if names.is_empty() {
+                    format!("{} takes no inputs", to_node.data().type_name())
+                } else {
+                    format!("{} has no such input, only {}", to_node.data().type_name(), names.join(", "))
+                };
+                return Err(NodeError::InvalidParameter { name: input_name.to_string(), reason });
+            }
+        }
+        // Kept only when it isn't the primary output, so both ways of
+        // connecting that are the same connection
+        let output_name = match (output_name, self.nodes.get(from)) {
+            (Some(output_name), Some(from_node)) => from_node.read().output_slot(output_name)?,
+            _ => None,
+        };
+
+        // The new edge closes a cycle exactly when `from` is already
+        // reachable from `to` (or is `to`). The search is iterative, so long
+        // chains don't overflow the stack the way a recursive check would.
+        if petgraph::algo::has_path_connecting(&self.graph, *to_idx, *from_idx, None) {
             error!("Cycle detected in graph");
-            self.graph.remove_edge(self.graph.find_edge(*from_idx, *to_idx).unwrap());
             return Err(NodeError::CycleDetected {
                 from: from.to_string(),
                 to: to.to_string(),
             });
         }
 
+        debug!("Adding edge between nodes");
+        self.graph.add_edge(*from_idx, *to_idx, ());
+
+        let mut replaced = None;
         if let Some(to_node) = self.nodes.get(to) {
-            to_node.write().connect_input(input_name, from.clone());
+            let mut to_node = to_node.write();
+            replaced = to_node.inputs.get(input_name).cloned();
+            to_node.connect_input(input_name, from.clone());
+            if let Some(output_name) = &output_name {
+                to_node.source_outputs.insert(input_name.to_string(), output_name.clone());
+            }
             debug!("Connected nodes successfully");
         }
-
-        Ok(())
-    }
-
-    pub fn get_node(&self, id: &NodeId) -> Option<Arc<RwLock<Node>>> {
-        self.nodes.get(id).cloned()
-    }
-
-    #[instrument(skip(self), fields(node_id = %node_id.to_string()))]
-    pub fn evaluate(&self, node_id: &NodeId) -> Result<Box<dyn Any>, NodeError> {
-        let node = self.get_node(node_id).ok_or_else(|| {
-            error!("Node not found during evaluation: {}", node_id.to_string());
-            NodeError::NodeNotFound(node_id.0)
-        })?;
-        
-        let node = node.read();
-        debug!("Evaluating node: {}", node.data.type_name());
-        
-        let mut input_values = Vec::new();
-        for (input_name, input_id) in &node.inputs {
-            debug!("Evaluating input: {}", input_name);
-            let input_value = self.evaluate(input_id).map_err(|e| {
-                error!("Failed to evaluate input '{}': {}", input_name, e);
-                e
-            })?;
-            input_values.push(input_value);
+        // The input's previous connection goes with its edge
+        if let Some(previous) = &replaced {
+            let edge = self.node_indices.get(previous).and_then(|&index| self.graph.find_edge(index, *to_idx));
+            if let Some(edge) = edge {
+                self.graph.remove_edge(edge);
+            }
         }
+        self.mark_dirty(to);
 
-        node.data.compute(&input_values).map_err(|e| {
-            error!("Computation failed: {}", e);
-            e
-        })
+        if let Some(previous) = &replaced {
+            self.observers.notify(|observer| observer.edge_removed(previous, to, input_name));
+        }
+        self.observers.notify(|observer| observer.edge_added(from, output_name.as_deref(), to, input_name));
+        Ok(())
     }
 
-    #[instrument(skip(self))]
-    pub fn validate(&self) -> Result<(), NodeError> {
-        debug!("Validating graph");
-        // First validate each node's internal state
-        for node in self.nodes.values() {
-            node.read().validate()?;
+    /// Takes a node out of the graph along with its edges and returns its
+    /// handle, which [`NodeGraph::insert_node`] accepts to put it back. Inputs
+    /// of other nodes that were fed by it are cleared and marked missing (see
+    /// [`Node::missing_inputs`]), so [`NodeGraph::validate`] reports them
+    /// until they're connected again; the node keeps its own. Handles from
+    /// [`NodeGraph::get_node`] still held elsewhere share the returned one,
+    /// so removal never has to copy the node.
+    #[instrument(skip(self), fields(node_id = %id.to_string()))]
+    pub fn remove_node(&mut self, id: &NodeId) -> Result<Arc<RwLock<Node>>, NodeError> {
+        self.mark_dirty(id);
+        let index = self.node_indices.remove(id).ok_or(NodeError::NodeNotFound(id.0))?;
+        let node = self.nodes.remove(id).ok_or(NodeError::NodeNotFound(id.0))?;
+        self.order.retain(|other| other != id);
+        if self.designated_output.as_ref() == Some(id) {
+            self.designated_output = None;
         }
 
-        // Then validate node connections
-        for node in self.nodes.values() {
-            let node = node.read();
-            for (input_name, input_id) in &node.inputs {
-                if !self.nodes.contains_key(input_id) {
-                    error!("Node {} references missing input node {} for input '{}'", 
-                        node.id().to_string(), input_id.to_string(), input_name);
-                    return Err(NodeError::NodeNotFound(input_id.0));
+        // Every connection going with the node, as (from, to, input)
+        let mut removed: Vec<(NodeId, NodeId, String)> =
+            node.read().inputs.iter().map(|(name, source)| (source.clone(), id.clone(), name.clone())).collect();
+        let consumers: Vec<NodeId> = self.graph.edges(index).map(|edge| self.graph[edge.target()].clone()).collect();
+        for consumer_id in consumers {
+            if let Some(consumer) = self.nodes.get(&consumer_id) {
+                let mut consumer = consumer.write();
+                let fed: Vec<String> =
+                    consumer.inputs.iter().filter(|(_, source)| *source == id).map(|(name, _)| name.clone()).collect();
+                for name in fed {
+                    consumer.inputs.remove(&name);
+                    consumer.source_outputs.remove(&name);
+                    consumer.missing_inputs.insert(name.clone());
+                    removed.push((id.clone(), consumer_id.clone(), name));
                 }
             }
         }
 
-        debug!("Graph validation successful");
-        Ok(())
+        // Removal moves the last vertex into the freed index
+        self.graph.remove_node(index);
+        if let Some(moved) = self.graph.node_weight(index) {
+            self.node_indices.insert(moved.clone(), index);
+        }
+
+        debug!("Removed node from graph");
+        self.observers.notify(|observer| {
+            for (from, to, input) in &removed {
+                observer.edge_removed(from, to, input);
+            }
+            observer.node_removed(id);
+        });
+        Ok(node)
     }
 
-    // Debug helpers
-    pub fn dump_graph_debug_info(&self) -> String {
-        let mut info = String::new();
-        info.push_str("Graph Debug Information:\n");
-        info.push_str(&format!("Total nodes: {}\n", self.nodes.len()));
-        info.push_str(&format!("Total edges: {}\n", self.graph.edge_count()));
-        
-        info.push_str("\nNodes:\n");
-        for node in self.nodes.values() {
-            info.push_str(&node.read().dump_debug_info());
-            info.push_str("\n");
-        }
+    /// Removes the connection from `from` into `to`'s `input_name`.
+    #[instrument(skip(self), fields(from_id = %from.to_string(), to_id = %to.to_string()))]
+    pub fn disconnect(&mut self, from: &NodeId, to: &NodeId, input_name: &str) -> Result<(), NodeError> {
+        let from_idx = *self.node_indices.get(from).ok_or(NodeError::NodeNotFound(from.0))?;
+        let to_idx = *self.node_indices.get(to).ok_or(NodeError::NodeNotFound(to.0))?;
 
-        info.push_str("\nGraph Structure:\n");
-        for edge in self.graph.edge_references() {
-            let from = &self.graph[edge.source()];
-            let to = &self.graph[edge.target()];
-            info.push_str(&format!("  {} -> {}\n", from.to_string(), to.to_string()));
+        let mut to_node = self.nodes[to].write();
+        if to_node.inputs.get(input_name) != Some(from) {
+            return Err(NodeError::ValidationError(format!(
+                "Input '{}' of {} is not connected to {}",
+                input_name,
+                to.to_string(),
+                from.to_string()
+            )));
         }
-
-        info
+        to_node.inputs.remove(input_name);
+        to_node.source_outputs.remove(input_name);
+        drop(to_node);
+        self.mark_dirty(to);
+        if let Some(edge) = self.graph.find_edge(from_idx, to_idx) {
+            self.graph.remove_edge(edge);
+        }
+        debug!("Disconnected nodes");
+        self.observers.notify(|observer| observer.edge_removed(from, to, input_name));
+        Ok(())
     }
 
-    pub fn get_node_dependencies(&self, node_id: &NodeId) -> Result<Vec<NodeId>, NodeError> {
-        let mut deps = Vec::new();
-        if let Some(node_idx) = self.node_indices.get(node_id) {
-            for edge in self.graph.edges(*node_idx) {
-                deps.push(self.graph[edge.target()].clone());
+    /// Sets the parameter `name` of `node_id` (see [`NodeData::set_parameter`])
+    /// once it passes the node's [`NodeData::parameter_schema`], drops the
+    /// cached results of the node and everything downstream of it, and tells
+    /// observers its data changed.
+    pub fn set_node_parameter(&self, node_id: &NodeId, name: &str, value: serde_json::Value) -> Result<(), NodeError> {
+        let node = self.nodes.get(node_id).ok_or(NodeError::NodeNotFound(node_id.0))?;
+        {
+            let mut node = node.write();
+            let schema = node.data.parameter_schema();
+            if !schema.is_empty() {
+                let entry = serde_json::Map::from_iter([(name.to_string(), value.clone())]);
+                schema.validate(node.data.type_name(), &serde_json::Value::Object(entry))?;
             }
-            Ok(deps)
-        } else {
-            Err(NodeError::NodeNotFound(node_id.0))
+            node.data.set_parameter(name, value)?;
         }
+        self.mark_dirty(node_id);
+        self.observers.notify(|observer| observer.node_data_changed(node_id));
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::any::Any;
+    /// Has `observer` told about every later change to the graph made
+    /// through its methods, until [`NodeGraph::unsubscribe`].
+    pub fn subscribe(&self, observer: Arc<dyn GraphObserver>) -> SubscriptionId {
+        self.observers.subscribe(observer)
+    }
 
-    fn init_test_logging() {
-        let _ = tracing_subscriber::fmt()
-            .with_thread_ids(true)
-            .with_file(true)
-            .with_line_number(true)
-            .try_init();
+    /// Stops notifying the observer subscribed as `id`. Returns whether it
+    /// was still subscribed.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.observers.unsubscribe(id)
     }
 
-    #[derive(Debug)]
-    struct TestNode {
-        value: i32,
+    pub fn get_node(&self, id: &NodeId) -> Option<Arc<RwLock<Node>>> {
+        self.nodes.get(id).cloned()
     }
 
-    impl NodeData for TestNode {
-        fn as_any(&self) -> &dyn Any {
-            self
+    pub fn evaluate(&self, node_id: &NodeId) -> Result<NodeValue, NodeError> {
+        self.evaluate_with_context(node_id, &EvalContext::new())
+    }
+
+    /// Evaluates `node_id` and everything upstream of it. Each node computes
+    /// at most once per call, however many nodes it feeds.
+    #[instrument(skip(self, context), fields(node_id = %node_id.to_string()))]
+    pub fn evaluate_with_context(&self, node_id: &NodeId, context: &EvalContext) -> Result<NodeValue, NodeError> {
+        self.start_progress(context, Some(node_id));
+        self.evaluate_node(node_id, context, &mut HashMap::new(), None)?.output(node_id, None)
+    }
+
+    /// Like [`NodeGraph::evaluate`], returning every output of `node_id`
+    /// by name rather than only its primary one.
+    pub fn evaluate_outputs(&self, node_id: &NodeId) -> Result<NodeOutputs, NodeError> {
+        self.evaluate_outputs_with_context(node_id, &EvalContext::new())
+    }
+
+    pub fn evaluate_outputs_with_context(&self, node_id: &NodeId, context: &EvalContext) -> Result<NodeOutputs, NodeError> {
+        self.start_progress(context, Some(node_id));
+        Ok(self.evaluate_node(node_id, context, &mut HashMap::new(), None)?.into_outputs())
+    }
+
+    /// Like [`NodeGraph::evaluate`], reusing results from earlier calls.
+    pub fn evaluate_cached(&self, node_id: &NodeId) -> Result<NodeValue, NodeError> {
+        self.evaluate_cached_with_context(node_id, &EvalContext::new())
+    }
+
+    /// Like [`NodeGraph::evaluate_with_context`], reusing results from
+    /// earlier calls and keeping the new ones. A result is reused while the
+    /// node's [`NodeGraph::node_hash`] is unchanged, so parameter and
+    /// connection changes invalidate it along with every hashed node
+    /// downstream. A node has no hash when it or anything upstream of it
+    /// lacks a [`NodeData::content_hash`]; such nodes keep their results,
+    /// even through changes upstream, until [`NodeGraph::mark_dirty`]
+    /// (connection changes call it for you). Reused nodes aren't computed,
+    /// so reports and observers don't see them.
+    pub fn evaluate_cached_with_context(&self, node_id: &NodeId, context: &EvalContext) -> Result<NodeValue, NodeError> {
+        self.start_progress(context, Some(node_id));
+        let hashes = self.upstream_hashes(node_id);
+        self.evaluate_node(node_id, context, &mut HashMap::new(), Some(&hashes))?.output(node_id, None)
+    }
+
+    /// Like [`NodeGraph::evaluate`], timing each node computed along the
+    /// way. The profile covers the nodes computed before a failure too.
+    pub fn evaluate_profiled(&self, node_id: &NodeId) -> (Result<NodeValue, NodeError>, EvaluationProfile) {
+        let recorder = ReportRecorder::new();
+        let started = Instant::now();
+        let result = self.evaluate_with_context(node_id, &EvalContext::new().with(recorder.clone()));
+        let mut report = recorder.take();
+        report.total = started.elapsed();
+        (result, EvaluationProfile::from(report))
+    }
+
+    /// Drops the cached results of `node_id` and every node downstream of
+    /// it, e.g. after changing a node without a [`NodeData::content_hash`],
+    /// and returns the nodes whose results were dropped, in the order they
+    /// were added.
+    pub fn mark_dirty(&self, node_id: &NodeId) -> Vec<NodeId> {
+        let mut entries = self.cache.entries.lock();
+        if entries.is_empty() {
+            return Vec::new();
+        }
+        let Ok(downstream) = self.all_downstream(node_id) else {
+            return Vec::new();
+        };
+        std::iter::once(node_id.clone())
+            .chain(downstream)
+            .filter(|id| entries.remove(id).is_some())
+            .collect()
+    }
+
+    /// Evaluates every node once, each after the nodes feeding it, and
+    /// returns all their primary outputs. Unlike [`NodeGraph::evaluate`] it doesn't
+    /// recurse, so arbitrarily deep chains evaluate. Stops at the first
+    /// failure with [`NodeError::EvaluationFailed`].
+    pub fn evaluate_all(&self) -> Result<HashMap<NodeId, NodeValue>, NodeError> {
+        self.evaluate_all_with_context(&EvalContext::new())
+    }
+
+    #[instrument(skip(self, context))]
+    pub fn evaluate_all_with_context(&self, context: &EvalContext) -> Result<HashMap<NodeId, NodeValue>, NodeError> {
+        let order = petgraph::algo::toposort(&self.graph, None).map_err(|cycle| {
+            let id = &self.graph[cycle.node_id()];
+            NodeError::CycleDetected { from: id.to_string(), to: id.to_string() }
+        })?;
+
+        self.start_progress(context, None);
+        let mut results: HashMap<NodeId, Computed> = HashMap::with_capacity(order.len());
+        for index in order {
+            let node_id = &self.graph[index];
+            let output = self.compute_from(node_id, &results, context)?;
+            results.insert(node_id.clone(), output);
+        }
+        results
+            .into_iter()
+            .map(|(id, computed)| {
+                let value = computed.output(&id, None)?;
+                Ok((id, value))
+            })
+            .collect()
+    }
+
+    /// Like [`NodeGraph::evaluate`], computing independent branches at the
+    /// same time. Nodes upstream of `node_id` are grouped into levels, each
+    /// fed only by earlier ones, and the nodes of a level are computed on
+    /// the rayon thread pool before the next level starts. Runs on the
+    /// global pool unless called inside `ThreadPool::install`. Failures are
+    /// [`NodeError::EvaluationFailed`].
+    pub fn evaluate_parallel(&self, node_id: &NodeId) -> Result<NodeValue, NodeError> {
+        self.evaluate_parallel_with_context(node_id, &EvalContext::new())
+    }
+
+    #[instrument(skip(self, context), fields(node_id = %node_id.to_string()))]
+    pub fn evaluate_parallel_with_context(&self, node_id: &NodeId, context: &EvalContext) -> Result<NodeValue, NodeError> {
+        let &target = self.node_indices.get(node_id).ok_or(NodeError::NodeNotFound(node_id.0))?;
+        let mut upstream = HashSet::new();
+        let mut walk = Dfs::new(Reversed(&self.graph), target);
+        while let Some(index) = walk.next(Reversed(&self.graph)) {
+            upstream.insert(index);
+        }
+
+        // A node's level is one past its deepest input's
+        let order = petgraph::algo::toposort(&self.graph, None).map_err(|cycle| {
+            let id = &self.graph[cycle.node_id()];
+            NodeError::CycleDetected { from: id.to_string(), to: id.to_string() }
+        })?;
+        let mut depth: HashMap<NodeIndex, usize> = HashMap::with_capacity(upstream.len());
+        let mut levels: Vec<Vec<&NodeId>> = Vec::new();
+        for index in order.into_iter().filter(|index| upstream.contains(index)) {
+            let level = self
+                .graph
+                .neighbors_directed(index, petgraph::Direction::Incoming)
+                .map(|input| depth[&input] + 1)
+                .max()
+                .unwrap_or(0);
+            depth.insert(index, level);
+            if levels.len() <= level {
+                levels.resize_with(level + 1, Vec::new);
+            }
+            levels[level].push(&self.graph[index]);
+        }
+
+        self.start_progress(context, Some(node_id));
+        let mut results: HashMap<NodeId, Computed> = HashMap::with_capacity(upstream.len());
+        for level in levels {
+            let outputs: Vec<_> = level
+                .par_iter()
+                .map(|id| self.compute_from(id, &results, context).map(|output| ((*id).clone(), output)))
+                .collect::<Result<_, _>>()?;
+            results.extend(outputs);
+        }
+        results.remove(node_id).ok_or(NodeError::NodeNotFound(node_id.0))?.output(node_id, None)
+    }
+
+    /// Computes `node_id` from its inputs' results in `results`, which
+    /// must already hold them.
+    fn compute_from(
+        &self,
+        node_id: &NodeId,
+        results: &HashMap<NodeId, Computed>,
+        context: &EvalContext,
+    ) -> Result<Computed, NodeError> {
+        let node = self.nodes[node_id].read();
+        let failed = |input_name: Option<&str>, source: NodeError| NodeError::EvaluationFailed {
+            node_id: node_id.clone(),
+            node_type: node.data.type_name().to_string(),
+            input_name: input_name.map(str::to_string),
+            source: Box::new(source),
+        };
+        let mut input_values = Vec::with_capacity(node.inputs.len());
+        for (input_name, input_id) in node.ordered_inputs() {
+            let computed = results.get(input_id).ok_or_else(|| {
+                failed(Some(input_name), NodeError::ValidationError("not connected in the graph".to_string()))
+            })?;
+            let value = computed.output(input_id, node.source_output(input_name));
+            input_values.push(value.map_err(|e| failed(Some(input_name), e))?);
+        }
+        self.compute_node(node_id, &node, input_values, context).map_err(|e| failed(None, e))
+    }
+
+    /// Evaluates `targets`, each a node and the output read from it (`None`
+    /// for the primary one), taking the nodes in `seeds` as already
+    /// computed to their value. Progress counts the nodes computed here on
+    /// top of the evaluation already under way, e.g. the one computing the
+    /// [`SubgraphNode`] this graph is inside.
+    pub(crate) fn evaluate_seeded(
+        &self,
+        targets: &[(NodeId, Option<String>)],
+        seeds: Vec<(NodeId, NodeValue)>,
+        context: &EvalContext,
+    ) -> Result<Vec<NodeValue>, NodeError> {
+        let mut results: HashMap<NodeId, Computed> = seeds
+            .into_iter()
+            .map(|(id, value)| {
+                let outputs = NodeOutputs::from([(PortSpec::PRIMARY_OUTPUT.to_string(), value)]);
+                (id, Computed { primary: PortSpec::PRIMARY_OUTPUT.to_string(), outputs: Arc::new(outputs) })
+            })
+            .collect();
+        if let Some(progress) = context.get::<ProgressReporter>() {
+            let mut upstream = HashSet::new();
+            for (target, _) in targets {
+                if let Some(&index) = self.node_indices.get(target) {
+                    let mut walk = Dfs::new(Reversed(&self.graph), index);
+                    while let Some(index) = walk.next(Reversed(&self.graph)) {
+                        upstream.insert(index);
+                    }
+                }
+            }
+            progress.add_to_total(upstream.iter().filter(|&&index| !results.contains_key(&self.graph[index])).count());
+        }
+        targets
+            .iter()
+            .map(|(id, output)| self.evaluate_node(id, context, &mut results, None)?.output(id, output.as_deref()))
+            .collect()
+    }
+
+    // Readies the context's progress callback, if any, for an evaluation of
+    // everything upstream of `target`, or of the whole graph with `None`
+    fn start_progress(&self, context: &EvalContext, target: Option<&NodeId>) {
+        let Some(progress) = context.get::<ProgressReporter>() else {
+            return;
+        };
+        let total = match target {
+            Some(target) => self.node_indices.get(target).map_or(0, |&index| {
+                let mut upstream = Dfs::new(Reversed(&self.graph), index);
+                std::iter::from_fn(|| upstream.next(Reversed(&self.graph))).count()
+            }),
+            None => self.nodes.len(),
+        };
+        progress.start(total);
+    }
+
+    /// Drops every result kept by [`NodeGraph::evaluate_cached`].
+    pub fn clear_cache(&self) {
+        self.cache.entries.lock().clear();
+    }
+
+    // `results` holds what this call computed so far; with `hashes`, the
+    // node hashes of everything upstream, it also reads and fills the
+    // graph's cache
+    fn evaluate_node(
+        &self,
+        node_id: &NodeId,
+        context: &EvalContext,
+        results: &mut HashMap<NodeId, Computed>,
+        hashes: Option<&HashMap<NodeId, Option<u64>>>,
+    ) -> Result<Computed, NodeError> {
+        if let Some(value) = results.get(node_id) {
+            return Ok(value.clone());
+        }
+        let hash = hashes.and_then(|hashes| hashes.get(node_id).copied().flatten());
+        if hashes.is_some() {
+            if let Some(entry) = self.cache.entries.lock().get(node_id).filter(|entry| entry.hash == hash) {
+                debug!("Reusing cached result");
+                results.insert(node_id.clone(), entry.value.clone());
+                return Ok(entry.value.clone());
+            }
+        }
+
+        let node = self.get_node(node_id).ok_or_else(|| {
+            error!("Node not found during evaluation: {}", node_id.to_string());
+            NodeError::NodeNotFound(node_id.0)
+        })?;
+        
+        let node = node.read();
+        debug!("Evaluating node: {}", node.data.type_name());
+        let failed = |input_name: Option<&str>, source: NodeError| NodeError::EvaluationFailed {
+            node_id: node_id.clone(),
+            node_type: node.data.type_name().to_string(),
+            input_name: input_name.map(str::to_string),
+            source: Box::new(source),
+        };
+
+        let mut input_values = Vec::new();
+        for (input_name, input_id) in node.ordered_inputs() {
+            debug!("Evaluating input: {}", input_name);
+            let input_value = self
+                .evaluate_node(input_id, context, results, hashes)
+                .and_then(|computed| computed.output(input_id, node.source_output(input_name)))
+                .map_err(|e| {
+                    error!("Failed to evaluate input '{}': {}", input_name, e);
+                    failed(Some(input_name), e)
+                })?;
+            input_values.push(input_value);
+        }
+        let output = self.compute_node(node_id, &node, input_values, context).map_err(|e| failed(None, e))?;
+        results.insert(node_id.clone(), output.clone());
+        if hashes.is_some() {
+            self.cache.entries.lock().insert(node_id.clone(), CachedValue { hash, value: output.clone() });
+        }
+        Ok(output)
+    }
+
+    /// Computes one node's outputs from its inputs' results, or passes the
+    /// first input through as its primary output if it's bypassed,
+    /// reporting to the context's progress callback, recorder and observer.
+    fn compute_node(
+        &self,
+        node_id: &NodeId,
+        node: &Node,
+        mut input_values: Vec<NodeValue>,
+        context: &EvalContext,
+    ) -> Result<Computed, NodeError> {
+        let primary = node.data.primary_output();
+        let progress = context.get::<ProgressReporter>();
+        if let Some(progress) = progress {
+            progress.started(node_id, node.data.type_name());
+        }
+        let result = if node.bypassed && !input_values.is_empty() {
+            debug!("Node is bypassed, passing its first input through");
+            Ok(NodeOutputs::from([(primary.clone(), input_values.swap_remove(0))]))
+        } else {
+            let started = Instant::now();
+            let result = node.data.compute_outputs(&input_values, context).map_err(|e| {
+                error!("Computation failed: {}", e);
+                e
+            });
+            if let Some(recorder) = context.get::<ReportRecorder>() {
+                let type_name = node.data.type_name();
+                match &result {
+                    Ok(outputs) => recorder.record_timing(NodeTiming {
+                        node: node_id.clone(),
+                        type_name,
+                        duration: started.elapsed(),
+                        input_sizes: input_values.iter().map(NodeValue::estimated_size).collect(),
+                        output_type: outputs.get(&primary).map_or("None", NodeValue::variant_name),
+                    }),
+                    Err(e) => recorder.record_failure(NodeFailure { node: node_id.clone(), type_name, message: e.to_string() }),
+                }
+            }
+            result
+        };
+        if let Some(progress) = progress {
+            progress.finished(node_id, node.data.type_name());
+        }
+        let outputs = result?;
+        if let Some(observer) = context.get::<OutputObserver>() {
+            if let Some(output) = outputs.get(&primary) {
+                observer.observe(self, node_id, output);
+            }
+        }
+        Ok(Computed { primary, outputs: Arc::new(outputs) })
+    }
+
+    #[instrument(skip(self))]
+    pub fn validate(&self) -> Result<(), NodeError> {
+        debug!("Validating graph");
+        // First validate each node's internal state
+        for node in self.nodes.values() {
+            node.read().validate()?;
+        }
+
+        // Then validate node connections
+        for node in self.nodes.values() {
+            let node = node.read();
+            for (input_name, input_id) in &node.inputs {
+                if !self.nodes.contains_key(input_id) {
+                    error!("Node {} references missing input node {} for input '{}'", 
+                        node.id().to_string(), input_id.to_string(), input_name);
+                    return Err(NodeError::NodeNotFound(input_id.0));
+                }
+            }
+        }
+
+        debug!("Graph validation successful");
+        Ok(())
+    }
+
+    // Debug helpers
+    pub fn dump_graph_debug_info(&self) -> String {
+        let mut info = String::new();
+        info.push_str("Graph Debug Information:\n");
+        info.push_str(&format!("Total nodes: {}\n", self.nodes.len()));
+        info.push_str(&format!("Total edges: {}\n", self.graph.edge_count()));
+        
+        info.push_str("\nNodes:\n");
+        for node in self.nodes.values() {
+            info.push_str(&node.read().dump_debug_info());
+            info.push_str("\n");
+        }
+
+        info.push_str("\nGraph Structure:\n");
+        for edge in self.graph.edge_references() {
+            let from = &self.graph[edge.source()];
+            let to = &self.graph[edge.target()];
+            info.push_str(&format!("  {} -> {}\n", from.to_string(), to.to_string()));
+        }
+
+        info
+    }
+
+    /// Fingerprint of the graph's nodes, their parameters, and connections.
+    /// Equal hashes mean evaluation gives equal results. Returns `None` if a
+    /// node has no [`NodeData::content_hash`].
+    pub fn content_hash(&self) -> Option<u64> {
+        let mut ids: Vec<&NodeId> = self.nodes.keys().collect();
+        ids.sort_by_key(|id| id.0);
+
+        let mut hasher = DefaultHasher::new();
+        for id in ids {
+            let node = self.nodes[id].read();
+            id.hash(&mut hasher);
+            node.data.type_name().hash(&mut hasher);
+            node.data.content_hash()?.hash(&mut hasher);
+            node.inputs.hash(&mut hasher);
+            node.source_outputs.hash(&mut hasher);
+            node.bypassed.hash(&mut hasher);
+        }
+        self.designated_output.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    /// Fingerprint of a node and everything upstream of it, so it changes
+    /// exactly when the node's output may. Returns `None` if the node is
+    /// missing or one involved has no [`NodeData::content_hash`].
+    pub fn node_hash(&self, node_id: &NodeId) -> Option<u64> {
+        self.upstream_hashes(node_id).remove(node_id).flatten()
+    }
+
+    // The node hash of `node_id` and of every node upstream of it, each
+    // hashed once after the nodes feeding it, without recursing
+    fn upstream_hashes(&self, node_id: &NodeId) -> HashMap<NodeId, Option<u64>> {
+        let mut hashes = HashMap::new();
+        let Some(&target) = self.node_indices.get(node_id) else {
+            return hashes;
+        };
+        let mut upstream = DfsPostOrder::new(Reversed(&self.graph), target);
+        while let Some(index) = upstream.next(Reversed(&self.graph)) {
+            let id = &self.graph[index];
+            let hash = self.hash_node(id, &hashes);
+            hashes.insert(id.clone(), hash);
+        }
+        hashes
+    }
+
+    // One node's hash from `hashes`, which must hold those of its inputs
+    fn hash_node(&self, node_id: &NodeId, hashes: &HashMap<NodeId, Option<u64>>) -> Option<u64> {
+        let node = self.nodes.get(node_id)?.read();
+        let mut hasher = DefaultHasher::new();
+        node.data.type_name().hash(&mut hasher);
+        node.data.content_hash()?.hash(&mut hasher);
+        node.bypassed.hash(&mut hasher);
+        for (input, source) in &node.inputs {
+            input.hash(&mut hasher);
+            node.source_output(input).hash(&mut hasher);
+            hashes.get(source).copied().flatten()?.hash(&mut hasher);
+        }
+        Some(hasher.finish())
+    }
+
+    /// The nodes feeding `node_id`'s inputs, in the order they were added.
+    pub fn get_node_dependencies(&self, node_id: &NodeId) -> Result<Vec<NodeId>, NodeError> {
+        self.neighbors(node_id, Direction::Incoming, false)
+    }
+
+    /// The nodes reading `node_id`'s outputs, in the order they were added.
+    pub fn direct_dependents(&self, node_id: &NodeId) -> Result<Vec<NodeId>, NodeError> {
+        self.neighbors(node_id, Direction::Outgoing, false)
+    }
+
+    /// Every node `node_id` feeds, directly or through others, in the order
+    /// they were added.
+    pub fn all_downstream(&self, node_id: &NodeId) -> Result<Vec<NodeId>, NodeError> {
+        self.neighbors(node_id, Direction::Outgoing, true)
+    }
+
+    /// Every node feeding `node_id`, directly or through others, in the
+    /// order they were added.
+    pub fn all_upstream(&self, node_id: &NodeId) -> Result<Vec<NodeId>, NodeError> {
+        self.neighbors(node_id, Direction::Incoming, true)
+    }
+
+    // The nodes one edge from `node_id` in `direction`, or with `transitive`
+    // any number of edges
+    fn neighbors(&self, node_id: &NodeId, direction: Direction, transitive: bool) -> Result<Vec<NodeId>, NodeError> {
+        let &start = self.node_indices.get(node_id).ok_or(NodeError::NodeNotFound(node_id.0))?;
+        let mut found = HashSet::new();
+        let mut pending = vec![start];
+        while let Some(index) = pending.pop() {
+            for next in self.graph.neighbors_directed(index, direction) {
+                if found.insert(&self.graph[next]) && transitive {
+                    pending.push(next);
+                }
+            }
+        }
+        Ok(self.order.iter().filter(|id| found.contains(id)).cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::Any;
+
+    fn init_test_logging() {
+        let _ = tracing_subscriber::fmt()
+            .with_thread_ids(true)
+            .with_file(true)
+            .with_line_number(true)
+            .try_init();
+    }
+
+    #[derive(Debug, Clone)]
+    struct TestNode {
+        value: i32,
+    }
+
+    impl NodeData for TestNode {
+        fn as_any(&self) -> &dyn Any {
+            self
         }
 
         fn as_any_mut(&mut self) -> &mut dyn Any {
@@ -347,9 +1470,27 @@ mod tests {
             "TestNode"
         }
 
-        fn compute(&self, inputs: &[Box<dyn Any>]) -> Result<Box<dyn Any>, NodeError> {
+        fn content_hash(&self) -> Option<u64> {
+            Some(self.value as u64)
+        }
+
+        fn set_parameter(&mut self, name: &str, value: serde_json::Value) -> Result<(), NodeError> {
+            match (name, value.as_i64()) {
+                ("value", Some(value)) => {
+                    self.value = value as i32;
+                    Ok(())
+                }
+                _ => Err(NodeError::InvalidParameter { name: name.to_string(), reason: "not a value".to_string() }),
+            }
+        }
+
+        fn clone_box(&self) -> Box<dyn NodeData> {
+            Box::new(self.clone())
+        }
+
+        fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
             if inputs.is_empty() {
-                Ok(Box::new(self.value))
+                Ok(NodeValue::Int(self.value.into()))
             } else {
                 Err(NodeError::InvalidInputType {
                     expected: "none".to_string(),
@@ -375,6 +1516,46 @@ mod tests {
         assert_eq!(graph.nodes.len(), 1);
     }
 
+    #[test]
+    fn test_nodes_iterate_in_insertion_order() {
+        let mut graph = NodeGraph::new();
+        let ids: Vec<NodeId> = (0..6).map(|value| graph.add_node_data(Box::new(TestNode { value }))).collect();
+        let ported = graph.add_node_data(Box::new(PortedNode));
+        graph.connect(&ids[0], &ported, "value").unwrap();
+        graph.connect(&ids[1], &ported, "offset").unwrap();
+        graph.remove_node(&ids[2]).unwrap();
+
+        let mut expected = ids.clone();
+        expected.remove(2);
+        expected.push(ported.clone());
+        assert_eq!(graph.get_node_ids(), expected);
+        assert_eq!(graph.iter_nodes().map(|(id, _)| id).collect::<Vec<_>>(), expected);
+        assert_eq!(graph.node_count(), 6);
+        assert_eq!(graph.edge_count(), 2);
+        assert_eq!(graph.find_nodes_by_type("PortedNode"), vec![ported.clone()]);
+        assert_eq!(graph.find_nodes_by_type("TestNode").len(), 5);
+
+        // Putting a node back adds it at the end
+        let removed = graph.remove_node(&ids[0]).unwrap();
+        graph.insert_node(removed);
+        assert_eq!(graph.get_node_ids().last(), Some(&ids[0]));
+        assert_eq!(graph.edge_count(), 1);
+    }
+
+    #[test]
+    fn test_sources_sinks_and_outputs() {
+        let mut graph = NodeGraph::new();
+        let [a, b, c, d] = [1, 2, 3, 4].map(|value| graph.add_node_data(Box::new(TestNode { value })));
+        graph.connect(&a, &b, "input").unwrap();
+        graph.connect(&b, &c, "input").unwrap();
+
+        assert_eq!(graph.source_nodes(), vec![a.clone(), d.clone()]);
+        assert_eq!(graph.sink_nodes(), vec![c.clone(), d.clone()]);
+        assert_eq!(graph.output_nodes(), vec![c.clone(), d.clone()]);
+        graph.set_designated_output(Some(b.clone())).unwrap();
+        assert_eq!(graph.output_nodes(), vec![b.clone()]);
+    }
+
     #[test]
     fn test_node_not_found() {
         init_test_logging();
@@ -384,6 +1565,27 @@ mod tests {
         assert!(matches!(result, Err(NodeError::NodeNotFound(_))));
     }
 
+    #[test]
+    fn test_seeded_ids_make_graphs_reproducible() {
+        let build = || {
+            let mut graph = NodeGraph::new().with_id_generator(SequentialIds::new(42));
+            let a = graph.add_node_data(Box::new(TestNode { value: 1 }));
+            let b = graph.add_node_data(Box::new(TestNode { value: 2 }));
+            graph.connect(&a, &b, "input").unwrap();
+            graph
+        };
+        let saved = build().to_serialized();
+        assert_eq!(saved, build().to_serialized());
+        assert_eq!(saved.nodes[0].id, NodeId::from_uuid(Uuid::from_u128((42 << 64) | 1)));
+
+        // Ids given explicitly survive a round trip
+        let id = NodeId::from_uuid(Uuid::from_u128(7));
+        let mut graph = NodeGraph::new();
+        graph.add_node_with_id(Box::new(TestNode { value: 3 }), id.clone());
+        let loaded = NodeGraph::from_serialized(&graph.to_serialized(), &NodeRegistry::new()).unwrap();
+        assert_eq!(loaded.get_node_ids(), [id]);
+    }
+
     #[test]
     fn test_cycle_detection() {
         init_test_logging();
@@ -431,11 +1633,40 @@ mod tests {
         let id2 = graph.add_node(node2);
         graph.connect(&id2, &id, "input").unwrap();
 
-        let result = graph.evaluate(&id);
-        assert!(matches!(
-            result,
-            Err(NodeError::InvalidInputType { .. })
-        ));
+        let error = graph.evaluate(&id).unwrap_err();
+        assert!(matches!(error.root_cause(), NodeError::InvalidInputType { .. }));
+        assert_eq!(error.failed_node(), Some(&id));
+    }
+
+    #[test]
+    fn test_evaluation_errors_lead_to_the_failed_node() {
+        let mut graph = NodeGraph::new();
+        let a = graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+        let b = graph.add_node(Node::new(Box::new(TestNode { value: 2 })));
+        let subtract = graph.add_node(Node::new(Box::new(SubtractNode)));
+        graph.connect(&a, &b, "input").unwrap();
+        graph.connect(&a, &subtract, "value").unwrap();
+        graph.connect(&b, &subtract, "amount").unwrap();
+
+        // TestNode rejects inputs, so `b` fails on the way to `subtract`
+        let error = graph.evaluate(&subtract).unwrap_err();
+        match &error {
+            NodeError::EvaluationFailed { node_id, node_type, input_name, source } => {
+                assert_eq!((node_id, node_type.as_str(), input_name.as_deref()), (&subtract, "SubtractNode", Some("amount")));
+                assert!(matches!(**source, NodeError::EvaluationFailed { ref node_id, input_name: None, .. } if *node_id == b));
+            }
+            other => panic!("expected the failure to name subtract, got {:?}", other),
+        }
+        assert_eq!(error.failed_node(), Some(&b));
+        let short = |id: &NodeId| id.to_string()[..8].to_string();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "SubtractNode({}…) -> input 'amount' -> TestNode({}…): Invalid input type: expected none, got some",
+                short(&subtract),
+                short(&b)
+            )
+        );
     }
 
     #[test]
@@ -457,4 +1688,924 @@ mod tests {
         let graph_validation = graph.validate();
         assert!(matches!(graph_validation, Err(NodeError::NodeNotFound(_))));
     }
+
+    #[test]
+    fn test_content_hash_tracks_parameters_and_edges() {
+        let mut graph = NodeGraph::new();
+        let source = graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+        let sink = graph.add_node(Node::new(Box::new(TestNode { value: 2 })));
+        let initial = graph.content_hash().unwrap();
+        assert_eq!(graph.content_hash(), Some(initial));
+
+        graph.connect(&source, &sink, "input").unwrap();
+        let connected = graph.content_hash().unwrap();
+        assert_ne!(connected, initial);
+
+        let node = graph.get_node(&source).unwrap();
+        node.write().data_mut().as_any_mut().downcast_mut::<TestNode>().unwrap().value = 5;
+        assert_ne!(graph.content_hash().unwrap(), connected);
+    }
+
+    #[test]
+    fn test_remove_and_disconnect() {
+        let mut graph = NodeGraph::new();
+        let a = graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+        let b = graph.add_node(Node::new(Box::new(TestNode { value: 2 })));
+        let c = graph.add_node(Node::new(Box::new(TestNode { value: 3 })));
+        graph.connect(&a, &b, "input").unwrap();
+        graph.connect(&b, &c, "input").unwrap();
+
+        graph.disconnect(&b, &c, "input").unwrap();
+        assert!(graph.get_node(&c).unwrap().read().get_input("input").is_none());
+        assert!(graph.direct_dependents(&b).unwrap().is_empty());
+        assert!(graph.disconnect(&b, &c, "input").is_err());
+
+        // Removing `a` moves `c` into its vertex slot
+        let removed = graph.remove_node(&a).unwrap();
+        assert!(graph.get_node(&b).unwrap().read().get_input("input").is_none());
+        // `b` lost its source, and validation says so until it's reconnected
+        assert!(graph.get_node(&b).unwrap().read().missing_inputs().contains("input"));
+        assert!(matches!(graph.validate(), Err(NodeError::MissingInput(_))));
+        graph.connect(&c, &b, "input").unwrap();
+        assert!(graph.get_node(&b).unwrap().read().missing_inputs().is_empty());
+        assert_eq!(graph.direct_dependents(&c).unwrap(), vec![b.clone()]);
+        assert!(matches!(graph.remove_node(&a), Err(NodeError::NodeNotFound(_))));
+
+        assert_eq!(graph.insert_node(removed), a);
+        graph.connect(&a, &c, "input").unwrap();
+        assert_eq!(graph.get_node_ids().len(), 3);
+        graph.validate().unwrap();
+
+        // Handles held elsewhere don't stop removal, and share the node
+        let handle = graph.get_node(&c).unwrap();
+        let removed = graph.remove_node(&c).unwrap();
+        assert!(Arc::ptr_eq(&handle, &removed));
+        assert_eq!(removed.read().inputs().get("input"), Some(&a));
+        assert!(graph.get_node(&c).is_none());
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum GraphEvent {
+        Added(NodeId),
+        Removed(NodeId),
+        Connected(NodeId, NodeId, String),
+        Disconnected(NodeId, NodeId, String),
+        Changed(NodeId),
+    }
+
+    #[derive(Default)]
+    struct EventLog(Mutex<Vec<GraphEvent>>);
+
+    impl GraphObserver for EventLog {
+        fn node_added(&self, node: &NodeId) {
+            self.0.lock().push(GraphEvent::Added(node.clone()));
+        }
+
+        fn node_removed(&self, node: &NodeId) {
+            self.0.lock().push(GraphEvent::Removed(node.clone()));
+        }
+
+        fn edge_added(&self, from: &NodeId, _output: Option<&str>, to: &NodeId, input: &str) {
+            self.0.lock().push(GraphEvent::Connected(from.clone(), to.clone(), input.to_string()));
+        }
+
+        fn edge_removed(&self, from: &NodeId, to: &NodeId, input: &str) {
+            self.0.lock().push(GraphEvent::Disconnected(from.clone(), to.clone(), input.to_string()));
+        }
+
+        fn node_data_changed(&self, node: &NodeId) {
+            self.0.lock().push(GraphEvent::Changed(node.clone()));
+        }
+    }
+
+    #[test]
+    fn test_observers_see_successful_edits_in_order() {
+        use GraphEvent::*;
+        let mut graph = NodeGraph::new();
+        let log = Arc::new(EventLog::default());
+        let subscription = graph.subscribe(log.clone());
+
+        let a = graph.add_node_data(Box::new(TestNode { value: 1 }));
+        let b = graph.add_node_data(Box::new(TestNode { value: 2 }));
+        let c = graph.add_node_data(Box::new(TestNode { value: 3 }));
+        graph.connect(&a, &b, "input").unwrap();
+        graph.connect(&b, &c, "input").unwrap();
+        // Rejected edits tell no one
+        assert!(graph.connect(&c, &a, "input").is_err());
+        assert!(graph.set_node_parameter(&a, "size", 5.into()).is_err());
+        assert!(graph.disconnect(&a, &c, "input").is_err());
+        graph.set_node_parameter(&a, "value", 5.into()).unwrap();
+        // Replacing a connection removes the old one
+        graph.connect(&a, &c, "input").unwrap();
+        graph.remove_node(&b).unwrap();
+
+        let input = || "input".to_string();
+        assert_eq!(
+            *log.0.lock(),
+            vec![
+                Added(a.clone()),
+                Added(b.clone()),
+                Added(c.clone()),
+                Connected(a.clone(), b.clone(), input()),
+                Connected(b.clone(), c.clone(), input()),
+                Changed(a.clone()),
+                Disconnected(b.clone(), c.clone(), input()),
+                Connected(a.clone(), c.clone(), input()),
+                Disconnected(a.clone(), b.clone(), input()),
+                Removed(b.clone()),
+            ]
+        );
+        assert_eq!(graph.direct_dependents(&a).unwrap(), vec![c.clone()]);
+
+        assert!(graph.unsubscribe(subscription));
+        assert!(!graph.unsubscribe(subscription));
+        graph.remove_node(&a).unwrap();
+        assert_eq!(log.0.lock().len(), 10);
+    }
+
+    /// Sums an `int` "value" and an optional "offset".
+    #[derive(Debug, Clone)]
+    struct PortedNode;
+
+    impl NodeData for PortedNode {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn type_name(&self) -> &'static str {
+            "PortedNode"
+        }
+
+        fn clone_box(&self) -> Box<dyn NodeData> {
+            Box::new(self.clone())
+        }
+
+        fn input_ports(&self) -> Vec<PortSpec> {
+            vec![PortSpec::optional("offset", "int"), PortSpec::required("value", "int")]
+        }
+
+        fn output_ports(&self) -> Vec<PortSpec> {
+            vec![PortSpec::required("sum", "int")]
+        }
+
+        fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+            Ok(NodeValue::Int(inputs.iter().filter_map(NodeValue::as_int).sum()))
+        }
+    }
+
+    #[test]
+    fn test_declared_ports() {
+        let mut graph = NodeGraph::new();
+        let source = graph.add_node(Node::new(Box::new(TestNode { value: 2 })));
+        let ported = graph.add_node(Node::new(Box::new(PortedNode)));
+        let sink = graph.add_node(Node::new(Box::new(PortedNode)));
+
+        assert!(matches!(
+            graph.connect(&source, &ported, "input"),
+            Err(NodeError::InvalidParameter { name, .. }) if name == "input"
+        ));
+        assert!(graph.direct_dependents(&source).unwrap().is_empty());
+        // Nodes declaring no ports take any name
+        graph.connect(&ported, &source, "anything").unwrap();
+        graph.disconnect(&ported, &source, "anything").unwrap();
+
+        // Required ports must be connected, optional ones needn't be
+        graph.connect(&source, &ported, "value").unwrap();
+        assert!(matches!(graph.validate(), Err(NodeError::MissingInput(_))));
+        graph.connect(&ported, &sink, "value").unwrap();
+        graph.validate().unwrap();
+        assert_eq!(graph.evaluate(&sink).unwrap().as_int(), Some(2));
+    }
+
+    /// `value` minus `amount`, declared in that order so it differs from
+    /// the inputs' alphabetical one.
+    #[derive(Debug, Clone)]
+    struct SubtractNode;
+
+    impl NodeData for SubtractNode {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn type_name(&self) -> &'static str {
+            "SubtractNode"
+        }
+
+        fn clone_box(&self) -> Box<dyn NodeData> {
+            Box::new(self.clone())
+        }
+
+        fn content_hash(&self) -> Option<u64> {
+            Some(0)
+        }
+
+        fn input_ports(&self) -> Vec<PortSpec> {
+            vec![PortSpec::required("value", "int"), PortSpec::required("amount", "int")]
+        }
+
+        fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+            match inputs {
+                [value, amount] => Ok(NodeValue::Int(value.as_int().unwrap() - amount.as_int().unwrap())),
+                _ => Err(NodeError::MissingInput("value and amount".to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_inputs_arrive_in_port_order() {
+        let mut graph = NodeGraph::new();
+        let value = graph.add_node(Node::new(Box::new(TestNode { value: 10 })));
+        let amount = graph.add_node(Node::new(Box::new(TestNode { value: 3 })));
+        let subtract = graph.add_node(Node::new(Box::new(SubtractNode)));
+        graph.connect(&amount, &subtract, "amount").unwrap();
+        graph.connect(&value, &subtract, "value").unwrap();
+
+        let node = graph.get_node(&subtract).unwrap();
+        let names: Vec<_> = node.read().ordered_inputs().into_iter().map(|(name, _)| name.to_string()).collect();
+        assert_eq!(names, ["value", "amount"]);
+        assert_eq!(graph.evaluate(&subtract).unwrap().as_int(), Some(7));
+        assert_eq!(graph.evaluate_all().unwrap()[&subtract].as_int(), Some(7));
+        assert_eq!(graph.evaluate_parallel(&subtract).unwrap().as_int(), Some(7));
+
+        // Bypassing passes the first declared port through
+        node.write().set_bypassed(true);
+        assert_eq!(graph.evaluate(&subtract).unwrap().as_int(), Some(10));
+    }
+
+    // Divides its input by `divisor`, giving the quotient and remainder
+    #[derive(Debug, Clone)]
+    struct DivModNode {
+        divisor: i64,
+    }
+
+    impl NodeData for DivModNode {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn type_name(&self) -> &'static str {
+            "DivModNode"
+        }
+
+        fn clone_box(&self) -> Box<dyn NodeData> {
+            Box::new(self.clone())
+        }
+
+        fn content_hash(&self) -> Option<u64> {
+            Some(self.divisor as u64)
+        }
+
+        fn input_ports(&self) -> Vec<PortSpec> {
+            vec![PortSpec::required("value", "int")]
+        }
+
+        fn output_ports(&self) -> Vec<PortSpec> {
+            vec![PortSpec::required("quotient", "int"), PortSpec::required("remainder", "int")]
+        }
+
+        fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+            let value = inputs.first().and_then(NodeValue::as_int).ok_or_else(|| NodeError::MissingInput("value".to_string()))?;
+            Ok(NodeValue::Int(value / self.divisor))
+        }
+
+        fn compute_outputs(&self, inputs: &[NodeValue], context: &EvalContext) -> Result<NodeOutputs, NodeError> {
+            let quotient = self.compute_with_context(inputs, context)?;
+            let value = inputs[0].as_int().unwrap_or_default();
+            Ok(NodeOutputs::from([
+                ("quotient".to_string(), quotient),
+                ("remainder".to_string(), NodeValue::Int(value % self.divisor)),
+            ]))
+        }
+    }
+
+    #[test]
+    fn test_inputs_read_named_outputs() {
+        let mut graph = NodeGraph::new();
+        let value = graph.add_node(Node::new(Box::new(TestNode { value: 17 })));
+        let divmod = graph.add_node(Node::new(Box::new(DivModNode { divisor: 5 })));
+        let subtract = graph.add_node(Node::new(Box::new(SubtractNode)));
+        graph.connect(&value, &divmod, "value").unwrap();
+        graph.connect(&divmod, &subtract, "value").unwrap();
+        graph.connect_output(&divmod, "remainder", &subtract, "amount").unwrap();
+
+        // 17 / 5 - 17 % 5
+        assert_eq!(graph.evaluate(&subtract).unwrap().as_int(), Some(1));
+        assert_eq!(graph.evaluate_all().unwrap()[&subtract].as_int(), Some(1));
+        assert_eq!(graph.evaluate_parallel(&subtract).unwrap().as_int(), Some(1));
+        let outputs = graph.evaluate_outputs(&divmod).unwrap();
+        assert_eq!(outputs["quotient"].as_int(), Some(3));
+        assert_eq!(outputs["remainder"].as_int(), Some(2));
+        assert_eq!(graph.evaluate(&divmod).unwrap().as_int(), Some(3));
+
+        let node = graph.get_node(&subtract).unwrap();
+        assert_eq!(node.read().source_output("amount"), Some("remainder"));
+        // Naming the primary output is the same as leaving it out
+        assert_eq!(node.read().source_output("value"), None);
+        let hash = graph.content_hash();
+        graph.connect_output(&divmod, "quotient", &subtract, "value").unwrap();
+        assert_eq!(graph.content_hash(), hash);
+        graph.connect_output(&divmod, "quotient", &subtract, "amount").unwrap();
+        assert_ne!(graph.content_hash(), hash);
+        assert_eq!(graph.evaluate_cached(&subtract).unwrap().as_int(), Some(0));
+
+        let error = graph.connect_output(&divmod, "output", &subtract, "amount").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid parameter: output - DivModNode has no such output, only quotient, remainder");
+        assert!(graph.connect_output(&value, "output", &divmod, "value").is_ok());
+        assert!(graph.connect_output(&value, "remainder", &divmod, "value").is_err());
+    }
+
+    #[test]
+    fn test_bypass_and_designated_output() {
+        let mut graph = NodeGraph::new();
+        let a = graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+        let b = graph.add_node(Node::new(Box::new(TestNode { value: 2 })));
+        graph.connect(&a, &b, "input").unwrap();
+        let initial = graph.content_hash().unwrap();
+
+        // TestNode rejects inputs, so only a bypassed `b` evaluates
+        assert!(graph.evaluate(&b).is_err());
+        graph.get_node(&b).unwrap().write().set_bypassed(true);
+        assert_eq!(graph.evaluate(&b).unwrap().as_int(), Some(1));
+        assert_ne!(graph.content_hash().unwrap(), initial);
+        // Without inputs there's nothing to pass through
+        graph.get_node(&a).unwrap().write().set_bypassed(true);
+        assert_eq!(graph.evaluate(&a).unwrap().as_int(), Some(1));
+
+        assert!(graph.set_designated_output(Some(NodeId::new())).is_err());
+        graph.set_designated_output(Some(a.clone())).unwrap();
+        assert_eq!(graph.designated_output(), Some(&a));
+        graph.remove_node(&a).unwrap();
+        assert_eq!(graph.designated_output(), None);
+    }
+
+    #[test]
+    fn test_report_records_each_computed_node() {
+        let mut graph = NodeGraph::new();
+        let a = graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+        let b = graph.add_node(Node::new(Box::new(TestNode { value: 2 })));
+        graph.connect(&a, &b, "input").unwrap();
+
+        let context = EvalContext::new().with(ReportRecorder::new());
+        // TestNode rejects inputs, so `a` computes and `b` fails
+        assert!(graph.evaluate_with_context(&b, &context).is_err());
+        let report = context.get::<ReportRecorder>().unwrap().take();
+        assert_eq!(report.timings.iter().map(|timing| &timing.node).collect::<Vec<_>>(), [&a]);
+        assert_eq!(report.slowest().map(|timing| &timing.node), Some(&a));
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].node, b);
+        assert!(context.get::<ReportRecorder>().unwrap().take().timings.is_empty());
+    }
+
+    #[test]
+    fn test_progress_events_bracket_each_node() {
+        let mut graph = NodeGraph::new();
+        let a = graph.add_node(Node::new(Box::new(SlowNode(std::time::Duration::ZERO))));
+        let b = graph.add_node(Node::new(Box::new(SlowNode(std::time::Duration::ZERO))));
+        let c = graph.add_node(Node::new(Box::new(SlowNode(std::time::Duration::ZERO))));
+        graph.connect(&a, &b, "input").unwrap();
+        graph.connect(&b, &c, "input").unwrap();
+        // Not upstream of `c`, so neither computed nor counted
+        let d = graph.add_node(Node::new(Box::new(SlowNode(std::time::Duration::ZERO))));
+        graph.connect(&a, &d, "input").unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let context = EvalContext::new().on_progress(Box::new(move |event| sink.lock().push(event)));
+        assert_eq!(graph.evaluate_with_context(&c, &context).unwrap().as_int(), Some(3));
+
+        let events = events.lock();
+        let seen: Vec<_> = events.iter().map(|event| (event.stage, event.completed, &event.node)).collect();
+        assert_eq!(
+            seen,
+            [
+                (ProgressStage::Started, 0, &a),
+                (ProgressStage::Finished, 1, &a),
+                (ProgressStage::Started, 1, &b),
+                (ProgressStage::Finished, 2, &b),
+                (ProgressStage::Started, 2, &c),
+                (ProgressStage::Finished, 3, &c),
+            ]
+        );
+        assert!(events.iter().all(|event| event.total == 3 && event.type_name == "SlowNode"));
+    }
+
+    #[test]
+    fn test_node_hash_follows_upstream_changes() {
+        let mut graph = NodeGraph::new();
+        let a = graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+        let b = graph.add_node(Node::new(Box::new(TestNode { value: 2 })));
+        let c = graph.add_node(Node::new(Box::new(TestNode { value: 2 })));
+        graph.connect(&a, &b, "input").unwrap();
+        let (hash_a, hash_b) = (graph.node_hash(&a).unwrap(), graph.node_hash(&b).unwrap());
+        // Equal nodes differ once their inputs do
+        assert_ne!(hash_b, graph.node_hash(&c).unwrap());
+
+        graph.get_node(&a).unwrap().write().data_mut().as_any_mut().downcast_mut::<TestNode>().unwrap().value = 5;
+        assert_ne!(graph.node_hash(&a).unwrap(), hash_a);
+        assert_ne!(graph.node_hash(&b).unwrap(), hash_b);
+        assert_eq!(graph.node_hash(&NodeId::new()), None);
+
+        // The observer sees each output as it's produced
+        let seen = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let context = EvalContext::new().with(OutputObserver::new(move |_, id, output| {
+            sink.lock().push((id.clone(), output.as_int()));
+        }));
+        graph.evaluate_with_context(&a, &context).unwrap();
+        graph.get_node(&b).unwrap().write().set_bypassed(true);
+        graph.evaluate_with_context(&b, &context).unwrap();
+        assert_eq!(*seen.lock(), [(a.clone(), Some(5)), (a.clone(), Some(5)), (b, Some(5))]);
+    }
+
+    #[test]
+    fn test_node_hash_handles_deep_and_wide_graphs() {
+        // A ladder of diamonds, each node hashed once rather than once per
+        // path to it
+        let mut graph = NodeGraph::new();
+        let mut last = graph.add_node(Node::new(Box::new(TestNode { value: 0 })));
+        for i in 0..64 {
+            let left = graph.add_node(Node::new(Box::new(TestNode { value: i })));
+            let right = graph.add_node(Node::new(Box::new(TestNode { value: -i })));
+            let join = graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+            graph.connect(&last, &left, "input").unwrap();
+            graph.connect(&last, &right, "input").unwrap();
+            graph.connect(&left, &join, "a").unwrap();
+            graph.connect(&right, &join, "b").unwrap();
+            last = join;
+        }
+        assert!(graph.node_hash(&last).is_some());
+
+        // And a deep chain, hashed without recursing
+        for _ in 0..5000 {
+            let next = graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+            graph.connect(&last, &next, "input").unwrap();
+            last = next;
+        }
+        let hash = graph.node_hash(&last).unwrap();
+        let first = graph.get_node_ids()[0].clone();
+        graph.get_node(&first).unwrap().write().data_mut().as_any_mut().downcast_mut::<TestNode>().unwrap().value = 9;
+        assert_ne!(graph.node_hash(&last).unwrap(), hash);
+    }
+
+    #[test]
+    fn test_deep_clone_shares_no_nodes() {
+        let mut graph = NodeGraph::new();
+        let a = graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+        let b = graph.add_node(Node::new(Box::new(TestNode { value: 2 })));
+        graph.connect(&a, &b, "input").unwrap();
+
+        let copy = graph.deep_clone();
+        assert_eq!(copy.content_hash(), graph.content_hash());
+        assert_eq!(copy.direct_dependents(&a).unwrap(), vec![b.clone()]);
+
+        let node = graph.get_node(&a).unwrap();
+        node.write().data_mut().as_any_mut().downcast_mut::<TestNode>().unwrap().value = 7;
+        assert_ne!(copy.content_hash(), graph.content_hash());
+        assert_eq!(copy.evaluate(&a).unwrap().as_int(), Some(1));
+    }
+
+    #[test]
+    fn test_duplicate_copies_under_a_new_id() {
+        let mut graph = NodeGraph::new();
+        let a = graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+        let b = graph.add_node(Node::new(Box::new(TestNode { value: 2 })));
+        graph.connect(&a, &b, "input").unwrap();
+        graph.get_node(&b).unwrap().write().set_label(Some("Two".to_string()));
+
+        let copy = graph.get_node(&b).unwrap().read().duplicate();
+        assert_ne!(copy.id(), &b);
+        assert!(copy.inputs().is_empty());
+        assert_eq!(copy.label(), Some("Two"));
+        assert_eq!(copy.data().content_hash(), Some(2));
+
+        // The copy's data is its own
+        let node = graph.get_node(&b).unwrap();
+        node.write().data_mut().as_any_mut().downcast_mut::<TestNode>().unwrap().value = 5;
+        assert_eq!(copy.data().content_hash(), Some(2));
+
+        // It can be added and wired like a new node
+        let copy = graph.add_node(copy);
+        graph.connect(&a, &copy, "input").unwrap();
+        assert_eq!(graph.direct_dependents(&a).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_import_copies_selected_nodes() {
+        let mut source = NodeGraph::new();
+        let [a, b, c] = [1, 2, 3].map(|value| source.add_node_data(Box::new(TestNode { value })));
+        source.connect(&a, &b, "input").unwrap();
+        source.connect(&b, &c, "input").unwrap();
+        source.get_node(&b).unwrap().write().set_label(Some("Middle".to_string()));
+
+        let mut target = NodeGraph::new();
+        let existing = target.add_node_data(Box::new(TestNode { value: 9 }));
+        let ids = target.import_subgraph(&source, &[b.clone(), c.clone()]).unwrap();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(target.get_node_ids().len(), 3);
+        assert!(ids.values().all(|id| *id != b && *id != c && *id != existing));
+
+        // b's input came from a, which was left behind
+        let new_b = target.get_node(&ids[&b]).unwrap();
+        assert!(new_b.read().inputs().is_empty());
+        assert_eq!(new_b.read().label(), Some("Middle"));
+        assert_eq!(target.get_node(&ids[&c]).unwrap().read().get_input("input"), Some(&ids[&b]));
+        assert_eq!(source.get_node(&c).unwrap().read().get_input("input"), Some(&b));
+
+        let missing = NodeId::new();
+        assert!(matches!(target.import_subgraph(&source, &[a.clone(), missing]), Err(NodeError::NodeNotFound(_))));
+        assert_eq!(target.get_node_ids().len(), 3);
+    }
+
+    /// Adds its inputs to its value, counting how often it computes.
+    #[derive(Debug, Clone)]
+    struct CountingNode {
+        value: i32,
+        hashed: bool,
+        computed: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl CountingNode {
+        fn new(value: i32, computed: &Arc<std::sync::atomic::AtomicUsize>) -> Box<Self> {
+            Box::new(Self { value, hashed: true, computed: computed.clone() })
+        }
+    }
+
+    impl NodeData for CountingNode {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn type_name(&self) -> &'static str {
+            "CountingNode"
+        }
+
+        fn clone_box(&self) -> Box<dyn NodeData> {
+            Box::new(self.clone())
+        }
+
+        fn content_hash(&self) -> Option<u64> {
+            self.hashed.then(|| self.value as u64)
+        }
+
+        fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+            self.computed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let sum = inputs.iter().filter_map(NodeValue::as_int).sum::<i64>();
+            Ok(NodeValue::Int(sum + i64::from(self.value)))
+        }
+    }
+
+    #[test]
+    fn test_dependencies_and_dependents_point_opposite_ways() {
+        let computed = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut graph = NodeGraph::new();
+        let [a, b, c, d] = [1, 10, 100, 1000].map(|value| graph.add_node(Node::new(CountingNode::new(value, &computed))));
+        // a feeds b and d, b feeds c
+        graph.connect(&a, &b, "input").unwrap();
+        graph.connect(&b, &c, "input").unwrap();
+        graph.connect(&a, &d, "input").unwrap();
+
+        assert_eq!(graph.get_node_dependencies(&b).unwrap(), vec![a.clone()]);
+        assert!(graph.get_node_dependencies(&a).unwrap().is_empty());
+        assert_eq!(graph.direct_dependents(&a).unwrap(), vec![b.clone(), d.clone()]);
+        assert!(graph.direct_dependents(&c).unwrap().is_empty());
+        assert_eq!(graph.all_downstream(&a).unwrap(), vec![b.clone(), c.clone(), d.clone()]);
+        assert_eq!(graph.all_upstream(&c).unwrap(), vec![a.clone(), b.clone()]);
+        assert!(graph.all_upstream(&a).unwrap().is_empty());
+        assert!(matches!(graph.all_downstream(&NodeId::new()), Err(NodeError::NodeNotFound(_))));
+
+        // Only results actually cached are reported stale
+        assert!(graph.mark_dirty(&b).is_empty());
+        assert_eq!(graph.evaluate_cached(&c).unwrap().as_int(), Some(111));
+        assert_eq!(graph.mark_dirty(&b), vec![b.clone(), c.clone()]);
+        assert!(graph.mark_dirty(&b).is_empty());
+    }
+
+    #[test]
+    fn test_diamond_computes_shared_input_once() {
+        let computed = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut graph = NodeGraph::new();
+        let source = graph.add_node(Node::new(CountingNode::new(1, &computed)));
+        let left = graph.add_node(Node::new(CountingNode::new(10, &computed)));
+        let right = graph.add_node(Node::new(CountingNode::new(100, &computed)));
+        let blend = graph.add_node(Node::new(CountingNode::new(0, &computed)));
+        graph.connect(&source, &left, "input").unwrap();
+        graph.connect(&source, &right, "input").unwrap();
+        graph.connect(&left, &blend, "a").unwrap();
+        graph.connect(&right, &blend, "b").unwrap();
+
+        let context = EvalContext::new().with(ReportRecorder::new());
+        let result = graph.evaluate_with_context(&blend, &context).unwrap();
+        assert_eq!(result.as_int(), Some(112));
+        assert_eq!(computed.load(std::sync::atomic::Ordering::SeqCst), 4);
+        let report = context.get::<ReportRecorder>().unwrap().take();
+        assert_eq!(report.timings.iter().filter(|timing| timing.node == source).count(), 1);
+
+        // Uncached evaluation computes everything again
+        graph.evaluate(&blend).unwrap();
+        assert_eq!(computed.load(std::sync::atomic::Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn test_cached_evaluation_follows_changes() {
+        let computed = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let count = || computed.load(std::sync::atomic::Ordering::SeqCst);
+        let mut graph = NodeGraph::new();
+        let source = graph.add_node(Node::new(CountingNode::new(1, &computed)));
+        let middle = graph.add_node(Node::new(CountingNode::new(10, &computed)));
+        let sink = graph.add_node(Node::new(CountingNode::new(100, &computed)));
+        graph.connect(&source, &middle, "input").unwrap();
+        graph.connect(&middle, &sink, "input").unwrap();
+
+        let first = graph.evaluate_cached(&sink).unwrap();
+        assert_eq!(first.as_int(), Some(111));
+        assert_eq!(count(), 3);
+        assert_eq!(graph.evaluate_cached(&sink).unwrap().as_int(), Some(111));
+        assert_eq!(count(), 3);
+
+        // A parameter change recomputes the node and what's downstream
+        let set_value = |graph: &NodeGraph, id: &NodeId, value: i32| {
+            let node = graph.get_node(id).unwrap();
+            node.write().data_mut().as_any_mut().downcast_mut::<CountingNode>().unwrap().value = value;
+        };
+        set_value(&graph, &middle, 20);
+        assert_eq!(graph.evaluate_cached(&sink).unwrap().as_int(), Some(121));
+        assert_eq!(count(), 5);
+
+        // So does a connection change
+        graph.disconnect(&source, &middle, "input").unwrap();
+        assert_eq!(graph.evaluate_cached(&sink).unwrap().as_int(), Some(120));
+        assert_eq!(count(), 7);
+
+        // Nodes without a hash wait for mark_dirty
+        {
+            let node = graph.get_node(&middle).unwrap();
+            let mut node = node.write();
+            let data = node.data_mut().as_any_mut().downcast_mut::<CountingNode>().unwrap();
+            data.hashed = false;
+            data.value = 30;
+        }
+        graph.mark_dirty(&middle);
+        assert_eq!(graph.evaluate_cached(&sink).unwrap().as_int(), Some(130));
+        assert_eq!(count(), 9);
+        set_value(&graph, &middle, 40);
+        assert_eq!(graph.evaluate_cached(&sink).unwrap().as_int(), Some(130));
+        graph.mark_dirty(&middle);
+        assert_eq!(graph.evaluate_cached(&sink).unwrap().as_int(), Some(140));
+        assert_eq!(count(), 11);
+        // and so do the nodes downstream of them, hashed or not
+        set_value(&graph, &sink, 200);
+        assert_eq!(graph.evaluate_cached(&sink).unwrap().as_int(), Some(140));
+        graph.mark_dirty(&sink);
+        assert_eq!(graph.evaluate_cached(&sink).unwrap().as_int(), Some(240));
+        assert_eq!(count(), 12);
+
+        // Copies don't share the cache
+        let copy = graph.clone();
+        copy.evaluate_cached(&sink).unwrap();
+        assert_eq!(count(), 14);
+        graph.clear_cache();
+        graph.evaluate_cached(&sink).unwrap();
+        assert_eq!(count(), 16);
+    }
+
+    #[test]
+    fn test_evaluate_all_handles_deep_chains() {
+        let computed = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut graph = NodeGraph::new();
+        let source = graph.add_node(Node::new(CountingNode::new(1, &computed)));
+        let mut last = source.clone();
+        for _ in 0..5000 {
+            let next = graph.add_node(Node::new(CountingNode::new(1, &computed)));
+            graph.connect(&last, &next, "input").unwrap();
+            last = next;
+        }
+        // A branch off the source shares its result
+        let branch = graph.add_node(Node::new(CountingNode::new(10, &computed)));
+        graph.connect(&source, &branch, "input").unwrap();
+
+        let results = graph.evaluate_all().unwrap();
+        assert_eq!(results.len(), 5002);
+        assert_eq!(results[&last].as_int(), Some(5001));
+        assert_eq!(results[&branch].as_int(), Some(11));
+        assert_eq!(computed.load(std::sync::atomic::Ordering::SeqCst), 5002);
+    }
+
+    #[test]
+    fn test_evaluate_all_names_the_failed_node() {
+        let mut graph = NodeGraph::new();
+        let a = graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+        let b = graph.add_node(Node::new(Box::new(TestNode { value: 2 })));
+        graph.connect(&a, &b, "input").unwrap();
+
+        // TestNode rejects inputs, so `b` fails after `a` computed
+        let context = EvalContext::new().with(ReportRecorder::new());
+        match graph.evaluate_all_with_context(&context) {
+            Err(NodeError::EvaluationFailed { node_id, node_type, input_name, source }) => {
+                assert_eq!(node_id, b);
+                assert_eq!(node_type, "TestNode");
+                assert_eq!(input_name, None);
+                assert!(matches!(*source, NodeError::InvalidInputType { .. }));
+            }
+            other => panic!("expected b to fail, got {:?}", other.map(|results| results.len())),
+        }
+        let report = context.get::<ReportRecorder>().unwrap().take();
+        assert_eq!(report.timings.len(), 1);
+        assert_eq!(report.failures[0].node, b);
+
+        graph.get_node(&b).unwrap().write().set_bypassed(true);
+        assert_eq!(graph.evaluate_all().unwrap()[&b].as_int(), Some(1));
+    }
+
+    /// Sleeps, then outputs how many inputs it had plus one.
+    #[derive(Debug, Clone)]
+    struct SlowNode(std::time::Duration);
+
+    impl NodeData for SlowNode {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn type_name(&self) -> &'static str {
+            "SlowNode"
+        }
+
+        fn clone_box(&self) -> Box<dyn NodeData> {
+            Box::new(self.clone())
+        }
+
+        fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+            std::thread::sleep(self.0);
+            let sum = inputs.iter().filter_map(NodeValue::as_int).sum::<i64>();
+            Ok(NodeValue::Int(sum + 1))
+        }
+    }
+
+    /// Sums its inputs plus one like [`SlowNode`], tracking how many
+    /// `OverlapNode`s sharing its counters are computing at once.
+    #[derive(Debug, Clone)]
+    struct OverlapNode {
+        running: Arc<std::sync::atomic::AtomicUsize>,
+        most: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl NodeData for OverlapNode {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn type_name(&self) -> &'static str {
+            "OverlapNode"
+        }
+
+        fn clone_box(&self) -> Box<dyn NodeData> {
+            Box::new(self.clone())
+        }
+
+        fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+            use std::sync::atomic::Ordering;
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(running, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            let sum = inputs.iter().filter_map(NodeValue::as_int).sum::<i64>();
+            Ok(NodeValue::Int(sum + 1))
+        }
+    }
+
+    #[test]
+    fn test_parallel_evaluation_runs_branches_concurrently() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let mut graph = NodeGraph::new();
+        let source = graph.add_node(Node::new(Box::new(SlowNode(std::time::Duration::ZERO))));
+        let merge = graph.add_node(Node::new(Box::new(SlowNode(std::time::Duration::ZERO))));
+        for chain in 0..8 {
+            let mut last = source.clone();
+            for _ in 0..3 {
+                let next = graph.add_node(Node::new(Box::new(OverlapNode { running: running.clone(), most: most.clone() })));
+                graph.connect(&last, &next, "input").unwrap();
+                last = next;
+            }
+            graph.connect(&last, &merge, &format!("chain{}", chain)).unwrap();
+        }
+        // Not upstream of `merge`, so never computed
+        let unrelated = graph.add_node(Node::new(Box::new(SlowNode(std::time::Duration::from_secs(10)))));
+        graph.connect(&source, &unrelated, "input").unwrap();
+
+        let sequential = graph.evaluate(&merge).unwrap();
+        assert_eq!(sequential.as_int(), Some(33));
+        assert_eq!(most.swap(0, Ordering::SeqCst), 1);
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(8).build().unwrap();
+        let context = EvalContext::new().with(ReportRecorder::new());
+        let parallel = pool.install(|| graph.evaluate_parallel_with_context(&merge, &context)).unwrap();
+
+        assert_eq!(parallel.as_int(), Some(33));
+        assert_eq!(context.get::<ReportRecorder>().unwrap().take().timings.len(), 26);
+        assert!(most.load(Ordering::SeqCst) > 1, "no two branches computed at once");
+        assert_eq!(running.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_profile_finds_the_slow_node() {
+        let mut graph = NodeGraph::new();
+        let mut last = graph.add_node(Node::new(Box::new(SlowNode(std::time::Duration::ZERO))));
+        let mut slow = None;
+        for step in 0..6 {
+            let delay = if step == 3 { std::time::Duration::from_millis(40) } else { std::time::Duration::ZERO };
+            let next = graph.add_node(Node::new(Box::new(SlowNode(delay))));
+            graph.connect(&last, &next, "input").unwrap();
+            if step == 3 {
+                slow = Some(next.clone());
+            }
+            last = next;
+        }
+        let slow = slow.unwrap();
+
+        let (result, profile) = graph.evaluate_profiled(&last);
+        assert_eq!(result.unwrap().as_int(), Some(7));
+        let timings = profile.timings();
+        assert_eq!(timings.len(), 7);
+        assert_eq!(timings[0].node, slow);
+        assert!(timings[0].duration >= std::time::Duration::from_millis(40));
+        assert!(profile.total() >= timings[0].duration);
+        assert_eq!(profile.get(&slow).unwrap().input_sizes, [std::mem::size_of::<NodeValue>()]);
+        assert_eq!(profile.get(&slow).unwrap().output_type, "Int");
+
+        let report = profile.report();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert!(lines[1].contains(&slow.to_string()) && lines[1].contains("SlowNode"), "{}", report);
+        assert!(lines[8].starts_with("7 nodes in "), "{}", report);
+    }
+
+    #[test]
+    fn test_parallel_evaluation_names_the_failed_node() {
+        let mut graph = NodeGraph::new();
+        let a = graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+        let b = graph.add_node(Node::new(Box::new(TestNode { value: 2 })));
+        graph.connect(&a, &b, "input").unwrap();
+        assert_eq!(graph.evaluate_parallel(&a).unwrap().as_int(), Some(1));
+        assert!(matches!(graph.evaluate_parallel(&b), Err(NodeError::EvaluationFailed { node_id, .. }) if node_id == b));
+        assert!(matches!(graph.evaluate_parallel(&NodeId::new()), Err(NodeError::NodeNotFound(_))));
+    }
+
+    #[test]
+    fn test_content_hash_requires_every_node() {
+        #[derive(Debug, Clone)]
+        struct Opaque;
+
+        impl NodeData for Opaque {
+            fn as_any(&self) -> &dyn Any {
+                self
+            }
+
+            fn as_any_mut(&mut self) -> &mut dyn Any {
+                self
+            }
+
+            fn type_name(&self) -> &'static str {
+                "Opaque"
+            }
+
+            fn clone_box(&self) -> Box<dyn NodeData> {
+                Box::new(self.clone())
+            }
+
+            fn compute(&self, _inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+                Ok(NodeValue::Null)
+            }
+        }
+
+        let mut graph = NodeGraph::new();
+        graph.add_node(Node::new(Box::new(TestNode { value: 1 })));
+        assert!(graph.content_hash().is_some());
+        graph.add_node(Node::new(Box::new(Opaque)));
+        assert!(graph.content_hash().is_none());
+    }
 }
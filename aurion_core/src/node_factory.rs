@@ -1,18 +1,38 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
 use std::sync::Arc;
 use parking_lot::RwLock;
 use anyhow::Result;
 use serde_json::Value;
-use crate::{Node, NodeData, NodeError};
-use tracing::{debug, error, info, instrument, warn};
+use crate::plugin::{self, NodeOrigin, OpenedPlugin, Plugin, PluginError, PluginHandle};
+use crate::{Node, NodeData, NodeError, NodeId, ParameterSchema};
+use tracing::{debug, error, instrument, warn};
 
 pub trait NodeFactory: Send + Sync {
     fn create(&self, parameters: &Value) -> Result<Box<dyn NodeData>, NodeError>;
     fn type_name(&self) -> &'static str;
+
+    /// Groups the node type in menus, e.g. "Filter". Uncategorized types are
+    /// listed on their own.
+    fn category(&self) -> Option<&'static str> {
+        None
+    }
     
+    /// The parameters [`NodeFactory::create`] accepts. Factories without
+    /// one take whatever `create` makes of its parameters.
+    fn parameter_schema(&self) -> ParameterSchema {
+        ParameterSchema::new()
+    }
+
+    /// Checks parameters against [`NodeFactory::parameter_schema`], if the
+    /// factory has one.
     fn validate_parameters(&self, parameters: &Value) -> Result<(), NodeError> {
         debug!("Validating parameters for node type: {}", self.type_name());
-        Ok(()) // Default implementation - no validation
+        let schema = self.parameter_schema();
+        if schema.is_empty() {
+            return Ok(());
+        }
+        schema.validate(self.type_name(), parameters)
     }
 
     fn get_debug_info(&self) -> String {
@@ -23,6 +43,12 @@ pub trait NodeFactory: Send + Sync {
 #[derive(Default)]
 pub struct NodeRegistry {
     factories: HashMap<&'static str, Arc<dyn NodeFactory>>,
+    // The plugins types were loaded from. After `factories`, so factories
+    // are dropped while their plugin's code is still loaded
+    plugins: HashMap<&'static str, Arc<Plugin>>,
+    // Old type names and what they now create
+    aliases: HashMap<String, &'static str>,
+    deprecated: HashSet<String>,
     #[allow(dead_code)]
     debug_mode: bool,
 }
@@ -31,6 +57,9 @@ impl NodeRegistry {
     pub fn new() -> Self {
         Self {
             factories: HashMap::new(),
+            plugins: HashMap::new(),
+            aliases: HashMap::new(),
+            deprecated: HashSet::new(),
             debug_mode: false,
         }
     }
@@ -38,6 +67,9 @@ impl NodeRegistry {
     pub fn with_debug(debug: bool) -> Self {
         Self {
             factories: HashMap::new(),
+            plugins: HashMap::new(),
+            aliases: HashMap::new(),
+            deprecated: HashSet::new(),
             debug_mode: debug,
         }
     }
@@ -47,18 +79,151 @@ impl NodeRegistry {
         let type_name = factory.type_name();
         debug!("Registering factory for node type: {}", type_name);
         self.factories.insert(type_name, Arc::new(factory));
+        self.plugins.remove(type_name);
+    }
+
+    /// Lets `old_name`, e.g. a type's name before it was renamed, create
+    /// nodes through `new_name`, which may be an alias itself. The nodes are
+    /// of the new type, so graphs saved afterwards use the new name. Aliases
+    /// are deprecated, see [`NodeRegistry::is_deprecated`], and a type
+    /// registered under `old_name` later takes its place.
+    ///
+    /// Fails if `old_name` is registered or the alias would lead back to it.
+    pub fn register_alias(&mut self, old_name: &str, new_name: &'static str) -> Result<(), NodeError> {
+        if self.factories.contains_key(old_name) {
+            return Err(NodeError::ValidationError(format!("Can't alias {}: a node type is registered under it", old_name)));
+        }
+        let mut name = new_name;
+        loop {
+            if name == old_name {
+                return Err(NodeError::ValidationError(format!("Aliasing {} to {} would make a loop", old_name, new_name)));
+            }
+            if self.factories.contains_key(name) {
+                break;
+            }
+            match self.aliases.get(name) {
+                Some(next) => name = next,
+                None => break,
+            }
+        }
+        debug!("Registering alias {} for node type: {}", old_name, new_name);
+        self.aliases.insert(old_name.to_string(), new_name);
+        Ok(())
+    }
+
+    /// Marks `type_name` as on its way out: it still creates nodes, with a
+    /// warning, but menus no longer list it.
+    pub fn deprecate(&mut self, type_name: &str) {
+        self.deprecated.insert(type_name.to_string());
+    }
+
+    /// Whether creating nodes through `type_name` warns, because it was
+    /// deprecated or is an alias.
+    pub fn is_deprecated(&self, type_name: &str) -> bool {
+        self.deprecated.contains(type_name) || (!self.factories.contains_key(type_name) && self.aliases.contains_key(type_name))
+    }
+
+    /// The type `type_name` creates, following aliases, or `None` if it
+    /// doesn't lead to a registered one.
+    pub fn canonical_name(&self, type_name: &str) -> Option<&'static str> {
+        let mut name = type_name;
+        // Aliases never make loops, so this ends
+        loop {
+            if let Some((registered, _)) = self.factories.get_key_value(name) {
+                return Some(*registered);
+            }
+            name = self.aliases.get(name)?;
+        }
+    }
+
+    fn factory(&self, type_name: &str) -> Option<&Arc<dyn NodeFactory>> {
+        self.factories.get(self.canonical_name(type_name)?)
+    }
+
+    /// Loads the plugin at `path` and registers its nodes; see
+    /// [`crate::plugin`]. Fails, registering none of them, when the plugin
+    /// can't be loaded, was built for another
+    /// [`crate::plugin::PLUGIN_ABI_VERSION`], registers nothing, or
+    /// registers a type that's already registered.
+    ///
+    /// # Safety
+    ///
+    /// Loading runs the library's initialization and registration code, so
+    /// `path` must be a plugin built with the compiler and aurion_core of
+    /// this application.
+    pub unsafe fn load_plugin(&mut self, path: &Path) -> Result<PluginHandle, PluginError> {
+        debug!("Loading plugin: {}", path.display());
+        self.install_plugin(plugin::open(path)?)
+    }
+
+    pub(crate) fn install_plugin(&mut self, mut opened: OpenedPlugin) -> Result<PluginHandle, PluginError> {
+        if let Some(type_name) = opened.registry.factories.keys().find(|type_name| self.factories.contains_key(*type_name)) {
+            return Err(PluginError::Conflict { path: opened.plugin.path.clone(), type_name: type_name.to_string() });
+        }
+        let mut node_types = Vec::new();
+        for (type_name, factory) in std::mem::take(&mut opened.registry.factories) {
+            debug!("Registering plugin factory for node type: {}", type_name);
+            self.factories.insert(type_name, factory);
+            self.plugins.insert(type_name, opened.plugin.clone());
+            node_types.push(type_name);
+        }
+        node_types.sort_unstable();
+        Ok(PluginHandle { plugin: opened.plugin.clone(), node_types })
+    }
+
+    /// Removes the types `plugin` registered here, unloading the plugin once
+    /// no registry holds any of them.
+    ///
+    /// # Safety
+    ///
+    /// The plugin's code and data go with it, so nothing it provided may be
+    /// used afterwards: no node created by its factories, nor any type name
+    /// [`NodeRegistry::get_available_node_types`] returned for them.
+    pub unsafe fn unload_plugin(&mut self, plugin: PluginHandle) {
+        debug!("Unloading plugin: {}", plugin.path().display());
+        for type_name in &plugin.node_types {
+            if self.plugins.get(type_name).map_or(false, |loaded| Arc::ptr_eq(loaded, &plugin.plugin)) {
+                self.factories.remove(type_name);
+                self.plugins.remove(type_name);
+            }
+        }
+    }
+
+    /// Whether `type_name` is built in or came from a plugin, or `None` if
+    /// it isn't registered.
+    pub fn node_origin(&self, type_name: &str) -> Option<NodeOrigin> {
+        if !self.factories.contains_key(type_name) {
+            return None;
+        }
+        Some(match self.plugins.get(type_name) {
+            Some(plugin) => NodeOrigin::Plugin(plugin.path.clone()),
+            None => NodeOrigin::BuiltIn,
+        })
     }
 
     #[instrument(skip(self, parameters))]
     pub fn create_node(&self, type_name: &str, parameters: &Value) -> Result<Node, NodeError> {
+        self.create_node_with_id(type_name, parameters, NodeId::new())
+    }
+
+    /// Like [`NodeRegistry::create_node`], with a known id, e.g. to recreate
+    /// a node on redo.
+    #[instrument(skip(self, parameters))]
+    pub fn create_node_with_id(&self, type_name: &str, parameters: &Value, id: NodeId) -> Result<Node, NodeError> {
         debug!("Creating node of type: {}", type_name);
         
-        let factory = self.factories.get(type_name)
+        let factory = self.factory(type_name)
             .ok_or_else(|| {
                 let available_types = self.get_available_node_types().join(", ");
                 error!("No factory registered for node type: {}. Available types: {}", type_name, available_types);
                 NodeError::ValidationError(format!("No factory registered for node type: {}. Available types: {}", type_name, available_types))
             })?;
+        if self.is_deprecated(type_name) {
+            match self.canonical_name(type_name) {
+                Some(canonical) if canonical != type_name => warn!("Node type {} is deprecated, creating {} instead", type_name, canonical),
+                _ => warn!("Node type {} is deprecated", type_name),
+            }
+        }
         
         // Validate parameters before creating the node
         factory.validate_parameters(parameters).map_err(|e| {
@@ -72,7 +237,7 @@ impl NodeRegistry {
             e
         })?;
         
-        let mut node = Node::new(node_data);
+        let mut node = Node::new_with_id(node_data, id);
         
         // Add debug information
         if self.debug_mode {
@@ -80,22 +245,61 @@ impl NodeRegistry {
             node.add_debug_info("parameters", serde_json::to_string(parameters).unwrap_or_default());
         }
         
-        // Validate the created node
-        node.validate().map_err(|e| {
-            error!("Node validation failed: {}", e);
-            e
-        })?;
-        
+        // Not validated: a new node has none of the inputs it requires yet
         debug!("Node created successfully");
         Ok(node)
     }
 
+    /// Every registered type, built in or from a plugin; see
+    /// [`NodeRegistry::node_origin`] and [`NodeRegistry::get_builtin_node_types`].
     pub fn get_available_node_types(&self) -> Vec<&'static str> {
         self.factories.keys().copied().collect()
     }
 
+    /// The registered types that didn't come from a plugin.
+    pub fn get_builtin_node_types(&self) -> Vec<&'static str> {
+        self.factories.keys().filter(|type_name| !self.plugins.contains_key(*type_name)).copied().collect()
+    }
+
+    /// Type names by category, both sorted. Types without a category are left
+    /// out, see [`NodeRegistry::get_uncategorized_types`], as are deprecated
+    /// ones.
+    pub fn get_types_by_category(&self) -> BTreeMap<&'static str, Vec<&'static str>> {
+        let mut categories: BTreeMap<&'static str, Vec<&'static str>> = BTreeMap::new();
+        for (type_name, factory) in &self.factories {
+            if self.deprecated.contains(*type_name) {
+                continue;
+            }
+            if let Some(category) = factory.category() {
+                categories.entry(category).or_default().push(*type_name);
+            }
+        }
+        for types in categories.values_mut() {
+            types.sort_unstable();
+        }
+        categories
+    }
+
+    /// Sorted type names of the factories without a category, other than
+    /// deprecated ones.
+    pub fn get_uncategorized_types(&self) -> Vec<&'static str> {
+        let mut types: Vec<_> = self.factories.iter()
+            .filter(|(type_name, factory)| factory.category().is_none() && !self.deprecated.contains(**type_name))
+            .map(|(type_name, _)| *type_name)
+            .collect();
+        types.sort_unstable();
+        types
+    }
+
+    /// The parameters nodes of `type_name` accept, see
+    /// [`NodeFactory::parameter_schema`].
+    pub fn get_schema(&self, type_name: &str) -> Option<ParameterSchema> {
+        self.factory(type_name).map(|factory| factory.parameter_schema())
+    }
+
+    /// Whether `type_name` creates nodes, directly or through an alias.
     pub fn has_factory(&self, type_name: &str) -> bool {
-        self.factories.contains_key(type_name)
+        self.canonical_name(type_name).is_some()
     }
 
     // Debug helpers
@@ -106,7 +310,10 @@ impl NodeRegistry {
         info.push_str("\nRegistered Node Types:\n");
         
         for (type_name, factory) in &self.factories {
-            info.push_str(&format!("- {}\n", type_name));
+            match self.plugins.get(type_name) {
+                Some(plugin) => info.push_str(&format!("- {} (plugin {})\n", type_name, plugin.path.display())),
+                None => info.push_str(&format!("- {}\n", type_name)),
+            }
             info.push_str(&format!("  Debug Info: {}\n", factory.get_debug_info()));
         }
         
@@ -119,17 +326,34 @@ lazy_static::lazy_static! {
     pub static ref NODE_REGISTRY: Arc<RwLock<NodeRegistry>> = Arc::new(RwLock::new(NodeRegistry::new()));
 }
 
-#[instrument]
+#[instrument(skip(factory))]
 pub fn register_node_factory<F: NodeFactory + 'static>(factory: F) {
     debug!("Registering global factory for node type: {}", factory.type_name());
     NODE_REGISTRY.write().register(factory);
 }
 
+/// Loads a plugin into the global registry, see [`NodeRegistry::load_plugin`].
+/// The plugin registers its nodes before the registry is locked, so one
+/// that fails leaves the registry untouched.
+///
+/// # Safety
+///
+/// As for [`NodeRegistry::load_plugin`].
+pub unsafe fn load_plugin(path: &Path) -> Result<PluginHandle, PluginError> {
+    let opened = plugin::open(path)?;
+    NODE_REGISTRY.write().install_plugin(opened)
+}
+
 #[instrument(skip(parameters))]
 pub fn create_node(type_name: &str, parameters: &Value) -> Result<Node, NodeError> {
     NODE_REGISTRY.read().create_node(type_name, parameters)
 }
 
+#[instrument(skip(parameters))]
+pub fn create_node_with_id(type_name: &str, parameters: &Value, id: NodeId) -> Result<Node, NodeError> {
+    NODE_REGISTRY.read().create_node_with_id(type_name, parameters, id)
+}
+
 // Add tests for debugging functionality
 #[cfg(test)]
 mod tests {
@@ -182,4 +406,65 @@ mod tests {
         assert!(info.contains("test"));
         assert!(info.contains("Total registered factories: 1"));
     }
+
+    struct CategorizedFactory(&'static str, &'static str);
+
+    impl NodeFactory for CategorizedFactory {
+        fn create(&self, _parameters: &Value) -> Result<Box<dyn NodeData>, NodeError> {
+            Err(NodeError::ValidationError("Not creatable".to_string()))
+        }
+
+        fn type_name(&self) -> &'static str {
+            self.0
+        }
+
+        fn category(&self) -> Option<&'static str> {
+            Some(self.1)
+        }
+    }
+
+    #[test]
+    fn test_types_by_category() {
+        let mut registry = NodeRegistry::new();
+        registry.register(CategorizedFactory("sharpen", "Filter"));
+        registry.register(CategorizedFactory("blur", "Filter"));
+        registry.register(CategorizedFactory("image", "Input"));
+        registry.register(TestFactory);
+
+        let categories = registry.get_types_by_category();
+        assert_eq!(categories.keys().copied().collect::<Vec<_>>(), vec!["Filter", "Input"]);
+        assert_eq!(categories["Filter"], vec!["blur", "sharpen"]);
+        assert_eq!(registry.get_uncategorized_types(), vec!["test"]);
+    }
+
+    #[test]
+    fn test_aliases_follow_renames() {
+        let mut registry = NodeRegistry::new();
+        registry.register(CategorizedFactory("BlurGaussian", "Filter"));
+        registry.register(CategorizedFactory("sharpen", "Filter"));
+        registry.register_alias("GaussianBlurV2", "BlurGaussian").unwrap();
+        registry.register_alias("GaussianBlur", "GaussianBlurV2").unwrap();
+
+        assert_eq!(registry.canonical_name("GaussianBlur"), Some("BlurGaussian"));
+        assert!(registry.has_factory("GaussianBlur"));
+        assert!(registry.is_deprecated("GaussianBlur"));
+        assert!(!registry.is_deprecated("BlurGaussian"));
+        assert!(matches!(registry.create_node("GaussianBlur", &json!({})), Err(NodeError::ValidationError(message)) if message == "Not creatable"));
+        assert_eq!(registry.canonical_name("Missing"), None);
+
+        // Registered names can't be aliased, and aliases can't loop
+        assert!(registry.register_alias("sharpen", "BlurGaussian").is_err());
+        assert!(registry.register_alias("GaussianBlurV2", "GaussianBlur").is_err());
+        registry.register_alias("Dangling", "NotYetRegistered").unwrap();
+        assert!(!registry.has_factory("Dangling"));
+
+        // A real registration takes the alias's place
+        registry.register(CategorizedFactory("GaussianBlurV2", "Legacy"));
+        assert_eq!(registry.canonical_name("GaussianBlur"), Some("GaussianBlurV2"));
+        assert!(!registry.is_deprecated("GaussianBlurV2"));
+
+        registry.deprecate("sharpen");
+        assert!(registry.is_deprecated("sharpen"));
+        assert_eq!(registry.get_types_by_category()["Filter"], vec!["BlurGaussian"]);
+    }
 } 
\ No newline at end of file
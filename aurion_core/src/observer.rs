@@ -0,0 +1,76 @@
+//! Notifications of changes to a [`NodeGraph`], so views of it can follow
+//! edits rather than rebuilding from the whole graph.
+
+use std::sync::Arc;
+use parking_lot::Mutex;
+use crate::NodeId;
+
+/// Told about changes to a graph it's subscribed to, see
+/// [`crate::NodeGraph::subscribe`]. Each call comes after the change is made
+/// and only when it succeeds. Edits made through a node's handle, e.g.
+/// [`crate::Node::set_label`], aren't seen by the graph and go unreported.
+pub trait GraphObserver: Send + Sync {
+    fn node_added(&self, _node: &NodeId) {}
+
+    /// Called after the node's connections are reported removed.
+    fn node_removed(&self, _node: &NodeId) {}
+
+    /// `output` is the output of `from` read, `None` for its primary one.
+    fn edge_added(&self, _from: &NodeId, _output: Option<&str>, _to: &NodeId, _input: &str) {}
+
+    fn edge_removed(&self, _from: &NodeId, _to: &NodeId, _input: &str) {}
+
+    /// The node's parameters changed, see [`crate::NodeGraph::set_node_parameter`].
+    fn node_data_changed(&self, _node: &NodeId) {}
+}
+
+/// Identifies a subscription for [`crate::NodeGraph::unsubscribe`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type Subscription = (SubscriptionId, Arc<dyn GraphObserver>);
+
+/// A graph's observers. Copies of a graph start with none.
+#[derive(Default)]
+pub(crate) struct Observers {
+    // With the last id given out
+    subscribed: Mutex<(u64, Vec<Subscription>)>,
+}
+
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Observers {
+    pub(crate) fn subscribe(&self, observer: Arc<dyn GraphObserver>) -> SubscriptionId {
+        let mut subscribed = self.subscribed.lock();
+        subscribed.0 += 1;
+        let id = SubscriptionId(subscribed.0);
+        subscribed.1.push((id, observer));
+        id
+    }
+
+    pub(crate) fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut subscribed = self.subscribed.lock();
+        let before = subscribed.1.len();
+        subscribed.1.retain(|(subscription, _)| *subscription != id);
+        subscribed.1.len() != before
+    }
+
+    /// Calls `notify` with each observer in the order they subscribed.
+    /// Observers may subscribe or unsubscribe while being notified.
+    pub(crate) fn notify(&self, notify: impl Fn(&dyn GraphObserver)) {
+        let observers: Vec<_> = {
+            let subscribed = self.subscribed.lock();
+            if subscribed.1.is_empty() {
+                return;
+            }
+            subscribed.1.iter().map(|(_, observer)| observer.clone()).collect()
+        };
+        for observer in observers {
+            notify(observer.as_ref());
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Node factories loaded from dynamic libraries, so custom nodes can ship
+//! without rebuilding the application.
+//!
+//! A plugin is a `cdylib` exporting its [`PLUGIN_ABI_VERSION`] and an
+//! `aurion_register_nodes(registry: &mut NodeRegistry)` entry point, which
+//! [`export_plugin!`](crate::export_plugin) defines:
+//!
+//! ```ignore
+//! fn register(registry: &mut NodeRegistry) {
+//!     registry.register(PosterizeFactory);
+//! }
+//!
+//! aurion_core::export_plugin!(register);
+//! ```
+//!
+//! The entry point hands Rust types across the library boundary, so plugins
+//! must be built with the same compiler and aurion_core as the application.
+//! The ABI version catches plugins built against an incompatible aurion_core,
+//! not ones built with another compiler.
+//!
+//! Plugins register into a registry of their own first, which is merged
+//! only once everything they registered is known to fit, so a plugin that
+//! fails to load leaves the registry it was loaded into as it was.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use libloading::Library;
+use thiserror::Error;
+use crate::NodeRegistry;
+
+/// Bumped whenever a change to aurion_core breaks compiled plugins.
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+const VERSION_SYMBOL: &[u8] = b"AURION_PLUGIN_ABI_VERSION";
+const REGISTER_SYMBOL: &[u8] = b"aurion_register_nodes";
+
+type RegisterNodes = unsafe extern "C" fn(&mut NodeRegistry);
+
+#[derive(Debug, Error)]
+pub enum PluginError {
+    /// The library couldn't be opened or lacks a plugin's exports.
+    #[error("Failed to load plugin {}: {source}", .path.display())]
+    Load {
+        path: PathBuf,
+        #[source]
+        source: libloading::Error,
+    },
+    #[error("Plugin {} was built for plugin ABI {found}, expected {expected}", .path.display())]
+    AbiMismatch {
+        path: PathBuf,
+        expected: u32,
+        found: u32,
+    },
+    /// Also what a plugin whose registration panicked fails with.
+    #[error("Plugin {} registered no nodes", .path.display())]
+    Empty { path: PathBuf },
+    #[error("Plugin {} registers {type_name}, which is already registered", .path.display())]
+    Conflict { path: PathBuf, type_name: String },
+}
+
+/// Where a registered node type comes from, see [`NodeRegistry::node_origin`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeOrigin {
+    /// Registered by the application, e.g. the standard nodes.
+    BuiltIn,
+    /// Loaded from the plugin at this path.
+    Plugin(PathBuf),
+}
+
+// An open plugin library. Registries hold one for each type it registered,
+// so it stays loaded while any of them remains.
+#[derive(Debug)]
+pub(crate) struct Plugin {
+    pub(crate) path: PathBuf,
+    _library: Library,
+}
+
+/// A plugin loaded by [`NodeRegistry::load_plugin`]. Dropping it leaves the
+/// plugin loaded; [`NodeRegistry::unload_plugin`] removes it.
+#[derive(Debug)]
+pub struct PluginHandle {
+    pub(crate) plugin: Arc<Plugin>,
+    pub(crate) node_types: Vec<&'static str>,
+}
+
+impl PluginHandle {
+    pub fn path(&self) -> &Path {
+        &self.plugin.path
+    }
+
+    /// The types the plugin registered, sorted.
+    pub fn node_types(&self) -> &[&str] {
+        &self.node_types
+    }
+}
+
+/// A plugin's library and the registry it registered into, ready for
+/// [`NodeRegistry::install_plugin`].
+pub(crate) struct OpenedPlugin {
+    // Declared first so the plugin's factories are dropped while its code is
+    // still loaded
+    pub(crate) registry: NodeRegistry,
+    pub(crate) plugin: Arc<Plugin>,
+}
+
+/// Loads the library at `path` and runs its registration.
+///
+/// # Safety
+///
+/// See [`NodeRegistry::load_plugin`].
+pub(crate) unsafe fn open(path: &Path) -> Result<OpenedPlugin, PluginError> {
+    let load = |source| PluginError::Load { path: path.to_path_buf(), source };
+    let library = Library::new(path).map_err(load)?;
+    let found = **library.get::<*const u32>(VERSION_SYMBOL).map_err(load)?;
+    if found != PLUGIN_ABI_VERSION {
+        return Err(PluginError::AbiMismatch { path: path.to_path_buf(), expected: PLUGIN_ABI_VERSION, found });
+    }
+    let mut registry = NodeRegistry::new();
+    {
+        let register = library.get::<RegisterNodes>(REGISTER_SYMBOL).map_err(load)?;
+        register(&mut registry);
+    }
+    let plugin = Arc::new(Plugin { path: path.to_path_buf(), _library: library });
+    if registry.get_available_node_types().is_empty() {
+        return Err(PluginError::Empty { path: path.to_path_buf() });
+    }
+    Ok(OpenedPlugin { registry, plugin })
+}
+
+/// Defines a plugin's exports around `register`, a
+/// `fn(&mut NodeRegistry)` registering its factories. A panic in `register`
+/// fails the load rather than unwinding into the application.
+#[macro_export]
+macro_rules! export_plugin {
+    ($register:path) => {
+        #[no_mangle]
+        pub static AURION_PLUGIN_ABI_VERSION: u32 = $crate::plugin::PLUGIN_ABI_VERSION;
+
+        #[no_mangle]
+        #[allow(improper_ctypes_definitions)]
+        pub extern "C" fn aurion_register_nodes(registry: &mut $crate::NodeRegistry) {
+            let register = std::panic::AssertUnwindSafe(|| $register(registry));
+            if std::panic::catch_unwind(register).is_err() {
+                // Dropping the factories `register` got to, so the load fails
+                *registry = $crate::NodeRegistry::new();
+            }
+        }
+    };
+}
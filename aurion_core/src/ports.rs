@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+/// An input or output a node declares, so editors can show its slots and
+/// [`crate::NodeGraph::connect`] can reject names it doesn't read.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PortSpec {
+    pub name: String,
+    /// What flows through the port, e.g. [`PortSpec::IMAGE`].
+    pub type_id: String,
+    /// For inputs, whether the node fails without something connected here.
+    pub required: bool,
+}
+
+impl PortSpec {
+    /// A `DynamicImage`.
+    pub const IMAGE: &'static str = "image";
+
+    /// The output of nodes declaring none, which every node has unless its
+    /// first declared output is named otherwise.
+    pub const PRIMARY_OUTPUT: &'static str = "output";
+
+    pub fn required(name: &str, type_id: &str) -> Self {
+        Self { name: name.to_string(), type_id: type_id.to_string(), required: true }
+    }
+
+    pub fn optional(name: &str, type_id: &str) -> Self {
+        Self { name: name.to_string(), type_id: type_id.to_string(), required: false }
+    }
+
+    /// The single required image input most filters read.
+    pub fn image_input() -> Self {
+        Self::required("input", Self::IMAGE)
+    }
+
+    /// The image output every standard node produces.
+    pub fn image_output() -> Self {
+        Self::required(Self::PRIMARY_OUTPUT, Self::IMAGE)
+    }
+}
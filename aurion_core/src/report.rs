@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use parking_lot::Mutex;
+use crate::{NodeGraph, NodeId, NodeValue};
+
+/// How long one node's own computation took, not counting its inputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeTiming {
+    pub node: NodeId,
+    pub type_name: &'static str,
+    pub duration: Duration,
+    /// Approximate bytes of each input, in the order the node received them
+    /// (see [`NodeValue::estimated_size`]).
+    pub input_sizes: Vec<usize>,
+    /// Variant of the node's primary output, e.g. "Image".
+    pub output_type: &'static str,
+}
+
+/// A node whose computation failed, and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeFailure {
+    pub node: NodeId,
+    pub type_name: &'static str,
+    pub message: String,
+}
+
+/// What happened while evaluating: each computed node's time and the nodes
+/// that failed, in the order they ran.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EvaluationReport {
+    pub timings: Vec<NodeTiming>,
+    pub failures: Vec<NodeFailure>,
+    /// Wall time of the whole evaluation, set by whoever started it.
+    pub total: Duration,
+}
+
+impl EvaluationReport {
+    /// The node that took longest to compute.
+    pub fn slowest(&self) -> Option<&NodeTiming> {
+        self.timings.iter().max_by_key(|timing| timing.duration)
+    }
+}
+
+/// Per-node timings of one evaluation, keyed by node, from
+/// [`NodeGraph::evaluate_profiled`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EvaluationProfile {
+    nodes: HashMap<NodeId, NodeTiming>,
+    failures: Vec<NodeFailure>,
+    total: Duration,
+}
+
+impl EvaluationProfile {
+    /// The node's timing, if it was computed.
+    pub fn get(&self, node: &NodeId) -> Option<&NodeTiming> {
+        self.nodes.get(node)
+    }
+
+    /// Wall time of the whole evaluation.
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    pub fn failures(&self) -> &[NodeFailure] {
+        &self.failures
+    }
+
+    /// Every computed node's timing, slowest first.
+    pub fn timings(&self) -> Vec<NodeTiming> {
+        let mut timings: Vec<_> = self.nodes.values().cloned().collect();
+        timings.sort_by(|a, b| b.duration.cmp(&a.duration).then_with(|| a.node.0.cmp(&b.node.0)));
+        timings
+    }
+
+    /// The timings as a table, slowest first, with each node's share of the
+    /// total and the bytes it read.
+    pub fn report(&self) -> String {
+        let mut table = format!("{:>10} {:>6}  {:<36}  {:<20} {:>10}  {}\n", "ms", "%", "node", "type", "inputs", "output");
+        let total = self.total.as_secs_f64();
+        for timing in self.timings() {
+            let share = if total > 0.0 { timing.duration.as_secs_f64() / total * 100.0 } else { 0.0 };
+            table.push_str(&format!(
+                "{:>10.3} {:>6.1}  {:<36}  {:<20} {:>10}  {}\n",
+                timing.duration.as_secs_f64() * 1000.0,
+                share,
+                timing.node.to_string(),
+                timing.type_name,
+                format_bytes(timing.input_sizes.iter().sum()),
+                timing.output_type
+            ));
+        }
+        table.push_str(&format!("{} nodes in {:.3} ms", self.nodes.len(), total * 1000.0));
+        if !self.failures.is_empty() {
+            table.push_str(&format!(", {} failed", self.failures.len()));
+        }
+        table
+    }
+}
+
+impl From<EvaluationReport> for EvaluationProfile {
+    fn from(report: EvaluationReport) -> Self {
+        Self {
+            nodes: report.timings.into_iter().map(|timing| (timing.node.clone(), timing)).collect(),
+            failures: report.failures,
+            total: report.total,
+        }
+    }
+}
+
+fn format_bytes(bytes: usize) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// Collects an [`EvaluationReport`] when inserted into an
+/// [`crate::EvalContext`]; graphs evaluated with the context record into it.
+/// Clones record into the same report, so the caller can keep one to read
+/// it back.
+#[derive(Debug, Clone, Default)]
+pub struct ReportRecorder {
+    report: Arc<Mutex<EvaluationReport>>,
+}
+
+impl ReportRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record_timing(&self, timing: NodeTiming) {
+        self.report.lock().timings.push(timing);
+    }
+
+    pub(crate) fn record_failure(&self, failure: NodeFailure) {
+        self.report.lock().failures.push(failure);
+    }
+
+    /// Takes what was recorded so far, leaving the recorder empty.
+    pub fn take(&self) -> EvaluationReport {
+        std::mem::take(&mut *self.report.lock())
+    }
+}
+
+/// Shown every node's output as it's produced, e.g. to keep previews of
+/// intermediate results, when inserted into an [`crate::EvalContext`].
+/// Bypassed nodes report the input they pass through.
+#[derive(Clone)]
+pub struct OutputObserver(Arc<ObserveOutput>);
+
+type ObserveOutput = dyn Fn(&NodeGraph, &NodeId, &NodeValue) + Send + Sync;
+
+impl OutputObserver {
+    pub fn new<F>(observe: F) -> Self
+    where
+        F: Fn(&NodeGraph, &NodeId, &NodeValue) + Send + Sync + 'static,
+    {
+        Self(Arc::new(observe))
+    }
+
+    pub(crate) fn observe(&self, graph: &NodeGraph, node: &NodeId, output: &NodeValue) {
+        (self.0)(graph, node, output)
+    }
+}
+
+impl fmt::Debug for OutputObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutputObserver").finish_non_exhaustive()
+    }
+}
+
+/// Whether a [`ProgressEvent`] comes before or after the node computes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressStage {
+    Started,
+    /// Sent whether or not the computation succeeded.
+    Finished,
+}
+
+/// Sent to the callback given to [`crate::EvalContext::on_progress`]
+/// before and after each node computes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressEvent {
+    pub stage: ProgressStage,
+    /// Nodes the evaluation may compute. Nodes served from the cache count
+    /// towards it without sending events, so `completed` can stop short.
+    pub total: usize,
+    /// Nodes finished so far, including this one once it's finished.
+    pub completed: usize,
+    pub node: NodeId,
+    pub type_name: &'static str,
+}
+
+/// The progress callback as kept in an [`crate::EvalContext`], counting the
+/// nodes finished since the evaluation started.
+pub(crate) struct ProgressReporter {
+    callback: Box<dyn Fn(ProgressEvent) + Send + Sync>,
+    total: AtomicUsize,
+    completed: AtomicUsize,
+}
+
+impl ProgressReporter {
+    pub(crate) fn new(callback: Box<dyn Fn(ProgressEvent) + Send + Sync>) -> Self {
+        Self { callback, total: AtomicUsize::new(0), completed: AtomicUsize::new(0) }
+    }
+
+    pub(crate) fn start(&self, total: usize) {
+        self.total.store(total, Ordering::SeqCst);
+        self.completed.store(0, Ordering::SeqCst);
+    }
+
+    /// Counts `more` nodes in the evaluation under way, e.g. a subgraph's.
+    pub(crate) fn add_to_total(&self, more: usize) {
+        self.total.fetch_add(more, Ordering::SeqCst);
+    }
+
+    pub(crate) fn started(&self, node: &NodeId, type_name: &'static str) {
+        let completed = self.completed.load(Ordering::SeqCst);
+        self.send(ProgressStage::Started, completed, node, type_name);
+    }
+
+    pub(crate) fn finished(&self, node: &NodeId, type_name: &'static str) {
+        let completed = self.completed.fetch_add(1, Ordering::SeqCst) + 1;
+        self.send(ProgressStage::Finished, completed, node, type_name);
+    }
+
+    fn send(&self, stage: ProgressStage, completed: usize, node: &NodeId, type_name: &'static str) {
+        let total = self.total.load(Ordering::SeqCst);
+        (self.callback)(ProgressEvent { stage, total, completed, node: node.clone(), type_name });
+    }
+}
@@ -0,0 +1,190 @@
+//! Descriptions of the parameters a node type accepts, see
+//! [`crate::NodeFactory::parameter_schema`], so editors can build creation
+//! dialogs and factories can check parameters before creating a node.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::NodeError;
+
+/// What a parameter holds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ParameterKind {
+    Float,
+    /// A whole number, written with or without a fraction, e.g. `2.0`.
+    Int,
+    String,
+    Bool,
+    /// One of a fixed list of names.
+    Enum { options: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParameterSpec {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ParameterKind,
+    /// What the node uses when the parameter isn't given.
+    pub default: Value,
+    /// Inclusive bounds for numbers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+impl ParameterSpec {
+    fn new(name: &str, kind: ParameterKind, default: Value) -> Self {
+        Self { name: name.to_string(), kind, default, min: None, max: None }
+    }
+
+    pub fn float(name: &str, default: f64) -> Self {
+        Self::new(name, ParameterKind::Float, Value::from(default))
+    }
+
+    pub fn int(name: &str, default: i64) -> Self {
+        Self::new(name, ParameterKind::Int, Value::from(default))
+    }
+
+    pub fn string(name: &str, default: &str) -> Self {
+        Self::new(name, ParameterKind::String, Value::from(default))
+    }
+
+    pub fn bool(name: &str, default: bool) -> Self {
+        Self::new(name, ParameterKind::Bool, Value::from(default))
+    }
+
+    pub fn one_of(name: &str, options: &[&str], default: &str) -> Self {
+        let options = options.iter().map(|option| option.to_string()).collect();
+        Self::new(name, ParameterKind::Enum { options }, Value::from(default))
+    }
+
+    /// Limits a number to `min..=max`.
+    pub fn with_range(mut self, min: f64, max: f64) -> Self {
+        self.min = Some(min);
+        self.max = Some(max);
+        self
+    }
+
+    /// Why `value` isn't acceptable, if it isn't.
+    fn check(&self, value: &Value) -> Result<(), String> {
+        let expected = match &self.kind {
+            ParameterKind::Float if value.is_number() => None,
+            ParameterKind::Int if value.as_f64().is_some_and(|v| v.fract() == 0.0) => None,
+            ParameterKind::String if value.is_string() => None,
+            ParameterKind::Bool if value.is_boolean() => None,
+            ParameterKind::Enum { options } => {
+                return match value.as_str() {
+                    Some(name) if options.iter().any(|option| option == name) => Ok(()),
+                    _ => Err(format!("expects one of {}, got {}", options.join(", "), value)),
+                };
+            }
+            ParameterKind::Float => Some("a number"),
+            ParameterKind::Int => Some("a whole number"),
+            ParameterKind::String => Some("a string"),
+            ParameterKind::Bool => Some("true or false"),
+        };
+        if let Some(expected) = expected {
+            return Err(format!("expects {}, got {}", expected, value));
+        }
+        let number = value.as_f64().unwrap_or_default();
+        let below = self.min.is_some_and(|min| number < min);
+        let above = self.max.is_some_and(|max| number > max);
+        if !below && !above {
+            return Ok(());
+        }
+        let range = match (self.min, self.max) {
+            (Some(min), Some(max)) => format!("{} to {}", min, max),
+            (Some(min), None) => format!("at least {}", min),
+            (None, _) => format!("at most {}", self.max.unwrap_or_default()),
+        };
+        Err(format!("expects {}, got {}", range, value))
+    }
+}
+
+/// The parameters a node type accepts, in the order editors list them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParameterSchema {
+    pub parameters: Vec<ParameterSpec>,
+}
+
+impl ParameterSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, parameter: ParameterSpec) -> Self {
+        self.parameters.push(parameter);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parameters.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ParameterSpec> {
+        self.parameters.iter().find(|parameter| parameter.name == name)
+    }
+
+    /// Every parameter at its default, as a parameter object.
+    pub fn defaults(&self) -> Value {
+        let defaults = self.parameters.iter().map(|parameter| (parameter.name.clone(), parameter.default.clone()));
+        Value::Object(defaults.collect())
+    }
+
+    /// Checks a parameter object for `type_name`: every entry must be a
+    /// parameter of the schema and in its range. Parameters left out keep
+    /// their defaults, and null stands for none given.
+    pub fn validate(&self, type_name: &str, parameters: &Value) -> Result<(), NodeError> {
+        let Some(entries) = parameters.as_object() else {
+            if parameters.is_null() {
+                return Ok(());
+            }
+            return Err(NodeError::ValidationError(format!(
+                "Parameters for {} must be an object, got {}",
+                type_name, parameters
+            )));
+        };
+        for (name, value) in entries {
+            let reason = match self.get(name) {
+                Some(parameter) => parameter.check(value).err().map(|reason| format!("{} {}", type_name, reason)),
+                None => Some(format!("{} has no such parameter", type_name)),
+            };
+            if let Some(reason) = reason {
+                return Err(NodeError::InvalidParameter { name: name.clone(), reason });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_schemas_check_kinds_and_ranges() {
+        let schema = ParameterSchema::new()
+            .with(ParameterSpec::float("sigma", 1.0).with_range(0.0, 100.0))
+            .with(ParameterSpec::int("threshold", 0))
+            .with(ParameterSpec::one_of("mode", &["Normal", "Add"], "Normal"));
+
+        assert!(schema.validate("Test", &json!({ "sigma": 2.5, "threshold": 3.0, "mode": "Add" })).is_ok());
+        assert!(schema.validate("Test", &Value::Null).is_ok());
+        assert!(schema.validate("Test", &json!([1])).is_err());
+
+        let reason = |parameters: Value| match schema.validate("Test", &parameters) {
+            Err(NodeError::InvalidParameter { reason, .. }) => reason,
+            other => panic!("expected an invalid parameter, got {:?}", other),
+        };
+        assert_eq!(reason(json!({ "sigma": -1 })), "Test expects 0 to 100, got -1");
+        assert_eq!(reason(json!({ "threshold": 1.5 })), "Test expects a whole number, got 1.5");
+        assert_eq!(reason(json!({ "mode": "Dodge" })), "Test expects one of Normal, Add, got \"Dodge\"");
+        assert_eq!(reason(json!({ "radius": 2 })), "Test has no such parameter");
+
+        assert_eq!(schema.defaults(), json!({ "sigma": 1.0, "threshold": 0, "mode": "Normal" }));
+        let json = serde_json::to_value(schema.get("mode").unwrap()).unwrap();
+        assert_eq!(json, json!({ "name": "mode", "type": "enum", "options": ["Normal", "Add"], "default": "Normal" }));
+    }
+}
@@ -0,0 +1,251 @@
+//! A saved form of a [`NodeGraph`], keeping node ids so that anything keyed
+//! by them, e.g. editor layouts, still applies after a reload.
+//!
+//! Nodes are saved as their type and [`NodeData::serialize_parameters`] and
+//! recreated through a [`NodeRegistry`]. Nodes of types the registry doesn't
+//! know load as [`UnknownNode`]s, which save as they were loaded. Groups
+//! ([`SubgraphNode`]) save the graph inside them among their parameters.
+
+use std::any::Any;
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::subgraph::{GroupInputNode, SubgraphNode};
+use crate::{Node, NodeData, NodeError, NodeGraph, NodeId, NodeRegistry, NodeValue, PortSpec};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SerializedGraph {
+    /// Sorted by id, so saving the same graph gives the same file.
+    pub nodes: Vec<SerializedNode>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edges: Vec<SerializedEdge>,
+    /// See [`NodeGraph::designated_output`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<NodeId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SerializedNode {
+    pub id: NodeId,
+    #[serde(rename = "type")]
+    pub type_name: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub parameters: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<[u8; 3]>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bypassed: bool,
+}
+
+/// Connects `from`'s output to the input `input` of `to`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SerializedEdge {
+    pub from: NodeId,
+    /// The output of `from` read, when it isn't its primary one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    pub to: NodeId,
+    pub input: String,
+}
+
+impl SerializedGraph {
+    /// The same graph with every node under a new id, e.g. for a copy
+    /// living alongside the original, and the new id of each old one.
+    pub fn with_new_ids(mut self) -> (Self, HashMap<NodeId, NodeId>) {
+        let ids: HashMap<NodeId, NodeId> = self.nodes.iter().map(|node| (node.id.clone(), NodeId::new())).collect();
+        let renumber = |id: &mut NodeId| {
+            if let Some(new) = ids.get(id) {
+                *id = new.clone();
+            }
+        };
+        for node in &mut self.nodes {
+            renumber(&mut node.id);
+        }
+        for edge in &mut self.edges {
+            renumber(&mut edge.from);
+            renumber(&mut edge.to);
+        }
+        if let Some(output) = &mut self.output {
+            renumber(output);
+        }
+        self.nodes.sort_by_key(|node| node.id.0);
+        (self, ids)
+    }
+}
+
+/// Stands in for a loaded node whose type isn't registered, keeping its
+/// type and parameters so saving the graph again doesn't lose them.
+/// Evaluating it fails.
+#[derive(Debug, Clone)]
+pub struct UnknownNode {
+    type_name: String,
+    parameters: Value,
+    // Outputs other nodes read, so their connections can be restored
+    outputs: Vec<String>,
+}
+
+impl UnknownNode {
+    pub fn new(type_name: impl Into<String>, parameters: Value) -> Self {
+        Self { type_name: type_name.into(), parameters, outputs: Vec::new() }
+    }
+
+    /// The type the node was saved as.
+    pub fn original_type(&self) -> &str {
+        &self.type_name
+    }
+}
+
+impl NodeData for UnknownNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        "UnknownNode"
+    }
+
+    fn compute(&self, _inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+        Err(NodeError::ValidationError(format!("Unknown node type: {}", self.type_name)))
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        if self.outputs.is_empty() {
+            return Vec::new();
+        }
+        let primary = PortSpec::optional(PortSpec::PRIMARY_OUTPUT, "unknown");
+        let others = self.outputs.iter().map(|name| PortSpec::optional(name, "unknown"));
+        std::iter::once(primary).chain(others).collect()
+    }
+
+    fn serialize_parameters(&self) -> Value {
+        self.parameters.clone()
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+}
+
+impl NodeGraph {
+    /// The graph's nodes, connections and designated output.
+    pub fn to_serialized(&self) -> SerializedGraph {
+        let mut ids = self.get_node_ids();
+        ids.sort_by_key(|id| id.0);
+        let mut nodes = Vec::with_capacity(ids.len());
+        let mut edges = Vec::new();
+        for id in ids {
+            let Some(node) = self.get_node(&id) else {
+                continue;
+            };
+            let node = node.read();
+            let type_name = match node.data().as_any().downcast_ref::<UnknownNode>() {
+                Some(unknown) => unknown.type_name.clone(),
+                None => node.data().type_name().to_string(),
+            };
+            nodes.push(SerializedNode {
+                id: id.clone(),
+                type_name,
+                parameters: node.data().serialize_parameters(),
+                label: node.label().map(str::to_string),
+                comment: node.comment().map(str::to_string),
+                color: node.color(),
+                bypassed: node.is_bypassed(),
+            });
+            for (input, source) in node.inputs() {
+                edges.push(SerializedEdge {
+                    from: source.clone(),
+                    output: node.source_output(input).map(str::to_string),
+                    to: id.clone(),
+                    input: input.clone(),
+                });
+            }
+        }
+        SerializedGraph { nodes, edges, output: self.designated_output().cloned() }
+    }
+
+    /// Recreates a graph saved by [`NodeGraph::to_serialized`], creating its
+    /// nodes through `registry`. Fails if a node's parameters are rejected
+    /// by its factory or an edge can't be connected.
+    pub fn from_serialized(data: &SerializedGraph, registry: &NodeRegistry) -> Result<NodeGraph, NodeError> {
+        let mut graph = NodeGraph::new();
+        for saved in &data.nodes {
+            let mut node = match saved.type_name.as_str() {
+                // Groups load what's inside them through the same registry
+                SubgraphNode::TYPE_NAME => {
+                    let group = SubgraphNode::from_parameters(&saved.parameters, registry)?;
+                    Node::new_with_id(Box::new(group), saved.id.clone())
+                }
+                GroupInputNode::TYPE_NAME => {
+                    let name = saved.parameters.get("name").and_then(Value::as_str).unwrap_or_default();
+                    Node::new_with_id(Box::new(GroupInputNode::new(name)), saved.id.clone())
+                }
+                type_name if registry.has_factory(type_name) => {
+                    registry.create_node_with_id(type_name, &saved.parameters, saved.id.clone())?
+                }
+                _ => {
+                    let mut unknown = UnknownNode::new(saved.type_name.clone(), saved.parameters.clone());
+                    let read = data.edges.iter().filter(|edge| edge.from == saved.id).filter_map(|edge| edge.output.clone());
+                    for output in read {
+                        if !unknown.outputs.contains(&output) {
+                            unknown.outputs.push(output);
+                        }
+                    }
+                    Node::new_with_id(Box::new(unknown), saved.id.clone())
+                }
+            };
+            node.set_label(saved.label.clone());
+            node.set_comment(saved.comment.clone());
+            node.set_color(saved.color);
+            node.set_bypassed(saved.bypassed);
+            graph.add_node(node);
+        }
+        for edge in &data.edges {
+            match &edge.output {
+                Some(output) => graph.connect_output(&edge.from, output, &edge.to, &edge.input)?,
+                None => graph.connect(&edge.from, &edge.to, &edge.input)?,
+            }
+        }
+        graph.set_designated_output(data.output.clone())?;
+        Ok(graph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_unknown_nodes_save_as_loaded() {
+        let saved: SerializedGraph = serde_json::from_value(json!({
+            "nodes": [
+                { "id": "00000000-0000-0000-0000-000000000001", "type": "FromThePlugin", "parameters": { "strength": 3 } },
+                { "id": "00000000-0000-0000-0000-000000000002", "type": "AlsoUnknown", "label": "Mask", "comment": "Feathered by hand", "color": [200, 60, 40] }
+            ],
+            "edges": [{
+                "from": "00000000-0000-0000-0000-000000000001",
+                "output": "mask",
+                "to": "00000000-0000-0000-0000-000000000002",
+                "input": "source"
+            }]
+        }))
+        .unwrap();
+
+        let graph = NodeGraph::from_serialized(&saved, &NodeRegistry::new()).unwrap();
+        let node = graph.get_node(&saved.nodes[0].id).unwrap();
+        let unknown = node.read().data().as_any().downcast_ref::<UnknownNode>().map(|node| node.original_type().to_string());
+        assert_eq!(unknown.as_deref(), Some("FromThePlugin"));
+        assert!(graph.evaluate(&saved.nodes[1].id).is_err());
+        let info = graph.get_node(&saved.nodes[1].id).unwrap().read().dump_debug_info();
+        assert!(info.contains("Label: Mask\nComment: Feathered by hand\nColor: #c83c28\n"));
+        assert_eq!(graph.to_serialized(), saved);
+    }
+}
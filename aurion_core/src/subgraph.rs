@@ -0,0 +1,612 @@
+//! Nodes collapsed into one, see [`NodeGraph::group_nodes`].
+//!
+//! A [`SubgraphNode`] owns the grouped nodes as a graph of its own. Each of
+//! its inputs feeds a [`GroupInputNode`] inside, standing in for whatever is
+//! connected to the group, and each of its outputs is an output of a node
+//! inside. Groups nest: a group's graph may hold groups itself.
+
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::serialization::SerializedGraph;
+use crate::{EvalContext, NodeData, NodeError, NodeGraph, NodeId, NodeOutputs, NodeRegistry, NodeValue, PortSpec};
+
+/// An input of a group, feeding `input` of the grouped node `node`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExposedInput {
+    pub name: String,
+    pub node: NodeId,
+    pub input: String,
+}
+
+impl ExposedInput {
+    pub fn new(name: &str, node: &NodeId, input: &str) -> Self {
+        Self { name: name.to_string(), node: node.clone(), input: input.to_string() }
+    }
+}
+
+/// An output of a group, giving the grouped node `node`'s output `output`,
+/// or its primary one with `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExposedOutput {
+    pub name: String,
+    pub node: NodeId,
+    pub output: Option<String>,
+}
+
+impl ExposedOutput {
+    pub fn new(name: &str, node: &NodeId) -> Self {
+        Self { name: name.to_string(), node: node.clone(), output: None }
+    }
+
+    pub fn with_output(mut self, output: &str) -> Self {
+        self.output = Some(output.to_string());
+        self
+    }
+}
+
+// One of a group's ports, as saved
+#[derive(Debug, Clone, PartialEq, Hash, Serialize, Deserialize)]
+struct GroupPort {
+    name: String,
+    // For inputs the GroupInputNode fed, for outputs the node read
+    node: NodeId,
+    // The output read, when it isn't the node's primary one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    #[serde(rename = "type")]
+    type_id: String,
+}
+
+// A SubgraphNode's parameters
+#[derive(Serialize, Deserialize)]
+struct SavedSubgraph {
+    graph: SerializedGraph,
+    #[serde(default)]
+    inputs: Vec<GroupPort>,
+    outputs: Vec<GroupPort>,
+}
+
+/// Stands inside a [`SubgraphNode`] for one of its inputs. Evaluated on its
+/// own, e.g. while nothing is connected to that input, it fails.
+#[derive(Debug, Clone)]
+pub struct GroupInputNode {
+    name: String,
+}
+
+impl GroupInputNode {
+    pub const TYPE_NAME: &'static str = "GroupInput";
+
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string() }
+    }
+
+    /// The group input it stands for.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl NodeData for GroupInputNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        Self::TYPE_NAME
+    }
+
+    fn compute(&self, _inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+        Err(NodeError::MissingInput(format!("'{}' of the group", self.name)))
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        self.name.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<Value> {
+        (name == "name").then(|| Value::from(self.name.as_str()))
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &["name"]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+}
+
+/// Nodes grouped into one by [`NodeGraph::group_nodes`], evaluating them
+/// when computed. Every input of the group must be connected.
+pub struct SubgraphNode {
+    graph: NodeGraph,
+    inputs: Vec<GroupPort>,
+    outputs: Vec<GroupPort>,
+}
+
+impl SubgraphNode {
+    pub const TYPE_NAME: &'static str = "SubgraphNode";
+
+    /// The grouped nodes, along with a [`GroupInputNode`] for each input.
+    pub fn graph(&self) -> &NodeGraph {
+        &self.graph
+    }
+
+    /// Recreates a group saved through [`NodeData::serialize_parameters`],
+    /// creating the nodes inside through `registry`.
+    pub(crate) fn from_parameters(parameters: &Value, registry: &NodeRegistry) -> Result<Self, NodeError> {
+        let saved: SavedSubgraph = serde_json::from_value(parameters.clone()).map_err(|e| NodeError::InvalidParameter {
+            name: "graph".to_string(),
+            reason: format!("{} can't read its saved group: {}", Self::TYPE_NAME, e),
+        })?;
+        let graph = NodeGraph::from_serialized(&saved.graph, registry)?;
+        Ok(Self { graph, inputs: saved.inputs, outputs: saved.outputs })
+    }
+}
+
+impl fmt::Debug for SubgraphNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SubgraphNode")
+            .field("nodes", &self.graph.get_node_ids().len())
+            .field("inputs", &self.inputs)
+            .field("outputs", &self.outputs)
+            .finish()
+    }
+}
+
+impl NodeData for SubgraphNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        Self::TYPE_NAME
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+        self.compute_with_context(inputs, &EvalContext::new())
+    }
+
+    fn compute_with_context(&self, inputs: &[NodeValue], context: &EvalContext) -> Result<NodeValue, NodeError> {
+        let primary = self.primary_output();
+        self.compute_outputs(inputs, context)?
+            .remove(&primary)
+            .ok_or_else(|| NodeError::ValidationError(format!("{} has no outputs", Self::TYPE_NAME)))
+    }
+
+    fn compute_outputs(&self, inputs: &[NodeValue], context: &EvalContext) -> Result<NodeOutputs, NodeError> {
+        if inputs.len() != self.inputs.len() {
+            return Err(NodeError::InvalidInputType {
+                expected: format!("{} inputs", self.inputs.len()),
+                actual: format!("{} inputs", inputs.len()),
+            });
+        }
+        let seeds = self.inputs.iter().zip(inputs).map(|(port, value)| (port.node.clone(), value.clone())).collect();
+        let targets: Vec<_> = self.outputs.iter().map(|port| (port.node.clone(), port.output.clone())).collect();
+        let values = self.graph.evaluate_seeded(&targets, seeds, context)?;
+        Ok(self.outputs.iter().map(|port| port.name.clone()).zip(values).collect())
+    }
+
+    fn input_ports(&self) -> Vec<PortSpec> {
+        self.inputs.iter().map(|port| PortSpec::required(&port.name, &port.type_id)).collect()
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        self.outputs.iter().map(|port| PortSpec::required(&port.name, &port.type_id)).collect()
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        self.graph.content_hash()?.hash(&mut hasher);
+        self.inputs.hash(&mut hasher);
+        self.outputs.hash(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    fn serialize_parameters(&self) -> Value {
+        let saved = SavedSubgraph { graph: self.graph.to_serialized(), inputs: self.inputs.clone(), outputs: self.outputs.clone() };
+        serde_json::to_value(saved).unwrap_or_default()
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(Self { graph: self.graph.deep_clone(), inputs: self.inputs.clone(), outputs: self.outputs.clone() })
+    }
+
+    fn estimated_memory(&self) -> usize {
+        std::mem::size_of_val(self) + self.graph.estimated_memory()
+    }
+}
+
+impl NodeGraph {
+    /// Moves the nodes `ids` into a [`SubgraphNode`] taking their place, and
+    /// returns its id. Connections into the group arrive at its `inputs`
+    /// and connections out of it leave from its `outputs`, the first of
+    /// which is its primary output. A group holding the designated output
+    /// becomes it.
+    ///
+    /// Fails, leaving the graph as it was, if a node is missing, `outputs`
+    /// is empty, a connection into the group doesn't arrive at one of
+    /// `inputs` or one out of it doesn't leave from one of `outputs`, or a
+    /// path out of the group leads back into it: though the nodes make no
+    /// cycle, the group would then feed itself.
+    pub fn group_nodes(&mut self, ids: &[NodeId], inputs: Vec<ExposedInput>, outputs: Vec<ExposedOutput>) -> Result<NodeId, NodeError> {
+        let mut grouped = HashSet::new();
+        let ids: Vec<NodeId> = ids.iter().filter(|id| grouped.insert(*id)).cloned().collect();
+        if let Some(id) = ids.iter().find(|id| !self.nodes.contains_key(id)) {
+            return Err(NodeError::NodeNotFound(id.0));
+        }
+        if outputs.is_empty() {
+            return Err(NodeError::ValidationError("A group needs at least one output".to_string()));
+        }
+        let invalid = |name: &str, reason: String| NodeError::InvalidParameter { name: name.to_string(), reason };
+
+        let mut input_types = Vec::with_capacity(inputs.len());
+        let mut names = HashSet::new();
+        for exposed in &inputs {
+            if !grouped.contains(&exposed.node) {
+                return Err(invalid(&exposed.name, format!("feeds {}, which isn't grouped", exposed.node.to_string())));
+            }
+            if !names.insert(&exposed.name) {
+                return Err(invalid(&exposed.name, "is exposed twice".to_string()));
+            }
+            let node = self.nodes[&exposed.node].read();
+            if node.get_input(&exposed.input).is_some_and(|source| grouped.contains(source)) {
+                return Err(invalid(&exposed.name, format!("feeds '{}', which is fed inside the group", exposed.input)));
+            }
+            let port = node.data().input_ports().into_iter().find(|port| port.name == exposed.input);
+            input_types.push(port.map_or("unknown".to_string(), |port| port.type_id));
+        }
+
+        let mut output_slots = Vec::with_capacity(outputs.len());
+        let mut output_types = Vec::with_capacity(outputs.len());
+        let mut names = HashSet::new();
+        for exposed in &outputs {
+            if !grouped.contains(&exposed.node) {
+                return Err(invalid(&exposed.name, format!("reads {}, which isn't grouped", exposed.node.to_string())));
+            }
+            if !names.insert(&exposed.name) {
+                return Err(invalid(&exposed.name, "is exposed twice".to_string()));
+            }
+            let node = self.nodes[&exposed.node].read();
+            let slot = match &exposed.output {
+                Some(output) => node.output_slot(output)?,
+                None => None,
+            };
+            let read = slot.clone().unwrap_or_else(|| node.data().primary_output());
+            let port = node.data().output_ports().into_iter().find(|port| port.name == read);
+            output_types.push(port.map_or("unknown".to_string(), |port| port.type_id));
+            output_slots.push(slot);
+        }
+
+        // Connections crossing into the group, by the input they arrive at,
+        // and out of it, by the output they leave from
+        let mut incoming = Vec::new();
+        let mut outgoing = Vec::new();
+        for (id, node) in &self.nodes {
+            let node = node.read();
+            for (input, source) in node.inputs() {
+                let slot = node.source_output(input);
+                match (grouped.contains(id), grouped.contains(source)) {
+                    (true, false) => {
+                        let exposed = inputs.iter().find(|exposed| &exposed.node == id && &exposed.input == input).ok_or_else(|| {
+                            NodeError::ValidationError(format!(
+                                "Input '{}' of {} is fed from outside the group but isn't exposed",
+                                input,
+                                id.to_string()
+                            ))
+                        })?;
+                        incoming.push((exposed.name.clone(), source.clone(), slot.map(str::to_string)));
+                    }
+                    (false, true) => {
+                        let (exposed, _) = outputs
+                            .iter()
+                            .zip(&output_slots)
+                            .find(|(exposed, exposed_slot)| &exposed.node == source && exposed_slot.as_deref() == slot)
+                            .ok_or_else(|| {
+                                NodeError::ValidationError(format!(
+                                    "{} reads an output of {} the group doesn't expose",
+                                    id.to_string(),
+                                    source.to_string()
+                                ))
+                            })?;
+                        outgoing.push((id.clone(), input.clone(), exposed.name.clone()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Walk everything downstream of the group outside it; reaching the
+        // group again means it would feed itself
+        let mut pending: Vec<_> = ids
+            .iter()
+            .flat_map(|id| self.graph.neighbors(self.node_indices[id]))
+            .filter(|index| !grouped.contains(&self.graph[*index]))
+            .collect();
+        let mut visited: HashSet<_> = pending.iter().copied().collect();
+        while let Some(index) = pending.pop() {
+            for next in self.graph.neighbors(index) {
+                if grouped.contains(&self.graph[next]) {
+                    return Err(NodeError::CycleDetected { from: self.graph[index].to_string(), to: self.graph[next].to_string() });
+                }
+                if visited.insert(next) {
+                    pending.push(next);
+                }
+            }
+        }
+
+        // Connections inside the group, read before removing the nodes clears them
+        let mut wiring = Vec::new();
+        for id in &ids {
+            let node = self.nodes[id].read();
+            for (input, source) in node.inputs().iter().filter(|(_, source)| grouped.contains(source)) {
+                wiring.push((id.clone(), input.clone(), source.clone(), node.source_output(input).map(str::to_string)));
+            }
+        }
+        let designated = self.designated_output.as_ref().is_some_and(|id| grouped.contains(id));
+
+        let mut inner = NodeGraph::new();
+        inner.ids = self.ids.clone();
+        for id in &ids {
+            let node = self.remove_node(id)?;
+            {
+                let mut node = node.write();
+                node.inputs.clear();
+                node.source_outputs.clear();
+            }
+            inner.insert_node(node);
+        }
+        for (to, input, from, slot) in wiring {
+            inner.connect_slot(&from, slot.as_deref(), &to, &input)?;
+        }
+        let mut group_inputs = Vec::with_capacity(inputs.len());
+        for (exposed, type_id) in inputs.iter().zip(input_types) {
+            let placeholder = inner.add_node_data(Box::new(GroupInputNode::new(&exposed.name)));
+            inner.connect(&placeholder, &exposed.node, &exposed.input)?;
+            group_inputs.push(GroupPort { name: exposed.name.clone(), node: placeholder, output: None, type_id });
+        }
+        let group_outputs = outputs
+            .iter()
+            .zip(output_slots)
+            .zip(output_types)
+            .map(|((exposed, output), type_id)| GroupPort { name: exposed.name.clone(), node: exposed.node.clone(), output, type_id })
+            .collect();
+
+        let group = self.add_node_data(Box::new(SubgraphNode { graph: inner, inputs: group_inputs, outputs: group_outputs }));
+        for (input, source, slot) in incoming {
+            self.connect_slot(&source, slot.as_deref(), &group, &input)?;
+        }
+        for (consumer, input, output) in outgoing {
+            self.connect_output(&group, &output, &consumer, &input)?;
+        }
+        if designated {
+            self.designated_output = Some(group.clone());
+        }
+        Ok(group)
+    }
+
+    /// Puts the nodes of the group `group` back in its place, connected as
+    /// the group was, and returns their ids. Nodes keep their ids unless the
+    /// graph already has one, e.g. from ungrouping a copy of the same group.
+    /// Inputs fed by group inputs that weren't connected are left
+    /// unconnected. The group's node is left empty.
+    pub fn ungroup(&mut self, group: &NodeId) -> Result<Vec<NodeId>, NodeError> {
+        let handle = self.get_node(group).ok_or(NodeError::NodeNotFound(group.0))?;
+        let mut guard = handle.write();
+        let node = &mut *guard;
+        let subgraph = node
+            .data
+            .as_any_mut()
+            .downcast_mut::<SubgraphNode>()
+            .ok_or_else(|| NodeError::ValidationError(format!("{} is not a group", group.to_string())))?;
+
+        let sources: HashMap<String, (NodeId, Option<String>)> = node
+            .inputs
+            .iter()
+            .map(|(input, source)| (input.clone(), (source.clone(), node.source_outputs.get(input).cloned())))
+            .collect();
+        let primary = subgraph.primary_output();
+        let mut consumers = Vec::new();
+        for (id, consumer) in &self.nodes {
+            if id == group {
+                continue;
+            }
+            let consumer = consumer.read();
+            for (input, _) in consumer.inputs().iter().filter(|(_, source)| *source == group) {
+                let output = consumer.source_output(input).map_or_else(|| primary.clone(), str::to_string);
+                consumers.push((id.clone(), input.clone(), output));
+            }
+        }
+        let designated = self.designated_output.as_ref() == Some(group);
+        let inner = std::mem::replace(&mut subgraph.graph, NodeGraph::new());
+        let inputs = std::mem::take(&mut subgraph.inputs);
+        let outputs = std::mem::take(&mut subgraph.outputs);
+        drop(guard);
+        self.remove_node(group)?;
+
+        let placeholders: HashMap<&NodeId, &str> = inputs.iter().map(|port| (&port.node, port.name.as_str())).collect();
+        let mut inner_ids: Vec<NodeId> = inner.get_node_ids().into_iter().filter(|id| !placeholders.contains_key(id)).collect();
+        inner_ids.sort_by_key(|id| id.0);
+        let mut wiring = Vec::new();
+        for id in &inner_ids {
+            let node = inner.nodes[id].read();
+            for (input, source) in node.inputs() {
+                wiring.push((id.clone(), input.clone(), source.clone(), node.source_output(input).map(str::to_string)));
+            }
+        }
+
+        let mut renamed = HashMap::with_capacity(inner_ids.len());
+        for id in &inner_ids {
+            let node = inner.nodes[id].clone();
+            let new_id = if self.nodes.contains_key(id) { self.next_node_id() } else { id.clone() };
+            {
+                let mut node = node.write();
+                node.id = new_id.clone();
+                node.inputs.clear();
+                node.source_outputs.clear();
+            }
+            self.insert_node(node);
+            renamed.insert(id.clone(), new_id);
+        }
+        for (to, input, from, slot) in wiring {
+            let to = &renamed[&to];
+            match placeholders.get(&from) {
+                Some(name) => {
+                    if let Some((source, source_slot)) = sources.get(*name) {
+                        self.connect_slot(source, source_slot.as_deref(), to, &input)?;
+                    }
+                }
+                None => self.connect_slot(&renamed[&from], slot.as_deref(), to, &input)?,
+            }
+        }
+        for (consumer, input, output) in consumers {
+            let Some(port) = outputs.iter().find(|port| port.name == output) else {
+                continue;
+            };
+            if let Some(source) = renamed.get(&port.node) {
+                self.connect_slot(source, port.output.as_deref(), &consumer, &input)?;
+            }
+        }
+        if designated {
+            self.designated_output = outputs.first().and_then(|port| renamed.get(&port.node)).cloned();
+        }
+        Ok(inner_ids.iter().map(|id| renamed[id].clone()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NodeFactory;
+
+    /// Sums its inputs and `amount`.
+    #[derive(Debug, Clone)]
+    struct AddNode(i64);
+
+    impl NodeData for AddNode {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn type_name(&self) -> &'static str {
+            "Add"
+        }
+
+        fn content_hash(&self) -> Option<u64> {
+            Some(self.0 as u64)
+        }
+
+        fn get_parameter(&self, name: &str) -> Option<Value> {
+            (name == "amount").then(|| Value::from(self.0))
+        }
+
+        fn parameter_names(&self) -> &'static [&'static str] {
+            &["amount"]
+        }
+
+        fn clone_box(&self) -> Box<dyn NodeData> {
+            Box::new(self.clone())
+        }
+
+        fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+            Ok(NodeValue::Int(inputs.iter().filter_map(NodeValue::as_int).sum::<i64>() + self.0))
+        }
+    }
+
+    struct AddFactory;
+
+    impl NodeFactory for AddFactory {
+        fn create(&self, parameters: &Value) -> Result<Box<dyn NodeData>, NodeError> {
+            Ok(Box::new(AddNode(parameters.get("amount").and_then(Value::as_i64).unwrap_or(0))))
+        }
+
+        fn type_name(&self) -> &'static str {
+            "Add"
+        }
+    }
+
+    // 1 -> +10 -> +100 -> +1000, by id
+    fn chain() -> (NodeGraph, [NodeId; 4]) {
+        let mut graph = NodeGraph::new();
+        let ids = [1, 10, 100, 1000].map(|amount| graph.add_node_data(Box::new(AddNode(amount))));
+        for pair in ids.windows(2) {
+            graph.connect(&pair[0], &pair[1], "x").unwrap();
+        }
+        (graph, ids)
+    }
+
+    fn reload(graph: &NodeGraph, registry: &NodeRegistry) -> NodeGraph {
+        let json = serde_json::to_string(&graph.to_serialized()).unwrap();
+        NodeGraph::from_serialized(&serde_json::from_str(&json).unwrap(), registry).unwrap()
+    }
+
+    #[test]
+    fn test_groups_evaluate_save_and_ungroup() {
+        let mut registry = NodeRegistry::new();
+        registry.register(AddFactory);
+        let (mut graph, [a, b, c, d]) = chain();
+        let sum = |graph: &NodeGraph| graph.evaluate(&d).unwrap().as_int();
+        assert_eq!(sum(&graph), Some(1111));
+
+        let group = graph
+            .group_nodes(&[b.clone(), c.clone()], vec![ExposedInput::new("in", &b, "x")], vec![ExposedOutput::new("out", &c)])
+            .unwrap();
+        assert_eq!(graph.get_node_ids().len(), 3);
+        assert_eq!(graph.get_node(&d).unwrap().read().get_input("x"), Some(&group));
+        assert_eq!(graph.get_node(&group).unwrap().read().get_input("in"), Some(&a));
+        assert_eq!(sum(&graph), Some(1111));
+
+        // Groups nest, and save with what's inside them
+        let outer = graph.group_nodes(&[a.clone(), group.clone()], vec![], vec![ExposedOutput::new("out", &group)]).unwrap();
+        assert_eq!(sum(&graph), Some(1111));
+        let mut graph = reload(&graph, &registry);
+        assert_eq!(sum(&graph), Some(1111));
+        assert_eq!(graph.to_serialized(), reload(&graph, &registry).to_serialized());
+
+        let mut restored = graph.ungroup(&outer).unwrap();
+        restored.sort_by_key(|id| id.0);
+        let mut expected = vec![a.clone(), group.clone()];
+        expected.sort_by_key(|id| id.0);
+        assert_eq!(restored, expected);
+        graph.ungroup(&group).unwrap();
+        assert_eq!(graph.get_node_ids().len(), 4);
+        assert_eq!(graph.get_node(&d).unwrap().read().get_input("x"), Some(&c));
+        assert_eq!(graph.get_node(&b).unwrap().read().get_input("x"), Some(&a));
+        assert_eq!(sum(&graph), Some(1111));
+    }
+
+    #[test]
+    fn test_groups_must_not_feed_themselves() {
+        let (mut graph, [a, b, c, d]) = chain();
+        let before = graph.content_hash();
+
+        // a and c are joined through b, outside the group
+        let result = graph.group_nodes(&[a.clone(), c.clone()], vec![ExposedInput::new("in", &c, "x")], vec![ExposedOutput::new("a", &a), ExposedOutput::new("c", &c)]);
+        assert!(matches!(result, Err(NodeError::CycleDetected { .. })));
+
+        // d reads c, which the group doesn't expose
+        let result = graph.group_nodes(&[b.clone(), c.clone()], vec![ExposedInput::new("in", &b, "x")], vec![ExposedOutput::new("b", &b)]);
+        assert!(matches!(result, Err(NodeError::ValidationError(_))));
+        assert!(graph.group_nodes(std::slice::from_ref(&d), vec![], vec![ExposedOutput::new("out", &d)]).is_err());
+        assert_eq!(graph.content_hash(), before);
+    }
+}
@@ -0,0 +1,169 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use image::DynamicImage;
+use crate::NodeError;
+
+/// A node's result, and what its consumers receive as inputs.
+///
+/// Images are shared rather than copied when they feed several nodes or are
+/// kept by [`crate::NodeGraph::evaluate_cached`], so large buffers aren't
+/// duplicated; take one with [`NodeValue::into_image`] to own it.
+#[derive(Clone)]
+pub enum NodeValue {
+    Image(Arc<DynamicImage>),
+    Float(f32),
+    Int(i64),
+    Bool(bool),
+    String(String),
+    Vector(Vec<NodeValue>),
+    Null,
+    /// Anything else, for extension nodes, e.g. a `GrayImage` mask.
+    Custom(Arc<dyn Any + Send + Sync>),
+}
+
+/// A node's results by output name, see [`crate::NodeData::compute_outputs`].
+pub type NodeOutputs = HashMap<String, NodeValue>;
+
+impl NodeValue {
+    pub fn image(image: DynamicImage) -> Self {
+        Self::Image(Arc::new(image))
+    }
+
+    pub fn custom<T: Any + Send + Sync>(value: T) -> Self {
+        Self::Custom(Arc::new(value))
+    }
+
+    /// The variant's name, e.g. "Image", for error messages.
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Self::Image(_) => "Image",
+            Self::Float(_) => "Float",
+            Self::Int(_) => "Int",
+            Self::Bool(_) => "Bool",
+            Self::String(_) => "String",
+            Self::Vector(_) => "Vector",
+            Self::Null => "Null",
+            Self::Custom(_) => "Custom",
+        }
+    }
+
+    /// Approximate bytes held, e.g. an image's pixel buffer, for profiles.
+    pub fn estimated_size(&self) -> usize {
+        match self {
+            Self::Image(image) => image.as_bytes().len(),
+            Self::String(value) => value.len(),
+            Self::Vector(values) => values.iter().map(Self::estimated_size).sum(),
+            _ => std::mem::size_of::<Self>(),
+        }
+    }
+
+    pub fn as_image(&self) -> Option<&DynamicImage> {
+        match self {
+            Self::Image(image) => Some(image),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f32> {
+        match self {
+            Self::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The value inside a [`NodeValue::Custom`], if it's a `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        match self {
+            Self::Custom(value) => value.downcast_ref::<T>(),
+            _ => None,
+        }
+    }
+
+    /// The image, or an error naming what arrived instead.
+    pub fn try_image(&self) -> Result<&DynamicImage, NodeError> {
+        self.as_image().ok_or_else(|| NodeError::InvalidInputType {
+            expected: "Image".to_string(),
+            actual: self.variant_name().to_string(),
+        })
+    }
+
+    /// The image, copied only if something else still shares it.
+    pub fn into_image(self) -> Option<DynamicImage> {
+        match self {
+            Self::Image(image) => Some(Arc::try_unwrap(image).unwrap_or_else(|image| (*image).clone())),
+            _ => None,
+        }
+    }
+}
+
+impl From<DynamicImage> for NodeValue {
+    fn from(image: DynamicImage) -> Self {
+        Self::image(image)
+    }
+}
+
+impl fmt::Debug for NodeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Image(image) => write!(f, "Image({}x{})", image.width(), image.height()),
+            Self::Float(value) => write!(f, "Float({})", value),
+            Self::Int(value) => write!(f, "Int({})", value),
+            Self::Bool(value) => write!(f, "Bool({})", value),
+            Self::String(value) => write!(f, "String({:?})", value),
+            Self::Vector(values) => f.debug_tuple("Vector").field(values).finish(),
+            Self::Null => write!(f, "Null"),
+            Self::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn test_accessors_name_the_actual_variant() {
+        let image = NodeValue::image(DynamicImage::ImageRgba8(RgbaImage::new(3, 2)));
+        assert_eq!(image.try_image().unwrap().width(), 3);
+        assert_eq!(NodeValue::Int(4).as_int(), Some(4));
+        assert_eq!(NodeValue::Int(4).as_float(), None);
+        assert_eq!(NodeValue::custom(7u32).downcast_ref::<u32>(), Some(&7));
+
+        let error = NodeValue::Float(0.5).try_image().unwrap_err();
+        assert_eq!(error.to_string(), "Invalid input type: expected Image, got Float");
+        assert_eq!(format!("{:?}", NodeValue::Vector(vec![image, NodeValue::Null])), "Vector([Image(3x2), Null])");
+    }
+
+    #[test]
+    fn test_into_image_shares_until_taken() {
+        let value = NodeValue::image(DynamicImage::ImageRgba8(RgbaImage::new(2, 2)));
+        let shared = value.clone();
+        assert_eq!(value.into_image().unwrap().width(), 2);
+        assert_eq!(shared.into_image().unwrap().height(), 2);
+        assert!(NodeValue::Null.into_image().is_none());
+    }
+}
@@ -1,14 +1,112 @@
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
+//! An example plugin: a posterize node, loaded at runtime rather than linked
+//! into the application. See [`aurion_core::plugin`].
+
+use std::any::Any;
+use aurion_core::{NodeData, NodeError, NodeFactory, NodeRegistry, NodeValue, ParameterSchema, ParameterSpec, PortSpec};
+use image::DynamicImage;
+use serde_json::Value;
+
+/// Reduces each channel to `levels` evenly spaced values.
+#[derive(Debug, Clone)]
+pub struct PosterizeNode {
+    levels: u32,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl PosterizeNode {
+    pub fn new(levels: u32) -> Self {
+        Self { levels: levels.clamp(2, 256) }
+    }
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
+    fn posterize(&self, channel: u8) -> u8 {
+        let step = 255.0 / (self.levels - 1) as f32;
+        ((channel as f32 / step).round() * step).round() as u8
     }
 }
+
+impl NodeData for PosterizeNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        "PosterizeNode"
+    }
+
+    fn input_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_input()]
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        Some(u64::from(self.levels))
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<Value> {
+        (name == "levels").then(|| Value::from(self.levels))
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &["levels"]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: Value) -> Result<(), NodeError> {
+        let levels = value.as_f64().filter(|_| name == "levels").ok_or_else(|| NodeError::InvalidParameter {
+            name: name.to_string(),
+            reason: format!("{} takes a number of levels, got {}", self.type_name(), value),
+        })?;
+        *self = Self::new(levels.round() as u32);
+        Ok(())
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+        let input = inputs.first().ok_or_else(|| NodeError::MissingInput("input".to_string()))?.try_image()?;
+        let mut output = input.to_rgba8();
+        for pixel in output.pixels_mut() {
+            for channel in &mut pixel.0[..3] {
+                *channel = self.posterize(*channel);
+            }
+        }
+        Ok(NodeValue::image(DynamicImage::ImageRgba8(output)))
+    }
+}
+
+struct PosterizeFactory;
+
+impl NodeFactory for PosterizeFactory {
+    fn create(&self, parameters: &Value) -> Result<Box<dyn NodeData>, NodeError> {
+        let mut node = PosterizeNode::new(4);
+        if let Some(levels) = parameters.get("levels") {
+            node.set_parameter("levels", levels.clone())?;
+        }
+        Ok(Box::new(node))
+    }
+
+    fn type_name(&self) -> &'static str {
+        "PosterizeNode"
+    }
+
+    fn category(&self) -> Option<&'static str> {
+        Some("Filter")
+    }
+
+    fn parameter_schema(&self) -> ParameterSchema {
+        ParameterSchema::new().with(ParameterSpec::int("levels", 4).with_range(2.0, 256.0))
+    }
+}
+
+fn register(registry: &mut NodeRegistry) {
+    registry.register(PosterizeFactory);
+}
+
+aurion_core::export_plugin!(register);
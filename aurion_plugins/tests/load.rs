@@ -0,0 +1,67 @@
+//! Loads this crate's library as a plugin, the way the application would.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use aurion_core::{NodeOrigin, NodeRegistry, NodeValue, PluginError};
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde_json::json;
+
+/// Builds the plugin library into the target directory this test was built
+/// in, with the same profile, and returns its path. `cargo test` only
+/// builds what the tests link against, which a cdylib never is.
+fn plugin_path() -> PathBuf {
+    // <target dir>/<profile>/deps/<test binary>
+    let exe = std::env::current_exe().unwrap();
+    let profile_dir = exe.parent().and_then(Path::parent).expect("test binary outside a profile directory");
+    let target_dir = profile_dir.parent().expect("profile directory outside a target directory");
+    let profile = match profile_dir.file_name().and_then(|name| name.to_str()) {
+        Some("debug") | None => "dev",
+        Some(name) => name,
+    };
+    let output = Command::new(env!("CARGO"))
+        .args(["build", "-p", "aurion_plugins", "--profile", profile, "--target-dir"])
+        .arg(target_dir)
+        .output()
+        .expect("cargo didn't run");
+    assert!(output.status.success(), "building the plugin failed:\n{}", String::from_utf8_lossy(&output.stderr));
+
+    let name = format!("{}aurion_plugins{}", std::env::consts::DLL_PREFIX, std::env::consts::DLL_SUFFIX);
+    profile_dir.join(name)
+}
+
+#[test]
+fn test_plugin_nodes_load_and_unload() {
+    let path = plugin_path();
+    let mut registry = NodeRegistry::new();
+    let plugin = unsafe { registry.load_plugin(&path) }.unwrap();
+    assert_eq!(plugin.node_types(), ["PosterizeNode"]);
+    assert_eq!(registry.node_origin("PosterizeNode"), Some(NodeOrigin::Plugin(path.clone())));
+    assert!(registry.get_builtin_node_types().is_empty());
+    assert_eq!(registry.get_schema("PosterizeNode").unwrap().get("levels").unwrap().min, Some(2.0));
+
+    let node = registry.create_node("PosterizeNode", &json!({ "levels": 2 })).unwrap();
+    let image = RgbaImage::from_fn(2, 1, |x, _| if x == 0 { Rgba([100, 100, 100, 255]) } else { Rgba([200, 200, 200, 255]) });
+    let output = node.data().compute(&[NodeValue::image(DynamicImage::ImageRgba8(image))]).unwrap();
+    let output = output.as_image().unwrap().to_rgba8();
+    assert_eq!(output.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+    assert_eq!(output.get_pixel(1, 0), &Rgba([255, 255, 255, 255]));
+    assert!(registry.create_node("PosterizeNode", &json!({ "levels": 1 })).is_err());
+    drop(node);
+
+    // Loading it again would register the same type twice
+    let again = unsafe { registry.load_plugin(&path) };
+    assert!(matches!(again, Err(PluginError::Conflict { type_name, .. }) if type_name == "PosterizeNode"));
+    assert_eq!(registry.get_available_node_types(), ["PosterizeNode"]);
+
+    unsafe { registry.unload_plugin(plugin) };
+    assert!(registry.node_origin("PosterizeNode").is_none());
+    assert!(registry.get_available_node_types().is_empty());
+}
+
+#[test]
+fn test_missing_plugins_fail_to_load() {
+    let mut registry = NodeRegistry::new();
+    let result = unsafe { registry.load_plugin(Path::new("no_such_plugin.so")) };
+    assert!(matches!(result, Err(PluginError::Load { .. })));
+    assert!(registry.get_available_node_types().is_empty());
+}
@@ -0,0 +1,132 @@
+//! Nodes referencing shared image assets owned by the host.
+
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+use aurion_core::{EvalContext, NodeData, NodeError, NodeValue, PortSpec};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+/// Identifies an asset by the hash of its content.
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+pub struct AssetId(String);
+
+impl AssetId {
+    pub fn from_hash(hash: impl Into<String>) -> Self {
+        Self(hash.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for AssetId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Looks up asset images. Hosts insert an `Arc<dyn AssetResolver>` into the
+/// [`EvalContext`] so [`AssetRefNode`]s can find their pixels.
+pub trait AssetResolver: Send + Sync {
+    fn resolve(&self, id: &AssetId) -> Option<Arc<DynamicImage>>;
+}
+
+/// Outputs the image of a shared asset without holding a copy of it.
+#[derive(Debug, Clone)]
+pub struct AssetRefNode {
+    asset: AssetId,
+}
+
+impl AssetRefNode {
+    pub fn new(asset: AssetId) -> Self {
+        Self { asset }
+    }
+
+    pub fn asset(&self) -> &AssetId {
+        &self.asset
+    }
+}
+
+impl NodeData for AssetRefNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        "AssetRefNode"
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        Some(crate::hash_value(&self.asset))
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+        self.compute_with_context(inputs, &EvalContext::new())
+    }
+
+    fn compute_with_context(&self, inputs: &[NodeValue], context: &EvalContext) -> Result<NodeValue, NodeError> {
+        if !inputs.is_empty() {
+            return Err(NodeError::InvalidInputType {
+                expected: "none".to_string(),
+                actual: format!("{} inputs", inputs.len()),
+            });
+        }
+
+        let resolver = context
+            .get::<Arc<dyn AssetResolver>>()
+            .ok_or_else(|| NodeError::MissingInput("asset resolver".to_string()))?;
+        let image = resolver
+            .resolve(&self.asset)
+            .ok_or_else(|| NodeError::MissingInput(format!("asset {}", self.asset)))?;
+        Ok(NodeValue::Image(image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    struct SingleAsset(AssetId, Arc<DynamicImage>);
+
+    impl AssetResolver for SingleAsset {
+        fn resolve(&self, id: &AssetId) -> Option<Arc<DynamicImage>> {
+            (id == &self.0).then(|| self.1.clone())
+        }
+    }
+
+    #[test]
+    fn test_resolves_through_context() {
+        let id = AssetId::from_hash("abc");
+        let image = Arc::new(DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([9, 9, 9, 255]))));
+        let resolver: Arc<dyn AssetResolver> = Arc::new(SingleAsset(id.clone(), image));
+        let context = EvalContext::new().with(resolver);
+
+        let output = AssetRefNode::new(id).compute_with_context(&[], &context).unwrap();
+        let output = output.as_image().unwrap();
+        assert_eq!(output.width(), 2);
+
+        let missing = AssetRefNode::new(AssetId::from_hash("other")).compute_with_context(&[], &context);
+        assert!(matches!(missing, Err(NodeError::MissingInput(_))));
+    }
+
+    #[test]
+    fn test_requires_resolver() {
+        let result = AssetRefNode::new(AssetId::from_hash("abc")).compute(&[]);
+        assert!(matches!(result, Err(NodeError::MissingInput(_))));
+    }
+}
@@ -1,169 +1,248 @@
 //! Node factory implementations for creating standard node types.
-//! 
+//!
 //! This module provides factory implementations for all standard nodes,
 //! allowing them to be created dynamically with parameters from serialized data
 //! or through the UI.
 
-use anyhow::Result;
+use std::sync::Once;
 use serde_json::Value;
-use aurion_core::{NodeData, NodeFactory};
-use crate::{ImageNode, AiImageGenNode, ColorAdjustNode, filters::{GaussianBlurNode, BrightnessContrastNode, HSLNode, SharpenNode}};
+use aurion_core::{NodeData, NodeError, NodeFactory, NodeRegistry, ParameterSchema};
+use crate::filters::{BlurNode, BrightnessNode, ContrastNode, InvertNode, SharpenNode};
+use crate::mask::ApplyMaskNode;
+use crate::{BlendMode, BlendNode, ImageNode, OutputNode};
+
+/// Creates a standard node with default parameters, then applies each entry
+/// of the parameter object through [`NodeData::set_parameter`]. Parameter
+/// objects are checked against the factory's schema first, the default
+/// node's [`NodeData::parameter_schema`] unless given another, so nodes
+/// without one accept no parameters.
+pub struct StandardNodeFactory {
+    type_name: &'static str,
+    category: Option<&'static str>,
+    schema: ParameterSchema,
+    create_default: fn() -> Box<dyn NodeData>,
+}
 
-/// Factory for creating basic image nodes that can load and display images.
-pub struct ImageNodeFactory;
+impl StandardNodeFactory {
+    pub fn new(type_name: &'static str, create_default: fn() -> Box<dyn NodeData>) -> Self {
+        Self { type_name, category: None, schema: create_default().parameter_schema(), create_default }
+    }
 
-impl NodeFactory for ImageNodeFactory {
-    fn create(&self, _parameters: &Value) -> Result<Box<dyn NodeData>> {
-        Ok(Box::new(ImageNode::new()))
+    /// Lists the node under `category` in menus.
+    pub fn with_category(mut self, category: &'static str) -> Self {
+        self.category = Some(category);
+        self
     }
 
-    fn type_name(&self) -> &'static str {
-        "ImageNode"
+    pub fn with_schema(mut self, schema: ParameterSchema) -> Self {
+        self.schema = schema;
+        self
     }
 }
 
-/// Factory for creating AI-powered image generation nodes.
-pub struct AiImageGenNodeFactory;
-
-impl NodeFactory for AiImageGenNodeFactory {
-    fn create(&self, parameters: &Value) -> Result<Box<dyn NodeData>> {
-        let prompt = parameters.get("prompt")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string();
-        
-        Ok(Box::new(AiImageGenNode::new(prompt)))
+impl NodeFactory for StandardNodeFactory {
+    fn create(&self, parameters: &Value) -> Result<Box<dyn NodeData>, NodeError> {
+        let mut data = (self.create_default)();
+        if let Some(parameters) = parameters.as_object() {
+            for (name, value) in parameters {
+                data.set_parameter(name, value.clone())?;
+            }
+        }
+        Ok(data)
     }
 
     fn type_name(&self) -> &'static str {
-        "AiImageGenNode"
+        self.type_name
     }
-}
 
-/// Factory for creating color adjustment nodes.
-pub struct ColorAdjustNodeFactory;
-
-impl NodeFactory for ColorAdjustNodeFactory {
-    fn create(&self, parameters: &Value) -> Result<Box<dyn NodeData>> {
-        let brightness = parameters.get("brightness")
-            .and_then(|v| v.as_f64())
-            .map(|v| v as f32)
-            .unwrap_or(1.0);
-            
-        let contrast = parameters.get("contrast")
-            .and_then(|v| v.as_f64())
-            .map(|v| v as f32)
-            .unwrap_or(1.0);
-            
-        let saturation = parameters.get("saturation")
-            .and_then(|v| v.as_f64())
-            .map(|v| v as f32)
-            .unwrap_or(1.0);
-        
-        Ok(Box::new(ColorAdjustNode::new(brightness, contrast, saturation)))
+    fn category(&self) -> Option<&'static str> {
+        self.category
     }
 
-    fn type_name(&self) -> &'static str {
-        "ColorAdjustNode"
+    fn parameter_schema(&self) -> ParameterSchema {
+        self.schema.clone()
     }
-}
-
-/// Factory for creating Gaussian blur filter nodes.
-pub struct GaussianBlurFactory;
 
-impl NodeFactory for GaussianBlurFactory {
-    fn create(&self, parameters: &Value) -> Result<Box<dyn NodeData>> {
-        let sigma = parameters.get("sigma")
-            .and_then(|v| v.as_f64())
-            .map(|v| v as f32)
-            .unwrap_or(1.0);
-            
-        Ok(Box::new(GaussianBlurNode::new(sigma)))
+    fn validate_parameters(&self, parameters: &Value) -> Result<(), NodeError> {
+        self.schema.validate(self.type_name, parameters)
     }
+}
 
-    fn type_name(&self) -> &'static str {
-        "GaussianBlur"
+/// Factories for every standard node, named by the nodes' type names.
+pub fn standard_factories() -> Vec<StandardNodeFactory> {
+    fn factory(category: &'static str, type_name: &'static str, create_default: fn() -> Box<dyn NodeData>) -> StandardNodeFactory {
+        StandardNodeFactory::new(type_name, create_default).with_category(category)
     }
+    vec![
+        factory("Input", "ImageNode", || Box::new(ImageNode::new())),
+        factory("Output", "OutputNode", || Box::new(OutputNode::new())),
+        factory("Composite", "BlendNode", || Box::new(BlendNode::new(BlendMode::Normal))),
+        factory("Filter", "BrightnessNode", || Box::new(BrightnessNode::new(0.0))),
+        factory("Filter", "ContrastNode", || Box::new(ContrastNode::new(0.0))),
+        factory("Filter", "BlurNode", || Box::new(BlurNode::new(1.0))),
+        factory("Filter", "SharpenNode", || Box::new(SharpenNode::new(1.0, 0))),
+        factory("Filter", "InvertNode", || Box::new(InvertNode::new())),
+        factory("Composite", "ApplyMaskNode", || Box::new(ApplyMaskNode::new())),
+    ]
 }
 
-/// Factory for creating brightness/contrast adjustment nodes.
-pub struct BrightnessContrastFactory;
+/// Registers all standard node factories with the global registry. Calls
+/// after the first do nothing.
+pub fn register_standard_nodes() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        for factory in standard_factories() {
+            aurion_core::register_node_factory(factory);
+        }
+    });
+}
 
-impl NodeFactory for BrightnessContrastFactory {
-    fn create(&self, parameters: &Value) -> Result<Box<dyn NodeData>> {
-        let brightness = parameters.get("brightness")
-            .and_then(|v| v.as_f64())
-            .map(|v| v as f32)
-            .unwrap_or(0.0);
-            
-        let contrast = parameters.get("contrast")
-            .and_then(|v| v.as_f64())
-            .map(|v| v as f32)
-            .unwrap_or(0.0);
-            
-        Ok(Box::new(BrightnessContrastNode::new(brightness, contrast)))
+/// A registry holding only the standard nodes.
+pub fn standard_registry() -> NodeRegistry {
+    let mut registry = NodeRegistry::new();
+    for factory in standard_factories() {
+        registry.register(factory);
     }
+    registry
+}
 
-    fn type_name(&self) -> &'static str {
-        "BrightnessContrast"
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_factories_apply_parameters() {
+        let registry = standard_registry();
+        for factory in standard_factories() {
+            let node = registry.create_node(factory.type_name(), &json!({})).unwrap();
+            assert_eq!(node.data().type_name(), factory.type_name());
+        }
+
+        let blur = registry.create_node("BlurNode", &json!({ "sigma": 2.5 })).unwrap();
+        assert_eq!(blur.data().get_parameter("sigma"), Some(json!(2.5)));
+        let blend = registry.create_node("BlendNode", &json!({ "mode": "Multiply" })).unwrap();
+        assert_eq!(blend.data().get_parameter("mode"), Some(json!("Multiply")));
+
+        assert!(matches!(
+            registry.create_node("BlurNode", &json!({ "radius": 2 })),
+            Err(NodeError::InvalidParameter { .. })
+        ));
+        assert!(registry.create_node("BlendNode", &json!({ "mode": "Dodge" })).is_err());
     }
-}
 
-/// Factory for creating HSL adjustment nodes.
-pub struct HSLFactory;
-
-impl NodeFactory for HSLFactory {
-    fn create(&self, parameters: &Value) -> Result<Box<dyn NodeData>> {
-        let hue = parameters.get("hue")
-            .and_then(|v| v.as_f64())
-            .map(|v| v as f32)
-            .unwrap_or(0.0);
-            
-        let saturation = parameters.get("saturation")
-            .and_then(|v| v.as_f64())
-            .map(|v| v as f32)
-            .unwrap_or(0.0);
-            
-        let lightness = parameters.get("lightness")
-            .and_then(|v| v.as_f64())
-            .map(|v| v as f32)
-            .unwrap_or(0.0);
-            
-        Ok(Box::new(HSLNode::new(hue, saturation, lightness)))
+    #[test]
+    fn test_schemas_describe_the_nodes() {
+        let registry = standard_registry();
+        for factory in standard_factories() {
+            let schema = registry.get_schema(factory.type_name()).unwrap();
+            let node = registry.create_node(factory.type_name(), &schema.defaults()).unwrap();
+            assert_eq!(node.data().serialize_parameters(), schema.defaults(), "{}", factory.type_name());
+        }
+        assert_eq!(registry.get_schema("BlurNode").unwrap().get("sigma").unwrap().max, Some(100.0));
+        assert!(registry.get_schema("Missing").is_none());
+
+        assert!(registry.create_node("BrightnessNode", &json!({ "value": 2.0 })).is_err());
+        assert!(registry.create_node("SharpenNode", &json!({ "threshold": 2 })).is_ok());
+        assert!(registry.create_node("InvertNode", &json!({ "amount": 1 })).is_err());
+        assert!(registry.create_node("InvertNode", &json!("fast")).is_err());
     }
 
-    fn type_name(&self) -> &'static str {
-        "HSL"
+    #[test]
+    fn test_graph_parameters_are_checked() {
+        let registry = standard_registry();
+        let mut graph = aurion_core::NodeGraph::new();
+        let image = graph.add_node(registry.create_node("ImageNode", &json!({})).unwrap());
+        let blur = graph.add_node(registry.create_node("BlurNode", &json!({})).unwrap());
+        graph.connect(&image, &blur, "input").unwrap();
+
+        graph.set_node_parameter(&blur, "sigma", json!(3.0)).unwrap();
+        assert_eq!(graph.get_node(&blur).unwrap().read().data().get_parameter("sigma"), Some(json!(3.0)));
+
+        let reason = |name: &str, value: Value| match graph.set_node_parameter(&blur, name, value) {
+            Err(NodeError::InvalidParameter { reason, .. }) => reason,
+            other => panic!("expected an invalid parameter, got {:?}", other),
+        };
+        assert_eq!(reason("sigma", json!(-1.0)), "BlurNode expects 0 to 100, got -1.0");
+        assert_eq!(reason("radius", json!(2)), "BlurNode has no such parameter");
+        assert_eq!(graph.get_node(&blur).unwrap().read().data().get_parameter("sigma"), Some(json!(3.0)));
+
+        for factory in standard_factories() {
+            let node = registry.create_node(factory.type_name(), &json!({})).unwrap();
+            assert_eq!(node.data().parameter_schema(), factory.parameter_schema(), "{}", factory.type_name());
+        }
     }
-}
 
-/// Factory for creating image sharpening nodes.
-pub struct SharpenFactory;
+    #[test]
+    fn test_standard_nodes_are_categorized() {
+        let registry = standard_registry();
+        assert!(registry.get_uncategorized_types().is_empty());
+        let categorized: usize = registry.get_types_by_category().values().map(Vec::len).sum();
+        assert_eq!(categorized, standard_factories().len());
+        assert_eq!(registry.get_types_by_category()["Filter"], vec!["BlurNode", "BrightnessNode", "ContrastNode", "InvertNode", "SharpenNode"]);
+    }
 
-impl NodeFactory for SharpenFactory {
-    fn create(&self, parameters: &Value) -> Result<Box<dyn NodeData>> {
-        let amount = parameters.get("amount")
-            .and_then(|v| v.as_f64())
-            .map(|v| v as f32)
-            .unwrap_or(1.0);
-            
-        Ok(Box::new(SharpenNode::new(amount)))
+    #[test]
+    fn test_standard_nodes_declare_ports() {
+        let registry = standard_registry();
+        let inputs = |type_name: &str| -> Vec<String> {
+            let node = registry.create_node(type_name, &json!({})).unwrap();
+            assert_eq!(node.data().output_ports().len(), 1, "{}", type_name);
+            node.data().input_ports().into_iter().map(|port| port.name).collect()
+        };
+        assert!(inputs("ImageNode").is_empty());
+        assert_eq!(inputs("BlurNode"), ["input"]);
+        assert_eq!(inputs("BlendNode"), ["a", "b"]);
+        assert_eq!(inputs("ApplyMaskNode"), ["base", "effect", "mask"]);
+        for factory in standard_factories() {
+            inputs(factory.type_name());
+        }
     }
 
-    fn type_name(&self) -> &'static str {
-        "Sharpen"
+    #[test]
+    fn test_graphs_round_trip_through_json() {
+        use aurion_core::{NodeGraph, SerializedGraph};
+
+        let registry = standard_registry();
+        let mut graph = NodeGraph::new();
+        let image = graph.add_node(registry.create_node("ImageNode", &json!({})).unwrap());
+        let blur = graph.add_node(registry.create_node("BlurNode", &json!({ "sigma": 2.5 })).unwrap());
+        let blend = graph.add_node(registry.create_node("BlendNode", &json!({ "mode": "Multiply" })).unwrap());
+        graph.connect(&image, &blur, "input").unwrap();
+        graph.connect(&blur, &blend, "a").unwrap();
+        graph.connect(&image, &blend, "b").unwrap();
+        graph.get_node(&blur).unwrap().write().set_label(Some("Soften".to_string()));
+        graph.set_designated_output(Some(blend.clone())).unwrap();
+
+        let saved = graph.to_serialized();
+        let json = serde_json::to_string(&saved).unwrap();
+        let loaded = NodeGraph::from_serialized(&serde_json::from_str::<SerializedGraph>(&json).unwrap(), &registry).unwrap();
+        assert_eq!(loaded.to_serialized(), saved);
+        assert_eq!(loaded.content_hash(), graph.content_hash());
+
+        let node = |id| loaded.get_node(id).unwrap();
+        assert_eq!(node(&blur).read().data().get_parameter("sigma"), Some(json!(2.5)));
+        assert_eq!(node(&blur).read().label(), Some("Soften"));
+        assert_eq!(node(&blend).read().data().get_parameter("mode"), Some(json!("Multiply")));
+        assert_eq!(node(&blend).read().get_input("a"), Some(&blur));
+        assert_eq!(node(&blend).read().get_input("b"), Some(&image));
+        assert_eq!(loaded.designated_output(), Some(&blend));
     }
-}
 
-/// Registers all standard node factories with the global registry.
-pub fn register_standard_nodes() {
-    use aurion_core::register_node_factory;
-    
-    register_node_factory(ImageNodeFactory);
-    register_node_factory(AiImageGenNodeFactory);
-    register_node_factory(ColorAdjustNodeFactory);
-    register_node_factory(GaussianBlurFactory);
-    register_node_factory(BrightnessContrastFactory);
-    register_node_factory(HSLFactory);
-    register_node_factory(SharpenFactory);
-} 
\ No newline at end of file
+    #[test]
+    fn test_renamed_types_save_under_the_new_name() {
+        use aurion_core::{NodeGraph, SerializedGraph};
+
+        let mut registry = standard_registry();
+        registry.register_alias("GaussianBlur", "BlurNode").unwrap();
+        let saved: SerializedGraph = serde_json::from_value(json!({
+            "nodes": [{ "id": "00000000-0000-0000-0000-000000000001", "type": "GaussianBlur", "parameters": { "sigma": 3.0 } }]
+        }))
+        .unwrap();
+
+        let loaded = NodeGraph::from_serialized(&saved, &registry).unwrap();
+        let migrated = loaded.to_serialized();
+        assert_eq!(migrated.nodes[0].type_name, "BlurNode");
+        assert_eq!(migrated.nodes[0].parameters, json!({ "sigma": 3.0 }));
+    }
+}
@@ -5,10 +5,17 @@
 //! version based on its parameters.
 
 use std::any::Any;
-use aurion_core::{NodeData, NodeError};
+use aurion_core::{NodeData, NodeError, NodeValue, ParameterSchema, ParameterSpec, PortSpec};
+use serde_json::Value;
+use crate::{float_parameter, unknown_parameter};
 use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
 
-#[derive(Debug)]
+// Blur radius shared by the blurring filters
+fn sigma() -> ParameterSpec {
+    ParameterSpec::float("sigma", 1.0).with_range(0.0, 100.0)
+}
+
+#[derive(Debug, Clone)]
 pub struct BrightnessNode {
     value: f32,
 }
@@ -32,7 +39,43 @@ impl NodeData for BrightnessNode {
         "BrightnessNode"
     }
 
-    fn compute(&self, inputs: &[Box<dyn Any>]) -> Result<Box<dyn Any>, NodeError> {
+    fn input_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_input()]
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        Some(crate::hash_value(&self.value.to_bits()))
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<Value> {
+        (name == "value").then(|| Value::from(self.value))
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &["value"]
+    }
+
+    fn parameter_schema(&self) -> ParameterSchema {
+        ParameterSchema::new().with(ParameterSpec::float("value", 0.0).with_range(-1.0, 1.0))
+    }
+
+    fn set_parameter(&mut self, name: &str, value: Value) -> Result<(), NodeError> {
+        match name {
+            "value" => self.value = float_parameter(self.type_name(), name, &value)?,
+            _ => return Err(unknown_parameter(self.type_name(), name)),
+        }
+        Ok(())
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
         if inputs.len() != 1 {
             return Err(NodeError::InvalidInputType {
                 expected: "one image input".to_string(),
@@ -40,20 +83,15 @@ impl NodeData for BrightnessNode {
             });
         }
 
-        let input = inputs[0]
-            .downcast_ref::<DynamicImage>()
-            .ok_or_else(|| NodeError::InvalidInputType {
-                expected: "DynamicImage".to_string(),
-                actual: "unknown".to_string(),
-            })?;
+        let input = inputs[0].try_image()?;
 
         let output = input.clone();
         output.adjust_contrast(self.value);
-        Ok(Box::new(output))
+        Ok(NodeValue::image(output))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ContrastNode {
     value: f32,
 }
@@ -77,7 +115,43 @@ impl NodeData for ContrastNode {
         "ContrastNode"
     }
 
-    fn compute(&self, inputs: &[Box<dyn Any>]) -> Result<Box<dyn Any>, NodeError> {
+    fn input_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_input()]
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        Some(crate::hash_value(&self.value.to_bits()))
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<Value> {
+        (name == "value").then(|| Value::from(self.value))
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &["value"]
+    }
+
+    fn parameter_schema(&self) -> ParameterSchema {
+        ParameterSchema::new().with(ParameterSpec::float("value", 0.0).with_range(-100.0, 100.0))
+    }
+
+    fn set_parameter(&mut self, name: &str, value: Value) -> Result<(), NodeError> {
+        match name {
+            "value" => self.value = float_parameter(self.type_name(), name, &value)?,
+            _ => return Err(unknown_parameter(self.type_name(), name)),
+        }
+        Ok(())
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
         if inputs.len() != 1 {
             return Err(NodeError::InvalidInputType {
                 expected: "one image input".to_string(),
@@ -85,20 +159,15 @@ impl NodeData for ContrastNode {
             });
         }
 
-        let input = inputs[0]
-            .downcast_ref::<DynamicImage>()
-            .ok_or_else(|| NodeError::InvalidInputType {
-                expected: "DynamicImage".to_string(),
-                actual: "unknown".to_string(),
-            })?;
+        let input = inputs[0].try_image()?;
 
         let output = input.clone();
         output.adjust_contrast(self.value);
-        Ok(Box::new(output))
+        Ok(NodeValue::image(output))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BlurNode {
     sigma: f32,
 }
@@ -122,7 +191,43 @@ impl NodeData for BlurNode {
         "BlurNode"
     }
 
-    fn compute(&self, inputs: &[Box<dyn Any>]) -> Result<Box<dyn Any>, NodeError> {
+    fn input_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_input()]
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        Some(crate::hash_value(&self.sigma.to_bits()))
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<Value> {
+        (name == "sigma").then(|| Value::from(self.sigma))
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &["sigma"]
+    }
+
+    fn parameter_schema(&self) -> ParameterSchema {
+        ParameterSchema::new().with(sigma())
+    }
+
+    fn set_parameter(&mut self, name: &str, value: Value) -> Result<(), NodeError> {
+        match name {
+            "sigma" => self.sigma = float_parameter(self.type_name(), name, &value)?,
+            _ => return Err(unknown_parameter(self.type_name(), name)),
+        }
+        Ok(())
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
         if inputs.len() != 1 {
             return Err(NodeError::InvalidInputType {
                 expected: "one image input".to_string(),
@@ -130,19 +235,97 @@ impl NodeData for BlurNode {
             });
         }
 
-        let input = inputs[0]
-            .downcast_ref::<DynamicImage>()
-            .ok_or_else(|| NodeError::InvalidInputType {
-                expected: "DynamicImage".to_string(),
-                actual: "unknown".to_string(),
-            })?;
+        let input = inputs[0].try_image()?;
 
         let output = input.blur(self.sigma);
-        Ok(Box::new(output))
+        Ok(NodeValue::image(output))
     }
 }
 
-#[derive(Debug)]
+/// Unsharp masking: adds back the difference from a blur of `sigma`
+/// wherever it exceeds `threshold`.
+#[derive(Debug, Clone)]
+pub struct SharpenNode {
+    sigma: f32,
+    threshold: i32,
+}
+
+impl SharpenNode {
+    pub fn new(sigma: f32, threshold: i32) -> Self {
+        Self { sigma, threshold }
+    }
+}
+
+impl NodeData for SharpenNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        "SharpenNode"
+    }
+
+    fn input_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_input()]
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        Some(crate::hash_value(&(self.sigma.to_bits(), self.threshold)))
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<Value> {
+        match name {
+            "sigma" => Some(Value::from(self.sigma)),
+            "threshold" => Some(Value::from(self.threshold)),
+            _ => None,
+        }
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &["sigma", "threshold"]
+    }
+
+    fn parameter_schema(&self) -> ParameterSchema {
+        ParameterSchema::new().with(sigma()).with(ParameterSpec::int("threshold", 0).with_range(0.0, 255.0))
+    }
+
+    fn set_parameter(&mut self, name: &str, value: Value) -> Result<(), NodeError> {
+        match name {
+            "sigma" => self.sigma = float_parameter(self.type_name(), name, &value)?,
+            "threshold" => self.threshold = float_parameter(self.type_name(), name, &value)?.round() as i32,
+            _ => return Err(unknown_parameter(self.type_name(), name)),
+        }
+        Ok(())
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+        if inputs.len() != 1 {
+            return Err(NodeError::InvalidInputType {
+                expected: "one image input".to_string(),
+                actual: format!("{} inputs", inputs.len()),
+            });
+        }
+
+        let input = inputs[0].try_image()?;
+
+        let output = input.unsharpen(self.sigma, self.threshold);
+        Ok(NodeValue::image(output))
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct InvertNode;
 
 impl InvertNode {
@@ -164,7 +347,23 @@ impl NodeData for InvertNode {
         "InvertNode"
     }
 
-    fn compute(&self, inputs: &[Box<dyn Any>]) -> Result<Box<dyn Any>, NodeError> {
+    fn input_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_input()]
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        Some(0)
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
         if inputs.len() != 1 {
             return Err(NodeError::InvalidInputType {
                 expected: "one image input".to_string(),
@@ -172,12 +371,7 @@ impl NodeData for InvertNode {
             });
         }
 
-        let input = inputs[0]
-            .downcast_ref::<DynamicImage>()
-            .ok_or_else(|| NodeError::InvalidInputType {
-                expected: "DynamicImage".to_string(),
-                actual: "unknown".to_string(),
-            })?;
+        let input = inputs[0].try_image()?;
 
         let mut output = RgbaImage::new(input.width(), input.height());
         
@@ -191,6 +385,6 @@ impl NodeData for InvertNode {
             ]);
         }
 
-        Ok(Box::new(DynamicImage::ImageRgba8(output)))
+        Ok(NodeValue::image(DynamicImage::ImageRgba8(output)))
     }
 } 
\ No newline at end of file
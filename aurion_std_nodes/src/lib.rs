@@ -1,21 +1,74 @@
 use std::any::Any;
-use aurion_core::{NodeData, NodeError};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use aurion_core::{NodeData, NodeError, NodeValue, ParameterSchema, ParameterSpec, PortSpec};
 use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba};
+use serde_json::Value;
 
+pub mod assets;
+pub mod factories;
 pub mod filters;
+pub mod mask;
+pub mod resample;
+pub mod transform;
 
-#[derive(Debug)]
+/// Hashes a node parameter for [`NodeData::content_hash`].
+pub(crate) fn hash_value<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub(crate) fn unknown_parameter(node: &str, name: &str) -> NodeError {
+    NodeError::InvalidParameter {
+        name: name.to_string(),
+        reason: format!("{} has no such parameter", node),
+    }
+}
+
+/// Reads a numeric value for [`NodeData::set_parameter`].
+pub(crate) fn float_parameter(node: &str, name: &str, value: &Value) -> Result<f32, NodeError> {
+    value.as_f64().map(|v| v as f32).ok_or_else(|| NodeError::InvalidParameter {
+        name: name.to_string(),
+        reason: format!("{} expects a number, got {}", node, value),
+    })
+}
+
+pub(crate) fn hash_image(image: &DynamicImage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    image.dimensions().hash(&mut hasher);
+    image.color().hash(&mut hasher);
+    image.as_bytes().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Debug, Clone)]
 pub struct ImageNode {
     image: Option<DynamicImage>,
+    // Hashing pixels is costly, so it's done once when the image is set
+    hash: u64,
 }
 
 impl ImageNode {
     pub fn new() -> Self {
-        Self { image: None }
+        Self::from_option(None)
     }
 
     pub fn with_image(image: DynamicImage) -> Self {
-        Self { image: Some(image) }
+        Self::from_option(Some(image))
+    }
+
+    fn from_option(image: Option<DynamicImage>) -> Self {
+        let hash = image.as_ref().map_or(0, hash_image);
+        Self { image, hash }
+    }
+
+    pub fn image(&self) -> Option<&DynamicImage> {
+        self.image.as_ref()
+    }
+
+    pub fn set_image(&mut self, image: Option<DynamicImage>) {
+        *self = Self::from_option(image);
     }
 }
 
@@ -32,7 +85,23 @@ impl NodeData for ImageNode {
         "ImageNode"
     }
 
-    fn compute(&self, inputs: &[Box<dyn Any>]) -> Result<Box<dyn Any>, NodeError> {
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn estimated_memory(&self) -> usize {
+        std::mem::size_of_val(self) + self.image.as_ref().map_or(0, |image| image.as_bytes().len())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        Some(self.hash)
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
         if !inputs.is_empty() {
             return Err(NodeError::InvalidInputType {
                 expected: "none".to_string(),
@@ -41,13 +110,13 @@ impl NodeData for ImageNode {
         }
 
         match &self.image {
-            Some(img) => Ok(Box::new(img.clone())),
+            Some(img) => Ok(NodeValue::image(img.clone())),
             None => Err(NodeError::MissingInput("image".to_string())),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OutputNode {
     image: Option<DynamicImage>,
 }
@@ -71,7 +140,27 @@ impl NodeData for OutputNode {
         "OutputNode"
     }
 
-    fn compute(&self, inputs: &[Box<dyn Any>]) -> Result<Box<dyn Any>, NodeError> {
+    fn input_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_input()]
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn estimated_memory(&self) -> usize {
+        std::mem::size_of_val(self) + self.image.as_ref().map_or(0, |image| image.as_bytes().len())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        Some(0)
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
         if inputs.len() != 1 {
             return Err(NodeError::InvalidInputType {
                 expected: "one image input".to_string(),
@@ -79,18 +168,14 @@ impl NodeData for OutputNode {
             });
         }
 
-        let input = inputs[0]
-            .downcast_ref::<DynamicImage>()
-            .ok_or_else(|| NodeError::InvalidInputType {
-                expected: "DynamicImage".to_string(),
-                actual: "unknown".to_string(),
-            })?;
+        inputs[0].try_image()?;
 
-        Ok(Box::new(input.clone()))
+        // Shared, not copied
+        Ok(inputs[0].clone())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BlendNode {
     mode: BlendMode,
 }
@@ -102,6 +187,22 @@ pub enum BlendMode {
     Multiply,
 }
 
+impl BlendMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlendMode::Normal => "Normal",
+            BlendMode::Add => "Add",
+            BlendMode::Multiply => "Multiply",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [BlendMode::Normal, BlendMode::Add, BlendMode::Multiply]
+            .into_iter()
+            .find(|mode| mode.name() == name)
+    }
+}
+
 impl BlendNode {
     pub fn new(mode: BlendMode) -> Self {
         Self { mode }
@@ -141,7 +242,47 @@ impl NodeData for BlendNode {
         "BlendNode"
     }
 
-    fn compute(&self, inputs: &[Box<dyn Any>]) -> Result<Box<dyn Any>, NodeError> {
+    fn input_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::required("a", PortSpec::IMAGE), PortSpec::required("b", PortSpec::IMAGE)]
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        Some(self.mode as u64)
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<Value> {
+        (name == "mode").then(|| Value::from(self.mode.name()))
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &["mode"]
+    }
+
+    fn parameter_schema(&self) -> ParameterSchema {
+        let modes = [BlendMode::Normal, BlendMode::Add, BlendMode::Multiply].map(|mode| mode.name());
+        ParameterSchema::new().with(ParameterSpec::one_of("mode", &modes, BlendMode::Normal.name()))
+    }
+
+    fn set_parameter(&mut self, name: &str, value: Value) -> Result<(), NodeError> {
+        if name != "mode" {
+            return Err(unknown_parameter(self.type_name(), name));
+        }
+        self.mode = value.as_str().and_then(BlendMode::from_name).ok_or_else(|| NodeError::InvalidParameter {
+            name: name.to_string(),
+            reason: format!("{} has no blend mode {}", self.type_name(), value),
+        })?;
+        Ok(())
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
         if inputs.len() != 2 {
             return Err(NodeError::InvalidInputType {
                 expected: "two image inputs".to_string(),
@@ -149,19 +290,9 @@ impl NodeData for BlendNode {
             });
         }
 
-        let image1 = inputs[0]
-            .downcast_ref::<DynamicImage>()
-            .ok_or_else(|| NodeError::InvalidInputType {
-                expected: "DynamicImage".to_string(),
-                actual: "unknown".to_string(),
-            })?;
+        let image1 = inputs[0].try_image()?;
 
-        let image2 = inputs[1]
-            .downcast_ref::<DynamicImage>()
-            .ok_or_else(|| NodeError::InvalidInputType {
-                expected: "DynamicImage".to_string(),
-                actual: "unknown".to_string(),
-            })?;
+        let image2 = inputs[1].try_image()?;
 
         let mut output = ImageBuffer::new(image1.width(), image1.height());
 
@@ -171,6 +302,6 @@ impl NodeData for BlendNode {
             *pixel = self.blend_pixels(&p1, &p2);
         }
 
-        Ok(Box::new(DynamicImage::ImageRgba8(output)))
+        Ok(NodeValue::image(DynamicImage::ImageRgba8(output)))
     }
 }
@@ -0,0 +1,139 @@
+//! Resampling helpers and nodes for producing reduced-resolution images.
+
+use std::any::Any;
+use aurion_core::{NodeData, NodeError, NodeValue, PortSpec};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+/// Computes the size that fits `width`×`height` within `max_dim` on its longest
+/// side while keeping the aspect ratio. Images already small enough keep their size.
+pub fn fit_within(width: u32, height: u32, max_dim: u32) -> (u32, u32) {
+    let longest = width.max(height);
+    if longest <= max_dim || longest == 0 {
+        return (width, height);
+    }
+    let scale = max_dim as f64 / longest as f64;
+    let fitted_width = ((width as f64 * scale).round() as u32).max(1);
+    let fitted_height = ((height as f64 * scale).round() as u32).max(1);
+    (fitted_width, fitted_height)
+}
+
+/// Downsamples `image` with a box filter so its longest side is at most `max_dim`.
+///
+/// Every output pixel is the average of the source pixels it covers, which
+/// avoids the aliasing of point sampling on large reductions.
+pub fn box_downsample(image: &DynamicImage, max_dim: u32) -> DynamicImage {
+    let (width, height) = fit_within(image.width(), image.height(), max_dim);
+    if (width, height) == (image.width(), image.height()) {
+        return image.clone();
+    }
+
+    let source = image.to_rgba8();
+    let (source_width, source_height) = source.dimensions();
+    let mut output = RgbaImage::new(width, height);
+
+    for (x, y, pixel) in output.enumerate_pixels_mut() {
+        let x0 = (x as u64 * source_width as u64 / width as u64) as u32;
+        let x1 = (((x as u64 + 1) * source_width as u64 / width as u64) as u32).max(x0 + 1);
+        let y0 = (y as u64 * source_height as u64 / height as u64) as u32;
+        let y1 = (((y as u64 + 1) * source_height as u64 / height as u64) as u32).max(y0 + 1);
+
+        let mut sum = [0u64; 4];
+        for sy in y0..y1.min(source_height) {
+            for sx in x0..x1.min(source_width) {
+                let p = source.get_pixel(sx, sy);
+                for i in 0..4 {
+                    sum[i] += p[i] as u64;
+                }
+            }
+        }
+        let count = ((x1.min(source_width) - x0) * (y1.min(source_height) - y0)).max(1) as u64;
+        *pixel = Rgba([
+            ((sum[0] + count / 2) / count) as u8,
+            ((sum[1] + count / 2) / count) as u8,
+            ((sum[2] + count / 2) / count) as u8,
+            ((sum[3] + count / 2) / count) as u8,
+        ]);
+    }
+
+    DynamicImage::ImageRgba8(output)
+}
+
+#[derive(Debug, Clone)]
+pub struct DownsampleNode {
+    max_dim: u32,
+}
+
+impl DownsampleNode {
+    pub fn new(max_dim: u32) -> Self {
+        Self { max_dim: max_dim.max(1) }
+    }
+}
+
+impl NodeData for DownsampleNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        "DownsampleNode"
+    }
+
+    fn input_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_input()]
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        Some(crate::hash_value(&self.max_dim))
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+        if inputs.len() != 1 {
+            return Err(NodeError::InvalidInputType {
+                expected: "one image input".to_string(),
+                actual: format!("{} inputs", inputs.len()),
+            });
+        }
+
+        let input = inputs[0].try_image()?;
+
+        Ok(NodeValue::image(box_downsample(input, self.max_dim)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_within_keeps_aspect_ratio() {
+        assert_eq!(fit_within(400, 200, 100), (100, 50));
+        assert_eq!(fit_within(200, 400, 100), (50, 100));
+        assert_eq!(fit_within(50, 20, 100), (50, 20));
+        assert_eq!(fit_within(1000, 1, 10), (10, 1));
+    }
+
+    #[test]
+    fn test_box_downsample_averages() {
+        let mut image = RgbaImage::new(2, 2);
+        image.put_pixel(0, 0, Rgba([0, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([255, 255, 255, 255]));
+        image.put_pixel(0, 1, Rgba([0, 0, 0, 255]));
+        image.put_pixel(1, 1, Rgba([255, 255, 255, 255]));
+
+        let result = box_downsample(&DynamicImage::ImageRgba8(image), 1).to_rgba8();
+        assert_eq!(result.dimensions(), (1, 1));
+        assert_eq!(*result.get_pixel(0, 0), Rgba([128, 128, 128, 255]));
+    }
+}
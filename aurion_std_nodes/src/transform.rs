@@ -0,0 +1,313 @@
+//! Affine transforms and resampling of images.
+
+use std::any::Any;
+use aurion_core::{NodeData, NodeError, NodeValue, PortSpec};
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+// Sample positions this close to a pixel center are snapped onto it, so
+// translations and quarter turns reproduce source pixels exactly.
+const SNAP_EPSILON: f64 = 1e-6;
+
+/// A 2D affine map `(x, y) -> (a*x + c*y + e, b*x + d*y + f)` in pixel
+/// coordinates, with y pointing down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Affine2 {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+    pub e: f64,
+    pub f: f64,
+}
+
+impl Affine2 {
+    pub const IDENTITY: Self = Self { a: 1.0, b: 0.0, c: 0.0, d: 1.0, e: 0.0, f: 0.0 };
+
+    pub fn translate(x: f64, y: f64) -> Self {
+        Self { e: x, f: y, ..Self::IDENTITY }
+    }
+
+    pub fn scale(x: f64, y: f64) -> Self {
+        Self { a: x, d: y, ..Self::IDENTITY }
+    }
+
+    /// Rotation about the origin. Positive angles turn clockwise on screen.
+    pub fn rotate_degrees(degrees: f64) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Self { a: cos, b: sin, c: -sin, d: cos, e: 0.0, f: 0.0 }
+    }
+
+    /// The map that applies `self` first and then `next`.
+    pub fn then(&self, next: &Affine2) -> Self {
+        Self {
+            a: next.a * self.a + next.c * self.b,
+            b: next.b * self.a + next.d * self.b,
+            c: next.a * self.c + next.c * self.d,
+            d: next.b * self.c + next.d * self.d,
+            e: next.a * self.e + next.c * self.f + next.e,
+            f: next.b * self.e + next.d * self.f + next.f,
+        }
+    }
+
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        (self.a * x + self.c * y + self.e, self.b * x + self.d * y + self.f)
+    }
+
+    /// Returns `None` for maps that collapse the plane, e.g. a zero scale.
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.a * self.d - self.b * self.c;
+        if det.abs() < f64::EPSILON {
+            return None;
+        }
+        let a = self.d / det;
+        let b = -self.b / det;
+        let c = -self.c / det;
+        let d = self.a / det;
+        Some(Self {
+            a,
+            b,
+            c,
+            d,
+            e: -(a * self.e + c * self.f),
+            f: -(b * self.e + d * self.f),
+        })
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::IDENTITY
+    }
+}
+
+impl Default for Affine2 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// How [`transform_image_with`] samples the source between pixel centers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ResampleFilter {
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+fn snap(value: f64) -> f64 {
+    let rounded = value.round();
+    if (value - rounded).abs() < SNAP_EPSILON {
+        rounded
+    } else {
+        value
+    }
+}
+
+/// Bilinear sample at pixel-center coordinates; outside the image is transparent.
+fn sample_bilinear(source: &RgbaImage, x: f64, y: f64) -> Rgba<u8> {
+    let (x, y) = (snap(x), snap(y));
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let tx = x - x0;
+    let ty = y - y0;
+    let (width, height) = (source.width() as i64, source.height() as i64);
+
+    // Interpolate premultiplied so transparent neighbours don't darken edges
+    let mut sum = [0f64; 4];
+    for (dx, dy, weight) in [
+        (0, 0, (1.0 - tx) * (1.0 - ty)),
+        (1, 0, tx * (1.0 - ty)),
+        (0, 1, (1.0 - tx) * ty),
+        (1, 1, tx * ty),
+    ] {
+        if weight == 0.0 {
+            continue;
+        }
+        let sx = x0 as i64 + dx;
+        let sy = y0 as i64 + dy;
+        if sx < 0 || sy < 0 || sx >= width || sy >= height {
+            continue;
+        }
+        let p = source.get_pixel(sx as u32, sy as u32);
+        let alpha = p[3] as f64 / 255.0;
+        sum[0] += p[0] as f64 * alpha * weight;
+        sum[1] += p[1] as f64 * alpha * weight;
+        sum[2] += p[2] as f64 * alpha * weight;
+        sum[3] += alpha * weight;
+    }
+
+    if sum[3] <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let unpremultiply = |c: f64| (c / sum[3]).round().clamp(0.0, 255.0) as u8;
+    Rgba([
+        unpremultiply(sum[0]),
+        unpremultiply(sum[1]),
+        unpremultiply(sum[2]),
+        (sum[3] * 255.0).round().clamp(0.0, 255.0) as u8,
+    ])
+}
+
+fn sample_nearest(source: &RgbaImage, x: f64, y: f64) -> Rgba<u8> {
+    // Pixel centers sit on integers here, so rounding picks the closest one
+    let (sx, sy) = (snap(x).round(), snap(y).round());
+    if sx < 0.0 || sy < 0.0 || sx >= source.width() as f64 || sy >= source.height() as f64 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    *source.get_pixel(sx as u32, sy as u32)
+}
+
+/// Maps `image` through `transform` onto a transparent `width`×`height` image.
+///
+/// Every output pixel center is mapped back into the source and sampled
+/// bilinearly. A degenerate transform yields a fully transparent result.
+pub fn transform_image(image: &DynamicImage, transform: &Affine2, width: u32, height: u32) -> RgbaImage {
+    transform_image_with(image, transform, width, height, ResampleFilter::Bilinear)
+}
+
+/// Like [`transform_image`] with an explicit sampling filter.
+pub fn transform_image_with(
+    image: &DynamicImage,
+    transform: &Affine2,
+    width: u32,
+    height: u32,
+    filter: ResampleFilter,
+) -> RgbaImage {
+    let mut output = RgbaImage::new(width, height);
+    let Some(inverse) = transform.inverse() else {
+        return output;
+    };
+    let source = image.to_rgba8();
+
+    for (x, y, pixel) in output.enumerate_pixels_mut() {
+        let (sx, sy) = inverse.apply(x as f64 + 0.5, y as f64 + 0.5);
+        *pixel = match filter {
+            ResampleFilter::Nearest => sample_nearest(&source, sx - 0.5, sy - 0.5),
+            ResampleFilter::Bilinear => sample_bilinear(&source, sx - 0.5, sy - 0.5),
+        };
+    }
+    output
+}
+
+/// Applies an affine transform to its input image, keeping the input's size.
+#[derive(Debug, Clone)]
+pub struct TransformNode {
+    transform: Affine2,
+}
+
+impl TransformNode {
+    pub fn new(transform: Affine2) -> Self {
+        Self { transform }
+    }
+
+    pub fn transform(&self) -> &Affine2 {
+        &self.transform
+    }
+
+    pub fn set_transform(&mut self, transform: Affine2) {
+        self.transform = transform;
+    }
+}
+
+impl NodeData for TransformNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        "TransformNode"
+    }
+
+    fn input_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_input()]
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn content_hash(&self) -> Option<u64> {
+        let t = &self.transform;
+        Some(crate::hash_value(&[t.a, t.b, t.c, t.d, t.e, t.f].map(f64::to_bits)))
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+        if inputs.len() != 1 {
+            return Err(NodeError::InvalidInputType {
+                expected: "one image input".to_string(),
+                actual: format!("{} inputs", inputs.len()),
+            });
+        }
+
+        let input = inputs[0].try_image()?;
+
+        let output = transform_image(input, &self.transform, input.width(), input.height());
+        Ok(NodeValue::image(DynamicImage::ImageRgba8(output)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x * 20) as u8, (y * 20) as u8, 7, 255])
+        }))
+    }
+
+    #[test]
+    fn test_inverse_round_trips() {
+        let transform = Affine2::scale(2.0, 3.0)
+            .then(&Affine2::rotate_degrees(30.0))
+            .then(&Affine2::translate(5.0, -4.0));
+        let (x, y) = transform.apply(1.5, 2.5);
+        let (bx, by) = transform.inverse().unwrap().apply(x, y);
+        assert!((bx - 1.5).abs() < 1e-9 && (by - 2.5).abs() < 1e-9);
+        assert!(Affine2::scale(0.0, 1.0).inverse().is_none());
+    }
+
+    #[test]
+    fn test_translation_is_exact() {
+        let image = numbered(4, 4);
+        let output = transform_image(&image, &Affine2::translate(2.0, 1.0), 8, 8);
+        let source = image.to_rgba8();
+        assert_eq!(output.get_pixel(2, 1), source.get_pixel(0, 0));
+        assert_eq!(output.get_pixel(5, 4), source.get_pixel(3, 3));
+        assert_eq!(output.get_pixel(0, 0)[3], 0);
+        assert_eq!(output.get_pixel(6, 4)[3], 0);
+    }
+
+    #[test]
+    fn test_nearest_scaling_duplicates_pixels() {
+        let image = numbered(2, 1);
+        let output = transform_image_with(&image, &Affine2::scale(2.0, 1.0), 4, 1, ResampleFilter::Nearest);
+        let source = image.to_rgba8();
+        assert_eq!(output.get_pixel(0, 0), source.get_pixel(0, 0));
+        assert_eq!(output.get_pixel(1, 0), source.get_pixel(0, 0));
+        assert_eq!(output.get_pixel(3, 0), source.get_pixel(1, 0));
+    }
+
+    #[test]
+    fn test_transform_node_quarter_turn() {
+        let image = numbered(4, 4);
+        let center = Affine2::translate(-2.0, -2.0)
+            .then(&Affine2::rotate_degrees(90.0))
+            .then(&Affine2::translate(2.0, 2.0));
+        let output = TransformNode::new(center).compute(&[NodeValue::image(image.clone())]).unwrap();
+        let output = output.as_image().unwrap().to_rgba8();
+        let source = image.to_rgba8();
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(output.get_pixel(3 - y, x), source.get_pixel(x, y));
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Layer access that can't hang on a lock held by another thread.
+//!
+//! Holding one layer's lock while waiting for another, e.g. the UI thread
+//! editing a layer while a render reads it, can deadlock. The closure-based
+//! accessors here scope the lock to the closure and give up after the
+//! document's lock timeout with [`DocumentError::LockTimeout`].
+
+use std::time::Duration;
+use crate::{Document, DocumentError, Layer, LayerId};
+
+/// Lock timeout of new documents.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl Document {
+    /// Calls `f` with the layer, waiting at most the lock timeout for
+    /// another thread writing it.
+    pub fn with_layer<R>(&self, id: &LayerId, f: impl FnOnce(&Layer) -> R) -> Result<R, DocumentError> {
+        let layer = self.layers.get(id).ok_or(DocumentError::LayerNotFound(id.0))?;
+        let layer = layer
+            .try_read_for(self.lock_timeout)
+            .ok_or(DocumentError::LockTimeout(id.0))?;
+        Ok(f(&layer))
+    }
+
+    /// Calls `f` with the layer locked for writing, waiting at most the lock
+    /// timeout for other threads using it.
+    pub fn with_layer_mut<R>(&self, id: &LayerId, f: impl FnOnce(&mut Layer) -> R) -> Result<R, DocumentError> {
+        let layer = self.layers.get(id).ok_or(DocumentError::LayerNotFound(id.0))?;
+        let mut layer = layer
+            .try_write_for(self.lock_timeout)
+            .ok_or(DocumentError::LockTimeout(id.0))?;
+        Ok(f(&mut layer))
+    }
+
+    pub fn lock_timeout(&self) -> Duration {
+        self.lock_timeout
+    }
+
+    pub fn set_lock_timeout(&mut self, timeout: Duration) {
+        self.lock_timeout = timeout;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_concurrent_writers_complete() {
+        let mut doc = Document::with_size(4, 4);
+        let id = doc.add_layer();
+        let doc = Arc::new(doc);
+
+        let writers: Vec<_> = (0..2)
+            .map(|writer| {
+                let doc = doc.clone();
+                let id = id.clone();
+                thread::spawn(move || {
+                    for step in 0..2000 {
+                        doc.with_layer_mut(&id, |layer| {
+                            let name = format!("Writer {} step {}", writer, step);
+                            layer.set_name(name);
+                            layer.set_opacity((step % 100) as f32 / 100.0);
+                        })
+                        .unwrap();
+                        doc.with_layer(&id, |layer| layer.opacity()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(doc.with_layer(&id, |layer| layer.opacity()).unwrap(), 0.99);
+    }
+
+    #[test]
+    fn test_held_lock_times_out() {
+        let mut doc = Document::with_size(4, 4);
+        let id = doc.add_layer();
+        doc.set_lock_timeout(Duration::from_millis(20));
+
+        let layer = doc.get_layer(&id).unwrap();
+        let guard = layer.write();
+        assert!(matches!(doc.with_layer(&id, |_| ()), Err(DocumentError::LockTimeout(_))));
+        assert!(matches!(doc.with_layer_mut(&id, |_| ()), Err(DocumentError::LockTimeout(_))));
+        drop(guard);
+
+        assert!(doc.with_layer(&id, |_| ()).is_ok());
+        assert!(matches!(doc.with_layer(&LayerId::new(), |_| ()), Err(DocumentError::LayerNotFound(_))));
+    }
+}
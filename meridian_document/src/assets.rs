@@ -0,0 +1,235 @@
+//! Shared image resources referenced by layers through [`AssetRefNode`]s.
+//!
+//! Assets are deduplicated by content hash, so adding the same image twice
+//! yields the same [`AssetId`] and a single stored copy.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use aurion_core::EvalContext;
+use aurion_std_nodes::assets::{AssetId, AssetRefNode, AssetResolver};
+use image::DynamicImage;
+use parking_lot::RwLock;
+use tracing::debug;
+use crate::package::content_hash;
+use crate::{Document, Layer, LayerId};
+
+/// A cheaply cloneable handle to the document's assets.
+#[derive(Clone, Default)]
+pub struct AssetStore {
+    assets: Arc<RwLock<HashMap<AssetId, Arc<DynamicImage>>>>,
+}
+
+impl AssetStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, image: DynamicImage) -> AssetId {
+        let id = AssetId::from_hash(content_hash(&image));
+        self.assets
+            .write()
+            .entry(id.clone())
+            .or_insert_with(|| Arc::new(image));
+        id
+    }
+
+    /// Inserts an image under a known id, as when loading a saved document.
+    pub(crate) fn insert_with_id(&self, id: AssetId, image: DynamicImage) {
+        self.assets.write().insert(id, Arc::new(image));
+    }
+
+    /// A store holding the same images that is unaffected by later
+    /// changes to this one. Images are shared, not copied.
+    pub fn detached(&self) -> Self {
+        Self { assets: Arc::new(RwLock::new(self.assets.read().clone())) }
+    }
+
+    pub fn get(&self, id: &AssetId) -> Option<Arc<DynamicImage>> {
+        self.assets.read().get(id).cloned()
+    }
+
+    pub fn contains(&self, id: &AssetId) -> bool {
+        self.assets.read().contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.assets.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assets.read().is_empty()
+    }
+
+    pub fn ids(&self) -> Vec<AssetId> {
+        let mut ids: Vec<_> = self.assets.read().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.assets.read().values().map(|image| image.as_bytes().len()).sum()
+    }
+
+    fn retain(&self, keep: &HashSet<AssetId>) -> Vec<AssetId> {
+        let mut assets = self.assets.write();
+        let mut removed: Vec<_> = assets.keys().filter(|id| !keep.contains(*id)).cloned().collect();
+        removed.sort();
+        for id in &removed {
+            assets.remove(id);
+        }
+        removed
+    }
+}
+
+impl AssetResolver for AssetStore {
+    fn resolve(&self, id: &AssetId) -> Option<Arc<DynamicImage>> {
+        self.get(id)
+    }
+}
+
+impl std::fmt::Debug for AssetStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AssetStore")
+            .field("assets", &self.len())
+            .finish()
+    }
+}
+
+impl Layer {
+    /// Assets referenced by this layer's graph.
+    pub fn referenced_assets(&self) -> Vec<AssetId> {
+        let mut ids = Vec::new();
+        let graph = self.node_graph();
+        for node_id in graph.get_node_ids() {
+            if let Some(node) = graph.get_node(&node_id) {
+                if let Some(asset_ref) = node.read().data().as_any().downcast_ref::<AssetRefNode>() {
+                    ids.push(asset_ref.asset().clone());
+                }
+            }
+        }
+        ids
+    }
+}
+
+impl Document {
+    pub fn assets(&self) -> &AssetStore {
+        &self.assets
+    }
+
+    pub fn add_asset(&mut self, image: DynamicImage) -> AssetId {
+        self.assets.add(image)
+    }
+
+    pub fn get_asset(&self, id: &AssetId) -> Option<Arc<DynamicImage>> {
+        self.assets.get(id)
+    }
+
+    /// Adds a top layer referencing `asset`.
+    pub fn add_asset_layer(&mut self, name: impl Into<String>, asset: AssetId) -> LayerId {
+        let id = LayerId::new();
+        let layer = Layer::with_asset(name, asset);
+        self.layers.insert(id.clone(), Arc::new(RwLock::new(layer)));
+        self.layer_order.push(id.clone());
+        self.mark_modified();
+        id
+    }
+
+    /// Number of asset references held by the document's layers.
+    pub fn asset_ref_count(&self, id: &AssetId) -> usize {
+        self.layers
+            .values()
+            .map(|layer| layer.read().referenced_assets().iter().filter(|asset| *asset == id).count())
+            .sum()
+    }
+
+    pub(crate) fn referenced_assets(&self) -> HashSet<AssetId> {
+        self.layers
+            .values()
+            .flat_map(|layer| layer.read().referenced_assets())
+            .collect()
+    }
+
+    /// Removes assets no layer references any more and returns their ids.
+    ///
+    /// Only live layers count as references, so assets used solely by layers
+    /// held in the undo history are collected too.
+    pub fn gc(&mut self) -> Vec<AssetId> {
+        let removed = self.assets.retain(&self.referenced_assets());
+        if !removed.is_empty() {
+            debug!("Collected {} unreferenced assets", removed.len());
+        }
+        removed
+    }
+
+    /// Context handed to graph evaluation so asset references resolve and,
+    /// while enabled, node previews are kept.
+    pub fn eval_context(&self) -> EvalContext {
+        let resolver: Arc<dyn AssetResolver> = Arc::new(self.assets.clone());
+        let context = EvalContext::new().with(resolver);
+        match self.previews_enabled() {
+            true => context.with(self.previews.observer()),
+            false => context,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn solid(color: [u8; 4]) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba(color)))
+    }
+
+    #[test]
+    fn test_add_asset_dedups_by_content() {
+        let mut doc = Document::new();
+        let first = doc.add_asset(solid([1, 2, 3, 255]));
+        let second = doc.add_asset(solid([1, 2, 3, 255]));
+        let other = doc.add_asset(solid([3, 2, 1, 255]));
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert_eq!(doc.assets().len(), 2);
+        assert!(Arc::ptr_eq(&doc.get_asset(&first).unwrap(), &doc.get_asset(&second).unwrap()));
+    }
+
+    #[test]
+    fn test_layers_share_one_asset_and_gc() {
+        let mut doc = Document::with_size(4, 4);
+        let asset = doc.add_asset(solid([200, 0, 0, 255]));
+        let first = doc.add_asset_layer("First", asset.clone());
+        let second = doc.add_asset_layer("Second", asset.clone());
+
+        assert_eq!(doc.assets().len(), 1);
+        assert_eq!(doc.asset_ref_count(&asset), 2);
+
+        let serialized = serde_json::to_value(doc.serialize().unwrap()).unwrap();
+        assert_eq!(serialized["assets"].as_object().unwrap().len(), 1);
+
+        let composite = doc.render_composite().unwrap().to_rgba8();
+        assert_eq!(*composite.get_pixel(1, 1), Rgba([200, 0, 0, 255]));
+
+        doc.remove_layer(&first).unwrap();
+        assert!(doc.gc().is_empty());
+        doc.remove_layer(&second).unwrap();
+        assert_eq!(doc.gc(), vec![asset.clone()]);
+        assert!(doc.get_asset(&asset).is_none());
+    }
+
+    #[test]
+    fn test_assets_survive_serialization() {
+        let mut doc = Document::with_size(4, 4);
+        let asset = doc.add_asset(solid([0, 0, 255, 255]));
+        let layer = doc.add_asset_layer("Sky", asset.clone());
+
+        let loaded = Document::deserialize(doc.serialize().unwrap()).unwrap();
+        assert_eq!(loaded.assets().ids(), vec![asset.clone()]);
+        assert_eq!(loaded.get_layer(&layer).unwrap().read().referenced_assets(), vec![asset]);
+        assert_eq!(
+            loaded.render_composite().unwrap().to_rgba8(),
+            doc.render_composite().unwrap().to_rgba8()
+        );
+    }
+}
@@ -0,0 +1,370 @@
+//! Loading and saving on a worker thread with progress reports.
+//!
+//! Both directions report [`Progress`] through a callback and can be
+//! cancelled cooperatively. Saves always go through a temp file that is
+//! renamed into place, so a cancelled or failed save never leaves a partial
+//! file behind.
+
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use crate::autosave::write_atomic;
+use crate::package::{load_error, PackageContents, SaveOptions, PACKAGE_EXTENSION, ZIP_MAGIC};
+use crate::serialization::SerializedDocument;
+use crate::{Document, DocumentError};
+
+const READ_CHUNK: usize = 1 << 20;
+
+/// Stages of loading a document, reported in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LoadPhase {
+    Reading,
+    DecompressingAssets,
+    BuildingGraphs,
+}
+
+/// Stages of saving a document, reported in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SavePhase {
+    Serializing,
+    EncodingAssets,
+    Writing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress<P> {
+    pub phase: P,
+    /// Completion of the current phase, from 0 to 1.
+    pub fraction: f32,
+}
+
+pub type LoadProgress = Progress<LoadPhase>;
+pub type SaveProgress = Progress<SavePhase>;
+
+impl<P> Progress<P> {
+    pub(crate) fn new(phase: P, done: usize, total: usize) -> Self {
+        let fraction = if total == 0 { 1.0 } else { done as f32 / total as f32 };
+        Self { phase, fraction }
+    }
+}
+
+/// A load or save running on a worker thread.
+pub struct IoTask<T> {
+    thread: JoinHandle<Result<T, DocumentError>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<T: Send + 'static> IoTask<T> {
+    fn spawn<F>(name: &str, cancelled: Arc<AtomicBool>, work: F) -> Result<Self, DocumentError>
+    where
+        F: FnOnce() -> Result<T, DocumentError> + Send + 'static,
+    {
+        let thread = std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(work)
+            .map_err(|e| DocumentError::Other(format!("Failed to start {} thread: {}", name, e)))?;
+        Ok(Self { thread, cancelled })
+    }
+
+    /// Asks the task to stop at its next progress report. Its result is
+    /// then [`DocumentError::Cancelled`].
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits for the task and returns its result.
+    pub fn join(self) -> Result<T, DocumentError> {
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(DocumentError::Other("Document I/O thread panicked".to_string())))
+    }
+}
+
+/// A save started by [`Document::save_async`]. Hand it back to
+/// [`Document::complete_save`] once finished.
+pub struct SaveHandle {
+    task: IoTask<()>,
+    path: PathBuf,
+    revision: u64,
+}
+
+impl SaveHandle {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn cancel(&self) {
+        self.task.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+/// What a save hands to its worker thread.
+enum Payload {
+    Package(PackageContents),
+    Json(SerializedDocument),
+}
+
+/// Wraps a user callback so that it fails once `cancelled` is set.
+fn reporter<P, F>(cancelled: Arc<AtomicBool>, progress: F) -> impl FnMut(Progress<P>) -> Result<(), DocumentError>
+where
+    F: Fn(Progress<P>),
+{
+    move |update| {
+        if cancelled.load(Ordering::SeqCst) {
+            return Err(DocumentError::Cancelled);
+        }
+        progress(update);
+        Ok(())
+    }
+}
+
+fn read_with_progress(
+    path: &Path,
+    progress: &mut dyn FnMut(LoadProgress) -> Result<(), DocumentError>,
+) -> Result<Vec<u8>, DocumentError> {
+    let mut file = File::open(path).map_err(|e| DocumentError::Other(format!("Failed to open file: {}", e)))?;
+    let total = file.metadata().map_or(0, |metadata| metadata.len() as usize);
+
+    let mut bytes = Vec::with_capacity(total);
+    let mut chunk = vec![0u8; READ_CHUNK];
+    progress(LoadProgress::new(LoadPhase::Reading, 0, total))?;
+    loop {
+        let read = file
+            .read(&mut chunk)
+            .map_err(|e| DocumentError::Other(format!("Failed to read file: {}", e)))?;
+        if read == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&chunk[..read]);
+        progress(LoadProgress::new(LoadPhase::Reading, bytes.len(), total.max(bytes.len())))?;
+    }
+    Ok(bytes)
+}
+
+impl Document {
+    pub(crate) fn load_with_progress(
+        path: &Path,
+        progress: &mut dyn FnMut(LoadProgress) -> Result<(), DocumentError>,
+    ) -> Result<Self, DocumentError> {
+        let bytes = read_with_progress(path, progress)?;
+        let mut document = if bytes.starts_with(ZIP_MAGIC) {
+            Self::from_package_reader(Cursor::new(bytes), progress).map_err(|e| load_error(path, e))?
+        } else {
+            Self::from_json_slice(&bytes, progress)?
+        };
+        document.path = Some(path.to_path_buf());
+        Ok(document)
+    }
+
+    /// Like [`Document::load`], on a worker thread. `progress` is called from
+    /// that thread.
+    pub fn load_async<P, F>(path: P, progress: F) -> Result<IoTask<Document>, DocumentError>
+    where
+        P: AsRef<Path>,
+        F: Fn(LoadProgress) + Send + 'static,
+    {
+        let path = path.as_ref().to_path_buf();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut report = reporter(cancelled.clone(), progress);
+        IoTask::spawn("document-load", cancelled, move || {
+            Self::load_with_progress(&path, &mut report)
+        })
+    }
+
+    /// Saves on a worker thread: a package if `path` has the package
+    /// extension, plain JSON otherwise.
+    ///
+    /// The document is serialized up front on the calling thread; image
+    /// encoding and writing happen on the worker, so the document can be
+    /// edited meanwhile. Pass the handle to [`Document::complete_save`] to
+    /// finish.
+    pub fn save_async<P, F>(&self, path: P, progress: F) -> Result<SaveHandle, DocumentError>
+    where
+        P: AsRef<Path>,
+        F: Fn(SaveProgress) + Send + 'static,
+    {
+        let path = path.as_ref().to_path_buf();
+        let is_package = path.extension().map_or(false, |extension| extension == PACKAGE_EXTENSION);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut report = reporter(cancelled.clone(), progress);
+
+        report(SaveProgress::new(SavePhase::Serializing, 0, 1))?;
+        let save_error = |e: anyhow::Error| {
+            e.downcast::<DocumentError>()
+                .unwrap_or_else(|e| DocumentError::Other(format!("Failed to save {}: {}", path.display(), e)))
+        };
+        let payload = if is_package {
+            Payload::Package(self.package_contents(&SaveOptions::default()).map_err(save_error)?)
+        } else {
+            Payload::Json(self.serialize().map_err(save_error)?)
+        };
+        report(SaveProgress::new(SavePhase::Serializing, 1, 1))?;
+
+        let target = path.clone();
+        let task = IoTask::spawn("document-save", cancelled, move || {
+            let bytes = match payload {
+                Payload::Package(package) => package
+                    .encode(&mut |done, total| report(SaveProgress::new(SavePhase::EncodingAssets, done, total)))
+                    .map_err(|e| load_error(&target, e))?,
+                Payload::Json(serialized) => {
+                    // Images were inlined while serializing
+                    report(SaveProgress::new(SavePhase::EncodingAssets, 1, 1))?;
+                    serde_json::to_vec_pretty(&serialized)
+                        .map_err(|e| DocumentError::Other(format!("Failed to write document: {}", e)))?
+                }
+            };
+
+            report(SaveProgress::new(SavePhase::Writing, 0, 1))?;
+            write_atomic(&target, &bytes)
+                .map_err(|e| DocumentError::Other(format!("Failed to write {}: {}", target.display(), e)))?;
+            report(SaveProgress::new(SavePhase::Writing, 1, 1)).or_else(|e| match e {
+                // The file is already in place, so a late cancel changes nothing
+                DocumentError::Cancelled => Ok(()),
+                e => Err(e),
+            })
+        })?;
+
+        Ok(SaveHandle { task, path, revision: self.revision })
+    }
+
+    /// Waits for a save started by [`Document::save_async`]. On success the
+    /// document takes the new path and, unless it was edited since the save
+    /// started, is no longer flagged as modified.
+    pub fn complete_save(&mut self, handle: SaveHandle) -> Result<(), DocumentError> {
+        let SaveHandle { task, path, revision } = handle;
+        task.join()?;
+        if self.revision == revision {
+            self.finish_save(&path);
+        } else {
+            self.path = Some(path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use parking_lot::{Mutex, RwLock};
+    use uuid::Uuid;
+    use crate::{Layer, LayerId};
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("meridian_async_io_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn document_with_assets() -> Document {
+        let mut doc = Document::with_size(64, 64);
+        for (index, shade) in [40u8, 120, 200].into_iter().enumerate() {
+            let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+                Rgba([shade, (x * 4) as u8, (y * 4) as u8, 255])
+            }));
+            doc.insert_layer_raw(index, LayerId::new(), Arc::new(RwLock::new(Layer::with_image("Layer", image))));
+        }
+        let asset = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([1, 2, 3, 255]))));
+        doc.add_asset_layer("Shared", asset);
+        doc
+    }
+
+    /// Phases in the order they were first reported.
+    fn phases<P: Copy + PartialEq>(updates: &[Progress<P>]) -> Vec<P> {
+        let mut phases: Vec<P> = Vec::new();
+        for update in updates {
+            if phases.last() != Some(&update.phase) {
+                phases.push(update.phase);
+            }
+        }
+        phases
+    }
+
+    #[test]
+    fn test_save_and_load_report_all_phases() {
+        let dir = temp_dir();
+        let path = dir.join("assets.artm");
+        let mut doc = document_with_assets();
+
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let sink = updates.clone();
+        let handle = doc.save_async(&path, move |update| sink.lock().push(update)).unwrap();
+        doc.complete_save(handle).unwrap();
+        assert!(!doc.is_modified());
+        assert_eq!(doc.path(), Some(path.as_path()));
+
+        let updates = updates.lock().clone();
+        assert_eq!(phases(&updates), vec![SavePhase::Serializing, SavePhase::EncodingAssets, SavePhase::Writing]);
+        assert!(updates.iter().all(|update| (0.0..=1.0).contains(&update.fraction)));
+        // A start report plus one per distinct image
+        let encoded = updates.iter().filter(|update| update.phase == SavePhase::EncodingAssets).count();
+        assert!(encoded >= 5, "{} asset reports", encoded);
+        assert_eq!(updates.last().unwrap().fraction, 1.0);
+
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let sink = updates.clone();
+        let loaded = Document::load_async(&path, move |update| sink.lock().push(update))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(loaded.layer_count(), 4);
+        assert_eq!(loaded.render_composite().unwrap().to_rgba8(), doc.render_composite().unwrap().to_rgba8());
+        assert_eq!(
+            phases(&updates.lock()),
+            vec![LoadPhase::Reading, LoadPhase::DecompressingAssets, LoadPhase::BuildingGraphs]
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_cancelled_save_leaves_no_file() {
+        let dir = temp_dir();
+        let path = dir.join("cancelled.artm");
+        let doc = document_with_assets();
+
+        // Hold the worker at its first asset until the save is cancelled
+        let (resume, wait) = mpsc::channel::<()>();
+        let wait = Mutex::new(wait);
+        let handle = doc
+            .save_async(&path, move |update| {
+                if update.phase == SavePhase::EncodingAssets && update.fraction == 0.0 {
+                    let _ = wait.lock().recv();
+                }
+            })
+            .unwrap();
+        handle.cancel();
+        resume.send(()).unwrap();
+
+        let mut doc = doc;
+        assert!(matches!(doc.complete_save(handle), Err(DocumentError::Cancelled)));
+        assert!(doc.path().is_none());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_edits_during_save_keep_document_modified() {
+        let dir = temp_dir();
+        let mut doc = document_with_assets();
+        let handle = doc.save_async(dir.join("edited.json"), |_| {}).unwrap();
+        doc.add_layer();
+        doc.complete_save(handle).unwrap();
+
+        assert!(doc.is_modified());
+        let loaded = Document::load(dir.join("edited.json")).unwrap();
+        assert_eq!(loaded.layer_count(), 4);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
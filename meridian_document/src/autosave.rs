@@ -0,0 +1,232 @@
+//! Periodic autosave and crash recovery.
+//!
+//! The host application calls [`Document::tick`] from its event loop. When the
+//! autosave interval has elapsed and the document changed since the previous
+//! autosave, the document is serialized to `<name>.autosave.json` in the
+//! configured directory. Layer locks are only held while the serialized
+//! snapshot is built; the file write happens afterwards.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+use crate::{Document, DocumentError};
+
+pub const AUTOSAVE_SUFFIX: &str = ".autosave.json";
+
+#[derive(Debug)]
+pub(crate) struct AutosaveState {
+    dir: PathBuf,
+    interval: Duration,
+    last_run: Option<Instant>,
+    saved_revision: Option<u64>,
+}
+
+impl Document {
+    pub fn enable_autosave<P: AsRef<Path>>(&mut self, dir: P, interval: Duration) {
+        self.autosave = Some(AutosaveState {
+            dir: dir.as_ref().to_path_buf(),
+            interval,
+            last_run: None,
+            saved_revision: None,
+        });
+    }
+
+    pub fn disable_autosave(&mut self) {
+        self.autosave = None;
+    }
+
+    pub fn is_autosave_enabled(&self) -> bool {
+        self.autosave.is_some()
+    }
+
+    /// Path the next autosave will be written to, if autosave is enabled.
+    pub fn autosave_path(&self) -> Option<PathBuf> {
+        self.autosave
+            .as_ref()
+            .map(|state| state.dir.join(format!("{}{}", self.name(), AUTOSAVE_SUFFIX)))
+    }
+
+    /// Writes an autosave if one is due. Returns the written path, if any.
+    pub fn tick(&mut self) -> Result<Option<PathBuf>, DocumentError> {
+        let revision = self.revision;
+        let due = match &self.autosave {
+            Some(state) => {
+                let interval_elapsed = state
+                    .last_run
+                    .is_none_or(|last_run| last_run.elapsed() >= state.interval);
+                interval_elapsed && state.saved_revision != Some(revision)
+            }
+            None => false,
+        };
+        if !due || !self.is_modified() {
+            return Ok(None);
+        }
+
+        let path = self.autosave_path().expect("autosave is enabled");
+        // Build the snapshot first so no layer lock is held during disk I/O
+        let snapshot = self.serialize()
+            .map_err(|e| DocumentError::Other(format!("Failed to serialize autosave: {}", e)))?;
+        let bytes = serde_json::to_vec_pretty(&snapshot)
+            .map_err(|e| DocumentError::Other(format!("Failed to encode autosave: {}", e)))?;
+
+        if let Some(state) = &mut self.autosave {
+            state.last_run = Some(Instant::now());
+        }
+        write_atomic(&path, &bytes)
+            .map_err(|e| DocumentError::Other(format!("Failed to write autosave {}: {}", path.display(), e)))?;
+        if let Some(state) = &mut self.autosave {
+            state.saved_revision = Some(revision);
+        }
+
+        debug!("Autosaved document to {}", path.display());
+        Ok(Some(path))
+    }
+
+    /// Removes the autosave file after a successful explicit save.
+    pub(crate) fn discard_autosave(&mut self) {
+        if let Some(path) = self.autosave_path() {
+            if path.exists() {
+                if let Err(e) = fs::remove_file(&path) {
+                    warn!("Failed to remove autosave {}: {}", path.display(), e);
+                }
+            }
+        }
+        if let Some(state) = &mut self.autosave {
+            state.saved_revision = Some(self.revision);
+        }
+    }
+
+    /// Lists autosave files left in `dir`, newest first.
+    pub fn find_recovery_files<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, DocumentError> {
+        let entries = fs::read_dir(dir.as_ref())
+            .map_err(|e| DocumentError::Other(format!("Failed to read {}: {}", dir.as_ref().display(), e)))?;
+
+        let mut files = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_autosave = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.ends_with(AUTOSAVE_SUFFIX));
+            if is_autosave {
+                let modified = entry.metadata().and_then(|m| m.modified()).ok();
+                files.push((modified, path));
+            }
+        }
+
+        files.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+        Ok(files.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Loads an autosave file. The result is flagged as modified and has no
+    /// path, so the user is asked where to save it.
+    pub fn load_recovery<P: AsRef<Path>>(path: P) -> Result<Self, DocumentError> {
+        let mut document = Self::load(path)?;
+        document.path = None;
+        document.mark_modified();
+        Ok(document)
+    }
+}
+
+/// Writes `bytes` to a sibling temp file and renames it over `path`, so readers
+/// never observe a partially written file.
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{}.tmp", file_name));
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("meridian_autosave_{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_tick_only_writes_when_modified() {
+        let dir = temp_dir();
+        let mut doc = Document::new();
+        doc.enable_autosave(&dir, Duration::ZERO);
+
+        assert!(doc.tick().unwrap().is_none());
+
+        doc.add_layer();
+        let path = doc.tick().unwrap().expect("autosave written");
+        assert!(path.ends_with(format!("untitled{}", AUTOSAVE_SUFFIX)));
+
+        // No edits since the last autosave
+        assert!(doc.tick().unwrap().is_none());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_tick_respects_interval() {
+        let dir = temp_dir();
+        let mut doc = Document::new();
+        doc.enable_autosave(&dir, Duration::from_secs(3600));
+
+        doc.add_layer();
+        assert!(doc.tick().unwrap().is_some());
+        doc.add_layer();
+        assert!(doc.tick().unwrap().is_none());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_recover_after_crash() {
+        let dir = temp_dir();
+        let mut doc = Document::new();
+        doc.enable_autosave(&dir, Duration::ZERO);
+        let first = doc.add_layer();
+        let second = doc.add_layer();
+        doc.tick().unwrap();
+
+        // Simulate a crash: the document is dropped without saving
+        drop(doc);
+
+        let recovery_files = Document::find_recovery_files(&dir).unwrap();
+        assert_eq!(recovery_files.len(), 1);
+
+        let recovered = Document::load_recovery(&recovery_files[0]).unwrap();
+        assert!(recovered.is_modified());
+        assert!(recovered.path().is_none());
+        let order: Vec<_> = recovered.layers().cloned().collect();
+        assert_eq!(order, vec![first, second]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_save_discards_autosave() {
+        let dir = temp_dir();
+        let mut doc = Document::new();
+        doc.save(dir.join("painting.json")).unwrap();
+        doc.enable_autosave(&dir, Duration::ZERO);
+
+        doc.add_layer();
+        let autosave = doc.tick().unwrap().unwrap();
+        assert!(autosave.ends_with(format!("painting{}", AUTOSAVE_SUFFIX)));
+
+        doc.save(dir.join("painting.json")).unwrap();
+        assert!(!autosave.exists());
+        assert!(Document::find_recovery_files(&dir).unwrap().is_empty());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -1,4 +1,5 @@
-use image::{DynamicImage, ImageBuffer, Rgba};
+use image::{DynamicImage, ImageBuffer, Rgba, Rgba32FImage, RgbaImage};
+use crate::color::srgb_to_linear;
 use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -18,6 +19,15 @@ impl BlendMode {
             BlendMode::Overlay => "Overlay",
         }
     }
+
+    pub fn all() -> &'static [BlendMode] {
+        &[BlendMode::Normal, BlendMode::Multiply, BlendMode::Screen, BlendMode::Overlay]
+    }
+
+    /// Parses a name produced by [`BlendMode::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().iter().copied().find(|mode| mode.name() == name)
+    }
 }
 
 pub fn blend_images(
@@ -45,14 +55,45 @@ pub fn blend_images(
     DynamicImage::ImageRgba8(output)
 }
 
+/// Blends `top` onto `canvas` in place with its top-left corner at `(x, y)`.
+///
+/// Only the overlapping region is touched; parts of `top` that fall outside
+/// the canvas are ignored.
+pub fn composite_onto(
+    canvas: &mut RgbaImage,
+    top: &DynamicImage,
+    x: i64,
+    y: i64,
+    mode: BlendMode,
+    opacity: f32,
+) {
+    let top_rgba = top.to_rgba8();
+    let x_start = x.max(0);
+    let y_start = y.max(0);
+    let x_end = (x + top_rgba.width() as i64).min(canvas.width() as i64);
+    let y_end = (y + top_rgba.height() as i64).min(canvas.height() as i64);
+
+    for cy in y_start..y_end {
+        for cx in x_start..x_end {
+            let top_pixel = top_rgba.get_pixel((cx - x) as u32, (cy - y) as u32);
+            let bottom_pixel = canvas.get_pixel(cx as u32, cy as u32);
+            let blended = blend_pixels(bottom_pixel, top_pixel, mode, opacity);
+            canvas.put_pixel(cx as u32, cy as u32, blended);
+        }
+    }
+}
+
 fn blend_pixels(bottom: &Rgba<u8>, top: &Rgba<u8>, mode: BlendMode, opacity: f32) -> Rgba<u8> {
-    let b = to_f32(bottom);
-    let t = to_f32(top);
+    to_u8(&blend_f32(&to_f32(bottom), &to_f32(top), mode, opacity))
+}
+
+/// Blends straight-alpha `top` over `bottom`, with channels in 0..=1.
+fn blend_f32(b: &[f32; 4], t: &[f32; 4], mode: BlendMode, opacity: f32) -> [f32; 4] {
     let mut result = match mode {
-        BlendMode::Normal => t,
-        BlendMode::Multiply => multiply(&b, &t),
-        BlendMode::Screen => screen(&b, &t),
-        BlendMode::Overlay => overlay(&b, &t),
+        BlendMode::Normal => *t,
+        BlendMode::Multiply => multiply(b, t),
+        BlendMode::Screen => screen(b, t),
+        BlendMode::Overlay => overlay(b, t),
     };
 
     // Apply opacity
@@ -66,8 +107,36 @@ fn blend_pixels(bottom: &Rgba<u8>, top: &Rgba<u8>, mode: BlendMode, opacity: f32
         }
     }
     result[3] = alpha;
+    result
+}
 
-    to_u8(&result)
+/// Like [`composite_onto`], but blends in linear light on a linear float
+/// canvas. `top` is sRGB encoded and is linearized before blending.
+pub fn composite_onto_linear(
+    canvas: &mut Rgba32FImage,
+    top: &DynamicImage,
+    x: i64,
+    y: i64,
+    mode: BlendMode,
+    opacity: f32,
+) {
+    let top_rgba = top.to_rgba8();
+    let x_start = x.max(0);
+    let y_start = y.max(0);
+    let x_end = (x + top_rgba.width() as i64).min(canvas.width() as i64);
+    let y_end = (y + top_rgba.height() as i64).min(canvas.height() as i64);
+
+    for cy in y_start..y_end {
+        for cx in x_start..x_end {
+            let top_pixel = top_rgba.get_pixel((cx - x) as u32, (cy - y) as u32);
+            let mut t = to_f32(top_pixel);
+            for channel in t.iter_mut().take(3) {
+                *channel = srgb_to_linear(*channel);
+            }
+            let bottom_pixel = canvas.get_pixel_mut(cx as u32, cy as u32);
+            bottom_pixel.0 = blend_f32(&bottom_pixel.0, &t, mode, opacity);
+        }
+    }
 }
 
 fn to_f32(pixel: &Rgba<u8>) -> [f32; 4] {
@@ -144,4 +213,16 @@ mod tests {
         let result = blend_pixels(&bottom, &top, BlendMode::Normal, 0.5);
         assert_eq!(result[3], 128); // Alpha should be halved
     }
+
+    #[test]
+    fn test_composite_onto_offset_and_clipping() {
+        let mut canvas = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+        let top = DynamicImage::ImageRgba8(RgbaImage::from_pixel(3, 3, Rgba([255, 0, 0, 255])));
+        composite_onto(&mut canvas, &top, 2, -1, BlendMode::Normal, 1.0);
+
+        assert_eq!(*canvas.get_pixel(2, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(*canvas.get_pixel(3, 1), Rgba([255, 0, 0, 255]));
+        assert_eq!(*canvas.get_pixel(1, 0), Rgba([0, 0, 0, 255]));
+        assert_eq!(*canvas.get_pixel(2, 2), Rgba([0, 0, 0, 255]));
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,351 @@
+//! Resizing, cropping, and scaling the canvas.
+//!
+//! All operations are non-destructive: layer pixels are left alone and only
+//! the canvas size and layer transforms change.
+
+use std::error::Error;
+use aurion_std_nodes::transform::ResampleFilter;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::{Command, CommandRecord, Document, DocumentError, DocumentEvent, LayerId, LayerTransform};
+
+/// The point of the old canvas that stays fixed when the canvas is resized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Horizontal and vertical position of the anchor, in halves of the canvas.
+    fn halves(&self) -> (i64, i64) {
+        match self {
+            Anchor::TopLeft => (0, 0),
+            Anchor::Top => (1, 0),
+            Anchor::TopRight => (2, 0),
+            Anchor::Left => (0, 1),
+            Anchor::Center => (1, 1),
+            Anchor::Right => (2, 1),
+            Anchor::BottomLeft => (0, 2),
+            Anchor::Bottom => (1, 2),
+            Anchor::BottomRight => (2, 2),
+        }
+    }
+
+    /// How far content moves when a canvas of `from` size becomes `to`.
+    /// Offsets are whole pixels so unscaled layers stay pixel aligned.
+    pub fn offset(&self, from: (u32, u32), to: (u32, u32)) -> (i64, i64) {
+        let (h, v) = self.halves();
+        let dx = (to.0 as i64 - from.0 as i64) * h;
+        let dy = (to.1 as i64 - from.1 as i64) * v;
+        (dx.div_euclid(2), dy.div_euclid(2))
+    }
+}
+
+/// A rectangle in canvas pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanvasRect {
+    pub x: i64,
+    pub y: i64,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CanvasRect {
+    pub fn new(x: i64, y: i64, width: u32, height: u32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    pub fn right(&self) -> i64 {
+        self.x + self.width as i64
+    }
+
+    pub fn bottom(&self) -> i64 {
+        self.y + self.height as i64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// The overlap of both rects, or `None` if they don't overlap.
+    pub fn intersect(&self, other: &CanvasRect) -> Option<CanvasRect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        (right > x && bottom > y).then(|| CanvasRect::new(x, y, (right - x) as u32, (bottom - y) as u32))
+    }
+
+    /// The smallest rect containing both. Empty rects are ignored.
+    pub fn union(&self, other: &CanvasRect) -> CanvasRect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+        CanvasRect::new(x, y, (right - x) as u32, (bottom - y) as u32)
+    }
+}
+
+pub(crate) type CanvasState = ((u32, u32), Vec<(LayerId, LayerTransform)>);
+
+/// Sets the canvas size together with new layer transforms, restoring both on undo.
+#[derive(Debug)]
+pub struct CanvasCommand {
+    size: (u32, u32),
+    transforms: Vec<(LayerId, LayerTransform)>,
+    previous: Mutex<Option<CanvasState>>,
+}
+
+impl CanvasCommand {
+    pub fn new(size: (u32, u32), transforms: Vec<(LayerId, LayerTransform)>) -> Self {
+        Self {
+            size,
+            transforms,
+            previous: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn restored(size: (u32, u32), transforms: Vec<(LayerId, LayerTransform)>, previous: Option<CanvasState>) -> Self {
+        Self {
+            size,
+            transforms,
+            previous: Mutex::new(previous),
+        }
+    }
+
+    fn apply(document: &mut Document, state: &CanvasState) -> Result<CanvasState, Box<dyn Error>> {
+        let (size, transforms) = state;
+        let mut previous = Vec::with_capacity(transforms.len());
+        for (id, transform) in transforms {
+            let before = document.with_layer_mut(id, |layer| {
+                let before = layer.transform();
+                layer.set_transform(*transform);
+                before
+            })?;
+            previous.push((id.clone(), before));
+        }
+        let previous_size = document.size();
+        (document.width, document.height) = *size;
+        document.events.emit(DocumentEvent::CanvasResized { width: size.0, height: size.1 });
+        Ok((previous_size, previous))
+    }
+}
+
+impl Command for CanvasCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let previous = Self::apply(document, &(self.size, self.transforms.clone()))?;
+        *self.previous.lock() = Some(previous);
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        if let Some(previous) = self.previous.lock().take() {
+            Self::apply(document, &previous)?;
+        }
+        Ok(())
+    }
+
+    fn to_record(&self) -> Option<CommandRecord> {
+        Some(CommandRecord::Canvas {
+            size: self.size,
+            transforms: self.transforms.clone(),
+            previous: self.previous.lock().clone(),
+        })
+    }
+}
+
+fn check_size(width: u32, height: u32) -> Result<(), DocumentError> {
+    if width == 0 || height == 0 {
+        return Err(DocumentError::InvalidOperation(format!(
+            "Invalid canvas size {}x{}",
+            width, height
+        )));
+    }
+    Ok(())
+}
+
+impl Document {
+    /// Changes the canvas size, keeping content fixed relative to `anchor`.
+    pub fn resize_canvas(&mut self, width: u32, height: u32, anchor: Anchor) -> Result<(), DocumentError> {
+        check_size(width, height)?;
+        let offset = anchor.offset(self.size(), (width, height));
+        self.reframe((width, height), offset)
+    }
+
+    /// Shrinks or grows the canvas to `rect`. Layers are only moved, never
+    /// clipped or removed, so content outside the rect comes back on undo or
+    /// a later resize.
+    pub fn crop_canvas(&mut self, rect: CanvasRect) -> Result<(), DocumentError> {
+        check_size(rect.width, rect.height)?;
+        self.reframe((rect.width, rect.height), (-rect.x, -rect.y))
+    }
+
+    fn reframe(&mut self, size: (u32, u32), (dx, dy): (i64, i64)) -> Result<(), DocumentError> {
+        let transforms = self
+            .layer_transforms()
+            .into_iter()
+            .map(|(id, mut transform)| {
+                transform.x += dx as f32;
+                transform.y += dy as f32;
+                (id, transform)
+            })
+            .collect();
+        self.execute_command(Box::new(CanvasCommand::new(size, transforms)))
+    }
+
+    /// Scales the canvas and every layer's placement by `factor`, resampling
+    /// layer output with `filter`.
+    pub fn scale_document(&mut self, factor: f32, filter: ResampleFilter) -> Result<(), DocumentError> {
+        if !(factor > 0.0) || !factor.is_finite() {
+            return Err(DocumentError::InvalidOperation(format!("Invalid scale factor {}", factor)));
+        }
+        let width = ((self.width as f32 * factor).round() as u32).max(1);
+        let height = ((self.height as f32 * factor).round() as u32).max(1);
+
+        let context = self.eval_context();
+        let mut transforms = Vec::new();
+        for (id, mut transform) in self.layer_transforms() {
+            // Scaling about the canvas origin moves the pivot, which sits at a
+            // fraction of the layer output's size
+            let output_size = match self.get_layer(&id) {
+                Some(layer) => self.layer_output(&id, &layer.read(), &context)?.map(|image| (image.width(), image.height())),
+                None => None,
+            };
+            let (pivot_x, pivot_y) = output_size.map_or((0.0, 0.0), |(w, h)| {
+                (transform.pivot[0] * w as f32, transform.pivot[1] * h as f32)
+            });
+            transform.x = transform.x * factor + (factor - 1.0) * pivot_x;
+            transform.y = transform.y * factor + (factor - 1.0) * pivot_y;
+            transform.scale_x *= factor;
+            transform.scale_y *= factor;
+            transform.filter = filter;
+            transforms.push((id, transform));
+        }
+        self.execute_command(Box::new(CanvasCommand::new((width, height), transforms)))
+    }
+
+    fn layer_transforms(&self) -> Vec<(LayerId, LayerTransform)> {
+        self.layer_order
+            .iter()
+            .filter_map(|id| self.get_layer(id).map(|layer| (id.clone(), layer.read().transform())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use parking_lot::RwLock;
+    use crate::Layer;
+
+    fn document_with_square() -> (Document, LayerId) {
+        let mut doc = Document::with_size(100, 100);
+        let square = DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 10, Rgba([0, 255, 0, 255])));
+        let mut layer = Layer::with_image("Square", square);
+        layer.set_transform(LayerTransform::translation(10.0, 10.0));
+        let id = LayerId::new();
+        doc.insert_layer_raw(0, id.clone(), Arc::new(RwLock::new(layer)));
+        (doc, id)
+    }
+
+    fn position(doc: &Document, id: &LayerId) -> (f32, f32) {
+        let transform = doc.get_layer(id).unwrap().read().transform();
+        (transform.x, transform.y)
+    }
+
+    #[test]
+    fn test_anchor_offsets() {
+        assert_eq!(Anchor::TopLeft.offset((100, 100), (200, 200)), (0, 0));
+        assert_eq!(Anchor::BottomRight.offset((100, 100), (200, 150)), (100, 50));
+        assert_eq!(Anchor::Center.offset((100, 100), (51, 51)), (-25, -25));
+    }
+
+    #[test]
+    fn test_rect_intersect_and_union() {
+        let a = CanvasRect::new(0, 0, 10, 10);
+        let b = CanvasRect::new(5, -2, 10, 4);
+        assert_eq!(a.intersect(&b), Some(CanvasRect::new(5, 0, 5, 2)));
+        assert_eq!(a.intersect(&CanvasRect::new(10, 0, 5, 5)), None);
+        assert_eq!(a.union(&b), CanvasRect::new(0, -2, 15, 12));
+        assert_eq!(CanvasRect::new(50, 50, 0, 0).union(&a), a);
+    }
+
+    #[test]
+    fn test_resize_canvas_center_anchor() {
+        let (mut doc, id) = document_with_square();
+        doc.resize_canvas(200, 200, Anchor::Center).unwrap();
+        assert_eq!(doc.size(), (200, 200));
+        assert_eq!(position(&doc, &id), (60.0, 60.0));
+        assert_eq!(*doc.render_composite().unwrap().to_rgba8().get_pixel(60, 60), Rgba([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn test_crop_then_undo_restores() {
+        let (mut doc, id) = document_with_square();
+        let before = doc.render_composite().unwrap().to_rgba8();
+
+        doc.crop_canvas(CanvasRect::new(5, 8, 20, 20)).unwrap();
+        assert_eq!(doc.size(), (20, 20));
+        assert_eq!(position(&doc, &id), (5.0, 2.0));
+
+        doc.undo().unwrap();
+        assert_eq!(doc.size(), (100, 100));
+        assert_eq!(position(&doc, &id), (10.0, 10.0));
+        assert_eq!(doc.render_composite().unwrap().to_rgba8(), before);
+    }
+
+    #[test]
+    fn test_scale_document() {
+        let (mut doc, id) = document_with_square();
+        doc.scale_document(2.0, ResampleFilter::Nearest).unwrap();
+        assert_eq!(doc.size(), (200, 200));
+
+        let composite = doc.render_composite().unwrap().to_rgba8();
+        assert_eq!(*composite.get_pixel(20, 20), Rgba([0, 255, 0, 255]));
+        assert_eq!(*composite.get_pixel(39, 39), Rgba([0, 255, 0, 255]));
+        assert_eq!(composite.get_pixel(19, 20)[3], 0);
+        assert_eq!(composite.get_pixel(40, 39)[3], 0);
+        assert_eq!(doc.get_layer(&id).unwrap().read().transform().scale_x, 2.0);
+    }
+
+    #[test]
+    fn test_canvas_events_and_invalid_sizes() {
+        let (mut doc, _) = document_with_square();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        doc.subscribe(move |event| {
+            if let DocumentEvent::CanvasResized { .. } = event {
+                sink.lock().push(event.clone());
+            }
+        });
+
+        doc.resize_canvas(50, 40, Anchor::TopLeft).unwrap();
+        doc.undo().unwrap();
+        assert_eq!(
+            *events.lock(),
+            vec![
+                DocumentEvent::CanvasResized { width: 50, height: 40 },
+                DocumentEvent::CanvasResized { width: 100, height: 100 },
+            ]
+        );
+        assert!(doc.resize_canvas(0, 10, Anchor::Center).is_err());
+        assert!(doc.scale_document(-1.0, ResampleFilter::Bilinear).is_err());
+    }
+}
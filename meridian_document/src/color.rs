@@ -0,0 +1,312 @@
+//! The document's working color space and ICC profiles for export.
+
+use std::io::Write;
+use anyhow::{bail, Result};
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use image::{DynamicImage, Rgba, Rgba32FImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+use crate::Document;
+
+/// Working color space of a document.
+///
+/// The profile decides whether layers are blended in linear light and which
+/// ICC profile is embedded in exported PNGs. Pixel data is always stored
+/// sRGB-encoded; no gamut conversion is performed yet.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum ColorProfile {
+    #[default]
+    Srgb,
+    /// sRGB primaries, composited in linear light.
+    LinearSrgb,
+    /// Display P3, optionally carrying the ICC profile it was imported with.
+    /// Treated as sRGB-encoded for compositing.
+    DisplayP3 { icc: Option<Vec<u8>> },
+}
+
+impl ColorProfile {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorProfile::Srgb => "sRGB",
+            ColorProfile::LinearSrgb => "Linear sRGB",
+            ColorProfile::DisplayP3 { .. } => "Display P3",
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == ColorProfile::Srgb
+    }
+
+    pub fn blends_in_linear_light(&self) -> bool {
+        matches!(self, ColorProfile::LinearSrgb)
+    }
+
+    /// The ICC profile to embed on export. sRGB is implied by PNG readers, so
+    /// nothing is embedded for it.
+    pub fn icc_profile(&self) -> Option<Vec<u8>> {
+        match self {
+            ColorProfile::Srgb => None,
+            ColorProfile::LinearSrgb => Some(matrix_profile("Linear sRGB", &SRGB_COLORANTS, &Curve::Gamma(1.0))),
+            ColorProfile::DisplayP3 { icc: Some(icc) } => Some(icc.clone()),
+            ColorProfile::DisplayP3 { icc: None } => Some(matrix_profile("Display P3", &DISPLAY_P3_COLORANTS, &Curve::Srgb)),
+        }
+    }
+}
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts a linear float canvas back to sRGB-encoded 8-bit pixels.
+pub(crate) fn linear_to_srgb_image(canvas: &Rgba32FImage) -> DynamicImage {
+    let to_u8 = |value: f32| (value * 255.0).round().clamp(0.0, 255.0) as u8;
+    let output = RgbaImage::from_fn(canvas.width(), canvas.height(), |x, y| {
+        let p = canvas.get_pixel(x, y);
+        Rgba([
+            to_u8(linear_to_srgb(p[0])),
+            to_u8(linear_to_srgb(p[1])),
+            to_u8(linear_to_srgb(p[2])),
+            to_u8(p[3]),
+        ])
+    });
+    DynamicImage::ImageRgba8(output)
+}
+
+// D50-adapted red, green, and blue colorants
+const SRGB_COLORANTS: [[f64; 3]; 3] = [
+    [0.4361, 0.2225, 0.0139],
+    [0.3851, 0.7169, 0.0971],
+    [0.1431, 0.0606, 0.7141],
+];
+const DISPLAY_P3_COLORANTS: [[f64; 3]; 3] = [
+    [0.5151, 0.2412, -0.0011],
+    [0.2920, 0.6922, 0.0419],
+    [0.1571, 0.0666, 0.7841],
+];
+const D50_WHITE: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+enum Curve {
+    Gamma(f64),
+    Srgb,
+}
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn xyz_tag(xyz: &[f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for value in xyz {
+        tag.extend_from_slice(&s15_fixed16(*value));
+    }
+    tag
+}
+
+fn curve_tag(curve: &Curve) -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    match curve {
+        Curve::Gamma(gamma) => {
+            tag.extend_from_slice(&1u32.to_be_bytes());
+            tag.extend_from_slice(&((gamma * 256.0).round() as u16).to_be_bytes());
+        }
+        Curve::Srgb => {
+            const ENTRIES: u32 = 1024;
+            tag.extend_from_slice(&ENTRIES.to_be_bytes());
+            for i in 0..ENTRIES {
+                let value = srgb_to_linear(i as f32 / (ENTRIES - 1) as f32);
+                tag.extend_from_slice(&((value * 65535.0).round() as u16).to_be_bytes());
+            }
+        }
+    }
+    tag
+}
+
+fn description_tag(description: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend_from_slice(&(description.len() as u32 + 1).to_be_bytes());
+    tag.extend_from_slice(description.as_bytes());
+    tag.push(0);
+    // Empty Unicode and ScriptCode descriptions
+    tag.extend_from_slice(&[0; 8]);
+    tag.extend_from_slice(&[0; 3]);
+    tag.extend_from_slice(&[0; 67]);
+    tag
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+/// Builds a version 2 RGB display profile from colorants and a transfer curve.
+fn matrix_profile(description: &str, colorants: &[[f64; 3]; 3], curve: &Curve) -> Vec<u8> {
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", description_tag(description)),
+        (b"cprt", text_tag("No copyright, use freely")),
+        (b"wtpt", xyz_tag(&D50_WHITE)),
+        (b"rXYZ", xyz_tag(&colorants[0])),
+        (b"gXYZ", xyz_tag(&colorants[1])),
+        (b"bXYZ", xyz_tag(&colorants[2])),
+        (b"rTRC", curve_tag(curve)),
+        (b"gTRC", curve_tag(curve)),
+        (b"bTRC", curve_tag(curve)),
+    ];
+
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = Vec::new();
+    let mut offset = 128 + 4 + 12 * tags.len();
+    for (signature, tag) in &tags {
+        table.extend_from_slice(*signature);
+        table.extend_from_slice(&(offset as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        data.extend_from_slice(tag);
+        // Tag data starts on 4-byte boundaries
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        offset = 128 + 4 + 12 * tags.len() + data.len();
+    }
+
+    let size = 128 + table.len() + data.len();
+    let mut header = vec![0u8; 128];
+    header[0..4].copy_from_slice(&(size as u32).to_be_bytes());
+    header[8..12].copy_from_slice(&0x0210_0000u32.to_be_bytes());
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(b"RGB ");
+    header[20..24].copy_from_slice(b"XYZ ");
+    header[36..40].copy_from_slice(b"acsp");
+    for (i, value) in D50_WHITE.iter().enumerate() {
+        header[68 + i * 4..72 + i * 4].copy_from_slice(&s15_fixed16(*value));
+    }
+
+    let mut profile = header;
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&data);
+    profile
+}
+
+/// Inserts an `iCCP` chunk holding `icc` right after the PNG's `IHDR` chunk.
+pub(crate) fn embed_icc_in_png(png: &[u8], name: &str, icc: &[u8]) -> Result<Vec<u8>> {
+    const SIGNATURE_LEN: usize = 8;
+    // IHDR is always first: 4 length + 4 type + 13 data + 4 CRC
+    const IHDR_END: usize = SIGNATURE_LEN + 25;
+    if png.len() < IHDR_END || &png[SIGNATURE_LEN + 4..SIGNATURE_LEN + 8] != b"IHDR" {
+        bail!("Not a PNG stream");
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(icc)?;
+    let compressed = encoder.finish()?;
+
+    // Profile names are 1-79 Latin-1 characters
+    let name: String = name.chars().filter(|c| c.is_ascii() && !c.is_ascii_control()).take(79).collect();
+    let mut chunk_data = name.into_bytes();
+    chunk_data.push(0); // name terminator
+    chunk_data.push(0); // zlib compression
+    chunk_data.extend_from_slice(&compressed);
+
+    let mut crc = Crc::new();
+    crc.update(b"iCCP");
+    crc.update(&chunk_data);
+
+    let mut output = Vec::with_capacity(png.len() + chunk_data.len() + 12);
+    output.extend_from_slice(&png[..IHDR_END]);
+    output.extend_from_slice(&(chunk_data.len() as u32).to_be_bytes());
+    output.extend_from_slice(b"iCCP");
+    output.extend_from_slice(&chunk_data);
+    output.extend_from_slice(&crc.sum().to_be_bytes());
+    output.extend_from_slice(&png[IHDR_END..]);
+    Ok(output)
+}
+
+impl Document {
+    pub fn color_profile(&self) -> &ColorProfile {
+        &self.color_profile
+    }
+
+    pub fn set_color_profile(&mut self, profile: ColorProfile) {
+        if self.color_profile != profile {
+            self.color_profile = profile;
+            self.mark_modified();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlendMode, ExportOptions, Layer, LayerId};
+    use parking_lot::RwLock;
+    use std::sync::Arc;
+
+    fn gray_document(profile: ColorProfile) -> Document {
+        let mut doc = Document::with_size(2, 2);
+        doc.set_color_profile(profile);
+        let gray = || DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([128, 128, 128, 255])));
+        doc.insert_layer_raw(0, LayerId::new(), Arc::new(RwLock::new(Layer::with_image("Base", gray()))));
+        let mut top = Layer::with_image("Shade", gray());
+        top.set_blend_mode(BlendMode::Multiply);
+        doc.insert_layer_raw(1, LayerId::new(), Arc::new(RwLock::new(top)));
+        doc
+    }
+
+    #[test]
+    fn test_srgb_round_trips_through_linear() {
+        for value in 0..=255u8 {
+            let linear = srgb_to_linear(value as f32 / 255.0);
+            assert_eq!((linear_to_srgb(linear) * 255.0).round() as u8, value);
+        }
+    }
+
+    #[test]
+    fn test_multiply_differs_in_linear_light() {
+        let srgb = gray_document(ColorProfile::Srgb).render_composite().unwrap().to_rgba8();
+        let linear = gray_document(ColorProfile::LinearSrgb).render_composite().unwrap().to_rgba8();
+
+        // 0.502² in encoded values vs. squaring the linear luminance
+        assert_eq!(srgb.get_pixel(0, 0)[0], 64);
+        assert_eq!(linear.get_pixel(0, 0)[0], 61);
+        assert_eq!(linear.get_pixel(0, 0)[3], 255);
+    }
+
+    #[test]
+    fn test_png_export_embeds_icc() {
+        let dir = std::env::temp_dir().join(format!("meridian_icc_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let with_profile = dir.join("p3.png");
+        gray_document(ColorProfile::DisplayP3 { icc: None })
+            .export(&with_profile, ExportOptions::default())
+            .unwrap();
+        let bytes = std::fs::read(&with_profile).unwrap();
+        assert!(bytes.windows(4).any(|w| w == b"iCCP"));
+        assert_eq!(image::open(&with_profile).unwrap().width(), 2);
+
+        let plain = dir.join("srgb.png");
+        gray_document(ColorProfile::Srgb).export(&plain, ExportOptions::default()).unwrap();
+        let bytes = std::fs::read(&plain).unwrap();
+        assert!(!bytes.windows(4).any(|w| w == b"iCCP"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_profile_serializes_with_document() {
+        let doc = gray_document(ColorProfile::LinearSrgb);
+        let loaded = Document::deserialize(doc.serialize().unwrap()).unwrap();
+        assert_eq!(loaded.color_profile(), &ColorProfile::LinearSrgb);
+    }
+}
@@ -0,0 +1,595 @@
+//! Undoable document-level commands.
+
+use std::error::Error;
+use std::sync::Arc;
+use image::{DynamicImage, Rgba, RgbaImage};
+use parking_lot::{Mutex, RwLock};
+use crate::blend::composite_onto;
+use crate::{BlendMode, Command, CommandRecord, Document, DocumentError, DocumentEvent, Layer, LayerId};
+
+// A replaced layer with the index it was at
+type CapturedLayer = (usize, LayerId, Arc<RwLock<Layer>>);
+
+/// Replaces a set of layers with a single new layer, e.g. for merge-down and
+/// flatten. The replaced layers are kept so undo restores them exactly.
+#[derive(Debug)]
+pub struct ReplaceLayersCommand {
+    removed: Vec<LayerId>,
+    replacement_id: LayerId,
+    replacement: Arc<RwLock<Layer>>,
+    captured: Mutex<Vec<CapturedLayer>>,
+}
+
+impl ReplaceLayersCommand {
+    pub fn new(removed: Vec<LayerId>, replacement: Layer) -> Self {
+        Self {
+            removed,
+            replacement_id: LayerId::new(),
+            replacement: Arc::new(RwLock::new(replacement)),
+            captured: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn replacement_id(&self) -> &LayerId {
+        &self.replacement_id
+    }
+}
+
+impl Command for ReplaceLayersCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let mut removed = self.removed.clone();
+        removed.sort_by_key(|id| document.layer_index(id));
+
+        let mut captured = self.captured.lock();
+        captured.clear();
+        for id in removed {
+            let (index, layer) = document.take_layer_raw(&id)?;
+            captured.push((index, id, layer));
+        }
+
+        let insert_at = captured.first().map_or(0, |(index, _, _)| *index);
+        document.insert_layer_raw(insert_at, self.replacement_id.clone(), self.replacement.clone());
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        document.take_layer_raw(&self.replacement_id)?;
+        let mut captured = self.captured.lock();
+        // Reinsert in reverse removal order so every recorded index is valid again
+        for (index, id, layer) in captured.drain(..).rev() {
+            document.insert_layer_raw(index, id, layer);
+        }
+        Ok(())
+    }
+
+    fn memory_hint(&self) -> usize {
+        // While applied the command holds the removed layers, otherwise the
+        // replacement
+        let captured = self.captured.lock();
+        let graphs = if captured.is_empty() {
+            self.replacement.read().node_graph().estimated_memory()
+        } else {
+            captured
+                .iter()
+                .map(|(_, _, layer)| layer.read().node_graph().estimated_memory())
+                .sum()
+        };
+        std::mem::size_of_val(self) + graphs
+    }
+}
+
+/// Moves a layer to a new position in the stack.
+#[derive(Debug)]
+pub struct MoveLayerCommand {
+    layer_id: LayerId,
+    to_index: usize,
+    from_index: Mutex<Option<usize>>,
+}
+
+impl MoveLayerCommand {
+    /// `to_index` is the layer's position once moved, 0 being the bottom.
+    pub fn new(layer_id: LayerId, to_index: usize) -> Self {
+        Self {
+            layer_id,
+            to_index,
+            from_index: Mutex::new(None),
+        }
+    }
+
+    /// Rebuilds a command from a [`CommandRecord`], `from_index` being set if
+    /// it was applied.
+    pub(crate) fn restored(layer_id: LayerId, to_index: usize, from_index: Option<usize>) -> Self {
+        Self {
+            layer_id,
+            to_index,
+            from_index: Mutex::new(from_index),
+        }
+    }
+
+    fn reorder(document: &mut Document, id: &LayerId, to: usize) -> Result<(), Box<dyn Error>> {
+        let from = document.layer_index(id).ok_or(DocumentError::LayerNotFound(id.0))?;
+        if to >= document.layer_order.len() {
+            return Err(DocumentError::InvalidOperation(format!("Invalid layer index {}", to)).into());
+        }
+        let layer_id = document.layer_order.remove(from);
+        document.layer_order.insert(to, layer_id);
+        document.events.emit(DocumentEvent::LayerReordered { id: id.clone(), from, to });
+        Ok(())
+    }
+}
+
+impl Command for MoveLayerCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let from = document.layer_index(&self.layer_id).ok_or(DocumentError::LayerNotFound(self.layer_id.0))?;
+        Self::reorder(document, &self.layer_id, self.to_index)?;
+        *self.from_index.lock() = Some(from);
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        if let Some(from) = self.from_index.lock().take() {
+            Self::reorder(document, &self.layer_id, from)?;
+        }
+        Ok(())
+    }
+
+    fn to_record(&self) -> Option<CommandRecord> {
+        Some(CommandRecord::MoveLayer {
+            layer_id: self.layer_id.clone(),
+            to_index: self.to_index,
+            from_index: *self.from_index.lock(),
+        })
+    }
+}
+
+/// Adds a layer to the stack, by default on top.
+#[derive(Debug)]
+pub struct AddLayerCommand {
+    layer_id: LayerId,
+    layer: Arc<RwLock<Layer>>,
+    index: Option<usize>,
+}
+
+impl AddLayerCommand {
+    pub fn new(layer: Layer) -> Self {
+        Self {
+            layer_id: LayerId::new(),
+            layer: Arc::new(RwLock::new(layer)),
+            index: None,
+        }
+    }
+
+    /// Inserts the layer at `index`, 0 being the bottom, instead of on top.
+    pub fn at(mut self, index: usize) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    pub fn layer_id(&self) -> &LayerId {
+        &self.layer_id
+    }
+}
+
+impl Command for AddLayerCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let index = self.index.unwrap_or(document.layer_order.len());
+        document.insert_layer_raw(index, self.layer_id.clone(), self.layer.clone());
+        document.events.emit(DocumentEvent::LayerAdded(self.layer_id.clone()));
+        document.invalidate_region(document.layer_bounds(&self.layer_id)?);
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let bounds = document.layer_bounds(&self.layer_id)?;
+        document.take_layer_raw(&self.layer_id)?;
+        document.events.emit(DocumentEvent::LayerRemoved(self.layer_id.clone()));
+        document.invalidate_region(bounds);
+        Ok(())
+    }
+
+    fn memory_hint(&self) -> usize {
+        std::mem::size_of_val(self) + self.layer.read().node_graph().estimated_memory()
+    }
+}
+
+/// Removes a layer, keeping it so undo puts it back where it was.
+#[derive(Debug)]
+pub struct RemoveLayerCommand {
+    layer_id: LayerId,
+    captured: Mutex<Option<(usize, Arc<RwLock<Layer>>)>>,
+}
+
+impl RemoveLayerCommand {
+    pub fn new(layer_id: LayerId) -> Self {
+        Self {
+            layer_id,
+            captured: Mutex::new(None),
+        }
+    }
+}
+
+impl Command for RemoveLayerCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let bounds = document.layer_bounds(&self.layer_id)?;
+        *self.captured.lock() = Some(document.take_layer_raw(&self.layer_id)?);
+        document.events.emit(DocumentEvent::LayerRemoved(self.layer_id.clone()));
+        document.invalidate_region(bounds);
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        if let Some((index, layer)) = self.captured.lock().take() {
+            document.insert_layer_raw(index, self.layer_id.clone(), layer);
+            document.events.emit(DocumentEvent::LayerAdded(self.layer_id.clone()));
+            document.invalidate_region(document.layer_bounds(&self.layer_id)?);
+        }
+        Ok(())
+    }
+
+    fn memory_hint(&self) -> usize {
+        let captured = self.captured.lock();
+        let graph = captured.as_ref().map_or(0, |(_, layer)| layer.read().node_graph().estimated_memory());
+        std::mem::size_of_val(self) + graph
+    }
+}
+
+/// A layer property that [`SetLayerPropertyCommand`] changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayerProperty {
+    Visible(bool),
+    Opacity(f32),
+    BlendMode(BlendMode),
+}
+
+/// Sets a layer's visibility, opacity or blend mode, restoring the previous
+/// value on undo.
+#[derive(Debug)]
+pub struct SetLayerPropertyCommand {
+    layer_id: LayerId,
+    property: LayerProperty,
+    previous: Mutex<Option<LayerProperty>>,
+}
+
+impl SetLayerPropertyCommand {
+    pub fn new(layer_id: LayerId, property: LayerProperty) -> Self {
+        Self {
+            layer_id,
+            property,
+            previous: Mutex::new(None),
+        }
+    }
+
+    /// Applies `property`, returning the value it replaced.
+    fn apply(document: &mut Document, id: &LayerId, property: LayerProperty) -> Result<LayerProperty, Box<dyn Error>> {
+        let previous = document.with_layer_mut(id, |layer| match property {
+            LayerProperty::Visible(visible) => {
+                let previous = LayerProperty::Visible(layer.is_visible());
+                layer.set_visible(visible);
+                previous
+            }
+            LayerProperty::Opacity(opacity) => {
+                let previous = LayerProperty::Opacity(layer.opacity());
+                layer.set_opacity(opacity);
+                previous
+            }
+            LayerProperty::BlendMode(mode) => {
+                let previous = LayerProperty::BlendMode(layer.blend_mode());
+                layer.set_blend_mode(mode);
+                previous
+            }
+        })?;
+        document.events.emit(DocumentEvent::LayerPropertyChanged(id.clone()));
+        document.invalidate_region(document.layer_bounds(id)?);
+        Ok(previous)
+    }
+}
+
+impl Command for SetLayerPropertyCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let previous = Self::apply(document, &self.layer_id, self.property)?;
+        *self.previous.lock() = Some(previous);
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        if let Some(previous) = self.previous.lock().take() {
+            Self::apply(document, &self.layer_id, previous)?;
+        }
+        Ok(())
+    }
+}
+
+impl Document {
+    /// Composites a layer onto the one directly below it, replacing both with
+    /// a single image layer. The merged layer keeps the lower layer's name,
+    /// blend mode, and opacity; the upper layer's, and both transforms, are
+    /// baked into the pixels.
+    pub fn merge_down(&mut self, id: &LayerId) -> Result<LayerId, DocumentError> {
+        let index = self.layer_index(id).ok_or_else(|| DocumentError::LayerNotFound(id.0))?;
+        if index == 0 {
+            return Err(DocumentError::InvalidOperation(
+                "Cannot merge down the bottom layer".to_string(),
+            ));
+        }
+        let lower_id = self.layer_order[index - 1].clone();
+        let lower = self.get_layer(&lower_id).ok_or_else(|| DocumentError::LayerNotFound(lower_id.0))?;
+        let upper = self.get_layer(id).ok_or_else(|| DocumentError::LayerNotFound(id.0))?;
+
+        let context = self.eval_context();
+        let mut canvas = RgbaImage::from_pixel(self.width, self.height, Rgba([0, 0, 0, 0]));
+        let merged = {
+            let lower = lower.read();
+            let upper = upper.read();
+            if lower.is_visible() {
+                if let Some((image, x, y)) = lower.render_placed(&context, self.width, self.height)? {
+                    composite_onto(&mut canvas, &image, x, y, BlendMode::Normal, 1.0);
+                }
+            }
+            if upper.is_visible() {
+                if let Some((image, x, y)) = upper.render_placed(&context, self.width, self.height)? {
+                    composite_onto(&mut canvas, &image, x, y, upper.blend_mode(), upper.opacity());
+                }
+            }
+
+            let mut merged = Layer::with_image(lower.name(), DynamicImage::ImageRgba8(canvas));
+            merged.set_blend_mode(lower.blend_mode());
+            merged.set_opacity(lower.opacity());
+            merged
+        };
+
+        let command = ReplaceLayersCommand::new(vec![lower_id, id.clone()], merged);
+        let merged_id = command.replacement_id().clone();
+        self.execute_command(Box::new(command))?;
+        Ok(merged_id)
+    }
+
+    /// Replaces the whole layer stack with one layer holding the composite.
+    pub fn flatten(&mut self) -> Result<LayerId, DocumentError> {
+        if self.layer_order.is_empty() {
+            return Err(DocumentError::InvalidOperation(
+                "Cannot flatten a document without layers".to_string(),
+            ));
+        }
+
+        let composite = self.render_composite()?;
+        let flattened = Layer::with_image("Flattened", composite);
+        let command = ReplaceLayersCommand::new(self.layer_order.clone(), flattened);
+        let flattened_id = command.replacement_id().clone();
+        self.execute_command(Box::new(command))?;
+        Ok(flattened_id)
+    }
+}
+
+impl Document {
+    /// Moves a layer one step towards the top. Does nothing for the top layer.
+    pub fn move_layer_up(&mut self, id: &LayerId) -> Result<(), DocumentError> {
+        let index = self.layer_index(id).ok_or_else(|| DocumentError::LayerNotFound(id.0))?;
+        if index + 1 >= self.layer_order.len() {
+            return Ok(());
+        }
+        self.move_layer(id, index + 2)
+    }
+
+    /// Moves a layer one step towards the bottom. Does nothing for the bottom layer.
+    pub fn move_layer_down(&mut self, id: &LayerId) -> Result<(), DocumentError> {
+        let index = self.layer_index(id).ok_or_else(|| DocumentError::LayerNotFound(id.0))?;
+        if index == 0 {
+            return Ok(());
+        }
+        self.move_layer(id, index - 1)
+    }
+
+    /// Places `id` directly above `other`.
+    pub fn move_above(&mut self, id: &LayerId, other: &LayerId) -> Result<(), DocumentError> {
+        let other_index = self.relative_target(id, other)?;
+        self.move_layer(id, other_index + 1)
+    }
+
+    /// Places `id` directly below `other`.
+    pub fn move_below(&mut self, id: &LayerId, other: &LayerId) -> Result<(), DocumentError> {
+        let other_index = self.relative_target(id, other)?;
+        self.move_layer(id, other_index)
+    }
+
+    fn relative_target(&self, id: &LayerId, other: &LayerId) -> Result<usize, DocumentError> {
+        if id == other {
+            return Err(DocumentError::InvalidOperation(
+                "Cannot move a layer relative to itself".to_string(),
+            ));
+        }
+        self.layer_index(id).ok_or_else(|| DocumentError::LayerNotFound(id.0))?;
+        self.layer_index(other).ok_or_else(|| DocumentError::LayerNotFound(other.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, color: [u8; 4]) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba(color)))
+    }
+
+    fn add_image_layer(doc: &mut Document, name: &str, image: DynamicImage) -> LayerId {
+        let id = LayerId::new();
+        let index = doc.layer_count();
+        doc.insert_layer_raw(index, id.clone(), Arc::new(RwLock::new(Layer::with_image(name, image))));
+        id
+    }
+
+    fn multiply_document() -> (Document, LayerId, LayerId) {
+        let mut doc = Document::with_size(4, 4);
+        let bottom = add_image_layer(&mut doc, "Bottom", solid(4, 4, [200, 100, 50, 255]));
+        let top = add_image_layer(&mut doc, "Top", solid(4, 2, [128, 255, 64, 255]));
+        doc.get_layer(&top).unwrap().write().set_blend_mode(BlendMode::Multiply);
+        (doc, bottom, top)
+    }
+
+    #[test]
+    fn test_layer_commands_undo() {
+        let (mut doc, bottom, top) = multiply_document();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        doc.subscribe(move |event| match event {
+            DocumentEvent::LayerAdded(_) | DocumentEvent::LayerRemoved(_) | DocumentEvent::LayerPropertyChanged(_) => {
+                sink.lock().push(event.clone())
+            }
+            _ => {}
+        });
+
+        let add = AddLayerCommand::new(Layer::new()).at(1);
+        let added = add.layer_id().clone();
+        doc.execute_command(Box::new(add)).unwrap();
+        assert_eq!(doc.layers().cloned().collect::<Vec<_>>(), vec![bottom.clone(), added.clone(), top.clone()]);
+        doc.execute_command(Box::new(RemoveLayerCommand::new(bottom.clone()))).unwrap();
+        doc.execute_command(Box::new(SetLayerPropertyCommand::new(top.clone(), LayerProperty::Opacity(0.25)))).unwrap();
+        doc.execute_command(Box::new(SetLayerPropertyCommand::new(top.clone(), LayerProperty::Visible(false)))).unwrap();
+        {
+            let layer = doc.get_layer(&top).unwrap();
+            assert_eq!((layer.read().opacity(), layer.read().is_visible()), (0.25, false));
+        }
+
+        for _ in 0..4 {
+            doc.undo().unwrap();
+        }
+        assert_eq!(doc.layers().cloned().collect::<Vec<_>>(), vec![bottom.clone(), top.clone()]);
+        let layer = doc.get_layer(&top).unwrap();
+        assert_eq!((layer.read().opacity(), layer.read().is_visible()), (1.0, true));
+        assert_eq!(
+            *events.lock(),
+            vec![
+                DocumentEvent::LayerAdded(added.clone()),
+                DocumentEvent::LayerRemoved(bottom.clone()),
+                DocumentEvent::LayerPropertyChanged(top.clone()),
+                DocumentEvent::LayerPropertyChanged(top.clone()),
+                DocumentEvent::LayerPropertyChanged(top.clone()),
+                DocumentEvent::LayerPropertyChanged(top),
+                DocumentEvent::LayerAdded(bottom),
+                DocumentEvent::LayerRemoved(added),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_flatten_matches_composite() {
+        let (mut doc, _, _) = multiply_document();
+        let before = doc.render_composite().unwrap().to_rgba8();
+
+        let flattened = doc.flatten().unwrap();
+        assert_eq!(doc.layer_count(), 1);
+        assert_eq!(doc.layers().next(), Some(&flattened));
+        assert_eq!(doc.render_composite().unwrap().to_rgba8(), before);
+    }
+
+    #[test]
+    fn test_flatten_undo_restores_layers() {
+        let (mut doc, bottom, top) = multiply_document();
+        doc.flatten().unwrap();
+        doc.undo().unwrap();
+
+        let order: Vec<_> = doc.layers().cloned().collect();
+        assert_eq!(order, vec![bottom, top.clone()]);
+        assert_eq!(doc.get_layer(&top).unwrap().read().blend_mode(), BlendMode::Multiply);
+
+        doc.redo().unwrap();
+        assert_eq!(doc.layer_count(), 1);
+    }
+
+    #[test]
+    fn test_merge_down() {
+        let (mut doc, bottom, top) = multiply_document();
+        let other = add_image_layer(&mut doc, "Other", solid(1, 1, [0, 0, 0, 255]));
+        let before = doc.render_composite().unwrap().to_rgba8();
+
+        let merged = doc.merge_down(&top).unwrap();
+        let order: Vec<_> = doc.layers().cloned().collect();
+        assert_eq!(order, vec![merged.clone(), other.clone()]);
+        assert_eq!(doc.get_layer(&merged).unwrap().read().name(), "Bottom");
+        assert_eq!(doc.render_composite().unwrap().to_rgba8(), before);
+
+        doc.undo().unwrap();
+        let order: Vec<_> = doc.layers().cloned().collect();
+        assert_eq!(order, vec![bottom, top, other]);
+    }
+
+    #[test]
+    fn test_merge_down_bottom_layer_errors() {
+        let (mut doc, bottom, _) = multiply_document();
+        assert!(matches!(doc.merge_down(&bottom), Err(DocumentError::InvalidOperation(_))));
+        assert!(!doc.can_undo());
+    }
+
+    fn stack(doc: &Document) -> Vec<LayerId> {
+        doc.layers().cloned().collect()
+    }
+
+    fn three_layers() -> (Document, LayerId, LayerId, LayerId) {
+        let mut doc = Document::with_size(1, 1);
+        let a = doc.add_layer();
+        let b = doc.add_layer();
+        let c = doc.add_layer();
+        (doc, a, b, c)
+    }
+
+    #[test]
+    fn test_move_layer_to_top_and_bottom() {
+        let (mut doc, a, b, c) = three_layers();
+        doc.move_layer(&a, 3).unwrap();
+        assert_eq!(stack(&doc), vec![b.clone(), c.clone(), a.clone()]);
+        doc.move_layer(&a, 0).unwrap();
+        assert_eq!(stack(&doc), vec![a.clone(), b.clone(), c.clone()]);
+        assert!(matches!(doc.move_layer(&a, 4), Err(DocumentError::InvalidOperation(_))));
+
+        doc.undo().unwrap();
+        assert_eq!(stack(&doc), vec![b, c, a]);
+    }
+
+    #[test]
+    fn test_relative_moves() {
+        let (mut doc, a, b, c) = three_layers();
+        doc.move_layer_up(&a).unwrap();
+        assert_eq!(stack(&doc), vec![b.clone(), a.clone(), c.clone()]);
+        doc.move_layer_down(&c).unwrap();
+        assert_eq!(stack(&doc), vec![b.clone(), c.clone(), a.clone()]);
+        doc.move_above(&b, &a).unwrap();
+        assert_eq!(stack(&doc), vec![c.clone(), a.clone(), b.clone()]);
+        doc.move_below(&b, &c).unwrap();
+        assert_eq!(stack(&doc), vec![b.clone(), c.clone(), a.clone()]);
+        assert!(doc.move_above(&a, &a).is_err());
+    }
+
+    #[test]
+    fn test_noop_moves_skip_history() {
+        let (mut doc, a, b, c) = three_layers();
+        doc.move_layer_up(&c).unwrap();
+        doc.move_layer_down(&a).unwrap();
+        doc.move_above(&b, &a).unwrap();
+        doc.move_below(&a, &b).unwrap();
+        doc.move_layer(&b, 1).unwrap();
+        doc.move_layer(&b, 2).unwrap();
+        assert!(!doc.can_undo());
+        assert_eq!(stack(&doc), vec![a, b, c]);
+    }
+
+    #[test]
+    fn test_moves_emit_reordered() {
+        let (mut doc, a, _, _) = three_layers();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        doc.subscribe(move |event| {
+            if let DocumentEvent::LayerReordered { .. } = event {
+                sink.lock().push(event.clone());
+            }
+        });
+
+        doc.move_layer_up(&a).unwrap();
+        doc.undo().unwrap();
+        assert_eq!(
+            *events.lock(),
+            vec![
+                DocumentEvent::LayerReordered { id: a.clone(), from: 0, to: 1 },
+                DocumentEvent::LayerReordered { id: a, from: 1, to: 0 },
+            ]
+        );
+    }
+}
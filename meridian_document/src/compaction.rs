@@ -0,0 +1,220 @@
+//! Consolidating old history entries into a single snapshot, see
+//! [`crate::History::compact`].
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+use aurion_core::NodeGraph;
+use parking_lot::RwLock;
+use crate::{Command, Document, DocumentError, DocumentEvent, HistoryError, Layer, LayerId};
+
+/// Copies `layers`, giving each graph an independent copy. Linked layers
+/// stay linked to each other.
+fn copy_layers(layers: &[(LayerId, Arc<RwLock<Layer>>)]) -> Vec<(LayerId, Arc<RwLock<Layer>>)> {
+    let mut graphs: HashMap<usize, Arc<RwLock<NodeGraph>>> = HashMap::new();
+    layers
+        .iter()
+        .map(|(id, layer)| {
+            let layer = layer.read();
+            let graph = graphs
+                .entry(layer.graph_key())
+                .or_insert_with(|| Arc::new(RwLock::new(layer.node_graph().deep_clone())));
+            let mut copy = layer.linked_copy();
+            copy.node_graph = graph.clone();
+            (id.clone(), Arc::new(RwLock::new(copy)))
+        })
+        .collect()
+}
+
+/// The first entry of a compacted history, standing in for the commands it
+/// replaced. It can't be undone; executing it restores the document to the
+/// state captured when the history was compacted.
+#[derive(Debug)]
+pub struct SnapshotBarrier {
+    size: (u32, u32),
+    // Bottom first
+    layers: Vec<(LayerId, Arc<RwLock<Layer>>)>,
+}
+
+impl SnapshotBarrier {
+    pub(crate) fn capture(document: &Document) -> Self {
+        let layers: Vec<_> = document
+            .layer_order
+            .iter()
+            .filter_map(|id| document.get_layer(id).map(|layer| (id.clone(), layer)))
+            .collect();
+        Self {
+            size: document.size(),
+            layers: copy_layers(&layers),
+        }
+    }
+}
+
+impl Command for SnapshotBarrier {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        for id in document.layer_order.clone() {
+            document.take_layer_raw(&id)?;
+        }
+        // Copied again so the snapshot survives edits after the restore
+        for (index, (id, layer)) in copy_layers(&self.layers).into_iter().enumerate() {
+            document.insert_layer_raw(index, id, layer);
+        }
+        let layers = &document.layers;
+        document.render_cache.retain(|id| layers.contains_key(id));
+
+        if document.size() != self.size {
+            (document.width, document.height) = self.size;
+            document.events.emit(DocumentEvent::CanvasResized { width: self.size.0, height: self.size.1 });
+        }
+        for id in &document.layer_order {
+            document.events.emit(DocumentEvent::GraphChanged(id.clone()));
+        }
+        document.invalidate_region(document.canvas_rect());
+        Ok(())
+    }
+
+    fn undo(&self, _document: &mut Document) -> Result<(), Box<dyn Error>> {
+        Err(Box::new(HistoryError::BarrierReached))
+    }
+
+    fn memory_hint(&self) -> usize {
+        let mut graphs = HashSet::new();
+        let nodes: usize = self
+            .layers
+            .iter()
+            .map(|(_, layer)| layer.read())
+            .filter(|layer| graphs.insert(layer.graph_key()))
+            .map(|layer| layer.node_graph().estimated_memory())
+            .sum();
+        std::mem::size_of_val(self) + nodes
+    }
+}
+
+impl Document {
+    /// Compacts the undo history, keeping the `keep_recent` latest applied
+    /// commands. See [`crate::History::compact`].
+    pub fn compact_history(&mut self, keep_recent: usize) -> Result<(), DocumentError> {
+        let mut history = std::mem::take(&mut self.history);
+        let result = history.compact(keep_recent, self);
+        self.history = history;
+        result.map_err(|e| DocumentError::Other(e.to_string()))
+    }
+
+    /// Returns to the state the history was compacted at in one step.
+    pub fn undo_to_barrier(&mut self) -> Result<(), DocumentError> {
+        let was_modified = self.is_modified();
+        let mut history = std::mem::take(&mut self.history);
+        let result = history.undo_to_barrier(self);
+        self.history = history;
+        result.map_err(|e| DocumentError::Other(e.to_string()))?;
+        self.record_change(was_modified);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use crate::{LayerTransform, NamePolicy};
+
+    fn document() -> (Document, Vec<LayerId>) {
+        let mut doc = Document::with_size(96, 96);
+        let mut ids = Vec::new();
+        for index in 0..4u8 {
+            let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(96, 96, Rgba([index * 60, 90, 200 - index * 40, 160])));
+            let id = LayerId::new();
+            doc.insert_layer_raw(index as usize, id.clone(), Arc::new(RwLock::new(Layer::with_image(format!("Layer {}", index), image))));
+            ids.push(id);
+        }
+        (doc, ids)
+    }
+
+    fn state(doc: &Document) -> (Vec<(LayerId, String, LayerTransform)>, Vec<u8>) {
+        let layers = doc
+            .layer_order
+            .iter()
+            .map(|id| {
+                let layer = doc.get_layer(id).unwrap();
+                let layer = layer.read();
+                (id.clone(), layer.name().to_string(), layer.transform())
+            })
+            .collect();
+        (layers, doc.render_composite().unwrap().to_rgba8().into_raw())
+    }
+
+    /// Merges every layer down into the bottom one, each merge capturing
+    /// the two layers it replaced.
+    fn merge_all(doc: &mut Document) {
+        while doc.layer_order.len() > 1 {
+            let top = doc.layer_order.last().unwrap().clone();
+            doc.merge_down(&top).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_compaction_releases_captured_layers() {
+        let (mut doc, _) = document();
+        merge_all(&mut doc);
+        let merged = doc.layer_order[0].clone();
+        doc.set_layer_transform(&merged, LayerTransform::translation(4.0, 2.0)).unwrap();
+        let before = doc.history.memory_bytes();
+        let current = state(&doc);
+
+        doc.compact_history(1).unwrap();
+        assert!(doc.history.memory_bytes() < before / 2);
+        assert_eq!(state(&doc), current);
+
+        // The kept command still undoes; then the barrier stops undo
+        doc.undo().unwrap();
+        assert_eq!(doc.get_layer(&merged).unwrap().read().transform(), LayerTransform::IDENTITY);
+        assert!(!doc.can_undo());
+        let error = doc.undo().unwrap_err();
+        assert!(error.to_string().contains("compacted"), "{}", error);
+        assert_eq!(doc.layer_order, vec![merged]);
+    }
+
+    #[test]
+    fn test_undo_to_barrier_restores_compacted_state() {
+        let (mut doc, ids) = document();
+        merge_all(&mut doc);
+        doc.compact_history(0).unwrap();
+        let compacted = state(&doc);
+
+        let merged = doc.layer_order[0].clone();
+        doc.rename_layer(&merged, "Merged", NamePolicy::AllowDuplicates).unwrap();
+        doc.set_layer_transform(&merged, LayerTransform::translation(-6.0, 8.0)).unwrap();
+        doc.resize_canvas(120, 80, crate::Anchor::Center).unwrap();
+        let edited = state(&doc);
+
+        doc.undo_to_barrier().unwrap();
+        assert_eq!(doc.size(), (96, 96));
+        assert_eq!(state(&doc), compacted);
+        assert!(!doc.can_undo());
+
+        for _ in 0..3 {
+            doc.redo().unwrap();
+        }
+        assert_eq!(state(&doc), edited);
+        assert!(!ids.iter().any(|id| doc.get_layer(id).is_some()));
+    }
+
+    #[test]
+    fn test_compaction_keeps_saved_position() {
+        let (mut doc, ids) = document();
+        for step in 0..5 {
+            doc.set_layer_transform(&ids[1], LayerTransform::translation(step as f32, 0.0)).unwrap();
+        }
+        doc.mark_saved();
+
+        doc.compact_history(0).unwrap();
+        assert!(!doc.is_modified());
+        assert!(doc.history.has_barrier());
+
+        // Compacting again folds the old barrier into the new one
+        doc.set_layer_transform(&ids[1], LayerTransform::translation(9.0, 9.0)).unwrap();
+        doc.compact_history(0).unwrap();
+        assert_eq!(doc.history.commands().len(), 1);
+        assert!(doc.undo().is_err());
+    }
+}
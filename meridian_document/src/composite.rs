@@ -0,0 +1,304 @@
+//! Flattening the layer stack into a single image.
+
+use std::time::Instant;
+use aurion_core::{EvalContext, EvaluationReport, NodeGraph, ReportRecorder};
+use image::{DynamicImage, Rgba, Rgba32FImage, RgbaImage};
+use crate::blend::{composite_onto, composite_onto_linear};
+use crate::color::linear_to_srgb_image;
+use crate::{BlendMode, CanvasRect, Document, DocumentError, Layer};
+
+impl Layer {
+    /// Evaluates the layer's node graph and returns the image it produces.
+    ///
+    /// The output is taken from the graph's designated output node or,
+    /// without one, its sink nodes (nodes nothing else consumes). Returns
+    /// `None` for layers whose graph yields no image.
+    pub fn render_output(&self) -> Result<Option<DynamicImage>, DocumentError> {
+        self.render_output_with(&EvalContext::new())
+    }
+
+    /// Like [`Layer::render_output`], with `context` passed to every node.
+    /// Layers referencing document assets need [`Document::eval_context`].
+    pub fn render_output_with(&self, context: &EvalContext) -> Result<Option<DynamicImage>, DocumentError> {
+        graph_output(&self.node_graph(), context)
+    }
+}
+
+/// The image a layer graph produces; see [`Layer::render_output`].
+pub(crate) fn graph_output(graph: &NodeGraph, context: &EvalContext) -> Result<Option<DynamicImage>, DocumentError> {
+    if let Some(output) = graph.designated_output() {
+        let result = graph.evaluate_with_context(output, context)?;
+        return Ok(result.into_image());
+    }
+
+    // The first sink added wins when a graph has several
+    for node_id in graph.sink_nodes() {
+        let result = graph.evaluate_with_context(&node_id, context)?;
+        if let Some(image) = result.into_image() {
+            return Ok(Some(image));
+        }
+    }
+    Ok(None)
+}
+
+/// Hands a layer's placed output, its offset within the region, blend mode
+/// and opacity to the compositor.
+pub(crate) type DrawLayer<'a> = dyn FnMut(&DynamicImage, i64, i64, BlendMode, f32) + 'a;
+
+/// Flattens layers into `rect` of a canvas covering `canvas`. `layers` is
+/// given the visible part of `rect` and draws each layer bottom to top.
+pub(crate) fn flatten<F>(canvas: CanvasRect, rect: CanvasRect, linear: bool, layers: F) -> Result<DynamicImage, DocumentError>
+where
+    F: FnOnce(CanvasRect, &mut DrawLayer) -> Result<(), DocumentError>,
+{
+    let Some(visible) = rect.intersect(&canvas) else {
+        return Ok(DynamicImage::ImageRgba8(RgbaImage::new(rect.width, rect.height)));
+    };
+
+    let rendered = if linear {
+        let mut canvas = Rgba32FImage::new(visible.width, visible.height);
+        layers(visible, &mut |image, x, y, mode, opacity| {
+            composite_onto_linear(&mut canvas, image, x, y, mode, opacity)
+        })?;
+        linear_to_srgb_image(&canvas)
+    } else {
+        let mut canvas = RgbaImage::from_pixel(visible.width, visible.height, Rgba([0, 0, 0, 0]));
+        layers(visible, &mut |image, x, y, mode, opacity| {
+            composite_onto(&mut canvas, image, x, y, mode, opacity)
+        })?;
+        DynamicImage::ImageRgba8(canvas)
+    };
+
+    if visible == rect {
+        return Ok(rendered);
+    }
+    let mut padded = RgbaImage::new(rect.width, rect.height);
+    image::imageops::replace(&mut padded, &rendered.to_rgba8(), visible.x - rect.x, visible.y - rect.y);
+    Ok(DynamicImage::ImageRgba8(padded))
+}
+
+impl Document {
+    /// Composites all visible layers bottom to top onto a transparent canvas
+    /// of the document's size, each placed by its [`crate::LayerTransform`].
+    ///
+    /// Profiles that blend in linear light composite on a float canvas and
+    /// encode the result back to sRGB at the end.
+    pub fn render_composite(&self) -> Result<DynamicImage, DocumentError> {
+        self.render_region(self.canvas_rect())
+    }
+
+    /// Composites only `rect` of the canvas, e.g. the part of a viewport that
+    /// needs repainting. The result equals the same crop of
+    /// [`Document::render_composite`]; parts of `rect` outside the canvas are
+    /// transparent.
+    ///
+    /// Layers are still evaluated whole, but through the render cache, so
+    /// only layers whose graph changed are evaluated again.
+    ///
+    /// With graph compositing enabled and an output node set, the
+    /// [`crate::CompositingGraph`] is rendered instead of the layer stack,
+    /// unless a layer is soloed (see [`Document::set_solo`]).
+    pub fn render_region(&self, rect: CanvasRect) -> Result<DynamicImage, DocumentError> {
+        self.render_region_with(rect, self.eval_context())
+    }
+
+    /// Like [`Document::render_composite`], also reporting how long the
+    /// render took, each node evaluated and the nodes that failed. Layers
+    /// served from the render cache aren't evaluated, so their nodes don't
+    /// appear.
+    pub fn render_composite_report(&self) -> (Result<DynamicImage, DocumentError>, EvaluationReport) {
+        let recorder = ReportRecorder::new();
+        let started = Instant::now();
+        let result = self.render_region_with(self.canvas_rect(), self.eval_context().with(recorder.clone()));
+        let mut report = recorder.take();
+        report.total = started.elapsed();
+        (result, report)
+    }
+
+    /// Like [`Document::render_composite_report`], evaluating the layers
+    /// the composite needs at the same time, one thread each, before
+    /// compositing them. Nodes within a layer still run one after another.
+    pub fn render_composite_parallel_report(&self) -> (Result<DynamicImage, DocumentError>, EvaluationReport) {
+        let recorder = ReportRecorder::new();
+        let started = Instant::now();
+        let context = self.eval_context().with(recorder.clone());
+        let result = self
+            .prerender_layers(&context)
+            .and_then(|()| self.render_region_with(self.canvas_rect(), context));
+        let mut report = recorder.take();
+        report.total = started.elapsed();
+        (result, report)
+    }
+
+    /// Evaluates the layers a composite reads into the render cache, one
+    /// thread each, so compositing afterwards only hits the cache. Returns
+    /// the first layer's error, bottom to top.
+    fn prerender_layers(&self, context: &EvalContext) -> Result<(), DocumentError> {
+        // The compositing graph reads its visible source layers, the layer
+        // stack its shown layers
+        let sources = self
+            .compositing
+            .as_ref()
+            .filter(|compositing| self.solo().is_none() && compositing.output().is_some())
+            .map(|compositing| compositing.layers());
+        let sources = sources.as_ref();
+        std::thread::scope(|scope| {
+            let renders: Vec<_> = self
+                .layer_order
+                .iter()
+                .map(|id| {
+                    scope.spawn(move || {
+                        let Some(layer) = self.get_layer(id) else {
+                            return Ok(());
+                        };
+                        let layer = layer.read();
+                        let read = match sources {
+                            Some(sources) => sources.contains(id) && layer.is_visible(),
+                            None => self.is_shown(id, &layer),
+                        };
+                        if read {
+                            self.layer_output(id, &layer, context)?;
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            renders
+                .into_iter()
+                .map(|render| render.join().unwrap_or_else(|_| Err(DocumentError::Other("A layer render panicked".to_string()))))
+                .collect()
+        })
+    }
+
+    fn render_region_with(&self, rect: CanvasRect, context: EvalContext) -> Result<DynamicImage, DocumentError> {
+        let linear = self.color_profile.blends_in_linear_light();
+        if let Some(compositing) = self.compositing.as_ref().filter(|_| self.solo().is_none()) {
+            if let Some(output) = compositing.output() {
+                return flatten(self.canvas_rect(), rect, linear, |region, draw| {
+                    self.composite_graph(compositing, output, region, context, draw)
+                });
+            }
+        }
+        flatten(self.canvas_rect(), rect, linear, |region, draw| self.composite_layers(region, &context, draw))
+    }
+
+    /// Renders each shown layer bottom to top and hands the part of its
+    /// placed output inside `region` to `draw`, offset relative to the region.
+    fn composite_layers(&self, region: CanvasRect, context: &EvalContext, draw: &mut DrawLayer) -> Result<(), DocumentError> {
+        for layer_id in &self.layer_order {
+            let Some(layer) = self.get_layer(layer_id) else {
+                continue;
+            };
+            let layer = layer.read();
+            if !self.is_shown(layer_id, &layer) {
+                continue;
+            }
+            let Some(output) = self.layer_output(layer_id, &layer, context)? else {
+                continue;
+            };
+            if let Some((image, x, y)) = layer.transform().place(&output, region) {
+                draw(&image, x, y, layer.blend_mode(), layer.opacity());
+            }
+        }
+        self.render_cache.retain(|id| self.layers.contains_key(id));
+        self.retain_previews();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aurion_core::Node;
+    use aurion_std_nodes::filters::InvertNode;
+    use aurion_std_nodes::ImageNode;
+
+    fn solid(width: u32, height: u32, color: [u8; 4]) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba(color)))
+    }
+
+    fn add_image_layer(doc: &mut Document, image: DynamicImage) -> crate::LayerId {
+        let id = doc.add_layer();
+        let layer = doc.get_layer(&id).unwrap();
+        layer.write().node_graph_mut().add_node(Node::new(Box::new(ImageNode::with_image(image))));
+        id
+    }
+
+    #[test]
+    fn test_empty_document_is_transparent() {
+        let doc = Document::with_size(8, 4);
+        let image = doc.render_composite().unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (8, 4));
+        assert!(image.pixels().all(|p| p[3] == 0));
+    }
+
+    #[test]
+    fn test_composite_blends_layers_in_order() {
+        let mut doc = Document::with_size(4, 4);
+        add_image_layer(&mut doc, solid(4, 4, [255, 255, 255, 255]));
+        let top = add_image_layer(&mut doc, solid(2, 2, [128, 128, 128, 255]));
+        doc.get_layer(&top).unwrap().write().set_blend_mode(BlendMode::Multiply);
+
+        let image = doc.render_composite().unwrap().to_rgba8();
+        assert_eq!(*image.get_pixel(0, 0), Rgba([128, 128, 128, 255]));
+        assert_eq!(*image.get_pixel(3, 3), Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn test_render_evaluates_only_layer_outputs() {
+        let mut doc = Document::with_size(2, 2);
+        let id = add_image_layer(&mut doc, solid(2, 2, [255, 0, 0, 255]));
+        {
+            let layer = doc.get_layer(&id).unwrap();
+            let mut layer = layer.write();
+            let mut graph = layer.node_graph_mut();
+            let image = graph.get_node_ids()[0].clone();
+            let invert = graph.add_node_data(Box::new(InvertNode::new()));
+            graph.connect(&image, &invert, "input").unwrap();
+        }
+
+        let images = doc.render().unwrap();
+        assert_eq!(images.len(), 1);
+        let image = images[0].downcast_ref::<DynamicImage>().unwrap().to_rgba8();
+        assert_eq!(*image.get_pixel(0, 0), Rgba([0, 255, 255, 255]));
+    }
+
+    #[test]
+    fn test_report_covers_evaluated_nodes() {
+        let mut doc = Document::with_size(2, 2);
+        add_image_layer(&mut doc, solid(2, 2, [255, 0, 0, 255]));
+        let (image, report) = doc.render_composite_report();
+        assert!(image.is_ok());
+        assert_eq!(report.timings.len(), 1);
+        assert_eq!(report.slowest().map(|timing| timing.type_name), Some("ImageNode"));
+        assert!(report.failures.is_empty());
+
+        // The cached layer isn't evaluated again
+        let (_, report) = doc.render_composite_report();
+        assert!(report.timings.is_empty());
+    }
+
+    #[test]
+    fn test_parallel_render_matches_sequential() {
+        let mut doc = Document::with_size(4, 4);
+        add_image_layer(&mut doc, solid(4, 4, [255, 255, 255, 255]));
+        let top = add_image_layer(&mut doc, solid(2, 2, [128, 128, 128, 255]));
+        doc.get_layer(&top).unwrap().write().set_blend_mode(BlendMode::Multiply);
+        let sequential = doc.render_composite().unwrap().to_rgba8();
+
+        doc.clear_render_cache();
+        let (image, report) = doc.render_composite_parallel_report();
+        assert_eq!(image.unwrap().to_rgba8(), sequential);
+        assert_eq!(report.timings.len(), 2);
+    }
+
+    #[test]
+    fn test_hidden_layers_are_skipped() {
+        let mut doc = Document::with_size(2, 2);
+        let id = add_image_layer(&mut doc, solid(2, 2, [255, 0, 0, 255]));
+        doc.get_layer(&id).unwrap().write().set_visible(false);
+
+        let image = doc.render_composite().unwrap().to_rgba8();
+        assert_eq!(*image.get_pixel(0, 0), Rgba([0, 0, 0, 0]));
+    }
+}
@@ -0,0 +1,471 @@
+//! Compositing the document through a node graph instead of the layer stack.
+//!
+//! Each layer is brought into the graph by a [`LayerSourceNode`], which
+//! yields the layer's output placed on a canvas-sized image. The graph's
+//! designated output node becomes the document's composite, so a layer can
+//! feed several branches, e.g. be blended with a blurred copy of itself.
+//! Until an output is designated the layer stack is used.
+
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Once};
+use aurion_core::{EvalContext, Node, NodeData, NodeError, NodeFactory, NodeGraph, NodeId, NodeValue, ParameterSchema, ParameterSpec, PortSpec};
+use aurion_std_nodes::factories::register_standard_nodes;
+use image::{DynamicImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::warn;
+use uuid::Uuid;
+use crate::composite::DrawLayer;
+use crate::{BlendMode, CanvasRect, Document, DocumentError, LayerId, LayerTransform};
+
+/// Placed layer outputs handed to [`LayerSourceNode`]s through the
+/// [`EvalContext`].
+#[derive(Clone)]
+struct LayerImages {
+    width: u32,
+    height: u32,
+    images: Arc<HashMap<LayerId, DynamicImage>>,
+}
+
+/// Outputs a layer of the document: its graph's output placed by the layer's
+/// transform on a transparent canvas of the document's size. Hidden and
+/// missing layers give a transparent canvas. Opacity and blend mode are left
+/// to the compositing graph.
+#[derive(Debug, Clone)]
+pub struct LayerSourceNode {
+    layer: LayerId,
+}
+
+impl LayerSourceNode {
+    pub const TYPE_NAME: &'static str = "LayerSourceNode";
+
+    pub fn new(layer: LayerId) -> Self {
+        Self { layer }
+    }
+
+    pub fn layer(&self) -> &LayerId {
+        &self.layer
+    }
+}
+
+impl NodeData for LayerSourceNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        Self::TYPE_NAME
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        Box::new(self.clone())
+    }
+
+    fn get_parameter(&self, name: &str) -> Option<Value> {
+        (name == "layer").then(|| Value::from(self.layer.0.to_string()))
+    }
+
+    fn parameter_names(&self) -> &'static [&'static str] {
+        &["layer"]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: Value) -> Result<(), NodeError> {
+        let layer = value
+            .as_str()
+            .filter(|_| name == "layer")
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .ok_or_else(|| NodeError::InvalidParameter {
+                name: name.to_string(),
+                reason: format!("{} takes a layer id, got {}", self.type_name(), value),
+            })?;
+        self.layer = LayerId(layer);
+        Ok(())
+    }
+
+    // No content hash: the output changes with the layer, not the node
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+        self.compute_with_context(inputs, &EvalContext::new())
+    }
+
+    fn compute_with_context(&self, inputs: &[NodeValue], context: &EvalContext) -> Result<NodeValue, NodeError> {
+        if !inputs.is_empty() {
+            return Err(NodeError::InvalidInputType {
+                expected: "none".to_string(),
+                actual: format!("{} inputs", inputs.len()),
+            });
+        }
+        let layers = context.get::<LayerImages>().ok_or_else(|| NodeError::ComputationError {
+            context: self.type_name().to_string(),
+            message: "Layer outputs are only available when compositing a document".to_string(),
+        })?;
+        let image = match layers.images.get(&self.layer) {
+            Some(image) => image.clone(),
+            None => {
+                warn!("Compositing graph references missing layer {}", self.layer.0);
+                DynamicImage::ImageRgba8(RgbaImage::new(layers.width, layers.height))
+            }
+        };
+        Ok(NodeValue::image(image))
+    }
+}
+
+struct LayerSourceFactory;
+
+impl NodeFactory for LayerSourceFactory {
+    fn create(&self, parameters: &Value) -> Result<Box<dyn NodeData>, NodeError> {
+        let mut node = LayerSourceNode::new(LayerId(Uuid::nil()));
+        node.set_parameter("layer", parameters.get("layer").cloned().unwrap_or_default())?;
+        Ok(Box::new(node))
+    }
+
+    fn type_name(&self) -> &'static str {
+        LayerSourceNode::TYPE_NAME
+    }
+
+    fn category(&self) -> Option<&'static str> {
+        Some("Input")
+    }
+
+    fn parameter_schema(&self) -> ParameterSchema {
+        ParameterSchema::new().with(ParameterSpec::string("layer", &Uuid::nil().to_string()))
+    }
+}
+
+/// Registers the standard nodes and [`LayerSourceNode`] with the global
+/// registry. Calls after the first do nothing.
+pub fn register_document_nodes() {
+    static REGISTER: Once = Once::new();
+    register_standard_nodes();
+    REGISTER.call_once(|| aurion_core::register_node_factory(LayerSourceFactory));
+}
+
+/// The graph a document is composited through; see the module docs.
+#[derive(Clone)]
+pub struct CompositingGraph {
+    graph: NodeGraph,
+    output: Option<NodeId>,
+}
+
+impl std::fmt::Debug for CompositingGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompositingGraph")
+            .field("nodes", &self.graph.get_node_ids().len())
+            .field("output", &self.output)
+            .finish()
+    }
+}
+
+impl CompositingGraph {
+    /// A graph holding a source node for each of `layers` and no output.
+    pub fn with_sources(layers: &[LayerId]) -> Self {
+        let mut compositing = Self { graph: NodeGraph::new(), output: None };
+        for layer in layers {
+            compositing.add_source(layer.clone());
+        }
+        compositing
+    }
+
+    pub fn graph(&self) -> &NodeGraph {
+        &self.graph
+    }
+
+    pub fn graph_mut(&mut self) -> &mut NodeGraph {
+        &mut self.graph
+    }
+
+    pub fn add_source(&mut self, layer: LayerId) -> NodeId {
+        self.graph.add_node(Node::new(Box::new(LayerSourceNode::new(layer))))
+    }
+
+    /// The first source node of `layer`, if any.
+    pub fn source_node(&self, layer: &LayerId) -> Option<NodeId> {
+        let mut ids = self.graph.get_node_ids();
+        ids.sort_by_key(|id| id.0);
+        ids.into_iter().find(|id| self.source_layer(id).as_ref() == Some(layer))
+    }
+
+    fn source_layer(&self, id: &NodeId) -> Option<LayerId> {
+        let node = self.graph.get_node(id)?;
+        let node = node.read();
+        node.data().as_any().downcast_ref::<LayerSourceNode>().map(|source| source.layer.clone())
+    }
+
+    /// Layers read by source nodes.
+    pub(crate) fn layers(&self) -> Vec<LayerId> {
+        let mut layers: Vec<_> = self.graph.get_node_ids().iter().filter_map(|id| self.source_layer(id)).collect();
+        layers.sort_by_key(|layer| layer.0);
+        layers.dedup();
+        layers
+    }
+
+    pub fn output(&self) -> Option<&NodeId> {
+        self.output.as_ref()
+    }
+
+    /// Designates the node whose image is the document's composite. `None`
+    /// composites the layer stack instead.
+    pub fn set_output(&mut self, output: Option<NodeId>) -> Result<(), DocumentError> {
+        if let Some(id) = &output {
+            if self.graph.get_node(id).is_none() {
+                return Err(NodeError::NodeNotFound(id.0).into());
+            }
+        }
+        self.output = output;
+        Ok(())
+    }
+
+    /// Nodes are saved as their type and named parameters (see
+    /// [`NodeData::parameter_names`]); any other state, such as an image
+    /// node's pixels, is not saved.
+    pub(crate) fn to_serialized(&self) -> SerializedCompositing {
+        let mut ids = self.graph.get_node_ids();
+        ids.sort_by_key(|id| id.0);
+        let nodes = ids
+            .into_iter()
+            .filter_map(|id| self.graph.get_node(&id))
+            .map(|node| {
+                let node = node.read();
+                let data = node.data();
+                let parameters = data
+                    .parameter_names()
+                    .iter()
+                    .filter_map(|name| Some((name.to_string(), data.get_parameter(name)?)))
+                    .collect();
+                SerializedNode {
+                    id: node.id().clone(),
+                    type_name: data.type_name().to_string(),
+                    parameters,
+                    inputs: node.inputs().clone(),
+                    outputs: node
+                        .inputs()
+                        .keys()
+                        .filter_map(|input| Some((input.clone(), node.source_output(input)?.to_string())))
+                        .collect(),
+                }
+            })
+            .collect();
+        SerializedCompositing { nodes, output: self.output.clone() }
+    }
+
+    pub(crate) fn from_serialized(data: SerializedCompositing) -> Result<Self, DocumentError> {
+        register_document_nodes();
+        let mut graph = NodeGraph::new();
+        for node in &data.nodes {
+            let parameters = Value::Object(node.parameters.clone());
+            graph.add_node(aurion_core::create_node_with_id(&node.type_name, &parameters, node.id.clone())?);
+        }
+        for node in &data.nodes {
+            for (input, source) in &node.inputs {
+                match node.outputs.get(input) {
+                    Some(output) => graph.connect_output(source, output, &node.id, input)?,
+                    None => graph.connect(source, &node.id, input)?,
+                }
+            }
+        }
+        let mut compositing = Self { graph, output: None };
+        compositing.set_output(data.output)?;
+        Ok(compositing)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SerializedCompositing {
+    nodes: Vec<SerializedNode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<NodeId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SerializedNode {
+    id: NodeId,
+    type_name: String,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    parameters: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    inputs: BTreeMap<String, NodeId>,
+    // The source output each input reads, where it isn't the primary one
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    outputs: BTreeMap<String, String>,
+}
+
+impl Document {
+    /// Switches to compositing through a [`CompositingGraph`], creating one
+    /// with a source node per layer if there is none. Rendering keeps using
+    /// the layer stack until an output node is set.
+    pub fn enable_graph_compositing(&mut self) -> &mut CompositingGraph {
+        if self.compositing.is_none() {
+            self.mark_modified();
+        }
+        let layers = &self.layer_order;
+        self.compositing.get_or_insert_with(|| CompositingGraph::with_sources(layers))
+    }
+
+    /// Goes back to the layer stack, returning the compositing graph.
+    pub fn disable_graph_compositing(&mut self) -> Option<CompositingGraph> {
+        let compositing = self.compositing.take()?;
+        self.mark_modified();
+        self.invalidate_region(self.canvas_rect());
+        Some(compositing)
+    }
+
+    pub fn compositing_graph(&self) -> Option<&CompositingGraph> {
+        self.compositing.as_ref()
+    }
+
+    /// Mutable access to the compositing graph. The document is marked
+    /// modified and the whole canvas invalidated.
+    pub fn compositing_graph_mut(&mut self) -> Option<&mut CompositingGraph> {
+        self.compositing.as_ref()?;
+        self.mark_modified();
+        self.invalidate_region(self.canvas_rect());
+        self.compositing.as_mut()
+    }
+
+    /// Composites through the compositing graph when it has an output; see
+    /// [`CompositingGraph::composite`].
+    pub(crate) fn composite_graph(
+        &self,
+        compositing: &CompositingGraph,
+        output: &NodeId,
+        region: CanvasRect,
+        context: EvalContext,
+        draw: &mut DrawLayer,
+    ) -> Result<(), DocumentError> {
+        let mut layers = HashMap::new();
+        for id in compositing.layers() {
+            let Some(layer) = self.get_layer(&id) else {
+                continue;
+            };
+            let layer = layer.read();
+            let layer_output = if layer.is_visible() {
+                self.layer_output(&id, &layer, &context)?
+            } else {
+                None
+            };
+            layers.insert(id, (layer_output, layer.transform()));
+        }
+        self.render_cache.retain(|id| self.layers.contains_key(id));
+        compositing.composite(output, layers, self.size(), context, region, draw)
+    }
+}
+
+/// A layer's cached output and the transform placing it.
+pub(crate) type LayerOutput = (Option<Arc<DynamicImage>>, LayerTransform);
+
+impl CompositingGraph {
+    /// Evaluates `output` with the source nodes reading `layers`, and hands
+    /// the part of the result inside `region` to `draw`. Layers without an
+    /// output (e.g. hidden ones) give a transparent canvas.
+    pub(crate) fn composite(
+        &self,
+        output: &NodeId,
+        layers: HashMap<LayerId, LayerOutput>,
+        (width, height): (u32, u32),
+        context: EvalContext,
+        region: CanvasRect,
+        draw: &mut DrawLayer,
+    ) -> Result<(), DocumentError> {
+        let canvas = CanvasRect::new(0, 0, width, height);
+        let images = layers
+            .into_iter()
+            .map(|(id, (layer_output, transform))| {
+                let mut placed = RgbaImage::new(width, height);
+                if let Some((image, x, y)) = layer_output.and_then(|image| transform.place(&image, canvas)) {
+                    image::imageops::replace(&mut placed, &image.to_rgba8(), x, y);
+                }
+                (id, DynamicImage::ImageRgba8(placed))
+            })
+            .collect();
+
+        let context = context.with(LayerImages { width, height, images: Arc::new(images) });
+        let result = self.graph.evaluate_with_context(output, &context)?;
+        let image = result.as_image().ok_or_else(|| {
+            DocumentError::InvalidOperation("The compositing output node does not produce an image".to_string())
+        })?;
+        if let Some((image, x, y)) = LayerTransform::IDENTITY.place(image, region) {
+            draw(&image, x, y, BlendMode::Normal, 1.0);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aurion_std_nodes::filters::BlurNode;
+    use aurion_std_nodes::{BlendMode as NodeBlendMode, BlendNode};
+    use image::Rgba;
+    use parking_lot::RwLock;
+    use crate::Layer;
+
+    fn document() -> (Document, LayerId) {
+        let mut doc = Document::with_size(32, 24);
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 12, |x, y| {
+            Rgba([(x * 16) as u8, (y * 20) as u8, 120, 255])
+        }));
+        let a = LayerId::new();
+        let mut layer = Layer::with_image("A", image);
+        layer.set_transform(LayerTransform::translation(8.0, 6.0));
+        doc.insert_layer_raw(0, a.clone(), Arc::new(RwLock::new(layer)));
+        doc.add_layer();
+        (doc, a)
+    }
+
+    /// Blends layer A with itself under two different blurs.
+    fn blur_twice(doc: &mut Document, a: &LayerId) {
+        let compositing = doc.enable_graph_compositing();
+        let source = compositing.source_node(a).unwrap();
+        let graph = compositing.graph_mut();
+        let soft = graph.add_node(Node::new(Box::new(BlurNode::new(1.0))));
+        let softer = graph.add_node(Node::new(Box::new(BlurNode::new(4.0))));
+        let blend = graph.add_node(Node::new(Box::new(BlendNode::new(NodeBlendMode::Add))));
+        graph.connect(&source, &soft, "input").unwrap();
+        graph.connect(&source, &softer, "input").unwrap();
+        graph.connect(&soft, &blend, "a").unwrap();
+        graph.connect(&softer, &blend, "b").unwrap();
+        compositing.set_output(Some(blend)).unwrap();
+    }
+
+    #[test]
+    fn test_graph_compositing_replaces_stack() {
+        let (mut doc, a) = document();
+        let stack = doc.render_composite().unwrap().to_rgba8();
+
+        doc.enable_graph_compositing();
+        assert_eq!(doc.compositing_graph().unwrap().layers().len(), 2);
+        assert_eq!(doc.render_composite().unwrap().to_rgba8(), stack, "no output set");
+
+        blur_twice(&mut doc, &a);
+        let graph = doc.render_composite().unwrap().to_rgba8();
+        assert_ne!(graph, stack);
+        assert_eq!(graph.dimensions(), stack.dimensions());
+        assert_eq!(doc.render_region(CanvasRect::new(4, 4, 10, 8)).unwrap().to_rgba8(),
+            image::imageops::crop_imm(&graph, 4, 4, 10, 8).to_image());
+
+        doc.disable_graph_compositing();
+        assert_eq!(doc.render_composite().unwrap().to_rgba8(), stack);
+    }
+
+    #[test]
+    fn test_compositing_graph_round_trips() {
+        let (mut doc, a) = document();
+        blur_twice(&mut doc, &a);
+        let expected = doc.render_composite().unwrap().to_rgba8();
+
+        let loaded = Document::deserialize(doc.serialize().unwrap()).unwrap();
+        let compositing = loaded.compositing_graph().unwrap();
+        assert_eq!(compositing.output(), doc.compositing_graph().unwrap().output());
+        assert_eq!(compositing.graph().get_node_ids().len(), 5);
+        assert_eq!(loaded.render_composite().unwrap().to_rgba8(), expected);
+    }
+}
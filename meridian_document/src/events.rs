@@ -0,0 +1,106 @@
+use std::sync::Arc;
+use crate::{CanvasRect, LayerId};
+
+/// Notifications emitted by a [`crate::Document`] when its state changes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DocumentEvent {
+    /// The document's unsaved-changes state flipped.
+    ModifiedChanged(bool),
+    /// A layer's node graph was edited.
+    GraphChanged(LayerId),
+    /// A layer was added to the stack, e.g. by undoing its removal.
+    LayerAdded(LayerId),
+    /// A layer was taken out of the stack.
+    LayerRemoved(LayerId),
+    /// A layer moved from one stack position to another.
+    LayerReordered { id: LayerId, from: usize, to: usize },
+    /// A layer property such as its name changed.
+    LayerPropertyChanged(LayerId),
+    /// The selection was set or cleared.
+    SelectionChanged,
+    /// A layer was soloed, or solo mode ended (`None`).
+    SoloChanged(Option<LayerId>),
+    /// The canvas size changed, e.g. by a resize, crop, or scale.
+    CanvasResized { width: u32, height: u32 },
+    /// Part of the rendered canvas is out of date and should be repainted.
+    RegionInvalidated(CanvasRect),
+    /// The editor layout stored for a layer changed.
+    UiLayoutChanged(LayerId),
+}
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub struct ListenerId(u64);
+
+type Listener<E> = Arc<dyn Fn(&E) + Send + Sync>;
+
+/// Listeners for events of type `E`, called in subscription order.
+pub(crate) struct EventBus<E = DocumentEvent> {
+    listeners: Vec<(ListenerId, Listener<E>)>,
+    next_id: u64,
+}
+
+impl<E> Default for EventBus<E> {
+    fn default() -> Self {
+        Self {
+            listeners: Vec::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl<E> EventBus<E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe<F>(&mut self, listener: F) -> ListenerId
+    where
+        F: Fn(&E) + Send + Sync + 'static,
+    {
+        let id = ListenerId(self.next_id);
+        self.next_id += 1;
+        self.listeners.push((id, Arc::new(listener)));
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: ListenerId) -> bool {
+        let before = self.listeners.len();
+        self.listeners.retain(|(listener_id, _)| *listener_id != id);
+        self.listeners.len() != before
+    }
+
+    pub fn emit(&self, event: E) {
+        for (_, listener) in &self.listeners {
+            listener(&event);
+        }
+    }
+}
+
+impl<E> std::fmt::Debug for EventBus<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventBus")
+            .field("listeners", &self.listeners.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    #[test]
+    fn test_subscribe_and_emit() {
+        let mut bus: EventBus = EventBus::new();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        let id = bus.subscribe(move |event| sink.lock().push(event.clone()));
+
+        bus.emit(DocumentEvent::ModifiedChanged(true));
+        assert_eq!(*received.lock(), vec![DocumentEvent::ModifiedChanged(true)]);
+
+        assert!(bus.unsubscribe(id));
+        bus.emit(DocumentEvent::ModifiedChanged(false));
+        assert_eq!(received.lock().len(), 1);
+    }
+}
@@ -0,0 +1,263 @@
+//! Writing the composited document to raster image files.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageEncoder, ImageError, RgbaImage};
+use crate::color::embed_icc_in_png;
+use crate::{Document, DocumentError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Png,
+    Jpeg,
+    WebP,
+}
+
+impl ExportFormat {
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ExportFormat::Png),
+            "jpg" | "jpeg" => Some(ExportFormat::Jpeg),
+            "webp" => Some(ExportFormat::WebP),
+            _ => None,
+        }
+    }
+
+    /// The usual file extension, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Png => "png",
+            ExportFormat::Jpeg => "jpg",
+            ExportFormat::WebP => "webp",
+        }
+    }
+
+    pub fn supports_alpha(&self) -> bool {
+        !matches!(self, ExportFormat::Jpeg)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportBackground {
+    /// Keep transparency. Formats without alpha are matted onto white.
+    Transparent,
+    /// Flatten onto an opaque RGB color.
+    Matte([u8; 3]),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportOptions {
+    /// Explicit format; inferred from the file extension when `None`.
+    pub format: Option<ExportFormat>,
+    /// JPEG quality from 1 to 100.
+    pub jpeg_quality: u8,
+    /// Lossless WebP. Lossy WebP encoding is not available.
+    pub webp_lossless: bool,
+    /// Uniform scale applied to the composite before encoding.
+    pub scale: f32,
+    pub background: ExportBackground,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            jpeg_quality: 90,
+            webp_lossless: true,
+            scale: 1.0,
+            background: ExportBackground::Transparent,
+        }
+    }
+}
+
+impl Document {
+    pub fn export<P: AsRef<Path>>(&self, path: P, options: ExportOptions) -> Result<(), DocumentError> {
+        let path = path.as_ref();
+        // Fail before rendering when the options can't be written
+        export_format(path, &options)?;
+        if !(options.scale > 0.0) {
+            return Err(DocumentError::Other(format!("Invalid export scale: {}", options.scale)));
+        }
+
+        let mut image = self.render_composite()?;
+        if options.scale != 1.0 {
+            let width = ((image.width() as f32 * options.scale).round() as u32).max(1);
+            let height = ((image.height() as f32 * options.scale).round() as u32).max(1);
+            image = image.resize_exact(width, height, FilterType::Lanczos3);
+        }
+        self.export_image(&image, path, &options)
+    }
+
+    /// Writes an already rendered composite the way [`Document::export`]
+    /// does, e.g. one resized to exact dimensions. `options.scale` is
+    /// ignored.
+    pub fn export_image<P: AsRef<Path>>(&self, image: &DynamicImage, path: P, options: &ExportOptions) -> Result<(), DocumentError> {
+        let path = path.as_ref();
+        let format = export_format(path, options)?;
+        let background = match options.background {
+            ExportBackground::Transparent if !format.supports_alpha() => Some([255, 255, 255]),
+            ExportBackground::Transparent => None,
+            ExportBackground::Matte(color) => Some(color),
+        };
+        let rgba = match background {
+            Some(color) => matte(image, color),
+            None => image.to_rgba8(),
+        };
+
+        let export_error = |source: ImageError| DocumentError::Export {
+            path: path.to_path_buf(),
+            source,
+        };
+        let file = File::create(path).map_err(|e| export_error(ImageError::IoError(e)))?;
+        let writer = BufWriter::new(file);
+        let (width, height) = rgba.dimensions();
+
+        match format {
+            ExportFormat::Png => self.write_png(writer, &rgba),
+            ExportFormat::Jpeg => {
+                let rgb = DynamicImage::ImageRgba8(rgba).to_rgb8();
+                JpegEncoder::new_with_quality(writer, options.jpeg_quality.clamp(1, 100))
+                    .write_image(rgb.as_raw(), width, height, image::ColorType::Rgb8)
+            }
+            ExportFormat::WebP => WebPEncoder::new_lossless(writer)
+                .write_image(rgba.as_raw(), width, height, image::ColorType::Rgba8),
+        }
+        .map_err(export_error)
+    }
+}
+
+/// The format `options` ask for at `path`, if it can be written.
+fn export_format(path: &Path, options: &ExportOptions) -> Result<ExportFormat, DocumentError> {
+    let format = options.format
+        .or_else(|| ExportFormat::from_extension(path))
+        .ok_or_else(|| DocumentError::Other(format!(
+            "Cannot infer export format from {}", path.display()
+        )))?;
+    if format == ExportFormat::WebP && !options.webp_lossless {
+        return Err(DocumentError::Other("Lossy WebP export is not supported".to_string()));
+    }
+    Ok(format)
+}
+
+impl Document {
+    /// Encodes a PNG, embedding the color profile's ICC data when it has one.
+    pub(crate) fn write_png<W: Write>(&self, mut writer: W, rgba: &RgbaImage) -> Result<(), ImageError> {
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png)
+            .write_image(rgba.as_raw(), rgba.width(), rgba.height(), image::ColorType::Rgba8)?;
+        if let Some(icc) = self.color_profile.icc_profile() {
+            png = embed_icc_in_png(&png, self.color_profile.name(), &icc).map_err(|e| {
+                ImageError::IoError(std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+            })?;
+        }
+        writer.write_all(&png).map_err(ImageError::IoError)
+    }
+}
+
+/// Flattens `image` onto an opaque background color.
+fn matte(image: &DynamicImage, color: [u8; 3]) -> RgbaImage {
+    let mut output = image.to_rgba8();
+    for pixel in output.pixels_mut() {
+        let alpha = pixel[3] as f32 / 255.0;
+        for i in 0..3 {
+            let value = pixel[i] as f32 * alpha + color[i] as f32 * (1.0 - alpha);
+            pixel[i] = value.round().clamp(0.0, 255.0) as u8;
+        }
+        pixel[3] = 255;
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aurion_core::Node;
+    use aurion_std_nodes::ImageNode;
+    use image::Rgba;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("meridian_export_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn test_document() -> Document {
+        let mut doc = Document::with_size(6, 4);
+        let mut pixels = RgbaImage::from_pixel(6, 4, Rgba([0, 0, 0, 0]));
+        pixels.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        pixels.put_pixel(5, 3, Rgba([0, 0, 255, 128]));
+        let id = doc.add_layer();
+        doc.get_layer(&id).unwrap().write().node_graph_mut()
+            .add_node(Node::new(Box::new(ImageNode::with_image(DynamicImage::ImageRgba8(pixels)))));
+        doc
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(ExportFormat::from_extension(Path::new("a.PNG")), Some(ExportFormat::Png));
+        assert_eq!(ExportFormat::from_extension(Path::new("a.jpg")), Some(ExportFormat::Jpeg));
+        assert_eq!(ExportFormat::from_extension(Path::new("a.webp")), Some(ExportFormat::WebP));
+        assert_eq!(ExportFormat::from_extension(Path::new("a.tga")), None);
+    }
+
+    #[test]
+    fn test_export_png_exact_pixels() {
+        let dir = temp_dir();
+        let doc = test_document();
+        let path = dir.join("out.png");
+        doc.export(&path, ExportOptions::default()).unwrap();
+
+        let decoded = image::open(&path).unwrap().to_rgba8();
+        assert_eq!(decoded, doc.render_composite().unwrap().to_rgba8());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_export_jpeg_and_webp_dimensions() {
+        let dir = temp_dir();
+        let doc = test_document();
+        for name in ["out.jpg", "out.webp"] {
+            let path = dir.join(name);
+            doc.export(&path, ExportOptions::default()).unwrap();
+            let decoded = image::open(&path).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (6, 4), "{}", name);
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_export_scale_and_matte() {
+        let dir = temp_dir();
+        let doc = test_document();
+        let path = dir.join("scaled.png");
+        let options = ExportOptions {
+            scale: 2.0,
+            background: ExportBackground::Matte([0, 255, 0]),
+            ..ExportOptions::default()
+        };
+        doc.export(&path, options).unwrap();
+
+        let decoded = image::open(&path).unwrap().to_rgba8();
+        assert_eq!(decoded.dimensions(), (12, 8));
+        assert!(decoded.pixels().all(|p| p[3] == 255));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_export_error_carries_path() {
+        let doc = test_document();
+        let path = std::env::temp_dir().join(format!("missing_{}", Uuid::new_v4())).join("out.png");
+        match doc.export(&path, ExportOptions::default()) {
+            Err(DocumentError::Export { path: error_path, .. }) => assert_eq!(error_path, path),
+            other => panic!("expected export error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,775 @@
+//! Undoable edits to a layer's node graph.
+//!
+//! Node ids survive undo and redo: removed nodes are put back as they were,
+//! and re-added nodes are recreated under the id they were first given.
+
+use std::error::Error;
+use std::sync::Arc;
+use aurion_core::{Node, NodeError, NodeGraph, NodeId};
+use parking_lot::{Mutex, RwLock};
+use serde_json::Value;
+use aurion_std_nodes::factories::register_standard_nodes;
+use crate::{Command, Document, DocumentEvent, LayerId};
+
+/// Applies `edit` to a layer's graph and reports the change.
+fn edit_graph<T>(
+    document: &Document,
+    layer: &LayerId,
+    edit: impl FnOnce(&mut NodeGraph) -> Result<T, NodeError>,
+) -> Result<T, Box<dyn Error>> {
+    let result = document.with_layer_mut(layer, |handle| edit(&mut handle.node_graph_mut()))??;
+    for id in document.linked_layers(layer) {
+        if let Some(linked) = document.get_layer(&id) {
+            linked.read().invalidate_thumbnail();
+        }
+        document.events.emit(DocumentEvent::GraphChanged(id));
+    }
+    document.invalidate_region(document.canvas_rect());
+    Ok(result)
+}
+
+/// Connects `from`'s `output`, or its primary output with `None`, as a
+/// removed connection is put back.
+fn reconnect(graph: &mut NodeGraph, from: &NodeId, output: Option<&str>, to: &NodeId, input: &str) -> Result<(), NodeError> {
+    match output {
+        Some(output) => graph.connect_output(from, output, to, input),
+        None => graph.connect(from, to, input),
+    }
+}
+
+/// The inputs of `node` as (input, source, output), see [`reconnect`].
+fn wired_inputs(node: &Node) -> Vec<(String, NodeId, Option<String>)> {
+    node.inputs()
+        .iter()
+        .map(|(input, source)| (input.clone(), source.clone(), node.source_output(input).map(str::to_string)))
+        .collect()
+}
+
+/// Adds a node created through the node registry. Standard node types are
+/// always available.
+#[derive(Debug)]
+pub struct AddNodeCommand {
+    layer: LayerId,
+    type_name: String,
+    params: Value,
+    node_id: NodeId,
+}
+
+impl AddNodeCommand {
+    pub fn new(layer: LayerId, type_name: impl Into<String>, params: Value) -> Self {
+        Self {
+            layer,
+            type_name: type_name.into(),
+            params,
+            node_id: NodeId::new(),
+        }
+    }
+
+    /// Id the node is created under.
+    pub fn node_id(&self) -> &NodeId {
+        &self.node_id
+    }
+}
+
+impl Command for AddNodeCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        register_standard_nodes();
+        let node = aurion_core::create_node_with_id(&self.type_name, &self.params, self.node_id.clone())?;
+        edit_graph(document, &self.layer, |graph| {
+            graph.add_node(node);
+            Ok(())
+        })
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        edit_graph(document, &self.layer, |graph| graph.remove_node(&self.node_id).map(drop))
+    }
+}
+
+#[derive(Debug)]
+struct RemovedNode {
+    node: Arc<RwLock<Node>>,
+    // Inputs of other nodes the removed node fed, with the output they read
+    consumers: Vec<(NodeId, String, Option<String>)>,
+    // Its own inputs, which reconnecting would lose the outputs of
+    inputs: Vec<(String, NodeId, Option<String>)>,
+    // Whether it was the graph's designated output
+    output: bool,
+}
+
+/// Removes a node and its connections, restoring both on undo.
+#[derive(Debug)]
+pub struct RemoveNodeCommand {
+    layer: LayerId,
+    node_id: NodeId,
+    removed: Mutex<Option<RemovedNode>>,
+}
+
+impl RemoveNodeCommand {
+    pub fn new(layer: LayerId, node_id: NodeId) -> Self {
+        Self {
+            layer,
+            node_id,
+            removed: Mutex::new(None),
+        }
+    }
+}
+
+impl Command for RemoveNodeCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let removed = edit_graph(document, &self.layer, |graph| {
+            let consumer_ids = graph.direct_dependents(&self.node_id)?;
+
+            let mut consumers = Vec::new();
+            for consumer in consumer_ids {
+                let Some(node) = graph.get_node(&consumer) else {
+                    continue;
+                };
+                let node = node.read();
+                for (input, source) in node.inputs() {
+                    if *source == self.node_id {
+                        consumers.push((consumer.clone(), input.clone(), node.source_output(input).map(str::to_string)));
+                    }
+                }
+            }
+            let output = graph.designated_output() == Some(&self.node_id);
+            let node = graph.remove_node(&self.node_id)?;
+            let inputs = wired_inputs(&node.read());
+            Ok(RemovedNode { node, consumers, inputs, output })
+        })?;
+        *self.removed.lock() = Some(removed);
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let Some(RemovedNode { node, consumers, inputs, output }) = self.removed.lock().take() else {
+            return Ok(());
+        };
+        edit_graph(document, &self.layer, |graph| {
+            let id = graph.insert_node(node);
+            for (input, source, source_output) in inputs {
+                reconnect(graph, &source, source_output.as_deref(), &id, &input)?;
+            }
+            for (consumer, input, source_output) in consumers {
+                reconnect(graph, &id, source_output.as_deref(), &consumer, &input)?;
+            }
+            if output {
+                graph.set_designated_output(Some(id))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn memory_hint(&self) -> usize {
+        let removed = self.removed.lock();
+        let node = removed.as_ref().map_or(0, |removed| removed.node.read().data().estimated_memory());
+        std::mem::size_of_val(self) + node
+    }
+}
+
+/// Removes several nodes and their connections as one undo step.
+#[derive(Debug)]
+pub struct RemoveNodesCommand {
+    removals: Vec<RemoveNodeCommand>,
+}
+
+impl RemoveNodesCommand {
+    pub fn new(layer: LayerId, node_ids: impl IntoIterator<Item = NodeId>) -> Self {
+        Self {
+            removals: node_ids.into_iter().map(|id| RemoveNodeCommand::new(layer.clone(), id)).collect(),
+        }
+    }
+}
+
+impl Command for RemoveNodesCommand {
+    /// Removes the nodes in order. If one fails, those already removed are
+    /// put back.
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        for (index, removal) in self.removals.iter().enumerate() {
+            if let Err(e) = removal.execute(document) {
+                for removed in self.removals[..index].iter().rev() {
+                    removed.undo(document)?;
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Restores the nodes in reverse, so connections between them come back
+    /// with the node they fed.
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        for removal in self.removals.iter().rev() {
+            removal.undo(document)?;
+        }
+        Ok(())
+    }
+
+    fn memory_hint(&self) -> usize {
+        std::mem::size_of_val(self) + self.removals.iter().map(Command::memory_hint).sum::<usize>()
+    }
+}
+
+/// Connects `from` into `to`'s `input`, replacing any existing connection
+/// to that input.
+#[derive(Debug)]
+pub struct ConnectCommand {
+    layer: LayerId,
+    from: NodeId,
+    // The output of `from` to connect, when it isn't the primary one
+    output: Option<String>,
+    to: NodeId,
+    input: String,
+    // The replaced source and the output it was read from
+    replaced: Mutex<Option<(NodeId, Option<String>)>>,
+}
+
+impl ConnectCommand {
+    pub fn new(layer: LayerId, from: NodeId, to: NodeId, input: impl Into<String>) -> Self {
+        Self {
+            layer,
+            from,
+            output: None,
+            to,
+            input: input.into(),
+            replaced: Mutex::new(None),
+        }
+    }
+
+    /// Connects `from`'s output named `output` instead of its primary one.
+    pub fn with_output(mut self, output: impl Into<String>) -> Self {
+        self.output = Some(output.into());
+        self
+    }
+}
+
+impl Command for ConnectCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let replaced = edit_graph(document, &self.layer, |graph| {
+            let target = graph.get_node(&self.to).ok_or(NodeError::NodeNotFound(self.to.0))?;
+            let previous = {
+                let target = target.read();
+                let previous = target.get_input(&self.input).cloned();
+                previous.map(|source| (source, target.source_output(&self.input).map(str::to_string)))
+            };
+            if let Some((previous, _)) = &previous {
+                graph.disconnect(previous, &self.to, &self.input)?;
+            }
+            if let Err(e) = reconnect(graph, &self.from, self.output.as_deref(), &self.to, &self.input) {
+                if let Some((previous, output)) = &previous {
+                    reconnect(graph, previous, output.as_deref(), &self.to, &self.input)?;
+                }
+                return Err(e);
+            }
+            Ok(previous)
+        })?;
+        *self.replaced.lock() = replaced;
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let replaced = self.replaced.lock().take();
+        edit_graph(document, &self.layer, |graph| {
+            graph.disconnect(&self.from, &self.to, &self.input)?;
+            if let Some((previous, output)) = replaced {
+                reconnect(graph, &previous, output.as_deref(), &self.to, &self.input)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Removes the connection from `from` into `to`'s `input`.
+#[derive(Debug)]
+pub struct DisconnectCommand {
+    layer: LayerId,
+    from: NodeId,
+    to: NodeId,
+    input: String,
+    // The output of `from` the input read, when it wasn't the primary one
+    output: Mutex<Option<String>>,
+}
+
+impl DisconnectCommand {
+    pub fn new(layer: LayerId, from: NodeId, to: NodeId, input: impl Into<String>) -> Self {
+        Self {
+            layer,
+            from,
+            to,
+            input: input.into(),
+            output: Mutex::new(None),
+        }
+    }
+}
+
+impl Command for DisconnectCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let output = edit_graph(document, &self.layer, |graph| {
+            let target = graph.get_node(&self.to).ok_or(NodeError::NodeNotFound(self.to.0))?;
+            let output = target.read().source_output(&self.input).map(str::to_string);
+            graph.disconnect(&self.from, &self.to, &self.input)?;
+            Ok(output)
+        })?;
+        *self.output.lock() = output;
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let output = self.output.lock().take();
+        edit_graph(document, &self.layer, |graph| reconnect(graph, &self.from, output.as_deref(), &self.to, &self.input))
+    }
+}
+
+/// Changes a node parameter from `before` to `after`.
+#[derive(Debug)]
+pub struct SetNodeParameterCommand {
+    layer: LayerId,
+    node: NodeId,
+    name: String,
+    before: Value,
+    after: Value,
+}
+
+impl SetNodeParameterCommand {
+    pub fn new(layer: LayerId, node: NodeId, name: impl Into<String>, before: Value, after: Value) -> Self {
+        Self {
+            layer,
+            node,
+            name: name.into(),
+            before,
+            after,
+        }
+    }
+
+    fn apply(&self, document: &Document, value: &Value) -> Result<(), Box<dyn Error>> {
+        edit_graph(document, &self.layer, |graph| graph.set_node_parameter(&self.node, &self.name, value.clone()))
+    }
+}
+
+impl Command for SetNodeParameterCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        self.apply(document, &self.after)
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        self.apply(document, &self.before)
+    }
+}
+
+// A connection as (from, to, input, output), see `reconnect`
+type Connection = (NodeId, NodeId, String, Option<String>);
+
+/// Removes every connection into and out of a node.
+#[derive(Debug)]
+pub struct DisconnectAllCommand {
+    layer: LayerId,
+    node: NodeId,
+    removed: Mutex<Vec<Connection>>,
+}
+
+impl DisconnectAllCommand {
+    pub fn new(layer: LayerId, node: NodeId) -> Self {
+        Self {
+            layer,
+            node,
+            removed: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Command for DisconnectAllCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let removed = edit_graph(document, &self.layer, |graph| {
+            let node = graph.get_node(&self.node).ok_or(NodeError::NodeNotFound(self.node.0))?;
+            let mut wires: Vec<_> = wired_inputs(&node.read())
+                .into_iter()
+                .map(|(input, source, output)| (source, self.node.clone(), input, output))
+                .collect();
+            let consumers = graph.direct_dependents(&self.node)?;
+            for consumer in consumers {
+                let Some(consumer) = graph.get_node(&consumer) else {
+                    continue;
+                };
+                let consumer = consumer.read();
+                for (input, source) in consumer.inputs() {
+                    if *source == self.node {
+                        let output = consumer.source_output(input).map(str::to_string);
+                        wires.push((self.node.clone(), consumer.id().clone(), input.clone(), output));
+                    }
+                }
+            }
+            for (from, to, input, _) in &wires {
+                graph.disconnect(from, to, input)?;
+            }
+            Ok(wires)
+        })?;
+        *self.removed.lock() = removed;
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let removed = std::mem::take(&mut *self.removed.lock());
+        edit_graph(document, &self.layer, |graph| {
+            for (from, to, input, output) in &removed {
+                reconnect(graph, from, output.as_deref(), to, input)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Adds a copy of a node under a new id, fed by the same inputs.
+#[derive(Debug)]
+pub struct DuplicateNodeCommand {
+    layer: LayerId,
+    source: NodeId,
+    node_id: NodeId,
+}
+
+impl DuplicateNodeCommand {
+    pub fn new(layer: LayerId, source: NodeId) -> Self {
+        Self {
+            layer,
+            source,
+            node_id: NodeId::new(),
+        }
+    }
+
+    /// Id the copy is created under.
+    pub fn node_id(&self) -> &NodeId {
+        &self.node_id
+    }
+}
+
+impl Command for DuplicateNodeCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        edit_graph(document, &self.layer, |graph| {
+            let source = graph.get_node(&self.source).ok_or(NodeError::NodeNotFound(self.source.0))?;
+            let copy = source.read().clone();
+            let inputs = wired_inputs(&copy);
+            let id = graph.add_node(copy.with_id(self.node_id.clone()));
+            for (input, from, output) in inputs {
+                reconnect(graph, &from, output.as_deref(), &id, &input)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        edit_graph(document, &self.layer, |graph| graph.remove_node(&self.node_id).map(drop))
+    }
+}
+
+/// Names a node, or with `None` clears its name.
+#[derive(Debug)]
+pub struct SetNodeLabelCommand {
+    layer: LayerId,
+    node: NodeId,
+    label: Option<String>,
+    previous: Mutex<Option<String>>,
+}
+
+impl SetNodeLabelCommand {
+    pub fn new(layer: LayerId, node: NodeId, label: Option<String>) -> Self {
+        Self {
+            layer,
+            node,
+            label,
+            previous: Mutex::new(None),
+        }
+    }
+
+    fn apply(&self, document: &Document, label: Option<String>) -> Result<Option<String>, Box<dyn Error>> {
+        edit_graph(document, &self.layer, |graph| {
+            let node = graph.get_node(&self.node).ok_or(NodeError::NodeNotFound(self.node.0))?;
+            let mut node = node.write();
+            let previous = node.label().map(str::to_string);
+            node.set_label(label);
+            Ok(previous)
+        })
+    }
+}
+
+impl Command for SetNodeLabelCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        *self.previous.lock() = self.apply(document, self.label.clone())?;
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let previous = self.previous.lock().take();
+        self.apply(document, previous).map(drop)
+    }
+}
+
+/// Turns a node's bypass on or off, see [`aurion_core::Node::is_bypassed`].
+#[derive(Debug)]
+pub struct SetNodeBypassCommand {
+    layer: LayerId,
+    node: NodeId,
+    bypassed: bool,
+}
+
+impl SetNodeBypassCommand {
+    pub fn new(layer: LayerId, node: NodeId, bypassed: bool) -> Self {
+        Self { layer, node, bypassed }
+    }
+
+    fn apply(&self, document: &Document, bypassed: bool) -> Result<(), Box<dyn Error>> {
+        edit_graph(document, &self.layer, |graph| {
+            let node = graph.get_node(&self.node).ok_or(NodeError::NodeNotFound(self.node.0))?;
+            node.write().set_bypassed(bypassed);
+            Ok(())
+        })
+    }
+}
+
+impl Command for SetNodeBypassCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        self.apply(document, self.bypassed)
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        self.apply(document, !self.bypassed)
+    }
+}
+
+/// Marks the node whose result is the layer's image, or with `None` goes
+/// back to the graph's sinks.
+#[derive(Debug)]
+pub struct SetOutputNodeCommand {
+    layer: LayerId,
+    node: Option<NodeId>,
+    previous: Mutex<Option<NodeId>>,
+}
+
+impl SetOutputNodeCommand {
+    pub fn new(layer: LayerId, node: Option<NodeId>) -> Self {
+        Self {
+            layer,
+            node,
+            previous: Mutex::new(None),
+        }
+    }
+
+    fn apply(&self, document: &Document, node: Option<NodeId>) -> Result<Option<NodeId>, Box<dyn Error>> {
+        edit_graph(document, &self.layer, |graph| {
+            let previous = graph.designated_output().cloned();
+            graph.set_designated_output(node)?;
+            Ok(previous)
+        })
+    }
+}
+
+impl Command for SetOutputNodeCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        *self.previous.lock() = self.apply(document, self.node.clone())?;
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let previous = self.previous.lock().take();
+        self.apply(document, previous).map(drop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use serde_json::json;
+    use crate::Layer;
+
+    /// Every node with its type, parameter hash and inputs, in id order.
+    fn describe(doc: &Document, layer: &LayerId) -> Vec<(NodeId, &'static str, Option<u64>, BTreeMap<String, NodeId>)> {
+        let layer = doc.get_layer(layer).unwrap();
+        let layer = layer.read();
+        let graph = layer.node_graph();
+        let mut ids = graph.get_node_ids();
+        ids.sort_by_key(|id| id.0);
+        ids.into_iter()
+            .map(|id| {
+                let node = graph.get_node(&id).unwrap();
+                let node = node.read();
+                (id.clone(), node.data().type_name(), node.data().content_hash(), node.inputs().clone())
+            })
+            .collect()
+    }
+
+    fn image_layer(doc: &mut Document) -> (LayerId, NodeId) {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 8, |x, y| Rgba([(x * 30) as u8, (y * 30) as u8, 60, 255])));
+        let id = LayerId::new();
+        doc.insert_layer_raw(0, id.clone(), Arc::new(RwLock::new(Layer::with_image("Layer", image))));
+        let node = doc.get_layer(&id).unwrap().read().node_graph().get_node_ids()[0].clone();
+        (id, node)
+    }
+
+    #[test]
+    fn test_graph_edits_undo_to_initial_state() {
+        let mut doc = Document::with_size(8, 8);
+        let (layer, image) = image_layer(&mut doc);
+        let initial = describe(&doc, &layer);
+        let initial_render = doc.render_composite().unwrap().to_rgba8();
+
+        let add_blur = AddNodeCommand::new(layer.clone(), "BlurNode", json!({ "sigma": 2.0 }));
+        let blur = add_blur.node_id().clone();
+        let add_invert = AddNodeCommand::new(layer.clone(), "InvertNode", json!({}));
+        let invert = add_invert.node_id().clone();
+        let commands: Vec<Box<dyn Command>> = vec![
+            Box::new(add_blur),
+            Box::new(ConnectCommand::new(layer.clone(), image.clone(), blur.clone(), "input")),
+            Box::new(SetNodeParameterCommand::new(layer.clone(), blur.clone(), "sigma", json!(2.0), json!(4.0))),
+            Box::new(add_invert),
+            Box::new(ConnectCommand::new(layer.clone(), blur.clone(), invert.clone(), "input")),
+            Box::new(RemoveNodeCommand::new(layer.clone(), blur.clone())),
+        ];
+        let count = commands.len();
+        for command in commands {
+            doc.execute_command(command).unwrap();
+        }
+
+        let edited = describe(&doc, &layer);
+        assert_eq!(edited.len(), 2);
+        assert!(edited.iter().all(|(_, _, _, inputs)| inputs.is_empty()));
+
+        // Undoing the removal restores the blur node, its parameter and both connections
+        doc.undo().unwrap();
+        {
+            let handle = doc.get_layer(&layer).unwrap();
+            let handle = handle.read();
+            let graph = handle.node_graph();
+            let node = graph.get_node(&blur).unwrap();
+            assert_eq!(node.read().data().get_parameter("sigma"), Some(json!(4.0)));
+            assert_eq!(node.read().get_input("input"), Some(&image));
+            assert_eq!(graph.get_node(&invert).unwrap().read().get_input("input"), Some(&blur));
+        }
+
+        for _ in 1..count {
+            doc.undo().unwrap();
+        }
+        assert!(!doc.can_undo());
+        assert_eq!(describe(&doc, &layer), initial);
+        assert_eq!(doc.render_composite().unwrap().to_rgba8(), initial_render);
+
+        // Redo recreates nodes under their original ids
+        for _ in 0..count {
+            doc.redo().unwrap();
+        }
+        assert_eq!(describe(&doc, &layer), edited);
+    }
+
+    #[test]
+    fn test_connect_replaces_and_restores_input() {
+        let mut doc = Document::with_size(4, 4);
+        let (layer, image) = image_layer(&mut doc);
+        let add_blend = AddNodeCommand::new(layer.clone(), "BlendNode", json!({ "mode": "Multiply" }));
+        let blend = add_blend.node_id().clone();
+        let add_other = AddNodeCommand::new(layer.clone(), "ImageNode", json!({}));
+        let other = add_other.node_id().clone();
+        doc.execute_command(Box::new(add_blend)).unwrap();
+        doc.execute_command(Box::new(add_other)).unwrap();
+        doc.execute_command(Box::new(ConnectCommand::new(layer.clone(), image.clone(), blend.clone(), "a"))).unwrap();
+        let connected = describe(&doc, &layer);
+
+        doc.execute_command(Box::new(ConnectCommand::new(layer.clone(), other.clone(), blend.clone(), "a"))).unwrap();
+        let input = |doc: &Document| {
+            let handle = doc.get_layer(&layer).unwrap();
+            let input = handle.read().node_graph().get_node(&blend).unwrap().read().get_input("a").cloned();
+            input
+        };
+        assert_eq!(input(&doc), Some(other.clone()));
+        doc.undo().unwrap();
+        assert_eq!(describe(&doc, &layer), connected);
+
+        // A connection that would form a cycle fails and leaves the graph alone
+        let cycle = ConnectCommand::new(layer.clone(), blend.clone(), blend.clone(), "b");
+        assert!(doc.execute_command(Box::new(cycle)).is_err());
+        assert_eq!(describe(&doc, &layer), connected);
+
+        doc.execute_command(Box::new(DisconnectCommand::new(layer.clone(), image.clone(), blend.clone(), "a"))).unwrap();
+        assert_eq!(input(&doc), None);
+        doc.undo().unwrap();
+        assert_eq!(input(&doc), Some(image));
+    }
+
+    #[test]
+    fn test_remove_nodes_is_one_undo_step() {
+        let mut doc = Document::with_size(4, 4);
+        let (layer, image) = image_layer(&mut doc);
+        let add_blur = AddNodeCommand::new(layer.clone(), "BlurNode", json!({}));
+        let blur = add_blur.node_id().clone();
+        let add_invert = AddNodeCommand::new(layer.clone(), "InvertNode", json!({}));
+        let invert = add_invert.node_id().clone();
+        doc.execute_command(Box::new(add_blur)).unwrap();
+        doc.execute_command(Box::new(add_invert)).unwrap();
+        doc.execute_command(Box::new(ConnectCommand::new(layer.clone(), image.clone(), blur.clone(), "input"))).unwrap();
+        doc.execute_command(Box::new(ConnectCommand::new(layer.clone(), blur.clone(), invert.clone(), "input"))).unwrap();
+        let connected = describe(&doc, &layer);
+
+        doc.execute_command(Box::new(RemoveNodesCommand::new(layer.clone(), [blur.clone(), invert.clone()]))).unwrap();
+        let remaining = describe(&doc, &layer);
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].0, image);
+        doc.undo().unwrap();
+        assert_eq!(describe(&doc, &layer), connected);
+
+        // A missing node fails the whole removal
+        let missing = RemoveNodesCommand::new(layer.clone(), [blur.clone(), NodeId::new()]);
+        assert!(doc.execute_command(Box::new(missing)).is_err());
+        assert_eq!(describe(&doc, &layer), connected);
+    }
+
+    #[test]
+    fn test_node_menu_commands_undo() {
+        let mut doc = Document::with_size(8, 8);
+        let (layer, image) = image_layer(&mut doc);
+        let add_blur = AddNodeCommand::new(layer.clone(), "BlurNode", json!({ "sigma": 2.0 }));
+        let blur = add_blur.node_id().clone();
+        let add_invert = AddNodeCommand::new(layer.clone(), "InvertNode", json!({}));
+        let invert = add_invert.node_id().clone();
+        doc.execute_command(Box::new(add_blur)).unwrap();
+        doc.execute_command(Box::new(add_invert)).unwrap();
+        doc.execute_command(Box::new(ConnectCommand::new(layer.clone(), image.clone(), blur.clone(), "input"))).unwrap();
+        doc.execute_command(Box::new(ConnectCommand::new(layer.clone(), blur.clone(), invert.clone(), "input"))).unwrap();
+        let connected = describe(&doc, &layer);
+        let inverted = doc.render_composite().unwrap().to_rgba8();
+        let node = |doc: &Document, id: &NodeId| doc.get_layer(&layer).unwrap().read().node_graph().get_node(id).unwrap();
+
+        doc.execute_command(Box::new(DisconnectAllCommand::new(layer.clone(), blur.clone()))).unwrap();
+        assert!(describe(&doc, &layer).iter().all(|(_, _, _, inputs)| inputs.is_empty()));
+        doc.undo().unwrap();
+        assert_eq!(describe(&doc, &layer), connected);
+
+        // The copy reads from the same input, with the same parameters
+        let duplicate = DuplicateNodeCommand::new(layer.clone(), blur.clone());
+        let copy = duplicate.node_id().clone();
+        doc.execute_command(Box::new(duplicate)).unwrap();
+        assert_eq!(node(&doc, &copy).read().get_input("input"), Some(&image));
+        assert_eq!(node(&doc, &copy).read().data().get_parameter("sigma"), Some(json!(2.0)));
+        doc.undo().unwrap();
+        assert_eq!(describe(&doc, &layer), connected);
+
+        doc.execute_command(Box::new(SetNodeLabelCommand::new(layer.clone(), blur.clone(), Some("Soften".to_string())))).unwrap();
+        assert_eq!(node(&doc, &blur).read().label(), Some("Soften"));
+        doc.undo().unwrap();
+        assert_eq!(node(&doc, &blur).read().label(), None);
+
+        doc.execute_command(Box::new(SetNodeBypassCommand::new(layer.clone(), invert.clone(), true))).unwrap();
+        assert_ne!(doc.render_composite().unwrap().to_rgba8(), inverted);
+        doc.undo().unwrap();
+        assert!(!node(&doc, &invert).read().is_bypassed());
+        assert_eq!(doc.render_composite().unwrap().to_rgba8(), inverted);
+
+        // The designated output wins over the sink, and survives removal and undo
+        doc.execute_command(Box::new(SetOutputNodeCommand::new(layer.clone(), Some(image.clone())))).unwrap();
+        let original = doc.get_layer(&layer).unwrap().read().node_graph().evaluate(&image).unwrap();
+        let original = original.as_image().unwrap().to_rgba8();
+        assert_eq!(doc.render_composite().unwrap().to_rgba8(), original);
+        doc.execute_command(Box::new(RemoveNodeCommand::new(layer.clone(), image.clone()))).unwrap();
+        doc.undo().unwrap();
+        assert_eq!(doc.get_layer(&layer).unwrap().read().node_graph().designated_output(), Some(&image));
+        doc.undo().unwrap();
+        assert_eq!(doc.render_composite().unwrap().to_rgba8(), inverted);
+    }
+}
@@ -1,6 +1,8 @@
 use std::error::Error;
 use thiserror::Error;
 use std::fmt::Debug;
+use crate::compaction::SnapshotBarrier;
+use crate::{CommandRecord, Document};
 
 #[derive(Error, Debug)]
 pub enum HistoryError {
@@ -10,17 +12,43 @@ pub enum HistoryError {
     NoRedoAvailable,
     #[error("Command execution failed: {0}")]
     CommandFailed(String),
+    #[error("Cannot undo past compacted history")]
+    BarrierReached,
 }
 
+/// An undoable edit. Commands receive the document they were executed on so
+/// they can restructure it, not just mutate layers they hold handles to.
 pub trait Command: Send + Sync + Debug {
-    fn execute(&self) -> Result<(), Box<dyn Error>>;
-    fn undo(&self) -> Result<(), Box<dyn Error>>;
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>>;
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>>;
+
+    /// Describes the command for saving the history with the document.
+    /// Commands that can't be saved return `None`, and the reloaded history
+    /// stops at them.
+    fn to_record(&self) -> Option<CommandRecord> {
+        None
+    }
+
+    /// Approximate memory the command keeps alive, e.g. layers it removed
+    /// and holds for undo. State still owned by the document isn't counted.
+    fn memory_hint(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+}
+
+#[derive(Debug)]
+struct HistoryEntry {
+    command: Box<dyn Command>,
+    serial: u64,
 }
 
 #[derive(Debug)]
 pub struct History {
-    commands: Vec<Box<dyn Command>>,
+    commands: Vec<HistoryEntry>,
     current_index: usize,
+    next_serial: u64,
+    // Whether the first command is a `SnapshotBarrier`
+    barrier: bool,
 }
 
 impl History {
@@ -28,12 +56,30 @@ impl History {
         Self {
             commands: Vec::new(),
             current_index: 0,
+            next_serial: 1,
+            barrier: false,
+        }
+    }
+
+    /// A history with `commands` of which the first `current_index` are applied.
+    pub(crate) fn from_commands(commands: Vec<Box<dyn Command>>, current_index: usize) -> Self {
+        let mut history = Self::new();
+        for command in commands {
+            history.push(command);
         }
+        history.current_index = current_index.min(history.commands.len());
+        history
+    }
+
+    fn push(&mut self, command: Box<dyn Command>) {
+        let serial = self.next_serial;
+        self.next_serial += 1;
+        self.commands.push(HistoryEntry { command, serial });
     }
 
-    pub fn execute(&mut self, command: Box<dyn Command>) -> Result<(), Box<dyn Error>> {
+    pub fn execute(&mut self, command: Box<dyn Command>, document: &mut Document) -> Result<(), Box<dyn Error>> {
         // Execute the command
-        command.execute()?;
+        command.execute(document)?;
 
         // If we're not at the end of the history, truncate the redo stack
         if self.current_index < self.commands.len() {
@@ -41,41 +87,124 @@ impl History {
         }
 
         // Add the command to history
-        self.commands.push(command);
+        self.push(command);
         self.current_index += 1;
 
         Ok(())
     }
 
-    pub fn undo(&mut self) -> Result<(), Box<dyn Error>> {
+    pub fn undo(&mut self, document: &mut Document) -> Result<(), Box<dyn Error>> {
         if self.current_index == 0 {
             return Err(Box::new(HistoryError::NoUndoAvailable));
         }
+        if self.current_index == 1 && self.barrier {
+            return Err(Box::new(HistoryError::BarrierReached));
+        }
 
         self.current_index -= 1;
-        self.commands[self.current_index].undo()?;
+        self.commands[self.current_index].command.undo(document)?;
 
         Ok(())
     }
 
-    pub fn redo(&mut self) -> Result<(), Box<dyn Error>> {
+    pub fn redo(&mut self, document: &mut Document) -> Result<(), Box<dyn Error>> {
         if self.current_index >= self.commands.len() {
             return Err(Box::new(HistoryError::NoRedoAvailable));
         }
 
-        self.commands[self.current_index].execute()?;
+        self.commands[self.current_index].command.execute(document)?;
         self.current_index += 1;
 
         Ok(())
     }
 
     pub fn can_undo(&self) -> bool {
-        self.current_index > 0
+        self.current_index > usize::from(self.barrier)
     }
 
     pub fn can_redo(&self) -> bool {
         self.current_index < self.commands.len()
     }
+
+    /// Commands oldest first, applied or not.
+    pub(crate) fn commands(&self) -> Vec<&dyn Command> {
+        self.commands.iter().map(|entry| entry.command.as_ref()).collect()
+    }
+
+    /// Number of applied commands.
+    pub(crate) fn current_index(&self) -> usize {
+        self.current_index
+    }
+
+    /// Replaces all applied commands but the `keep_recent` latest with a
+    /// [`SnapshotBarrier`] holding a copy of the document as they left it,
+    /// releasing whatever the replaced commands captured. Undo stops at the
+    /// barrier; [`History::undo_to_barrier`] returns to it in one step.
+    ///
+    /// The recent commands are undone and redone to reach the barrier's state.
+    pub fn compact(&mut self, keep_recent: usize, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let applied = self.current_index;
+        let cut = applied - keep_recent.min(applied);
+        if cut == 0 || (cut == 1 && self.barrier) {
+            return Ok(());
+        }
+
+        for entry in self.commands[cut..applied].iter().rev() {
+            entry.command.undo(document)?;
+        }
+        let barrier = SnapshotBarrier::capture(document);
+        for entry in &self.commands[cut..applied] {
+            entry.command.execute(document)?;
+        }
+
+        // The barrier takes the serial of the last command it replaces, so
+        // saved positions stay valid
+        let serial = self.commands[cut - 1].serial;
+        self.commands.splice(0..cut, [HistoryEntry { command: Box::new(barrier), serial }]);
+        self.current_index = applied - cut + 1;
+        self.barrier = true;
+        Ok(())
+    }
+
+    /// Restores the state captured by the barrier, leaving every later
+    /// command to redo.
+    pub fn undo_to_barrier(&mut self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        if !self.barrier {
+            return Err(Box::new(HistoryError::NoUndoAvailable));
+        }
+        // Executing the barrier restores its snapshot
+        self.commands[0].command.execute(document)?;
+        self.current_index = 1;
+        Ok(())
+    }
+
+    pub fn has_barrier(&self) -> bool {
+        self.barrier
+    }
+
+    /// Sum of the commands' [`Command::memory_hint`]s.
+    pub fn memory_bytes(&self) -> usize {
+        self.commands.iter().map(|entry| entry.command.memory_hint()).sum()
+    }
+
+    /// Identifies the state the history is currently at.
+    ///
+    /// Every executed command gets a unique serial, so two positions compare
+    /// equal only if they refer to the same applied command. Returns 0 when no
+    /// command is applied.
+    pub fn position(&self) -> u64 {
+        if self.current_index == 0 {
+            0
+        } else {
+            self.commands[self.current_index - 1].serial
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
@@ -100,12 +229,12 @@ mod tests {
     }
 
     impl Command for TestCommand {
-        fn execute(&self) -> Result<(), Box<dyn Error>> {
+        fn execute(&self, _document: &mut Document) -> Result<(), Box<dyn Error>> {
             self.executed.store(true, Ordering::SeqCst);
             Ok(())
         }
 
-        fn undo(&self) -> Result<(), Box<dyn Error>> {
+        fn undo(&self, _document: &mut Document) -> Result<(), Box<dyn Error>> {
             self.undone.store(true, Ordering::SeqCst);
             Ok(())
         }
@@ -114,21 +243,40 @@ mod tests {
     #[test]
     fn test_history_operations() {
         let mut history = History::new();
+        let mut document = Document::new();
         let command = Box::new(TestCommand::new());
         
         // Test execute
-        assert!(history.execute(command).is_ok());
+        assert!(history.execute(command, &mut document).is_ok());
         assert!(history.can_undo());
         assert!(!history.can_redo());
 
         // Test undo
-        assert!(history.undo().is_ok());
+        assert!(history.undo(&mut document).is_ok());
         assert!(!history.can_undo());
         assert!(history.can_redo());
 
         // Test redo
-        assert!(history.redo().is_ok());
+        assert!(history.redo(&mut document).is_ok());
         assert!(history.can_undo());
         assert!(!history.can_redo());
     }
+
+    #[test]
+    fn test_position_tracks_applied_command() {
+        let mut history = History::new();
+        let mut document = Document::new();
+        assert_eq!(history.position(), 0);
+
+        history.execute(Box::new(TestCommand::new()), &mut document).unwrap();
+        let first = history.position();
+        assert_ne!(first, 0);
+
+        history.undo(&mut document).unwrap();
+        assert_eq!(history.position(), 0);
+
+        // A new command after undo must not reuse the discarded position
+        history.execute(Box::new(TestCommand::new()), &mut document).unwrap();
+        assert_ne!(history.position(), first);
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,248 @@
+//! Saving the undo history alongside a document.
+//!
+//! Commands describe themselves through [`Command::to_record`], including
+//! the state they captured when executed, so a reloaded history can undo
+//! entries that were applied before the save. Layers are referenced by id,
+//! which survives a save and reload. Commands without a record (e.g. graph
+//! edits, whose node ids are not stable across reloads) are saved as
+//! barriers: the restored history stops at the nearest barrier on either side
+//! of the current position.
+
+use serde::{Deserialize, Serialize};
+use crate::canvas::{CanvasCommand, CanvasState};
+use crate::commands::MoveLayerCommand;
+use crate::naming::RenameLayerCommand;
+use crate::transform::SetLayerTransformCommand;
+use crate::{Command, Document, History, LayerId, LayerTransform};
+
+/// A serializable description of a command and the undo state it captured.
+///
+/// The captured state is `None` for commands that were undone (or never
+/// executed) when the record was made.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum CommandRecord {
+    MoveLayer {
+        layer_id: LayerId,
+        to_index: usize,
+        from_index: Option<usize>,
+    },
+    SetLayerTransform {
+        layer_id: LayerId,
+        transform: LayerTransform,
+        previous: Option<LayerTransform>,
+    },
+    RenameLayer {
+        layer_id: LayerId,
+        name: String,
+        previous: Option<String>,
+    },
+    Canvas {
+        size: (u32, u32),
+        transforms: Vec<(LayerId, LayerTransform)>,
+        previous: Option<CanvasState>,
+    },
+}
+
+impl CommandRecord {
+    /// Rebuilds the command with its captured state.
+    pub fn into_command(self) -> Box<dyn Command> {
+        match self {
+            Self::MoveLayer { layer_id, to_index, from_index } => {
+                Box::new(MoveLayerCommand::restored(layer_id, to_index, from_index))
+            }
+            Self::SetLayerTransform { layer_id, transform, previous } => {
+                Box::new(SetLayerTransformCommand::restored(layer_id, transform, previous))
+            }
+            Self::RenameLayer { layer_id, name, previous } => {
+                Box::new(RenameLayerCommand::restored(layer_id, name, previous))
+            }
+            Self::Canvas { size, transforms, previous } => {
+                Box::new(CanvasCommand::restored(size, transforms, previous))
+            }
+        }
+    }
+}
+
+/// The `history.json` entry of a package. `None` entries are barriers.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SerializedHistory {
+    entries: Vec<Option<CommandRecord>>,
+    current_index: usize,
+}
+
+impl SerializedHistory {
+    /// Records up to `max_entries` entries around the current position,
+    /// preferring undo steps over redo steps.
+    pub(crate) fn record(history: &History, max_entries: usize) -> Self {
+        let commands = history.commands();
+        let current = history.current_index();
+        let start = current - current.min(max_entries);
+        let end = commands.len().min(start + max_entries);
+        Self {
+            entries: commands[start..end].iter().map(|command| command.to_record()).collect(),
+            current_index: current - start,
+        }
+    }
+
+    /// Builds a history from the entries between the barriers nearest to the
+    /// current position.
+    pub(crate) fn into_history(self) -> anyhow::Result<History> {
+        let Self { mut entries, current_index } = self;
+        if current_index > entries.len() {
+            anyhow::bail!(
+                "History position {} is past its {} entries",
+                current_index,
+                entries.len()
+            );
+        }
+        let end = entries[current_index..]
+            .iter()
+            .position(Option::is_none)
+            .map_or(entries.len(), |offset| current_index + offset);
+        let start = entries[..current_index]
+            .iter()
+            .rposition(Option::is_none)
+            .map_or(0, |index| index + 1);
+        let commands = entries
+            .drain(start..end)
+            .flatten()
+            .map(CommandRecord::into_command)
+            .collect();
+        Ok(History::from_commands(commands, current_index - start))
+    }
+}
+
+impl Document {
+    /// Replaces the history with one loaded from a file. The document counts
+    /// as saved at the restored position.
+    pub(crate) fn restore_history(&mut self, history: SerializedHistory) -> anyhow::Result<()> {
+        self.history = history.into_history()?;
+        self.saved_position = self.history.position();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use parking_lot::RwLock;
+    use uuid::Uuid;
+    use crate::{Anchor, Layer, NamePolicy, SaveOptions};
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("meridian_history_file_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn document() -> (Document, Vec<LayerId>) {
+        let mut doc = Document::with_size(40, 30);
+        let mut ids = Vec::new();
+        for (index, color) in [[220u8, 40, 40, 255], [40, 220, 40, 200], [40, 40, 220, 150]].into_iter().enumerate() {
+            let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(20, 16, Rgba(color)));
+            let id = LayerId::new();
+            doc.insert_layer_raw(index, id.clone(), Arc::new(RwLock::new(Layer::with_image(format!("Layer {}", index), image))));
+            ids.push(id);
+        }
+        (doc, ids)
+    }
+
+    fn state(doc: &Document) -> ((u32, u32), Vec<(LayerId, String, LayerTransform)>) {
+        let layers = doc
+            .layer_order
+            .iter()
+            .map(|id| {
+                let layer = doc.get_layer(id).unwrap();
+                let layer = layer.read();
+                (id.clone(), layer.name().to_string(), layer.transform())
+            })
+            .collect();
+        (doc.size(), layers)
+    }
+
+    fn with_history() -> SaveOptions {
+        SaveOptions { include_history: true, ..SaveOptions::default() }
+    }
+
+    #[test]
+    fn test_reloaded_history_undoes_like_the_original() {
+        let dir = temp_dir();
+        let path = dir.join("history.artm");
+        let (mut doc, ids) = document();
+        doc.set_layer_transform(&ids[0], LayerTransform::translation(5.0, 3.0)).unwrap();
+        doc.rename_layer(&ids[1], "Renamed", NamePolicy::AllowDuplicates).unwrap();
+        doc.resize_canvas(60, 50, Anchor::Center).unwrap();
+        doc.move_layer(&ids[2], 0).unwrap();
+        doc.set_layer_transform(&ids[1], LayerTransform::translation(-4.0, 8.0)).unwrap();
+        doc.undo().unwrap();
+
+        doc.save_package_with(&path, &with_history()).unwrap();
+        let mut reloaded = Document::load_package(&path).unwrap();
+        assert_eq!(state(&reloaded), state(&doc));
+        assert!(!reloaded.is_modified());
+
+        for _ in 0..2 {
+            doc.undo().unwrap();
+            reloaded.undo().unwrap();
+        }
+        assert_eq!(state(&reloaded), state(&doc));
+        assert_eq!(reloaded.render_composite().unwrap().to_rgba8(), doc.render_composite().unwrap().to_rgba8());
+        assert!(reloaded.is_modified());
+
+        for _ in 0..3 {
+            doc.redo().unwrap();
+            reloaded.redo().unwrap();
+        }
+        assert_eq!(state(&reloaded), state(&doc));
+        assert!(!reloaded.history.can_redo());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_history_stops_at_barriers() {
+        let dir = temp_dir();
+        let path = dir.join("barrier.artm");
+        let (mut doc, ids) = document();
+        doc.set_layer_transform(&ids[0], LayerTransform::translation(2.0, 2.0)).unwrap();
+        doc.merge_down(&ids[2]).unwrap();
+        doc.rename_layer(&ids[0], "Base", NamePolicy::AllowDuplicates).unwrap();
+
+        doc.save_package_with(&path, &with_history()).unwrap();
+        let mut reloaded = Document::load_package(&path).unwrap();
+        reloaded.undo().unwrap();
+        assert_eq!(reloaded.get_layer(&ids[0]).unwrap().read().name(), "Layer 0");
+        assert!(!reloaded.history.can_undo());
+
+        // Without the option, no history is saved
+        doc.save_package(&path).unwrap();
+        assert!(!Document::load_package(&path).unwrap().history.can_undo());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_record_keeps_entries_around_current_position() {
+        let (mut doc, ids) = document();
+        for step in 0..6 {
+            doc.set_layer_transform(&ids[0], LayerTransform::translation(step as f32, 0.0)).unwrap();
+        }
+        for _ in 0..2 {
+            doc.undo().unwrap();
+        }
+
+        let recorded = SerializedHistory::record(&doc.history, 3);
+        assert_eq!(recorded.entries.len(), 3);
+        assert_eq!(recorded.current_index, 3);
+
+        let recorded = SerializedHistory::record(&doc.history, 5);
+        assert_eq!(recorded.entries.len(), 5);
+        assert_eq!(recorded.current_index, 4);
+        assert!(matches!(
+            recorded.entries[4],
+            Some(CommandRecord::SetLayerTransform { previous: None, .. })
+        ));
+    }
+}
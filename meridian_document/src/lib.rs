@@ -1,17 +1,79 @@
 mod history;
+pub mod access;
+pub mod assets;
+pub mod async_io;
+pub mod autosave;
 pub mod blend;
+pub mod canvas;
+pub mod color;
+pub mod commands;
+pub mod compaction;
+pub mod composite;
+pub mod compositing;
+pub mod events;
+pub mod export;
+pub mod graph_commands;
+pub mod history_file;
+pub mod linked;
+pub mod manager;
+pub mod memory;
+pub mod migrations;
+pub mod naming;
+pub mod ora;
+pub mod query;
+pub mod package;
+pub mod preview;
+pub mod region;
+pub mod render_cache;
+pub mod selection;
 pub mod serialization;
+pub mod smart;
+pub mod snapshot;
+pub mod solo;
+pub mod thumbnail;
+pub mod transform;
+pub mod ui_layout;
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use aurion_core::{NodeGraph, Node, NodeId, NodeError};
-use parking_lot::RwLock;
+use aurion_std_nodes::ImageNode;
+use aurion_std_nodes::assets::{AssetId, AssetRefNode};
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use serde::{Serialize, Deserialize};
 use thiserror::Error;
 use uuid::Uuid;
 use image::DynamicImage;
+use chrono::{DateTime, Utc};
+use assets::AssetStore;
+use autosave::AutosaveState;
+use render_cache::RenderCache;
+use events::EventBus;
+use commands::MoveLayerCommand;
+use thumbnail::ThumbnailCache;
+use preview::PreviewCache;
+pub use async_io::{IoTask, LoadPhase, LoadProgress, SaveHandle, SavePhase, SaveProgress};
 pub use blend::BlendMode;
+pub use canvas::{Anchor, CanvasRect};
+pub use color::ColorProfile;
+pub use compaction::SnapshotBarrier;
+pub use compositing::{CompositingGraph, LayerSourceNode};
+pub use events::{DocumentEvent, ListenerId};
+pub use export::{ExportBackground, ExportFormat, ExportOptions};
 pub use history::{History, Command, HistoryError};
+pub use history_file::CommandRecord;
+pub use manager::{Clipboard, DocumentId, DocumentManager};
+pub use memory::{AssetMemory, LayerMemory, MemoryReport};
+pub use naming::NamePolicy;
+pub use package::SaveOptions;
+pub use preview::{NodePreview, PreviewContent, PREVIEW_SIZE};
+pub use query::{LayerFilter, LayerKind};
+pub use render_cache::RenderCacheStats;
+pub use selection::Selection;
+pub use smart::{SmartObjectNode, SmartSource};
+pub use snapshot::{DocumentSnapshot, LayerSnapshot};
+pub use transform::LayerTransform;
 
 #[derive(Error, Debug)]
 pub enum DocumentError {
@@ -21,11 +83,28 @@ pub enum DocumentError {
     NodeError(#[from] NodeError),
     #[error("History error: {0}")]
     HistoryError(#[from] HistoryError),
+    #[error("Failed to export {}: {source}", path.display())]
+    Export {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    #[error("Unsupported document format version {found} (newest supported is {supported})")]
+    UnsupportedVersion {
+        found: u32,
+        supported: u32,
+    },
+    #[error("Invalid operation: {0}")]
+    InvalidOperation(String),
+    #[error("Operation cancelled")]
+    Cancelled,
+    #[error("Timed out waiting for layer {0}")]
+    LockTimeout(Uuid),
     #[error("Other error: {0}")]
     Other(String),
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct LayerId(Uuid);
 
 impl LayerId {
@@ -35,30 +114,57 @@ impl LayerId {
 }
 
 pub struct Layer {
-    node_graph: NodeGraph,
+    // Shared with linked layers
+    node_graph: Arc<RwLock<NodeGraph>>,
     opacity: f32,
     visible: bool,
+    locked: bool,
     name: String,
     blend_mode: BlendMode,
+    transform: LayerTransform,
+    thumbnail: ThumbnailCache,
 }
 
 impl Layer {
     pub fn new() -> Self {
         Self {
-            node_graph: NodeGraph::new(),
+            node_graph: Arc::new(RwLock::new(NodeGraph::new())),
             opacity: 1.0,
             visible: true,
+            locked: false,
             name: "New Layer".to_string(),
             blend_mode: BlendMode::Normal,
+            transform: LayerTransform::IDENTITY,
+            thumbnail: ThumbnailCache::new(),
         }
     }
 
-    pub fn node_graph(&self) -> &NodeGraph {
-        &self.node_graph
+    /// A layer whose graph is a single node holding `image`.
+    pub fn with_image(name: impl Into<String>, image: DynamicImage) -> Self {
+        let mut layer = Self::new();
+        layer.name = name.into();
+        layer.node_graph.write().add_node(Node::new(Box::new(ImageNode::with_image(image))));
+        layer
     }
 
-    pub fn node_graph_mut(&mut self) -> &mut NodeGraph {
-        &mut self.node_graph
+    /// A layer whose graph is a single reference to a document asset.
+    pub fn with_asset(name: impl Into<String>, asset: AssetId) -> Self {
+        let mut layer = Self::new();
+        layer.name = name.into();
+        layer.node_graph.write().add_node(Node::new(Box::new(AssetRefNode::new(asset))));
+        layer
+    }
+
+    pub fn node_graph(&self) -> RwLockReadGuard<'_, NodeGraph> {
+        self.node_graph.read()
+    }
+
+    /// Mutable access to the graph. Cached renders of this layer are dropped;
+    /// those of layers linked to it are not (see
+    /// [`Document::notify_graph_changed`]).
+    pub fn node_graph_mut(&mut self) -> RwLockWriteGuard<'_, NodeGraph> {
+        self.thumbnail.invalidate();
+        self.node_graph.write()
     }
 
     pub fn opacity(&self) -> f32 {
@@ -77,6 +183,15 @@ impl Layer {
         self.visible = visible;
     }
 
+    /// Whether the layer is protected from edits in the UI.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -92,6 +207,14 @@ impl Layer {
     pub fn set_blend_mode(&mut self, mode: BlendMode) {
         self.blend_mode = mode;
     }
+
+    pub fn transform(&self) -> LayerTransform {
+        self.transform
+    }
+
+    pub fn set_transform(&mut self, transform: LayerTransform) {
+        self.transform = transform;
+    }
 }
 
 impl std::fmt::Debug for Layer {
@@ -102,39 +225,199 @@ impl std::fmt::Debug for Layer {
     }
 }
 
+pub const DEFAULT_CANVAS_WIDTH: u32 = 1920;
+pub const DEFAULT_CANVAS_HEIGHT: u32 = 1080;
+
 #[derive(Debug)]
 pub struct Document {
+    width: u32,
+    height: u32,
     layers: HashMap<LayerId, Arc<RwLock<Layer>>>,
     layer_order: Vec<LayerId>,
     history: History,
+    events: EventBus,
+    // Set by mutations that bypass the history (e.g. add_layer)
+    modified: bool,
+    saved_position: u64,
+    last_saved_at: Option<DateTime<Utc>>,
+    revision: u64,
+    path: Option<PathBuf>,
+    autosave: Option<AutosaveState>,
+    assets: AssetStore,
+    selection: Option<Selection>,
+    color_profile: ColorProfile,
+    render_cache: RenderCache,
+    // Shared with the observer `eval_context` hands to evaluation
+    previews: Arc<PreviewCache>,
+    // When set, replaces the layer stack once it has an output node
+    compositing: Option<CompositingGraph>,
+    // Set on documents opened from an embedded smart object
+    smart_edit: Option<smart::SmartEditTarget>,
+    // Per-layer editor layout, saved but not interpreted
+    ui_layout: HashMap<LayerId, serde_json::Value>,
+    // Viewing state, never saved
+    solo: Option<LayerId>,
+    lock_timeout: std::time::Duration,
 }
 
 impl Document {
     pub fn new() -> Self {
+        Self::with_size(DEFAULT_CANVAS_WIDTH, DEFAULT_CANVAS_HEIGHT)
+    }
+
+    pub fn with_size(width: u32, height: u32) -> Self {
         Self {
+            width,
+            height,
             layers: HashMap::new(),
             layer_order: Vec::new(),
             history: History::new(),
+            events: EventBus::new(),
+            modified: false,
+            saved_position: 0,
+            last_saved_at: None,
+            revision: 0,
+            path: None,
+            autosave: None,
+            assets: AssetStore::new(),
+            selection: None,
+            color_profile: ColorProfile::Srgb,
+            render_cache: RenderCache::new(),
+            previews: Arc::new(PreviewCache::default()),
+            compositing: None,
+            smart_edit: None,
+            ui_layout: HashMap::new(),
+            solo: None,
+            lock_timeout: access::DEFAULT_LOCK_TIMEOUT,
         }
     }
 
-    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), DocumentError> {
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<(), DocumentError> {
         let serialized = self.serialize()
             .map_err(|e| DocumentError::Other(format!("Failed to serialize document: {}", e)))?;
-        let file = std::fs::File::create(path)
+        let file = std::fs::File::create(path.as_ref())
             .map_err(|e| DocumentError::Other(format!("Failed to create file: {}", e)))?;
-        serde_json::to_writer_pretty(file, &serialized)
+        serde_json::to_writer_pretty(std::io::BufWriter::new(file), &serialized)
             .map_err(|e| DocumentError::Other(format!("Failed to write document: {}", e)))?;
+        self.finish_save(path.as_ref());
         Ok(())
     }
 
-    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, DocumentError> {
-        let file = std::fs::File::open(path)
-            .map_err(|e| DocumentError::Other(format!("Failed to open file: {}", e)))?;
-        let serialized: serialization::SerializedDocument = serde_json::from_reader(file)
+    pub(crate) fn finish_save(&mut self, path: &Path) {
+        self.path = Some(path.to_path_buf());
+        self.mark_saved();
+        self.discard_autosave();
+    }
+
+    /// The file this document was last saved to or loaded from.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Name used for derived files such as autosaves.
+    pub fn name(&self) -> String {
+        self.path
+            .as_ref()
+            .and_then(|path| path.file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "untitled".to_string())
+    }
+
+    /// Whether the document has changes that have not been saved.
+    ///
+    /// Undoing back to the history position that was current at the last save
+    /// clears the flag again, unless a change outside the history happened.
+    pub fn is_modified(&self) -> bool {
+        self.modified || self.history.position() != self.saved_position
+    }
+
+    pub fn last_saved_at(&self) -> Option<DateTime<Utc>> {
+        self.last_saved_at
+    }
+
+    /// Flags a change made directly on a layer handle rather than through a command.
+    pub fn mark_modified(&mut self) {
+        let was_modified = self.is_modified();
+        self.modified = true;
+        self.record_change(was_modified);
+    }
+
+    fn mark_saved(&mut self) {
+        let was_modified = self.is_modified();
+        self.modified = false;
+        self.saved_position = self.history.position();
+        self.last_saved_at = Some(Utc::now());
+        self.notify_modified_change(was_modified);
+    }
+
+    /// Counter bumped by every change, used to detect edits since a point in time.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    fn record_change(&mut self, was_modified: bool) {
+        self.revision += 1;
+        self.notify_modified_change(was_modified);
+    }
+
+    fn notify_modified_change(&self, was_modified: bool) {
+        let is_modified = self.is_modified();
+        if is_modified != was_modified {
+            self.events.emit(DocumentEvent::ModifiedChanged(is_modified));
+        }
+    }
+
+    pub fn subscribe<F>(&mut self, listener: F) -> ListenerId
+    where
+        F: Fn(&DocumentEvent) + Send + Sync + 'static,
+    {
+        self.events.subscribe(listener)
+    }
+
+    pub fn unsubscribe(&mut self, id: ListenerId) -> bool {
+        self.events.unsubscribe(id)
+    }
+
+    /// Loads a plain JSON document or, detected by its magic bytes, a package.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, DocumentError> {
+        Self::load_with_progress(path.as_ref(), &mut |_| Ok(()))
+    }
+
+    /// Parses a plain JSON document, upgrading older format versions.
+    pub(crate) fn from_json_slice(
+        bytes: &[u8],
+        progress: &mut dyn FnMut(LoadProgress) -> Result<(), DocumentError>,
+    ) -> Result<Self, DocumentError> {
+        let value: serde_json::Value = serde_json::from_slice(bytes)
+            .map_err(|e| DocumentError::Other(format!("Failed to parse document: {}", e)))?;
+        let version = migrations::detect_version(&value)
+            .map_err(|e| DocumentError::Other(e.to_string()))?;
+        if version > serialization::CURRENT_FORMAT_VERSION {
+            return Err(DocumentError::UnsupportedVersion {
+                found: version,
+                supported: serialization::CURRENT_FORMAT_VERSION,
+            });
+        }
+        let value = migrations::migrate(value, version)
+            .map_err(|e| DocumentError::Other(format!("Failed to migrate document: {}", e)))?;
+        let serialized: serialization::SerializedDocument = serde_json::from_value(value)
             .map_err(|e| DocumentError::Other(format!("Failed to deserialize document: {}", e)))?;
-        Self::deserialize(serialized)
-            .map_err(|e| DocumentError::Other(format!("Failed to load document: {}", e)))
+        Self::deserialize_with(serialized, |source| source.decode_inline(), progress).map_err(|e| {
+            e.downcast::<DocumentError>()
+                .unwrap_or_else(|e| DocumentError::Other(format!("Failed to load document: {}", e)))
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
     }
 
     pub fn layer_count(&self) -> usize {
@@ -154,12 +437,14 @@ impl Document {
         let layer = Layer::new();
         self.layers.insert(id.clone(), Arc::new(RwLock::new(layer)));
         self.layer_order.push(id.clone());
+        self.mark_modified();
         id
     }
 
     pub fn remove_layer(&mut self, id: &LayerId) -> Result<(), DocumentError> {
         self.layers.remove(id).ok_or_else(|| DocumentError::LayerNotFound(id.0))?;
         self.layer_order.retain(|layer_id| layer_id != id);
+        self.mark_modified();
         Ok(())
     }
 
@@ -167,35 +452,62 @@ impl Document {
         self.layers.get(id).cloned()
     }
 
-    pub fn move_layer(&mut self, id: &LayerId, new_index: usize) -> Result<(), DocumentError> {
-        if !self.layers.contains_key(id) {
-            return Err(DocumentError::LayerNotFound(id.0));
-        }
+    /// Position of a layer in the stack, 0 being the bottom.
+    pub fn layer_index(&self, id: &LayerId) -> Option<usize> {
+        self.layer_order.iter().position(|layer_id| layer_id == id)
+    }
 
-        if new_index >= self.layer_order.len() {
-            return Err(DocumentError::Other("Invalid layer index".to_string()));
-        }
+    // Structural edits used by commands. They skip modified tracking because
+    // the history accounts for command-driven changes.
+    pub(crate) fn insert_layer_raw(&mut self, index: usize, id: LayerId, layer: Arc<RwLock<Layer>>) {
+        let index = index.min(self.layer_order.len());
+        self.layers.insert(id.clone(), layer);
+        self.layer_order.insert(index, id);
+    }
 
-        let current_index = self.layer_order.iter().position(|x| x == id)
-            .ok_or_else(|| DocumentError::Other("Layer not found in order".to_string()))?;
+    pub(crate) fn take_layer_raw(&mut self, id: &LayerId) -> Result<(usize, Arc<RwLock<Layer>>), DocumentError> {
+        let index = self.layer_index(id).ok_or_else(|| DocumentError::LayerNotFound(id.0))?;
+        let layer = self.layers.remove(id).ok_or_else(|| DocumentError::LayerNotFound(id.0))?;
+        self.layer_order.remove(index);
+        Ok((index, layer))
+    }
 
-        if current_index != new_index {
-            let layer_id = self.layer_order.remove(current_index);
-            self.layer_order.insert(new_index, layer_id);
+    /// Moves a layer so it sits in front of the layer currently at
+    /// `new_index`; `new_index == layer_count()` moves it to the top. Moves
+    /// that leave the stack unchanged are not recorded in the history.
+    pub fn move_layer(&mut self, id: &LayerId, new_index: usize) -> Result<(), DocumentError> {
+        let current_index = self.layer_index(id).ok_or_else(|| DocumentError::LayerNotFound(id.0))?;
+
+        if new_index > self.layer_order.len() {
+            return Err(DocumentError::InvalidOperation(format!(
+                "Invalid layer index {} for {} layers",
+                new_index,
+                self.layer_order.len()
+            )));
         }
 
-        Ok(())
+        // The layer's own slot disappears once it is taken out of the stack
+        let target_index = if new_index > current_index { new_index - 1 } else { new_index };
+        if target_index == current_index {
+            return Ok(());
+        }
+
+        self.execute_command(Box::new(MoveLayerCommand::new(id.clone(), target_index)))
     }
 
+    /// Evaluates each layer's output nodes (see [`NodeGraph::output_nodes`])
+    /// and returns the images they produce, bottom layer first.
     pub fn render(&self) -> Result<Vec<Box<dyn std::any::Any>>, DocumentError> {
         let mut results = Vec::new();
+        let context = self.eval_context();
 
         for layer_id in &self.layer_order {
             if let Some(layer) = self.get_layer(layer_id) {
                 let layer = layer.read();
-                for node_id in layer.node_graph.get_node_ids() {
-                    let result = layer.node_graph.evaluate(&node_id)?;
-                    if let Some(image) = result.downcast_ref::<DynamicImage>() {
+                let graph = layer.node_graph();
+                for node_id in graph.output_nodes() {
+                    let result = graph.evaluate_with_context(&node_id, &context)?;
+                    if let Some(image) = result.as_image() {
                         results.push(Box::new(image.clone()) as Box<dyn std::any::Any>);
                     }
                 }
@@ -206,19 +518,42 @@ impl Document {
     }
 
     pub fn execute_command(&mut self, command: Box<dyn Command>) -> Result<(), DocumentError> {
-        self.history.execute(command).map_err(|e| DocumentError::Other(e.to_string()))?;
+        let was_modified = self.is_modified();
+        let mut history = std::mem::take(&mut self.history);
+        let result = history.execute(command, self);
+        self.history = history;
+        result.map_err(|e| DocumentError::Other(e.to_string()))?;
+        self.record_change(was_modified);
         Ok(())
     }
 
     pub fn undo(&mut self) -> Result<(), DocumentError> {
-        self.history.undo().map_err(|e| DocumentError::Other(e.to_string()))?;
+        let was_modified = self.is_modified();
+        let mut history = std::mem::take(&mut self.history);
+        let result = history.undo(self);
+        self.history = history;
+        result.map_err(|e| DocumentError::Other(e.to_string()))?;
+        self.record_change(was_modified);
         Ok(())
     }
 
     pub fn redo(&mut self) -> Result<(), DocumentError> {
-        self.history.redo().map_err(|e| DocumentError::Other(e.to_string()))?;
+        let was_modified = self.is_modified();
+        let mut history = std::mem::take(&mut self.history);
+        let result = history.redo(self);
+        self.history = history;
+        result.map_err(|e| DocumentError::Other(e.to_string()))?;
+        self.record_change(was_modified);
         Ok(())
     }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
 }
 
 #[cfg(test)]
@@ -254,4 +589,96 @@ mod tests {
         layer.set_visible(false);
         assert!(!layer.is_visible());
     }
+
+    #[derive(Debug)]
+    struct NoopCommand;
+
+    impl Command for NoopCommand {
+        fn execute(&self, _document: &mut Document) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+
+        fn undo(&self, _document: &mut Document) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("meridian_{}_{}.json", name, Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_new_document_is_unmodified() {
+        let doc = Document::new();
+        assert!(!doc.is_modified());
+        assert!(doc.last_saved_at().is_none());
+    }
+
+    #[test]
+    fn test_save_clears_modified() {
+        let mut doc = Document::new();
+        doc.add_layer();
+        assert!(doc.is_modified());
+
+        let path = temp_path("save_clears");
+        doc.save(&path).unwrap();
+        assert!(!doc.is_modified());
+        assert!(doc.last_saved_at().is_some());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_undo_back_to_saved_state() {
+        let mut doc = Document::new();
+        let path = temp_path("undo_saved");
+        doc.save(&path).unwrap();
+
+        doc.execute_command(Box::new(NoopCommand)).unwrap();
+        assert!(doc.is_modified());
+
+        // Undoing the only edit returns to the saved history position
+        doc.undo().unwrap();
+        assert!(!doc.is_modified());
+
+        doc.redo().unwrap();
+        assert!(doc.is_modified());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_undo_past_saved_state_is_modified() {
+        let mut doc = Document::new();
+        doc.execute_command(Box::new(NoopCommand)).unwrap();
+        let path = temp_path("undo_past");
+        doc.save(&path).unwrap();
+
+        doc.undo().unwrap();
+        assert!(doc.is_modified());
+
+        // A different command at the same depth is not the saved state
+        doc.execute_command(Box::new(NoopCommand)).unwrap();
+        assert!(doc.is_modified());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_modified_changed_events() {
+        let mut doc = Document::new();
+        let events = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        doc.subscribe(move |event| sink.lock().push(event.clone()));
+
+        doc.execute_command(Box::new(NoopCommand)).unwrap();
+        doc.execute_command(Box::new(NoopCommand)).unwrap();
+        doc.undo().unwrap();
+        doc.undo().unwrap();
+
+        assert_eq!(
+            *events.lock(),
+            vec![
+                DocumentEvent::ModifiedChanged(true),
+                DocumentEvent::ModifiedChanged(false),
+            ]
+        );
+    }
 }
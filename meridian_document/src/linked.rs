@@ -0,0 +1,179 @@
+//! Linked layers: layers sharing one node graph while keeping their own
+//! opacity, visibility, blend mode and transform.
+//!
+//! Editing the graph through any of them changes all of them. The render
+//! cache is keyed by graph content, so a shared graph is evaluated once per
+//! composite however many layers show it.
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use crate::{Document, DocumentError, Layer, LayerId};
+
+impl Layer {
+    /// A layer with the same properties whose graph is this layer's graph.
+    pub fn linked_copy(&self) -> Layer {
+        let mut layer = Layer::new();
+        layer.node_graph = self.node_graph.clone();
+        layer.name = self.name.clone();
+        layer.opacity = self.opacity;
+        layer.visible = self.visible;
+        layer.blend_mode = self.blend_mode;
+        layer.transform = self.transform;
+        layer
+    }
+
+    /// Whether both layers share one graph.
+    pub fn is_linked_to(&self, other: &Layer) -> bool {
+        Arc::ptr_eq(&self.node_graph, &other.node_graph)
+    }
+
+    /// Makes this layer share `other`'s graph, dropping its own.
+    pub(crate) fn link_to(&mut self, other: &Layer) {
+        self.node_graph = other.node_graph.clone();
+        self.thumbnail.invalidate();
+    }
+
+    /// Identifies the graph, equal for linked layers.
+    pub(crate) fn graph_key(&self) -> usize {
+        Arc::as_ptr(&self.node_graph) as usize
+    }
+}
+
+impl Document {
+    /// Adds a layer above `source` sharing its graph.
+    pub fn add_linked_layer(&mut self, source: &LayerId) -> Result<LayerId, DocumentError> {
+        let index = self.layer_index(source).ok_or_else(|| DocumentError::LayerNotFound(source.0))?;
+        let mut linked = self.with_layer(source, Layer::linked_copy)?;
+        linked.name = self.unique_layer_name(&linked.name, None);
+
+        let id = LayerId::new();
+        self.insert_layer_raw(index + 1, id.clone(), Arc::new(RwLock::new(linked)));
+        self.mark_modified();
+        Ok(id)
+    }
+
+    /// Gives a linked layer its own copy of the graph. Layers that aren't
+    /// linked are left alone.
+    pub fn unlink_layer(&mut self, id: &LayerId) -> Result<(), DocumentError> {
+        if self.linked_layers(id).len() < 2 {
+            return Ok(());
+        }
+        self.with_layer_mut(id, |layer| {
+            let copy = layer.node_graph().deep_clone();
+            layer.node_graph = Arc::new(RwLock::new(copy));
+        })?;
+        self.mark_modified();
+        Ok(())
+    }
+
+    /// Layers sharing `id`'s graph, `id` included, bottom first. Empty if
+    /// there is no such layer.
+    pub fn linked_layers(&self, id: &LayerId) -> Vec<LayerId> {
+        let Some(key) = self.get_layer(id).map(|layer| layer.read().graph_key()) else {
+            return Vec::new();
+        };
+        self.layer_order
+            .iter()
+            .filter(|other| {
+                self.get_layer(other)
+                    .map_or(false, |layer| layer.read().graph_key() == key)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aurion_core::Node;
+    use aurion_std_nodes::filters::InvertNode;
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use crate::{BlendMode, LayerTransform};
+
+    fn document() -> (Document, LayerId) {
+        let mut doc = Document::with_size(24, 16);
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 8, |x, y| Rgba([(x * 30) as u8, (y * 30) as u8, 90, 255])));
+        let id = LayerId::new();
+        doc.insert_layer_raw(0, id.clone(), Arc::new(RwLock::new(Layer::with_image("Source", image))));
+        (doc, id)
+    }
+
+    fn output(doc: &Document, id: &LayerId) -> RgbaImage {
+        let layer = doc.get_layer(id).unwrap();
+        let layer = layer.read();
+        layer.render_output_with(&doc.eval_context()).unwrap().unwrap().to_rgba8()
+    }
+
+    // Inverts the layer's output, so appending twice undoes the first
+    fn append_invert(doc: &mut Document, id: &LayerId) {
+        let layer = doc.get_layer(id).unwrap();
+        {
+            let mut layer = layer.write();
+            let mut graph = layer.node_graph_mut();
+            let output = graph.output_nodes().pop().unwrap();
+            let invert = graph.add_node(Node::new(Box::new(InvertNode::new())));
+            graph.connect(&output, &invert, "input").unwrap();
+        }
+        doc.notify_graph_changed(id).unwrap();
+    }
+
+    #[test]
+    fn test_linked_layers_share_graph_edits() {
+        let (mut doc, source) = document();
+        let linked = doc.add_linked_layer(&source).unwrap();
+        assert_eq!(doc.linked_layers(&source), vec![source.clone(), linked.clone()]);
+        assert_eq!(doc.get_layer(&linked).unwrap().read().name(), "Source (2)");
+
+        // Properties stay independent
+        {
+            let layer = doc.get_layer(&linked).unwrap();
+            let mut layer = layer.write();
+            layer.set_transform(LayerTransform::translation(12.0, 4.0));
+            layer.set_blend_mode(BlendMode::Multiply);
+        }
+        assert_eq!(doc.get_layer(&source).unwrap().read().transform(), LayerTransform::IDENTITY);
+
+        let before = output(&doc, &linked);
+        let composite = doc.render_composite().unwrap().to_rgba8();
+        append_invert(&mut doc, &source);
+        assert_ne!(output(&doc, &linked), before);
+        assert_eq!(output(&doc, &linked), output(&doc, &source));
+        assert_ne!(doc.render_composite().unwrap().to_rgba8(), composite);
+
+        doc.unlink_layer(&linked).unwrap();
+        assert_eq!(doc.linked_layers(&linked), vec![linked.clone()]);
+        let unlinked = output(&doc, &linked);
+        append_invert(&mut doc, &source);
+        assert_eq!(output(&doc, &linked), unlinked);
+        assert_ne!(output(&doc, &source), unlinked);
+    }
+
+    #[test]
+    fn test_shared_graph_evaluated_once_per_composite() {
+        let (mut doc, source) = document();
+        doc.add_linked_layer(&source).unwrap();
+        doc.add_linked_layer(&source).unwrap();
+
+        doc.render_composite().unwrap();
+        let stats = doc.render_cache_stats();
+        assert_eq!((stats.misses, stats.hits), (1, 2));
+
+        append_invert(&mut doc, &source);
+        doc.render_composite().unwrap();
+        let stats = doc.render_cache_stats();
+        assert_eq!((stats.misses, stats.hits), (2, 4));
+    }
+
+    #[test]
+    fn test_link_survives_save() {
+        let (mut doc, source) = document();
+        let linked = doc.add_linked_layer(&source).unwrap();
+        let other = doc.add_layer();
+
+        let loaded = Document::deserialize(doc.serialize().unwrap()).unwrap();
+        assert_eq!(loaded.linked_layers(&source), vec![source.clone(), linked.clone()]);
+        assert_eq!(loaded.linked_layers(&other), vec![other.clone()]);
+        assert_eq!(output(&loaded, &linked), output(&doc, &source));
+    }
+}
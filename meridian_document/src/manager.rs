@@ -0,0 +1,327 @@
+//! Several open documents with a shared clipboard.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use aurion_std_nodes::assets::AssetId;
+use image::DynamicImage;
+use parking_lot::{Mutex, RwLock};
+use crate::events::EventBus;
+use crate::serialization::{ImageSource, SerializedLayer};
+use crate::{Document, DocumentError, LayerId, ListenerId};
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct DocumentId(u64);
+
+/// Notifications emitted by a [`DocumentManager`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManagerEvent {
+    Opened(DocumentId),
+    Closed(DocumentId),
+    /// The active document changed; `None` once the last document is closed.
+    Activated(Option<DocumentId>),
+}
+
+/// A layer copied out of a document, with the assets it references so it can
+/// be pasted into any other document.
+#[derive(Debug, Clone)]
+pub struct ClipboardLayer {
+    layer: SerializedLayer,
+    assets: Vec<(AssetId, Arc<DynamicImage>)>,
+}
+
+impl Document {
+    pub fn copy_layer(&self, id: &LayerId) -> Result<ClipboardLayer, DocumentError> {
+        let (serialized, references) = self.with_layer(id, |layer| {
+            let serialized = SerializedLayer::from_layer(layer, &mut |image| Ok(ImageSource::inline(image)));
+            (serialized, layer.referenced_assets())
+        })?;
+        let serialized = serialized.map_err(|e| DocumentError::Other(format!("Failed to copy layer: {}", e)))?;
+        let assets = references
+            .into_iter()
+            .filter_map(|asset| self.get_asset(&asset).map(|image| (asset, image)))
+            .collect();
+        Ok(ClipboardLayer { layer: serialized, assets })
+    }
+
+    /// Adds a copied layer on top of the stack, bringing its assets along.
+    pub fn paste_layer(&mut self, content: &ClipboardLayer) -> Result<LayerId, DocumentError> {
+        for (asset, image) in &content.assets {
+            if !self.assets.contains(asset) {
+                self.assets.insert_with_id(asset.clone(), (**image).clone());
+            }
+        }
+        let layer = content
+            .layer
+            .clone()
+            .without_node_id()
+            .into_layer(&mut |source| source.decode_inline())
+            .map_err(|e| DocumentError::Other(format!("Failed to paste layer: {}", e)))?;
+
+        let id = LayerId::new();
+        self.layers.insert(id.clone(), Arc::new(RwLock::new(layer)));
+        self.layer_order.push(id.clone());
+        self.mark_modified();
+        Ok(id)
+    }
+}
+
+/// A clipboard handle; clones share the same contents.
+#[derive(Debug, Clone, Default)]
+pub struct Clipboard {
+    content: Arc<Mutex<Option<ClipboardLayer>>>,
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, content: ClipboardLayer) {
+        *self.content.lock() = Some(content);
+    }
+
+    pub fn get(&self) -> Option<ClipboardLayer> {
+        self.content.lock().clone()
+    }
+
+    pub fn clear(&self) {
+        *self.content.lock() = None;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content.lock().is_none()
+    }
+}
+
+/// Keeps track of the open documents and which of them is active.
+#[derive(Debug, Default)]
+pub struct DocumentManager {
+    documents: BTreeMap<DocumentId, Document>,
+    active: Option<DocumentId>,
+    next_id: u64,
+    clipboard: Clipboard,
+    events: EventBus<ManagerEvent>,
+}
+
+impl DocumentManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a document and makes it active.
+    pub fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<DocumentId, DocumentError> {
+        let document = Document::load(path)?;
+        Ok(self.insert(document))
+    }
+
+    /// Creates an empty document and makes it active.
+    pub fn create(&mut self, width: u32, height: u32) -> DocumentId {
+        self.insert(Document::with_size(width, height))
+    }
+
+    /// Adds an already constructed document and makes it active.
+    pub fn insert(&mut self, document: Document) -> DocumentId {
+        let id = DocumentId(self.next_id);
+        self.next_id += 1;
+        self.documents.insert(id, document);
+        self.events.emit(ManagerEvent::Opened(id));
+        self.activate(Some(id));
+        id
+    }
+
+    /// Closes a document and returns it. Documents with unsaved changes are
+    /// only closed when `force` is set.
+    pub fn close(&mut self, id: DocumentId, force: bool) -> Result<Document, DocumentError> {
+        let document = self.documents.get(&id).ok_or_else(|| unknown_document(id))?;
+        if document.is_modified() && !force {
+            return Err(DocumentError::InvalidOperation(format!(
+                "Document '{}' has unsaved changes",
+                document.name()
+            )));
+        }
+
+        let document = self.documents.remove(&id).ok_or_else(|| unknown_document(id))?;
+        self.events.emit(ManagerEvent::Closed(id));
+        if self.active == Some(id) {
+            // Fall back to the most recently opened document
+            let next = self.documents.keys().next_back().copied();
+            self.activate(next);
+        }
+        Ok(document)
+    }
+
+    pub fn get(&self, id: DocumentId) -> Option<&Document> {
+        self.documents.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: DocumentId) -> Option<&mut Document> {
+        self.documents.get_mut(&id)
+    }
+
+    pub fn active_id(&self) -> Option<DocumentId> {
+        self.active
+    }
+
+    pub fn active_document(&self) -> Option<&Document> {
+        self.active.and_then(|id| self.documents.get(&id))
+    }
+
+    pub fn active_document_mut(&mut self) -> Option<&mut Document> {
+        self.active.and_then(|id| self.documents.get_mut(&id))
+    }
+
+    pub fn set_active(&mut self, id: DocumentId) -> Result<(), DocumentError> {
+        if !self.documents.contains_key(&id) {
+            return Err(unknown_document(id));
+        }
+        if self.active != Some(id) {
+            self.activate(Some(id));
+        }
+        Ok(())
+    }
+
+    fn activate(&mut self, id: Option<DocumentId>) {
+        self.active = id;
+        self.events.emit(ManagerEvent::Activated(id));
+    }
+
+    /// Open documents in the order they were opened.
+    pub fn iter(&self) -> impl Iterator<Item = (DocumentId, &Document)> {
+        self.documents.iter().map(|(id, document)| (*id, document))
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    pub fn clipboard(&self) -> &Clipboard {
+        &self.clipboard
+    }
+
+    pub fn copy_layer(&self, document: DocumentId, layer: &LayerId) -> Result<(), DocumentError> {
+        let document = self.documents.get(&document).ok_or_else(|| unknown_document(document))?;
+        self.clipboard.set(document.copy_layer(layer)?);
+        Ok(())
+    }
+
+    /// Pastes the clipboard's layer into `document` and returns the new layer's id.
+    pub fn paste_layer(&mut self, document: DocumentId) -> Result<LayerId, DocumentError> {
+        let content = self
+            .clipboard
+            .get()
+            .ok_or_else(|| DocumentError::InvalidOperation("The clipboard is empty".to_string()))?;
+        let document = self.documents.get_mut(&document).ok_or_else(|| unknown_document(document))?;
+        document.paste_layer(&content)
+    }
+
+    pub fn subscribe<F>(&mut self, listener: F) -> ListenerId
+    where
+        F: Fn(&ManagerEvent) + Send + Sync + 'static,
+    {
+        self.events.subscribe(listener)
+    }
+
+    pub fn unsubscribe(&mut self, id: ListenerId) -> bool {
+        self.events.unsubscribe(id)
+    }
+}
+
+fn unknown_document(id: DocumentId) -> DocumentError {
+    DocumentError::Other(format!("Unknown document {:?}", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use crate::Layer;
+
+    fn solid(color: [u8; 4]) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba(color)))
+    }
+
+    #[test]
+    fn test_copy_layer_between_documents() {
+        let dir = std::env::temp_dir().join(format!("meridian_manager_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("source.json");
+        {
+            let mut source = Document::with_size(4, 4);
+            let asset = source.add_asset(solid([0, 0, 255, 255]));
+            source.add_asset_layer("Sky", asset);
+            source.save(&path).unwrap();
+        }
+
+        let mut manager = DocumentManager::new();
+        let source = manager.open(&path).unwrap();
+        let target = manager.create(4, 4);
+        assert_eq!(manager.active_id(), Some(target));
+        {
+            let doc = manager.get_mut(target).unwrap();
+            let id = LayerId::new();
+            doc.insert_layer_raw(0, id, Arc::new(RwLock::new(Layer::with_image("Ground", solid([0, 255, 0, 255])))));
+        }
+
+        let sky = manager.get(source).unwrap().layers().next().unwrap().clone();
+        manager.copy_layer(source, &sky).unwrap();
+        let pasted = manager.paste_layer(target).unwrap();
+
+        let target_doc = manager.get(target).unwrap();
+        assert_eq!(target_doc.layer_count(), 2);
+        assert_eq!(target_doc.get_layer(&pasted).unwrap().read().name(), "Sky");
+        assert_eq!(target_doc.assets().len(), 1);
+
+        let blue = Rgba([0, 0, 255, 255]);
+        assert_eq!(*manager.get(source).unwrap().render_composite().unwrap().to_rgba8().get_pixel(0, 0), blue);
+        assert_eq!(*target_doc.render_composite().unwrap().to_rgba8().get_pixel(0, 0), blue);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_close_refuses_modified_unless_forced() {
+        let mut manager = DocumentManager::new();
+        let first = manager.create(8, 8);
+        let second = manager.create(8, 8);
+        manager.get_mut(second).unwrap().add_layer();
+
+        assert!(manager.close(second, false).is_err());
+        assert_eq!(manager.len(), 2);
+        manager.close(second, true).unwrap();
+        assert_eq!(manager.active_id(), Some(first));
+
+        manager.close(first, false).unwrap();
+        assert!(manager.active_document().is_none());
+        assert!(manager.set_active(first).is_err());
+    }
+
+    #[test]
+    fn test_manager_events() {
+        let mut manager = DocumentManager::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        manager.subscribe(move |event| sink.lock().push(event.clone()));
+
+        let first = manager.create(1, 1);
+        let second = manager.create(1, 1);
+        manager.set_active(first).unwrap();
+        manager.close(first, false).unwrap();
+
+        assert_eq!(
+            *events.lock(),
+            vec![
+                ManagerEvent::Opened(first),
+                ManagerEvent::Activated(Some(first)),
+                ManagerEvent::Opened(second),
+                ManagerEvent::Activated(Some(second)),
+                ManagerEvent::Activated(Some(first)),
+                ManagerEvent::Closed(first),
+                ManagerEvent::Activated(Some(second)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Memory usage estimates for the performance panel, and trimming of
+//! regenerable caches.
+//!
+//! Figures are estimates: node data reports its own size through
+//! [`aurion_core::NodeData::estimated_memory`] and images count their pixel
+//! buffers only.
+
+use std::collections::HashSet;
+use serde::Serialize;
+use crate::{Document, LayerId};
+
+/// Memory attributed to one layer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LayerMemory {
+    pub id: LayerId,
+    pub name: String,
+    /// Node data of the layer's graph. Linked layers share a graph, which is
+    /// counted for the bottom one only.
+    pub node_bytes: usize,
+    /// The layer's cached render output.
+    pub cache_bytes: usize,
+    pub thumbnail_bytes: usize,
+}
+
+/// Memory held by the document's asset store.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct AssetMemory {
+    pub count: usize,
+    pub bytes: usize,
+    /// Graphs referencing an asset, counted once per asset and graph.
+    pub references: usize,
+    /// Memory that storing a copy per reference would take on top of `bytes`.
+    pub dedup_savings: usize,
+}
+
+/// Returned by [`Document::memory_report`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryReport {
+    /// Bottom layer first.
+    pub layers: Vec<LayerMemory>,
+    pub assets: AssetMemory,
+    /// Sum of the undo history's [`crate::Command::memory_hint`]s.
+    pub history_bytes: usize,
+    /// Everything above, with outputs shared between layers counted once.
+    pub total_bytes: usize,
+}
+
+impl MemoryReport {
+    pub fn node_bytes(&self) -> usize {
+        self.layers.iter().map(|layer| layer.node_bytes).sum()
+    }
+
+    pub fn thumbnail_bytes(&self) -> usize {
+        self.layers.iter().map(|layer| layer.thumbnail_bytes).sum()
+    }
+}
+
+impl Document {
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut graphs = HashSet::new();
+        let mut references = HashSet::new();
+        let mut layers = Vec::with_capacity(self.layer_order.len());
+        for id in &self.layer_order {
+            let Some(layer) = self.get_layer(id) else {
+                continue;
+            };
+            let layer = layer.read();
+            let node_bytes = if graphs.insert(layer.graph_key()) {
+                for asset in layer.referenced_assets() {
+                    references.insert((layer.graph_key(), asset));
+                }
+                layer.node_graph().estimated_memory()
+            } else {
+                0
+            };
+            layers.push(LayerMemory {
+                id: id.clone(),
+                name: layer.name().to_string(),
+                node_bytes,
+                cache_bytes: self.render_cache.output_bytes(id),
+                thumbnail_bytes: layer.thumbnail.bytes(),
+            });
+        }
+
+        let referenced_bytes: usize = references
+            .iter()
+            .filter_map(|(_, asset)| self.assets.get(asset))
+            .map(|image| image.as_bytes().len())
+            .sum();
+        let unique_bytes: usize = references
+            .iter()
+            .map(|(_, asset)| asset)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .filter_map(|asset| self.assets.get(asset))
+            .map(|image| image.as_bytes().len())
+            .sum();
+        let assets = AssetMemory {
+            count: self.assets.len(),
+            bytes: self.assets.total_bytes(),
+            references: references.len(),
+            dedup_savings: referenced_bytes - unique_bytes,
+        };
+
+        let history_bytes = self.history.memory_bytes();
+        let thumbnail_bytes: usize = layers.iter().map(|layer| layer.thumbnail_bytes).sum();
+        let node_bytes: usize = layers.iter().map(|layer| layer.node_bytes).sum();
+        let total_bytes = node_bytes
+            + self.render_cache.stats().bytes
+            + thumbnail_bytes
+            + assets.bytes
+            + history_bytes;
+        MemoryReport { layers, assets, history_bytes, total_bytes }
+    }
+
+    /// Drops regenerable caches until the report's total is at most
+    /// `target_bytes`, in this order:
+    ///
+    /// 1. thumbnails, which are small and cheap to re-render;
+    /// 2. cached outputs of hidden layers, which composites don't need;
+    /// 3. the remaining cached outputs, largest first.
+    ///
+    /// Node data, assets and history are never dropped, so the target may
+    /// not be reached. Returns the report after trimming.
+    pub fn trim_memory(&self, target_bytes: usize) -> MemoryReport {
+        let mut report = self.memory_report();
+        if report.total_bytes <= target_bytes {
+            return report;
+        }
+
+        for id in &self.layer_order {
+            if let Some(layer) = self.get_layer(id) {
+                layer.read().invalidate_thumbnail();
+            }
+        }
+        report = self.memory_report();
+
+        let hidden = |id: &LayerId| self.get_layer(id).map_or(true, |layer| !layer.read().is_visible());
+        let mut cached: Vec<(bool, usize, LayerId)> = report
+            .layers
+            .iter()
+            .filter(|layer| layer.cache_bytes > 0)
+            .map(|layer| (hidden(&layer.id), layer.cache_bytes, layer.id.clone()))
+            .collect();
+        // Hidden layers first, then largest first
+        cached.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+        for (_, _, id) in cached {
+            if report.total_bytes <= target_bytes {
+                break;
+            }
+            self.render_cache.remove(&id);
+            report = self.memory_report();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use parking_lot::RwLock;
+    use crate::Layer;
+
+    fn image_layer(doc: &mut Document, name: &str, size: u32) -> LayerId {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(size, size, Rgba([120, 60, 30, 255])));
+        let id = LayerId::new();
+        let index = doc.layer_order.len();
+        doc.insert_layer_raw(index, id.clone(), Arc::new(RwLock::new(Layer::with_image(name, image))));
+        id
+    }
+
+    fn layer<'a>(report: &'a MemoryReport, id: &LayerId) -> &'a LayerMemory {
+        report.layers.iter().find(|layer| layer.id == *id).unwrap()
+    }
+
+    #[test]
+    fn test_report_counts_large_image_node() {
+        let mut doc = Document::with_size(256, 256);
+        image_layer(&mut doc, "Small", 8);
+        let before = doc.memory_report();
+
+        let large = image_layer(&mut doc, "Large", 256);
+        let report = doc.memory_report();
+        assert!(layer(&report, &large).node_bytes >= 256 * 256 * 4);
+        assert!(report.total_bytes >= before.total_bytes + 256 * 256 * 4);
+        assert_eq!(layer(&report, &large).cache_bytes, 0);
+
+        doc.render_composite().unwrap();
+        doc.thumbnails(32);
+        let report = doc.memory_report();
+        assert_eq!(layer(&report, &large).cache_bytes, 256 * 256 * 4);
+        assert_eq!(layer(&report, &large).thumbnail_bytes, 32 * 32 * 4);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["total_bytes"], report.total_bytes);
+        assert_eq!(json["layers"][1]["name"], "Large");
+
+        // A linked layer adds no node data
+        let linked = doc.add_linked_layer(&large).unwrap();
+        assert_eq!(layer(&doc.memory_report(), &linked).node_bytes, 0);
+    }
+
+    #[test]
+    fn test_shared_assets_are_counted_once() {
+        let mut doc = Document::with_size(64, 64);
+        let asset = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::new(64, 64)));
+        doc.add_asset_layer("First", asset.clone());
+        doc.add_asset_layer("Second", asset);
+
+        let assets = doc.memory_report().assets;
+        assert_eq!((assets.count, assets.references), (1, 2));
+        assert_eq!(assets.bytes, 64 * 64 * 4);
+        assert_eq!(assets.dedup_savings, 64 * 64 * 4);
+    }
+
+    #[test]
+    fn test_trim_memory_drops_caches_in_order() {
+        let mut doc = Document::with_size(128, 128);
+        let visible = image_layer(&mut doc, "Visible", 128);
+        let hidden = image_layer(&mut doc, "Hidden", 64);
+        doc.render_composite().unwrap();
+        doc.get_layer(&hidden).unwrap().write().set_visible(false);
+        doc.thumbnails(16);
+        let full = doc.memory_report();
+        assert!(full.thumbnail_bytes() > 0);
+
+        // Dropping thumbnails is enough
+        let report = doc.trim_memory(full.total_bytes - 1);
+        assert_eq!(report.thumbnail_bytes(), 0);
+        assert_eq!(layer(&report, &hidden).cache_bytes, 64 * 64 * 4);
+
+        // Hidden layers go before visible ones
+        let report = doc.trim_memory(report.total_bytes - 1);
+        assert_eq!(layer(&report, &hidden).cache_bytes, 0);
+        assert_eq!(layer(&report, &visible).cache_bytes, 128 * 128 * 4);
+
+        let report = doc.trim_memory(0);
+        assert_eq!(layer(&report, &visible).cache_bytes, 0);
+        assert_eq!(report.total_bytes, report.node_bytes() + report.assets.bytes + report.history_bytes);
+    }
+}
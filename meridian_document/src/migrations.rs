@@ -0,0 +1,152 @@
+//! Upgrades serialized documents written by older versions.
+//!
+//! Each step takes the JSON of version `n` and returns version `n + 1`.
+//! [`migrate`] chains the steps from the file's version up to
+//! [`CURRENT_FORMAT_VERSION`].
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value};
+use crate::serialization::CURRENT_FORMAT_VERSION;
+
+type Migration = fn(Value) -> Result<Value>;
+
+/// Migrations indexed by the version they upgrade from.
+const MIGRATIONS: &[Migration] = &[v0_to_v1];
+
+/// Reads `format_version`, treating a missing field as version 0.
+pub fn detect_version(value: &Value) -> Result<u32> {
+    match value.get("format_version") {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| anyhow!("Invalid format_version: {}", version)),
+    }
+}
+
+pub fn migrate(mut value: Value, from: u32) -> Result<Value> {
+    if from > CURRENT_FORMAT_VERSION {
+        bail!(
+            "Document format version {} is newer than the supported version {}",
+            from,
+            CURRENT_FORMAT_VERSION
+        );
+    }
+
+    for version in from..CURRENT_FORMAT_VERSION {
+        let step = MIGRATIONS[version as usize];
+        value = step(value)
+            .map_err(|e| anyhow!("Migration from version {} failed: {}", version, e))?;
+    }
+    Ok(value)
+}
+
+/// Version 0 files have no canvas size and only placeholder layer
+/// properties, with blend modes written in lowercase.
+fn v0_to_v1(mut value: Value) -> Result<Value> {
+    let document = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Document is not a JSON object"))?;
+
+    document.entry("width").or_insert(json!(crate::DEFAULT_CANVAS_WIDTH));
+    document.entry("height").or_insert(json!(crate::DEFAULT_CANVAS_HEIGHT));
+
+    if let Some(layers) = document.get_mut("layers").and_then(Value::as_object_mut) {
+        for layer in layers.values_mut() {
+            let Some(layer) = layer.as_object_mut() else {
+                bail!("Layer entry is not a JSON object");
+            };
+            let blend_mode = layer
+                .get("blend_mode")
+                .and_then(Value::as_str)
+                .unwrap_or("normal");
+            let canonical = match blend_mode.to_ascii_lowercase().as_str() {
+                "multiply" => "Multiply",
+                "screen" => "Screen",
+                "overlay" => "Overlay",
+                _ => "Normal",
+            };
+            layer.insert("blend_mode".to_string(), json!(canonical));
+            layer.entry("name").or_insert(json!("Layer"));
+            layer.entry("visible").or_insert(json!(true));
+            layer.entry("opacity").or_insert(json!(1.0));
+        }
+    }
+
+    document.insert("format_version".to_string(), json!(1));
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlendMode, Document, DocumentError};
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    #[test]
+    fn test_detect_version() {
+        assert_eq!(detect_version(&json!({})).unwrap(), 0);
+        assert_eq!(detect_version(&json!({ "format_version": 1 })).unwrap(), 1);
+        assert!(detect_version(&json!({ "format_version": "one" })).is_err());
+    }
+
+    #[test]
+    fn test_migrate_rejects_newer_versions() {
+        let result = migrate(json!({}), CURRENT_FORMAT_VERSION + 1);
+        assert!(result.unwrap_err().to_string().contains("newer than the supported"));
+    }
+
+    #[test]
+    fn test_v0_to_v1() {
+        let v0 = json!({
+            "layers": { "7d2f1f0e-0c7a-4a53-9a4e-3f1b2c6d8e90": {
+                "name": "Layer", "visible": true, "opacity": 1.0, "blend_mode": "multiply"
+            }},
+            "layer_order": ["7d2f1f0e-0c7a-4a53-9a4e-3f1b2c6d8e90"]
+        });
+        let v1 = migrate(v0, 0).unwrap();
+        assert_eq!(v1["format_version"], 1);
+        assert_eq!(v1["width"], crate::DEFAULT_CANVAS_WIDTH);
+        assert_eq!(v1["layers"]["7d2f1f0e-0c7a-4a53-9a4e-3f1b2c6d8e90"]["blend_mode"], "Multiply");
+    }
+
+    #[test]
+    fn test_load_v0_fixture() {
+        let doc = Document::load(fixture("document_v0.json")).unwrap();
+        assert_eq!(doc.layer_count(), 2);
+        assert_eq!(doc.size(), (crate::DEFAULT_CANVAS_WIDTH, crate::DEFAULT_CANVAS_HEIGHT));
+        let top = doc.layers().last().unwrap().clone();
+        assert_eq!(doc.get_layer(&top).unwrap().read().blend_mode(), BlendMode::Screen);
+    }
+
+    #[test]
+    fn test_load_v1_fixture() {
+        let doc = Document::load(fixture("document_v1.json")).unwrap();
+        assert_eq!(doc.size(), (640, 480));
+        let names: Vec<String> = doc
+            .layers()
+            .map(|id| doc.get_layer(id).unwrap().read().name().to_string())
+            .collect();
+        assert_eq!(names, vec!["Background", "Glow"]);
+        let glow = doc.layers().last().unwrap().clone();
+        let glow = doc.get_layer(&glow).unwrap();
+        assert_eq!(glow.read().opacity(), 0.5);
+        assert!(!glow.read().is_visible());
+    }
+
+    #[test]
+    fn test_load_future_version_errors() {
+        let path = std::env::temp_dir().join(format!("meridian_future_{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, r#"{ "format_version": 999, "layers": {}, "layer_order": [] }"#).unwrap();
+        let result = Document::load(&path);
+        assert!(matches!(
+            result,
+            Err(DocumentError::UnsupportedVersion { found: 999, .. })
+        ));
+        let _ = std::fs::remove_file(path);
+    }
+}
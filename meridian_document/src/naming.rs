@@ -0,0 +1,182 @@
+//! Layer lookup by name and renaming with optional uniqueness.
+
+use std::error::Error;
+use std::sync::Arc;
+use parking_lot::{Mutex, RwLock};
+use crate::{Command, CommandRecord, Document, DocumentError, DocumentEvent, Layer, LayerId};
+
+/// How a requested layer name is treated when another layer already uses it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamePolicy {
+    AllowDuplicates,
+    /// Appends " (2)", " (3)", … until the name is unused.
+    MakeUnique,
+}
+
+/// Renames a layer, restoring the previous name on undo.
+#[derive(Debug)]
+pub struct RenameLayerCommand {
+    layer_id: LayerId,
+    name: String,
+    previous: Mutex<Option<String>>,
+}
+
+impl RenameLayerCommand {
+    pub fn new(layer_id: LayerId, name: String) -> Self {
+        Self {
+            layer_id,
+            name,
+            previous: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn restored(layer_id: LayerId, name: String, previous: Option<String>) -> Self {
+        Self {
+            layer_id,
+            name,
+            previous: Mutex::new(previous),
+        }
+    }
+
+    fn set_name(document: &mut Document, id: &LayerId, name: String) -> Result<String, Box<dyn Error>> {
+        let previous = document.with_layer_mut(id, |layer| std::mem::replace(&mut layer.name, name))?;
+        document.events.emit(DocumentEvent::LayerPropertyChanged(id.clone()));
+        Ok(previous)
+    }
+}
+
+impl Command for RenameLayerCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let previous = Self::set_name(document, &self.layer_id, self.name.clone())?;
+        *self.previous.lock() = Some(previous);
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        if let Some(previous) = self.previous.lock().take() {
+            Self::set_name(document, &self.layer_id, previous)?;
+        }
+        Ok(())
+    }
+
+    fn to_record(&self) -> Option<CommandRecord> {
+        Some(CommandRecord::RenameLayer {
+            layer_id: self.layer_id.clone(),
+            name: self.name.clone(),
+            previous: self.previous.lock().clone(),
+        })
+    }
+}
+
+impl Document {
+    /// Layers named exactly `name`, bottom first.
+    pub fn find_layer_by_name(&self, name: &str) -> Vec<LayerId> {
+        self.layer_order
+            .iter()
+            .filter(|id| {
+                self.get_layer(id)
+                    .map_or(false, |layer| layer.read().name() == name)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns `name`, or the first `"name (n)"` no layer other than `except` uses.
+    pub fn unique_layer_name(&self, name: &str, except: Option<&LayerId>) -> String {
+        let taken = |candidate: &str| {
+            self.find_layer_by_name(candidate)
+                .iter()
+                .any(|id| Some(id) != except)
+        };
+        if !taken(name) {
+            return name.to_string();
+        }
+        (2..)
+            .map(|n| format!("{} ({})", name, n))
+            .find(|candidate| !taken(candidate))
+            .expect("unbounded search finds a free name")
+    }
+
+    /// Adds a top layer with the given name.
+    pub fn add_layer_named(&mut self, name: &str, policy: NamePolicy) -> LayerId {
+        let name = match policy {
+            NamePolicy::AllowDuplicates => name.to_string(),
+            NamePolicy::MakeUnique => self.unique_layer_name(name, None),
+        };
+        let mut layer = Layer::new();
+        layer.set_name(name);
+
+        let id = LayerId::new();
+        self.layers.insert(id.clone(), Arc::new(RwLock::new(layer)));
+        self.layer_order.push(id.clone());
+        self.mark_modified();
+        id
+    }
+
+    /// Renames a layer through the history and returns the name it was given.
+    pub fn rename_layer(&mut self, id: &LayerId, name: &str, policy: NamePolicy) -> Result<String, DocumentError> {
+        let name = match policy {
+            NamePolicy::AllowDuplicates => name.to_string(),
+            NamePolicy::MakeUnique => self.unique_layer_name(name, Some(id)),
+        };
+        if self.with_layer(id, |layer| layer.name() == name)? {
+            return Ok(name);
+        }
+        self.execute_command(Box::new(RenameLayerCommand::new(id.clone(), name.clone())))?;
+        Ok(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name_of(doc: &Document, id: &LayerId) -> String {
+        doc.get_layer(id).unwrap().read().name().to_string()
+    }
+
+    #[test]
+    fn test_add_layer_named_makes_unique() {
+        let mut doc = Document::new();
+        let ids: Vec<_> = (0..3).map(|_| doc.add_layer_named("Layer", NamePolicy::MakeUnique)).collect();
+        let names: Vec<_> = ids.iter().map(|id| name_of(&doc, id)).collect();
+        assert_eq!(names, vec!["Layer", "Layer (2)", "Layer (3)"]);
+        assert_eq!(doc.find_layer_by_name("Layer (2)"), vec![ids[1].clone()]);
+    }
+
+    #[test]
+    fn test_rename_layer_policies() {
+        let mut doc = Document::new();
+        let first = doc.add_layer_named("Sky", NamePolicy::AllowDuplicates);
+        let second = doc.add_layer_named("Ground", NamePolicy::AllowDuplicates);
+
+        assert_eq!(doc.rename_layer(&second, "Sky", NamePolicy::MakeUnique).unwrap(), "Sky (2)");
+        // Keeping a layer's own name does not count as a clash
+        assert_eq!(doc.rename_layer(&first, "Sky", NamePolicy::MakeUnique).unwrap(), "Sky");
+        doc.rename_layer(&second, "Sky", NamePolicy::AllowDuplicates).unwrap();
+        assert_eq!(doc.find_layer_by_name("Sky"), vec![first, second.clone()]);
+
+        doc.undo().unwrap();
+        assert_eq!(name_of(&doc, &second), "Sky (2)");
+        doc.undo().unwrap();
+        assert_eq!(name_of(&doc, &second), "Ground");
+        assert!(!doc.can_undo());
+    }
+
+    #[test]
+    fn test_rename_emits_property_changed() {
+        let mut doc = Document::new();
+        let id = doc.add_layer();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        doc.subscribe(move |event| {
+            if let DocumentEvent::LayerPropertyChanged(_) = event {
+                sink.lock().push(event.clone());
+            }
+        });
+
+        doc.rename_layer(&id, "Ink", NamePolicy::AllowDuplicates).unwrap();
+        doc.undo().unwrap();
+        assert_eq!(events.lock().len(), 2);
+    }
+}
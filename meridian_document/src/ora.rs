@@ -0,0 +1,363 @@
+//! OpenRaster (`.ora`) export and import for exchanging layered images with
+//! Krita, GIMP and MyPaint.
+//!
+//! An OpenRaster file is a ZIP archive holding:
+//! - `mimetype`, stored uncompressed as the first entry,
+//! - `stack.xml`, the layer stack top first, with each layer's name,
+//!   offset, opacity, visibility and composite op,
+//! - `data/<id>.png` for each layer,
+//! - `mergedimage.png` and `Thumbnails/thumbnail.png` with the composite.
+//!
+//! Layers are written as their rendered, placed output, so graphs and
+//! transforms are baked into pixels.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, Write};
+use std::path::Path;
+use std::sync::Arc;
+use image::{DynamicImage, ImageOutputFormat, RgbaImage};
+use parking_lot::RwLock;
+use tracing::warn;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use aurion_std_nodes::resample::box_downsample;
+use crate::autosave::write_atomic;
+use crate::{BlendMode, Document, DocumentError, Layer, LayerId, LayerTransform};
+
+pub const ORA_EXTENSION: &str = "ora";
+const MIMETYPE: &str = "image/openraster";
+const STACK_ENTRY: &str = "stack.xml";
+const MERGED_ENTRY: &str = "mergedimage.png";
+const THUMBNAIL_ENTRY: &str = "Thumbnails/thumbnail.png";
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// The OpenRaster composite op for a blend mode.
+fn composite_op(mode: BlendMode) -> &'static str {
+    match mode {
+        BlendMode::Normal => "svg:src-over",
+        BlendMode::Multiply => "svg:multiply",
+        BlendMode::Screen => "svg:screen",
+        BlendMode::Overlay => "svg:overlay",
+    }
+}
+
+/// The blend mode for an OpenRaster composite op. Ops without a matching
+/// mode (e.g. `svg:color-dodge`) fall back to [`BlendMode::Normal`].
+fn blend_mode(op: &str) -> BlendMode {
+    BlendMode::all()
+        .iter()
+        .copied()
+        .find(|mode| composite_op(*mode) == op)
+        .unwrap_or_else(|| {
+            warn!("Unsupported OpenRaster composite op '{}', using Normal", op);
+            BlendMode::Normal
+        })
+}
+
+fn ora_error(path: &Path, message: impl std::fmt::Display) -> DocumentError {
+    DocumentError::Other(format!("OpenRaster {}: {}", path.display(), message))
+}
+
+fn encode_png(image: &DynamicImage) -> anyhow::Result<Vec<u8>> {
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, ImageOutputFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Attributes of every `<name ...>` element in `xml`, in document order.
+/// stack.xml keeps everything in attributes, so this is all the parsing
+/// import needs.
+fn elements(xml: &str, name: &str) -> Vec<HashMap<String, String>> {
+    let open = format!("<{}", name);
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        if !rest.starts_with(|c: char| c.is_whitespace() || c == '/' || c == '>') {
+            continue;
+        }
+        let end = rest.find('>').unwrap_or(rest.len());
+        found.push(attributes(&rest[..end]));
+        rest = &rest[end..];
+    }
+    found
+}
+
+fn attributes(mut tag: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    while let Some(eq) = tag.find('=') {
+        let key = tag[..eq].trim().to_string();
+        let value = tag[eq + 1..].trim_start();
+        let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            break;
+        };
+        let Some(len) = value[1..].find(quote) else {
+            break;
+        };
+        attributes.insert(key, unescape_xml(&value[1..1 + len]));
+        tag = &value[len + 2..];
+    }
+    attributes
+}
+
+impl Document {
+    /// Writes the document as an OpenRaster file.
+    pub fn export_ora<P: AsRef<Path>>(&self, path: P) -> Result<(), DocumentError> {
+        let path = path.as_ref();
+        let bytes = self.ora_bytes().map_err(|e| ora_error(path, e))?;
+        write_atomic(path, &bytes).map_err(|e| ora_error(path, e))
+    }
+
+    fn ora_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        zip.start_file("mimetype", stored)?;
+        zip.write_all(MIMETYPE.as_bytes())?;
+
+        let context = self.eval_context();
+        let mut stack = String::new();
+        for id in self.layer_order.iter().rev() {
+            let Some(layer) = self.get_layer(id) else {
+                continue;
+            };
+            let layer = layer.read();
+            let placed = self
+                .layer_output(id, &layer, &context)?
+                .and_then(|output| layer.transform().place(&output, self.canvas_rect()));
+            // Keep empty layers, with a single transparent pixel
+            let (image, x, y) = placed.unwrap_or_else(|| (DynamicImage::ImageRgba8(RgbaImage::new(1, 1)), 0, 0));
+
+            let src = format!("data/{}.png", id.0);
+            zip.start_file(src.as_str(), stored)?;
+            zip.write_all(&encode_png(&image)?)?;
+            stack.push_str(&format!(
+                "    <layer name=\"{}\" src=\"{}\" x=\"{}\" y=\"{}\" opacity=\"{}\" visibility=\"{}\" composite-op=\"{}\"/>\n",
+                escape_xml(layer.name()),
+                src,
+                x,
+                y,
+                layer.opacity(),
+                if layer.is_visible() { "visible" } else { "hidden" },
+                composite_op(layer.blend_mode()),
+            ));
+        }
+
+        zip.start_file(STACK_ENTRY, deflated)?;
+        write!(
+            zip,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<image version=\"0.0.3\" w=\"{}\" h=\"{}\">\n  <stack>\n{}  </stack>\n</image>\n",
+            self.width, self.height, stack
+        )?;
+
+        let composite = self.render_composite()?;
+        zip.start_file(MERGED_ENTRY, stored)?;
+        self.write_png(&mut zip, &composite.to_rgba8())?;
+        zip.start_file(THUMBNAIL_ENTRY, stored)?;
+        zip.write_all(&encode_png(&box_downsample(&composite, THUMBNAIL_SIZE))?)?;
+
+        Ok(zip.finish()?.into_inner())
+    }
+
+    /// Reads an OpenRaster file into a new document with one image layer
+    /// per OpenRaster layer. Nested stacks are flattened into the layer
+    /// list, and composite ops without a matching [`BlendMode`] become
+    /// [`BlendMode::Normal`].
+    pub fn import_ora<P: AsRef<Path>>(path: P) -> Result<Self, DocumentError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| ora_error(path, e))?;
+        Self::from_ora_reader(file).map_err(|e| ora_error(path, e))
+    }
+
+    fn from_ora_reader<R: Read + Seek>(reader: R) -> anyhow::Result<Self> {
+        let mut archive = ZipArchive::new(reader)?;
+        let mut xml = String::new();
+        archive.by_name(STACK_ENTRY)?.read_to_string(&mut xml)?;
+
+        let image = elements(&xml, "image")
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("{} has no image element", STACK_ENTRY))?;
+        let dimension = |key: &str| -> anyhow::Result<u32> {
+            let value = image.get(key).ok_or_else(|| anyhow::anyhow!("image element has no '{}'", key))?;
+            Ok(value.parse()?)
+        };
+        let mut document = Document::with_size(dimension("w")?, dimension("h")?);
+
+        // stack.xml lists layers top first
+        for attributes in elements(&xml, "layer").into_iter().rev() {
+            let src = attributes
+                .get("src")
+                .ok_or_else(|| anyhow::anyhow!("layer element has no 'src'"))?;
+            let mut png = Vec::new();
+            archive.by_name(src)?.read_to_end(&mut png)?;
+
+            let name = attributes.get("name").cloned().unwrap_or_else(|| "Layer".to_string());
+            let mut layer = Layer::with_image(name, image::load_from_memory(&png)?);
+            let number = |key: &str, default: f32| -> anyhow::Result<f32> {
+                attributes.get(key).map_or(Ok(default), |value| Ok(value.parse()?))
+            };
+            layer.set_transform(LayerTransform::translation(number("x", 0.0)?, number("y", 0.0)?));
+            layer.set_opacity(number("opacity", 1.0)?);
+            layer.set_visible(attributes.get("visibility").map_or(true, |value| value != "hidden"));
+            layer.set_blend_mode(attributes.get("composite-op").map_or(BlendMode::Normal, |op| blend_mode(op)));
+
+            let index = document.layer_count();
+            document.insert_layer_raw(index, LayerId::new(), Arc::new(RwLock::new(layer)));
+        }
+        Ok(document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use image::Rgba;
+    use uuid::Uuid;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("meridian_ora_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn gradient(width: u32, height: u32, alpha: u8) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x * 20) as u8, (y * 30) as u8, 128, alpha])
+        }))
+    }
+
+    fn layered_document() -> Document {
+        let mut doc = Document::with_size(12, 8);
+        let layers = [
+            Layer::with_image("Base", gradient(12, 8, 255)),
+            {
+                let mut layer = Layer::with_image("Shade <&\"'>", gradient(6, 6, 200));
+                layer.set_blend_mode(BlendMode::Multiply);
+                layer.set_opacity(0.5);
+                layer.set_transform(LayerTransform::translation(3.0, 2.0));
+                layer
+            },
+            {
+                let mut layer = Layer::with_image("Hidden", gradient(4, 4, 255));
+                layer.set_visible(false);
+                layer.set_blend_mode(BlendMode::Screen);
+                layer
+            },
+            {
+                let mut layer = Layer::with_image("Overhang", gradient(10, 3, 180));
+                layer.set_blend_mode(BlendMode::Overlay);
+                layer.set_transform(LayerTransform::translation(-4.0, 6.0));
+                layer
+            },
+        ];
+        for (index, layer) in layers.into_iter().enumerate() {
+            doc.insert_layer_raw(index, LayerId::new(), Arc::new(RwLock::new(layer)));
+        }
+        doc
+    }
+
+    fn properties(doc: &Document) -> Vec<(String, f32, bool, BlendMode)> {
+        doc.layers()
+            .map(|id| {
+                let layer = doc.get_layer(id).unwrap();
+                let layer = layer.read();
+                (layer.name().to_string(), layer.opacity(), layer.is_visible(), layer.blend_mode())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip_preserves_layers_and_composite() {
+        let dir = temp_dir();
+        let path = dir.join("layers.ora");
+        let doc = layered_document();
+        doc.export_ora(&path).unwrap();
+
+        let imported = Document::import_ora(&path).unwrap();
+        assert_eq!(imported.size(), doc.size());
+        assert_eq!(properties(&imported), properties(&doc));
+        assert_eq!(imported.render_composite().unwrap().to_rgba8(), doc.render_composite().unwrap().to_rgba8());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_fixture_layout_and_merged_image() {
+        let dir = temp_dir();
+        let path = dir.join("fixture.ora");
+        let doc = layered_document();
+        doc.export_ora(&path).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        {
+            let mut mimetype = archive.by_index(0).unwrap();
+            assert_eq!(mimetype.name(), "mimetype");
+            assert_eq!(mimetype.compression(), CompressionMethod::Stored);
+            let mut contents = String::new();
+            mimetype.read_to_string(&mut contents).unwrap();
+            assert_eq!(contents, MIMETYPE);
+        }
+
+        let mut xml = String::new();
+        archive.by_name(STACK_ENTRY).unwrap().read_to_string(&mut xml).unwrap();
+        let layers = elements(&xml, "layer");
+        let ops: Vec<_> = layers.iter().map(|layer| layer["composite-op"].as_str()).collect();
+        assert_eq!(ops, vec!["svg:overlay", "svg:screen", "svg:multiply", "svg:src-over"]);
+        assert_eq!(layers[1]["visibility"], "hidden");
+        assert_eq!(layers[2]["name"], "Shade <&\"'>");
+        // Placed output is clipped to the canvas
+        assert_eq!((layers[0]["x"].as_str(), layers[0]["y"].as_str()), ("0", "6"));
+
+        let mut merged = Vec::new();
+        archive.by_name(MERGED_ENTRY).unwrap().read_to_end(&mut merged).unwrap();
+        let merged = image::load_from_memory(&merged).unwrap().to_rgba8();
+        let imported = Document::import_ora(&path).unwrap().render_composite().unwrap().to_rgba8();
+        assert_eq!(merged, imported);
+        assert!(archive.by_name(THUMBNAIL_ENTRY).is_ok());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_unknown_composite_op_falls_back_to_normal() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("mimetype", FileOptions::default()).unwrap();
+        zip.write_all(MIMETYPE.as_bytes()).unwrap();
+        zip.start_file("data/a.png", FileOptions::default()).unwrap();
+        zip.write_all(&encode_png(&gradient(2, 2, 255)).unwrap()).unwrap();
+        zip.start_file(STACK_ENTRY, FileOptions::default()).unwrap();
+        zip.write_all(
+            b"<image w='4' h='4'><stack><layer src='data/a.png' composite-op='svg:color-dodge' opacity='0.25'/></stack></image>",
+        )
+        .unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+
+        let doc = Document::from_ora_reader(Cursor::new(bytes)).unwrap();
+        assert_eq!(properties(&doc), vec![("Layer".to_string(), 0.25, true, BlendMode::Normal)]);
+    }
+}
@@ -0,0 +1,331 @@
+//! The packaged `.artm` container.
+//!
+//! A package is a ZIP archive holding:
+//! - `manifest.json` with the container format version,
+//! - `document.json`, the serialized document with layer pixels replaced by
+//!   asset references,
+//! - `assets/<hash>.png` for every distinct image, named by content hash,
+//! - optionally `history.json`, the undo history (see [`crate::history_file`]).
+//!
+//! Storing pixels as PNG instead of base64 RGBA keeps packages small, and
+//! identical images are only written once.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use crate::autosave::write_atomic;
+use crate::history_file::SerializedHistory;
+use crate::migrations;
+use crate::serialization::{ImageSource, SerializedDocument, CURRENT_FORMAT_VERSION};
+use crate::{Document, DocumentError, LoadProgress};
+
+pub const PACKAGE_EXTENSION: &str = "artm";
+pub(crate) const ZIP_MAGIC: &[u8; 4] = b"PK\x03\x04";
+const MANIFEST_ENTRY: &str = "manifest.json";
+const DOCUMENT_ENTRY: &str = "document.json";
+const ASSETS_DIR: &str = "assets";
+const HISTORY_ENTRY: &str = "history.json";
+
+/// Options for [`Document::save_package_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveOptions {
+    /// Saves the undo history so it can be used after reloading.
+    pub include_history: bool,
+    /// Most history entries to save, undo steps first.
+    pub max_entries: usize,
+}
+
+impl Default for SaveOptions {
+    fn default() -> Self {
+        Self {
+            include_history: false,
+            max_entries: 100,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PackageManifest {
+    format_version: u32,
+    generator: String,
+}
+
+/// Hex SHA-256 over an image's dimensions and RGBA8 pixels.
+pub fn content_hash(image: &DynamicImage) -> String {
+    let rgba = image.to_rgba8();
+    let mut hasher = Sha256::new();
+    hasher.update(rgba.width().to_le_bytes());
+    hasher.update(rgba.height().to_le_bytes());
+    hasher.update(rgba.as_raw());
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn asset_entry(hash: &str) -> String {
+    format!("{}/{}.png", ASSETS_DIR, hash)
+}
+
+fn package_error(path: &Path, message: impl std::fmt::Display) -> DocumentError {
+    DocumentError::Other(format!("Package {}: {}", path.display(), message))
+}
+
+impl Document {
+    /// Whether `path` looks like a packaged document, judged by its magic bytes.
+    pub fn is_package<P: AsRef<Path>>(path: P) -> bool {
+        let mut magic = [0u8; 4];
+        File::open(path.as_ref())
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .map(|_| &magic == ZIP_MAGIC)
+            .unwrap_or(false)
+    }
+
+    pub fn save_package<P: AsRef<Path>>(&mut self, path: P) -> Result<(), DocumentError> {
+        self.save_package_with(path, &SaveOptions::default())
+    }
+
+    pub fn save_package_with<P: AsRef<Path>>(&mut self, path: P, options: &SaveOptions) -> Result<(), DocumentError> {
+        let path = path.as_ref();
+        let bytes = self.package_bytes(options).map_err(|e| package_error(path, e))?;
+        write_atomic(path, &bytes).map_err(|e| package_error(path, e))?;
+        self.finish_save(path);
+        Ok(())
+    }
+
+    pub(crate) fn package_bytes(&self, options: &SaveOptions) -> anyhow::Result<Vec<u8>> {
+        self.package_contents(options)?.encode(&mut |_, _| Ok(()))
+    }
+
+    /// Serializes the document and collects the images to embed. Encoding
+    /// them is left to [`PackageContents::encode`], which may run on another
+    /// thread.
+    pub(crate) fn package_contents(&self, options: &SaveOptions) -> anyhow::Result<PackageContents> {
+        let mut assets: HashMap<String, DynamicImage> = HashMap::new();
+        let serialized = self.serialize_with(|image| {
+            let hash = content_hash(image);
+            assets.entry(hash.clone()).or_insert_with(|| image.clone());
+            Ok(ImageSource::Asset { hash })
+        })?;
+        let mut assets: Vec<_> = assets.into_iter().collect();
+        assets.sort_by(|a, b| a.0.cmp(&b.0));
+        let history = options
+            .include_history
+            .then(|| SerializedHistory::record(&self.history, options.max_entries));
+        Ok(PackageContents { serialized, assets, history })
+    }
+
+    pub fn load_package<P: AsRef<Path>>(path: P) -> Result<Self, DocumentError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| package_error(path, e))?;
+        let mut document = Self::from_package_reader(file, &mut |_| Ok(()))
+            .map_err(|e| load_error(path, e))?;
+        document.path = Some(path.to_path_buf());
+        Ok(document)
+    }
+
+    pub(crate) fn from_package_reader<R: Read + std::io::Seek>(
+        reader: R,
+        progress: &mut dyn FnMut(LoadProgress) -> Result<(), DocumentError>,
+    ) -> anyhow::Result<Self> {
+        let mut archive = ZipArchive::new(reader)?;
+
+        let manifest: PackageManifest = serde_json::from_reader(archive.by_name(MANIFEST_ENTRY)?)?;
+        if manifest.format_version > CURRENT_FORMAT_VERSION {
+            return Err(DocumentError::UnsupportedVersion {
+                found: manifest.format_version,
+                supported: CURRENT_FORMAT_VERSION,
+            }
+            .into());
+        }
+
+        let value: serde_json::Value = serde_json::from_reader(archive.by_name(DOCUMENT_ENTRY)?)?;
+        let version = migrations::detect_version(&value)?;
+        let value = migrations::migrate(value, version)?;
+        let serialized: SerializedDocument = serde_json::from_value(value)?;
+
+        let mut decoded: HashMap<String, DynamicImage> = HashMap::new();
+        let mut document = Self::deserialize_with(serialized, |source| match source {
+            ImageSource::Asset { hash } => {
+                if let Some(image) = decoded.get(hash) {
+                    return Ok(image.clone());
+                }
+                let mut bytes = Vec::new();
+                archive.by_name(&asset_entry(hash))?.read_to_end(&mut bytes)?;
+                let image = image::load_from_memory(&bytes)?;
+                decoded.insert(hash.clone(), image.clone());
+                Ok(image)
+            }
+            inline => inline.decode_inline(),
+        }, progress)?;
+
+        let history = match archive.by_name(HISTORY_ENTRY) {
+            Ok(entry) => Some(serde_json::from_reader::<_, SerializedHistory>(entry)?),
+            Err(zip::result::ZipError::FileNotFound) => None,
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(history) = history {
+            document.restore_history(history)?;
+        }
+        Ok(document)
+    }
+}
+
+/// A serialized document and the images it references, ready to be zipped.
+pub(crate) struct PackageContents {
+    serialized: SerializedDocument,
+    assets: Vec<(String, DynamicImage)>,
+    history: Option<SerializedHistory>,
+}
+
+impl PackageContents {
+    /// Builds the archive, calling `on_asset` with the number of assets
+    /// encoded so far and the total. An error from `on_asset` aborts.
+    pub fn encode(
+        &self,
+        on_asset: &mut dyn FnMut(usize, usize) -> Result<(), DocumentError>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        // PNG data is already compressed
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+
+        let manifest = PackageManifest {
+            format_version: CURRENT_FORMAT_VERSION,
+            generator: format!("artemisia {}", env!("CARGO_PKG_VERSION")),
+        };
+        zip.start_file(MANIFEST_ENTRY, deflated)?;
+        zip.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+
+        zip.start_file(DOCUMENT_ENTRY, deflated)?;
+        zip.write_all(&serde_json::to_vec(&self.serialized)?)?;
+
+        if let Some(history) = &self.history {
+            zip.start_file(HISTORY_ENTRY, deflated)?;
+            zip.write_all(&serde_json::to_vec(history)?)?;
+        }
+
+        on_asset(0, self.assets.len())?;
+        for (index, (hash, image)) in self.assets.iter().enumerate() {
+            // The image crate's default PNG compression favours speed
+            let mut png = Vec::new();
+            image.write_with_encoder(PngEncoder::new_with_quality(&mut png, CompressionType::Default, FilterType::Adaptive))?;
+            zip.start_file(asset_entry(hash), stored)?;
+            zip.write_all(&png)?;
+            on_asset(index + 1, self.assets.len())?;
+        }
+
+        Ok(zip.finish()?.into_inner())
+    }
+}
+
+/// Maps a package load failure to a [`DocumentError`], keeping errors that
+/// already are one (e.g. an unsupported version or a cancellation).
+pub(crate) fn load_error(path: &Path, error: anyhow::Error) -> DocumentError {
+    match error.downcast::<DocumentError>() {
+        Ok(document_error) => document_error,
+        Err(e) => package_error(path, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialization::layer_image;
+    use crate::{Layer, LayerId};
+    use image::{Rgba, RgbaImage};
+    use parking_lot::RwLock;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("meridian_package_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8, 255])
+        }))
+    }
+
+    fn image_document(image: DynamicImage) -> (Document, LayerId) {
+        let mut doc = Document::with_size(image.width(), image.height());
+        let id = LayerId::new();
+        doc.insert_layer_raw(0, id.clone(), Arc::new(RwLock::new(Layer::with_image("Photo", image))));
+        (doc, id)
+    }
+
+    #[test]
+    fn test_package_round_trip_is_smaller_than_json() {
+        let dir = temp_dir();
+        let image = gradient(2048, 1536);
+        let (mut doc, id) = image_document(image.clone());
+
+        let json_path = dir.join("photo.json");
+        let package_path = dir.join("photo.artm");
+        doc.save(&json_path).unwrap();
+        doc.save_package(&package_path).unwrap();
+
+        let json_size = std::fs::metadata(&json_path).unwrap().len();
+        let package_size = std::fs::metadata(&package_path).unwrap().len();
+        assert!(package_size * 10 < json_size, "package {} vs json {}", package_size, json_size);
+
+        let loaded = Document::load_package(&package_path).unwrap();
+        assert_eq!(loaded.size(), (2048, 1536));
+        let layer = loaded.get_layer(&id).unwrap();
+        assert_eq!(layer.read().name(), "Photo");
+        assert_eq!(layer_image(&layer.read()).unwrap().to_rgba8(), image.to_rgba8());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_load_detects_package() {
+        let dir = temp_dir();
+        let (mut doc, id) = image_document(gradient(8, 8));
+        let package_path = dir.join("detect.artm");
+        doc.save_package(&package_path).unwrap();
+        assert!(Document::is_package(&package_path));
+
+        let loaded = Document::load(&package_path).unwrap();
+        assert!(loaded.get_layer(&id).is_some());
+        assert_eq!(loaded.path(), Some(package_path.as_path()));
+
+        let json_path = dir.join("detect.json");
+        doc.save(&json_path).unwrap();
+        assert!(!Document::is_package(&json_path));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_identical_images_stored_once() {
+        let image = gradient(16, 16);
+        let (mut doc, _) = image_document(image.clone());
+        doc.insert_layer_raw(1, LayerId::new(), Arc::new(RwLock::new(Layer::with_image("Copy", image))));
+
+        let bytes = doc.package_bytes(&SaveOptions::default()).unwrap();
+        let archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let assets = archive.file_names().filter(|name| name.starts_with(ASSETS_DIR)).count();
+        assert_eq!(assets, 1);
+    }
+
+    #[test]
+    fn test_save_package_clears_modified() {
+        let dir = temp_dir();
+        let mut doc = Document::new();
+        doc.add_layer();
+        doc.save_package(dir.join("clean.artm")).unwrap();
+        assert!(!doc.is_modified());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
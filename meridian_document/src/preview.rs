@@ -0,0 +1,198 @@
+//! Small previews of each node's output, kept from the renders the document
+//! does anyway so showing them never evaluates anything.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use aurion_core::{NodeGraph, NodeId, NodeValue, OutputObserver};
+use aurion_std_nodes::resample::box_downsample;
+use image::DynamicImage;
+use parking_lot::Mutex;
+use crate::{Document, LayerId};
+
+/// The longest side of an image preview.
+pub const PREVIEW_SIZE: u32 = 96;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreviewContent {
+    /// The output downsampled to fit [`PREVIEW_SIZE`].
+    Image(DynamicImage),
+    /// A one-line summary of a non-image output, e.g. "0.500".
+    Text(String),
+}
+
+/// A node's output as of the render that last evaluated it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodePreview {
+    /// The node's [`NodeGraph::node_hash`] when it was evaluated.
+    pub hash: u64,
+    pub content: PreviewContent,
+}
+
+/// Sums up a node's output for its preview, or `None` for values it can't
+/// show.
+pub fn summarize(output: &NodeValue) -> Option<PreviewContent> {
+    let text = match output {
+        NodeValue::Image(image) => return Some(PreviewContent::Image(box_downsample(image, PREVIEW_SIZE))),
+        NodeValue::Float(value) => format!("{:.3}", value),
+        NodeValue::Int(value) => value.to_string(),
+        NodeValue::Bool(value) => value.to_string(),
+        NodeValue::String(value) => value.clone(),
+        NodeValue::Vector(values) => format!("{} values", values.len()),
+        NodeValue::Null => return None,
+        NodeValue::Custom(_) => {
+            // Histograms: where the peak is
+            let bins = output.downcast_ref::<Vec<u32>>()?;
+            let (peak, count) = bins.iter().enumerate().max_by_key(|(i, count)| (**count, std::cmp::Reverse(*i)))?;
+            format!("Peak at {} ({})", peak, count)
+        }
+    };
+    Some(PreviewContent::Text(text))
+}
+
+/// Previews by node, each kept until the node's hash changes.
+#[derive(Default)]
+pub(crate) struct PreviewCache {
+    enabled: AtomicBool,
+    entries: Mutex<HashMap<NodeId, NodePreview>>,
+}
+
+impl PreviewCache {
+    /// An observer storing the previews of every output it sees. Outputs
+    /// whose hash already has a preview aren't downsampled again.
+    pub fn observer(self: &Arc<Self>) -> OutputObserver {
+        let cache = self.clone();
+        OutputObserver::new(move |graph, node, output| cache.store(graph, node, output))
+    }
+
+    fn store(&self, graph: &NodeGraph, node: &NodeId, output: &NodeValue) {
+        let Some(hash) = graph.node_hash(node) else {
+            return;
+        };
+        if self.entries.lock().get(node).map_or(false, |preview| preview.hash == hash) {
+            return;
+        }
+        if let Some(content) = summarize(output) {
+            self.entries.lock().insert(node.clone(), NodePreview { hash, content });
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+}
+
+impl std::fmt::Debug for PreviewCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreviewCache")
+            .field("enabled", &self.is_enabled())
+            .field("entries", &self.entries.lock().len())
+            .finish()
+    }
+}
+
+impl Document {
+    /// Starts or stops keeping node previews. Enabling drops the render
+    /// cache and invalidates the canvas, so the next render evaluates every
+    /// layer and fills them in.
+    pub fn set_previews_enabled(&mut self, enabled: bool) {
+        if self.previews.enabled.swap(enabled, Ordering::Relaxed) == enabled {
+            return;
+        }
+        if enabled {
+            self.clear_render_cache();
+            self.invalidate_region(self.canvas_rect());
+        } else {
+            self.previews.entries.lock().clear();
+        }
+    }
+
+    pub fn previews_enabled(&self) -> bool {
+        self.previews.is_enabled()
+    }
+
+    /// The preview of `node` in `layer`'s graph, if a render evaluated it
+    /// since it last changed. Never evaluates anything itself.
+    pub fn node_preview(&self, layer: &LayerId, node: &NodeId) -> Option<NodePreview> {
+        let hash = self.get_layer(layer)?.read().node_graph().node_hash(node)?;
+        self.previews.entries.lock().get(node).filter(|preview| preview.hash == hash).cloned()
+    }
+
+    /// Drops previews of nodes no layer has any more.
+    pub(crate) fn retain_previews(&self) {
+        if !self.previews_enabled() {
+            return;
+        }
+        let layers: Vec<_> = self.layers.values().cloned().collect();
+        self.previews.entries.lock().retain(|node, _| {
+            layers.iter().any(|layer| layer.read().node_graph().get_node(node).is_some())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aurion_core::Node;
+    use aurion_std_nodes::filters::BlurNode;
+    use aurion_std_nodes::ImageNode;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_summaries() {
+        let image = NodeValue::image(DynamicImage::ImageRgba8(RgbaImage::new(400, 200)));
+        match summarize(&image) {
+            Some(PreviewContent::Image(preview)) => assert_eq!((preview.width(), preview.height()), (96, 48)),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(summarize(&NodeValue::Float(0.5)), Some(PreviewContent::Text("0.500".to_string())));
+        let histogram = NodeValue::custom(vec![3u32, 9, 9, 1]);
+        assert_eq!(summarize(&histogram), Some(PreviewContent::Text("Peak at 1 (9)".to_string())));
+        assert_eq!(summarize(&NodeValue::custom(Vec::<u32>::new())), None);
+        assert_eq!(summarize(&NodeValue::custom('x')), None);
+        assert_eq!(summarize(&NodeValue::Null), None);
+    }
+
+    #[test]
+    fn test_previews_follow_renders() {
+        let mut doc = Document::with_size(8, 8);
+        let layer = doc.add_layer();
+        let (source, blur) = {
+            let handle = doc.get_layer(&layer).unwrap();
+            let mut handle = handle.write();
+            let mut graph = handle.node_graph_mut();
+            let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([200, 0, 0, 255])));
+            let source = graph.add_node(Node::new(Box::new(ImageNode::with_image(image))));
+            let blur = graph.add_node(Node::new(Box::new(BlurNode::new(1.0))));
+            graph.connect(&source, &blur, "input").unwrap();
+            (source, blur)
+        };
+        doc.render_composite().unwrap();
+        // Off by default, and enabling doesn't evaluate anything by itself
+        assert_eq!(doc.node_preview(&layer, &blur), None);
+        doc.set_previews_enabled(true);
+        assert_eq!(doc.node_preview(&layer, &blur), None);
+
+        doc.render_composite().unwrap();
+        let before = doc.node_preview(&layer, &blur).unwrap();
+        let source_preview = doc.node_preview(&layer, &source).unwrap();
+        assert!(matches!(before.content, PreviewContent::Image(_)));
+
+        // A changed sigma makes the blur's preview stale until the next
+        // render, which leaves its unchanged input alone
+        {
+            let handle = doc.get_layer(&layer).unwrap();
+            let handle = handle.read();
+            let node = handle.node_graph().get_node(&blur).unwrap();
+            node.write().data_mut().set_parameter("sigma", 3.0.into()).unwrap();
+        }
+        assert_eq!(doc.node_preview(&layer, &blur), None);
+        doc.render_composite().unwrap();
+        let after = doc.node_preview(&layer, &blur).unwrap();
+        assert_ne!(after.hash, before.hash);
+        assert_eq!(doc.node_preview(&layer, &source), Some(source_preview));
+
+        doc.set_previews_enabled(false);
+        assert_eq!(doc.node_preview(&layer, &blur), None);
+    }
+}
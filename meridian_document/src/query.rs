@@ -0,0 +1,170 @@
+//! Finding layers by their properties, e.g. for filtering the layers panel.
+
+use aurion_core::NodeGraph;
+use crate::{BlendMode, Document, Layer, LayerId};
+
+/// What a layer is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerKind {
+    /// A layer producing its own content.
+    Normal,
+    /// A layer modifying the layers below it.
+    Adjustment,
+    /// A layer containing other layers.
+    Group,
+    /// A layer showing another document, see [`crate::smart`].
+    SmartObject,
+}
+
+impl Layer {
+    /// Adjustment and group layers are not supported yet.
+    pub fn kind(&self) -> LayerKind {
+        if self.is_smart_object() {
+            LayerKind::SmartObject
+        } else {
+            LayerKind::Normal
+        }
+    }
+}
+
+/// Criteria for [`Document::query_layers`]. A layer matches when it meets
+/// every criterion set; an empty filter matches all layers.
+#[derive(Debug, Clone, Default)]
+pub struct LayerFilter {
+    name: Option<String>,
+    visible: Option<bool>,
+    blend_mode: Option<BlendMode>,
+    locked: Option<bool>,
+    kind: Option<LayerKind>,
+    node_type: Option<String>,
+}
+
+impl LayerFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Layers whose name contains `text`, ignoring case.
+    pub fn name_contains(mut self, text: impl Into<String>) -> Self {
+        self.name = Some(text.into().to_lowercase());
+        self
+    }
+
+    pub fn visible(mut self, visible: bool) -> Self {
+        self.visible = Some(visible);
+        self
+    }
+
+    pub fn blend_mode(mut self, mode: BlendMode) -> Self {
+        self.blend_mode = Some(mode);
+        self
+    }
+
+    pub fn locked(mut self, locked: bool) -> Self {
+        self.locked = Some(locked);
+        self
+    }
+
+    pub fn kind(mut self, kind: LayerKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Layers whose graph has a node of the given type, e.g. `"BlurNode"`.
+    pub fn contains_node(mut self, type_name: impl Into<String>) -> Self {
+        self.node_type = Some(type_name.into());
+        self
+    }
+
+    pub fn matches(&self, layer: &Layer) -> bool {
+        self.name.as_ref().is_none_or(|name| layer.name().to_lowercase().contains(name))
+            && self.visible.is_none_or(|visible| layer.is_visible() == visible)
+            && self.blend_mode.is_none_or(|mode| layer.blend_mode() == mode)
+            && self.locked.is_none_or(|locked| layer.is_locked() == locked)
+            && self.kind.is_none_or(|kind| layer.kind() == kind)
+            && self
+                .node_type
+                .as_ref()
+                .is_none_or(|type_name| has_node_type(&layer.node_graph(), type_name))
+    }
+}
+
+fn has_node_type(graph: &NodeGraph, type_name: &str) -> bool {
+    graph.get_node_ids().iter().any(|id| {
+        graph
+            .get_node(id)
+            .is_some_and(|node| node.read().data().type_name() == type_name)
+    })
+}
+
+impl Document {
+    /// Layers matching `filter`, bottom first.
+    pub fn query_layers(&self, filter: LayerFilter) -> Vec<LayerId> {
+        self.layer_order
+            .iter()
+            .filter(|id| {
+                self.get_layer(id)
+                    .is_some_and(|layer| filter.matches(&layer.read()))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aurion_core::Node;
+    use aurion_std_nodes::filters::BlurNode;
+    use std::sync::Arc;
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use parking_lot::RwLock;
+
+    fn document() -> (Document, Vec<LayerId>) {
+        let mut doc = Document::with_size(16, 16);
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba([90, 90, 90, 255])));
+        let mut ids = Vec::new();
+        for (index, name) in ["Background", "Sky glow", "Shadows", "sky detail"].into_iter().enumerate() {
+            let id = LayerId::new();
+            doc.insert_layer_raw(index, id.clone(), Arc::new(RwLock::new(Layer::with_image(name, image.clone()))));
+            ids.push(id);
+        }
+
+        doc.get_layer(&ids[1]).unwrap().write().set_blend_mode(BlendMode::Screen);
+        doc.get_layer(&ids[2]).unwrap().write().set_visible(false);
+        doc.get_layer(&ids[0]).unwrap().write().set_locked(true);
+        {
+            let layer = doc.get_layer(&ids[3]).unwrap();
+            let mut layer = layer.write();
+            let mut graph = layer.node_graph_mut();
+            let source = graph.get_node_ids()[0].clone();
+            let blur = graph.add_node(Node::new(Box::new(BlurNode::new(2.0))));
+            graph.connect(&source, &blur, "input").unwrap();
+        }
+        (doc, ids)
+    }
+
+    #[test]
+    fn test_query_layers() {
+        let (doc, ids) = document();
+
+        assert_eq!(doc.query_layers(LayerFilter::new()), ids);
+        assert_eq!(doc.query_layers(LayerFilter::new().name_contains("SKY")), vec![ids[1].clone(), ids[3].clone()]);
+        assert_eq!(doc.query_layers(LayerFilter::new().visible(false)), vec![ids[2].clone()]);
+        assert_eq!(doc.query_layers(LayerFilter::new().locked(true)), vec![ids[0].clone()]);
+        assert_eq!(doc.query_layers(LayerFilter::new().blend_mode(BlendMode::Screen)), vec![ids[1].clone()]);
+        assert_eq!(doc.query_layers(LayerFilter::new().kind(LayerKind::Group)), Vec::<LayerId>::new());
+        assert_eq!(doc.query_layers(LayerFilter::new().contains_node("BlurNode")), vec![ids[3].clone()]);
+        assert_eq!(doc.query_layers(LayerFilter::new().contains_node("ImageNode")).len(), 4);
+    }
+
+    #[test]
+    fn test_criteria_combine() {
+        let (doc, ids) = document();
+
+        let sky = LayerFilter::new().name_contains("sky").visible(true);
+        assert_eq!(doc.query_layers(sky.clone().contains_node("BlurNode")), vec![ids[3].clone()]);
+        assert_eq!(doc.query_layers(sky.blend_mode(BlendMode::Normal).kind(LayerKind::Normal)), vec![ids[3].clone()]);
+        assert!(doc.query_layers(LayerFilter::new().locked(true).visible(false)).is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+//! Dirty-region tracking so viewports can repaint only what changed.
+
+use crate::{CanvasRect, Document, DocumentError, DocumentEvent, LayerId};
+
+impl Document {
+    /// The whole canvas as a rect at the origin.
+    pub fn canvas_rect(&self) -> CanvasRect {
+        CanvasRect::new(0, 0, self.width, self.height)
+    }
+
+    /// Emits [`DocumentEvent::RegionInvalidated`] for the part of `rect`
+    /// inside the canvas. Nothing is emitted if they don't overlap.
+    pub fn invalidate_region(&self, rect: CanvasRect) {
+        if let Some(rect) = rect.intersect(&self.canvas_rect()) {
+            self.events.emit(DocumentEvent::RegionInvalidated(rect));
+        }
+    }
+
+    /// Canvas area a layer covers, based on its last rendered output.
+    /// Layers that haven't been rendered yet are assumed to cover the canvas.
+    pub fn layer_bounds(&self, id: &LayerId) -> Result<CanvasRect, DocumentError> {
+        let bounds = self.with_layer(id, |layer| self.placed_bounds(id, layer))?;
+        Ok(bounds.unwrap_or_else(|| self.canvas_rect()))
+    }
+
+    /// Reports an edit confined to `rect` of a layer's output, e.g. a brush
+    /// stroke. Like [`Document::notify_graph_changed`], but only the canvas
+    /// area `rect` maps to is invalidated.
+    pub fn notify_layer_region_changed(&mut self, id: &LayerId, rect: CanvasRect) -> Result<(), DocumentError> {
+        let dirty = self.with_layer(id, |layer| {
+            layer.invalidate_thumbnail();
+            match self.render_cache.output_size(id) {
+                Some(Some((width, height))) => layer.transform().map_rect(rect, width, height),
+                _ => self.canvas_rect(),
+            }
+        })?;
+        self.events.emit(DocumentEvent::GraphChanged(id.clone()));
+        self.invalidate_region(dirty);
+        self.mark_modified();
+        Ok(())
+    }
+
+    /// Invalidates the area a layer left and the area it now covers.
+    pub(crate) fn invalidate_moved_layer(&self, before: Option<CanvasRect>, after: Option<CanvasRect>) {
+        match (before, after) {
+            (Some(before), Some(after)) => self.invalidate_region(before.union(&after)),
+            _ => self.invalidate_region(self.canvas_rect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::Any;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use aurion_core::{Node, NodeData, NodeError, NodeValue};
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use parking_lot::{Mutex, RwLock};
+    use crate::{BlendMode, Layer, LayerTransform};
+
+    #[derive(Debug, Clone)]
+    struct CountingImageNode {
+        color: [u8; 4],
+        computes: Arc<AtomicUsize>,
+    }
+
+    impl NodeData for CountingImageNode {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn type_name(&self) -> &'static str {
+            "CountingImageNode"
+        }
+
+        fn clone_box(&self) -> Box<dyn NodeData> {
+            Box::new(self.clone())
+        }
+
+        fn content_hash(&self) -> Option<u64> {
+            Some(u32::from_le_bytes(self.color) as u64)
+        }
+
+        fn compute(&self, _inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+            self.computes.fetch_add(1, Ordering::SeqCst);
+            Ok(NodeValue::image(DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba(self.color)))))
+        }
+    }
+
+    fn counting_layer(doc: &mut Document, color: [u8; 4]) -> (LayerId, Arc<AtomicUsize>) {
+        let computes = Arc::new(AtomicUsize::new(0));
+        let id = doc.add_layer();
+        doc.get_layer(&id).unwrap().write().node_graph_mut().add_node(Node::new(Box::new(
+            CountingImageNode { color, computes: computes.clone() },
+        )));
+        (id, computes)
+    }
+
+    fn crop(image: &DynamicImage, rect: CanvasRect) -> RgbaImage {
+        image.crop_imm(rect.x as u32, rect.y as u32, rect.width, rect.height).to_rgba8()
+    }
+
+    #[test]
+    fn test_region_matches_composite_crop() {
+        let mut doc = Document::with_size(32, 24);
+        let gradient = RgbaImage::from_fn(32, 24, |x, y| Rgba([(x * 8) as u8, (y * 10) as u8, 90, 255]));
+        doc.insert_layer_raw(0, LayerId::new(), Arc::new(RwLock::new(Layer::with_image("Base", DynamicImage::ImageRgba8(gradient)))));
+
+        let mut moved = Layer::with_image("Moved", DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 6, Rgba([255, 0, 0, 200]))));
+        moved.set_transform(LayerTransform::translation(-3.0, 15.0));
+        doc.insert_layer_raw(1, LayerId::new(), Arc::new(RwLock::new(moved)));
+
+        let stripes = RgbaImage::from_fn(12, 4, |x, _| Rgba([0, (x * 20) as u8, 255, 255]));
+        let mut turned = Layer::with_image("Turned", DynamicImage::ImageRgba8(stripes));
+        turned.set_transform(LayerTransform { x: 14.0, y: 2.0, rotation_degrees: 90.0, ..LayerTransform::IDENTITY });
+        turned.set_blend_mode(BlendMode::Multiply);
+        doc.insert_layer_raw(2, LayerId::new(), Arc::new(RwLock::new(turned)));
+
+        let composite = doc.render_composite().unwrap();
+        for rect in [CanvasRect::new(5, 3, 17, 11), CanvasRect::new(0, 14, 9, 10), CanvasRect::new(31, 23, 1, 1)] {
+            let region = doc.render_region(rect).unwrap().to_rgba8();
+            assert_eq!(region, crop(&composite, rect), "region {:?}", rect);
+        }
+
+        // Outside the canvas is transparent
+        let region = doc.render_region(CanvasRect::new(-2, -2, 6, 6)).unwrap().to_rgba8();
+        assert_eq!(region.get_pixel(1, 1)[3], 0);
+        assert_eq!(region.get_pixel(2, 2), composite.to_rgba8().get_pixel(0, 0));
+    }
+
+    #[test]
+    fn test_only_changed_layers_reevaluate() {
+        let mut doc = Document::with_size(8, 8);
+        let (_, bottom) = counting_layer(&mut doc, [0, 0, 255, 255]);
+        let (top, top_computes) = counting_layer(&mut doc, [255, 0, 0, 128]);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        doc.subscribe(move |event| {
+            if let DocumentEvent::RegionInvalidated(rect) = event {
+                sink.lock().push(*rect);
+            }
+        });
+
+        let rect = CanvasRect::new(2, 2, 4, 4);
+        doc.render_region(rect).unwrap();
+        doc.render_region(rect).unwrap();
+        assert_eq!(bottom.load(Ordering::SeqCst), 1);
+        assert_eq!(top_computes.load(Ordering::SeqCst), 1);
+
+        doc.set_layer_transform(&top, LayerTransform::translation(1.0, 0.0)).unwrap();
+        {
+            let layer = doc.get_layer(&top).unwrap();
+            let layer = layer.read();
+            let graph = layer.node_graph();
+            let node = graph.get_node(&graph.get_node_ids()[0]).unwrap();
+            let mut node = node.write();
+            node.data_mut().as_any_mut().downcast_mut::<CountingImageNode>().unwrap().color = [0, 255, 0, 128];
+        }
+        doc.notify_layer_region_changed(&top, CanvasRect::new(0, 0, 2, 2)).unwrap();
+        doc.render_region(rect).unwrap();
+        assert_eq!(bottom.load(Ordering::SeqCst), 1);
+        assert_eq!(top_computes.load(Ordering::SeqCst), 2);
+
+        // The move dirties old and new bounds, clipped to the canvas; the
+        // stroke maps through the layer's transform
+        assert_eq!(*events.lock(), vec![CanvasRect::new(0, 0, 8, 8), CanvasRect::new(1, 0, 2, 2)]);
+    }
+}
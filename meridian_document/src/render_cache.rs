@@ -0,0 +1,288 @@
+//! Evaluated layer outputs reused across renders until a layer's graph changes.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use aurion_core::EvalContext;
+use image::DynamicImage;
+use parking_lot::Mutex;
+use crate::{Document, DocumentError, Layer, LayerId};
+
+/// Counters reported by [`Document::render_cache_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Pixel memory held by cached outputs.
+    pub bytes: usize,
+}
+
+struct CacheEntry {
+    // `None` for graphs without a content hash, which never hit
+    key: Option<u64>,
+    output: Option<Arc<DynamicImage>>,
+}
+
+/// Layer outputs keyed by [`aurion_core::NodeGraph::content_hash`].
+///
+/// Outputs are cached before placement, so moving or scaling a layer never
+/// re-evaluates it.
+#[derive(Default)]
+pub(crate) struct RenderCache {
+    entries: Mutex<HashMap<LayerId, CacheEntry>>,
+    stats: Mutex<(u64, u64)>,
+}
+
+impl RenderCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lookup(&self, id: &LayerId, key: Option<u64>) -> Option<Option<Arc<DynamicImage>>> {
+        let entries = self.entries.lock();
+        let entry = entries.get(id).filter(|entry| key.is_some() && entry.key == key)?;
+        Some(entry.output.clone())
+    }
+
+    /// An output cached for any layer under `key`. Equal content hashes give
+    /// equal outputs, so this serves layers sharing a graph.
+    fn lookup_shared(&self, key: Option<u64>) -> Option<Option<Arc<DynamicImage>>> {
+        key?;
+        let entries = self.entries.lock();
+        let entry = entries.values().find(|entry| entry.key == key)?;
+        Some(entry.output.clone())
+    }
+
+    /// Size of a layer's last evaluated output: `None` if nothing is cached,
+    /// `Some(None)` if its graph yields no image.
+    pub fn output_size(&self, id: &LayerId) -> Option<Option<(u32, u32)>> {
+        self.entries
+            .lock()
+            .get(id)
+            .map(|entry| entry.output.as_ref().map(|image| (image.width(), image.height())))
+    }
+
+    /// Pixel memory of a layer's cached output, 0 if nothing is cached.
+    pub fn output_bytes(&self, id: &LayerId) -> usize {
+        self.entries
+            .lock()
+            .get(id)
+            .and_then(|entry| entry.output.as_ref())
+            .map_or(0, |image| image.as_bytes().len())
+    }
+
+    pub fn remove(&self, id: &LayerId) {
+        self.entries.lock().remove(id);
+    }
+
+    /// Drops entries of layers for which `keep` returns false.
+    pub fn retain<F: FnMut(&LayerId) -> bool>(&self, mut keep: F) {
+        self.entries.lock().retain(|id, _| keep(id));
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().clear();
+        *self.stats.lock() = (0, 0);
+    }
+
+    pub fn stats(&self) -> RenderCacheStats {
+        let (hits, misses) = *self.stats.lock();
+        // Layers sharing a graph share their output
+        let mut counted = HashSet::new();
+        let bytes = self
+            .entries
+            .lock()
+            .values()
+            .filter_map(|entry| entry.output.as_ref())
+            .filter(|image| counted.insert(Arc::as_ptr(image)))
+            .map(|image| image.as_bytes().len())
+            .sum();
+        RenderCacheStats { hits, misses, bytes }
+    }
+}
+
+impl std::fmt::Debug for RenderCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RenderCache").field("stats", &self.stats()).finish()
+    }
+}
+
+impl Document {
+    /// Evaluates a layer's graph, reusing the cached output while its content
+    /// hash is unchanged, or another layer's output for the same content.
+    pub(crate) fn layer_output(
+        &self,
+        id: &LayerId,
+        layer: &Layer,
+        context: &EvalContext,
+    ) -> Result<Option<Arc<DynamicImage>>, DocumentError> {
+        let key = layer.node_graph().content_hash();
+        if let Some(output) = self.render_cache.lookup(id, key) {
+            self.render_cache.stats.lock().0 += 1;
+            return Ok(output);
+        }
+
+        let output = match self.render_cache.lookup_shared(key) {
+            Some(output) => {
+                self.render_cache.stats.lock().0 += 1;
+                output
+            }
+            None => {
+                self.render_cache.stats.lock().1 += 1;
+                layer.render_output_with(context)?.map(Arc::new)
+            }
+        };
+        self.render_cache
+            .entries
+            .lock()
+            .insert(id.clone(), CacheEntry { key, output: output.clone() });
+        Ok(output)
+    }
+
+    pub fn render_cache_stats(&self) -> RenderCacheStats {
+        self.render_cache.stats()
+    }
+
+    /// Drops all cached layer outputs and resets the counters.
+    pub fn clear_render_cache(&self) {
+        self.render_cache.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::Any;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use aurion_core::{Node, NodeData, NodeError, NodeValue};
+    use image::{Rgba, RgbaImage};
+
+    #[derive(Debug, Clone)]
+    struct CountingImageNode {
+        color: [u8; 4],
+        computes: Arc<AtomicUsize>,
+    }
+
+    impl NodeData for CountingImageNode {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn type_name(&self) -> &'static str {
+            "CountingImageNode"
+        }
+
+        fn clone_box(&self) -> Box<dyn NodeData> {
+            Box::new(self.clone())
+        }
+
+        fn content_hash(&self) -> Option<u64> {
+            Some(u32::from_le_bytes(self.color) as u64)
+        }
+
+        fn compute(&self, _inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+            self.computes.fetch_add(1, Ordering::SeqCst);
+            Ok(NodeValue::image(DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba(self.color)))))
+        }
+    }
+
+    fn counting_layer(doc: &mut Document, color: [u8; 4]) -> (LayerId, Arc<AtomicUsize>) {
+        let computes = Arc::new(AtomicUsize::new(0));
+        let id = doc.add_layer();
+        doc.get_layer(&id).unwrap().write().node_graph_mut().add_node(Node::new(Box::new(
+            CountingImageNode { color, computes: computes.clone() },
+        )));
+        (id, computes)
+    }
+
+    fn computes(counters: &[Arc<AtomicUsize>]) -> Vec<usize> {
+        counters.iter().map(|c| c.load(Ordering::SeqCst)).collect()
+    }
+
+    #[test]
+    fn test_editing_one_layer_reevaluates_only_it() {
+        let mut doc = Document::with_size(4, 4);
+        let (_, first) = counting_layer(&mut doc, [255, 0, 0, 255]);
+        let (middle, second) = counting_layer(&mut doc, [0, 255, 0, 128]);
+        let (_, third) = counting_layer(&mut doc, [0, 0, 255, 64]);
+        let counters = [first, second, third];
+
+        doc.render_composite().unwrap();
+        doc.render_composite().unwrap();
+        assert_eq!(computes(&counters), vec![1, 1, 1]);
+        let stats = doc.render_cache_stats();
+        assert_eq!((stats.hits, stats.misses), (3, 3));
+        assert_eq!(stats.bytes, 3 * 4 * 4 * 4);
+
+        // Edit layer 2's parameter in place through a node handle
+        {
+            let layer = doc.get_layer(&middle).unwrap();
+            let layer = layer.read();
+            let graph = layer.node_graph();
+            let node = graph.get_node(&graph.get_node_ids()[0]).unwrap();
+            let mut node = node.write();
+            node.data_mut().as_any_mut().downcast_mut::<CountingImageNode>().unwrap().color = [9, 9, 9, 255];
+        }
+        let composite = doc.render_composite().unwrap().to_rgba8();
+        assert_eq!(computes(&counters), vec![1, 2, 1]);
+        assert_eq!(composite.get_pixel(0, 0)[0], composite.get_pixel(0, 0)[1]);
+
+        doc.clear_render_cache();
+        assert_eq!(doc.render_cache_stats(), RenderCacheStats::default());
+        doc.render_composite().unwrap();
+        assert_eq!(computes(&counters), vec![2, 3, 2]);
+    }
+
+    #[test]
+    fn test_unhashable_graphs_always_miss() {
+        #[derive(Debug, Clone)]
+        struct Opaque(Arc<AtomicUsize>);
+
+        impl NodeData for Opaque {
+            fn as_any(&self) -> &dyn Any {
+                self
+            }
+
+            fn as_any_mut(&mut self) -> &mut dyn Any {
+                self
+            }
+
+            fn type_name(&self) -> &'static str {
+                "Opaque"
+            }
+
+            fn clone_box(&self) -> Box<dyn NodeData> {
+                Box::new(self.clone())
+            }
+
+            fn compute(&self, _inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(NodeValue::image(DynamicImage::ImageRgba8(RgbaImage::new(2, 2))))
+            }
+        }
+
+        let mut doc = Document::with_size(2, 2);
+        let computes = Arc::new(AtomicUsize::new(0));
+        let id = doc.add_layer();
+        doc.get_layer(&id).unwrap().write().node_graph_mut().add_node(Node::new(Box::new(Opaque(computes.clone()))));
+
+        doc.render_composite().unwrap();
+        doc.render_composite().unwrap();
+        assert_eq!(computes.load(Ordering::SeqCst), 2);
+        assert_eq!(doc.render_cache_stats().hits, 0);
+    }
+
+    #[test]
+    fn test_removed_layers_are_evicted() {
+        let mut doc = Document::with_size(4, 4);
+        let (id, _) = counting_layer(&mut doc, [1, 2, 3, 255]);
+        doc.render_composite().unwrap();
+        assert!(doc.render_cache_stats().bytes > 0);
+        doc.remove_layer(&id).unwrap();
+        doc.render_composite().unwrap();
+        assert_eq!(doc.render_cache_stats().bytes, 0);
+    }
+}
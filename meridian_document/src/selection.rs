@@ -0,0 +1,220 @@
+//! The document's active selection and its rasterization to masks.
+
+use aurion_core::{Node, NodeError, NodeGraph, NodeId};
+use aurion_std_nodes::mask::ApplyMaskNode;
+use aurion_std_nodes::ImageNode;
+use image::{DynamicImage, GrayImage, Luma};
+use serde::{Deserialize, Serialize};
+use crate::{Document, DocumentEvent};
+
+/// A region of the canvas, in canvas pixels.
+///
+/// Shapes cover the pixels whose centers lie inside them. Masks carry soft
+/// coverage, which boolean combinations preserve.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Selection {
+    Rect { x: f32, y: f32, width: f32, height: f32 },
+    /// The ellipse inscribed in the given bounds.
+    Ellipse { x: f32, y: f32, width: f32, height: f32 },
+    Mask(#[serde(with = "mask_serde")] GrayImage),
+    Union(Box<Selection>, Box<Selection>),
+    Intersect(Box<Selection>, Box<Selection>),
+    Subtract(Box<Selection>, Box<Selection>),
+}
+
+impl Selection {
+    pub fn rect(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Selection::Rect { x, y, width, height }
+    }
+
+    pub fn ellipse(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Selection::Ellipse { x, y, width, height }
+    }
+
+    pub fn union(self, other: Selection) -> Self {
+        Selection::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersect(self, other: Selection) -> Self {
+        Selection::Intersect(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtract(self, other: Selection) -> Self {
+        Selection::Subtract(Box::new(self), Box::new(other))
+    }
+
+    /// Coverage of pixel `(x, y)` from 0 (unselected) to 255 (fully selected).
+    pub fn coverage(&self, x: u32, y: u32) -> u8 {
+        let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+        let inside = |inside: bool| -> u8 { if inside { 255 } else { 0 } };
+        match self {
+            Selection::Rect { x, y, width, height } => {
+                inside(cx >= *x && cx < x + width && cy >= *y && cy < y + height)
+            }
+            Selection::Ellipse { x, y, width, height } => {
+                if *width <= 0.0 || *height <= 0.0 {
+                    return 0;
+                }
+                let dx = (cx - (x + width / 2.0)) / (width / 2.0);
+                let dy = (cy - (y + height / 2.0)) / (height / 2.0);
+                inside(dx * dx + dy * dy <= 1.0)
+            }
+            Selection::Mask(mask) => mask.get_pixel_checked(x, y).map_or(0, |p| p[0]),
+            Selection::Union(a, b) => a.coverage(x, y).max(b.coverage(x, y)),
+            Selection::Intersect(a, b) => a.coverage(x, y).min(b.coverage(x, y)),
+            Selection::Subtract(a, b) => a.coverage(x, y).min(255 - b.coverage(x, y)),
+        }
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        self.coverage(x, y) > 0
+    }
+
+    pub fn to_mask(&self, width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| Luma([self.coverage(x, y)]))
+    }
+}
+
+mod mask_serde {
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+    use image::GrayImage;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct EncodedMask {
+        width: u32,
+        height: u32,
+        luma_base64: String,
+    }
+
+    pub fn serialize<S: Serializer>(mask: &GrayImage, serializer: S) -> Result<S::Ok, S::Error> {
+        EncodedMask {
+            width: mask.width(),
+            height: mask.height(),
+            luma_base64: BASE64.encode(mask.as_raw()),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<GrayImage, D::Error> {
+        let encoded = EncodedMask::deserialize(deserializer)?;
+        let bytes = BASE64.decode(&encoded.luma_base64).map_err(de::Error::custom)?;
+        GrayImage::from_raw(encoded.width, encoded.height, bytes)
+            .ok_or_else(|| de::Error::custom("mask data does not match its size"))
+    }
+}
+
+impl Document {
+    pub fn selection(&self) -> Option<&Selection> {
+        self.selection.as_ref()
+    }
+
+    pub fn set_selection(&mut self, selection: Selection) {
+        self.selection = Some(selection);
+        self.events.emit(DocumentEvent::SelectionChanged);
+        self.mark_modified();
+    }
+
+    pub fn clear_selection(&mut self) {
+        if self.selection.take().is_some() {
+            self.events.emit(DocumentEvent::SelectionChanged);
+            self.mark_modified();
+        }
+    }
+
+    /// The selection rasterized at the document's size, if there is one.
+    pub fn selection_mask(&self) -> Option<GrayImage> {
+        self.selection
+            .as_ref()
+            .map(|selection| selection.to_mask(self.width, self.height))
+    }
+
+    /// Limits a filter to the selection by routing `base` and its filtered
+    /// version `effect` through an [`ApplyMaskNode`] in `graph`.
+    ///
+    /// Returns the node whose output is the restricted result, which is
+    /// `effect` itself when nothing is selected.
+    pub fn restrict_to_selection(
+        &self,
+        graph: &mut NodeGraph,
+        base: &NodeId,
+        effect: &NodeId,
+    ) -> Result<NodeId, NodeError> {
+        let Some(mask) = self.selection_mask() else {
+            return Ok(effect.clone());
+        };
+
+        let mask_node = graph.add_node(Node::new(Box::new(ImageNode::with_image(DynamicImage::ImageLuma8(mask)))));
+        let apply = graph.add_node(Node::new(Box::new(ApplyMaskNode::new())));
+        graph.connect(base, &apply, ApplyMaskNode::BASE_INPUT)?;
+        graph.connect(effect, &apply, ApplyMaskNode::EFFECT_INPUT)?;
+        graph.connect(&mask_node, &apply, ApplyMaskNode::MASK_INPUT)?;
+        Ok(apply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aurion_std_nodes::filters::InvertNode;
+    use image::{Rgba, RgbaImage};
+
+    fn rect_minus_ellipse() -> Selection {
+        Selection::rect(0.0, 0.0, 20.0, 20.0).subtract(Selection::ellipse(5.0, 5.0, 10.0, 10.0))
+    }
+
+    #[test]
+    fn test_subtract_rasterizes() {
+        let mask = rect_minus_ellipse().to_mask(30, 30);
+        // Rect corners survive, the ellipse center is cut out
+        assert_eq!(mask.get_pixel(0, 0)[0], 255);
+        assert_eq!(mask.get_pixel(19, 19)[0], 255);
+        assert_eq!(mask.get_pixel(10, 10)[0], 0);
+        assert_eq!(mask.get_pixel(5, 10)[0], 0);
+        // Inside the ellipse's bounds but outside the ellipse itself
+        assert_eq!(mask.get_pixel(5, 5)[0], 255);
+        // Outside the rect entirely
+        assert_eq!(mask.get_pixel(25, 5)[0], 0);
+        assert!(!rect_minus_ellipse().contains(10, 10));
+    }
+
+    #[test]
+    fn test_union_and_intersect() {
+        let a = Selection::rect(0.0, 0.0, 4.0, 4.0);
+        let b = Selection::rect(2.0, 2.0, 4.0, 4.0);
+        assert!(a.clone().union(b.clone()).contains(5, 5));
+        assert!(!a.clone().intersect(b.clone()).contains(1, 1));
+        assert!(a.intersect(b).contains(3, 3));
+    }
+
+    #[test]
+    fn test_selection_serializes_with_document() {
+        let mut doc = Document::with_size(8, 8);
+        let mut mask = GrayImage::new(8, 8);
+        mask.put_pixel(3, 4, Luma([200]));
+        let selection = rect_minus_ellipse().union(Selection::Mask(mask));
+        doc.set_selection(selection.clone());
+
+        let loaded = Document::deserialize(doc.serialize().unwrap()).unwrap();
+        assert_eq!(loaded.selection(), Some(&selection));
+    }
+
+    #[test]
+    fn test_restrict_filter_to_selection() {
+        let mut doc = Document::with_size(4, 1);
+        doc.set_selection(Selection::rect(2.0, 0.0, 2.0, 1.0));
+
+        let mut graph = NodeGraph::new();
+        let image = RgbaImage::from_pixel(4, 1, Rgba([0, 0, 0, 255]));
+        let base = graph.add_node(Node::new(Box::new(ImageNode::with_image(DynamicImage::ImageRgba8(image)))));
+        let effect = graph.add_node(Node::new(Box::new(InvertNode::new())));
+        graph.connect(&base, &effect, "input").unwrap();
+
+        let output = doc.restrict_to_selection(&mut graph, &base, &effect).unwrap();
+        let result = graph.evaluate(&output).unwrap();
+        let result = result.as_image().unwrap().to_rgba8();
+        assert_eq!(*result.get_pixel(1, 0), Rgba([0, 0, 0, 255]));
+        assert_eq!(*result.get_pixel(2, 0), Rgba([255, 255, 255, 255]));
+    }
+}
@@ -1,57 +1,437 @@
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
-use crate::{Document, Layer, LayerId};
-use std::collections::HashMap;
+use aurion_core::node_factory::NODE_REGISTRY;
+use aurion_core::{NodeGraph, NodeId, SerializedGraph};
+use crate::{Document, DocumentError, Layer, LayerId};
+use crate::async_io::{LoadPhase, LoadProgress};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 use std::sync::Arc;
 use parking_lot::RwLock;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use image::{DynamicImage, RgbaImage};
+use aurion_std_nodes::ImageNode;
+use aurion_std_nodes::assets::{AssetId, AssetRefNode};
+use crate::blend::BlendMode;
+use crate::color::ColorProfile;
+use crate::compositing::{register_document_nodes, CompositingGraph, SerializedCompositing};
+use crate::selection::Selection;
+use crate::smart::SmartContent;
+use crate::transform::LayerTransform;
+
+/// Version written by [`Document::serialize`]. Older files are upgraded by
+/// [`crate::migrations::migrate`] on load.
+pub const CURRENT_FORMAT_VERSION: u32 = 1;
 
 #[derive(Serialize, Deserialize)]
 pub struct SerializedDocument {
+    format_version: u32,
+    width: u32,
+    height: u32,
     layers: HashMap<Uuid, SerializedLayer>,
     layer_order: Vec<Uuid>,
+    /// Shared images referenced by asset layers, each stored once.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    assets: BTreeMap<AssetId, ImageSource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    selection: Option<Selection>,
+    #[serde(default, skip_serializing_if = "ColorProfile::is_default")]
+    color_profile: ColorProfile,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compositing: Option<SerializedCompositing>,
+    /// Editor layout by layer, see [`Document::ui_layout`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    ui_layout: BTreeMap<Uuid, serde_json::Value>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SerializedLayer {
     name: String,
     visible: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    locked: bool,
     opacity: f32,
     blend_mode: String,
+    #[serde(default, skip_serializing_if = "LayerTransform::is_default")]
+    transform: LayerTransform,
+    /// Pixel content of layers whose graph is a single image node.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    image: Option<ImageSource>,
+    /// Set for layers whose graph is a single asset reference.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    asset: Option<AssetId>,
+    /// Set for layers sharing the graph of another layer, which stores it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    linked_to: Option<Uuid>,
+    /// Set for smart-object layers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    smart: Option<SmartSourceData>,
+    /// Id of the node of image, asset and smart-object layers, so layouts
+    /// keyed by it survive a reload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    node: Option<NodeId>,
+    /// The graph of any other layer with nodes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    graph: Option<SerializedGraph>,
+    /// Pixels of the image nodes in `graph`, which their parameters don't
+    /// hold.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    images: BTreeMap<Uuid, ImageSource>,
+}
+
+/// The document shown by a smart-object layer.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum SmartSourceData {
+    Linked { path: PathBuf },
+    /// A [`SerializedDocument`] with inline images.
+    Embedded { document: serde_json::Value },
+}
+
+impl SmartSourceData {
+    fn from_content(content: &SmartContent) -> Result<Self> {
+        Ok(match content {
+            SmartContent::Linked(path) => SmartSourceData::Linked { path: path.clone() },
+            SmartContent::Embedded(embedded) => SmartSourceData::Embedded {
+                document: serde_json::to_value(embedded.document().serialize()?)?,
+            },
+        })
+    }
+
+    fn to_content(&self) -> Result<SmartContent> {
+        Ok(match self {
+            SmartSourceData::Linked { path } => SmartContent::Linked(path.clone()),
+            SmartSourceData::Embedded { document } => {
+                SmartContent::embedded(Document::deserialize(serde_json::from_value(document.clone())?)?)
+            }
+        })
+    }
+}
+
+/// Where a layer's pixels are stored.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ImageSource {
+    /// Raw RGBA8 pixels, base64 encoded, as written to plain JSON documents.
+    Inline {
+        width: u32,
+        height: u32,
+        rgba_base64: String,
+    },
+    /// A PNG stored next to the document, identified by its content hash.
+    Asset {
+        hash: String,
+    },
+}
+
+impl ImageSource {
+    pub fn inline(image: &DynamicImage) -> Self {
+        let rgba = image.to_rgba8();
+        ImageSource::Inline {
+            width: rgba.width(),
+            height: rgba.height(),
+            rgba_base64: BASE64.encode(rgba.as_raw()),
+        }
+    }
+
+    pub(crate) fn decode_inline(&self) -> Result<DynamicImage> {
+        match self {
+            ImageSource::Inline { width, height, rgba_base64 } => {
+                let bytes = BASE64.decode(rgba_base64)?;
+                let rgba = RgbaImage::from_raw(*width, *height, bytes)
+                    .ok_or_else(|| anyhow!("Inline image data does not match {}x{}", width, height))?;
+                Ok(DynamicImage::ImageRgba8(rgba))
+            }
+            ImageSource::Asset { hash } => Err(anyhow!("Asset {} cannot be resolved outside a package", hash)),
+        }
+    }
+}
+
+/// The image held by a layer whose graph is exactly one [`ImageNode`].
+pub(crate) fn layer_image(layer: &Layer) -> Option<DynamicImage> {
+    let node_ids = layer.node_graph().get_node_ids();
+    let [node_id] = node_ids.as_slice() else {
+        return None;
+    };
+    let node = layer.node_graph().get_node(node_id)?;
+    let node = node.read();
+    node.data()
+        .as_any()
+        .downcast_ref::<ImageNode>()
+        .and_then(|image_node| image_node.image().cloned())
+}
+
+/// The asset referenced by a layer whose graph is exactly one [`AssetRefNode`].
+pub(crate) fn layer_asset(layer: &Layer) -> Option<AssetId> {
+    let node_ids = layer.node_graph().get_node_ids();
+    let [node_id] = node_ids.as_slice() else {
+        return None;
+    };
+    let node = layer.node_graph().get_node(node_id)?;
+    let node = node.read();
+    node.data()
+        .as_any()
+        .downcast_ref::<AssetRefNode>()
+        .map(|asset_ref| asset_ref.asset().clone())
+}
+
+impl SerializedLayer {
+    pub(crate) fn from_layer<F>(layer: &Layer, store_image: &mut F) -> Result<Self>
+    where
+        F: FnMut(&DynamicImage) -> Result<ImageSource>,
+    {
+        let image = match layer_image(layer) {
+            Some(image) => Some(store_image(&image)?),
+            None => None,
+        };
+        let smart = match layer.smart_content() {
+            Some(content) => Some(SmartSourceData::from_content(&content)?),
+            None => None,
+        };
+        let asset = layer_asset(layer);
+        let node = match (&image, &asset, &smart) {
+            (None, None, None) => None,
+            _ => layer.node_graph().get_node_ids().into_iter().next(),
+        };
+        let mut graph = None;
+        let mut images = BTreeMap::new();
+        if image.is_none() && asset.is_none() && smart.is_none() && !layer.node_graph().get_node_ids().is_empty() {
+            let nodes = layer.node_graph();
+            for id in nodes.get_node_ids() {
+                let Some(node) = nodes.get_node(&id) else {
+                    continue;
+                };
+                let node = node.read();
+                if let Some(pixels) = node.data().as_any().downcast_ref::<ImageNode>().and_then(ImageNode::image) {
+                    images.insert(id.0, store_image(pixels)?);
+                }
+            }
+            graph = Some(nodes.to_serialized());
+        }
+        Ok(SerializedLayer {
+            image,
+            asset,
+            smart,
+            node,
+            graph,
+            images,
+            ..Self::properties(layer)
+        })
+    }
+
+    /// A layer sharing the graph of the layer serialized under `owner`.
+    pub(crate) fn linked(layer: &Layer, owner: Uuid) -> Self {
+        SerializedLayer {
+            linked_to: Some(owner),
+            ..Self::properties(layer)
+        }
+    }
+
+    fn properties(layer: &Layer) -> Self {
+        SerializedLayer {
+            name: layer.name().to_string(),
+            visible: layer.is_visible(),
+            locked: layer.is_locked(),
+            opacity: layer.opacity(),
+            blend_mode: layer.blend_mode().name().to_string(),
+            transform: layer.transform(),
+            image: None,
+            asset: None,
+            linked_to: None,
+            smart: None,
+            node: None,
+            graph: None,
+            images: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn into_layer<F>(self, resolve_image: &mut F) -> Result<Layer>
+    where
+        F: FnMut(&ImageSource) -> Result<DynamicImage>,
+    {
+        let blend_mode = BlendMode::from_name(&self.blend_mode)
+            .ok_or_else(|| anyhow!("Unknown blend mode: {}", self.blend_mode))?;
+        let mut layer = match (&self.image, &self.asset, &self.smart) {
+            (_, _, Some(smart)) => Layer::with_smart_content(self.name.clone(), smart.to_content()?),
+            (Some(source), _, None) => Layer::with_image(self.name.clone(), resolve_image(source)?),
+            (None, Some(asset), None) => Layer::with_asset(self.name.clone(), asset.clone()),
+            (None, None, None) => Layer::new(),
+        };
+        if let Some(id) = self.node {
+            restore_node_id(&mut layer, id)?;
+        }
+        if let Some(graph) = &self.graph {
+            *layer.node_graph_mut() = restore_graph(graph, &self.images, resolve_image)?;
+        }
+        layer.set_name(self.name);
+        layer.set_visible(self.visible);
+        layer.set_locked(self.locked);
+        layer.set_opacity(self.opacity);
+        layer.set_blend_mode(blend_mode);
+        layer.set_transform(self.transform);
+        Ok(layer)
+    }
+
+    /// The layer without its saved node ids, for copies living alongside
+    /// the original.
+    pub(crate) fn without_node_id(self) -> Self {
+        let Some(graph) = self.graph else {
+            return Self { node: None, ..self };
+        };
+        let (graph, ids) = graph.with_new_ids();
+        let images = self
+            .images
+            .into_iter()
+            .map(|(id, source)| (ids.get(&NodeId(id)).map_or(id, |new| new.0), source))
+            .collect();
+        Self { node: None, graph: Some(graph), images, ..self }
+    }
+
+    /// The asset the layer references, if any.
+    pub(crate) fn asset(&self) -> Option<&AssetId> {
+        self.asset.as_ref()
+    }
+}
+
+/// Gives the only node of `layer` its saved id.
+fn restore_node_id(layer: &mut Layer, id: NodeId) -> Result<()> {
+    let mut graph = layer.node_graph_mut();
+    let [current] = graph.get_node_ids().try_into().map_err(|_| anyhow!("Saved node id for a layer without a single node"))?;
+    let node = graph.remove_node(&current)?;
+    let node = Arc::try_unwrap(node).map_err(|_| anyhow!("Layer node {} is still in use", current.to_string()))?;
+    graph.add_node(node.into_inner().with_id(id));
+    Ok(())
+}
+
+/// Rebuilds a saved layer graph with the pixels of its image nodes.
+fn restore_graph<F>(saved: &SerializedGraph, images: &BTreeMap<Uuid, ImageSource>, resolve_image: &mut F) -> Result<NodeGraph>
+where
+    F: FnMut(&ImageSource) -> Result<DynamicImage>,
+{
+    register_document_nodes();
+    let graph = NodeGraph::from_serialized(saved, &NODE_REGISTRY.read())?;
+    for (id, source) in images {
+        let node = graph.get_node(&NodeId(*id)).ok_or_else(|| anyhow!("Saved pixels for missing node {}", id))?;
+        let mut node = node.write();
+        let image_node = node
+            .data_mut()
+            .as_any_mut()
+            .downcast_mut::<ImageNode>()
+            .ok_or_else(|| anyhow!("Saved pixels for node {}, which isn't an image node", id))?;
+        image_node.set_image(Some(resolve_image(source)?));
+    }
+    Ok(graph)
+}
+
+impl SerializedDocument {
+    pub fn format_version(&self) -> u32 {
+        self.format_version
+    }
 }
 
 impl Document {
     pub fn serialize(&self) -> Result<SerializedDocument> {
+        self.serialize_with(|image| Ok(ImageSource::inline(image)))
+    }
+
+    /// Serializes the document, letting `store_image` decide how layer pixels are referenced.
+    pub(crate) fn serialize_with<F>(&self, mut store_image: F) -> Result<SerializedDocument>
+    where
+        F: FnMut(&DynamicImage) -> Result<ImageSource>,
+    {
+        // The bottom layer of each linked set stores the graph
+        let mut owners = HashMap::new();
+        for id in &self.layer_order {
+            if let Some(layer) = self.get_layer(id) {
+                owners.entry(layer.read().graph_key()).or_insert(id.0);
+            }
+        }
+
         let mut layers = HashMap::new();
-        
         for (layer_id, layer) in &self.layers {
             let layer = layer.read();
-            layers.insert(layer_id.0, SerializedLayer {
-                name: "Layer".to_string(), // TODO: Add name to Layer struct
-                visible: true,
-                opacity: 1.0,
-                blend_mode: "normal".to_string(),
-            });
+            let serialized = match owners.get(&layer.graph_key()) {
+                Some(owner) if *owner != layer_id.0 => SerializedLayer::linked(&layer, *owner),
+                _ => SerializedLayer::from_layer(&layer, &mut store_image)?,
+            };
+            layers.insert(layer_id.0, serialized);
+        }
+
+        let mut assets = BTreeMap::new();
+        for id in self.referenced_assets() {
+            let image = self.assets.get(&id)
+                .ok_or_else(|| anyhow!("Layer references missing asset {}", id))?;
+            assets.insert(id, store_image(&*image)?);
         }
 
         let layer_order = self.layer_order.iter().map(|id| id.0).collect();
 
         Ok(SerializedDocument {
+            format_version: CURRENT_FORMAT_VERSION,
+            width: self.width,
+            height: self.height,
             layers,
             layer_order,
+            assets,
+            selection: self.selection.clone(),
+            color_profile: self.color_profile.clone(),
+            compositing: self.compositing.as_ref().map(CompositingGraph::to_serialized),
+            ui_layout: self.ui_layout.iter()
+                .filter(|(id, _)| self.layers.contains_key(*id))
+                .map(|(id, layout)| (id.0, layout.clone()))
+                .collect(),
         })
     }
 
     pub fn deserialize(data: SerializedDocument) -> Result<Self> {
-        let mut document = Document::new();
+        Self::deserialize_with(data, |source| source.decode_inline(), &mut |_| Ok(()))
+    }
+
+    /// Rebuilds a document, resolving layer pixel references through `resolve_image`.
+    /// An error returned by `progress` aborts the load.
+    pub(crate) fn deserialize_with<F>(
+        data: SerializedDocument,
+        mut resolve_image: F,
+        progress: &mut dyn FnMut(LoadProgress) -> Result<(), DocumentError>,
+    ) -> Result<Self>
+    where
+        F: FnMut(&ImageSource) -> Result<DynamicImage>,
+    {
+        let mut document = Document::with_size(data.width, data.height);
+
+        let asset_count = data.assets.len();
+        progress(LoadProgress::new(LoadPhase::DecompressingAssets, 0, asset_count))?;
+        for (index, (id, source)) in data.assets.iter().enumerate() {
+            document.assets.insert_with_id(id.clone(), resolve_image(source)?);
+            progress(LoadProgress::new(LoadPhase::DecompressingAssets, index + 1, asset_count))?;
+        }
 
         // Create layers
-        for (uuid, layer_data) in data.layers {
-            let layer_id = LayerId(uuid);
-            let layer = Layer::new();
-            document.layers.insert(layer_id.clone(), Arc::new(RwLock::new(layer)));
+        let layer_count = data.layers.len();
+        progress(LoadProgress::new(LoadPhase::BuildingGraphs, 0, layer_count))?;
+        let mut links = Vec::new();
+        for (index, (uuid, layer_data)) in data.layers.into_iter().enumerate() {
+            if let Some(owner) = layer_data.linked_to {
+                links.push((LayerId(uuid), LayerId(owner)));
+            }
+            let layer = layer_data.into_layer(&mut resolve_image)?;
+            document.layers.insert(LayerId(uuid), Arc::new(RwLock::new(layer)));
+            progress(LoadProgress::new(LoadPhase::BuildingGraphs, index + 1, layer_count))?;
+        }
+        for (id, owner) in links {
+            let owner = document.layers.get(&owner)
+                .filter(|_| owner != id)
+                .ok_or_else(|| anyhow!("Layer {} is linked to missing layer {}", id.0, owner.0))?;
+            document.layers[&id].write().link_to(&owner.read());
         }
 
+        document.selection = data.selection;
+        document.color_profile = data.color_profile;
+        document.compositing = data.compositing.map(CompositingGraph::from_serialized).transpose()?;
+        document.ui_layout = data.ui_layout.into_iter().map(|(id, layout)| (LayerId(id), layout)).collect();
+
         // Restore layer order
         document.layer_order = data.layer_order.into_iter()
             .map(LayerId)
@@ -78,4 +458,87 @@ mod tests {
         assert_eq!(deserialized.layers.len(), 1);
         assert_eq!(deserialized.layer_order.len(), 1);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_layer_properties_round_trip() {
+        let mut doc = Document::new();
+        let layer_id = doc.add_layer();
+        {
+            let layer = doc.get_layer(&layer_id).unwrap();
+            let mut layer = layer.write();
+            layer.set_name("Shadows".to_string());
+            layer.set_visible(false);
+            layer.set_opacity(0.25);
+            layer.set_blend_mode(BlendMode::Screen);
+        }
+
+        let serialized = doc.serialize().unwrap();
+        assert_eq!(serialized.format_version, CURRENT_FORMAT_VERSION);
+
+        let deserialized = Document::deserialize(serialized).unwrap();
+        let layer = deserialized.get_layer(&layer_id).unwrap();
+        let layer = layer.read();
+        assert_eq!(layer.name(), "Shadows");
+        assert!(!layer.is_visible());
+        assert_eq!(layer.opacity(), 0.25);
+        assert_eq!(layer.blend_mode(), BlendMode::Screen);
+    }
+
+    #[test]
+    fn test_image_layer_round_trip() {
+        let mut doc = Document::with_size(3, 2);
+        let mut pixels = RgbaImage::new(3, 2);
+        pixels.put_pixel(2, 1, image::Rgba([1, 2, 3, 4]));
+        let image = DynamicImage::ImageRgba8(pixels);
+        let id = LayerId::new();
+        doc.insert_layer_raw(0, id.clone(), Arc::new(RwLock::new(Layer::with_image("Pixels", image.clone()))));
+
+        let deserialized = Document::deserialize(doc.serialize().unwrap()).unwrap();
+        let layer = deserialized.get_layer(&id).unwrap();
+        assert_eq!(layer_image(&layer.read()).unwrap().to_rgba8(), image.to_rgba8());
+    }
+
+    #[test]
+    fn test_single_node_ids_survive_reload() {
+        let mut doc = Document::with_size(2, 2);
+        let id = LayerId::new();
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(2, 2));
+        doc.insert_layer_raw(0, id.clone(), Arc::new(RwLock::new(Layer::with_image("Pixels", image))));
+        let node_ids = doc.get_layer(&id).unwrap().read().node_graph().get_node_ids();
+
+        let deserialized = Document::deserialize(doc.serialize().unwrap()).unwrap();
+        let layer = deserialized.get_layer(&id).unwrap();
+        assert_eq!(layer.read().node_graph().get_node_ids(), node_ids);
+        assert!(layer_image(&layer.read()).is_some());
+    }
+
+    #[test]
+    fn test_layer_graphs_round_trip() {
+        use aurion_core::Node;
+        use aurion_std_nodes::filters::BlurNode;
+
+        let mut doc = Document::with_size(4, 4);
+        let mut pixels = RgbaImage::new(4, 4);
+        pixels.put_pixel(1, 2, image::Rgba([200, 100, 50, 255]));
+        let id = LayerId::new();
+        let mut layer = Layer::with_image("Blurred", DynamicImage::ImageRgba8(pixels));
+        let blur = {
+            let mut graph = layer.node_graph_mut();
+            let image = graph.get_node_ids()[0].clone();
+            let blur = graph.add_node(Node::new(Box::new(BlurNode::new(1.5))));
+            graph.connect(&image, &blur, "input").unwrap();
+            blur
+        };
+        let expected = layer.node_graph().evaluate(&blur).unwrap().into_image().unwrap();
+        doc.insert_layer_raw(0, id.clone(), Arc::new(RwLock::new(layer)));
+
+        let serialized = doc.serialize().unwrap();
+        assert!(serialized.layers[&id.0].graph.is_some());
+        let deserialized = Document::deserialize(serialized).unwrap();
+        let layer = deserialized.get_layer(&id).unwrap();
+        let layer = layer.read();
+        assert_eq!(layer.node_graph().get_node_ids().len(), 2);
+        let output = layer.node_graph().evaluate(&blur).unwrap().into_image().unwrap();
+        assert_eq!(output.to_rgba8(), expected.to_rgba8());
+    }
+}
@@ -0,0 +1,525 @@
+//! Smart-object layers: layers showing the composite of another document.
+//!
+//! The other document is either embedded in this one or linked by the path
+//! of an `.artm` package. A linked document is re-read when the file's
+//! modification time changes. An embedded one changes when an edit session
+//! opened with [`Document::open_smart_layer_for_edit`] is saved back with
+//! [`Document::save_smart_edit`]. Either way the layer's content hash
+//! changes, so the next render picks the change up, and
+//! [`Document::refresh_smart_layers`] reports it to the UI.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::SystemTime;
+use aurion_core::{Node, NodeData, NodeError, NodeValue, PortSpec};
+use image::DynamicImage;
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
+use uuid::Uuid;
+use crate::{Document, DocumentError, DocumentEvent, Layer, LayerId};
+
+/// What a smart-object layer shows.
+#[derive(Debug)]
+pub enum SmartSource {
+    /// A document stored inside this one.
+    Embedded(Box<Document>),
+    /// An `.artm` package on disk.
+    Linked(PathBuf),
+}
+
+/// An embedded document, shared by its [`SmartObjectNode`] and the edit
+/// sessions opened on it.
+#[derive(Debug)]
+pub(crate) struct EmbeddedDocument {
+    id: Uuid,
+    document: RwLock<Document>,
+    // Bumped whenever an edit session is saved back
+    version: AtomicU64,
+}
+
+/// Handle kept by an edit session on an embedded document.
+pub(crate) type SmartEditTarget = Weak<EmbeddedDocument>;
+
+#[derive(Debug, Clone)]
+pub(crate) enum SmartContent {
+    Embedded(Arc<EmbeddedDocument>),
+    Linked(PathBuf),
+}
+
+impl SmartContent {
+    pub(crate) fn embedded(document: Document) -> Self {
+        Self::Embedded(Arc::new(EmbeddedDocument {
+            id: Uuid::new_v4(),
+            document: RwLock::new(document),
+            version: AtomicU64::new(0),
+        }))
+    }
+}
+
+impl EmbeddedDocument {
+    pub(crate) fn document(&self) -> RwLockReadGuard<'_, Document> {
+        self.document.read()
+    }
+}
+
+thread_local! {
+    // Linked documents being rendered on this thread, guarding against
+    // files changed on disk into a cycle after they were linked
+    static RENDERING: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// An independent copy of `document`, limited to what a save preserves.
+fn copy_document(document: &Document) -> Result<Document, DocumentError> {
+    let serialized = document
+        .serialize()
+        .map_err(|e| DocumentError::Other(format!("Failed to copy document: {}", e)))?;
+    Document::deserialize(serialized).map_err(|e| DocumentError::Other(format!("Failed to copy document: {}", e)))
+}
+
+/// Outputs the composite of another document. It takes no inputs.
+#[derive(Debug)]
+pub struct SmartObjectNode {
+    content: SmartContent,
+    // A linked document's composite and the modification time it was read at
+    linked_output: Mutex<Option<(SystemTime, DynamicImage)>>,
+    // Content hash last reported by `Document::refresh_smart_layers`
+    seen: Mutex<Option<u64>>,
+}
+
+impl SmartObjectNode {
+    pub const TYPE_NAME: &'static str = "SmartObjectNode";
+
+    fn with_content(content: SmartContent) -> Self {
+        let node = Self {
+            content,
+            linked_output: Mutex::new(None),
+            seen: Mutex::new(None),
+        };
+        *node.seen.lock() = node.content_hash();
+        node
+    }
+
+    /// The linked file, `None` for embedded documents.
+    pub fn linked_path(&self) -> Option<&Path> {
+        match &self.content {
+            SmartContent::Linked(path) => Some(path),
+            SmartContent::Embedded(_) => None,
+        }
+    }
+
+    fn render_linked(&self, path: &Path) -> Result<DynamicImage, NodeError> {
+        let error = |message: String| NodeError::ComputationError {
+            context: format!("smart object {}", path.display()),
+            message,
+        };
+        let modified = modified_time(path).ok_or_else(|| error("File not found".to_string()))?;
+        if let Some((read_at, output)) = &*self.linked_output.lock() {
+            if *read_at == modified {
+                return Ok(output.clone());
+            }
+        }
+
+        let key = canonical(path);
+        if RENDERING.with(|rendering| rendering.borrow().contains(&key)) {
+            return Err(error("The document references itself".to_string()));
+        }
+        RENDERING.with(|rendering| rendering.borrow_mut().push(key));
+        let output = Document::load_package(path).and_then(|document| document.render_composite());
+        RENDERING.with(|rendering| rendering.borrow_mut().pop());
+
+        let output = output.map_err(|e| error(e.to_string()))?;
+        *self.linked_output.lock() = Some((modified, output.clone()));
+        Ok(output)
+    }
+}
+
+impl NodeData for SmartObjectNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_name(&self) -> &'static str {
+        Self::TYPE_NAME
+    }
+
+    fn output_ports(&self) -> Vec<PortSpec> {
+        vec![PortSpec::image_output()]
+    }
+
+    /// Changes with the linked file's modification time or with edits to
+    /// the embedded document.
+    fn content_hash(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        match &self.content {
+            SmartContent::Embedded(embedded) => {
+                embedded.id.hash(&mut hasher);
+                embedded.version.load(Ordering::SeqCst).hash(&mut hasher);
+                embedded.document.read().revision().hash(&mut hasher);
+            }
+            SmartContent::Linked(path) => {
+                path.hash(&mut hasher);
+                modified_time(path)?.hash(&mut hasher);
+            }
+        }
+        Some(hasher.finish())
+    }
+
+    /// Embedded documents are copied, see [`Document::serialize`] for what
+    /// a copy keeps. One that can't be serialized stays shared with the
+    /// original.
+    fn clone_box(&self) -> Box<dyn NodeData> {
+        let content = match &self.content {
+            SmartContent::Embedded(embedded) => match copy_document(&embedded.document.read()) {
+                Ok(document) => SmartContent::embedded(document),
+                Err(_) => SmartContent::Embedded(embedded.clone()),
+            },
+            SmartContent::Linked(path) => SmartContent::Linked(path.clone()),
+        };
+        Box::new(Self::with_content(content))
+    }
+
+    fn estimated_memory(&self) -> usize {
+        let content = match &self.content {
+            SmartContent::Embedded(embedded) => embedded.document.read().memory_report().total_bytes,
+            SmartContent::Linked(_) => self
+                .linked_output
+                .lock()
+                .as_ref()
+                .map_or(0, |(_, image)| image.as_bytes().len()),
+        };
+        std::mem::size_of_val(self) + content
+    }
+
+    fn compute(&self, inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+        if !inputs.is_empty() {
+            return Err(NodeError::InvalidInputType {
+                expected: "none".to_string(),
+                actual: format!("{} inputs", inputs.len()),
+            });
+        }
+        let output = match &self.content {
+            SmartContent::Embedded(embedded) => {
+                embedded.document.read().render_composite().map_err(|e| NodeError::ComputationError {
+                    context: "embedded smart object".to_string(),
+                    message: e.to_string(),
+                })?
+            }
+            SmartContent::Linked(path) => self.render_linked(path)?,
+        };
+        Ok(NodeValue::image(output))
+    }
+}
+
+impl Layer {
+    /// A layer whose graph is a single [`SmartObjectNode`].
+    pub(crate) fn with_smart_content(name: impl Into<String>, content: SmartContent) -> Self {
+        let mut layer = Self::new();
+        layer.name = name.into();
+        layer.node_graph.write().add_node(Node::new(Box::new(SmartObjectNode::with_content(content))));
+        layer
+    }
+
+    /// The document shown by a smart-object layer.
+    pub(crate) fn smart_content(&self) -> Option<SmartContent> {
+        self.with_smart_node(|node| node.content.clone())
+    }
+
+    fn with_smart_node<T>(&self, f: impl FnOnce(&SmartObjectNode) -> T) -> Option<T> {
+        let graph = self.node_graph();
+        let node_ids = graph.get_node_ids();
+        let [node_id] = node_ids.as_slice() else {
+            return None;
+        };
+        let node = graph.get_node(node_id)?;
+        let node = node.read();
+        node.data().as_any().downcast_ref::<SmartObjectNode>().map(f)
+    }
+
+    pub fn is_smart_object(&self) -> bool {
+        self.with_smart_node(|_| ()).is_some()
+    }
+}
+
+/// Fails if a smart layer of `document`, followed through linked files and
+/// embedded documents, leads to a file on `chain`.
+fn check_references(document: &Document, chain: &mut Vec<PathBuf>) -> Result<(), DocumentError> {
+    for id in &document.layer_order {
+        let Some(content) = document.get_layer(id).and_then(|layer| layer.read().smart_content()) else {
+            continue;
+        };
+        match content {
+            SmartContent::Embedded(embedded) => check_references(&embedded.document.read(), chain)?,
+            SmartContent::Linked(path) => check_linked(&path, chain)?,
+        }
+    }
+    Ok(())
+}
+
+fn check_linked(path: &Path, chain: &mut Vec<PathBuf>) -> Result<(), DocumentError> {
+    let key = canonical(path);
+    if chain.contains(&key) {
+        return Err(DocumentError::InvalidOperation(format!(
+            "Smart object {} would reference itself",
+            path.display()
+        )));
+    }
+    let document = Document::load_package(path)?;
+    chain.push(key);
+    check_references(&document, chain)?;
+    chain.pop();
+    Ok(())
+}
+
+impl Document {
+    /// Adds a smart-object layer on top of the stack. Sources that lead back
+    /// to this document's file, directly or through other smart objects,
+    /// are refused.
+    pub fn add_smart_layer(&mut self, source: SmartSource) -> Result<LayerId, DocumentError> {
+        let mut chain: Vec<PathBuf> = self.path.iter().map(|path| canonical(path)).collect();
+        let name = match &source {
+            SmartSource::Embedded(document) => {
+                check_references(document, &mut chain)?;
+                document.name()
+            }
+            SmartSource::Linked(path) => {
+                check_linked(path, &mut chain)?;
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "Smart Object".to_string())
+            }
+        };
+
+        let content = match source {
+            SmartSource::Embedded(document) => SmartContent::embedded(*document),
+            SmartSource::Linked(path) => SmartContent::Linked(path),
+        };
+        let layer = Layer::with_smart_content(self.unique_layer_name(&name, None), content);
+        let id = LayerId::new();
+        self.insert_layer_raw(self.layer_order.len(), id.clone(), Arc::new(RwLock::new(layer)));
+        self.mark_modified();
+        Ok(id)
+    }
+
+    /// Opens the document a smart-object layer shows. Save the returned
+    /// document with [`Document::save_smart_edit`] to update the layer.
+    ///
+    /// Linked documents are loaded from their file. Embedded ones are
+    /// copied, keeping what a save keeps.
+    pub fn open_smart_layer_for_edit(&self, id: &LayerId) -> Result<Document, DocumentError> {
+        let content = self
+            .with_layer(id, Layer::smart_content)?
+            .ok_or_else(|| DocumentError::InvalidOperation(format!("Layer {} is not a smart object", id.0)))?;
+        match content {
+            SmartContent::Linked(path) => Document::load_package(path),
+            SmartContent::Embedded(embedded) => {
+                let mut document = copy_document(&embedded.document.read())?;
+                document.smart_edit = Some(Arc::downgrade(&embedded));
+                Ok(document)
+            }
+        }
+    }
+
+    /// Saves a document opened with [`Document::open_smart_layer_for_edit`]
+    /// back to where it came from: the smart layer for embedded documents,
+    /// the file for linked ones.
+    pub fn save_smart_edit(&mut self) -> Result<(), DocumentError> {
+        let Some(target) = &self.smart_edit else {
+            let path = self.path.clone().ok_or_else(|| {
+                DocumentError::InvalidOperation("Document was not opened from a smart object".to_string())
+            })?;
+            return self.save_package(path);
+        };
+        let embedded = target.upgrade().ok_or_else(|| {
+            DocumentError::InvalidOperation("The smart object this document was opened from is gone".to_string())
+        })?;
+        *embedded.document.write() = copy_document(self)?;
+        embedded.version.fetch_add(1, Ordering::SeqCst);
+        self.mark_saved();
+        Ok(())
+    }
+
+    /// Finds smart-object layers whose content changed since they were
+    /// added or last refreshed, e.g. on window focus. Their thumbnails are
+    /// dropped and [`DocumentEvent::GraphChanged`] is emitted for each.
+    pub fn refresh_smart_layers(&self) -> Vec<LayerId> {
+        let mut checked = HashSet::new();
+        let mut changed = Vec::new();
+        for id in &self.layer_order {
+            let Some(layer) = self.get_layer(id) else {
+                continue;
+            };
+            let layer = layer.read();
+            if !checked.insert(layer.graph_key()) {
+                continue;
+            }
+            let updated = layer.with_smart_node(|node| {
+                let hash = node.content_hash();
+                std::mem::replace(&mut *node.seen.lock(), hash) != hash
+            });
+            if updated == Some(true) {
+                changed.extend(self.linked_layers(id));
+            }
+        }
+
+        for id in &changed {
+            if let Some(layer) = self.get_layer(id) {
+                layer.read().invalidate_thumbnail();
+            }
+            self.events.emit(DocumentEvent::GraphChanged(id.clone()));
+        }
+        if !changed.is_empty() {
+            self.invalidate_region(self.canvas_rect());
+        }
+        changed
+    }
+
+    /// Files linked by smart layers, directly or through embedded
+    /// documents, e.g. to watch them for changes. Links inside linked files
+    /// are not followed.
+    pub fn linked_files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for id in &self.layer_order {
+            match self.get_layer(id).and_then(|layer| layer.read().smart_content()) {
+                Some(SmartContent::Linked(path)) => files.push(path),
+                Some(SmartContent::Embedded(embedded)) => files.extend(embedded.document().linked_files()),
+                None => {}
+            }
+        }
+        files.sort();
+        files.dedup();
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::Duration;
+    use image::{Rgba, RgbaImage};
+    use crate::LayerKind;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("meridian_smart_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn filled(doc: &mut Document, color: [u8; 4]) {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(doc.width(), doc.height(), Rgba(color)));
+        let index = doc.layer_order.len();
+        doc.insert_layer_raw(index, LayerId::new(), Arc::new(RwLock::new(Layer::with_image("Fill", image))));
+    }
+
+    fn child(color: [u8; 4]) -> Document {
+        let mut doc = Document::with_size(8, 8);
+        filled(&mut doc, color);
+        doc
+    }
+
+    fn pixel(doc: &Document, x: u32, y: u32) -> [u8; 4] {
+        doc.render_composite().unwrap().to_rgba8().get_pixel(x, y).0
+    }
+
+    #[test]
+    fn test_embedded_edit_updates_parent() {
+        let mut parent = Document::with_size(16, 16);
+        let id = parent.add_smart_layer(SmartSource::Embedded(Box::new(child([200, 0, 0, 255])))).unwrap();
+        assert_eq!(parent.get_layer(&id).unwrap().read().kind(), LayerKind::SmartObject);
+        assert_eq!(pixel(&parent, 3, 3), [200, 0, 0, 255]);
+        assert_eq!(pixel(&parent, 12, 12)[3], 0);
+
+        let mut session = parent.open_smart_layer_for_edit(&id).unwrap();
+        filled(&mut session, [0, 0, 200, 255]);
+        // Nothing changes until the session is saved
+        assert!(parent.refresh_smart_layers().is_empty());
+        assert_eq!(pixel(&parent, 3, 3), [200, 0, 0, 255]);
+
+        session.save_smart_edit().unwrap();
+        assert!(!session.is_modified());
+        assert_eq!(parent.refresh_smart_layers(), vec![id.clone()]);
+        assert_eq!(pixel(&parent, 3, 3), [0, 0, 200, 255]);
+        assert!(parent.refresh_smart_layers().is_empty());
+
+        // The embedded document is saved with the parent
+        let loaded = Document::deserialize(parent.serialize().unwrap()).unwrap();
+        assert_eq!(pixel(&loaded, 3, 3), [0, 0, 200, 255]);
+        assert!(loaded.get_layer(&id).unwrap().read().is_smart_object());
+    }
+
+    #[test]
+    fn test_linked_file_changes_update_parent() {
+        let dir = temp_dir();
+        let path = dir.join("child.artm");
+        child([0, 150, 0, 255]).save_package(&path).unwrap();
+
+        let mut parent = Document::with_size(16, 16);
+        let id = parent.add_smart_layer(SmartSource::Linked(path.clone())).unwrap();
+        assert_eq!(parent.get_layer(&id).unwrap().read().name(), "child");
+        assert_eq!(parent.linked_files(), vec![path.clone()]);
+        assert_eq!(pixel(&parent, 3, 3), [0, 150, 0, 255]);
+
+        let mut session = parent.open_smart_layer_for_edit(&id).unwrap();
+        filled(&mut session, [150, 150, 0, 255]);
+        session.save_smart_edit().unwrap();
+        // Filesystem timestamps may be too coarse to tell the saves apart
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+
+        assert_eq!(parent.refresh_smart_layers(), vec![id]);
+        assert_eq!(pixel(&parent, 3, 3), [150, 150, 0, 255]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_circular_references_are_refused() {
+        let dir = temp_dir();
+        let a_path = dir.join("a.artm");
+        let b_path = dir.join("b.artm");
+        let mut a = child([10, 20, 30, 255]);
+        a.save_package(&a_path).unwrap();
+
+        let mut b = child([40, 50, 60, 255]);
+        b.add_smart_layer(SmartSource::Linked(a_path.clone())).unwrap();
+        b.save_package(&b_path).unwrap();
+
+        // A links B, which links A
+        assert!(matches!(
+            a.add_smart_layer(SmartSource::Linked(b_path.clone())),
+            Err(DocumentError::InvalidOperation(_))
+        ));
+        // A embeds a document linking A
+        let b = Document::load_package(&b_path).unwrap();
+        assert!(a.add_smart_layer(SmartSource::Embedded(Box::new(b))).is_err());
+        // A links itself
+        assert!(a.add_smart_layer(SmartSource::Linked(a_path.clone())).is_err());
+        assert_eq!(a.layer_count(), 1);
+
+        // Unrelated documents are fine
+        let c_path = dir.join("c.artm");
+        child([70, 80, 90, 255]).save_package(&c_path).unwrap();
+        a.add_smart_layer(SmartSource::Linked(c_path)).unwrap();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,359 @@
+//! Immutable copies of a document for rendering off the editing thread.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use aurion_core::{EvalContext, NodeGraph, NodeId};
+use aurion_std_nodes::assets::AssetResolver;
+use image::DynamicImage;
+use crate::assets::AssetStore;
+use crate::composite::{flatten, graph_output, DrawLayer};
+use crate::compositing::CompositingGraph;
+use crate::{BlendMode, CanvasRect, ColorProfile, Document, DocumentError, LayerId, LayerTransform};
+
+/// What a snapshot renders a layer from.
+#[derive(Clone)]
+enum LayerSource {
+    /// The output the render cache held when the snapshot was taken.
+    Rendered(Option<Arc<DynamicImage>>),
+    /// The layer's graph, evaluated when the snapshot is rendered.
+    Graph(Arc<NodeGraph>),
+}
+
+impl std::fmt::Debug for LayerSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rendered(output) => f
+                .debug_tuple("Rendered")
+                .field(&output.as_ref().map(|image| (image.width(), image.height())))
+                .finish(),
+            Self::Graph(_) => f.write_str("Graph"),
+        }
+    }
+}
+
+/// A layer's properties at the time of a [`DocumentSnapshot`].
+#[derive(Debug, Clone)]
+pub struct LayerSnapshot {
+    id: LayerId,
+    name: String,
+    opacity: f32,
+    visible: bool,
+    blend_mode: BlendMode,
+    transform: LayerTransform,
+    source: LayerSource,
+    content_hash: Option<u64>,
+}
+
+impl LayerSnapshot {
+    pub fn id(&self) -> &LayerId {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    pub fn transform(&self) -> LayerTransform {
+        self.transform
+    }
+
+    /// The content hash of the layer's graph when the snapshot was taken, or
+    /// `None` if its output can't be cached. Equal hashes mean equal outputs.
+    pub fn content_hash(&self) -> Option<u64> {
+        self.content_hash
+    }
+
+    fn output(&self, context: &EvalContext) -> Result<Option<Arc<DynamicImage>>, DocumentError> {
+        match &self.source {
+            LayerSource::Rendered(output) => Ok(output.clone()),
+            LayerSource::Graph(graph) => Ok(graph_output(graph, context)?.map(Arc::new)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SnapshotData {
+    width: u32,
+    height: u32,
+    color_profile: ColorProfile,
+    assets: AssetStore,
+    // Bottom to top
+    layers: Vec<LayerSnapshot>,
+    // Set if the document composites through a graph with an output
+    compositing: Option<(CompositingGraph, NodeId)>,
+    solo: Option<LayerId>,
+}
+
+/// A frozen view of a document that can be rendered on another thread while
+/// the document keeps being edited. Cloning is cheap.
+///
+/// Layer properties, the layer stack, assets and the compositing graph are
+/// copied. Layers whose output was cached keep that output; the others keep a
+/// copy of their graph. Copied graphs share nodes with the document (see
+/// [`NodeGraph`]'s `Clone`), so edits made in place through node handles
+/// after the snapshot can show up in its renders.
+#[derive(Debug, Clone)]
+pub struct DocumentSnapshot {
+    data: Arc<SnapshotData>,
+}
+
+impl DocumentSnapshot {
+    pub fn width(&self) -> u32 {
+        self.data.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.data.height
+    }
+
+    /// Layers bottom to top.
+    pub fn layers(&self) -> &[LayerSnapshot] {
+        &self.data.layers
+    }
+
+    /// Layers composites draw, bottom to top: the soloed layer if there is
+    /// one, otherwise the visible layers.
+    pub fn shown_layers(&self) -> impl Iterator<Item = &LayerSnapshot> {
+        self.data.layers.iter().filter(move |layer| match &self.data.solo {
+            Some(solo) => *solo == layer.id,
+            None => layer.visible,
+        })
+    }
+
+    pub fn color_profile(&self) -> &ColorProfile {
+        &self.data.color_profile
+    }
+
+    /// Whether composites go through the document's compositing graph rather
+    /// than stacking [`Self::shown_layers`].
+    pub fn composites_through_graph(&self) -> bool {
+        self.data.compositing.is_some() && self.data.solo.is_none()
+    }
+
+    /// Whether both are clones of the same snapshot.
+    pub fn ptr_eq(&self, other: &DocumentSnapshot) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+
+    /// The untransformed output of one of the snapshot's layers, evaluating
+    /// its graph if the output wasn't cached.
+    pub fn layer_output(&self, layer: &LayerSnapshot) -> Result<Option<Arc<DynamicImage>>, DocumentError> {
+        layer.output(&self.context())
+    }
+
+    pub fn canvas_rect(&self) -> CanvasRect {
+        CanvasRect::new(0, 0, self.data.width, self.data.height)
+    }
+
+    /// Same as [`Document::render_composite`] on the document as it was.
+    pub fn render_composite(&self) -> Result<DynamicImage, DocumentError> {
+        self.render_region(self.canvas_rect())
+    }
+
+    /// Same as [`Document::render_region`] on the document as it was.
+    pub fn render_region(&self, rect: CanvasRect) -> Result<DynamicImage, DocumentError> {
+        let linear = self.data.color_profile.blends_in_linear_light();
+        if let Some((compositing, output)) = self.data.compositing.as_ref().filter(|_| self.composites_through_graph()) {
+            return flatten(self.canvas_rect(), rect, linear, |region, draw| {
+                self.composite_graph(compositing, output, region, draw)
+            });
+        }
+        flatten(self.canvas_rect(), rect, linear, |region, draw| self.composite_layers(region, draw))
+    }
+
+    fn context(&self) -> EvalContext {
+        let resolver: Arc<dyn AssetResolver> = Arc::new(self.data.assets.clone());
+        EvalContext::new().with(resolver)
+    }
+
+    fn composite_graph(
+        &self,
+        compositing: &CompositingGraph,
+        output: &NodeId,
+        region: CanvasRect,
+        draw: &mut DrawLayer,
+    ) -> Result<(), DocumentError> {
+        let context = self.context();
+        let mut layers = HashMap::new();
+        for id in compositing.layers() {
+            let Some(layer) = self.data.layers.iter().find(|layer| layer.id == id) else {
+                continue;
+            };
+            let layer_output = if layer.visible { layer.output(&context)? } else { None };
+            layers.insert(id, (layer_output, layer.transform));
+        }
+        compositing.composite(output, layers, (self.data.width, self.data.height), context, region, draw)
+    }
+
+    fn composite_layers(&self, region: CanvasRect, draw: &mut DrawLayer) -> Result<(), DocumentError> {
+        let context = self.context();
+        for layer in self.shown_layers() {
+            let Some(output) = layer.output(&context)? else {
+                continue;
+            };
+            if let Some((image, x, y)) = layer.transform.place(&output, region) {
+                draw(&image, x, y, layer.blend_mode, layer.opacity);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Document {
+    /// Takes a [`DocumentSnapshot`] for rendering in the background.
+    ///
+    /// Cached outputs and assets are shared. Layers without a cached output
+    /// get a copy of their graph, image nodes included. Each layer is locked
+    /// only briefly and one at a time.
+    pub fn snapshot(&self) -> DocumentSnapshot {
+        let layers = self
+            .layer_order
+            .iter()
+            .filter_map(|id| {
+                let layer = self.get_layer(id)?;
+                let layer = layer.read();
+                let content_hash = layer.node_graph().content_hash();
+                let source = match self.render_cache.lookup(id, content_hash) {
+                    Some(output) => LayerSource::Rendered(output),
+                    // Copied, so edits made to the nodes afterwards don't
+                    // reach the snapshot
+                    None => LayerSource::Graph(Arc::new(layer.node_graph().deep_clone())),
+                };
+                Some(LayerSnapshot {
+                    id: id.clone(),
+                    name: layer.name().to_string(),
+                    opacity: layer.opacity(),
+                    visible: layer.is_visible(),
+                    blend_mode: layer.blend_mode(),
+                    transform: layer.transform(),
+                    source,
+                    content_hash,
+                })
+            })
+            .collect();
+
+        DocumentSnapshot {
+            data: Arc::new(SnapshotData {
+                width: self.width,
+                height: self.height,
+                color_profile: self.color_profile.clone(),
+                assets: self.assets.detached(),
+                layers,
+                compositing: self.compositing.as_ref().and_then(|compositing| {
+                    Some((compositing.clone(), compositing.output()?.clone()))
+                }),
+                solo: self.solo().cloned(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use image::{Rgba, RgbaImage};
+    use parking_lot::RwLock;
+    use crate::Layer;
+
+    fn document() -> (Document, Vec<LayerId>) {
+        let mut doc = Document::with_size(48, 32);
+        let mut ids = Vec::new();
+        for (index, color) in [[200u8, 30, 30, 255], [30, 200, 30, 160], [30, 30, 200, 100]].into_iter().enumerate() {
+            let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(24 + index as u32 * 8, 20, |x, _| {
+                Rgba([color[0], color[1], (x * 5) as u8, color[3]])
+            }));
+            let id = LayerId::new();
+            let mut layer = Layer::with_image(format!("Layer {}", index), image);
+            layer.set_transform(LayerTransform::translation(index as f32 * 6.0, index as f32 * 3.0));
+            doc.insert_layer_raw(index, id.clone(), Arc::new(RwLock::new(layer)));
+            ids.push(id);
+        }
+        let asset = doc.add_asset(DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 10, Rgba([255, 255, 0, 255]))));
+        ids.push(doc.add_asset_layer("Asset", asset));
+        (doc, ids)
+    }
+
+    fn mutate(doc: &mut Document, ids: &[LayerId]) {
+        doc.set_layer_transform(&ids[0], LayerTransform::translation(-10.0, 4.0)).unwrap();
+        doc.get_layer(&ids[1]).unwrap().write().set_blend_mode(BlendMode::Multiply);
+        doc.remove_layer(&ids[2]).unwrap();
+        let top = doc.add_layer();
+        doc.get_layer(&top).unwrap().write().node_graph_mut().add_node(aurion_core::Node::new(Box::new(
+            aurion_std_nodes::ImageNode::with_image(DynamicImage::ImageRgba8(RgbaImage::from_pixel(48, 32, Rgba([0, 0, 0, 255])))),
+        )));
+        doc.merge_down(&top).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_renders_state_before_concurrent_edits() {
+        for warm in [true, false] {
+            let (mut doc, ids) = document();
+            let expected = doc.render_composite().unwrap().to_rgba8();
+            if !warm {
+                doc.clear_render_cache();
+            }
+
+            let snapshot = doc.snapshot();
+            let worker = {
+                let snapshot = snapshot.clone();
+                thread::spawn(move || snapshot.render_composite().unwrap().to_rgba8())
+            };
+            mutate(&mut doc, &ids);
+
+            assert_eq!(worker.join().unwrap(), expected, "warm cache: {}", warm);
+            assert_ne!(doc.render_composite().unwrap().to_rgba8(), expected);
+            assert_eq!(snapshot.layers().len(), 4);
+            assert_eq!(snapshot.render_region(CanvasRect::new(4, 4, 20, 10)).unwrap().to_rgba8(),
+                image::imageops::crop_imm(&expected, 4, 4, 20, 10).to_image());
+        }
+    }
+
+    #[test]
+    fn test_snapshot_keeps_parameters_from_before_edits() {
+        let (doc, ids) = document();
+        let blur = {
+            let layer = doc.get_layer(&ids[0]).unwrap();
+            let mut layer = layer.write();
+            let mut graph = layer.node_graph_mut();
+            let output = graph.output_nodes().pop().unwrap();
+            let blur = graph.add_node(aurion_core::Node::new(Box::new(aurion_std_nodes::filters::BlurNode::new(1.0))));
+            graph.connect(&output, &blur, "input").unwrap();
+            blur
+        };
+        let expected = doc.render_composite().unwrap().to_rgba8();
+        doc.clear_render_cache();
+        let snapshot = doc.snapshot();
+
+        // Changed in place, the way parameter edits are
+        let node = doc.get_layer(&ids[0]).unwrap().read().node_graph().get_node(&blur).unwrap();
+        node.write().data_mut().set_parameter("sigma", serde_json::json!(4.0)).unwrap();
+        assert_ne!(doc.render_composite().unwrap().to_rgba8(), expected);
+        assert_eq!(snapshot.render_composite().unwrap().to_rgba8(), expected);
+    }
+
+    #[test]
+    fn test_snapshot_shares_cached_outputs() {
+        let (doc, ids) = document();
+        doc.render_composite().unwrap();
+        let snapshot = doc.snapshot();
+
+        let cached = doc.render_cache.lookup(&ids[0], doc.get_layer(&ids[0]).unwrap().read().node_graph().content_hash());
+        match (&snapshot.layers()[0].source, cached) {
+            (LayerSource::Rendered(Some(shared)), Some(Some(cached))) => assert!(Arc::ptr_eq(shared, &cached)),
+            other => panic!("expected a shared output, got {:?}", other.0),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Viewing a single layer: solo mode and isolated layer renders.
+
+use image::DynamicImage;
+use crate::composite::flatten;
+use crate::{BlendMode, Document, DocumentError, DocumentEvent, Layer, LayerId};
+
+impl Document {
+    /// While set, composites show only `layer`, drawn with its blend mode
+    /// and opacity over a transparent canvas, whether or not it's visible.
+    /// The layers' visibility is left alone.
+    ///
+    /// Solo is a viewing state: it isn't saved or recorded in the history.
+    /// It lapses if the layer is removed.
+    pub fn set_solo(&mut self, layer: Option<LayerId>) -> Result<(), DocumentError> {
+        if let Some(id) = &layer {
+            if !self.layers.contains_key(id) {
+                return Err(DocumentError::LayerNotFound(id.0));
+            }
+        }
+        if self.solo == layer {
+            return Ok(());
+        }
+        self.solo = layer.clone();
+        self.events.emit(DocumentEvent::SoloChanged(layer));
+        self.invalidate_region(self.canvas_rect());
+        Ok(())
+    }
+
+    /// The soloed layer, if it still exists.
+    pub fn solo(&self) -> Option<&LayerId> {
+        self.solo.as_ref().filter(|id| self.layers.contains_key(*id))
+    }
+
+    /// Whether composites draw the layer, given solo mode.
+    pub(crate) fn is_shown(&self, id: &LayerId, layer: &Layer) -> bool {
+        match self.solo() {
+            Some(solo) => solo == id,
+            None => layer.is_visible(),
+        }
+    }
+
+    /// Renders one layer alone on a transparent canvas of the document's
+    /// size, placed by its transform. Visibility, blend mode and opacity are
+    /// ignored.
+    pub fn render_layer(&self, id: &LayerId) -> Result<DynamicImage, DocumentError> {
+        let (output, transform) = self.with_layer(id, |layer| {
+            self.layer_output(id, layer, &self.eval_context())
+                .map(|output| (output, layer.transform()))
+        })??;
+        flatten(self.canvas_rect(), self.canvas_rect(), false, |region, draw| {
+            if let Some((image, x, y)) = output.as_ref().and_then(|output| transform.place(output, region)) {
+                draw(&image, x, y, BlendMode::Normal, 1.0);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use image::{Rgba, RgbaImage};
+    use parking_lot::{Mutex, RwLock};
+    use crate::LayerTransform;
+
+    fn document() -> (Document, LayerId, LayerId) {
+        let mut doc = Document::with_size(8, 8);
+        let mut ids = Vec::new();
+        for (index, (size, color)) in [(8, [200u8, 0, 0, 255]), (4, [0, 200, 0, 255])].into_iter().enumerate() {
+            let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(size, size, Rgba(color)));
+            let id = LayerId::new();
+            doc.insert_layer_raw(index, id.clone(), Arc::new(RwLock::new(Layer::with_image(format!("Layer {}", index), image))));
+            ids.push(id);
+        }
+        (doc, ids[0].clone(), ids[1].clone())
+    }
+
+    fn pixel(image: &DynamicImage, x: u32, y: u32) -> [u8; 4] {
+        image.to_rgba8().get_pixel(x, y).0
+    }
+
+    #[test]
+    fn test_solo_shows_only_that_layer() {
+        let (mut doc, bottom, top) = document();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        doc.subscribe(move |event| {
+            if let DocumentEvent::SoloChanged(solo) = event {
+                sink.lock().push(solo.clone());
+            }
+        });
+        let normal = doc.render_composite().unwrap();
+        assert_eq!(pixel(&normal, 1, 1), [0, 200, 0, 255]);
+
+        doc.get_layer(&bottom).unwrap().write().set_visible(false);
+        doc.set_solo(Some(bottom.clone())).unwrap();
+        let solo = doc.render_composite().unwrap();
+        assert_eq!(pixel(&solo, 1, 1), [200, 0, 0, 255]);
+        assert_eq!(pixel(&solo, 6, 6), [200, 0, 0, 255]);
+        assert!(!doc.get_layer(&bottom).unwrap().read().is_visible());
+        assert!(doc.get_layer(&top).unwrap().read().is_visible());
+
+        // Solo isn't saved
+        let loaded = Document::deserialize(doc.serialize().unwrap()).unwrap();
+        assert_eq!(loaded.solo(), None);
+
+        doc.set_solo(None).unwrap();
+        doc.get_layer(&bottom).unwrap().write().set_visible(true);
+        assert_eq!(doc.render_composite().unwrap().to_rgba8(), normal.to_rgba8());
+        assert_eq!(*events.lock(), vec![Some(bottom), None]);
+        assert!(!doc.is_modified());
+    }
+
+    #[test]
+    fn test_solo_overrides_graph_compositing_and_lapses_on_removal() {
+        let (mut doc, bottom, top) = document();
+        let graph = doc.enable_graph_compositing();
+        let source = graph.add_source(bottom.clone());
+        graph.set_output(Some(source)).unwrap();
+        assert_eq!(pixel(&doc.render_composite().unwrap(), 1, 1), [200, 0, 0, 255]);
+
+        doc.set_solo(Some(top.clone())).unwrap();
+        let solo = doc.render_composite().unwrap();
+        assert_eq!(pixel(&solo, 1, 1), [0, 200, 0, 255]);
+        assert_eq!(pixel(&solo, 6, 6)[3], 0);
+        assert_eq!(pixel(&doc.snapshot().render_composite().unwrap(), 6, 6)[3], 0);
+
+        doc.remove_layer(&top).unwrap();
+        assert_eq!(doc.solo(), None);
+        assert_eq!(pixel(&doc.render_composite().unwrap(), 1, 1), [200, 0, 0, 255]);
+        assert!(doc.set_solo(Some(top)).is_err());
+    }
+
+    #[test]
+    fn test_render_layer_ignores_blending() {
+        let (doc, _, top) = document();
+        {
+            let layer = doc.get_layer(&top).unwrap();
+            let mut layer = layer.write();
+            layer.set_opacity(0.25);
+            layer.set_blend_mode(BlendMode::Multiply);
+            layer.set_visible(false);
+            layer.set_transform(LayerTransform::translation(4.0, 4.0));
+        }
+
+        let image = doc.render_layer(&top).unwrap();
+        assert_eq!(image.width(), 8);
+        assert_eq!(pixel(&image, 5, 5), [0, 200, 0, 255]);
+        assert_eq!(pixel(&image, 1, 1)[3], 0);
+        assert!(doc.render_layer(&LayerId::new()).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+//! Lazily generated, cached layer thumbnails for the layers panel.
+
+use aurion_core::EvalContext;
+use image::DynamicImage;
+use parking_lot::Mutex;
+use tracing::warn;
+use aurion_std_nodes::resample::box_downsample;
+use crate::{Document, DocumentError, DocumentEvent, Layer, LayerId};
+
+#[derive(Default)]
+pub(crate) struct ThumbnailCache {
+    entry: Mutex<Option<(u32, DynamicImage)>>,
+}
+
+impl ThumbnailCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn invalidate(&self) {
+        *self.entry.lock() = None;
+    }
+
+    /// Pixel memory of the cached thumbnail.
+    pub fn bytes(&self) -> usize {
+        self.entry.lock().as_ref().map_or(0, |(_, image)| image.as_bytes().len())
+    }
+}
+
+impl Layer {
+    /// Returns the layer's output downsampled to fit within `max_dim`.
+    ///
+    /// The thumbnail is rendered on first request and reused until the
+    /// layer's graph changes or a different size is requested.
+    pub fn thumbnail(&self, max_dim: u32) -> Option<DynamicImage> {
+        self.thumbnail_with(max_dim, &EvalContext::new())
+    }
+
+    pub fn thumbnail_with(&self, max_dim: u32, context: &EvalContext) -> Option<DynamicImage> {
+        let mut entry = self.thumbnail.entry.lock();
+        if let Some((dim, image)) = &*entry {
+            if *dim == max_dim {
+                return Some(image.clone());
+            }
+        }
+
+        let output = match self.render_output_with(context) {
+            Ok(output) => output?,
+            Err(e) => {
+                warn!("Failed to render thumbnail for layer '{}': {}", self.name, e);
+                return None;
+            }
+        };
+        let thumbnail = box_downsample(&output, max_dim.max(1));
+        *entry = Some((max_dim, thumbnail.clone()));
+        Some(thumbnail)
+    }
+
+    pub fn invalidate_thumbnail(&self) {
+        self.thumbnail.invalidate();
+    }
+}
+
+impl Document {
+    /// Thumbnails for every layer in stack order, bottom first.
+    pub fn thumbnails(&self, max_dim: u32) -> Vec<(LayerId, Option<DynamicImage>)> {
+        let context = self.eval_context();
+        self.layer_order
+            .iter()
+            .filter_map(|id| {
+                self.get_layer(id)
+                    .map(|layer| (id.clone(), layer.read().thumbnail_with(max_dim, &context)))
+            })
+            .collect()
+    }
+
+    /// Reports that a layer's graph was edited through a node handle, dropping
+    /// cached renders of it and of layers linked to it, and emitting
+    /// [`DocumentEvent::GraphChanged`] for each.
+    /// The whole canvas is invalidated since the edit may change anything;
+    /// see [`Document::notify_layer_region_changed`] for local edits.
+    pub fn notify_graph_changed(&mut self, id: &LayerId) -> Result<(), DocumentError> {
+        if self.get_layer(id).is_none() {
+            return Err(DocumentError::LayerNotFound(id.0));
+        }
+        for linked in self.linked_layers(id) {
+            if let Some(layer) = self.get_layer(&linked) {
+                layer.read().invalidate_thumbnail();
+            }
+            self.events.emit(DocumentEvent::GraphChanged(linked));
+        }
+        self.invalidate_region(self.canvas_rect());
+        self.mark_modified();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::Any;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use aurion_core::{Node, NodeData, NodeError, NodeValue};
+    use image::{Rgba, RgbaImage};
+
+    #[derive(Debug, Clone)]
+    struct CountingImageNode {
+        image: DynamicImage,
+        computes: Arc<AtomicUsize>,
+    }
+
+    impl NodeData for CountingImageNode {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn type_name(&self) -> &'static str {
+            "CountingImageNode"
+        }
+
+        fn clone_box(&self) -> Box<dyn NodeData> {
+            Box::new(self.clone())
+        }
+
+        fn compute(&self, _inputs: &[NodeValue]) -> Result<NodeValue, NodeError> {
+            self.computes.fetch_add(1, Ordering::SeqCst);
+            Ok(NodeValue::image(self.image.clone()))
+        }
+    }
+
+    fn counting_layer(doc: &mut Document, width: u32, height: u32) -> (LayerId, Arc<AtomicUsize>) {
+        let computes = Arc::new(AtomicUsize::new(0));
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba([10, 20, 30, 255])));
+        let id = doc.add_layer();
+        doc.get_layer(&id).unwrap().write().node_graph_mut().add_node(Node::new(Box::new(
+            CountingImageNode { image, computes: computes.clone() },
+        )));
+        (id, computes)
+    }
+
+    #[test]
+    fn test_thumbnail_cache_hit_and_miss() {
+        let mut doc = Document::new();
+        let (id, computes) = counting_layer(&mut doc, 64, 32);
+        let layer = doc.get_layer(&id).unwrap();
+
+        assert!(layer.read().thumbnail(16).is_some());
+        assert!(layer.read().thumbnail(16).is_some());
+        assert_eq!(computes.load(Ordering::SeqCst), 1);
+
+        // A different size is a miss
+        layer.read().thumbnail(8);
+        assert_eq!(computes.load(Ordering::SeqCst), 2);
+
+        // Graph edits invalidate, but regeneration waits for the next request
+        doc.notify_graph_changed(&id).unwrap();
+        assert_eq!(computes.load(Ordering::SeqCst), 2);
+        layer.read().thumbnail(8);
+        assert_eq!(computes.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_node_graph_mut_invalidates() {
+        let mut doc = Document::new();
+        let (id, computes) = counting_layer(&mut doc, 10, 10);
+        let layer = doc.get_layer(&id).unwrap();
+
+        layer.read().thumbnail(4);
+        layer.read().thumbnail(4);
+        assert_eq!(computes.load(Ordering::SeqCst), 1);
+        layer.write().node_graph_mut();
+        layer.read().thumbnail(4);
+        assert_eq!(computes.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_thumbnail_aspect_ratio() {
+        let mut doc = Document::new();
+        let (id, _) = counting_layer(&mut doc, 300, 100);
+        counting_layer(&mut doc, 50, 200);
+
+        let thumbnails = doc.thumbnails(60);
+        assert_eq!(thumbnails.len(), 2);
+        assert_eq!(thumbnails[0].0, id);
+
+        let wide = thumbnails[0].1.as_ref().unwrap();
+        assert_eq!((wide.width(), wide.height()), (60, 20));
+        let tall = thumbnails[1].1.as_ref().unwrap();
+        assert_eq!((tall.width(), tall.height()), (15, 60));
+    }
+}
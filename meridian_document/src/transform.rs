@@ -0,0 +1,288 @@
+//! Per-layer placement of a layer's output on the canvas.
+
+use std::error::Error;
+use aurion_core::EvalContext;
+use aurion_std_nodes::transform::{transform_image_with, Affine2, ResampleFilter};
+use image::DynamicImage;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use crate::{CanvasRect, Command, CommandRecord, Document, DocumentError, Layer, LayerId};
+
+/// Position, scale, and rotation of a layer's output on the canvas.
+///
+/// Scale and rotation are applied about `pivot`, given as a fraction of the
+/// layer output's size (`[0.5, 0.5]` is its center). The result is then
+/// offset by `x`, `y` canvas pixels. `filter` is used whenever the output
+/// has to be resampled.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayerTransform {
+    pub x: f32,
+    pub y: f32,
+    pub scale_x: f32,
+    pub scale_y: f32,
+    pub rotation_degrees: f32,
+    pub pivot: [f32; 2],
+    pub filter: ResampleFilter,
+}
+
+impl LayerTransform {
+    pub const IDENTITY: Self = Self {
+        x: 0.0,
+        y: 0.0,
+        scale_x: 1.0,
+        scale_y: 1.0,
+        rotation_degrees: 0.0,
+        pivot: [0.5, 0.5],
+        filter: ResampleFilter::Bilinear,
+    };
+
+    pub fn translation(x: f32, y: f32) -> Self {
+        Self { x, y, ..Self::IDENTITY }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.x == 0.0 && self.y == 0.0 && !self.scales_or_rotates()
+    }
+
+    /// Identity placement with the default filter, i.e. nothing to store.
+    pub fn is_default(&self) -> bool {
+        *self == Self::IDENTITY
+    }
+
+    fn scales_or_rotates(&self) -> bool {
+        self.scale_x != 1.0 || self.scale_y != 1.0 || self.rotation_degrees % 360.0 != 0.0
+    }
+
+    /// The map from layer output pixels to canvas pixels for an output of
+    /// `width`×`height`.
+    pub fn to_affine(&self, width: u32, height: u32) -> Affine2 {
+        let pivot_x = self.pivot[0] as f64 * width as f64;
+        let pivot_y = self.pivot[1] as f64 * height as f64;
+        Affine2::translate(-pivot_x, -pivot_y)
+            .then(&Affine2::scale(self.scale_x as f64, self.scale_y as f64))
+            .then(&Affine2::rotate_degrees(self.rotation_degrees as f64))
+            .then(&Affine2::translate(pivot_x + self.x as f64, pivot_y + self.y as f64))
+    }
+
+    /// Canvas pixels touched by `rect` of a `width`×`height` layer output.
+    pub fn map_rect(&self, rect: CanvasRect, width: u32, height: u32) -> CanvasRect {
+        let affine = self.to_affine(width, height);
+        let (mut min_x, mut min_y) = (f64::INFINITY, f64::INFINITY);
+        let (mut max_x, mut max_y) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for (x, y) in [(rect.x, rect.y), (rect.right(), rect.y), (rect.x, rect.bottom()), (rect.right(), rect.bottom())] {
+            let (cx, cy) = affine.apply(x as f64, y as f64);
+            min_x = min_x.min(cx);
+            min_y = min_y.min(cy);
+            max_x = max_x.max(cx);
+            max_y = max_y.max(cy);
+        }
+        let (x, y) = (min_x.floor() as i64, min_y.floor() as i64);
+        CanvasRect::new(x, y, (max_x.ceil() as i64 - x) as u32, (max_y.ceil() as i64 - y) as u32)
+    }
+
+    /// Canvas area covered by a `width`×`height` layer output.
+    pub fn bounds(&self, width: u32, height: u32) -> CanvasRect {
+        self.map_rect(CanvasRect::new(0, 0, width, height), width, height)
+    }
+
+    /// Positions `output` and keeps only the part inside `region`.
+    ///
+    /// Returns the image with its offset relative to the region's top-left
+    /// corner, or `None` if nothing lands in the region. Whole-pixel
+    /// translations are cropped without resampling.
+    pub(crate) fn place(&self, output: &DynamicImage, region: CanvasRect) -> Option<(DynamicImage, i64, i64)> {
+        let visible = self.bounds(output.width(), output.height()).intersect(&region)?;
+        if !self.scales_or_rotates() && self.x.fract() == 0.0 && self.y.fract() == 0.0 {
+            let (x, y) = (self.x as i64, self.y as i64);
+            let cropped = output.crop_imm((visible.x - x) as u32, (visible.y - y) as u32, visible.width, visible.height);
+            return Some((cropped, visible.x - region.x, visible.y - region.y));
+        }
+
+        let affine = self
+            .to_affine(output.width(), output.height())
+            .then(&Affine2::translate(-visible.x as f64, -visible.y as f64));
+        let placed = transform_image_with(output, &affine, visible.width, visible.height, self.filter);
+        Some((DynamicImage::ImageRgba8(placed), visible.x - region.x, visible.y - region.y))
+    }
+}
+
+impl Default for LayerTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Layer {
+    /// Renders the layer's output positioned for a `width`×`height` canvas.
+    ///
+    /// Returns the image with the offset to composite it at. Whole-pixel
+    /// translations are returned unresampled; anything else is resampled
+    /// onto the part of the canvas it covers.
+    pub(crate) fn render_placed(
+        &self,
+        context: &EvalContext,
+        width: u32,
+        height: u32,
+    ) -> Result<Option<(DynamicImage, i64, i64)>, DocumentError> {
+        let Some(output) = self.render_output_with(context)? else {
+            return Ok(None);
+        };
+        Ok(self.transform.place(&output, CanvasRect::new(0, 0, width, height)))
+    }
+
+}
+
+impl Document {
+    /// Canvas area covered by a layer, if its output has been rendered.
+    pub(crate) fn placed_bounds(&self, id: &LayerId, layer: &Layer) -> Option<CanvasRect> {
+        let size = self.render_cache.output_size(id)?;
+        Some(size.map_or(CanvasRect::new(0, 0, 0, 0), |(width, height)| layer.transform().bounds(width, height)))
+    }
+}
+
+/// Sets a layer's transform, restoring the previous one on undo.
+#[derive(Debug)]
+pub struct SetLayerTransformCommand {
+    layer_id: LayerId,
+    transform: LayerTransform,
+    previous: Mutex<Option<LayerTransform>>,
+}
+
+impl SetLayerTransformCommand {
+    pub fn new(layer_id: LayerId, transform: LayerTransform) -> Self {
+        Self {
+            layer_id,
+            transform,
+            previous: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn restored(layer_id: LayerId, transform: LayerTransform, previous: Option<LayerTransform>) -> Self {
+        Self {
+            layer_id,
+            transform,
+            previous: Mutex::new(previous),
+        }
+    }
+}
+
+impl Command for SetLayerTransformCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        document.with_layer_mut(&self.layer_id, |layer| {
+            *self.previous.lock() = Some(layer.transform());
+            let before = document.placed_bounds(&self.layer_id, layer);
+            layer.set_transform(self.transform);
+            document.invalidate_moved_layer(before, document.placed_bounds(&self.layer_id, layer));
+        })?;
+        Ok(())
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        let Some(previous) = self.previous.lock().take() else {
+            return Ok(());
+        };
+        document.with_layer_mut(&self.layer_id, |layer| {
+            let before = document.placed_bounds(&self.layer_id, layer);
+            layer.set_transform(previous);
+            document.invalidate_moved_layer(before, document.placed_bounds(&self.layer_id, layer));
+        })?;
+        Ok(())
+    }
+
+    fn to_record(&self) -> Option<CommandRecord> {
+        Some(CommandRecord::SetLayerTransform {
+            layer_id: self.layer_id.clone(),
+            transform: self.transform,
+            previous: *self.previous.lock(),
+        })
+    }
+}
+
+impl Document {
+    /// Sets a layer's transform through the history.
+    pub fn set_layer_transform(&mut self, id: &LayerId, transform: LayerTransform) -> Result<(), DocumentError> {
+        if self.get_layer(id).is_none() {
+            return Err(DocumentError::LayerNotFound(id.0));
+        }
+        self.execute_command(Box::new(SetLayerTransformCommand::new(id.clone(), transform)))
+    }
+
+    /// Sets a layer's transform outside the history, e.g. while a transform
+    /// handle is dragged. Put the original back before recording the result
+    /// with [`Document::set_layer_transform`], so undo returns to it.
+    pub fn preview_layer_transform(&self, id: &LayerId, transform: LayerTransform) -> Result<(), DocumentError> {
+        self.with_layer_mut(id, |layer| {
+            let before = self.placed_bounds(id, layer);
+            layer.set_transform(transform);
+            self.invalidate_moved_layer(before, self.placed_bounds(id, layer));
+        })
+    }
+
+    /// Size of a layer's output before its transform, as of its last render.
+    /// `None` until it's rendered, and for layers without an image.
+    pub fn layer_output_size(&self, id: &LayerId) -> Option<(u32, u32)> {
+        self.render_cache.output_size(id).flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use image::{Rgba, RgbaImage};
+    use parking_lot::RwLock;
+
+    fn add_image_layer(doc: &mut Document, image: DynamicImage) -> LayerId {
+        let id = LayerId::new();
+        let index = doc.layer_count();
+        doc.insert_layer_raw(index, id.clone(), Arc::new(RwLock::new(Layer::with_image("Layer", image))));
+        id
+    }
+
+    #[test]
+    fn test_translated_layer_lands_in_place() {
+        let mut doc = Document::with_size(64, 64);
+        let red = DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 10, Rgba([255, 0, 0, 255])));
+        let id = add_image_layer(&mut doc, red);
+        doc.set_layer_transform(&id, LayerTransform::translation(20.0, 30.0)).unwrap();
+
+        let composite = doc.render_composite().unwrap().to_rgba8();
+        for (x, y, pixel) in composite.enumerate_pixels() {
+            let inside = (20..30).contains(&x) && (30..40).contains(&y);
+            let expected = if inside { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 0, 0]) };
+            assert_eq!(*pixel, expected, "pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn test_quarter_turn_about_center() {
+        let mut doc = Document::with_size(10, 10);
+        let source = RgbaImage::from_fn(10, 10, |x, y| Rgba([(x * 25) as u8, (y * 25) as u8, 100, 255]));
+        let id = add_image_layer(&mut doc, DynamicImage::ImageRgba8(source.clone()));
+        let transform = LayerTransform { rotation_degrees: 90.0, ..LayerTransform::IDENTITY };
+        doc.set_layer_transform(&id, transform).unwrap();
+
+        // A clockwise quarter turn about the center sends (x, y) to (9 - y, x)
+        let composite = doc.render_composite().unwrap().to_rgba8();
+        for (x, y, pixel) in source.enumerate_pixels() {
+            assert_eq!(composite.get_pixel(9 - y, x), pixel);
+        }
+    }
+
+    #[test]
+    fn test_set_transform_undo_and_serialization() {
+        let mut doc = Document::with_size(8, 8);
+        let id = add_image_layer(&mut doc, DynamicImage::ImageRgba8(RgbaImage::new(2, 2)));
+        let transform = LayerTransform { x: 1.5, scale_y: 2.0, rotation_degrees: 15.0, ..LayerTransform::IDENTITY };
+        doc.set_layer_transform(&id, transform).unwrap();
+
+        let loaded = Document::deserialize(doc.serialize().unwrap()).unwrap();
+        assert_eq!(loaded.get_layer(&id).unwrap().read().transform(), transform);
+
+        doc.undo().unwrap();
+        assert!(doc.get_layer(&id).unwrap().read().transform().is_identity());
+        doc.redo().unwrap();
+        assert_eq!(doc.get_layer(&id).unwrap().read().transform(), transform);
+    }
+}
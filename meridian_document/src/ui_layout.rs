@@ -0,0 +1,61 @@
+//! Editor layout saved with the document, e.g. node positions in the node
+//! editor. The document stores it per layer without interpreting it.
+
+use serde_json::Value;
+use crate::{Document, DocumentError, DocumentEvent, LayerId};
+
+impl Document {
+    /// The editor layout stored for `layer`, if any.
+    pub fn ui_layout(&self, layer: &LayerId) -> Option<&Value> {
+        self.ui_layout.get(layer).filter(|_| self.layers.contains_key(layer))
+    }
+
+    /// Stores `layout` for `layer`, to be saved in the document's
+    /// `ui_layout` section. Marks the document modified and emits
+    /// [`crate::DocumentEvent::UiLayoutChanged`] if it changed, but isn't
+    /// recorded in the history.
+    pub fn set_ui_layout(&mut self, layer: &LayerId, layout: Value) -> Result<(), DocumentError> {
+        if !self.layers.contains_key(layer) {
+            return Err(DocumentError::LayerNotFound(layer.0));
+        }
+        if self.ui_layout.get(layer) != Some(&layout) {
+            self.ui_layout.insert(layer.clone(), layout);
+            self.mark_modified();
+            self.events.emit(DocumentEvent::UiLayoutChanged(layer.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use parking_lot::Mutex;
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn test_ui_layout_round_trips_and_lapses_with_its_layer() {
+        let mut doc = Document::new();
+        let layer = doc.add_layer();
+        let other = doc.add_layer();
+        let changed = Arc::new(Mutex::new(Vec::new()));
+        let sink = changed.clone();
+        doc.subscribe(move |event| {
+            if let DocumentEvent::UiLayoutChanged(layer) = event {
+                sink.lock().push(layer.clone());
+            }
+        });
+        let layout = json!({ "zoom": 2.0, "anything": ["the", "core", "ignores"] });
+        doc.set_ui_layout(&layer, layout.clone()).unwrap();
+        doc.set_ui_layout(&layer, layout.clone()).unwrap();
+        assert_eq!(*changed.lock(), vec![layer.clone()]);
+        doc.set_ui_layout(&other, json!({})).unwrap();
+        assert!(doc.set_ui_layout(&LayerId::new(), json!({})).is_err());
+
+        doc.remove_layer(&other).unwrap();
+        let loaded = Document::deserialize(doc.serialize().unwrap()).unwrap();
+        assert_eq!(loaded.ui_layout(&layer), Some(&layout));
+        assert_eq!(loaded.ui_layout(&other), None);
+    }
+}
@@ -0,0 +1,240 @@
+//! `polaris batch`: runs a pipeline over every image in a directory, a few
+//! files at a time.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use clap::Args;
+use image::ImageFormat;
+use meridian_document::ExportFormat;
+use crate::error::{CliError, EXIT_INTERRUPTED};
+use crate::output::FormatArg;
+use crate::run::{self, parse_override, Override, Pipeline};
+
+/// Set by Ctrl+C: workers finish the file they're on and take no more.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Args)]
+pub struct BatchArgs {
+    /// Pipeline to run over each image
+    pub pipeline: PathBuf,
+    /// Directory of images, searched recursively
+    #[arg(long)]
+    pub input_dir: PathBuf,
+    /// Directory to write results to, mirroring the input's subdirectories
+    #[arg(long)]
+    pub output_dir: PathBuf,
+    /// Files processed at the same time; defaults to the number of CPUs
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub jobs: Option<u32>,
+    /// Appended to each output's file name, e.g. _edited
+    #[arg(long, default_value = "")]
+    pub suffix: String,
+    /// Overrides a node parameter, e.g. GaussianBlur1.sigma=4.5
+    #[arg(long = "set", value_name = "NODE.PARAMETER=VALUE", value_parser = parse_override)]
+    pub overrides: Vec<Override>,
+    /// Output format; each output keeps its input's format when left out,
+    /// or is PNG when that can't be written
+    #[arg(long, value_enum)]
+    pub format: Option<FormatArg>,
+    /// JPEG quality from 1 to 100
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: Option<u8>,
+    /// Process files even when their output is newer than them and the
+    /// pipeline
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// One image to process and where its result goes.
+#[derive(Debug, Clone)]
+struct Job {
+    input: PathBuf,
+    output: PathBuf,
+}
+
+enum Outcome {
+    Processed,
+    Skipped,
+    Failed(String),
+}
+
+/// How a batch went, in input order.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub processed: usize,
+    pub skipped: usize,
+    /// Each failed input and why.
+    pub failed: Vec<(PathBuf, String)>,
+    /// Files left unprocessed after Ctrl+C.
+    pub interrupted: usize,
+}
+
+impl Summary {
+    fn print(&self) {
+        println!("Processed {}, skipped {}, failed {}", self.processed, self.skipped, self.failed.len());
+        for (input, reason) in &self.failed {
+            println!("  {}: {}", input.display(), reason);
+        }
+        if self.interrupted > 0 {
+            println!("Interrupted, {} files not processed", self.interrupted);
+        }
+    }
+}
+
+pub fn run(args: &BatchArgs) -> Result<(), CliError> {
+    // A second Ctrl+C doesn't wait for the files in flight
+    let handled = ctrlc::set_handler(|| {
+        if STOP.swap(true, Ordering::SeqCst) {
+            std::process::exit(i32::from(EXIT_INTERRUPTED));
+        }
+        eprintln!("Stopping after the files in progress, press Ctrl+C again to quit now");
+    });
+    if let Err(e) = handled {
+        eprintln!("warning: Ctrl+C will stop the batch midway: {}", e);
+    }
+
+    let summary = batch(args)?;
+    summary.print();
+    if summary.interrupted > 0 {
+        Err(CliError::Interrupted)
+    } else if !summary.failed.is_empty() {
+        let total = summary.processed + summary.skipped + summary.failed.len();
+        Err(CliError::Batch { failed: summary.failed.len(), total })
+    } else {
+        Ok(())
+    }
+}
+
+/// Processes every image under the input directory on `--jobs` threads.
+/// Each thread builds its own graph from the pipeline for every file.
+pub fn batch(args: &BatchArgs) -> Result<Summary, CliError> {
+    let pipeline = Pipeline::load(&args.pipeline, &args.overrides)?;
+    let pipeline_modified = modified(&args.pipeline);
+    let jobs = jobs(args)?;
+    let threads = args
+        .jobs
+        .map(|jobs| jobs as usize)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+        .min(jobs.len())
+        .max(1);
+
+    let next = AtomicUsize::new(0);
+    let outcomes: Mutex<Vec<Option<Outcome>>> = Mutex::new((0..jobs.len()).map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            let pipeline = pipeline.clone();
+            let (jobs, next, outcomes) = (&jobs, &next, &outcomes);
+            scope.spawn(move || {
+                while !STOP.load(Ordering::SeqCst) {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(job) = jobs.get(index) else {
+                        break;
+                    };
+                    let outcome = if !args.force && up_to_date(job, pipeline_modified) {
+                        Outcome::Skipped
+                    } else {
+                        match process(&pipeline, job, args) {
+                            Ok(()) => Outcome::Processed,
+                            Err(e) => Outcome::Failed(e.to_string()),
+                        }
+                    };
+                    outcomes.lock().unwrap()[index] = Some(outcome);
+                }
+            });
+        }
+    });
+
+    let mut summary = Summary::default();
+    for (job, outcome) in jobs.iter().zip(outcomes.into_inner().unwrap()) {
+        match outcome {
+            Some(Outcome::Processed) => summary.processed += 1,
+            Some(Outcome::Skipped) => summary.skipped += 1,
+            Some(Outcome::Failed(reason)) => summary.failed.push((job.input.clone(), reason)),
+            None => summary.interrupted += 1,
+        }
+    }
+    Ok(summary)
+}
+
+fn process(pipeline: &Pipeline, job: &Job, args: &BatchArgs) -> Result<(), CliError> {
+    let image = image::open(&job.input).map_err(|e| CliError::load(&job.input, e))?;
+    let (output, _) = pipeline.run(image)?;
+    if let Some(dir) = job.output.parent() {
+        fs::create_dir_all(dir).map_err(|e| CliError::encode(&job.output, e))?;
+    }
+    run::write(&output, &job.output, args.format, args.quality)
+}
+
+/// The images under the input directory, sorted, with their outputs.
+fn jobs(args: &BatchArgs) -> Result<Vec<Job>, CliError> {
+    let mut inputs = Vec::new();
+    // Outputs written inside the input directory aren't inputs next time
+    let skip = args.output_dir.canonicalize().ok();
+    find_images(&args.input_dir, skip.as_deref(), &mut inputs).map_err(|e| CliError::load(&args.input_dir, e))?;
+    inputs.sort();
+    Ok(inputs
+        .into_iter()
+        .map(|input| {
+            let relative = input.strip_prefix(&args.input_dir).unwrap_or(&input);
+            let output = args.output_dir.join(output_name(relative, &args.suffix, args.format));
+            Job { input, output }
+        })
+        .collect())
+}
+
+fn find_images(dir: &Path, skip: Option<&Path>, images: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if skip.is_none() || path.canonicalize().ok().as_deref() != skip {
+                find_images(&path, skip, images)?;
+            }
+        } else if ImageFormat::from_path(&path).map_or(false, |format| format.reading_enabled()) {
+            images.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// `relative` with `suffix` added to the file name, and the extension of
+/// `format`, or its own when that's a format outputs can be written in.
+fn output_name(relative: &Path, suffix: &str, format: Option<FormatArg>) -> PathBuf {
+    let stem = relative.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = match format {
+        Some(format) => ExportFormat::from(format).extension().to_string(),
+        None if ExportFormat::from_extension(relative).is_some() => {
+            relative.extension().unwrap_or_default().to_string_lossy().into_owned()
+        }
+        None => ExportFormat::Png.extension().to_string(),
+    };
+    relative.with_file_name(format!("{}{}.{}", stem, suffix, extension))
+}
+
+/// Whether the output was written after both its input and the pipeline
+/// last changed.
+fn up_to_date(job: &Job, pipeline_modified: Option<SystemTime>) -> bool {
+    let (Some(output), Some(input)) = (modified(&job.output), modified(&job.input)) else {
+        return false;
+    };
+    output >= input && pipeline_modified.map_or(false, |pipeline| output >= pipeline)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_name() {
+        let name = |path: &str, suffix: &str, format| output_name(Path::new(path), suffix, format);
+        assert_eq!(name("a/b/photo.JPG", "_edited", None), PathBuf::from("a/b/photo_edited.JPG"));
+        assert_eq!(name("photo.png", "", Some(FormatArg::Jpeg)), PathBuf::from("photo.jpg"));
+        assert_eq!(name("scan.tiff", "_x", None), PathBuf::from("scan_x.png"));
+    }
+}
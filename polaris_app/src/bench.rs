@@ -0,0 +1,240 @@
+//! `polaris bench`: evaluates a document or pipeline repeatedly and reports
+//! where the time goes, node by node.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use aurion_core::spec::BuiltGraph;
+use aurion_core::{EvaluationReport, NodeId};
+use clap::Args;
+use meridian_document::Document;
+use serde::Serialize;
+use crate::error::CliError;
+use crate::render;
+use crate::run::{is_pipeline, Evaluation, Pipeline};
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    /// Document or pipeline to benchmark
+    pub source: PathBuf,
+    /// Image fed to a pipeline's Input node
+    #[arg(short, long)]
+    pub input: Option<PathBuf>,
+    /// Measured evaluations
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    pub runs: u32,
+    /// Evaluations run first and left out of the results
+    #[arg(long, default_value_t = 1)]
+    pub warmup: u32,
+    /// Print the results as JSON
+    #[arg(long)]
+    pub json: bool,
+    /// Evaluate a document's layers, or a pipeline's independent branches,
+    /// at the same time. Node times then overlap, so they can add up to more
+    /// than the total. Pipelines evaluated this way don't cache
+    #[arg(long)]
+    pub parallel: bool,
+    /// Clear the document's render cache, or the pipeline's results, before
+    /// each evaluation, so every node is evaluated
+    #[arg(long)]
+    pub no_cache: bool,
+    /// Nodes listed in the table, hottest first
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+/// Results of a benchmark, as printed by `--json`.
+#[derive(Debug, Serialize)]
+pub struct Bench {
+    pub source: PathBuf,
+    pub runs: u32,
+    pub warmup: u32,
+    pub parallel: bool,
+    /// Whether cached layers were reused between evaluations.
+    pub cache: bool,
+    /// Wall time of whole evaluations.
+    pub total: Timing,
+    /// Hottest first, by mean time.
+    pub nodes: Vec<NodeStats>,
+}
+
+/// Times over a set of samples, in milliseconds.
+#[derive(Debug, Clone, Serialize)]
+pub struct Timing {
+    pub mean_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    pub total_ms: f64,
+}
+
+impl Timing {
+    fn new(samples: &[Duration]) -> Self {
+        let ms: Vec<f64> = samples.iter().map(|sample| sample.as_secs_f64() * 1000.0).collect();
+        let total_ms = ms.iter().sum::<f64>();
+        Self {
+            mean_ms: total_ms / ms.len().max(1) as f64,
+            min_ms: ms.iter().cloned().reduce(f64::min).unwrap_or(0.0),
+            max_ms: ms.iter().cloned().reduce(f64::max).unwrap_or(0.0),
+            total_ms,
+        }
+    }
+}
+
+/// One node's own computation times across all measured evaluations.
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeStats {
+    pub id: String,
+    /// The pipeline node's name, or the graph a document's node sits in.
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: &'static str,
+    /// Times the node was computed; nodes served from the cache, or reached
+    /// more than once per evaluation, differ from the run count.
+    pub evaluations: usize,
+    #[serde(flatten)]
+    pub timing: Timing,
+}
+
+enum Target {
+    Document(Box<Document>),
+    Pipeline(Pipeline, Box<BuiltGraph>),
+}
+
+impl Target {
+    fn load(args: &BenchArgs) -> Result<Self, CliError> {
+        if !is_pipeline(&args.source) {
+            return Ok(Target::Document(Box::new(render::load(&args.source)?)));
+        }
+        let Some(input) = &args.input else {
+            return Err(CliError::load(&args.source, "Pipelines need an input image, see --input"));
+        };
+        let pipeline = Pipeline::load(&args.source, &[])?;
+        let image = image::open(input).map_err(|e| CliError::load(input, e))?;
+        let built = pipeline.build(image)?;
+        Ok(Target::Pipeline(pipeline, Box::new(built)))
+    }
+
+    fn evaluate(&self, args: &BenchArgs) -> Result<EvaluationReport, CliError> {
+        match self {
+            Target::Document(document) => {
+                if args.no_cache {
+                    document.clear_render_cache();
+                }
+                let (result, report) = if args.parallel {
+                    document.render_composite_parallel_report()
+                } else {
+                    document.render_composite_report()
+                };
+                match result {
+                    Ok(_) => Ok(report),
+                    Err(e) => Err(render::evaluation_error(document, e.into(), &report)),
+                }
+            }
+            // The graph is built once, so node ids stay the same between
+            // runs and cached results carry over
+            Target::Pipeline(pipeline, built) => {
+                if args.no_cache {
+                    built.graph.clear_cache();
+                }
+                Ok(pipeline.evaluate(built, pipeline_evaluation(args))?.1)
+            }
+        }
+    }
+
+    /// Whether evaluations reuse results of earlier ones.
+    fn caches(&self, args: &BenchArgs) -> bool {
+        match self {
+            Target::Document(_) => !args.no_cache,
+            Target::Pipeline(..) => pipeline_evaluation(args) == Evaluation::Cached,
+        }
+    }
+
+    fn name(&self, node: &NodeId) -> String {
+        match self {
+            Target::Document(document) => render::node_owner(document, node),
+            Target::Pipeline(_, built) => built.name_of(node).unwrap_or("?").to_string(),
+        }
+    }
+}
+
+/// How a pipeline is evaluated under the flags given.
+fn pipeline_evaluation(args: &BenchArgs) -> Evaluation {
+    match (args.parallel, args.no_cache) {
+        (true, _) => Evaluation::Parallel,
+        (false, false) => Evaluation::Cached,
+        (false, true) => Evaluation::Sequential,
+    }
+}
+
+pub fn run(args: &BenchArgs) -> Result<(), CliError> {
+    let bench = bench(args)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&bench).expect("bench results serialize"));
+    } else {
+        print_table(&bench, args.top);
+    }
+    Ok(())
+}
+
+/// Runs the warmup and measured evaluations and aggregates their reports.
+pub fn bench(args: &BenchArgs) -> Result<Bench, CliError> {
+    let target = Target::load(args)?;
+    for _ in 0..args.warmup {
+        target.evaluate(args)?;
+    }
+    let mut totals = Vec::new();
+    let mut samples: HashMap<NodeId, (&'static str, Vec<Duration>)> = HashMap::new();
+    for _ in 0..args.runs {
+        let report = target.evaluate(args)?;
+        totals.push(report.total);
+        for timing in report.timings {
+            samples.entry(timing.node).or_insert_with(|| (timing.type_name, Vec::new())).1.push(timing.duration);
+        }
+    }
+
+    let mut nodes: Vec<_> = samples
+        .into_iter()
+        .map(|(node, (type_name, durations))| NodeStats {
+            id: node.to_string(),
+            name: target.name(&node),
+            type_name,
+            evaluations: durations.len(),
+            timing: Timing::new(&durations),
+        })
+        .collect();
+    nodes.sort_by(|a, b| b.timing.mean_ms.total_cmp(&a.timing.mean_ms));
+    Ok(Bench {
+        source: args.source.clone(),
+        runs: args.runs,
+        warmup: args.warmup,
+        parallel: args.parallel,
+        cache: target.caches(args),
+        total: Timing::new(&totals),
+        nodes,
+    })
+}
+
+fn print_table(bench: &Bench, top: usize) {
+    println!(
+        "{}: {} runs after {} warmup, {}, {}",
+        bench.source.display(),
+        bench.runs,
+        bench.warmup,
+        if bench.parallel { "parallel" } else { "sequential" },
+        if bench.cache { "cached" } else { "uncached" }
+    );
+    println!("{:>10} {:>10} {:>10} {:>10} {:>6}  node", "mean ms", "min ms", "max ms", "total ms", "evals");
+    let row = |timing: &Timing, evaluations: usize, label: &str| {
+        println!(
+            "{:>10.3} {:>10.3} {:>10.3} {:>10.3} {:>6}  {}",
+            timing.mean_ms, timing.min_ms, timing.max_ms, timing.total_ms, evaluations, label
+        );
+    };
+    row(&bench.total, bench.runs as usize, "(total)");
+    for node in bench.nodes.iter().take(top) {
+        row(&node.timing, node.evaluations, &format!("{} ({})", node.name, node.type_name));
+    }
+    if bench.nodes.len() > top {
+        println!("... {} more nodes, see --top", bench.nodes.len() - top);
+    }
+}
@@ -0,0 +1,80 @@
+//! How commands fail, and the exit code each failure maps to.
+
+use std::path::PathBuf;
+use thiserror::Error;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Exit code for documents, pipelines or inputs that can't be loaded.
+/// Usage errors exit with 2.
+pub const EXIT_LOAD: u8 = 3;
+/// Exit code for graphs that fail to evaluate, and batches where any file
+/// failed.
+pub const EXIT_EVALUATE: u8 = 4;
+/// Exit code for results that can't be written.
+pub const EXIT_ENCODE: u8 = 5;
+/// Exit code after Ctrl+C, as shells report it.
+pub const EXIT_INTERRUPTED: u8 = 130;
+
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("Couldn't load {}: {source}", path.display())]
+    Load {
+        path: PathBuf,
+        source: BoxError,
+    },
+    #[error("Couldn't render {}: {source}", path.display())]
+    Evaluate {
+        path: PathBuf,
+        source: BoxError,
+        /// Where each failed node sits and why it failed.
+        failures: Vec<String>,
+    },
+    #[error("Couldn't write {}: {source}", path.display())]
+    Encode {
+        path: PathBuf,
+        source: BoxError,
+    },
+    #[error("{failed} of {total} files failed")]
+    Batch {
+        failed: usize,
+        total: usize,
+    },
+    #[error("Interrupted")]
+    Interrupted,
+}
+
+impl CliError {
+    pub fn load(path: impl Into<PathBuf>, source: impl Into<BoxError>) -> Self {
+        CliError::Load { path: path.into(), source: source.into() }
+    }
+
+    pub fn encode(path: impl Into<PathBuf>, source: impl Into<BoxError>) -> Self {
+        CliError::Encode { path: path.into(), source: source.into() }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Load { .. } => EXIT_LOAD,
+            CliError::Evaluate { .. } => EXIT_EVALUATE,
+            CliError::Encode { .. } => EXIT_ENCODE,
+            CliError::Batch { .. } => EXIT_EVALUATE,
+            CliError::Interrupted => EXIT_INTERRUPTED,
+        }
+    }
+
+    pub fn failures(&self) -> &[String] {
+        match self {
+            CliError::Evaluate { failures, .. } => failures,
+            _ => &[],
+        }
+    }
+
+    /// Prints the error and any failed nodes to stderr.
+    pub fn report(&self) {
+        eprintln!("error: {}", self);
+        for failure in self.failures() {
+            eprintln!("  at {}", failure);
+        }
+    }
+}
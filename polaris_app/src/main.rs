@@ -1,20 +1,54 @@
-use aurion_core::{NodeGraph, NodeError, Node};
-use aurion_std_nodes::{ImageNode, BlendNode, BlendMode};
+//! `polaris`, the command line front end: renders documents and runs
+//! pipelines without the desktop UI.
 
-fn main() -> Result<(), NodeError> {
-    let mut graph = NodeGraph::new();
+mod batch;
+mod bench;
+mod error;
+mod output;
+mod render;
+mod run;
+mod watch;
 
-    // Create nodes
-    let image_node = Node::new(Box::new(ImageNode::new()));
-    let blend_node = Node::new(Box::new(BlendNode::new(BlendMode::Normal)));
+use std::process::ExitCode;
+use clap::{Parser, Subcommand};
+use meridian_document::compositing::register_document_nodes;
 
-    // Add nodes to graph
-    let image_id = graph.add_node(image_node);
-    let blend_id = graph.add_node(blend_node);
+#[derive(Debug, Parser)]
+#[command(name = "polaris", version, about = "Render Artemisia documents from the command line")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-    // Connect nodes
-    graph.connect(&image_id, &blend_id, "input")?;
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Render a document's composite to an image file
+    Render(render::RenderArgs),
+    /// Run a pipeline over an input image
+    Run(run::RunArgs),
+    /// Render a document or pipeline again whenever it or its inputs change
+    Watch(watch::WatchArgs),
+    /// Run a pipeline over every image in a directory
+    Batch(batch::BatchArgs),
+    /// Evaluate a document or pipeline repeatedly and time each node
+    Bench(bench::BenchArgs),
+}
 
-    println!("Graph created successfully!");
-    Ok(())
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    register_document_nodes();
+    let result = match cli.command {
+        Command::Render(args) => render::run(&args),
+        Command::Run(args) => run::run(&args),
+        Command::Watch(args) => watch::run(&args),
+        Command::Batch(args) => batch::run(&args),
+        Command::Bench(args) => bench::run(&args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            e.report();
+            ExitCode::from(e.exit_code())
+        }
+    }
 }
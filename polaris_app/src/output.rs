@@ -0,0 +1,107 @@
+//! Writing results to image files.
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use clap::ValueEnum;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageEncoder, ImageOutputFormat};
+use meridian_document::ExportFormat;
+use crate::error::BoxError;
+
+/// JPEG quality when --quality is left out, as for exports.
+const DEFAULT_QUALITY: u8 = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FormatArg {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl From<FormatArg> for ExportFormat {
+    fn from(format: FormatArg) -> Self {
+        match format {
+            FormatArg::Png => ExportFormat::Png,
+            FormatArg::Jpeg => ExportFormat::Jpeg,
+            FormatArg::Webp => ExportFormat::WebP,
+        }
+    }
+}
+
+/// The format to write `path` in: `format` if given, else the one its
+/// extension names.
+pub fn output_format(path: &Path, format: Option<FormatArg>) -> Result<ExportFormat, BoxError> {
+    let format = format
+        .map(ExportFormat::from)
+        .or_else(|| ExportFormat::from_extension(path))
+        .ok_or_else(|| format!("Cannot infer the image format from {}", path.display()))?;
+    Ok(format)
+}
+
+/// Writes `image` as `format`. WebP is lossless and `quality` only applies
+/// to JPEG.
+pub fn write_image(image: &DynamicImage, path: &Path, format: ExportFormat, quality: Option<u8>) -> Result<(), BoxError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        ExportFormat::Png => image.write_to(&mut writer, ImageOutputFormat::Png)?,
+        ExportFormat::Jpeg => {
+            let rgb = image.to_rgb8();
+            JpegEncoder::new_with_quality(writer, quality.unwrap_or(DEFAULT_QUALITY))
+                .write_image(rgb.as_raw(), rgb.width(), rgb.height(), image::ColorType::Rgb8)?
+        }
+        ExportFormat::WebP => {
+            let rgba = image.to_rgba8();
+            WebPEncoder::new_lossless(writer)
+                .write_image(rgba.as_raw(), rgba.width(), rgba.height(), image::ColorType::Rgba8)?
+        }
+    }
+    Ok(())
+}
+
+/// Has `write` fill a temp file next to `path`, then moves it into place,
+/// so whoever reads `path` never sees a half-written image.
+pub fn replace_file<F>(path: &Path, write: F) -> Result<(), BoxError>
+where
+    F: FnOnce(&Path) -> Result<(), BoxError>,
+{
+    let name = path.file_name().ok_or_else(|| format!("{} isn't a file", path.display()))?;
+    let temp = path.with_file_name(format!(".{}.tmp", name.to_string_lossy()));
+    if let Err(e) = write(&temp) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn test_replace_file_leaves_no_partial_output() {
+        let dir = std::env::temp_dir().join(format!("polaris_output_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.png");
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(3, 2));
+        let format = output_format(&path, None).unwrap();
+        replace_file(&path, |temp| write_image(&image, temp, format, None)).unwrap();
+        assert_eq!(image::open(&path).unwrap().to_rgba8(), image.to_rgba8());
+
+        // A failed write keeps the previous output
+        let failed = replace_file(&path, |temp| {
+            fs::write(temp, "partial")?;
+            Err("encoder failed".into())
+        });
+        assert!(failed.is_err());
+        assert_eq!(image::open(&path).unwrap().to_rgba8(), image.to_rgba8());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        assert_eq!(output_format(&path, Some(FormatArg::Jpeg)).unwrap(), ExportFormat::Jpeg);
+        assert!(output_format(&dir.join("out.tga"), None).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+//! `polaris render`: loads a document, composites it and writes the result.
+
+use std::path::{Path, PathBuf};
+use aurion_core::{EvaluationReport, NodeFailure, NodeId};
+use clap::Args;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use meridian_document::{Document, ExportOptions};
+use crate::error::{BoxError, CliError};
+use crate::output::{output_format, replace_file, FormatArg};
+
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    /// Document to render, plain JSON or an .artm package
+    pub document: PathBuf,
+    /// Image file to write
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// Output width in pixels; keeps the aspect ratio unless --height is set too
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: Option<u32>,
+    /// Output height in pixels; keeps the aspect ratio unless --width is set too
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: Option<u32>,
+    /// Output format, inferred from the output's extension when left out
+    #[arg(long, value_enum)]
+    pub format: Option<FormatArg>,
+    /// JPEG quality from 1 to 100
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: Option<u8>,
+}
+
+pub fn run(args: &RenderArgs) -> Result<(), CliError> {
+    let document = load(&args.document)?;
+    let (image, _) = render(&document, args.width, args.height)?;
+    write(&document, &image, &args.output, args.format, args.quality)
+}
+
+pub fn load(path: &Path) -> Result<Document, CliError> {
+    Document::load(path).map_err(|e| CliError::load(path, e))
+}
+
+/// The document's composite, resized as asked, and how rendering it went.
+pub fn render(document: &Document, width: Option<u32>, height: Option<u32>) -> Result<(DynamicImage, EvaluationReport), CliError> {
+    let (result, report) = document.render_composite_report();
+    let image = result.map_err(|source| evaluation_error(document, source.into(), &report))?;
+    Ok((resize(image, width, height), report))
+}
+
+/// A failed render of `document`, with a breadcrumb to each failed node.
+pub fn evaluation_error(document: &Document, source: BoxError, report: &EvaluationReport) -> CliError {
+    CliError::Evaluate {
+        path: document.path().map(Path::to_path_buf).unwrap_or_default(),
+        source,
+        failures: report.failures.iter().map(|failure| breadcrumb(document, failure)).collect(),
+    }
+}
+
+/// Writes a render of `document` with its color profile, replacing `path`
+/// only once the image is complete.
+pub fn write(document: &Document, image: &DynamicImage, path: &Path, format: Option<FormatArg>, quality: Option<u8>) -> Result<(), CliError> {
+    let write = || -> Result<(), BoxError> {
+        let mut options = ExportOptions {
+            format: Some(output_format(path, format)?),
+            ..ExportOptions::default()
+        };
+        if let Some(quality) = quality {
+            options.jpeg_quality = quality;
+        }
+        replace_file(path, |temp| Ok(document.export_image(image, temp, &options)?))
+    };
+    write().map_err(|e| CliError::encode(path, e))
+}
+
+/// Scales to the requested size. With only one side given the other keeps
+/// the aspect ratio.
+fn resize(image: DynamicImage, width: Option<u32>, height: Option<u32>) -> DynamicImage {
+    let aspect = image.width() as f64 / image.height().max(1) as f64;
+    let (width, height) = match (width, height) {
+        (None, None) => return image,
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, ((width as f64 / aspect).round() as u32).max(1)),
+        (None, Some(height)) => (((height as f64 * aspect).round() as u32).max(1), height),
+    };
+    if (width, height) == (image.width(), image.height()) {
+        return image;
+    }
+    image.resize_exact(width, height, FilterType::Lanczos3)
+}
+
+/// Where a failed node sits and why it failed, e.g.
+/// `Layer "Glow" > GaussianBlur 1b2c…: Invalid parameter: sigma - …`.
+fn breadcrumb(document: &Document, failure: &NodeFailure) -> String {
+    format!("{} > {} {}: {}", node_owner(document, &failure.node), failure.type_name, failure.node.to_string(), failure.message)
+}
+
+/// The graph a node of `document` sits in, e.g. `Layer "Glow"`.
+pub fn node_owner(document: &Document, node: &NodeId) -> String {
+    document
+        .layers()
+        .find_map(|id| {
+            let layer = document.get_layer(id)?;
+            let layer = layer.read();
+            let owns = layer.node_graph().get_node(node).is_some();
+            owns.then(|| format!("Layer \"{}\"", layer.name()))
+        })
+        .or_else(|| {
+            document
+                .compositing_graph()
+                .filter(|compositing| compositing.graph().get_node(node).is_some())
+                .map(|_| "Compositing graph".to_string())
+        })
+        // e.g. inside a smart object's document
+        .unwrap_or_else(|| "Nested graph".to_string())
+}
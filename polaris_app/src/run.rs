@@ -0,0 +1,189 @@
+//! `polaris run`: evaluates a pipeline, a graph in the
+//! [`aurion_core::spec`] form, over an input image.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use aurion_core::node_factory::NODE_REGISTRY;
+use aurion_core::spec::{BuiltGraph, PLACEHOLDER_TYPE};
+use aurion_core::{EvalContext, EvaluationReport, GraphSpec, NodeData, ReportRecorder};
+use aurion_std_nodes::ImageNode;
+use clap::Args;
+use image::DynamicImage;
+use serde_json::Value;
+use crate::error::{BoxError, CliError};
+use crate::output::{output_format, replace_file, write_image, FormatArg};
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Pipeline to run
+    pub pipeline: PathBuf,
+    /// Image fed to the pipeline's Input node
+    #[arg(short, long)]
+    pub input: PathBuf,
+    /// Image file to write
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// Overrides a node parameter, e.g. GaussianBlur1.sigma=4.5
+    #[arg(long = "set", value_name = "NODE.PARAMETER=VALUE", value_parser = parse_override)]
+    pub overrides: Vec<Override>,
+    /// Output format, inferred from the output's extension when left out
+    #[arg(long, value_enum)]
+    pub format: Option<FormatArg>,
+    /// JPEG quality from 1 to 100
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: Option<u8>,
+}
+
+/// A parameter set from the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    pub node: String,
+    pub parameter: String,
+    pub value: Value,
+}
+
+/// Parses `NODE.PARAMETER=VALUE`. Values are read as JSON when they can be,
+/// e.g. `4.5` or `true`, and as a string otherwise, e.g. `Multiply`.
+pub fn parse_override(text: &str) -> Result<Override, String> {
+    let (target, value) = text.split_once('=').ok_or("expected NODE.PARAMETER=VALUE")?;
+    let (node, parameter) = target.rsplit_once('.').ok_or("expected NODE.PARAMETER before the =")?;
+    if node.is_empty() || parameter.is_empty() {
+        return Err("expected NODE.PARAMETER before the =".to_string());
+    }
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::from(value));
+    Ok(Override { node: node.to_string(), parameter: parameter.to_string(), value })
+}
+
+/// How [`Pipeline::evaluate`] goes through a built graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Evaluation {
+    /// Every node, one after another.
+    Sequential,
+    /// Independent branches at the same time, see
+    /// [`aurion_core::NodeGraph::evaluate_parallel`].
+    Parallel,
+    /// Reusing the graph's results from earlier calls, see
+    /// [`aurion_core::NodeGraph::evaluate_cached`].
+    Cached,
+}
+
+/// A pipeline file with its overrides applied.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    path: PathBuf,
+    spec: GraphSpec,
+    // The Input node the image goes to
+    input: String,
+}
+
+impl Pipeline {
+    /// Reads the pipeline at `path`, which must have exactly one
+    /// [`PLACEHOLDER_TYPE`] node.
+    pub fn load(path: &Path, overrides: &[Override]) -> Result<Self, CliError> {
+        let json = std::fs::read_to_string(path).map_err(|e| CliError::load(path, e))?;
+        let mut spec = GraphSpec::from_json(&json).map_err(|e| CliError::load(path, e))?;
+        for set in overrides {
+            spec.set_parameter(&set.node, &set.parameter, set.value.clone()).map_err(|e| CliError::load(path, e))?;
+        }
+        let input = match spec.placeholders().as_slice() {
+            [input] => input.to_string(),
+            placeholders => {
+                let message = format!("Pipelines need exactly one {} node, found {}", PLACEHOLDER_TYPE, placeholders.len());
+                return Err(CliError::load(path, message));
+            }
+        };
+        Ok(Self { path: path.to_path_buf(), spec, input })
+    }
+
+    /// The graph with `image` in its Input node. Fails on nodes that can't be
+    /// created, e.g. unknown types or bad parameters.
+    pub fn build(&self, image: DynamicImage) -> Result<BuiltGraph, CliError> {
+        let input: Box<dyn NodeData> = Box::new(ImageNode::with_image(image));
+        let placeholders = HashMap::from([(self.input.clone(), input)]);
+        self.spec.build(&NODE_REGISTRY.read(), placeholders).map_err(|e| CliError::load(&self.path, e))
+    }
+
+    /// Evaluates the pipeline over `image`, reporting how it went.
+    pub fn run(&self, image: DynamicImage) -> Result<(DynamicImage, EvaluationReport), CliError> {
+        self.evaluate(&self.build(image)?, Evaluation::Sequential)
+    }
+
+    /// Evaluates a graph from [`Pipeline::build`]. Only
+    /// [`Evaluation::Cached`] reuses results, so otherwise each call
+    /// computes every node again.
+    pub fn evaluate(&self, built: &BuiltGraph, evaluation: Evaluation) -> Result<(DynamicImage, EvaluationReport), CliError> {
+        let recorder = ReportRecorder::new();
+        let context = EvalContext::new().with(recorder.clone());
+        let started = Instant::now();
+        let result = match evaluation {
+            Evaluation::Sequential => built.graph.evaluate_with_context(&built.output, &context),
+            Evaluation::Parallel => built.graph.evaluate_parallel_with_context(&built.output, &context),
+            Evaluation::Cached => built.graph.evaluate_cached_with_context(&built.output, &context),
+        };
+        let mut report = recorder.take();
+        report.total = started.elapsed();
+        let output = result.map_err(|source| CliError::Evaluate {
+            path: self.path.clone(),
+            source: source.into(),
+            failures: report
+                .failures
+                .iter()
+                .map(|failure| {
+                    let name = built.name_of(&failure.node).unwrap_or("?");
+                    format!("{} ({}): {}", name, failure.type_name, failure.message)
+                })
+                .collect(),
+        })?;
+        match output.into_image() {
+            Some(image) => Ok((image, report)),
+            None => Err(CliError::Evaluate {
+                path: self.path.clone(),
+                source: format!("{} didn't produce an image", self.spec.output).into(),
+                failures: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// Pipelines are JSON objects with a list of nodes; anything else is taken
+/// for a document.
+pub fn is_pipeline(path: &Path) -> bool {
+    let Ok(bytes) = std::fs::read(path) else {
+        return false;
+    };
+    serde_json::from_slice::<Value>(&bytes).is_ok_and(|value| value.get("nodes").is_some_and(Value::is_array))
+}
+
+pub fn run(args: &RunArgs) -> Result<(), CliError> {
+    let pipeline = Pipeline::load(&args.pipeline, &args.overrides)?;
+    let input = image::open(&args.input).map_err(|e| CliError::load(&args.input, e))?;
+    let (output, _) = pipeline.run(input)?;
+    write(&output, &args.output, args.format, args.quality)
+}
+
+/// Writes a pipeline's result, replacing `path` only once the image is
+/// complete.
+pub fn write(image: &DynamicImage, path: &Path, format: Option<FormatArg>, quality: Option<u8>) -> Result<(), CliError> {
+    let write = || -> Result<(), BoxError> {
+        let format = output_format(path, format)?;
+        replace_file(path, |temp| write_image(image, temp, format, quality))
+    };
+    write().map_err(|e| CliError::encode(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_override() {
+        let set = parse_override("GaussianBlur1.sigma=4.5").unwrap();
+        assert_eq!(set, Override { node: "GaussianBlur1".to_string(), parameter: "sigma".to_string(), value: Value::from(4.5) });
+        assert_eq!(parse_override("Blend.mode=Multiply").unwrap().value, Value::from("Multiply"));
+        assert_eq!(parse_override("v1.2.sigma=1").unwrap().node, "v1.2");
+        assert!(parse_override("GaussianBlur1.sigma").is_err());
+        assert!(parse_override("sigma=4.5").is_err());
+        assert!(parse_override(".sigma=4.5").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! `polaris watch`: renders a document or pipeline again whenever it, or a
+//! file it reads, changes.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use aurion_core::EvaluationReport;
+use clap::Args;
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use crate::error::CliError;
+use crate::output::FormatArg;
+use crate::render;
+use crate::run::{self, is_pipeline, Pipeline};
+
+#[derive(Debug, Args)]
+pub struct WatchArgs {
+    /// Document or pipeline to render
+    pub source: PathBuf,
+    /// Image file to write after each render
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// Image fed to a pipeline's Input node, watched too
+    #[arg(short, long)]
+    pub input: Option<PathBuf>,
+    /// Exit on the first failed render instead of reporting it and watching on
+    #[arg(long)]
+    pub fail_fast: bool,
+    /// Milliseconds without changes to wait for before rendering, so a burst
+    /// of saves renders once
+    #[arg(long, default_value_t = 200)]
+    pub debounce: u64,
+    /// Output format, inferred from the output's extension when left out
+    #[arg(long, value_enum)]
+    pub format: Option<FormatArg>,
+    /// JPEG quality from 1 to 100
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: Option<u8>,
+}
+
+pub fn run(args: &WatchArgs) -> Result<(), CliError> {
+    let mut watcher = FileWatcher::new().map_err(|e| CliError::load(&args.source, e))?;
+    let quiet = Duration::from_millis(args.debounce);
+    let mut generation = 0;
+    loop {
+        generation += 1;
+        let started = Instant::now();
+        let (files, result) = render_once(args);
+        // Watch before reporting, so changes made in response aren't missed
+        watcher.watch(&files).map_err(|e| CliError::load(&args.source, e))?;
+        match result {
+            Ok(report) => println!(
+                "[{}] Rendered {} in {}",
+                generation,
+                args.output.display(),
+                summary(&report, started.elapsed())
+            ),
+            Err(e) if args.fail_fast => return Err(e),
+            Err(e) => e.report(),
+        }
+        if !watcher.wait(quiet) {
+            return Ok(());
+        }
+    }
+}
+
+/// Renders and writes the output once. Returns the files the render read,
+/// which are known even when it fails, e.g. after a bad save.
+fn render_once(args: &WatchArgs) -> (Vec<PathBuf>, Result<EvaluationReport, CliError>) {
+    let mut files = vec![args.source.clone()];
+    if is_pipeline(&args.source) {
+        files.extend(args.input.clone());
+        return (files, render_pipeline(args));
+    }
+    let document = match render::load(&args.source) {
+        Ok(document) => document,
+        Err(e) => return (files, Err(e)),
+    };
+    files.extend(document.linked_files());
+    let result = render::render(&document, None, None).and_then(|(image, report)| {
+        render::write(&document, &image, &args.output, args.format, args.quality)?;
+        Ok(report)
+    });
+    (files, result)
+}
+
+fn render_pipeline(args: &WatchArgs) -> Result<EvaluationReport, CliError> {
+    let Some(input) = &args.input else {
+        return Err(CliError::load(&args.source, "Pipelines need an input image, see --input"));
+    };
+    let pipeline = Pipeline::load(&args.source, &[])?;
+    let image = image::open(input).map_err(|e| CliError::load(input, e))?;
+    let (output, report) = pipeline.run(image)?;
+    run::write(&output, &args.output, args.format, args.quality)?;
+    Ok(report)
+}
+
+fn summary(report: &EvaluationReport, elapsed: Duration) -> String {
+    let mut summary = format!(
+        "{:.1} ms (evaluation {:.1} ms, {} nodes",
+        elapsed.as_secs_f64() * 1000.0,
+        report.total.as_secs_f64() * 1000.0,
+        report.timings.len()
+    );
+    if let Some(slowest) = report.slowest() {
+        summary.push_str(&format!(", slowest {} {:.1} ms", slowest.type_name, slowest.duration.as_secs_f64() * 1000.0));
+    }
+    summary.push(')');
+    summary
+}
+
+/// Watches a changing set of files through their directories, since
+/// editors often save by replacing a file rather than writing to it.
+struct FileWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    dirs: HashSet<PathBuf>,
+    // Canonical paths, as events report them
+    files: HashSet<PathBuf>,
+}
+
+impl FileWatcher {
+    fn new() -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        Ok(Self {
+            watcher: notify::recommended_watcher(sender)?,
+            events,
+            dirs: HashSet::new(),
+            files: HashSet::new(),
+        })
+    }
+
+    /// Watches `files` from now on, instead of the previous set.
+    fn watch(&mut self, files: &[PathBuf]) -> notify::Result<()> {
+        self.files.clear();
+        for file in files {
+            let dir = match file.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let (Ok(dir), Some(name)) = (dir.canonicalize(), file.file_name()) else {
+                continue;
+            };
+            self.files.insert(dir.join(name));
+            if self.dirs.insert(dir.clone()) {
+                self.watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Blocks until a watched file changes, then until they stop changing
+    /// for `quiet`. Returns false once no more events can arrive.
+    fn wait(&self, quiet: Duration) -> bool {
+        loop {
+            match self.events.recv() {
+                Ok(Ok(event)) if self.touches(&event) => break,
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => eprintln!("warning: {}", e),
+                Err(_) => return false,
+            }
+        }
+        let mut deadline = Instant::now() + quiet;
+        loop {
+            match self.events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(Ok(event)) if self.touches(&event) => deadline = Instant::now() + quiet,
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => return true,
+                Err(RecvTimeoutError::Disconnected) => return false,
+            }
+        }
+    }
+
+    fn touches(&self, event: &Event) -> bool {
+        // Reading a file for a render mustn't trigger the next one
+        let changed = match event.kind {
+            EventKind::Modify(ModifyKind::Metadata(_)) => false,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => true,
+            EventKind::Access(_) | EventKind::Any | EventKind::Other => false,
+        };
+        changed && event.paths.iter().any(|path| self.files.contains(path))
+    }
+}
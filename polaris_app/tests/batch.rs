@@ -0,0 +1,88 @@
+//! Runs batches through the `polaris` binary.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("polaris_batch_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+fn batch(input_dir: &Path, output_dir: &Path, extra: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_polaris"));
+    command
+        .arg("batch")
+        .arg(fixture("blur_sharpen.json"))
+        .arg("--input-dir")
+        .arg(input_dir)
+        .arg("--output-dir")
+        .arg(output_dir)
+        .args(["--jobs", "2", "--suffix", "_edited"])
+        .args(extra);
+    command.output().unwrap()
+}
+
+fn write_image(path: &Path, color: [u8; 4]) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, Rgba(color))).save(path).unwrap();
+}
+
+#[test]
+fn test_batch_processes_then_skips_up_to_date_outputs() {
+    let dir = temp_dir("skip");
+    let (input_dir, output_dir) = (dir.join("photos"), dir.join("out"));
+    write_image(&input_dir.join("a.png"), [255, 0, 0, 255]);
+    write_image(&input_dir.join("nested/b.png"), [0, 0, 255, 255]);
+    fs::write(input_dir.join("notes.txt"), "not an image").unwrap();
+
+    let result = batch(&input_dir, &output_dir, &[]);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(result.status.success(), "{}{}", stdout, String::from_utf8_lossy(&result.stderr));
+    assert!(stdout.contains("Processed 2, skipped 0, failed 0"), "{}", stdout);
+    assert_eq!(image::open(output_dir.join("a_edited.png")).unwrap().to_rgba8().dimensions(), (16, 16));
+    assert!(output_dir.join("nested/b_edited.png").is_file());
+
+    let result = batch(&input_dir, &output_dir, &[]);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(stdout.contains("Processed 0, skipped 2, failed 0"), "{}", stdout);
+
+    // --force and a changed format process everything again
+    let result = batch(&input_dir, &output_dir, &["--force", "--format", "jpeg", "--quality", "80"]);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(stdout.contains("Processed 2, skipped 0, failed 0"), "{}", stdout);
+    assert!(output_dir.join("nested/b_edited.jpg").is_file());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_corrupt_input_is_reported_in_summary() {
+    let dir = temp_dir("corrupt");
+    let (input_dir, output_dir) = (dir.join("photos"), dir.join("out"));
+    write_image(&input_dir.join("good.png"), [0, 255, 0, 255]);
+    fs::write(input_dir.join("bad.png"), "not really a png").unwrap();
+
+    let result = batch(&input_dir, &output_dir, &[]);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert_eq!(result.status.code(), Some(4), "{}", stdout);
+    assert!(stdout.contains("Processed 1, skipped 0, failed 1"), "{}", stdout);
+    let reason = stdout.lines().find(|line| line.contains("bad.png:")).expect("no reason for bad.png");
+    assert!(reason.contains("Couldn't load"), "{}", reason);
+    assert!(output_dir.join("good_edited.png").is_file());
+    assert!(!output_dir.join("bad_edited.png").exists());
+
+    // Skipped files still count toward the total
+    let result = batch(&input_dir, &output_dir, &[]);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(stdout.contains("Processed 0, skipped 1, failed 1"), "{}", stdout);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("1 of 2 files failed"), "{}", stderr);
+    fs::remove_dir_all(dir).unwrap();
+}
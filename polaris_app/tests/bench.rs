@@ -0,0 +1,109 @@
+//! Benchmarks a pipeline through the `polaris` binary.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde_json::Value;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("polaris_bench_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+fn polaris() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_polaris"))
+}
+
+#[test]
+fn test_json_covers_nodes_and_total() {
+    let dir = temp_dir("json");
+    let input = dir.join("input.png");
+    let image = RgbaImage::from_fn(256, 256, |x, y| Rgba([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8, 255]));
+    DynamicImage::ImageRgba8(image).save(&input).unwrap();
+
+    let output = polaris()
+        .arg("bench")
+        .arg(fixture("blur_sharpen.json"))
+        .arg("-i")
+        .arg(&input)
+        .args(["--runs", "3", "--warmup", "1", "--json", "--no-cache"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let bench: Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(bench["runs"], 3);
+    assert_eq!(bench["warmup"], 1);
+    assert_eq!(bench["parallel"], false);
+    assert_eq!(bench["cache"], false);
+    for key in ["mean_ms", "min_ms", "max_ms", "total_ms"] {
+        assert!(bench["total"][key].is_f64(), "total.{} missing", key);
+    }
+
+    let nodes = bench["nodes"].as_array().unwrap();
+    let mut names: Vec<_> = nodes.iter().map(|node| node["name"].as_str().unwrap()).collect();
+    names.sort();
+    assert_eq!(names, ["GaussianBlur1", "Input", "Sharpen1"]);
+    for node in nodes {
+        assert!(node["id"].is_string());
+        assert!(node["type"].is_string());
+        assert_eq!(node["evaluations"], 3);
+        let (min, mean, max) = (node["min_ms"].as_f64().unwrap(), node["mean_ms"].as_f64().unwrap(), node["max_ms"].as_f64().unwrap());
+        assert!(min <= mean && mean <= max);
+    }
+    let means: Vec<_> = nodes.iter().map(|node| node["mean_ms"].as_f64().unwrap()).collect();
+    assert!(means.windows(2).all(|pair| pair[0] >= pair[1]), "nodes aren't hottest first");
+
+    // Nodes' own times fit within each evaluation
+    let nodes_ms: f64 = nodes.iter().map(|node| node["total_ms"].as_f64().unwrap()).sum();
+    let total_ms = bench["total"]["total_ms"].as_f64().unwrap();
+    assert!(nodes_ms <= total_ms * 1.01, "nodes {} ms, total {} ms", nodes_ms, total_ms);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+fn bench_json(input: &Path, flags: &[&str]) -> Value {
+    let output = polaris()
+        .arg("bench")
+        .arg(fixture("blur_sharpen.json"))
+        .arg("-i")
+        .arg(input)
+        .args(["--runs", "2", "--warmup", "1", "--json"])
+        .args(flags)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn test_pipeline_flags_pick_the_evaluation() {
+    let dir = temp_dir("flags");
+    let input = dir.join("input.png");
+    DynamicImage::ImageRgba8(RgbaImage::new(16, 16)).save(&input).unwrap();
+    let evaluations = |bench: &Value| -> Vec<u64> {
+        bench["nodes"].as_array().unwrap().iter().map(|node| node["evaluations"].as_u64().unwrap()).collect()
+    };
+
+    // In parallel every node is computed on every run
+    let bench = bench_json(&input, &["--parallel"]);
+    assert_eq!(bench["parallel"], true);
+    assert_eq!(bench["cache"], false);
+    assert_eq!(evaluations(&bench), [2, 2, 2]);
+
+    // Cached by default, so the warmup leaves nothing to compute
+    let bench = bench_json(&input, &[]);
+    assert_eq!(bench["parallel"], false);
+    assert_eq!(bench["cache"], true);
+    assert!(evaluations(&bench).is_empty());
+
+    let bench = bench_json(&input, &["--no-cache"]);
+    assert_eq!(bench["cache"], false);
+    assert_eq!(evaluations(&bench), [2, 2, 2]);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,101 @@
+//! Runs the `polaris` binary against documents written to a temp dir.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use aurion_core::Node;
+use aurion_std_nodes::ImageNode;
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use meridian_document::Document;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("polaris_render_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn polaris(args: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_polaris")).args(args).output().unwrap()
+}
+
+fn render(document: &Path, output: &Path, extra: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_polaris"));
+    command.arg("render").arg(document).arg("-o").arg(output).args(extra);
+    command.output().unwrap()
+}
+
+/// An 8×6 document with a single red layer.
+fn image_document() -> Document {
+    let mut doc = Document::with_size(8, 6);
+    let id = doc.add_layer();
+    let red = RgbaImage::from_pixel(8, 6, Rgba([255, 0, 0, 255]));
+    doc.get_layer(&id).unwrap().write().node_graph_mut()
+        .add_node(Node::new(Box::new(ImageNode::with_image(DynamicImage::ImageRgba8(red)))));
+    doc
+}
+
+#[test]
+fn test_renders_documents_and_packages() {
+    let dir = temp_dir("documents");
+    let json = dir.join("red.json");
+    let package = dir.join("red.artm");
+    image_document().save(&json).unwrap();
+    image_document().save_package(&package).unwrap();
+
+    let output = dir.join("red.png");
+    let result = render(&json, &output, &[]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let image = image::open(&output).unwrap();
+    assert_eq!((image.width(), image.height()), (8, 6));
+    assert_eq!(image.to_rgba8().get_pixel(3, 3), &Rgba([255, 0, 0, 255]));
+
+    // One side keeps the aspect ratio, both set it exactly
+    let output = dir.join("wide.png");
+    assert!(render(&package, &output, &["--width", "16"]).status.success());
+    let image = image::open(&output).unwrap();
+    assert_eq!((image.width(), image.height()), (16, 12));
+    assert!(render(&package, &output, &["--width", "5", "--height", "20"]).status.success());
+    let image = image::open(&output).unwrap();
+    assert_eq!((image.width(), image.height()), (5, 20));
+
+    // The format flag wins over the extension
+    let output = dir.join("red.out");
+    assert!(render(&json, &output, &["--format", "jpeg", "--quality", "80"]).status.success());
+    assert_eq!(image::io::Reader::open(&output).unwrap().with_guessed_format().unwrap().format(), Some(ImageFormat::Jpeg));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_exit_codes_tell_failures_apart() {
+    let dir = temp_dir("failures");
+    let output = dir.join("out.png");
+
+    let garbage = dir.join("garbage.json");
+    fs::write(&garbage, "not a document").unwrap();
+    let result = render(&garbage, &output, &[]);
+    assert_eq!(result.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("Couldn't load"));
+    assert_eq!(render(&dir.join("missing.json"), &output, &[]).status.code(), Some(3));
+
+    // An image node saved without its pixels fails to evaluate
+    let mut doc = image_document();
+    let compositing = doc.enable_graph_compositing();
+    let empty = compositing.graph_mut().add_node(Node::new(Box::new(ImageNode::new())));
+    compositing.set_output(Some(empty.clone())).unwrap();
+    let failing = dir.join("failing.json");
+    doc.save(&failing).unwrap();
+    let result = render(&failing, &output, &[]);
+    assert_eq!(result.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains(&format!("Compositing graph > ImageNode {}", empty.to_string())), "{}", stderr);
+    assert!(!output.exists());
+
+    let document = dir.join("red.json");
+    image_document().save(&document).unwrap();
+    assert_eq!(render(&document, &dir.join("missing").join("out.png"), &[]).status.code(), Some(5));
+    assert_eq!(render(&document, &dir.join("out.tga"), &[]).status.code(), Some(5));
+
+    // Usage errors are clap's
+    assert_eq!(polaris(&[Path::new("render"), &document]).status.code(), Some(2));
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,84 @@
+//! Runs pipelines through the `polaris` binary.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("polaris_run_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+fn run(pipeline: &Path, input: &Path, output: &Path, extra: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_polaris"));
+    command.arg("run").arg(pipeline).arg("-i").arg(input).arg("-o").arg(output).args(extra);
+    command.output().unwrap()
+}
+
+/// A 32×32 checkerboard of 4 pixel squares, all hard edges.
+fn write_checkerboard(path: &Path) {
+    let image = RgbaImage::from_fn(32, 32, |x, y| {
+        if (x / 4 + y / 4) % 2 == 0 { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 255]) }
+    });
+    DynamicImage::ImageRgba8(image).save(path).unwrap();
+}
+
+#[test]
+fn test_pipeline_runs_and_overrides_change_output() {
+    let dir = temp_dir("pipeline");
+    let input = dir.join("input.png");
+    write_checkerboard(&input);
+    let pipeline = fixture("blur_sharpen.json");
+
+    let default = dir.join("default.png");
+    let result = run(&pipeline, &input, &default, &[]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let default = image::open(&default).unwrap().to_rgba8();
+    assert_eq!(default.dimensions(), (32, 32));
+    assert_ne!(default, image::open(&input).unwrap().to_rgba8());
+
+    let blurred = dir.join("blurred.png");
+    let result = run(&pipeline, &input, &blurred, &["--set", "GaussianBlur1.sigma=4.5"]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    assert_ne!(image::open(&blurred).unwrap().to_rgba8(), default);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_pipeline_failures() {
+    let dir = temp_dir("failures");
+    let input = dir.join("input.png");
+    write_checkerboard(&input);
+    let pipeline = fixture("blur_sharpen.json");
+    let output = dir.join("out.png");
+
+    // Bad overrides are caught before anything runs
+    assert_eq!(run(&pipeline, &input, &output, &["--set", "Blur9.sigma=2"]).status.code(), Some(3));
+    assert_eq!(run(&pipeline, &input, &output, &["--set", "GaussianBlur1.radius=2"]).status.code(), Some(3));
+    assert_eq!(run(&pipeline, &input, &output, &["--set", "sigma"]).status.code(), Some(2));
+    assert_eq!(run(&pipeline, &dir.join("missing.png"), &output, &[]).status.code(), Some(3));
+
+    // Connecting to an input the node doesn't have is caught at load
+    let original = fs::read_to_string(&pipeline).unwrap();
+    let edge = r#"{ "from": "GaussianBlur1", "to": "Sharpen1", "input": "input" }"#;
+    let spec = original.replace(edge, &format!("{},\n    {}", edge, r#"{ "from": "Input", "to": "Sharpen1", "input": "mask" }"#));
+    let broken = dir.join("broken.json");
+    fs::write(&broken, spec).unwrap();
+    let result = run(&broken, &input, &output, &[]);
+    assert_eq!(result.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("mask"));
+
+    // A node left without its input fails to evaluate, and is named
+    fs::write(&broken, original.replace(&format!(",\n    {}", edge), "")).unwrap();
+    let result = run(&broken, &input, &output, &[]);
+    assert_eq!(result.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("Sharpen1 (SharpenNode)"), "{}", stderr);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,132 @@
+//! Runs `polaris watch` and changes its files underneath it.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+use aurion_core::Node;
+use aurion_std_nodes::ImageNode;
+use image::{DynamicImage, Rgba, RgbaImage};
+use meridian_document::Document;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("polaris_watch_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The watcher, and the lines it prints to stdout and stderr as they come.
+struct Watch {
+    child: Child,
+    lines: Receiver<String>,
+}
+
+impl Watch {
+    fn start(source: &Path, output: &Path, extra: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_polaris"))
+            .arg("watch")
+            .arg(source)
+            .arg("-o")
+            .arg(output)
+            .args(["--debounce", "50"])
+            .args(extra)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let (sender, lines) = mpsc::channel();
+        forward(child.stdout.take().unwrap(), sender.clone());
+        forward(child.stderr.take().unwrap(), sender);
+        Self { child, lines }
+    }
+
+    /// Waits for a line containing `text`, returning it.
+    fn expect(&self, text: &str) -> String {
+        loop {
+            let line = self.lines.recv_timeout(TIMEOUT).unwrap_or_else(|_| panic!("no line containing {:?}", text));
+            if line.contains(text) {
+                return line;
+            }
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn forward(stream: impl Read + Send + 'static, sender: mpsc::Sender<String>) {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            let _ = sender.send(line);
+        }
+    });
+}
+
+fn save_filled(path: &Path, color: [u8; 4]) {
+    let mut doc = Document::with_size(4, 4);
+    let id = doc.add_layer();
+    let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba(color)));
+    doc.get_layer(&id).unwrap().write().node_graph_mut().add_node(Node::new(Box::new(ImageNode::with_image(image))));
+    doc.save(path).unwrap();
+}
+
+fn pixel(path: &Path) -> [u8; 4] {
+    image::open(path).unwrap().to_rgba8().get_pixel(1, 1).0
+}
+
+#[test]
+fn test_changes_render_again() {
+    let dir = temp_dir("changes");
+    let source = dir.join("doc.json");
+    let output = dir.join("out.png");
+    save_filled(&source, [255, 0, 0, 255]);
+
+    let watch = Watch::start(&source, &output, &[]);
+    assert!(watch.expect("Rendered").starts_with("[1]"));
+    assert_eq!(pixel(&output), [255, 0, 0, 255]);
+
+    save_filled(&source, [0, 0, 255, 255]);
+    assert!(watch.expect("Rendered").starts_with("[2]"));
+    assert_eq!(pixel(&output), [0, 0, 255, 255]);
+    // Only the output is left, no temp files
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+    drop(watch);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_failures_keep_watching_unless_fail_fast() {
+    let dir = temp_dir("failures");
+    let source = dir.join("doc.json");
+    let output = dir.join("out.png");
+    fs::write(&source, "{ half a document").unwrap();
+
+    let watch = Watch::start(&source, &output, &[]);
+    watch.expect("Couldn't load");
+    save_filled(&source, [0, 255, 0, 255]);
+    watch.expect("Rendered");
+    assert_eq!(pixel(&output), [0, 255, 0, 255]);
+    drop(watch);
+
+    fs::write(&source, "{ half a document").unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_polaris"))
+        .arg("watch")
+        .arg(&source)
+        .arg("-o")
+        .arg(&output)
+        .arg("--fail-fast")
+        .output()
+        .unwrap()
+        .status;
+    assert_eq!(status.code(), Some(3));
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,209 @@
+//! The node editor drawn with egui over astria_render's GPU-cleared
+//! viewport, through [`astria_render::EguiLayer`].
+//!
+//! Nodes and the canvas can be dragged inside the editor window. Clicks
+//! outside it are routed to the viewport, which changes its background to
+//! show it got them.
+//!
+//! ```text
+//! cargo run -p solaris_ui_desktop --example egui_node_editor
+//! ```
+
+use std::collections::HashMap;
+use anyhow::Result;
+use astria_render::{EguiLayer, EventRoute, Renderer};
+use aurion_core::node_factory::NODE_REGISTRY;
+use aurion_core::{NodeGraph, NodeId};
+use aurion_std_nodes::factories::register_standard_nodes;
+use egui::{Align2, Color32, FontId, Pos2, Rect, Sense, Stroke, Vec2};
+use serde_json::json;
+use winit_028::dpi::LogicalSize;
+use winit_028::event::{ElementState, Event, WindowEvent};
+use winit_028::event_loop::EventLoop;
+use winit_028::window::WindowBuilder;
+
+const NODE_SIZE: Vec2 = Vec2::new(160.0, 60.0);
+const HEADER_HEIGHT: f32 = 24.0;
+const SLOT_RADIUS: f32 = 4.0;
+const GRID_SIZE: f32 = 20.0;
+/// Space between the columns and rows nodes start in.
+const SPACING: Vec2 = Vec2::new(220.0, 100.0);
+
+/// Backgrounds the viewport steps through on clicks routed to it.
+const BACKGROUNDS: [wgpu::Color; 3] = [
+    wgpu::Color { r: 0.08, g: 0.08, b: 0.1, a: 1.0 },
+    wgpu::Color { r: 0.1, g: 0.14, b: 0.2, a: 1.0 },
+    wgpu::Color { r: 0.16, g: 0.1, b: 0.12, a: 1.0 },
+];
+
+/// The editor's state: the graph and where its nodes sit on the canvas.
+struct Editor {
+    graph: NodeGraph,
+    positions: HashMap<NodeId, Pos2>,
+    // The canvas point shown at the canvas area's top-left corner
+    scroll: Vec2,
+}
+
+impl Editor {
+    /// A small graph blending a blurred and an inverted image, laid out in
+    /// columns by how far each node is from the sources.
+    fn new() -> Result<Self> {
+        register_standard_nodes();
+        let registry = NODE_REGISTRY.read();
+        let mut graph = NodeGraph::new();
+        let mut add = |type_name: &str| -> Result<NodeId> { Ok(graph.add_node(registry.create_node(type_name, &json!({}))?)) };
+        let (photo, overlay) = (add("ImageNode")?, add("ImageNode")?);
+        let (blur, invert, blend) = (add("BlurNode")?, add("InvertNode")?, add("BlendNode")?);
+        graph.connect(&photo, &blur, "input")?;
+        graph.connect(&overlay, &invert, "input")?;
+        graph.connect(&blur, &blend, "a")?;
+        graph.connect(&invert, &blend, "b")?;
+
+        let mut depth: HashMap<NodeId, usize> = HashMap::new();
+        let mut rows: HashMap<usize, usize> = HashMap::new();
+        let mut positions = HashMap::new();
+        // Insertion order has every node after the ones feeding it here
+        for id in graph.get_node_ids() {
+            let column = graph.get_node_dependencies(&id)?.iter().map(|input| depth[input] + 1).max().unwrap_or(0);
+            let row = rows.entry(column).or_default();
+            positions.insert(id.clone(), Pos2::new(40.0 + SPACING.x * column as f32, 40.0 + SPACING.y * *row as f32));
+            *row += 1;
+            depth.insert(id, column);
+        }
+        Ok(Self { graph, positions, scroll: Vec2::ZERO })
+    }
+
+    fn title(&self, id: &NodeId) -> String {
+        let Some(node) = self.graph.get_node(id) else {
+            return String::new();
+        };
+        let node = node.read();
+        match node.label() {
+            Some(label) => label.to_string(),
+            None => node.data().type_name().trim_end_matches("Node").to_string(),
+        }
+    }
+
+    /// The node's input slots, spread down its left edge, in canvas space.
+    fn input_slots(&self, id: &NodeId) -> Vec<(String, Pos2)> {
+        let (Some(node), Some(position)) = (self.graph.get_node(id), self.positions.get(id)) else {
+            return Vec::new();
+        };
+        let ports: Vec<_> = node.read().data().input_ports().into_iter().map(|port| port.name).collect();
+        let step = NODE_SIZE.y / (ports.len() + 1) as f32;
+        ports
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| (name, *position + Vec2::new(0.0, step * (i + 1) as f32)))
+            .collect()
+    }
+
+    /// Draws the editor window: the grid, wires, then the nodes, which can
+    /// be dragged, as can the canvas around them.
+    fn show(&mut self, context: &egui::Context) {
+        egui::Window::new("Node editor").default_size([760.0, 420.0]).show(context, |ui| {
+            let (canvas, painter) = ui.allocate_painter(ui.available_size(), Sense::drag());
+            if canvas.dragged() {
+                self.scroll -= canvas.drag_delta();
+            }
+            let area = canvas.rect;
+            let to_screen = |point: Pos2| area.min + (point.to_vec2() - self.scroll);
+            painter.rect_filled(area, 0.0, Color32::from_rgb(30, 30, 34));
+
+            let grid = Stroke::new(1.0, Color32::from_rgb(44, 44, 50));
+            let mut x = area.min.x - self.scroll.x.rem_euclid(GRID_SIZE);
+            while x <= area.max.x {
+                painter.line_segment([Pos2::new(x, area.min.y), Pos2::new(x, area.max.y)], grid);
+                x += GRID_SIZE;
+            }
+            let mut y = area.min.y - self.scroll.y.rem_euclid(GRID_SIZE);
+            while y <= area.max.y {
+                painter.line_segment([Pos2::new(area.min.x, y), Pos2::new(area.max.x, y)], grid);
+                y += GRID_SIZE;
+            }
+
+            let ids = self.graph.get_node_ids();
+            let wire = Stroke::new(2.0, Color32::from_rgb(150, 150, 160));
+            for id in &ids {
+                let Some(node) = self.graph.get_node(id) else {
+                    continue;
+                };
+                let slots = self.input_slots(id);
+                for (input, source) in node.read().inputs() {
+                    let from = self.positions[source] + Vec2::new(NODE_SIZE.x, NODE_SIZE.y / 2.0);
+                    if let Some((_, to)) = slots.iter().find(|(name, _)| name == input) {
+                        painter.line_segment([to_screen(from), to_screen(*to)], wire);
+                    }
+                }
+            }
+
+            for id in &ids {
+                let rect = Rect::from_min_size(to_screen(self.positions[id]), NODE_SIZE);
+                let response = ui.interact(rect, ui.id().with(id.0), Sense::drag());
+                if response.dragged() {
+                    *self.positions.get_mut(id).expect("every node is placed") += response.drag_delta();
+                }
+                let header = Rect::from_min_size(rect.min, Vec2::new(NODE_SIZE.x, HEADER_HEIGHT));
+                painter.rect_filled(rect, 6.0, Color32::from_rgb(52, 52, 60));
+                painter.rect_filled(header, egui::Rounding { nw: 6.0, ne: 6.0, sw: 0.0, se: 0.0 }, Color32::from_rgb(70, 90, 130));
+                let outline = if response.hovered() { Color32::from_rgb(90, 160, 255) } else { Color32::from_rgb(20, 20, 24) };
+                painter.rect_stroke(rect, 6.0, Stroke::new(1.0, outline));
+                painter.text(
+                    Pos2::new(rect.min.x + 8.0, header.center().y),
+                    Align2::LEFT_CENTER,
+                    self.title(id),
+                    FontId::proportional(13.0),
+                    Color32::WHITE,
+                );
+                for (_, slot) in self.input_slots(id) {
+                    painter.circle_filled(to_screen(slot), SLOT_RADIUS, Color32::from_rgb(200, 200, 210));
+                }
+                painter.circle_filled(Pos2::new(rect.max.x, rect.center().y), SLOT_RADIUS, Color32::from_rgb(200, 200, 210));
+            }
+        });
+    }
+}
+
+fn main() -> Result<()> {
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("Solaris node editor (egui)")
+        .with_inner_size(LogicalSize::new(1024.0, 640.0))
+        .build(&event_loop)?;
+    let mut renderer = pollster::block_on(Renderer::new(&window))?;
+    let mut ui = EguiLayer::new(renderer.device(), renderer.surface_format(), &window);
+    let mut editor = Editor::new()?;
+    let mut background = 0;
+    renderer.set_background_color(BACKGROUNDS[background]);
+
+    event_loop.run(move |event, _, control_flow| {
+        control_flow.set_wait();
+        match event {
+            Event::WindowEvent { event, window_id } if window_id == window.id() => {
+                let route = ui.handle_event(&event);
+                match event {
+                    WindowEvent::CloseRequested => control_flow.set_exit(),
+                    WindowEvent::Resized(size) => renderer.resize(size),
+                    WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+                        renderer.on_scale_factor_changed(scale_factor, *new_inner_size)
+                    }
+                    // Only clicks the editor didn't take reach the viewport
+                    WindowEvent::MouseInput { state: ElementState::Pressed, .. } if route == EventRoute::Viewport => {
+                        background = (background + 1) % BACKGROUNDS.len();
+                        renderer.set_background_color(BACKGROUNDS[background]);
+                    }
+                    _ => {}
+                }
+                window.request_redraw();
+            }
+            Event::RedrawRequested(_) => {
+                let context = ui.begin_frame(&window);
+                editor.show(&context);
+                if let Err(e) = renderer.render_with_ui(&mut ui, &window) {
+                    eprintln!("error: {}", e);
+                }
+            }
+            _ => {}
+        }
+    })
+}
@@ -0,0 +1,531 @@
+//! The File menu: opening, saving and exporting the document through native
+//! dialogs. The dialogs sit behind [`FileDialogService`] so the flow can run
+//! without a window.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use parking_lot::RwLock;
+use rfd::{FileDialog, MessageButtons, MessageDialog, MessageDialogResult};
+use meridian_document::async_io::{IoTask, LoadPhase};
+use meridian_document::package::PACKAGE_EXTENSION;
+use meridian_document::{Document, DocumentError, ExportFormat, ExportOptions};
+use crate::notifications::{NotificationLevel, Notifier, ProgressToast};
+
+const APP_NAME: &str = "Solaris UI";
+const DOCUMENT_EXTENSION: &str = "json";
+
+/// What to do with unsaved changes before they'd be lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsavedChoice {
+    Save,
+    Discard,
+    Cancel,
+}
+
+/// The dialogs the File menu asks through. `None` and
+/// [`UnsavedChoice::Cancel`] mean the user backed out.
+pub trait FileDialogService {
+    /// A document to open.
+    fn pick_open(&mut self) -> Option<PathBuf>;
+    /// Where to save, suggesting `file_name` and offering `extensions`, the
+    /// first being the default.
+    fn pick_save(&mut self, file_name: &str, extensions: &[&str]) -> Option<PathBuf>;
+    /// Whether to save `name`'s changes first.
+    fn confirm_unsaved(&mut self, name: &str) -> UnsavedChoice;
+}
+
+/// The platform's dialogs.
+#[derive(Debug, Default)]
+pub struct NativeDialogs;
+
+impl FileDialogService for NativeDialogs {
+    fn pick_open(&mut self) -> Option<PathBuf> {
+        FileDialog::new()
+            .add_filter("Artemisia document", &[PACKAGE_EXTENSION, DOCUMENT_EXTENSION])
+            .pick_file()
+    }
+
+    fn pick_save(&mut self, file_name: &str, extensions: &[&str]) -> Option<PathBuf> {
+        extensions.iter()
+            .fold(FileDialog::new().set_file_name(file_name), |dialog, extension| dialog.add_filter(*extension, &[*extension]))
+            .save_file()
+    }
+
+    fn confirm_unsaved(&mut self, name: &str) -> UnsavedChoice {
+        let result = MessageDialog::new()
+            .set_title(APP_NAME)
+            .set_description(format!("Save changes to \"{}\" before closing it?", name))
+            .set_buttons(MessageButtons::YesNoCancel)
+            .show();
+        match result {
+            MessageDialogResult::Yes => UnsavedChoice::Save,
+            MessageDialogResult::No => UnsavedChoice::Discard,
+            _ => UnsavedChoice::Cancel,
+        }
+    }
+}
+
+/// The entries of the File menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileAction {
+    New,
+    Open,
+    Save,
+    SaveAs,
+    /// Opens the export options, see [`FileMenu::confirm_export`].
+    Export,
+}
+
+/// The export popover's options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportSettings {
+    pub format: ExportFormat,
+    /// JPEG quality from 1 to 100.
+    pub quality: u8,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Png,
+            quality: ExportOptions::default().jpeg_quality,
+        }
+    }
+}
+
+/// A document being opened on a worker thread.
+struct Loading {
+    path: PathBuf,
+    task: IoTask<Document>,
+    progress: ProgressToast,
+}
+
+/// Runs the File menu against the open document, reporting failures and
+/// the progress of opening through `notifier`.
+pub struct FileMenu<D> {
+    document: Arc<RwLock<Document>>,
+    dialogs: D,
+    notifier: Notifier,
+    loading: Option<Loading>,
+    // The export popover, while it's open
+    export: Option<ExportSettings>,
+}
+
+impl<D: FileDialogService> FileMenu<D> {
+    pub fn new(document: Arc<RwLock<Document>>, dialogs: D, notifier: Notifier) -> Self {
+        Self {
+            document,
+            dialogs,
+            notifier,
+            loading: None,
+            export: None,
+        }
+    }
+
+    /// The open document. New and Open replace it, so views built on the
+    /// old one need rebuilding when [`FileMenu::run`] says so.
+    pub fn document(&self) -> &Arc<RwLock<Document>> {
+        &self.document
+    }
+
+    /// The file name and, with unsaved changes, a `*`.
+    pub fn window_title(&self) -> String {
+        let document = self.document.read();
+        let name = document.path()
+            .and_then(Path::file_name)
+            .map_or_else(|| "Untitled".to_string(), |name| name.to_string_lossy().into_owned());
+        let modified = if document.is_modified() { "*" } else { "" };
+        format!("{}{} - {}", name, modified, APP_NAME)
+    }
+
+    /// Runs a menu entry. Returns whether it replaced the document; Open
+    /// does so later, see [`FileMenu::poll`].
+    pub fn run(&mut self, action: FileAction) -> bool {
+        match action {
+            FileAction::New => self.new_document(),
+            FileAction::Open => self.open(),
+            FileAction::Save => {
+                self.save();
+                false
+            }
+            FileAction::SaveAs => {
+                self.save_as();
+                false
+            }
+            FileAction::Export => {
+                self.open_export();
+                false
+            }
+        }
+    }
+
+    /// Replaces the document with an empty one of the same size, once
+    /// unsaved changes are dealt with.
+    pub fn new_document(&mut self) -> bool {
+        if !self.resolve_unsaved() {
+            return false;
+        }
+        let (width, height) = self.document.read().size();
+        self.document = Arc::new(RwLock::new(Document::with_size(width, height)));
+        true
+    }
+
+    /// Starts loading a document picked from disk, once unsaved changes are
+    /// dealt with. It replaces the document once loaded, see
+    /// [`FileMenu::poll`], so this never does and returns false.
+    pub fn open(&mut self) -> bool {
+        if self.loading.is_some() || !self.resolve_unsaved() {
+            return false;
+        }
+        let Some(path) = self.dialogs.pick_open() else {
+            return false;
+        };
+        self.load(path);
+        false
+    }
+
+    /// Starts loading `path`, e.g. from File > Recent, once unsaved changes
+    /// are dealt with. Like [`FileMenu::open`], it replaces the document
+    /// once loaded.
+    pub fn open_path(&mut self, path: &Path) {
+        if self.loading.is_none() && self.resolve_unsaved() {
+            self.load(path.to_path_buf());
+        }
+    }
+
+    fn load(&mut self, path: PathBuf) {
+        let progress = self.notifier.progress(format!("Opening {}", file_name(&path)));
+        let reporter = progress.clone();
+        let started = Document::load_async(&path, move |update| reporter.update(update.fraction, load_phase_name(update.phase)));
+        match started {
+            Ok(task) => self.loading = Some(Loading { path, task, progress }),
+            Err(e) => self.show_error(format!("Couldn't open {}", path.display()), &e),
+        }
+    }
+
+    /// Whether a document is being opened.
+    pub fn is_loading(&self) -> bool {
+        self.loading.is_some()
+    }
+
+    /// Checks on the document being opened, passing on a press of its
+    /// progress toast's cancel button. Returns whether it finished loading
+    /// and replaced the document.
+    pub fn poll(&mut self) -> bool {
+        let Some(loading) = &self.loading else {
+            return false;
+        };
+        if loading.progress.is_cancelled() {
+            loading.task.cancel();
+        }
+        if !loading.task.is_finished() {
+            return false;
+        }
+        let Loading { path, task, progress } = self.loading.take().expect("checked above");
+        // The toast goes once the worker's handle is gone too
+        drop(progress);
+        match task.join() {
+            Ok(document) => {
+                self.document = Arc::new(RwLock::new(document));
+                true
+            }
+            Err(DocumentError::Cancelled) => {
+                self.notifier.notify(NotificationLevel::Info, format!("Stopped opening {}", file_name(&path)), None);
+                false
+            }
+            Err(e) => {
+                self.show_error(format!("Couldn't open {}", path.display()), &e);
+                false
+            }
+        }
+    }
+
+    /// Saves to the file the document came from, or asks where for a new
+    /// document. Returns whether it saved.
+    pub fn save(&mut self) -> bool {
+        let path = self.document.read().path().map(Path::to_path_buf);
+        match path {
+            Some(path) => self.write(&path),
+            None => self.save_as(),
+        }
+    }
+
+    /// Asks where to save, as a package unless a plain document is picked.
+    pub fn save_as(&mut self) -> bool {
+        let name = self.document.read().name();
+        match self.dialogs.pick_save(&format!("{}.{}", name, PACKAGE_EXTENSION), &[PACKAGE_EXTENSION, DOCUMENT_EXTENSION]) {
+            Some(path) => self.write(&path),
+            None => false,
+        }
+    }
+
+    fn write(&mut self, path: &Path) -> bool {
+        let mut document = self.document.write();
+        let saved = if is_package(path) {
+            document.save_package(path)
+        } else {
+            document.save(path)
+        };
+        drop(document);
+        match saved {
+            Ok(()) => true,
+            Err(e) => {
+                self.show_error(format!("Couldn't save {}", path.display()), &e);
+                false
+            }
+        }
+    }
+
+    /// Asks to save unsaved changes about to be lost. Returns whether to go
+    /// on.
+    fn resolve_unsaved(&mut self) -> bool {
+        if !self.document.read().is_modified() {
+            return true;
+        }
+        let name = self.document.read().name();
+        match self.dialogs.confirm_unsaved(&name) {
+            UnsavedChoice::Save => self.save(),
+            UnsavedChoice::Discard => true,
+            UnsavedChoice::Cancel => false,
+        }
+    }
+
+    /// The export popover's options, while it's open.
+    pub fn export_settings(&self) -> Option<&ExportSettings> {
+        self.export.as_ref()
+    }
+
+    pub fn export_settings_mut(&mut self) -> Option<&mut ExportSettings> {
+        self.export.as_mut()
+    }
+
+    /// Opens the export popover.
+    pub fn open_export(&mut self) {
+        self.export.get_or_insert_with(ExportSettings::default);
+    }
+
+    pub fn cancel_export(&mut self) {
+        self.export = None;
+    }
+
+    /// Closes the popover and exports with its options to a file picked in
+    /// their format. Returns whether it exported.
+    pub fn confirm_export(&mut self) -> bool {
+        let Some(settings) = self.export.take() else {
+            return false;
+        };
+        let extension = settings.format.extension();
+        let name = self.document.read().name();
+        let Some(path) = self.dialogs.pick_save(&format!("{}.{}", name, extension), &[extension]) else {
+            return false;
+        };
+        let options = ExportOptions {
+            format: Some(settings.format),
+            jpeg_quality: settings.quality.clamp(1, 100),
+            ..ExportOptions::default()
+        };
+        let exported = self.document.read().export(&path, options);
+        match exported {
+            Ok(()) => true,
+            Err(e) => {
+                self.show_error(format!("Couldn't export {}", path.display()), &e);
+                false
+            }
+        }
+    }
+
+    fn show_error(&mut self, message: String, error: &DocumentError) {
+        tracing::warn!("{}: {}", message, error);
+        self.notifier.error(message, error);
+    }
+}
+
+fn is_package(path: &Path) -> bool {
+    path.extension().map_or(false, |extension| extension == PACKAGE_EXTENSION)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+fn load_phase_name(phase: LoadPhase) -> &'static str {
+    match phase {
+        LoadPhase::Reading => "Reading",
+        LoadPhase::DecompressingAssets => "Decompressing images",
+        LoadPhase::BuildingGraphs => "Building graphs",
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::fs;
+    use std::time::{Duration, Instant};
+    use crate::notifications::NotificationCenter;
+
+    /// Answers dialogs from queues, as if the user picked each in turn.
+    #[derive(Default)]
+    struct ScriptedDialogs {
+        opens: VecDeque<Option<PathBuf>>,
+        saves: VecDeque<Option<PathBuf>>,
+        unsaved: VecDeque<UnsavedChoice>,
+    }
+
+    impl FileDialogService for ScriptedDialogs {
+        fn pick_open(&mut self) -> Option<PathBuf> {
+            self.opens.pop_front().expect("unexpected open dialog")
+        }
+
+        fn pick_save(&mut self, _file_name: &str, _extensions: &[&str]) -> Option<PathBuf> {
+            self.saves.pop_front().expect("unexpected save dialog")
+        }
+
+        fn confirm_unsaved(&mut self, _name: &str) -> UnsavedChoice {
+            self.unsaved.pop_front().expect("unexpected unsaved changes prompt")
+        }
+    }
+
+    /// Waits for the document being opened. Returns whether it replaced
+    /// the open one.
+    fn finish_loading<D: FileDialogService>(menu: &mut FileMenu<D>) -> bool {
+        loop {
+            if menu.poll() {
+                return true;
+            }
+            if !menu.is_loading() {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("solaris_files_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_save_open_and_unsaved_changes() {
+        let dir = temp_dir("save_open");
+        let package = dir.join("art.artm");
+        let mut menu = FileMenu::new(Arc::new(RwLock::new(Document::with_size(8, 8))), ScriptedDialogs::default(), NotificationCenter::new().notifier());
+        menu.document().write().add_layer();
+        assert_eq!(menu.window_title(), "Untitled* - Solaris UI");
+
+        // Save on a new document asks where, then keeps saving there
+        menu.dialogs.saves.push_back(Some(package.clone()));
+        assert!(menu.save());
+        assert_eq!(menu.window_title(), "art.artm - Solaris UI");
+        menu.document().write().add_layer();
+        assert!(!menu.run(FileAction::Save));
+        assert!(!menu.document().read().is_modified());
+
+        // Open with unsaved changes asks first, and cancelling keeps them
+        menu.document().write().add_layer();
+        menu.dialogs.unsaved.push_back(UnsavedChoice::Cancel);
+        assert!(!menu.run(FileAction::Open));
+        assert!(!menu.is_loading());
+        assert_eq!(menu.document().read().layer_count(), 3);
+
+        let plain = dir.join("plain.json");
+        menu.dialogs.saves.push_back(Some(plain.clone()));
+        assert!(menu.save_as());
+        menu.document().write().add_layer();
+        menu.dialogs.unsaved.push_back(UnsavedChoice::Discard);
+        menu.dialogs.opens.push_back(Some(package.clone()));
+        assert!(!menu.run(FileAction::Open));
+        assert!(finish_loading(&mut menu));
+        assert_eq!(menu.document().read().layer_count(), 2);
+        assert_eq!(menu.document().read().path(), Some(package.as_path()));
+
+        menu.dialogs.opens.push_back(Some(plain));
+        menu.run(FileAction::Open);
+        assert!(finish_loading(&mut menu));
+        assert_eq!(menu.document().read().layer_count(), 3);
+        assert_eq!(menu.window_title(), "plain.json - Solaris UI");
+
+        // New keeps the size
+        assert!(menu.run(FileAction::New));
+        assert_eq!(menu.document().read().size(), (8, 8));
+        assert_eq!(menu.document().read().layer_count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_failures_and_progress_notify() {
+        let dir = temp_dir("failures");
+        let mut center = NotificationCenter::new();
+        let mut menu = FileMenu::new(Arc::new(RwLock::new(Document::with_size(8, 8))), ScriptedDialogs::default(), center.notifier());
+        let broken = dir.join("broken.json");
+        fs::write(&broken, "not a document").unwrap();
+        menu.dialogs.opens.push_back(Some(broken));
+        menu.run(FileAction::Open);
+        assert!(menu.is_loading());
+        assert!(!finish_loading(&mut menu));
+        center.update(Instant::now());
+        // Only the error is left once the progress toast finishes
+        let error = center.shown().last().unwrap();
+        assert_eq!(center.shown().len(), 1);
+        assert_eq!(error.level, NotificationLevel::Error);
+        assert!(error.message.starts_with("Couldn't open"), "{}", error.message);
+        assert!(error.details.is_some());
+
+        menu.dialogs.saves.push_back(Some(dir.join("missing").join("art.artm")));
+        assert!(!menu.run(FileAction::SaveAs));
+        center.update(Instant::now());
+        assert!(center.shown().last().unwrap().message.starts_with("Couldn't save"));
+
+        // Cancelling from the progress toast stops the load and keeps the
+        // open document
+        let package = dir.join("art.artm");
+        menu.document().write().save_package(&package).unwrap();
+        menu.document().write().add_layer();
+        menu.dialogs.unsaved.push_back(UnsavedChoice::Discard);
+        menu.dialogs.opens.push_back(Some(package));
+        menu.run(FileAction::Open);
+        let progress = menu.loading.as_ref().unwrap().progress.id();
+        center.update(Instant::now());
+        center.cancel(progress);
+        let replaced = finish_loading(&mut menu);
+        center.update(Instant::now());
+        assert!(center.shown().iter().all(|notification| notification.id != progress));
+        if replaced {
+            // The load finished before it saw the cancel
+            assert_eq!(menu.document().read().layer_count(), 0);
+        } else {
+            assert!(center.shown().last().unwrap().message.starts_with("Stopped opening"));
+            assert_eq!(menu.document().read().layer_count(), 1);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_export_uses_the_popover_options() {
+        let dir = temp_dir("export");
+        let mut document = Document::with_size(8, 6);
+        let asset = document.add_asset(image::DynamicImage::new_rgba8(8, 6));
+        document.add_asset_layer("Photo", asset);
+        let mut menu = FileMenu::new(Arc::new(RwLock::new(document)), ScriptedDialogs::default(), NotificationCenter::new().notifier());
+        menu.run(FileAction::Export);
+        let settings = menu.export_settings_mut().unwrap();
+        settings.format = ExportFormat::Jpeg;
+        settings.quality = 70;
+
+        // Cancelling the file dialog exports nothing
+        menu.dialogs.saves.push_back(None);
+        assert!(!menu.confirm_export());
+        assert!(menu.export_settings().is_none());
+
+        let path = dir.join("art.jpg");
+        menu.open_export();
+        menu.export_settings_mut().unwrap().format = ExportFormat::Jpeg;
+        menu.dialogs.saves.push_back(Some(path.clone()));
+        assert!(menu.confirm_export());
+        let exported = image::open(&path).unwrap();
+        assert_eq!((exported.width(), exported.height()), (8, 6));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,686 @@
+//! The layer panel: the stack top layer first, with controls for each layer.
+//! Every change goes through the document's history, and the rows follow
+//! the document through its events, so undo shows up here too.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use parking_lot::RwLock;
+use winit::keyboard::{Key, NamedKey};
+use vello::{
+    SceneBuilder,
+    peniko::{Fill, Color, Stroke},
+    kurbo::{Affine, Circle, Line, Point, Rect},
+};
+use meridian_document::commands::{AddLayerCommand, LayerProperty, RemoveLayerCommand, SetLayerPropertyCommand};
+use meridian_document::{BlendMode, Command, Document, DocumentError, Layer, LayerId, ListenerId, NamePolicy};
+use crate::theme::Theme;
+
+pub const PANEL_WIDTH: f64 = 240.0;
+const HEADER_HEIGHT: f64 = 36.0;
+const ROW_HEIGHT: f64 = 52.0;
+const OPTION_HEIGHT: f64 = 20.0;
+
+/// What the panel shows of a layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerRow {
+    pub id: LayerId,
+    pub name: String,
+    pub visible: bool,
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+    /// Whether the layer's graph has nodes, so deleting it asks first.
+    pub has_content: bool,
+}
+
+/// The part of the panel under a point.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PanelHit {
+    AddButton,
+    Row(LayerId),
+    Visibility(LayerId),
+    Name(LayerId),
+    MoveUp(LayerId),
+    MoveDown(LayerId),
+    Delete(LayerId),
+    /// The opacity slider, at the opacity under the point.
+    Opacity(LayerId, f32),
+    BlendModeButton(LayerId),
+    BlendModeOption(LayerId, BlendMode),
+    ConfirmDelete,
+    CancelDelete,
+}
+
+/// Areas of a row, in panel coordinates.
+struct RowRects {
+    row: Rect,
+    visibility: Rect,
+    name: Rect,
+    move_up: Rect,
+    move_down: Rect,
+    delete: Rect,
+    opacity: Rect,
+    blend_mode: Rect,
+}
+
+impl RowRects {
+    fn new(index: usize) -> Self {
+        let y = HEADER_HEIGHT + ROW_HEIGHT * index as f64;
+        Self {
+            row: Rect::new(0.0, y, PANEL_WIDTH, y + ROW_HEIGHT),
+            visibility: Rect::new(8.0, y + 8.0, 28.0, y + 28.0),
+            name: Rect::new(34.0, y + 6.0, 150.0, y + 26.0),
+            move_up: Rect::new(156.0, y + 6.0, 176.0, y + 26.0),
+            move_down: Rect::new(180.0, y + 6.0, 200.0, y + 26.0),
+            delete: Rect::new(208.0, y + 6.0, 228.0, y + 26.0),
+            opacity: Rect::new(34.0, y + 32.0, 150.0, y + 44.0),
+            blend_mode: Rect::new(156.0, y + 30.0, 228.0, y + 46.0),
+        }
+    }
+
+    /// The blend mode menu's options, dropping down from its button.
+    fn blend_options(&self) -> impl Iterator<Item = (BlendMode, Rect)> + '_ {
+        BlendMode::all().iter().enumerate().map(|(i, mode)| {
+            let y = self.blend_mode.y1 + OPTION_HEIGHT * i as f64;
+            (*mode, Rect::new(self.blend_mode.x0, y, self.blend_mode.x1, y + OPTION_HEIGHT))
+        })
+    }
+
+    /// The slider's opacity at `x`.
+    fn opacity_at(&self, x: f64) -> f32 {
+        ((x - self.opacity.x0) / self.opacity.width()).clamp(0.0, 1.0) as f32
+    }
+}
+
+fn add_button_rect() -> Rect {
+    Rect::new(PANEL_WIDTH - 32.0, 6.0, PANEL_WIDTH - 8.0, 30.0)
+}
+
+/// The delete confirmation, over the top of the panel, and its buttons.
+fn confirm_rects() -> (Rect, Rect, Rect) {
+    (
+        Rect::new(8.0, 40.0, PANEL_WIDTH - 8.0, 120.0),
+        Rect::new(20.0, 84.0, 110.0, 110.0),
+        Rect::new(130.0, 84.0, PANEL_WIDTH - 20.0, 110.0),
+    )
+}
+
+/// Draws `label` at `point` in panel coordinates.
+pub(crate) fn draw_label(builder: &mut SceneBuilder, transform: Affine, label: &str, color: Color, point: Point) {
+    let point = transform * point;
+    builder.draw_text(label, color, (point.x, point.y));
+}
+
+pub struct LayerPanel {
+    document: Arc<RwLock<Document>>,
+    listener: ListenerId,
+    // Set by document events, see `LayerPanel::sync`
+    stale: Arc<AtomicBool>,
+    rows: Vec<LayerRow>,
+    selected: Option<LayerId>,
+    // The layer being renamed and the name typed so far
+    renaming: Option<(LayerId, String)>,
+    // A layer with content waiting for its deletion to be confirmed
+    pending_delete: Option<LayerId>,
+    blend_menu: Option<LayerId>,
+    // The layer whose opacity is being dragged, and its opacity before
+    opacity_drag: Option<(LayerId, f32)>,
+}
+
+impl LayerPanel {
+    pub fn new(document: Arc<RwLock<Document>>) -> Self {
+        let stale = Arc::new(AtomicBool::new(false));
+        let flag = stale.clone();
+        let listener = document.write().subscribe(move |_| flag.store(true, Ordering::Relaxed));
+        let mut panel = Self {
+            document,
+            listener,
+            stale,
+            rows: Vec::new(),
+            selected: None,
+            renaming: None,
+            pending_delete: None,
+            blend_menu: None,
+            opacity_drag: None,
+        };
+        panel.refresh();
+        panel
+    }
+
+    /// The layers, top first.
+    pub fn rows(&self) -> &[LayerRow] {
+        &self.rows
+    }
+
+    pub fn row(&self, id: &LayerId) -> Option<&LayerRow> {
+        self.rows.iter().find(|row| row.id == *id)
+    }
+
+    pub fn selected(&self) -> Option<&LayerId> {
+        self.selected.as_ref()
+    }
+
+    pub fn renaming(&self) -> Option<&(LayerId, String)> {
+        self.renaming.as_ref()
+    }
+
+    pub fn pending_delete(&self) -> Option<&LayerId> {
+        self.pending_delete.as_ref()
+    }
+
+    pub fn blend_menu(&self) -> Option<&LayerId> {
+        self.blend_menu.as_ref()
+    }
+
+    /// Rereads the rows from the document, dropping state for layers that
+    /// are gone.
+    pub fn refresh(&mut self) {
+        let document = self.document.read();
+        let mut ids: Vec<LayerId> = document.layers().cloned().collect();
+        ids.reverse();
+        self.rows = ids.into_iter()
+            .filter_map(|id| {
+                let layer = document.get_layer(&id)?;
+                let layer = layer.read();
+                Some(LayerRow {
+                    name: layer.name().to_string(),
+                    visible: layer.is_visible(),
+                    opacity: layer.opacity(),
+                    blend_mode: layer.blend_mode(),
+                    has_content: !layer.node_graph().get_node_ids().is_empty(),
+                    id,
+                })
+            })
+            .collect();
+        drop(document);
+        let exists = |id: &LayerId| self.rows.iter().any(|row| row.id == *id);
+        let selected = self.selected.take().filter(|id| exists(id));
+        let renaming = self.renaming.take().filter(|(id, _)| exists(id));
+        let pending_delete = self.pending_delete.take().filter(|id| exists(id));
+        let blend_menu = self.blend_menu.take().filter(|id| exists(id));
+        self.selected = selected;
+        self.renaming = renaming;
+        self.pending_delete = pending_delete;
+        self.blend_menu = blend_menu;
+    }
+
+    /// Refreshes the rows if the document changed since the last call.
+    /// Returns whether it did, so the panel needs repainting.
+    pub fn sync(&mut self) -> bool {
+        let stale = self.stale.swap(false, Ordering::Relaxed);
+        if stale {
+            self.refresh();
+        }
+        stale
+    }
+
+    fn execute(&mut self, command: Box<dyn Command>) -> Result<(), DocumentError> {
+        let result = self.document.write().execute_command(command);
+        self.sync();
+        result
+    }
+
+    /// Adds an empty layer above the selected one, or on top, and selects
+    /// it.
+    pub fn add_layer(&mut self) -> Result<LayerId, DocumentError> {
+        let mut layer = Layer::new();
+        let command = {
+            let document = self.document.read();
+            layer.set_name(document.unique_layer_name("Layer", None));
+            let command = AddLayerCommand::new(layer);
+            match self.selected.as_ref().and_then(|id| document.layer_index(id)) {
+                Some(index) => command.at(index + 1),
+                None => command,
+            }
+        };
+        let id = command.layer_id().clone();
+        self.execute(Box::new(command))?;
+        self.selected = Some(id.clone());
+        Ok(id)
+    }
+
+    pub fn toggle_visibility(&mut self, id: &LayerId) -> Result<(), DocumentError> {
+        // A missing layer fails in the command
+        let visible = self.row(id).map_or(true, |row| row.visible);
+        self.execute(Box::new(SetLayerPropertyCommand::new(id.clone(), LayerProperty::Visible(!visible))))
+    }
+
+    pub fn move_up(&mut self, id: &LayerId) -> Result<(), DocumentError> {
+        let result = self.document.write().move_layer_up(id);
+        self.sync();
+        result
+    }
+
+    pub fn move_down(&mut self, id: &LayerId) -> Result<(), DocumentError> {
+        let result = self.document.write().move_layer_down(id);
+        self.sync();
+        result
+    }
+
+    /// Deletes the layer, or for a layer with content asks for
+    /// confirmation first, see [`LayerPanel::confirm_delete`].
+    pub fn request_delete(&mut self, id: &LayerId) -> Result<(), DocumentError> {
+        match self.row(id) {
+            Some(row) if row.has_content => {
+                self.pending_delete = Some(id.clone());
+                Ok(())
+            }
+            _ => self.execute(Box::new(RemoveLayerCommand::new(id.clone()))),
+        }
+    }
+
+    pub fn confirm_delete(&mut self) -> Result<(), DocumentError> {
+        match self.pending_delete.take() {
+            Some(id) => self.execute(Box::new(RemoveLayerCommand::new(id))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn cancel_delete(&mut self) {
+        self.pending_delete = None;
+    }
+
+    /// Starts renaming the layer inline, from its current name.
+    pub fn begin_rename(&mut self, id: &LayerId) {
+        if let Some(row) = self.row(id) {
+            self.renaming = Some((id.clone(), row.name.clone()));
+        }
+    }
+
+    /// Renames the layer to the typed name, made unique. An empty name
+    /// keeps the old one.
+    pub fn commit_rename(&mut self) -> Result<(), DocumentError> {
+        let Some((id, name)) = self.renaming.take() else {
+            return Ok(());
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return Ok(());
+        }
+        let result = self.document.write().rename_layer(&id, name, NamePolicy::MakeUnique).map(|_| ());
+        self.sync();
+        result
+    }
+
+    pub fn cancel_rename(&mut self) {
+        self.renaming = None;
+    }
+
+    /// Types into the name being edited: Enter commits, Escape cancels.
+    /// Returns whether a rename took the key.
+    pub fn handle_key(&mut self, key: &Key) -> Result<bool, DocumentError> {
+        let Some((_, name)) = self.renaming.as_mut() else {
+            return Ok(false);
+        };
+        match key {
+            Key::Named(NamedKey::Enter) => self.commit_rename()?,
+            Key::Named(NamedKey::Escape) => self.cancel_rename(),
+            Key::Named(NamedKey::Backspace) => {
+                name.pop();
+            }
+            Key::Named(NamedKey::Space) => name.push(' '),
+            Key::Character(text) => name.push_str(text),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// Sets the layer's opacity in one undo step.
+    pub fn set_opacity(&mut self, id: &LayerId, opacity: f32) -> Result<(), DocumentError> {
+        self.execute(Box::new(SetLayerPropertyCommand::new(id.clone(), LayerProperty::Opacity(opacity))))
+    }
+
+    /// Starts dragging the layer's opacity slider. The drag previews on the
+    /// layer and [`LayerPanel::end_opacity_drag`] records it.
+    pub fn begin_opacity_drag(&mut self, id: &LayerId) {
+        if let Some(row) = self.row(id) {
+            self.opacity_drag = Some((id.clone(), row.opacity));
+        }
+    }
+
+    pub fn drag_opacity(&mut self, opacity: f32) {
+        let Some((id, _)) = &self.opacity_drag else {
+            return;
+        };
+        if let Some(layer) = self.document.read().get_layer(id) {
+            layer.write().set_opacity(opacity);
+        }
+        if let Some(row) = self.rows.iter_mut().find(|row| row.id == *id) {
+            row.opacity = opacity;
+        }
+    }
+
+    /// Ends the slider drag as a single undoable change, from the opacity
+    /// the drag started at.
+    pub fn end_opacity_drag(&mut self) -> Result<(), DocumentError> {
+        let Some((id, original)) = self.opacity_drag.take() else {
+            return Ok(());
+        };
+        let Some(opacity) = self.row(&id).map(|row| row.opacity) else {
+            return Ok(());
+        };
+        if let Some(layer) = self.document.read().get_layer(&id) {
+            layer.write().set_opacity(original);
+        }
+        if opacity == original {
+            return Ok(());
+        }
+        self.set_opacity(&id, opacity)
+    }
+
+    pub fn toggle_blend_menu(&mut self, id: &LayerId) {
+        self.blend_menu = match self.blend_menu.take() {
+            Some(open) if open == *id => None,
+            _ => Some(id.clone()),
+        };
+    }
+
+    pub fn set_blend_mode(&mut self, id: &LayerId, mode: BlendMode) -> Result<(), DocumentError> {
+        self.blend_menu = None;
+        self.execute(Box::new(SetLayerPropertyCommand::new(id.clone(), LayerProperty::BlendMode(mode))))
+    }
+
+    /// What's under `point`, in panel coordinates. While a deletion waits
+    /// for confirmation only its buttons respond, and an open blend mode
+    /// menu covers the rows below it.
+    pub fn hit(&self, point: Point) -> Option<PanelHit> {
+        if self.pending_delete.is_some() {
+            let (_, confirm, cancel) = confirm_rects();
+            return if confirm.contains(point) {
+                Some(PanelHit::ConfirmDelete)
+            } else if cancel.contains(point) {
+                Some(PanelHit::CancelDelete)
+            } else {
+                None
+            };
+        }
+        if add_button_rect().contains(point) {
+            return Some(PanelHit::AddButton);
+        }
+        if let Some(open) = &self.blend_menu {
+            let index = self.rows.iter().position(|row| row.id == *open)?;
+            let option = RowRects::new(index).blend_options().find(|(_, rect)| rect.contains(point));
+            if let Some((mode, _)) = option {
+                return Some(PanelHit::BlendModeOption(open.clone(), mode));
+            }
+        }
+        let (index, row) = self.rows.iter().enumerate().find(|(i, _)| RowRects::new(*i).row.contains(point))?;
+        let rects = RowRects::new(index);
+        let id = row.id.clone();
+        Some(if rects.visibility.contains(point) {
+            PanelHit::Visibility(id)
+        } else if rects.name.contains(point) {
+            PanelHit::Name(id)
+        } else if rects.move_up.contains(point) {
+            PanelHit::MoveUp(id)
+        } else if rects.move_down.contains(point) {
+            PanelHit::MoveDown(id)
+        } else if rects.delete.contains(point) {
+            PanelHit::Delete(id)
+        } else if rects.opacity.contains(point) {
+            PanelHit::Opacity(id, rects.opacity_at(point.x))
+        } else if rects.blend_mode.contains(point) {
+            PanelHit::BlendModeButton(id)
+        } else {
+            PanelHit::Row(id)
+        })
+    }
+
+    /// A press at `point`, in panel coordinates. A press away from the name
+    /// being edited commits it first.
+    pub fn pointer_down(&mut self, point: Point, double_click: bool) -> Result<(), DocumentError> {
+        let hit = self.hit(point);
+        let editing = self.renaming.as_ref().map(|(id, _)| id.clone());
+        if editing.is_some() && editing.as_ref() != hit.as_ref().and_then(|hit| match hit {
+            PanelHit::Name(id) => Some(id),
+            _ => None,
+        }) {
+            self.commit_rename()?;
+        }
+        if !matches!(hit, Some(PanelHit::BlendModeButton(_) | PanelHit::BlendModeOption(..))) {
+            self.blend_menu = None;
+        }
+        match hit {
+            None => Ok(()),
+            Some(PanelHit::AddButton) => self.add_layer().map(|_| ()),
+            Some(PanelHit::Row(id)) => {
+                self.selected = Some(id);
+                Ok(())
+            }
+            Some(PanelHit::Name(id)) => {
+                if double_click {
+                    self.begin_rename(&id);
+                }
+                self.selected = Some(id);
+                Ok(())
+            }
+            Some(PanelHit::Visibility(id)) => self.toggle_visibility(&id),
+            Some(PanelHit::MoveUp(id)) => self.move_up(&id),
+            Some(PanelHit::MoveDown(id)) => self.move_down(&id),
+            Some(PanelHit::Delete(id)) => self.request_delete(&id),
+            Some(PanelHit::Opacity(id, opacity)) => {
+                self.begin_opacity_drag(&id);
+                self.drag_opacity(opacity);
+                Ok(())
+            }
+            Some(PanelHit::BlendModeButton(id)) => {
+                self.toggle_blend_menu(&id);
+                Ok(())
+            }
+            Some(PanelHit::BlendModeOption(id, mode)) => self.set_blend_mode(&id, mode),
+            Some(PanelHit::ConfirmDelete) => self.confirm_delete(),
+            Some(PanelHit::CancelDelete) => {
+                self.cancel_delete();
+                Ok(())
+            }
+        }
+    }
+
+    /// Moves the opacity slider being dragged to `point`.
+    pub fn pointer_move(&mut self, point: Point) {
+        let index = self.opacity_drag.as_ref()
+            .and_then(|(id, _)| self.rows.iter().position(|row| row.id == *id));
+        if let Some(index) = index {
+            self.drag_opacity(RowRects::new(index).opacity_at(point.x));
+        }
+    }
+
+    pub fn pointer_up(&mut self) -> Result<(), DocumentError> {
+        self.end_opacity_drag()
+    }
+
+    /// Draws the panel `height` tall, placed by `transform`.
+    pub fn paint(&self, builder: &mut SceneBuilder, transform: Affine, height: f64, theme: &Theme) {
+        let text = theme.text.color();
+        let control = theme.control.color();
+        builder.fill(Fill::NonZero, transform, theme.panel_background.color(), None, &Rect::new(0.0, 0.0, PANEL_WIDTH, height));
+
+        // The header with its add button
+        draw_label(builder, transform, "Layers", text, Point::new(10.0, HEADER_HEIGHT / 2.0));
+        let add = add_button_rect();
+        builder.fill(Fill::NonZero, transform, control, None, &add.to_rounded_rect(4.0));
+        let center = add.center();
+        builder.stroke(&Stroke::new(2.0), transform, text, None, &Line::new((center.x - 6.0, center.y), (center.x + 6.0, center.y)));
+        builder.stroke(&Stroke::new(2.0), transform, text, None, &Line::new((center.x, center.y - 6.0), (center.x, center.y + 6.0)));
+
+        for (index, row) in self.rows.iter().enumerate() {
+            let rects = RowRects::new(index);
+            let background = if self.selected.as_ref() == Some(&row.id) {
+                theme.panel_row_selected
+            } else {
+                theme.panel_background
+            };
+            builder.fill(Fill::NonZero, transform, background.color(), None, &rects.row.inset(-2.0));
+
+            // Visibility is a filled eye, or an outline while hidden
+            let eye = Circle::new(rects.visibility.center(), 6.0);
+            if row.visible {
+                builder.fill(Fill::NonZero, transform, text, None, &eye);
+            } else {
+                builder.stroke(&Stroke::new(1.0), transform, text, None, &eye);
+            }
+
+            match &self.renaming {
+                Some((id, name)) if *id == row.id => {
+                    builder.fill(Fill::NonZero, transform, theme.field_background.color(), None, &rects.name);
+                    builder.stroke(&Stroke::new(1.0), transform, theme.selection.color(), None, &rects.name);
+                    draw_label(builder, transform, name, text, Point::new(rects.name.x0 + 4.0, rects.name.center().y));
+                }
+                _ => draw_label(builder, transform, &row.name, text, Point::new(rects.name.x0 + 4.0, rects.name.center().y)),
+            }
+
+            for button in [&rects.move_up, &rects.move_down, &rects.delete] {
+                builder.fill(Fill::NonZero, transform, control, None, &button.to_rounded_rect(3.0));
+            }
+            draw_label(builder, transform, "▲", text, Point::new(rects.move_up.x0 + 5.0, rects.move_up.center().y));
+            draw_label(builder, transform, "▼", text, Point::new(rects.move_down.x0 + 5.0, rects.move_down.center().y));
+            draw_label(builder, transform, "✕", text, Point::new(rects.delete.x0 + 5.0, rects.delete.center().y));
+
+            // The opacity slider fills up to the layer's opacity
+            builder.fill(Fill::NonZero, transform, control, None, &rects.opacity.to_rounded_rect(3.0));
+            let filled = Rect::new(
+                rects.opacity.x0,
+                rects.opacity.y0,
+                rects.opacity.x0 + rects.opacity.width() * row.opacity as f64,
+                rects.opacity.y1,
+            );
+            builder.fill(Fill::NonZero, transform, theme.selection.color(), None, &filled.to_rounded_rect(3.0));
+
+            builder.fill(Fill::NonZero, transform, control, None, &rects.blend_mode.to_rounded_rect(3.0));
+            draw_label(builder, transform, row.blend_mode.name(), text, Point::new(rects.blend_mode.x0 + 4.0, rects.blend_mode.center().y));
+        }
+
+        // The open blend mode menu covers the rows below it
+        if let Some(index) = self.blend_menu.as_ref().and_then(|open| self.rows.iter().position(|row| row.id == *open)) {
+            let rects = RowRects::new(index);
+            for (mode, rect) in rects.blend_options() {
+                let color = if mode == self.rows[index].blend_mode { theme.highlight } else { theme.popup_background };
+                builder.fill(Fill::NonZero, transform, color.color(), None, &rect);
+                draw_label(builder, transform, mode.name(), text, Point::new(rect.x0 + 4.0, rect.center().y));
+            }
+        }
+
+        if let Some(row) = self.pending_delete.as_ref().and_then(|id| self.row(id)) {
+            let (dialog, confirm, cancel) = confirm_rects();
+            builder.fill(Fill::NonZero, transform, theme.popup_background.color(), None, &dialog.to_rounded_rect(6.0));
+            draw_label(builder, transform, &format!("Delete \"{}\"?", row.name), text, Point::new(dialog.x0 + 12.0, dialog.y0 + 20.0));
+            builder.fill(Fill::NonZero, transform, theme.danger.color(), None, &confirm.to_rounded_rect(4.0));
+            draw_label(builder, transform, "Delete", theme.text_on_accent.color(), Point::new(confirm.x0 + 20.0, confirm.center().y));
+            builder.fill(Fill::NonZero, transform, control, None, &cancel.to_rounded_rect(4.0));
+            draw_label(builder, transform, "Cancel", text, Point::new(cancel.x0 + 20.0, cancel.center().y));
+        }
+    }
+}
+
+impl Drop for LayerPanel {
+    fn drop(&mut self) {
+        self.document.write().unsubscribe(self.listener);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+
+    fn click(panel: &mut LayerPanel, point: Point) {
+        panel.pointer_down(point, false).unwrap();
+        panel.pointer_up().unwrap();
+    }
+
+    fn rects_of(panel: &LayerPanel, id: &LayerId) -> RowRects {
+        RowRects::new(panel.rows().iter().position(|row| row.id == *id).unwrap())
+    }
+
+    fn names(panel: &LayerPanel) -> Vec<&str> {
+        panel.rows().iter().map(|row| row.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_manage_layers_and_undo_each_change() {
+        let document = Arc::new(RwLock::new(Document::with_size(8, 8)));
+        let (paper, sketch, photo) = {
+            let mut document = document.write();
+            let paper = document.add_layer_named("Background", NamePolicy::MakeUnique);
+            let sketch = document.add_layer_named("Sketch", NamePolicy::MakeUnique);
+            let add = AddLayerCommand::new(Layer::with_image("Photo", DynamicImage::new_rgba8(8, 8)));
+            let photo = add.layer_id().clone();
+            document.execute_command(Box::new(add)).unwrap();
+            (paper, sketch, photo)
+        };
+        let mut panel = LayerPanel::new(document.clone());
+        let initial = panel.rows().to_vec();
+        assert_eq!(names(&panel), ["Photo", "Sketch", "Background"]);
+
+        click(&mut panel, add_button_rect().center());
+        let added = panel.selected().unwrap().clone();
+        assert_eq!(names(&panel), ["Layer", "Photo", "Sketch", "Background"]);
+
+        click(&mut panel, rects_of(&panel, &sketch).visibility.center());
+        assert!(!panel.row(&sketch).unwrap().visible);
+
+        // Double-clicking the name edits it in place
+        panel.pointer_down(rects_of(&panel, &paper).name.center(), true).unwrap();
+        for _ in 0.."Background".len() {
+            panel.handle_key(&Key::Named(NamedKey::Backspace)).unwrap();
+        }
+        for c in "Paper".chars() {
+            panel.handle_key(&Key::Character(c.to_string().into())).unwrap();
+        }
+        assert!(panel.handle_key(&Key::Named(NamedKey::Enter)).unwrap());
+        assert_eq!(panel.row(&paper).unwrap().name, "Paper");
+
+        click(&mut panel, rects_of(&panel, &sketch).move_up.center());
+        assert_eq!(names(&panel), ["Layer", "Sketch", "Photo", "Paper"]);
+
+        // A slider drag is a single change
+        let slider = rects_of(&panel, &photo).opacity;
+        panel.pointer_down(Point::new(slider.x0 + slider.width() * 0.8, slider.center().y), false).unwrap();
+        panel.pointer_move(Point::new(slider.x0 + slider.width() * 0.5, slider.center().y));
+        panel.pointer_up().unwrap();
+        assert_eq!(panel.row(&photo).unwrap().opacity, 0.5);
+
+        click(&mut panel, rects_of(&panel, &photo).blend_mode.center());
+        assert_eq!(panel.blend_menu(), Some(&photo));
+        let (_, multiply) = rects_of(&panel, &photo).blend_options().find(|(mode, _)| *mode == BlendMode::Multiply).unwrap();
+        click(&mut panel, multiply.center());
+        assert_eq!(panel.row(&photo).unwrap().blend_mode, BlendMode::Multiply);
+        assert!(panel.blend_menu().is_none());
+
+        // The photo has content, so deleting it asks first
+        click(&mut panel, rects_of(&panel, &photo).delete.center());
+        assert_eq!(panel.pending_delete(), Some(&photo));
+        assert_eq!(panel.rows().len(), 4);
+        click(&mut panel, confirm_rects().2.center());
+        assert!(panel.pending_delete().is_none());
+        assert_eq!(panel.rows().len(), 4);
+        click(&mut panel, rects_of(&panel, &photo).delete.center());
+        click(&mut panel, confirm_rects().1.center());
+        assert_eq!(names(&panel), ["Layer", "Sketch", "Paper"]);
+        // An empty layer goes straight away
+        click(&mut panel, rects_of(&panel, &added).delete.center());
+        assert_eq!(names(&panel), ["Sketch", "Paper"]);
+
+        // Each change undoes on its own, and the panel follows the document
+        let undo = |panel: &mut LayerPanel| {
+            document.write().undo().unwrap();
+            assert!(panel.sync());
+        };
+        undo(&mut panel);
+        assert_eq!(names(&panel), ["Layer", "Sketch", "Paper"]);
+        undo(&mut panel);
+        assert_eq!(names(&panel), ["Layer", "Sketch", "Photo", "Paper"]);
+        undo(&mut panel);
+        assert_eq!(panel.row(&photo).unwrap().blend_mode, BlendMode::Normal);
+        undo(&mut panel);
+        assert_eq!(panel.row(&photo).unwrap().opacity, 1.0);
+        undo(&mut panel);
+        assert_eq!(names(&panel), ["Layer", "Photo", "Sketch", "Paper"]);
+        undo(&mut panel);
+        assert_eq!(panel.row(&paper).unwrap().name, "Background");
+        undo(&mut panel);
+        assert!(panel.row(&sketch).unwrap().visible);
+        undo(&mut panel);
+        assert_eq!(panel.rows(), initial.as_slice());
+        assert!(panel.selected().is_none());
+    }
+}
@@ -0,0 +1,47 @@
+//! Undoable node editor edits that live in the layout rather than the graph.
+
+use std::error::Error;
+use aurion_core::NodeId;
+use meridian_document::{Command, Document, LayerId};
+use super::EditorLayout;
+
+/// A node moved by a drag, from one position to another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeMove {
+    pub id: NodeId,
+    pub from: [f64; 2],
+    pub to: [f64; 2],
+}
+
+/// Moves nodes in a layer's stored layout. The editor makes one per drag,
+/// however many pointer moves it took, so a drag undoes in one step.
+#[derive(Debug)]
+pub struct MoveNodesCommand {
+    layer: LayerId,
+    moves: Vec<NodeMove>,
+}
+
+impl MoveNodesCommand {
+    pub fn new(layer: LayerId, moves: Vec<NodeMove>) -> Self {
+        Self { layer, moves }
+    }
+
+    fn place(&self, document: &mut Document, position: impl Fn(&NodeMove) -> [f64; 2]) -> Result<(), Box<dyn Error>> {
+        let mut layout = document.ui_layout(&self.layer).map(EditorLayout::from_value).unwrap_or_default();
+        for node in &self.moves {
+            layout.positions.insert(node.id.clone(), position(node));
+        }
+        document.set_ui_layout(&self.layer, layout.to_value())?;
+        Ok(())
+    }
+}
+
+impl Command for MoveNodesCommand {
+    fn execute(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        self.place(document, |node| node.to)
+    }
+
+    fn undo(&self, document: &mut Document) -> Result<(), Box<dyn Error>> {
+        self.place(document, |node| node.from)
+    }
+}
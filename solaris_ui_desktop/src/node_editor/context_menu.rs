@@ -0,0 +1,70 @@
+//! The menu opened by right-clicking a node.
+
+use aurion_core::NodeId;
+use vello::kurbo::{Point, Rect};
+
+const ITEM_WIDTH: f64 = 180.0;
+const ITEM_HEIGHT: f64 = 24.0;
+
+/// An entry of the node context menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeMenuAction {
+    Rename,
+    ToggleBypass,
+    DisconnectAll,
+    Duplicate,
+    Delete,
+    SetAsOutput,
+}
+
+impl NodeMenuAction {
+    pub const ALL: [NodeMenuAction; 6] = [
+        NodeMenuAction::Rename,
+        NodeMenuAction::ToggleBypass,
+        NodeMenuAction::DisconnectAll,
+        NodeMenuAction::Duplicate,
+        NodeMenuAction::Delete,
+        NodeMenuAction::SetAsOutput,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            NodeMenuAction::Rename => "Rename",
+            NodeMenuAction::ToggleBypass => "Bypass",
+            NodeMenuAction::DisconnectAll => "Disconnect all",
+            NodeMenuAction::Duplicate => "Duplicate",
+            NodeMenuAction::Delete => "Delete",
+            NodeMenuAction::SetAsOutput => "Set as layer output",
+        }
+    }
+}
+
+/// The open context menu: the node it's for and where it was opened, in
+/// canvas coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeContextMenu {
+    pub node: NodeId,
+    pub position: Point,
+}
+
+impl NodeContextMenu {
+    pub fn new(node: NodeId, position: Point) -> Self {
+        Self { node, position }
+    }
+
+    /// Each entry and its row, top to bottom from where the menu opened.
+    pub fn items(&self) -> impl Iterator<Item = (NodeMenuAction, Rect)> + '_ {
+        NodeMenuAction::ALL.into_iter().enumerate().map(|(i, action)| {
+            let origin = self.position + (0.0, ITEM_HEIGHT * i as f64);
+            (action, Rect::from_origin_size(origin, (ITEM_WIDTH, ITEM_HEIGHT)))
+        })
+    }
+
+    pub fn rect(&self) -> Rect {
+        Rect::from_origin_size(self.position, (ITEM_WIDTH, ITEM_HEIGHT * NodeMenuAction::ALL.len() as f64))
+    }
+
+    pub fn action_at(&self, point: Point) -> Option<NodeMenuAction> {
+        self.items().find(|(_, rect)| rect.contains(point)).map(|(action, _)| action)
+    }
+}
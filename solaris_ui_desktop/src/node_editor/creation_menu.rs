@@ -0,0 +1,73 @@
+//! The menu for adding nodes, listing every type in the node registry.
+
+use aurion_core::node_factory::NODE_REGISTRY;
+use aurion_core::NodeRegistry;
+use meridian_document::compositing::register_document_nodes;
+use vello::kurbo::Point;
+use super::rank;
+
+/// A node type the menu can create.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MenuEntry {
+    pub type_name: &'static str,
+    pub category: Option<&'static str>,
+}
+
+impl MenuEntry {
+    /// The type name without its "Node" suffix, e.g. "Blur".
+    pub fn label(&self) -> &'static str {
+        self.type_name.strip_suffix("Node").filter(|label| !label.is_empty()).unwrap_or(self.type_name)
+    }
+}
+
+/// Entries for every type in `registry`, grouped by category, then the
+/// types without one.
+pub fn menu_entries(registry: &NodeRegistry) -> Vec<MenuEntry> {
+    let mut entries = Vec::new();
+    for (category, types) in registry.get_types_by_category() {
+        entries.extend(types.into_iter().map(|type_name| MenuEntry { type_name, category: Some(category) }));
+    }
+    entries.extend(registry.get_uncategorized_types().into_iter().map(|type_name| MenuEntry { type_name, category: None }));
+    entries
+}
+
+/// The menu opened by right-clicking the node editor, with a search box
+/// filtering its entries.
+#[derive(Debug, Clone)]
+pub struct NodeCreationMenu {
+    /// Where the menu was opened, and where created nodes are placed.
+    pub position: Point,
+    query: String,
+    entries: Vec<MenuEntry>,
+}
+
+impl NodeCreationMenu {
+    /// Lists the types in the global registry, with the standard and
+    /// document nodes registered.
+    pub fn open(position: Point) -> Self {
+        register_document_nodes();
+        Self::with_entries(position, menu_entries(&NODE_REGISTRY.read()))
+    }
+
+    pub fn with_entries(position: Point, entries: Vec<MenuEntry>) -> Self {
+        Self {
+            position,
+            query: String::new(),
+            entries,
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn set_query(&mut self, query: impl Into<String>) {
+        self.query = query.into();
+    }
+
+    /// Entries matching the search by label or type name, best matches
+    /// first. Everything, in menu order, while the search is empty.
+    pub fn filtered(&self) -> Vec<MenuEntry> {
+        rank(&self.query, self.entries.iter().copied(), |entry| [entry.label(), entry.type_name])
+    }
+}
@@ -1,39 +1,1523 @@
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use parking_lot::RwLock;
+use std::time::{Duration, Instant};
+use parking_lot::{Mutex, RwLock};
+use winit::keyboard::{Key, ModifiersState, NamedKey};
 use xilem::core::Widget;
 use masonry::WidgetCtx;
 use vello::{
     Scene, SceneBuilder,
-    peniko::{self, Fill, Color, Style},
-    kurbo::{Affine, Rect, Line},
+    peniko::{self, Blob, Fill, Format, Image, Style},
+    kurbo::{Affine, Circle, Rect, Line, Point, Size, Vec2},
 };
-use meridian_document::Document;
-use aurion_core::NodeId;
+use serde_json::json;
+use meridian_document::graph_commands::{
+    AddNodeCommand, ConnectCommand, DisconnectAllCommand, DisconnectCommand, DuplicateNodeCommand, RemoveNodesCommand,
+    SetNodeBypassCommand, SetNodeLabelCommand, SetOutputNodeCommand,
+};
+use meridian_document::compositing::register_document_nodes;
+use meridian_document::{
+    Command, Document, DocumentError, DocumentEvent, ExportOptions, LayerId, ListenerId, PreviewContent, PREVIEW_SIZE,
+};
+use aurion_core::node_factory::NODE_REGISTRY;
+use aurion_core::{NodeGraph, NodeId};
+use super::{
+    auto_layout, menu_entries, CommandPalette, EditorLayout, MenuEntry, MoveNodesCommand, NodeContextMenu, NodeCreationMenu,
+    NodeMenuAction, NodeMove, PaletteAction,
+};
+use crate::layer_panel::draw_label;
+use crate::theme::Theme;
+
+/// How long an error toast stays up.
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+/// How long a rejected wire flashes red.
+const FLASH_DURATION: Duration = Duration::from_millis(600);
+const NODE_SIZE: Size = Size::new(160.0, 60.0);
+/// Height of collapsed nodes, which only show their title.
+const COLLAPSED_HEIGHT: f64 = 24.0;
+/// Extra height of nodes showing a preview: the preview and a margin.
+const PREVIEW_HEIGHT: f64 = PREVIEW_SIZE as f64 + 8.0;
+/// How close a dragged wire has to come to a slot to snap to it.
+const SNAP_RADIUS: f64 = 24.0;
+const SLOT_RADIUS: f64 = 4.0;
+/// Rough width of a character of label text, which isn't measured yet.
+const CHAR_WIDTH: f64 = 7.0;
+const MIN_ZOOM: f64 = 0.1;
+const MAX_ZOOM: f64 = 2.0;
+/// Screen space fit all leaves around the nodes.
+const FIT_PADDING: f64 = 40.0;
+const MINIMAP_SIZE: Size = Size::new(200.0, 140.0);
+/// Space between the minimap and the view's corner, and around its content.
+const MINIMAP_MARGIN: f64 = 12.0;
+const FIT_BUTTON_SIZE: f64 = 18.0;
+const DEFAULT_GRID_SIZE: f64 = 20.0;
+/// How far from the original a duplicated node is placed.
+const DUPLICATE_OFFSET: Vec2 = Vec2::new(24.0, 24.0);
+
+/// `point` moved to the nearest intersection of a `grid` sized grid.
+fn snap_point(point: Point, grid: f64) -> Point {
+    Point::new((point.x / grid).round() * grid, (point.y / grid).round() * grid)
+}
+
+/// Whether `a` and `b` overlap by more than an edge.
+fn overlaps(a: &Rect, b: &Rect) -> bool {
+    a.x0 < b.x1 && b.x0 < a.x1 && a.y0 < b.y1 && b.y0 < a.y1
+}
+
+/// Which edge of the selection [`NodeEditorState::align_selection`] lines
+/// nodes up on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+/// The axis [`NodeEditorState::distribute_selection`] spaces nodes along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Distribution {
+    Horizontal,
+    Vertical,
+}
+
+/// A graph edit picked from the node context menu, for the editor to run as
+/// a command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeEdit {
+    SetBypass(NodeId, bool),
+    /// Marks the layer's output node, or with `None` clears it.
+    SetOutput(Option<NodeId>),
+    DisconnectAll(NodeId),
+    Duplicate(NodeId),
+    Delete(Vec<NodeId>),
+}
+
+/// What a pointer press on the canvas started.
+#[derive(Debug, Clone, PartialEq)]
+enum PointerDrag {
+    /// Moving the selection, last at the point, from where the nodes were
+    /// when the drag started.
+    Nodes(Point, HashMap<NodeId, Point>),
+    /// A marquee from the first point to the second.
+    Marquee(Point, Point),
+    /// Panning from the minimap, which maps the canvas as it did when the
+    /// drag started so it doesn't rescale under the pointer.
+    Minimap(Affine),
+}
 
-#[derive(Default)]
 pub struct NodeEditorState {
-    selected_node: Option<NodeId>,
-    dragging_node: Option<NodeId>,
-    dragging_connection: Option<(NodeId, String)>,
+    selected_nodes: HashSet<NodeId>,
+    drag: Option<PointerDrag>,
+    // Where the pointer last was on the canvas
+    cursor: Option<Point>,
+    // The node whose output a wire is being dragged from, and the pointer
+    dragging_connection: Option<NodeId>,
+    wire_end: Option<Point>,
+    // Mirrors the active layer's graph, see `NodeEditorState::rebuild_connections`
     connections: Vec<NodeConnection>,
+    node_positions: HashMap<NodeId, Point>,
+    node_types: HashMap<NodeId, &'static str>,
+    // Names of the input ports each node declares, in slot order
+    input_ports: HashMap<NodeId, Vec<String>>,
+    scroll: Vec2,
+    zoom: f64,
+    collapsed: HashSet<NodeId>,
+    show_previews: bool,
+    hidden_previews: HashSet<NodeId>,
+    // Mirror the active layer's graph, like `connections`
+    labels: HashMap<NodeId, String>,
+    colors: HashMap<NodeId, [u8; 3]>,
+    bypassed: HashSet<NodeId>,
+    output_node: Option<NodeId>,
+    context_menu: Option<NodeContextMenu>,
+    // The node being renamed and the name typed so far
+    renaming: Option<(NodeId, String)>,
+    // Dragged nodes land on the grid when snapping is on
+    snap_to_grid: bool,
+    grid_size: f64,
+    creation_menu: Option<NodeCreationMenu>,
+    toast: Option<Toast>,
+    flash: Option<(NodeConnection, Instant)>,
 }
 
-#[derive(Clone)]
+impl Default for NodeEditorState {
+    fn default() -> Self {
+        Self {
+            selected_nodes: HashSet::new(),
+            drag: None,
+            cursor: None,
+            dragging_connection: None,
+            wire_end: None,
+            connections: Vec::new(),
+            node_positions: HashMap::new(),
+            node_types: HashMap::new(),
+            input_ports: HashMap::new(),
+            scroll: Vec2::ZERO,
+            zoom: 1.0,
+            collapsed: HashSet::new(),
+            show_previews: false,
+            hidden_previews: HashSet::new(),
+            labels: HashMap::new(),
+            colors: HashMap::new(),
+            bypassed: HashSet::new(),
+            output_node: None,
+            context_menu: None,
+            renaming: None,
+            snap_to_grid: false,
+            grid_size: DEFAULT_GRID_SIZE,
+            creation_menu: None,
+            toast: None,
+            flash: None,
+        }
+    }
+}
+
+/// A wire from a node's output into another node's input.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NodeConnection {
-    from_node: NodeId,
-    to_node: NodeId,
-    to_input: String,
+    pub from_node: NodeId,
+    /// The output of `from_node` the wire leaves, when it isn't the
+    /// primary one (see [`aurion_core::NodeGraph::connect_output`]).
+    pub from_output: Option<String>,
+    pub to_node: NodeId,
+    pub to_input: String,
+}
+
+/// An input slot on a node, where wires end.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InputSlot {
+    pub node: NodeId,
+    pub input: String,
+}
+
+/// Whether a dragged wire can end in a slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlotCompatibility {
+    Compatible,
+    /// Dropping the wire there would fail, for the reason given.
+    Incompatible(String),
+}
+
+/// A message shown over the editor for a while.
+#[derive(Debug, Clone)]
+pub struct Toast {
+    pub message: String,
+    pub expires: Instant,
+}
+
+impl Toast {
+    /// A toast that stays up for the usual time.
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            expires: Instant::now() + TOAST_DURATION,
+        }
+    }
 }
 
 impl NodeEditorState {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// The state for `graph` with a saved layout. Nodes the layout has no
+    /// position for are placed by [`auto_layout`].
+    pub fn from_layout(layout: &EditorLayout, graph: &NodeGraph) -> Self {
+        let mut state = Self {
+            node_positions: layout.positions.iter().map(|(id, [x, y])| (id.clone(), Point::new(*x, *y))).collect(),
+            scroll: Vec2::new(layout.scroll[0], layout.scroll[1]),
+            zoom: layout.zoom,
+            collapsed: layout.collapsed.clone(),
+            hidden_previews: layout.hidden_previews.clone(),
+            ..Self::default()
+        };
+        state.rebuild_connections(graph);
+        state
+    }
+
+    pub fn to_layout(&self) -> EditorLayout {
+        EditorLayout {
+            positions: self.node_positions.iter().map(|(id, point)| (id.clone(), [point.x, point.y])).collect(),
+            scroll: [self.scroll.x, self.scroll.y],
+            zoom: self.zoom,
+            collapsed: self.collapsed.clone(),
+            hidden_previews: self.hidden_previews.clone(),
+        }
+    }
+
+    pub fn node_position(&self, id: &NodeId) -> Option<Point> {
+        self.node_positions.get(id).copied()
+    }
+
+    pub fn move_node(&mut self, id: &NodeId, position: Point) {
+        if let Some(current) = self.node_positions.get_mut(id) {
+            *current = position;
+        }
+    }
+
+    /// The node's area on the canvas, shorter while it's collapsed and
+    /// taller while it shows a preview.
+    pub fn node_rect(&self, id: &NodeId) -> Option<Rect> {
+        let origin = self.node_position(id)?;
+        let height = match (self.is_collapsed(id), self.preview_shown(id)) {
+            (true, _) => COLLAPSED_HEIGHT,
+            (false, true) => NODE_SIZE.height + PREVIEW_HEIGHT,
+            (false, false) => NODE_SIZE.height,
+        };
+        Some(Rect::from_origin_size(origin, (NODE_SIZE.width, height)))
+    }
+
+    /// Where the node's preview goes, below its body, while it's shown.
+    pub fn preview_rect(&self, id: &NodeId) -> Option<Rect> {
+        if !self.preview_shown(id) {
+            return None;
+        }
+        let rect = self.node_rect(id)?;
+        let size = PREVIEW_SIZE as f64;
+        let origin = Point::new(rect.center().x - size / 2.0, rect.y0 + NODE_SIZE.height);
+        Some(Rect::from_origin_size(origin, (size, size)))
+    }
+
+    /// The node drawn at `point`. Where nodes overlap, the one drawn last,
+    /// on top, wins.
+    pub fn node_at(&self, point: Point) -> Option<NodeId> {
+        self.drawing_order()
+            .into_iter()
+            .rev()
+            .find(|id| self.node_rect(id).map_or(false, |rect| rect.contains(point)))
+    }
+
+    /// Nodes whose area overlaps `rect`, in drawing order.
+    pub fn nodes_in_rect(&self, rect: Rect) -> Vec<NodeId> {
+        let rect = rect.abs();
+        self.drawing_order()
+            .into_iter()
+            .filter(|id| self.node_rect(id).map_or(false, |node| overlaps(&node, &rect)))
+            .collect()
+    }
+
+    fn drawing_order(&self) -> Vec<NodeId> {
+        let mut ids: Vec<_> = self.node_positions.keys().cloned().collect();
+        ids.sort_by_key(|id| id.0);
+        ids
+    }
+
+    pub fn selected_nodes(&self) -> &HashSet<NodeId> {
+        &self.selected_nodes
+    }
+
+    pub fn is_selected(&self, id: &NodeId) -> bool {
+        self.selected_nodes.contains(id)
+    }
+
+    /// Selects only `id` on a click, or toggles it on a ctrl-click.
+    pub fn click_node(&mut self, id: &NodeId, toggle: bool) {
+        if !toggle {
+            self.selected_nodes.clear();
+            self.selected_nodes.insert(id.clone());
+        } else if !self.selected_nodes.remove(id) {
+            self.selected_nodes.insert(id.clone());
+        }
+    }
+
+    /// A press at `point`. On a node it selects it like
+    /// [`NodeEditorState::click_node`] and starts dragging the selection;
+    /// a plain press on an already selected node keeps the selection so it
+    /// moves as a group. On empty canvas it starts a marquee, clearing the
+    /// selection unless `toggle` is held.
+    pub fn pointer_down(&mut self, point: Point, toggle: bool) {
+        self.cursor = Some(point);
+        match self.node_at(point) {
+            Some(id) => {
+                if toggle || !self.is_selected(&id) {
+                    self.click_node(&id, toggle);
+                }
+                let origins = self.selected_nodes.iter()
+                    .filter_map(|id| Some((id.clone(), self.node_position(id)?)))
+                    .collect();
+                self.drag = self.is_selected(&id).then_some(PointerDrag::Nodes(point, origins));
+            }
+            None => {
+                if !toggle {
+                    self.selected_nodes.clear();
+                }
+                self.drag = Some(PointerDrag::Marquee(point, point));
+            }
+        }
+    }
+
+    /// Moves the selection with the pointer, or grows the marquee and
+    /// selects the nodes it touches along with those selected before it.
+    pub fn pointer_move(&mut self, point: Point) {
+        self.cursor = Some(point);
+        match self.drag.take() {
+            Some(PointerDrag::Nodes(last, origins)) => {
+                self.move_selection(point - last);
+                self.drag = Some(PointerDrag::Nodes(point, origins));
+            }
+            Some(PointerDrag::Marquee(start, end)) => {
+                for id in self.nodes_in_rect(Rect::from_points(start, end)) {
+                    self.selected_nodes.remove(&id);
+                }
+                self.selected_nodes.extend(self.nodes_in_rect(Rect::from_points(start, point)));
+                self.drag = Some(PointerDrag::Marquee(start, point));
+            }
+            drag => self.drag = drag,
+        }
+    }
+
+    /// Ends the drag, returning the nodes it moved and from where. With
+    /// snapping on, they land on the grid here rather than while dragging,
+    /// so the drag itself stays smooth.
+    pub fn pointer_up(&mut self) -> Vec<NodeMove> {
+        let Some(PointerDrag::Nodes(_, origins)) = self.drag.take() else {
+            return Vec::new();
+        };
+        if self.snap_to_grid {
+            for id in origins.keys() {
+                if let Some(position) = self.node_positions.get_mut(id) {
+                    *position = snap_point(*position, self.grid_size);
+                }
+            }
+        }
+        self.moves_from(origins)
+    }
+
+    /// The nodes that moved away from `origins`, in id order.
+    fn moves_from(&self, origins: impl IntoIterator<Item = (NodeId, Point)>) -> Vec<NodeMove> {
+        let mut moves: Vec<_> = origins.into_iter()
+            .filter_map(|(id, from)| {
+                let to = self.node_position(&id).filter(|to| *to != from)?;
+                Some(NodeMove { id, from: [from.x, from.y], to: [to.x, to.y] })
+            })
+            .collect();
+        moves.sort_by_key(|node| node.id.0);
+        moves
+    }
+
+    pub fn snap_to_grid(&self) -> bool {
+        self.snap_to_grid
+    }
+
+    pub fn set_snap_to_grid(&mut self, snap: bool) {
+        self.snap_to_grid = snap;
+    }
+
+    pub fn grid_size(&self) -> f64 {
+        self.grid_size
+    }
+
+    /// Sets the grid spacing in canvas units. Sizes that aren't positive are
+    /// ignored.
+    pub fn set_grid_size(&mut self, size: f64) {
+        if size > 0.0 {
+            self.grid_size = size;
+        }
+    }
+
+    /// The name shown for a node: its label, or its type without the
+    /// "Node" suffix.
+    pub fn node_title(&self, id: &NodeId) -> Option<String> {
+        if let Some(label) = self.labels.get(id) {
+            return Some(label.clone());
+        }
+        let type_name = self.node_types.get(id)?;
+        Some(MenuEntry { type_name, category: None }.label().to_string())
+    }
+
+    pub fn node_label(&self, id: &NodeId) -> Option<&str> {
+        self.labels.get(id).map(String::as_str)
+    }
+
+    /// The color tag a node's header is drawn in, see [`aurion_core::Node::color`].
+    pub fn node_color(&self, id: &NodeId) -> Option<[u8; 3]> {
+        self.colors.get(id).copied()
+    }
+
+    pub fn is_bypassed(&self, id: &NodeId) -> bool {
+        self.bypassed.contains(id)
+    }
+
+    /// The node marked as the layer's output, if any.
+    pub fn output_node(&self) -> Option<&NodeId> {
+        self.output_node.as_ref()
+    }
+
+    pub fn context_menu(&self) -> Option<&NodeContextMenu> {
+        self.context_menu.as_ref()
+    }
+
+    /// Opens the context menu for the node at `point`, selecting it unless
+    /// it's already part of the selection. Returns whether there was a node.
+    pub fn open_context_menu(&mut self, point: Point) -> bool {
+        let Some(id) = self.node_at(point) else {
+            return false;
+        };
+        if !self.is_selected(&id) {
+            self.click_node(&id, false);
+        }
+        self.creation_menu = None;
+        self.context_menu = Some(NodeContextMenu::new(id, point));
+        true
+    }
+
+    /// The context menu entry under the pointer, lit while the menu is open.
+    pub fn context_menu_hovered(&self) -> Option<NodeMenuAction> {
+        self.context_menu.as_ref()?.action_at(self.cursor?)
+    }
+
+    pub fn close_context_menu(&mut self) {
+        self.context_menu = None;
+    }
+
+    /// A click while the context menu is open, which closes it. Returns the
+    /// edit the clicked entry asks for; Rename starts editing the name in
+    /// place instead.
+    pub fn context_menu_click(&mut self, point: Point) -> Option<NodeEdit> {
+        let menu = self.context_menu.take()?;
+        let node = menu.node.clone();
+        Some(match menu.action_at(point)? {
+            NodeMenuAction::Rename => {
+                self.begin_rename(&node);
+                return None;
+            }
+            NodeMenuAction::ToggleBypass => NodeEdit::SetBypass(node.clone(), !self.is_bypassed(&node)),
+            NodeMenuAction::DisconnectAll => NodeEdit::DisconnectAll(node),
+            NodeMenuAction::Duplicate => NodeEdit::Duplicate(node),
+            NodeMenuAction::Delete => {
+                let mut ids: Vec<_> = self.selected_nodes.iter().cloned().collect();
+                ids.sort_by_key(|id| id.0);
+                NodeEdit::Delete(ids)
+            }
+            // Choosing it again for the output goes back to the graph's sinks
+            NodeMenuAction::SetAsOutput if self.output_node.as_ref() == Some(&node) => NodeEdit::SetOutput(None),
+            NodeMenuAction::SetAsOutput => NodeEdit::SetOutput(Some(node)),
+        })
+    }
+
+    /// Starts editing the node's name in place, from the name shown.
+    pub fn begin_rename(&mut self, id: &NodeId) {
+        if let Some(title) = self.node_title(id) {
+            self.renaming = Some((id.clone(), title));
+        }
+    }
+
+    pub fn renaming(&self) -> Option<&(NodeId, String)> {
+        self.renaming.as_ref()
+    }
+
+    pub fn cancel_rename(&mut self) {
+        self.renaming = None;
+    }
+
+    /// The selected nodes' areas, in id order.
+    fn selected_rects(&self) -> Vec<(NodeId, Rect)> {
+        let mut rects: Vec<_> = self.selected_nodes.iter()
+            .filter_map(|id| Some((id.clone(), self.node_rect(id)?)))
+            .collect();
+        rects.sort_by_key(|(id, _)| id.0);
+        rects
+    }
+
+    /// Moves nodes to new top-left corners, returning the moves.
+    fn place(&mut self, positions: Vec<(NodeId, Point)>) -> Vec<NodeMove> {
+        let origins: Vec<_> = positions.iter()
+            .filter_map(|(id, _)| Some((id.clone(), self.node_position(id)?)))
+            .collect();
+        for (id, position) in positions {
+            self.move_node(&id, position);
+        }
+        self.moves_from(origins)
+    }
+
+    /// Lines the selected nodes up on the selection's outermost edge, e.g.
+    /// the leftmost left edge. Returns the nodes it moved and from where.
+    pub fn align_selection(&mut self, alignment: Alignment) -> Vec<NodeMove> {
+        let rects = self.selected_rects();
+        if rects.len() < 2 {
+            return Vec::new();
+        }
+        let edges = rects.iter().map(|(_, rect)| match alignment {
+            Alignment::Left => rect.x0,
+            Alignment::Right => rect.x1,
+            Alignment::Top => rect.y0,
+            Alignment::Bottom => rect.y1,
+        });
+        let edge = match alignment {
+            Alignment::Left | Alignment::Top => edges.fold(f64::INFINITY, f64::min),
+            Alignment::Right | Alignment::Bottom => edges.fold(f64::NEG_INFINITY, f64::max),
+        };
+        let positions = rects.into_iter()
+            .map(|(id, rect)| {
+                let position = match alignment {
+                    Alignment::Left => Point::new(edge, rect.y0),
+                    Alignment::Right => Point::new(edge - rect.width(), rect.y0),
+                    Alignment::Top => Point::new(rect.x0, edge),
+                    Alignment::Bottom => Point::new(rect.x0, edge - rect.height()),
+                };
+                (id, position)
+            })
+            .collect();
+        self.place(positions)
+    }
+
+    /// Spaces the selected nodes evenly along an axis, keeping the first
+    /// and last where they are and leaving the same gap between each pair
+    /// of neighbours. Needs three nodes. Returns the nodes it moved and from
+    /// where.
+    pub fn distribute_selection(&mut self, distribution: Distribution) -> Vec<NodeMove> {
+        let mut rects = self.selected_rects();
+        if rects.len() < 3 {
+            return Vec::new();
+        }
+        // Start and length along the axis
+        let span = |rect: &Rect| match distribution {
+            Distribution::Horizontal => (rect.x0, rect.width()),
+            Distribution::Vertical => (rect.y0, rect.height()),
+        };
+        rects.sort_by(|(_, a), (_, b)| span(a).0.total_cmp(&span(b).0));
+        let (start, _) = span(&rects[0].1);
+        let end = rects.iter().map(|(_, rect)| span(rect).0 + span(rect).1).fold(f64::NEG_INFINITY, f64::max);
+        let lengths: f64 = rects.iter().map(|(_, rect)| span(rect).1).sum();
+        let gap = (end - start - lengths) / (rects.len() - 1) as f64;
+
+        let mut next = start;
+        let positions = rects.into_iter()
+            .map(|(id, rect)| {
+                let position = match distribution {
+                    Distribution::Horizontal => Point::new(next, rect.y0),
+                    Distribution::Vertical => Point::new(rect.x0, next),
+                };
+                next += span(&rect).1 + gap;
+                (id, position)
+            })
+            .collect();
+        self.place(positions)
+    }
+
+    /// Where the pointer was last seen on the canvas.
+    pub fn cursor(&self) -> Option<Point> {
+        self.cursor
+    }
+
+    /// The marquee being dragged, if any.
+    pub fn marquee(&self) -> Option<Rect> {
+        match self.drag {
+            Some(PointerDrag::Marquee(start, end)) => Some(Rect::from_points(start, end)),
+            _ => None,
+        }
+    }
+
+    /// Moves every selected node by `delta`.
+    pub fn move_selection(&mut self, delta: Vec2) {
+        for id in &self.selected_nodes {
+            if let Some(position) = self.node_positions.get_mut(id) {
+                *position += delta;
+            }
+        }
+    }
+
+    /// The node's input slots and where they sit, spread down its left edge.
+    pub fn input_slots(&self, id: &NodeId) -> Vec<(InputSlot, Point)> {
+        let Some(rect) = self.node_rect(id) else {
+            return Vec::new();
+        };
+        let names = self.input_ports.get(id).map(Vec::as_slice).unwrap_or_default();
+        let step = rect.height() / (names.len() + 1) as f64;
+        names.iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let slot = InputSlot { node: id.clone(), input: name.to_string() };
+                (slot, Point::new(rect.x0, rect.y0 + step * (i + 1) as f64))
+            })
+            .collect()
+    }
+
+    /// Where wires leave the node, the middle of its right edge.
+    pub fn output_point(&self, id: &NodeId) -> Option<Point> {
+        self.node_rect(id).map(|rect| Point::new(rect.x1, rect.center().y))
+    }
+
+    /// Whether `source`'s output reaches `target`, directly or through other
+    /// nodes.
+    fn feeds(&self, source: &NodeId, target: &NodeId) -> bool {
+        let mut pending = vec![target.clone()];
+        let mut seen = HashSet::new();
+        while let Some(id) = pending.pop() {
+            if id == *source {
+                return true;
+            }
+            if seen.insert(id.clone()) {
+                pending.extend(self.connections.iter().filter(|wire| wire.to_node == id).map(|wire| wire.from_node.clone()));
+            }
+        }
+        false
+    }
+
+    /// Whether a wire from `from`'s output can end in `slot`, and if not,
+    /// why, naming the node types.
+    pub fn slot_compatibility(&self, from: &NodeId, slot: &InputSlot) -> SlotCompatibility {
+        let type_name = |id: &NodeId| self.node_types.get(id).copied().unwrap_or("node");
+        let (source, target) = (type_name(from), type_name(&slot.node));
+        if *from == slot.node {
+            SlotCompatibility::Incompatible(format!("{} can't feed its own '{}' input", source, slot.input))
+        } else if self.feeds(&slot.node, from) {
+            SlotCompatibility::Incompatible(format!(
+                "Cycle detected: {} already feeds {}, so it can't take '{}'",
+                target, source, slot.input,
+            ))
+        } else {
+            SlotCompatibility::Compatible
+        }
+    }
+
+    /// The slot nearest `point` within [`SNAP_RADIUS`] that `accept` takes.
+    fn nearest_slot(&self, point: Point, accept: impl Fn(&InputSlot) -> bool) -> Option<(InputSlot, Point)> {
+        self.drawing_order()
+            .iter()
+            .flat_map(|id| self.input_slots(id))
+            .filter(|(slot, position)| position.distance(point) <= SNAP_RADIUS && accept(slot))
+            .min_by(|(_, a), (_, b)| a.distance(point).total_cmp(&b.distance(point)))
+    }
+
+    /// The slot under `point`, compatible or not.
+    pub fn slot_at(&self, point: Point) -> Option<InputSlot> {
+        self.nearest_slot(point, |_| true).map(|(slot, _)| slot)
+    }
+
+    /// The slot a wire from `from` dropped at `point` snaps to: the nearest
+    /// compatible one within [`SNAP_RADIUS`].
+    pub fn snap_target(&self, from: &NodeId, point: Point) -> Option<(InputSlot, Point)> {
+        self.nearest_slot(point, |slot| self.slot_compatibility(from, slot) == SlotCompatibility::Compatible)
+    }
+
+    /// The node the dragged wire starts from.
+    pub fn dragging_connection(&self) -> Option<&NodeId> {
+        self.dragging_connection.as_ref()
+    }
+
+    /// Moves the end of the dragged wire to the pointer.
+    pub fn drag_wire(&mut self, point: Point) {
+        if self.dragging_connection.is_some() {
+            self.wire_end = Some(point);
+        }
+    }
+
+    /// Where the dragged wire ends: the slot it snaps to, or the pointer.
+    pub fn wire_endpoint(&self) -> Option<Point> {
+        let point = self.wire_end?;
+        let from = self.dragging_connection.as_ref()?;
+        Some(self.snap_target(from, point).map_or(point, |(_, slot)| slot))
+    }
+
+    /// Why the slot under the dragged wire would refuse it, and where that
+    /// slot sits, for a tooltip beside it. `None` while the wire snaps to a
+    /// compatible slot.
+    pub fn wire_tooltip(&self) -> Option<(String, Point)> {
+        let point = self.wire_end?;
+        let from = self.dragging_connection.as_ref()?;
+        if self.snap_target(from, point).is_some() {
+            return None;
+        }
+        let (slot, position) = self.nearest_slot(point, |_| true)?;
+        match self.slot_compatibility(from, &slot) {
+            SlotCompatibility::Incompatible(reason) => Some((reason, position)),
+            SlotCompatibility::Compatible => None,
+        }
+    }
+
+    /// Where the wire ends on `connection`'s target.
+    fn wire_target(&self, connection: &NodeConnection) -> Option<Point> {
+        self.input_slots(&connection.to_node)
+            .into_iter()
+            .find(|(slot, _)| slot.input == connection.to_input)
+            .map(|(_, position)| position)
+            .or_else(|| self.node_rect(&connection.to_node).map(|rect| Point::new(rect.x0, rect.center().y)))
+    }
+
+    /// Maps the canvas to the screen. `scroll` is the canvas point shown at
+    /// the top-left corner.
+    pub fn view_transform(&self) -> Affine {
+        Affine::scale(self.zoom) * Affine::translate(-self.scroll)
+    }
+
+    pub fn screen_to_canvas(&self, point: Point) -> Point {
+        self.view_transform().inverse() * point
+    }
+
+    /// The part of the canvas a `viewport`-sized view shows.
+    pub fn visible_rect(&self, viewport: Size) -> Rect {
+        Rect::from_origin_size(self.scroll.to_point(), viewport / self.zoom)
+    }
+
+    /// The area every node covers, or `None` without nodes.
+    pub fn world_bounds(&self) -> Option<Rect> {
+        self.drawing_order().iter().filter_map(|id| self.node_rect(id)).reduce(|a, b| a.union(b))
+    }
+
+    /// Scrolls so the canvas `point` is in the middle of a `viewport`-sized
+    /// view.
+    pub fn center_on(&mut self, viewport: Size, point: Point) {
+        self.scroll = point.to_vec2() - (viewport / self.zoom).to_vec2() / 2.0;
+    }
+
+    /// Zooms and scrolls a `viewport`-sized view to frame every node,
+    /// [`FIT_PADDING`] in from its edges.
+    pub fn fit_all(&mut self, viewport: Size) {
+        let Some(bounds) = self.world_bounds() else {
+            return;
+        };
+        let width = (viewport.width - 2.0 * FIT_PADDING).max(1.0);
+        let height = (viewport.height - 2.0 * FIT_PADDING).max(1.0);
+        self.zoom = (width / bounds.width()).min(height / bounds.height()).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.center_on(viewport, bounds.center());
+    }
+
+    /// Where the minimap sits on the screen, in the view's bottom-right
+    /// corner.
+    pub fn minimap_rect(&self, viewport: Size) -> Rect {
+        let origin = Point::new(
+            viewport.width - MINIMAP_MARGIN - MINIMAP_SIZE.width,
+            viewport.height - MINIMAP_MARGIN - MINIMAP_SIZE.height,
+        );
+        Rect::from_origin_size(origin, MINIMAP_SIZE)
+    }
+
+    /// The minimap's fit all button, in its top-right corner.
+    pub fn fit_button_rect(&self, viewport: Size) -> Rect {
+        let minimap = self.minimap_rect(viewport);
+        Rect::new(minimap.x1 - FIT_BUTTON_SIZE - 4.0, minimap.y0 + 4.0, minimap.x1 - 4.0, minimap.y0 + 4.0 + FIT_BUTTON_SIZE)
+    }
+
+    /// Maps the canvas into the minimap, scaled to show the nodes and the
+    /// visible area, centered.
+    pub fn minimap_transform(&self, viewport: Size) -> Affine {
+        let visible = self.visible_rect(viewport);
+        let world = self.world_bounds().map_or(visible, |bounds| bounds.union(visible));
+        let inner = self.minimap_rect(viewport).inset(-MINIMAP_MARGIN);
+        let scale = (inner.width() / world.width()).min(inner.height() / world.height());
+        Affine::translate(inner.center().to_vec2()) * Affine::scale(scale) * Affine::translate(-world.center().to_vec2())
+    }
+
+    /// The visible area's outline in the minimap.
+    pub fn minimap_viewport(&self, viewport: Size) -> Rect {
+        self.minimap_transform(viewport).transform_rect_bbox(self.visible_rect(viewport))
+    }
+
+    /// A press at the screen `point`. In the minimap it centers the view on
+    /// the point and starts dragging it, and on the fit all button frames
+    /// every node. Returns whether the minimap took the press.
+    pub fn minimap_press(&mut self, viewport: Size, point: Point) -> bool {
+        if !self.minimap_rect(viewport).contains(point) {
+            return false;
+        }
+        if self.fit_button_rect(viewport).contains(point) {
+            self.fit_all(viewport);
+            return true;
+        }
+        let transform = self.minimap_transform(viewport);
+        self.center_on(viewport, transform.inverse() * point);
+        self.drag = Some(PointerDrag::Minimap(transform));
+        true
+    }
+
+    /// Pans the view with a drag started in the minimap, to the screen
+    /// `point`.
+    pub fn minimap_drag(&mut self, viewport: Size, point: Point) {
+        if let Some(PointerDrag::Minimap(transform)) = self.drag {
+            self.center_on(viewport, transform.inverse() * point);
+        }
+    }
+
+    pub fn scroll(&self) -> Vec2 {
+        self.scroll
+    }
+
+    pub fn zoom(&self) -> f64 {
+        self.zoom
+    }
+
+    /// Moves the nodes `layout` has positions for there, e.g. after a move
+    /// is undone.
+    pub fn apply_positions(&mut self, layout: &EditorLayout) {
+        for (id, [x, y]) in &layout.positions {
+            self.move_node(id, Point::new(*x, *y));
+        }
+    }
+
+    pub fn set_view(&mut self, scroll: Vec2, zoom: f64) {
+        self.scroll = scroll;
+        self.zoom = zoom;
+    }
+
+    pub fn is_collapsed(&self, id: &NodeId) -> bool {
+        self.collapsed.contains(id)
+    }
+
+    /// Whether previews are shown on nodes, unless turned off per node.
+    pub fn show_previews(&self) -> bool {
+        self.show_previews
+    }
+
+    pub fn set_show_previews(&mut self, show: bool) {
+        self.show_previews = show;
+    }
+
+    /// Whether the node shows its preview: previews are on, the node isn't
+    /// collapsed and its own preview isn't turned off.
+    pub fn preview_shown(&self, id: &NodeId) -> bool {
+        self.show_previews && !self.is_collapsed(id) && !self.hidden_previews.contains(id)
+    }
+
+    /// Turns the node's own preview off or back on.
+    pub fn toggle_preview(&mut self, id: &NodeId) {
+        if !self.hidden_previews.remove(id) {
+            self.hidden_previews.insert(id.clone());
+        }
+    }
+
+    pub fn set_collapsed(&mut self, id: &NodeId, collapsed: bool) {
+        if collapsed {
+            self.collapsed.insert(id.clone());
+        } else {
+            self.collapsed.remove(id);
+        }
+    }
+
+    pub fn creation_menu(&self) -> Option<&NodeCreationMenu> {
+        self.creation_menu.as_ref()
+    }
+
+    pub fn creation_menu_mut(&mut self) -> Option<&mut NodeCreationMenu> {
+        self.creation_menu.as_mut()
+    }
+
+    pub fn connections(&self) -> &[NodeConnection] {
+        &self.connections
+    }
+
+    /// The error toast, unless it has expired.
+    pub fn toast(&self) -> Option<&Toast> {
+        self.toast.as_ref().filter(|toast| toast.expires > Instant::now())
+    }
+
+    /// The wire the graph last rejected, while it flashes.
+    pub fn flashing_connection(&self) -> Option<&NodeConnection> {
+        self.flash.as_ref().filter(|(_, until)| *until > Instant::now()).map(|(connection, _)| connection)
+    }
+
+    /// Replaces the wires with the connections in `graph`, forgets nodes no
+    /// longer in it and places new ones with [`auto_layout`].
+    pub fn rebuild_connections(&mut self, graph: &NodeGraph) {
+        let ids = graph.get_node_ids();
+        self.connections.clear();
+        self.node_types.clear();
+        self.input_ports.clear();
+        self.labels.clear();
+        self.colors.clear();
+        self.bypassed.clear();
+        self.output_node = graph.designated_output().cloned();
+        for id in &ids {
+            let Some(node) = graph.get_node(id) else {
+                continue;
+            };
+            self.node_types.insert(id.clone(), node.read().data().type_name());
+            let mut ports: Vec<_> = node.read().data().input_ports().into_iter().map(|port| port.name).collect();
+            // Nodes declaring no ports at all predate them; most read one input
+            if ports.is_empty() && node.read().data().output_ports().is_empty() {
+                ports.push("input".to_string());
+            }
+            self.input_ports.insert(id.clone(), ports);
+            if let Some(label) = node.read().label() {
+                self.labels.insert(id.clone(), label.to_string());
+            }
+            if let Some(color) = node.read().color() {
+                self.colors.insert(id.clone(), color);
+            }
+            if node.read().is_bypassed() {
+                self.bypassed.insert(id.clone());
+            }
+            let node = node.read();
+            for (input, source) in node.inputs() {
+                self.connections.push(NodeConnection {
+                    from_node: source.clone(),
+                    from_output: node.source_output(input).map(str::to_string),
+                    to_node: id.clone(),
+                    to_input: input.clone(),
+                });
+            }
+        }
+        self.node_positions.retain(|id, _| ids.contains(id));
+        self.collapsed.retain(|id| ids.contains(id));
+        self.hidden_previews.retain(|id| ids.contains(id));
+        self.selected_nodes.retain(|id| ids.contains(id));
+        if self.context_menu.as_ref().map_or(false, |menu| !ids.contains(&menu.node)) {
+            self.context_menu = None;
+        }
+        if self.renaming.as_ref().map_or(false, |(id, _)| !ids.contains(id)) {
+            self.renaming = None;
+        }
+        auto_layout(graph, &mut self.node_positions);
+    }
+
+    fn show_error(&mut self, message: String) {
+        self.toast = Some(Toast::new(message));
+    }
+}
+
+/// A node preview ready to paint.
+enum PaintedPreview {
+    Image(Image),
+    Text(String),
 }
 
 pub struct NodeEditor {
     state: NodeEditorState,
     document: Arc<RwLock<Document>>,
+    /// The layer whose graph is edited.
+    active_layer: Option<LayerId>,
+    palette: CommandPalette,
+    // The view's size at the last layout
+    viewport: Size,
+    theme: Theme,
+    // The previews shown, each with the node hash it's for
+    previews: HashMap<NodeId, (u64, PaintedPreview)>,
+    // Document events not yet applied, see `NodeEditor::sync`
+    events: Arc<Mutex<Vec<DocumentEvent>>>,
+    listener: ListenerId,
+}
+
+impl NodeEditor {
+    pub fn new(document: Arc<RwLock<Document>>) -> Self {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let listener = document.write().subscribe(move |event| match event {
+            DocumentEvent::GraphChanged(_) | DocumentEvent::UiLayoutChanged(_) => sink.lock().push(event.clone()),
+            _ => {}
+        });
+        Self {
+            state: NodeEditorState::new(),
+            document,
+            active_layer: None,
+            palette: CommandPalette::new(),
+            viewport: Size::new(800.0, 600.0),
+            theme: Theme::default(),
+            previews: HashMap::new(),
+            events,
+            listener,
+        }
+    }
+
+    pub fn state(&self) -> &NodeEditorState {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut NodeEditorState {
+        &mut self.state
+    }
+
+    /// Paints with `theme` from the next layout on.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// Edits `layer`'s graph with its saved layout, after saving the layout
+    /// of the layer edited so far.
+    pub fn set_active_layer(&mut self, layer: Option<LayerId>) {
+        if let Err(e) = self.store_layout() {
+            tracing::warn!("Couldn't keep the node editor layout: {}", e);
+        }
+        self.active_layer = layer;
+        let document = self.document.read();
+        let graph_layer = self.active_layer.as_ref().and_then(|layer| document.get_layer(layer));
+        let layout = self.active_layer.as_ref()
+            .and_then(|layer| document.ui_layout(layer))
+            .map(EditorLayout::from_value)
+            .unwrap_or_default();
+        let state = match graph_layer {
+            Some(graph_layer) => NodeEditorState::from_layout(&layout, &graph_layer.read().node_graph()),
+            None => NodeEditorState::from_layout(&layout, &NodeGraph::new()),
+        };
+        drop(document);
+        let show_previews = self.state.show_previews();
+        self.state = state;
+        self.state.set_show_previews(show_previews);
+        self.previews.clear();
+    }
+
+    /// Shows or hides previews on every node. While shown the document keeps
+    /// the output of each node its renders evaluate, downsampled, so the
+    /// previews never cost an evaluation of their own.
+    pub fn set_show_previews(&mut self, show: bool) {
+        self.state.set_show_previews(show);
+        self.document.write().set_previews_enabled(show);
+        if !show {
+            self.previews.clear();
+        }
+    }
+
+    /// Takes in the previews renders made since the last call. A node's old
+    /// preview stays until a render evaluates its new output. Returns
+    /// whether any changed.
+    fn refresh_previews(&mut self) -> bool {
+        let Some(layer) = self.active_layer.clone().filter(|_| self.state.show_previews()) else {
+            return false;
+        };
+        let mut changed = false;
+        let state = &self.state;
+        self.previews.retain(|id, _| state.preview_shown(id));
+        let document = self.document.read();
+        for id in self.state.drawing_order() {
+            if !self.state.preview_shown(&id) {
+                continue;
+            }
+            let Some(preview) = document.node_preview(&layer, &id) else {
+                continue;
+            };
+            if self.previews.get(&id).map_or(false, |(hash, _)| *hash == preview.hash) {
+                continue;
+            }
+            let painted = match preview.content {
+                PreviewContent::Image(image) => {
+                    let image = image.to_rgba8();
+                    let (width, height) = image.dimensions();
+                    PaintedPreview::Image(Image::new(Blob::new(Arc::new(image.into_raw())), Format::Rgba8, width, height))
+                }
+                PreviewContent::Text(text) => PaintedPreview::Text(text),
+            };
+            self.previews.insert(id, (preview.hash, painted));
+            changed = true;
+        }
+        changed
+    }
+
+    /// The node hash the shown preview of `id` was made for.
+    pub fn preview_hash(&self, id: &NodeId) -> Option<u64> {
+        self.previews.get(id).map(|(hash, _)| *hash)
+    }
+
+    /// The shown preview of a node whose output isn't an image.
+    pub fn preview_summary(&self, id: &NodeId) -> Option<&str> {
+        match self.previews.get(id) {
+            Some((_, PaintedPreview::Text(text))) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Shows `node` of `layer`'s graph: edits that layer, selects the node
+    /// alone and scrolls it to the middle of the view.
+    pub fn reveal(&mut self, layer: &LayerId, node: &NodeId) {
+        if self.active_layer.as_ref() != Some(layer) {
+            self.set_active_layer(Some(layer.clone()));
+        }
+        let Some(rect) = self.state.node_rect(node) else {
+            return;
+        };
+        self.state.click_node(node, false);
+        self.state.center_on(self.viewport, rect.center());
+    }
+
+    /// Saves the active layer's layout in the document, e.g. before the
+    /// document is saved.
+    pub fn store_layout(&self) -> Result<(), DocumentError> {
+        match &self.active_layer {
+            Some(layer) => self.document.write().set_ui_layout(layer, self.state.to_layout().to_value()),
+            None => Ok(()),
+        }
+    }
+
+    /// Rebuilds the wires from the active layer's graph, e.g. after loading
+    /// the document or undoing an edit.
+    pub fn reload(&mut self) {
+        let layer = self.active_layer.as_ref().and_then(|layer| self.document.read().get_layer(layer));
+        match layer {
+            Some(layer) => self.state.rebuild_connections(&layer.read().node_graph()),
+            None => self.state.rebuild_connections(&NodeGraph::new()),
+        }
+    }
+
+    /// Applies the document changes since the last call to the active
+    /// layer: graph edits, including undone and redone ones, rebuild the
+    /// state and layout changes move the nodes. Previews renders made are
+    /// picked up too. Returns whether the editor needs repainting.
+    pub fn sync(&mut self) -> bool {
+        let events = std::mem::take(&mut *self.events.lock());
+        let Some(active) = self.active_layer.clone() else {
+            return false;
+        };
+        let graph_changed = events.iter().any(|event| matches!(event, DocumentEvent::GraphChanged(layer) if *layer == active));
+        let layout_changed = events.iter().any(|event| matches!(event, DocumentEvent::UiLayoutChanged(layer) if *layer == active));
+        if graph_changed {
+            self.reload();
+        }
+        if layout_changed {
+            let layout = self.document.read().ui_layout(&active).map(EditorLayout::from_value);
+            if let Some(layout) = layout {
+                self.state.apply_positions(&layout);
+            }
+        }
+        let previews_changed = self.refresh_previews();
+        graph_changed || layout_changed || previews_changed
+    }
+
+    /// Undoes the document's last edit, whether made here or elsewhere.
+    pub fn undo(&mut self) -> Result<(), DocumentError> {
+        if !self.document.read().can_undo() {
+            return Ok(());
+        }
+        let result = self.document.write().undo();
+        if let Err(e) = &result {
+            self.state.show_error(format!("Couldn't undo: {}", e));
+        }
+        self.sync();
+        result
+    }
+
+    pub fn redo(&mut self) -> Result<(), DocumentError> {
+        if !self.document.read().can_redo() {
+            return Ok(());
+        }
+        let result = self.document.write().redo();
+        if let Err(e) = &result {
+            self.state.show_error(format!("Couldn't redo: {}", e));
+        }
+        self.sync();
+        result
+    }
+
+    /// Handles the editor's shortcuts: Ctrl+Z to undo, Ctrl+Shift+Z or
+    /// Ctrl+Y to redo, Ctrl+P for the palette, F to fit all nodes in view
+    /// and Delete or Backspace to delete the selection. While the palette is open it takes every key,
+    /// as does a node being renamed: Enter names it and Escape cancels.
+    /// Returns whether `key` was handled.
+    pub fn handle_key(&mut self, key: &Key, modifiers: ModifiersState) -> bool {
+        if let Some((_, name)) = self.state.renaming.as_mut() {
+            match key {
+                Key::Named(NamedKey::Enter) => {
+                    // Failures are shown as a toast
+                    let _ = self.commit_rename();
+                }
+                Key::Named(NamedKey::Escape) => self.state.cancel_rename(),
+                Key::Named(NamedKey::Backspace) => {
+                    name.pop();
+                }
+                Key::Named(NamedKey::Space) => name.push(' '),
+                Key::Character(text) => name.push_str(text),
+                _ => {}
+            }
+            return true;
+        }
+        if self.state.context_menu.is_some() && *key == Key::Named(NamedKey::Escape) {
+            self.state.close_context_menu();
+            return true;
+        }
+        if self.palette.is_open() {
+            if let Some(action) = self.palette.handle_key(key) {
+                // Failures are shown as a toast
+                let _ = self.run_action(action);
+            }
+            return true;
+        }
+        let result = match key {
+            Key::Character(c) if modifiers.control_key() => match c.to_lowercase().as_str() {
+                "z" if modifiers.shift_key() => self.redo(),
+                "z" => self.undo(),
+                "y" => self.redo(),
+                "p" => {
+                    self.open_palette();
+                    Ok(())
+                }
+                _ => return false,
+            },
+            Key::Character(c) if c.eq_ignore_ascii_case("f") => {
+                self.state.fit_all(self.viewport);
+                Ok(())
+            }
+            Key::Named(NamedKey::Delete | NamedKey::Backspace) => self.delete_selected(),
+            _ => return false,
+        };
+        if let Err(e) = result {
+            tracing::warn!("Node editor shortcut failed: {}", e);
+        }
+        true
+    }
+
+    fn require_layer(&self) -> Result<LayerId, DocumentError> {
+        self.active_layer.clone()
+            .ok_or_else(|| DocumentError::InvalidOperation("No layer to edit the graph of".to_string()))
+    }
+
+    pub fn palette(&self) -> &CommandPalette {
+        &self.palette
+    }
+
+    /// Opens the quick-action palette over the global registry's node types.
+    pub fn open_palette(&mut self) {
+        register_document_nodes();
+        self.palette.open(menu_entries(&NODE_REGISTRY.read()));
+    }
+
+    /// Runs an action chosen in the palette. Failures are also shown as a
+    /// toast.
+    pub fn run_action(&mut self, action: PaletteAction) -> Result<(), DocumentError> {
+        let result = match action {
+            PaletteAction::AddNode(entry) => self.create_node_at(entry.type_name, self.state.cursor).map(|_| ()),
+            PaletteAction::AddLayer => {
+                self.document.write().add_layer();
+                Ok(())
+            }
+            PaletteAction::Flatten => self.document.write().flatten().map(|_| ()),
+            PaletteAction::Export => {
+                let document = self.document.read();
+                let path = document.path().map(|path| path.with_extension("png"));
+                match path {
+                    Some(path) => document.export(path, ExportOptions::default()),
+                    None => Err(DocumentError::InvalidOperation("Save the document before exporting".to_string())),
+                }
+            }
+        };
+        if let Err(e) = &result {
+            self.state.show_error(format!("{} failed: {}", action.label(), e));
+        }
+        result
+    }
+
+    /// Opens the node creation menu at `position`, on right-click.
+    pub fn open_creation_menu(&mut self, position: Point) {
+        self.state.creation_menu = Some(NodeCreationMenu::open(position));
+    }
+
+    pub fn close_creation_menu(&mut self) {
+        self.state.creation_menu = None;
+    }
+
+    /// Adds a `type_name` node with default parameters to the active layer's
+    /// graph, undoably, where the creation menu was opened or else
+    /// automatically, and selects it. Closes the menu.
+    pub fn create_node(&mut self, type_name: &str) -> Result<NodeId, DocumentError> {
+        let position = self.state.creation_menu.take().map(|menu| menu.position);
+        self.create_node_at(type_name, position)
+    }
+
+    /// Adds a `type_name` node like [`NodeEditor::create_node`], at
+    /// `position` if given.
+    pub fn create_node_at(&mut self, type_name: &str, position: Option<Point>) -> Result<NodeId, DocumentError> {
+        let layer = self.require_layer()?;
+        let command = AddNodeCommand::new(layer, type_name, json!({}));
+        let id = command.node_id().clone();
+        self.document.write().execute_command(Box::new(command))?;
+
+        if let Some(position) = position {
+            self.state.node_positions.insert(id.clone(), position);
+        }
+        self.sync();
+        self.state.click_node(&id, false);
+        Ok(id)
+    }
+
+    /// Removes the selected nodes and their connections, as one undo step.
+    pub fn delete_selected(&mut self) -> Result<(), DocumentError> {
+        if self.state.selected_nodes.is_empty() {
+            return Ok(());
+        }
+        let layer = self.require_layer()?;
+        let mut ids: Vec<_> = self.state.selected_nodes.iter().cloned().collect();
+        ids.sort_by_key(|id| id.0);
+        let result = self.document.write().execute_command(Box::new(RemoveNodesCommand::new(layer, ids)));
+        if let Err(e) = &result {
+            self.state.show_error(format!("Couldn't delete: {}", e));
+        }
+        self.sync();
+        result
+    }
+
+    /// A right click: the context menu on a node, the creation menu
+    /// elsewhere.
+    pub fn right_click(&mut self, point: Point) {
+        if !self.state.open_context_menu(point) {
+            self.open_creation_menu(point);
+        }
+    }
+
+    /// A click while the node context menu is open, running the entry
+    /// clicked.
+    pub fn context_menu_click(&mut self, point: Point) -> Result<(), DocumentError> {
+        match self.state.context_menu_click(point) {
+            Some(edit) => self.apply_edit(edit),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs a context menu edit as an undoable command. A duplicate is
+    /// placed beside its original and selected.
+    pub fn apply_edit(&mut self, edit: NodeEdit) -> Result<(), DocumentError> {
+        let layer = self.require_layer()?;
+        let mut duplicate = None;
+        let command: Box<dyn Command> = match edit {
+            NodeEdit::SetBypass(id, bypassed) => Box::new(SetNodeBypassCommand::new(layer, id, bypassed)),
+            NodeEdit::SetOutput(id) => Box::new(SetOutputNodeCommand::new(layer, id)),
+            NodeEdit::DisconnectAll(id) => Box::new(DisconnectAllCommand::new(layer, id)),
+            NodeEdit::Duplicate(id) => {
+                let command = DuplicateNodeCommand::new(layer, id.clone());
+                duplicate = Some((command.node_id().clone(), id));
+                Box::new(command)
+            }
+            NodeEdit::Delete(ids) => Box::new(RemoveNodesCommand::new(layer, ids)),
+        };
+        let result = self.document.write().execute_command(command);
+        if let Err(e) = &result {
+            self.state.show_error(format!("Couldn't edit the node: {}", e));
+        }
+        let duplicate = duplicate.filter(|_| result.is_ok());
+        if let Some((copy, original)) = &duplicate {
+            if let Some(position) = self.state.node_position(original) {
+                self.state.node_positions.insert(copy.clone(), position + DUPLICATE_OFFSET);
+            }
+        }
+        self.sync();
+        if let Some((copy, _)) = &duplicate {
+            self.state.click_node(copy, false);
+        }
+        result
+    }
+
+    /// Names the node being renamed as typed. An empty name goes back to
+    /// showing the type.
+    pub fn commit_rename(&mut self) -> Result<(), DocumentError> {
+        let Some((id, name)) = self.state.renaming.take() else {
+            return Ok(());
+        };
+        let label = Some(name.trim().to_string()).filter(|name| !name.is_empty());
+        if label.as_deref() == self.state.node_label(&id) {
+            return Ok(());
+        }
+        let layer = self.require_layer()?;
+        let result = self.document.write().execute_command(Box::new(SetNodeLabelCommand::new(layer, id, label)));
+        if let Err(e) = &result {
+            self.state.show_error(format!("Couldn't rename: {}", e));
+        }
+        self.sync();
+        result
+    }
+
+    /// Ends a pointer drag. Nodes it moved are moved undoably, as one step
+    /// for the whole drag.
+    pub fn pointer_up(&mut self) -> Result<(), DocumentError> {
+        let moves = self.state.pointer_up();
+        self.commit_moves(moves)
+    }
+
+    /// Lines up the selected nodes, as one undo step.
+    pub fn align(&mut self, alignment: Alignment) -> Result<(), DocumentError> {
+        let moves = self.state.align_selection(alignment);
+        self.commit_moves(moves)
+    }
+
+    /// Spaces out the selected nodes evenly, as one undo step.
+    pub fn distribute(&mut self, distribution: Distribution) -> Result<(), DocumentError> {
+        let moves = self.state.distribute_selection(distribution);
+        self.commit_moves(moves)
+    }
+
+    /// Records nodes the state already moved as a single undoable step.
+    fn commit_moves(&mut self, moves: Vec<NodeMove>) -> Result<(), DocumentError> {
+        if moves.is_empty() {
+            return Ok(());
+        }
+        let layer = self.require_layer()?;
+        // The stored layout has to match what's on screen for undo to
+        // restore it
+        self.store_layout()?;
+        self.document.write().execute_command(Box::new(MoveNodesCommand::new(layer, moves)))?;
+        self.sync();
+        Ok(())
+    }
+
+    /// Starts dragging a wire from `from`'s output.
+    pub fn begin_connection(&mut self, from: NodeId) {
+        self.state.wire_end = self.state.output_point(&from);
+        self.state.dragging_connection = Some(from);
+    }
+
+    pub fn cancel_connection(&mut self) {
+        self.state.dragging_connection = None;
+        self.state.wire_end = None;
+    }
+
+    /// Drops the dragged wire at `point`, on the compatible slot it snaps
+    /// to or else the slot under it, which refuses it if incompatible.
+    /// Dropped anywhere else, the wire is discarded.
+    pub fn drop_connection(&mut self, point: Point) -> Result<(), DocumentError> {
+        let Some(from) = self.state.dragging_connection.clone() else {
+            return Ok(());
+        };
+        let slot = self.state.snap_target(&from, point)
+            .map(|(slot, _)| slot)
+            .or_else(|| self.state.slot_at(point));
+        match slot {
+            Some(slot) => self.finish_connection(slot.node, &slot.input),
+            None => {
+                self.cancel_connection();
+                Ok(())
+            }
+        }
+    }
+
+    /// Drops the dragged wire on `to`'s `input`, connecting the graph
+    /// undoably. If the slot is incompatible or the graph rejects the wire,
+    /// the reason is shown as a toast and the wire flashes red instead of
+    /// being added.
+    pub fn finish_connection(&mut self, to: NodeId, input: &str) -> Result<(), DocumentError> {
+        let Some(from) = self.state.dragging_connection.take() else {
+            return Ok(());
+        };
+        self.state.wire_end = None;
+        let slot = InputSlot { node: to.clone(), input: input.to_string() };
+        let result = match self.state.slot_compatibility(&from, &slot) {
+            SlotCompatibility::Compatible => {
+                let layer = self.require_layer()?;
+                let command = ConnectCommand::new(layer, from.clone(), to.clone(), input);
+                self.document.write().execute_command(Box::new(command))
+            }
+            SlotCompatibility::Incompatible(reason) => Err(DocumentError::InvalidOperation(reason)),
+        };
+        if let Err(e) = &result {
+            self.state.show_error(format!("Couldn't connect: {}", e));
+            let wire = NodeConnection {
+                from_node: from,
+                from_output: None,
+                to_node: to,
+                to_input: input.to_string(),
+            };
+            self.state.flash = Some((wire, Instant::now() + FLASH_DURATION));
+        }
+        self.sync();
+        result
+    }
+
+    /// Removes `connection` from the graph, undoably.
+    pub fn delete_connection(&mut self, connection: &NodeConnection) -> Result<(), DocumentError> {
+        let layer = self.require_layer()?;
+        let command = DisconnectCommand::new(
+            layer,
+            connection.from_node.clone(),
+            connection.to_node.clone(),
+            connection.to_input.clone(),
+        );
+        let result = self.document.write().execute_command(Box::new(command));
+        if let Err(e) = &result {
+            self.state.show_error(format!("Couldn't disconnect: {}", e));
+        }
+        self.sync();
+        result
+    }
+}
+
+impl Drop for NodeEditor {
+    fn drop(&mut self) {
+        self.document.write().unsubscribe(self.listener);
+    }
 }
 
 impl Widget for NodeEditor {
@@ -46,25 +1530,760 @@ impl Widget for NodeEditor {
     }
 
     fn event(&mut self, _ctx: &mut WidgetCtx, _event: &Self::Event) -> bool {
-        false
+        self.sync()
     }
 
     fn layout(&mut self, ctx: &mut WidgetCtx) {
+        self.sync();
         let size = ctx.window_size();
+        self.viewport = size;
         let mut scene = Scene::new();
         let mut builder = SceneBuilder::for_scene(&mut scene);
+        let theme = &self.theme;
 
         // Draw node editor background
         builder.fill(
             Fill::NonZero,
             Affine::IDENTITY,
-            Color::rgb8(30, 33, 40),
+            theme.window_background.color(),
             None,
             &Rect::new(0.0, 0.0, size.width, size.height),
         );
 
-        // TODO: Draw nodes and connections
+        let view = self.state.view_transform();
+
+        // Draw the grid nodes snap to
+        if self.state.snap_to_grid() {
+            let visible = self.state.visible_rect(size);
+            let grid = self.state.grid_size();
+            let color = theme.grid.color();
+            let mut x = (visible.x0 / grid).floor() * grid;
+            while x <= visible.x1 {
+                builder.stroke(&peniko::Stroke::new(1.0), view, color, None, &Line::new((x, visible.y0), (x, visible.y1)));
+                x += grid;
+            }
+            let mut y = (visible.y0 / grid).floor() * grid;
+            while y <= visible.y1 {
+                builder.stroke(&peniko::Stroke::new(1.0), view, color, None, &Line::new((visible.x0, y), (visible.x1, y)));
+                y += grid;
+            }
+        }
+
+        // Draw connections, the rejected one in red, and the dragged wire
+        let wires = self.state.connections.iter().map(|wire| (wire, theme.wire.color()));
+        let flash = self.state.flashing_connection().map(|wire| (wire, theme.wire_rejected.color()));
+        for (wire, color) in wires.chain(flash) {
+            let from = self.state.output_point(&wire.from_node);
+            let to = self.state.wire_target(wire);
+            if let (Some(from), Some(to)) = (from, to) {
+                builder.stroke(&peniko::Stroke::new(2.0), view, color, None, &Line::new(from, to));
+            }
+        }
+        let dragged = self.state.dragging_connection().and_then(|from| self.state.output_point(from));
+        if let (Some(from), Some(to)) = (dragged, self.state.wire_endpoint()) {
+            builder.stroke(&peniko::Stroke::new(2.0), view, theme.wire_dragging.color(), None, &Line::new(from, to));
+        }
+
+        // Draw nodes titled in their headers, selected ones outlined,
+        // bypassed ones dimmed and the layer output badged, then the marquee
+        for id in self.state.drawing_order() {
+            let Some(rect) = self.state.node_rect(&id) else {
+                continue;
+            };
+            let rounded = rect.to_rounded_rect(6.0);
+            let body = if self.state.is_bypassed(&id) { theme.node_body_bypassed } else { theme.node_body };
+            builder.fill(Fill::NonZero, view, body.color(), None, &rounded);
+            let header = Rect::new(rect.x0, rect.y0, rect.x1, rect.y0 + COLLAPSED_HEIGHT);
+            if !self.state.is_bypassed(&id) {
+                let color = match self.state.node_color(&id) {
+                    Some([r, g, b]) => peniko::Color::rgb8(r, g, b),
+                    None => theme.node_header.color(),
+                };
+                builder.fill(Fill::NonZero, view, color, None, &header.to_rounded_rect((6.0, 6.0, 0.0, 0.0)));
+            }
+            if self.state.renaming().map_or(true, |(renamed, _)| *renamed != id) {
+                if let Some(title) = self.state.node_title(&id) {
+                    let color = if self.state.is_bypassed(&id) { theme.text_muted } else { theme.text };
+                    draw_label(&mut builder, view, &title, color.color(), Point::new(rect.x0 + 8.0, header.center().y));
+                }
+            }
+            if self.state.output_node() == Some(&id) {
+                let badge = Circle::new((rect.x1 - 10.0, rect.y0 + 10.0), 5.0);
+                builder.fill(Fill::NonZero, view, theme.output_badge.color(), None, &badge);
+            }
+            if let Some(area) = self.state.preview_rect(&id) {
+                builder.fill(Fill::NonZero, view, theme.field_background.color(), None, &area);
+                match self.previews.get(&id) {
+                    Some((_, PaintedPreview::Image(image))) => {
+                        let offset = Vec2::new(area.width() - image.width as f64, area.height() - image.height as f64) / 2.0;
+                        builder.draw_image(image, view * Affine::translate(area.origin().to_vec2() + offset));
+                    }
+                    Some((_, PaintedPreview::Text(text))) => {
+                        draw_label(&mut builder, view, text, theme.text_muted.color(), Point::new(area.x0 + 4.0, area.center().y));
+                    }
+                    None => {}
+                }
+            }
+            if let Some((_, name)) = self.state.renaming().filter(|(renamed, _)| *renamed == id) {
+                let field = Rect::new(rect.x0 + 4.0, rect.y0 + 4.0, rect.x1 - 20.0, rect.y0 + 20.0);
+                builder.fill(Fill::NonZero, view, theme.field_background.color(), None, &field);
+                builder.stroke(&peniko::Stroke::new(1.0), view, theme.selection.color(), None, &field);
+                draw_label(&mut builder, view, name, theme.text.color(), Point::new(field.x0 + 4.0, field.center().y));
+                let caret = (field.x0 + 4.0 + CHAR_WIDTH * name.chars().count() as f64).min(field.x1 - 2.0);
+                let caret = Line::new((caret, field.y0 + 3.0), (caret, field.y1 - 3.0));
+                builder.stroke(&peniko::Stroke::new(1.0), view, theme.text.color(), None, &caret);
+            }
+            let (width, outline) = if self.state.is_selected(&id) {
+                (2.0, theme.selection.color())
+            } else {
+                (1.0, theme.node_outline.color())
+            };
+            builder.stroke(&peniko::Stroke::new(width), view, outline, None, &rounded);
+
+            // While a wire is dragged, slots it can end in are green and
+            // the rest red
+            for (slot, position) in self.state.input_slots(&id) {
+                let color = match self.state.dragging_connection() {
+                    Some(from) => match self.state.slot_compatibility(from, &slot) {
+                        SlotCompatibility::Compatible => theme.slot_compatible.color(),
+                        SlotCompatibility::Incompatible(_) => theme.slot_incompatible.color(),
+                    },
+                    None => theme.slot.color(),
+                };
+                builder.fill(Fill::NonZero, view, color, None, &Circle::new(position, SLOT_RADIUS));
+            }
+        }
+        if let Some(marquee) = self.state.marquee() {
+            builder.fill(Fill::NonZero, view, theme.selection_fill.color(), None, &marquee);
+            builder.stroke(&peniko::Stroke::new(1.0), view, theme.selection.color(), None, &marquee);
+        }
+
+        // Draw why the slot under the dragged wire refuses it, beside the
+        // slot
+        if let Some((reason, slot)) = self.state.wire_tooltip() {
+            let anchor = view * slot;
+            let width = CHAR_WIDTH * reason.chars().count() as f64 + 16.0;
+            let tooltip = Rect::new(anchor.x + 12.0, anchor.y - 12.0, anchor.x + 12.0 + width, anchor.y + 12.0);
+            builder.fill(Fill::NonZero, Affine::IDENTITY, theme.popup_background.color(), None, &tooltip.to_rounded_rect(4.0));
+            builder.stroke(&peniko::Stroke::new(1.0), Affine::IDENTITY, theme.popup_border.color(), None, &tooltip.to_rounded_rect(4.0));
+            draw_label(&mut builder, Affine::IDENTITY, &reason, theme.error_text.color(), Point::new(tooltip.x0 + 8.0, tooltip.center().y));
+        }
+
+        // Draw the minimap: nodes, the visible area and the fit all button
+        let minimap = self.state.minimap_rect(size);
+        builder.fill(Fill::NonZero, Affine::IDENTITY, theme.overlay_background.color(), None, &minimap.to_rounded_rect(6.0));
+        let to_minimap = self.state.minimap_transform(size);
+        for id in self.state.drawing_order() {
+            if let Some(rect) = self.state.node_rect(&id) {
+                let color = if self.state.is_selected(&id) { theme.selection } else { theme.minimap_node }.color();
+                builder.fill(Fill::NonZero, Affine::IDENTITY, color, None, &to_minimap.transform_rect_bbox(rect));
+            }
+        }
+        let visible = self.state.minimap_viewport(size).intersect(minimap);
+        builder.stroke(&peniko::Stroke::new(1.0), Affine::IDENTITY, theme.minimap_viewport.color(), None, &visible);
+        let fit = self.state.fit_button_rect(size);
+        builder.fill(Fill::NonZero, Affine::IDENTITY, theme.control.color(), None, &fit.to_rounded_rect(3.0));
+        builder.stroke(&peniko::Stroke::new(1.0), Affine::IDENTITY, theme.text_muted.color(), None, &fit.inset(-5.0));
+
+        // Draw the palette centered near the top: the query, then the
+        // results with the highlighted one lit
+        if self.palette.is_open() {
+            let row_height = 28.0;
+            let results = self.palette.results();
+            let rows = results.len().min(10);
+            let x = (size.width - 420.0) / 2.0;
+            let panel = Rect::new(x, 60.0, x + 420.0, 60.0 + 44.0 + row_height * rows as f64);
+            builder.fill(Fill::NonZero, Affine::IDENTITY, theme.popup_background.color(), None, &panel.to_rounded_rect(8.0));
+            let search = Rect::new(panel.x0 + 8.0, panel.y0 + 8.0, panel.x1 - 8.0, panel.y0 + 36.0);
+            builder.fill(Fill::NonZero, Affine::IDENTITY, theme.field_background.color(), None, &search.to_rounded_rect(4.0));
+            let query = self.palette.query();
+            let (query, color) = if query.is_empty() { ("Search…", theme.text_muted) } else { (query, theme.text) };
+            draw_label(&mut builder, Affine::IDENTITY, query, color.color(), Point::new(search.x0 + 8.0, search.center().y));
+            let selected = self.palette.selected_index();
+            for (i, action) in results.iter().take(rows).enumerate() {
+                let y = panel.y0 + 44.0 + row_height * i as f64;
+                let row = Rect::new(panel.x0 + 4.0, y, panel.x1 - 4.0, y + row_height);
+                if i == selected {
+                    builder.fill(Fill::NonZero, Affine::IDENTITY, theme.highlight.color(), None, &row.to_rounded_rect(4.0));
+                }
+                draw_label(&mut builder, Affine::IDENTITY, &action.label(), theme.text.color(), Point::new(row.x0 + 8.0, row.center().y));
+            }
+        }
+
+        // Draw the node context menu, in canvas space like the node it's
+        // for, the entry under the pointer lit
+        if let Some(menu) = self.state.context_menu() {
+            builder.fill(Fill::NonZero, view, theme.popup_background.color(), None, &menu.rect().to_rounded_rect(4.0));
+            let hovered = self.state.context_menu_hovered();
+            for (action, row) in menu.items() {
+                if hovered == Some(action) {
+                    builder.fill(Fill::NonZero, view, theme.highlight.color(), None, &row.inset(-2.0).to_rounded_rect(3.0));
+                }
+                draw_label(&mut builder, view, action.label(), theme.text.color(), Point::new(row.x0 + 10.0, row.center().y));
+            }
+            builder.stroke(&peniko::Stroke::new(1.0), view, theme.popup_border.color(), None, &menu.rect().to_rounded_rect(4.0));
+        }
+
+        // Draw the toast centered along the bottom
+        if let Some(toast) = self.state.toast() {
+            let width = CHAR_WIDTH * toast.message.chars().count() as f64 + 24.0;
+            let x = (size.width - width) / 2.0;
+            let panel = Rect::new(x, size.height - 56.0, x + width, size.height - 24.0);
+            builder.fill(Fill::NonZero, Affine::IDENTITY, theme.popup_background.color(), None, &panel.to_rounded_rect(6.0));
+            builder.stroke(&peniko::Stroke::new(1.0), Affine::IDENTITY, theme.danger.color(), None, &panel.to_rounded_rect(6.0));
+            draw_label(&mut builder, Affine::IDENTITY, &toast.message, theme.text.color(), Point::new(panel.x0 + 12.0, panel.center().y));
+        }
 
         ctx.set_scene(scene);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connections_drive_the_graph() {
+        let document = Arc::new(RwLock::new(Document::with_size(8, 8)));
+        let layer = document.write().add_layer();
+        let mut editor = NodeEditor::new(document.clone());
+        editor.set_active_layer(Some(layer.clone()));
+        let blur = editor.create_node("BlurNode").unwrap();
+        let invert = editor.create_node("InvertNode").unwrap();
+
+        editor.begin_connection(blur.clone());
+        editor.finish_connection(invert.clone(), "input").unwrap();
+        let wire = NodeConnection {
+            from_node: blur.clone(),
+            from_output: None,
+            to_node: invert.clone(),
+            to_input: "input".to_string(),
+        };
+        assert_eq!(editor.state().connections(), &[wire.clone()]);
+        assert!(editor.state().toast().is_none());
+
+        // Closing the loop is rejected by the graph
+        editor.begin_connection(invert.clone());
+        assert!(editor.finish_connection(blur.clone(), "input").is_err());
+        let toast = editor.state().toast().unwrap();
+        assert!(toast.message.contains("Cycle detected"), "{}", toast.message);
+        let rejected = editor.state().flashing_connection().unwrap();
+        assert_eq!((&rejected.from_node, &rejected.to_node), (&invert, &blur));
+        assert_eq!(editor.state().connections(), &[wire.clone()]);
+        {
+            let layer = document.read().get_layer(&layer).unwrap();
+            let layer = layer.read();
+            let graph = layer.node_graph();
+            assert!(graph.get_node(&blur).unwrap().read().inputs().is_empty());
+            assert_eq!(graph.get_node(&invert).unwrap().read().get_input("input"), Some(&blur));
+        }
+
+        editor.delete_connection(&wire).unwrap();
+        assert!(editor.state().connections().is_empty());
+        document.write().undo().unwrap();
+        editor.reload();
+        assert_eq!(editor.state().connections(), &[wire]);
+    }
+
+    #[test]
+    fn test_undo_rolls_back_graph_and_editor() {
+        let document = Arc::new(RwLock::new(Document::with_size(8, 8)));
+        let layer = document.write().add_layer();
+        let mut editor = NodeEditor::new(document.clone());
+        editor.set_active_layer(Some(layer.clone()));
+        let blur = editor.create_node("BlurNode").unwrap();
+        editor.open_creation_menu(Point::new(300.0, 40.0));
+        let invert = editor.create_node("InvertNode").unwrap();
+        editor.begin_connection(blur.clone());
+        editor.finish_connection(invert.clone(), "input").unwrap();
+        let node_count = || document.read().get_layer(&layer).unwrap().read().node_graph().get_node_ids().len();
+
+        // Dragging the invert node is one undo step, however many moves
+        editor.state_mut().pointer_down(Point::new(310.0, 50.0), false);
+        editor.state_mut().pointer_move(Point::new(320.0, 60.0));
+        editor.state_mut().pointer_move(Point::new(350.0, 90.0));
+        editor.pointer_up().unwrap();
+        assert_eq!(editor.state().node_position(&invert), Some(Point::new(340.0, 80.0)));
+        let ctrl_z = Key::Character("z".into());
+        assert!(editor.handle_key(&ctrl_z, ModifiersState::CONTROL));
+        assert_eq!(editor.state().node_position(&invert), Some(Point::new(300.0, 40.0)));
+        assert!(editor.handle_key(&Key::Character("y".into()), ModifiersState::CONTROL));
+        assert_eq!(editor.state().node_position(&invert), Some(Point::new(340.0, 80.0)));
+        editor.undo().unwrap();
+
+        editor.handle_key(&ctrl_z, ModifiersState::CONTROL);
+        assert!(editor.state().connections().is_empty());
+        assert!(document.read().get_layer(&layer).unwrap().read().node_graph()
+            .get_node(&invert).unwrap().read().inputs().is_empty());
+        assert_eq!(node_count(), 2);
+
+        editor.handle_key(&ctrl_z, ModifiersState::CONTROL);
+        assert_eq!(node_count(), 1);
+        assert_eq!(editor.state().node_position(&invert), None);
+        assert!(editor.state().node_position(&blur).is_some());
+        assert!(editor.state().selected_nodes().is_empty());
+
+        // Changes made outside the editor reach it through document events
+        assert!(editor.handle_key(&ctrl_z, ModifiersState::CONTROL | ModifiersState::SHIFT));
+        assert_eq!(node_count(), 2);
+        assert!(editor.state().node_position(&invert).is_some());
+        document.write().undo().unwrap();
+        assert!(editor.sync());
+        assert_eq!(editor.state().node_position(&invert), None);
+        assert!(!editor.sync());
+    }
+
+    #[test]
+    fn test_palette_adds_nodes_at_the_cursor() {
+        let document = Arc::new(RwLock::new(Document::with_size(8, 8)));
+        let layer = document.write().add_layer();
+        let mut editor = NodeEditor::new(document.clone());
+        editor.set_active_layer(Some(layer));
+        editor.state_mut().pointer_move(Point::new(500.0, 300.0));
+
+        assert!(editor.handle_key(&Key::Character("p".into()), ModifiersState::CONTROL));
+        assert!(editor.palette().is_open());
+        for c in "invert".chars() {
+            editor.handle_key(&Key::Character(c.to_string().into()), ModifiersState::empty());
+        }
+        editor.handle_key(&Key::Named(NamedKey::Enter), ModifiersState::empty());
+        assert!(!editor.palette().is_open());
+        let invert = editor.state().selected_nodes().iter().next().cloned().unwrap();
+        assert_eq!(editor.state().node_position(&invert), Some(Point::new(500.0, 300.0)));
+
+        // Document commands run from the palette too
+        editor.open_palette();
+        for c in "add layer".chars() {
+            let key = if c == ' ' { Key::Named(NamedKey::Space) } else { Key::Character(c.to_string().into()) };
+            editor.handle_key(&key, ModifiersState::empty());
+        }
+        assert_eq!(editor.palette().selected(), Some(PaletteAction::AddLayer));
+        editor.handle_key(&Key::Named(NamedKey::Enter), ModifiersState::empty());
+        assert_eq!(document.read().layers().count(), 2);
+    }
+
+    /// A state with nodes at known positions, made up ids in order.
+    fn state_with_nodes(positions: &[(f64, f64)]) -> (NodeEditorState, Vec<NodeId>) {
+        let mut ids: Vec<_> = positions.iter().map(|_| NodeId::new()).collect();
+        ids.sort_by_key(|id| id.0);
+        let mut state = NodeEditorState::new();
+        for (id, (x, y)) in ids.iter().zip(positions) {
+            state.node_positions.insert(id.clone(), Point::new(*x, *y));
+        }
+        (state, ids)
+    }
+
+    #[test]
+    fn test_marquee_selects_intersecting_nodes() {
+        // 160×60 nodes at (0, 0), (200, 0) and (0, 200)
+        let (mut state, ids) = state_with_nodes(&[(0.0, 0.0), (200.0, 0.0), (0.0, 200.0)]);
+        assert_eq!(state.nodes_in_rect(Rect::new(150.0, 50.0, 210.0, 100.0)), vec![ids[0].clone(), ids[1].clone()]);
+        // Touching an edge isn't overlapping, and backwards drags work
+        assert!(state.nodes_in_rect(Rect::new(160.0, 60.0, 200.0, 200.0)).is_empty());
+        assert_eq!(state.nodes_in_rect(Rect::new(10.0, 300.0, -10.0, 250.0)), vec![ids[2].clone()]);
+
+        state.pointer_down(Point::new(180.0, 100.0), false);
+        state.pointer_move(Point::new(170.0, 210.0));
+        assert_eq!(state.marquee(), Some(Rect::new(170.0, 100.0, 180.0, 210.0)));
+        assert!(state.selected_nodes().is_empty());
+        state.pointer_move(Point::new(150.0, 210.0));
+        assert_eq!(state.selected_nodes(), &HashSet::from([ids[2].clone()]));
+        // Shrinking the marquee deselects what it no longer touches
+        state.pointer_move(Point::new(150.0, 150.0));
+        assert!(state.selected_nodes().is_empty());
+        state.pointer_move(Point::new(100.0, 20.0));
+        assert_eq!(state.selected_nodes(), &HashSet::from([ids[0].clone()]));
+        state.pointer_up();
+        assert_eq!(state.marquee(), None);
+
+        // Ctrl-click toggles, a plain click replaces
+        state.click_node(&ids[2], true);
+        state.click_node(&ids[1], true);
+        state.click_node(&ids[0], true);
+        assert_eq!(state.selected_nodes(), &HashSet::from([ids[1].clone(), ids[2].clone()]));
+        state.click_node(&ids[0], false);
+        assert_eq!(state.selected_nodes(), &HashSet::from([ids[0].clone()]));
+    }
+
+    #[test]
+    fn test_dragging_a_selected_node_moves_the_selection() {
+        let (mut state, ids) = state_with_nodes(&[(0.0, 0.0), (200.0, 0.0), (0.0, 200.0)]);
+        state.click_node(&ids[0], false);
+        state.click_node(&ids[1], true);
+
+        state.pointer_down(Point::new(210.0, 10.0), false);
+        state.pointer_move(Point::new(220.0, 15.0));
+        state.pointer_move(Point::new(240.0, 40.0));
+        state.pointer_up();
+        assert_eq!(state.node_position(&ids[0]), Some(Point::new(30.0, 30.0)));
+        assert_eq!(state.node_position(&ids[1]), Some(Point::new(230.0, 30.0)));
+        assert_eq!(state.node_position(&ids[2]), Some(Point::new(0.0, 200.0)));
+
+        // Pressing an unselected node drags it alone
+        state.pointer_down(Point::new(10.0, 210.0), false);
+        state.pointer_move(Point::new(20.0, 210.0));
+        assert_eq!(state.selected_nodes(), &HashSet::from([ids[2].clone()]));
+        assert_eq!(state.node_position(&ids[2]), Some(Point::new(10.0, 200.0)));
+        assert_eq!(state.node_position(&ids[0]), Some(Point::new(30.0, 30.0)));
+    }
+
+    #[test]
+    fn test_grid_snapping_applies_on_release() {
+        let (mut state, ids) = state_with_nodes(&[(0.0, 0.0), (200.0, 0.0)]);
+        state.set_snap_to_grid(true);
+        state.set_grid_size(25.0);
+        state.set_grid_size(0.0);
+        assert_eq!(state.grid_size(), 25.0);
+        state.click_node(&ids[0], false);
+        state.click_node(&ids[1], true);
+
+        // Moves stay smooth until the drop
+        state.pointer_down(Point::new(10.0, 10.0), false);
+        state.pointer_move(Point::new(23.0, 47.0));
+        assert_eq!(state.node_position(&ids[0]), Some(Point::new(13.0, 37.0)));
+        let moves = state.pointer_up();
+        assert_eq!(state.node_position(&ids[0]), Some(Point::new(25.0, 25.0)));
+        assert_eq!(state.node_position(&ids[1]), Some(Point::new(225.0, 25.0)));
+        assert_eq!(moves, vec![
+            NodeMove { id: ids[0].clone(), from: [0.0, 0.0], to: [25.0, 25.0] },
+            NodeMove { id: ids[1].clone(), from: [200.0, 0.0], to: [225.0, 25.0] },
+        ]);
+
+        // A drop that rounds back to where the drag started moves nothing
+        state.pointer_down(Point::new(30.0, 30.0), false);
+        state.pointer_move(Point::new(35.0, 24.0));
+        assert!(state.pointer_up().is_empty());
+        assert_eq!(state.node_position(&ids[0]), Some(Point::new(25.0, 25.0)));
+    }
+
+    #[test]
+    fn test_align_and_distribute_selection() {
+        // 160×60 nodes, the last collapsed to 24 high
+        let (mut state, ids) = state_with_nodes(&[(10.0, 0.0), (300.0, 50.0), (120.0, 400.0), (0.0, 230.0)]);
+        state.set_collapsed(&ids[3], true);
+        state.click_node(&ids[0], false);
+        for id in &ids[1..] {
+            state.click_node(id, true);
+        }
+        let positions = |state: &NodeEditorState| ids.iter().map(|id| state.node_position(id).unwrap()).collect::<Vec<_>>();
+
+        let moves = state.align_selection(Alignment::Left);
+        assert_eq!(positions(&state), [Point::new(0.0, 0.0), Point::new(0.0, 50.0), Point::new(0.0, 400.0), Point::new(0.0, 230.0)]);
+        // The node already on the edge didn't move
+        assert_eq!(moves.len(), 3);
+        assert_eq!(moves[0], NodeMove { id: ids[0].clone(), from: [10.0, 0.0], to: [0.0, 0.0] });
+
+        // Bottom edges meet at the lowest one, 400 + 60
+        state.align_selection(Alignment::Bottom);
+        assert_eq!(positions(&state), [Point::new(0.0, 400.0), Point::new(0.0, 400.0), Point::new(0.0, 400.0), Point::new(0.0, 436.0)]);
+
+        // Vertical: 0 to 420 holds 60 + 60 + 24 + 60 of nodes and three gaps of 72
+        for (id, y) in ids.iter().zip([0.0, 50.0, 360.0, 230.0]) {
+            state.move_node(id, Point::new(0.0, y));
+        }
+        state.distribute_selection(Distribution::Vertical);
+        assert_eq!(positions(&state), [Point::new(0.0, 0.0), Point::new(0.0, 132.0), Point::new(0.0, 360.0), Point::new(0.0, 264.0)]);
+
+        // Horizontal: 0 to 460 holds four 160 wide nodes, overlapping by 60
+        for (id, x) in ids.iter().zip([0.0, 300.0, 90.0, 40.0]) {
+            state.move_node(id, Point::new(x, 0.0));
+        }
+        state.distribute_selection(Distribution::Horizontal);
+        assert_eq!(positions(&state).iter().map(|point| point.x).collect::<Vec<_>>(), [0.0, 300.0, 200.0, 100.0]);
+
+        // Too few nodes to line up leaves them alone
+        state.click_node(&ids[0], false);
+        state.click_node(&ids[1], true);
+        assert!(state.distribute_selection(Distribution::Horizontal).is_empty());
+        state.click_node(&ids[0], false);
+        assert!(state.align_selection(Alignment::Right).is_empty());
+    }
+
+    /// An image at (0, 0) wired into a blur at (200, 0), and a blend at
+    /// (0, 200) with slots at (0, 220) and (0, 240).
+    fn wired_state() -> (NodeEditorState, Vec<NodeId>) {
+        let (mut state, ids) = state_with_nodes(&[(0.0, 0.0), (200.0, 0.0), (0.0, 200.0)]);
+        let ports: [&[&str]; 3] = [&[], &["input"], &["a", "b"]];
+        for ((id, type_name), ports) in ids.iter().zip(["ImageNode", "BlurNode", "BlendNode"]).zip(ports) {
+            state.node_types.insert(id.clone(), type_name);
+            state.input_ports.insert(id.clone(), ports.iter().map(|port| port.to_string()).collect());
+        }
+        state.connections.push(NodeConnection {
+            from_node: ids[0].clone(),
+            from_output: None,
+            to_node: ids[1].clone(),
+            to_input: "input".to_string(),
+        });
+        (state, ids)
+    }
+
+    #[test]
+    fn test_slot_compatibility() {
+        let (state, ids) = wired_state();
+        assert!(state.input_slots(&ids[0]).is_empty());
+        let blend_a = InputSlot { node: ids[2].clone(), input: "a".to_string() };
+        let blend_b = InputSlot { node: ids[2].clone(), input: "b".to_string() };
+        assert_eq!(
+            state.input_slots(&ids[2]),
+            vec![(blend_a.clone(), Point::new(0.0, 220.0)), (blend_b, Point::new(0.0, 240.0))],
+        );
+
+        assert_eq!(state.slot_compatibility(&ids[1], &blend_a), SlotCompatibility::Compatible);
+        let blur_input = InputSlot { node: ids[1].clone(), input: "input".to_string() };
+        assert!(matches!(state.slot_compatibility(&ids[1], &blur_input), SlotCompatibility::Incompatible(_)));
+        // Feeding the image from anything downstream of it is a cycle
+        let (mut state, ids) = wired_state();
+        state.connections.push(NodeConnection {
+            from_node: ids[1].clone(),
+            from_output: None,
+            to_node: ids[2].clone(),
+            to_input: "a".to_string(),
+        });
+        let SlotCompatibility::Incompatible(reason) = state.slot_compatibility(&ids[2], &blur_input) else {
+            panic!("Closing the loop should be incompatible");
+        };
+        assert!(reason.contains("BlurNode") && reason.contains("BlendNode"), "{}", reason);
+        assert_eq!(state.slot_compatibility(&ids[0], &blend_a), SlotCompatibility::Compatible);
+    }
+
+    #[test]
+    fn test_dragged_wire_snaps_to_compatible_slots_in_range() {
+        let (mut state, ids) = wired_state();
+        let blur_input = InputSlot { node: ids[1].clone(), input: "input".to_string() };
+        assert_eq!(state.snap_target(&ids[0], Point::new(190.0, 35.0)), Some((blur_input.clone(), Point::new(200.0, 30.0))));
+        assert_eq!(state.snap_target(&ids[0], Point::new(200.0, 60.0)), None);
+        // The nearer of two slots in range wins
+        let blend_b = InputSlot { node: ids[2].clone(), input: "b".to_string() };
+        assert_eq!(state.snap_target(&ids[0], Point::new(5.0, 232.0)), Some((blend_b, Point::new(0.0, 240.0))));
+
+        state.dragging_connection = Some(ids[0].clone());
+        state.drag_wire(Point::new(190.0, 35.0));
+        assert_eq!(state.wire_endpoint(), Some(Point::new(200.0, 30.0)));
+        assert_eq!(state.wire_tooltip(), None);
+
+        // Incompatible slots don't snap, and explain why
+        state.dragging_connection = Some(ids[1].clone());
+        assert_eq!(state.snap_target(&ids[1], Point::new(190.0, 35.0)), None);
+        assert_eq!(state.slot_at(Point::new(190.0, 35.0)), Some(blur_input));
+        assert_eq!(state.wire_endpoint(), Some(Point::new(190.0, 35.0)));
+        let (reason, position) = state.wire_tooltip().unwrap();
+        assert!(reason.contains("BlurNode"), "{}", reason);
+        assert_eq!(position, Point::new(200.0, 30.0));
+    }
+
+    #[test]
+    fn test_fit_all_frames_every_node() {
+        let viewport = Size::new(800.0, 600.0);
+        let mut state = NodeEditorState::new();
+        state.fit_all(viewport);
+        assert_eq!((state.scroll(), state.zoom()), (Vec2::ZERO, 1.0));
+
+        let (mut state, _) = state_with_nodes(&[(0.0, 0.0), (200.0, 0.0), (0.0, 200.0)]);
+        assert_eq!(state.world_bounds(), Some(Rect::new(0.0, 0.0, 360.0, 260.0)));
+        // 360×260 fits 720×520 at twice the size, leaving 40px around it
+        state.fit_all(viewport);
+        assert_eq!(state.zoom(), 2.0);
+        assert_eq!(state.visible_rect(viewport), Rect::new(-20.0, -20.0, 380.0, 280.0));
+        assert_eq!(state.screen_to_canvas(Point::new(40.0, 40.0)), Point::ZERO);
+
+        // Far apart nodes zoom out no further than the minimum
+        state.node_positions.insert(NodeId::new(), Point::new(100_000.0, 0.0));
+        state.fit_all(viewport);
+        assert_eq!(state.zoom(), MIN_ZOOM);
+    }
+
+    #[test]
+    fn test_minimap_pans_the_view() {
+        let viewport = Size::new(800.0, 600.0);
+        let (mut state, _) = state_with_nodes(&[(0.0, 0.0), (200.0, 0.0), (0.0, 200.0)]);
+        let minimap = state.minimap_rect(viewport);
+        let outline = state.minimap_viewport(viewport);
+        assert!(minimap.contains(outline.origin()) && minimap.contains(Point::new(outline.x1, outline.y1)));
+
+        let close = |a: Point, b: Point| (a - b).hypot() < 1e-6;
+        let to_minimap = state.minimap_transform(viewport);
+        assert!(!state.minimap_press(viewport, Point::new(10.0, 10.0)));
+        assert!(state.minimap_press(viewport, to_minimap * Point::new(100.0, 50.0)));
+        assert!(close(state.visible_rect(viewport).center(), Point::new(100.0, 50.0)));
+        // Dragging maps the pointer as the press did
+        state.minimap_drag(viewport, to_minimap * Point::new(300.0, 200.0));
+        assert!(close(state.visible_rect(viewport).center(), Point::new(300.0, 200.0)));
+        assert!(state.pointer_up().is_empty());
+        state.minimap_drag(viewport, to_minimap * Point::new(0.0, 0.0));
+        assert!(close(state.visible_rect(viewport).center(), Point::new(300.0, 200.0)));
+
+        assert!(state.minimap_press(viewport, state.fit_button_rect(viewport).center()));
+        assert_eq!(state.zoom(), 2.0);
+    }
+
+    #[test]
+    fn test_layout_survives_save_and_reload() {
+        let mut doc = Document::with_size(8, 8);
+        let asset = doc.add_asset(image::DynamicImage::new_rgba8(8, 8));
+        let layer = doc.add_asset_layer("Layer", asset);
+        let mut editor = NodeEditor::new(Arc::new(RwLock::new(doc)));
+        editor.set_active_layer(Some(layer.clone()));
+        let node = editor.document.read().get_layer(&layer).unwrap().read().node_graph().get_node_ids()[0].clone();
+        editor.state_mut().move_node(&node, Point::new(310.0, 120.0));
+        editor.state_mut().set_view(Vec2::new(-50.0, 20.0), 1.5);
+        editor.state_mut().set_collapsed(&node, true);
+        editor.store_layout().unwrap();
+
+        let saved = editor.document.read().serialize().unwrap();
+        let loaded = Document::deserialize(saved).unwrap();
+        let mut editor = NodeEditor::new(Arc::new(RwLock::new(loaded)));
+        editor.set_active_layer(Some(layer));
+        assert_eq!(editor.state().node_position(&node), Some(Point::new(310.0, 120.0)));
+        assert_eq!((editor.state().scroll(), editor.state().zoom()), (Vec2::new(-50.0, 20.0), 1.5));
+        assert!(editor.state().is_collapsed(&node));
+
+        // A node without a saved position goes in the first column
+        let blur = editor.create_node("BlurNode").unwrap();
+        let position = editor.state().node_position(&blur).unwrap();
+        assert_eq!(position.x, 40.0);
+        assert_ne!(position, Point::new(310.0, 120.0));
+    }
+
+    #[test]
+    fn test_context_menu_edits_undo() {
+        let document = Arc::new(RwLock::new(Document::with_size(8, 8)));
+        let layer = document.write().add_layer();
+        let mut editor = NodeEditor::new(document.clone());
+        editor.set_active_layer(Some(layer.clone()));
+        let blur = editor.create_node("BlurNode").unwrap();
+        let invert = editor.create_node("InvertNode").unwrap();
+        editor.begin_connection(blur.clone());
+        editor.finish_connection(invert.clone(), "input").unwrap();
+
+        // Entries are 24 high, top to bottom from where the menu opened
+        let menu_click = |editor: &mut NodeEditor, node: &NodeId, action: NodeMenuAction| {
+            let point = editor.state().node_rect(node).unwrap().center();
+            editor.right_click(point);
+            let index = NodeMenuAction::ALL.iter().position(|entry| *entry == action).unwrap();
+            editor.context_menu_click(point + Vec2::new(90.0, 24.0 * index as f64 + 12.0)).unwrap();
+        };
+        menu_click(&mut editor, &invert, NodeMenuAction::ToggleBypass);
+        assert!(editor.state().is_bypassed(&invert));
+        assert!(editor.state().context_menu().is_none());
+        menu_click(&mut editor, &blur, NodeMenuAction::SetAsOutput);
+        assert_eq!(editor.state().output_node(), Some(&blur));
+        // Choosing it again clears it
+        menu_click(&mut editor, &blur, NodeMenuAction::SetAsOutput);
+        assert_eq!(editor.state().output_node(), None);
+        editor.undo().unwrap();
+        assert_eq!(editor.state().output_node(), Some(&blur));
+
+        // The copy keeps the original's inputs, sits beside it and is selected
+        menu_click(&mut editor, &invert, NodeMenuAction::Duplicate);
+        let copy = editor.state().selected_nodes().iter().next().unwrap().clone();
+        assert_ne!(copy, invert);
+        assert_eq!(editor.state().connections().iter().filter(|wire| wire.from_node == blur).count(), 2);
+        assert_eq!(
+            editor.state().node_position(&copy),
+            editor.state().node_position(&invert).map(|point| point + DUPLICATE_OFFSET)
+        );
+
+        menu_click(&mut editor, &blur, NodeMenuAction::DisconnectAll);
+        assert!(editor.state().connections().is_empty());
+        editor.undo().unwrap();
+        assert_eq!(editor.state().connections().len(), 2);
+
+        // Rename edits in place and an empty name goes back to the type
+        menu_click(&mut editor, &blur, NodeMenuAction::Rename);
+        assert_eq!(editor.state().renaming(), Some(&(blur.clone(), "Blur".to_string())));
+        for key in [Key::Named(NamedKey::Backspace), Key::Character("r".into()), Key::Named(NamedKey::Enter)] {
+            assert!(editor.handle_key(&key, ModifiersState::empty()));
+        }
+        assert_eq!(editor.state().node_title(&blur).as_deref(), Some("Blur"));
+        assert_eq!(editor.state().node_label(&blur), Some("Blur"));
+        editor.state_mut().begin_rename(&blur);
+        editor.state_mut().renaming.as_mut().unwrap().1 = "  ".to_string();
+        editor.commit_rename().unwrap();
+        assert_eq!(editor.state().node_label(&blur), None);
+        editor.undo().unwrap();
+        assert_eq!(editor.state().node_title(&blur).as_deref(), Some("Blur"));
+
+        // Off a node a right click opens the creation menu instead
+        editor.right_click(Point::new(-500.0, -500.0));
+        assert!(editor.state().context_menu().is_none());
+    }
+
+    #[test]
+    fn test_context_menu_lists_entries_under_the_node() {
+        let (mut state, ids) = state_with_nodes(&[(0.0, 0.0), (200.0, 0.0)]);
+        let point = state.node_rect(&ids[1]).unwrap().center();
+        assert!(state.open_context_menu(point));
+        assert_eq!(state.selected_nodes(), &HashSet::from([ids[1].clone()]));
+
+        let menu = state.context_menu().unwrap();
+        assert_eq!(menu.node, ids[1]);
+        let labels: Vec<_> = menu.items().map(|(action, _)| action.label()).collect();
+        assert_eq!(labels, ["Rename", "Bypass", "Disconnect all", "Duplicate", "Delete", "Set as layer output"]);
+        let rows: Vec<_> = menu.items().map(|(_, row)| row).collect();
+        assert_eq!(rows[0].origin(), point);
+        assert!(rows.windows(2).all(|pair| pair[0].y1 == pair[1].y0));
+
+        // The entry under the pointer is the lit one
+        assert_eq!(state.context_menu_hovered(), None);
+        state.pointer_move(rows[3].center());
+        assert_eq!(state.context_menu_hovered(), Some(NodeMenuAction::Duplicate));
+        state.pointer_move(point - Vec2::new(10.0, 10.0));
+        assert_eq!(state.context_menu_hovered(), None);
+        state.close_context_menu();
+        assert_eq!(state.context_menu_hovered(), None);
+    }
+
+    #[test]
+    fn test_previews_toggle_and_resize_nodes() {
+        let (mut state, ids) = state_with_nodes(&[(0.0, 0.0), (200.0, 0.0)]);
+        assert_eq!(state.node_rect(&ids[0]).unwrap().height(), 60.0);
+        assert_eq!(state.preview_rect(&ids[0]), None);
+
+        state.set_show_previews(true);
+        assert_eq!(state.node_rect(&ids[0]).unwrap().height(), 60.0 + PREVIEW_HEIGHT);
+        assert_eq!(state.preview_rect(&ids[0]), Some(Rect::new(32.0, 60.0, 128.0, 156.0)));
+        // Turned off per node, and hidden while collapsed
+        state.toggle_preview(&ids[0]);
+        assert!(!state.preview_shown(&ids[0]));
+        assert_eq!(state.node_rect(&ids[0]).unwrap().height(), 60.0);
+        state.set_collapsed(&ids[1], true);
+        assert_eq!(state.node_rect(&ids[1]).unwrap().height(), COLLAPSED_HEIGHT);
+        assert_eq!(state.to_layout().hidden_previews, HashSet::from([ids[0].clone()]));
+    }
+
+    #[test]
+    fn test_previews_follow_parameter_changes() {
+        let mut doc = Document::with_size(8, 8);
+        let asset = doc.add_asset(image::DynamicImage::new_rgba8(8, 8));
+        let layer = doc.add_asset_layer("Layer", asset);
+        let document = Arc::new(RwLock::new(doc));
+        let mut editor = NodeEditor::new(document.clone());
+        editor.set_active_layer(Some(layer.clone()));
+        let source = editor.document.read().get_layer(&layer).unwrap().read().node_graph().get_node_ids()[0].clone();
+        let blur = editor.create_node("BlurNode").unwrap();
+        editor.begin_connection(source.clone());
+        editor.finish_connection(blur.clone(), "input").unwrap();
+
+        editor.set_show_previews(true);
+        editor.sync();
+        assert_eq!(editor.preview_hash(&blur), None);
+        document.read().render_composite().unwrap();
+        assert!(editor.sync());
+        let (source_hash, blur_hash) = (editor.preview_hash(&source).unwrap(), editor.preview_hash(&blur).unwrap());
+        assert_eq!(editor.preview_summary(&blur), None);
+
+        // The old preview shows until a render evaluates the new sigma
+        let command = meridian_document::graph_commands::SetNodeParameterCommand::new(layer.clone(), blur.clone(), "sigma", json!(1.0), json!(4.0));
+        document.write().execute_command(Box::new(command)).unwrap();
+        editor.sync();
+        assert_eq!(editor.preview_hash(&blur), Some(blur_hash));
+        document.read().render_composite().unwrap();
+        assert!(editor.sync());
+        assert_ne!(editor.preview_hash(&blur), Some(blur_hash));
+        assert_eq!(editor.preview_hash(&source), Some(source_hash));
+
+        editor.set_show_previews(false);
+        assert_eq!(editor.preview_hash(&blur), None);
+        assert!(!document.read().previews_enabled());
+    }
+}
@@ -0,0 +1,112 @@
+//! Fuzzy matching for search boxes, e.g. "gblr" finding "GaussianBlur".
+
+use std::cmp::Reverse;
+
+/// Score for a matched character starting a word, e.g. the "B" of "GaussianBlur".
+const WORD_START_BONUS: i32 = 8;
+/// Score for a matched character right after the previous one.
+const CONSECUTIVE_BONUS: i32 = 4;
+/// Most a gap between matched characters costs, so long names aren't
+/// buried for their length alone.
+const MAX_GAP_PENALTY: i32 = 3;
+
+fn is_word_start(chars: &[char], i: usize) -> bool {
+    let Some(before) = i.checked_sub(1).map(|before| chars[before]) else {
+        return true;
+    };
+    let c = chars[i];
+    (!before.is_alphanumeric() && c.is_alphanumeric())
+        || (before.is_lowercase() && c.is_uppercase())
+        || (!before.is_ascii_digit() && c.is_ascii_digit())
+}
+
+fn gap_penalty(gap: usize) -> i32 {
+    gap.min(MAX_GAP_PENALTY as usize) as i32
+}
+
+/// How well `query` matches `text`, ignoring case and whitespace in the
+/// query, or `None` if its characters don't all appear in order. Higher is
+/// better: matches at word starts and runs of adjacent characters score
+/// more, and skipped characters cost a little.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let query: Vec<char> = query.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).collect();
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+    let Some((first, rest)) = query.split_first() else {
+        return Some(0);
+    };
+    let bonus = |j: usize| if is_word_start(&chars, j) { WORD_START_BONUS } else { 1 };
+
+    // The best score for the query so far with its last character matched
+    // at each position
+    let mut best: Vec<Option<i32>> = (0..chars.len())
+        .map(|j| (lower[j] == *first).then(|| bonus(j) - gap_penalty(j)))
+        .collect();
+    for wanted in rest {
+        best = (0..chars.len())
+            .map(|j| {
+                if lower[j] != *wanted {
+                    return None;
+                }
+                let previous = (0..j)
+                    .filter_map(|k| {
+                        let step = if k + 1 == j { CONSECUTIVE_BONUS } else { -gap_penalty(j - k - 1) };
+                        Some(best[k]? + step)
+                    })
+                    .max()?;
+                Some(previous + bonus(j))
+            })
+            .collect();
+    }
+    best.into_iter().flatten().max()
+}
+
+/// The `items` matching `query`, best first. Each item scores as its best
+/// matching key, and equal scores keep their order, so an empty query
+/// lists everything as given.
+pub fn rank<T, K>(query: &str, items: impl IntoIterator<Item = T>, keys: impl Fn(&T) -> K) -> Vec<T>
+where
+    K: IntoIterator,
+    K::Item: AsRef<str>,
+{
+    let mut matches: Vec<_> = items.into_iter()
+        .filter_map(|item| {
+            let score = keys(&item).into_iter().filter_map(|key| fuzzy_score(query, key.as_ref())).max()?;
+            Some((score, item))
+        })
+        .collect();
+    // Stable, so equal matches keep their order
+    matches.sort_by_key(|(score, _)| Reverse(*score));
+    matches.into_iter().map(|(_, item)| item).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_score_prefers_word_starts() {
+        assert!(fuzzy_score("gblr", "GaussianBlur").is_some());
+        assert_eq!(fuzzy_score("gblr", "BrightnessContrast"), None);
+        assert_eq!(fuzzy_score("", "Anything"), Some(0));
+        assert_eq!(fuzzy_score("add layer", "Add layer"), fuzzy_score("addlayer", "Add layer"));
+
+        // "C" and "T" both start words in "ColorTransform"
+        assert!(fuzzy_score("ct", "ColorTransform") > fuzzy_score("ct", "Contrast"));
+        assert!(fuzzy_score("bc", "BrightnessContrast") > fuzzy_score("bc", "Blackbody"));
+        // Adjacent characters beat scattered ones
+        assert!(fuzzy_score("vert", "Invert") > fuzzy_score("vert", "Overcast"));
+    }
+
+    #[test]
+    fn test_rank_orders_by_best_key() {
+        let names = ["BrightnessContrast", "Contrast", "ColorTransform", "GaussianBlur"];
+        assert_eq!(rank("gblr", names, |name| [*name]), vec!["GaussianBlur"]);
+        assert_eq!(rank("ct", names, |name| [*name]), vec!["ColorTransform", "Contrast", "BrightnessContrast"]);
+        assert_eq!(rank(" ", names, |name| [*name]), names.to_vec());
+
+        // An item matches through any of its keys
+        let entries = [("Blur", "GaussianBlurNode"), ("Invert", "InvertNode")];
+        assert_eq!(rank("gauss", entries, |(label, type_name)| [*label, *type_name]), vec![entries[0]]);
+    }
+}
@@ -0,0 +1,192 @@
+//! The quick-action palette opened with Ctrl+P, searching node types and
+//! document commands.
+
+use winit::keyboard::{Key, NamedKey};
+use super::{rank, MenuEntry};
+
+/// How many recently run actions the palette lists first.
+const MAX_RECENT: usize = 5;
+
+/// Something the palette can run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteAction {
+    /// Adds a node where the pointer last was in the node editor.
+    AddNode(MenuEntry),
+    AddLayer,
+    Flatten,
+    /// Exports a PNG next to the saved document.
+    Export,
+}
+
+impl PaletteAction {
+    pub const DOCUMENT_COMMANDS: [PaletteAction; 3] = [PaletteAction::AddLayer, PaletteAction::Flatten, PaletteAction::Export];
+
+    pub fn label(&self) -> String {
+        match self {
+            PaletteAction::AddNode(entry) => format!("Add {} node", entry.label()),
+            PaletteAction::AddLayer => "Add layer".to_string(),
+            PaletteAction::Flatten => "Flatten image".to_string(),
+            PaletteAction::Export => "Export PNG".to_string(),
+        }
+    }
+
+    /// What the search matches: the label, and for nodes their type name
+    /// alone.
+    fn search_keys(&self) -> Vec<String> {
+        match self {
+            PaletteAction::AddNode(entry) => vec![entry.label().to_string(), entry.type_name.to_string()],
+            _ => vec![self.label()],
+        }
+    }
+}
+
+/// A centered search box over the actions, driven from the keyboard.
+#[derive(Debug, Clone, Default)]
+pub struct CommandPalette {
+    open: bool,
+    query: String,
+    actions: Vec<PaletteAction>,
+    /// Most recent first, kept between openings.
+    recent: Vec<PaletteAction>,
+    selected: usize,
+}
+
+impl CommandPalette {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens an empty search over the document commands and `node_types`.
+    pub fn open(&mut self, node_types: Vec<MenuEntry>) {
+        self.actions = PaletteAction::DOCUMENT_COMMANDS.into_iter()
+            .chain(node_types.into_iter().map(PaletteAction::AddNode))
+            .collect();
+        self.query.clear();
+        self.selected = 0;
+        self.open = true;
+    }
+
+    pub fn close(&mut self) {
+        self.open = false;
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn set_query(&mut self, query: impl Into<String>) {
+        self.query = query.into();
+        self.selected = 0;
+    }
+
+    /// Actions matching the search, best first. Recently run ones come
+    /// before others that match as well, and lead the list while the search
+    /// is empty.
+    pub fn results(&self) -> Vec<PaletteAction> {
+        let recent = self.recent.iter().filter(|action| self.actions.contains(action));
+        let others = self.actions.iter().filter(|action| !self.recent.contains(action));
+        rank(&self.query, recent.chain(others).copied(), PaletteAction::search_keys)
+    }
+
+    pub fn selected_index(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected(&self) -> Option<PaletteAction> {
+        self.results().get(self.selected).copied()
+    }
+
+    /// Moves the highlight down a row, wrapping to the top.
+    pub fn select_next(&mut self) {
+        let count = self.results().len();
+        if count > 0 {
+            self.selected = (self.selected + 1) % count;
+        }
+    }
+
+    /// Moves the highlight up a row, wrapping to the bottom.
+    pub fn select_previous(&mut self) {
+        let count = self.results().len();
+        if count > 0 {
+            self.selected = (self.selected + count - 1) % count;
+        }
+    }
+
+    /// Closes the palette and returns the highlighted action to run,
+    /// remembering it as recent.
+    pub fn confirm(&mut self) -> Option<PaletteAction> {
+        let action = self.selected()?;
+        self.recent.retain(|recent| *recent != action);
+        self.recent.insert(0, action);
+        self.recent.truncate(MAX_RECENT);
+        self.close();
+        Some(action)
+    }
+
+    /// Handles a key press while open: typing edits the search, Up and Down
+    /// move the highlight, Enter confirms and Escape closes. Returns the
+    /// action to run, if Enter chose one.
+    pub fn handle_key(&mut self, key: &Key) -> Option<PaletteAction> {
+        match key {
+            Key::Named(NamedKey::ArrowDown) => self.select_next(),
+            Key::Named(NamedKey::ArrowUp) => self.select_previous(),
+            Key::Named(NamedKey::Enter) => return self.confirm(),
+            Key::Named(NamedKey::Escape) => self.close(),
+            Key::Named(NamedKey::Backspace) => {
+                let mut query = self.query.clone();
+                query.pop();
+                self.set_query(query);
+            }
+            Key::Named(NamedKey::Space) => self.set_query(format!("{} ", self.query)),
+            Key::Character(text) => self.set_query(format!("{}{}", self.query, text)),
+            _ => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLUR: MenuEntry = MenuEntry { type_name: "GaussianBlurNode", category: Some("Filter") };
+    const CONTRAST: MenuEntry = MenuEntry { type_name: "BrightnessContrastNode", category: Some("Filter") };
+
+    fn type_text(palette: &mut CommandPalette, text: &str) {
+        for c in text.chars() {
+            palette.handle_key(&Key::Character(c.to_string().into()));
+        }
+    }
+
+    #[test]
+    fn test_keyboard_navigation_and_recent_actions() {
+        let mut palette = CommandPalette::new();
+        palette.open(vec![CONTRAST, BLUR]);
+        assert_eq!(palette.results().len(), 5);
+        type_text(&mut palette, "gblr");
+        assert_eq!(palette.results(), vec![PaletteAction::AddNode(BLUR)]);
+        assert_eq!(palette.handle_key(&Key::Named(NamedKey::Enter)), Some(PaletteAction::AddNode(BLUR)));
+        assert!(!palette.is_open());
+
+        // The last action leads the next opening, and arrows wrap around
+        palette.open(vec![CONTRAST, BLUR]);
+        assert_eq!(palette.selected(), Some(PaletteAction::AddNode(BLUR)));
+        palette.handle_key(&Key::Named(NamedKey::ArrowUp));
+        assert_eq!(palette.selected(), Some(PaletteAction::AddNode(CONTRAST)));
+        palette.handle_key(&Key::Named(NamedKey::ArrowDown));
+        palette.handle_key(&Key::Named(NamedKey::ArrowDown));
+        assert_eq!(palette.selected(), Some(PaletteAction::AddLayer));
+
+        type_text(&mut palette, "flat");
+        palette.handle_key(&Key::Named(NamedKey::Backspace));
+        assert_eq!(palette.query(), "fla");
+        assert_eq!(palette.selected(), Some(PaletteAction::Flatten));
+        palette.handle_key(&Key::Named(NamedKey::Escape));
+        assert!(!palette.is_open());
+        assert_eq!(palette.recent, vec![PaletteAction::AddNode(BLUR)]);
+    }
+}
@@ -0,0 +1,645 @@
+//! Notifications: errors, messages and the progress of long operations,
+//! shown as toasts stacked in the bottom-right corner of the window. A
+//! [`Notifier`] posts them over a channel from any thread, and the
+//! [`NotificationCenter`] picks them up when the window repaints.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use vello::{
+    SceneBuilder,
+    peniko::{Color, Fill},
+    kurbo::{Affine, Point, Rect, Size},
+};
+use aurion_core::EvaluationReport;
+use crate::layer_panel::draw_label;
+use crate::theme::Theme;
+
+/// How long info toasts stay up.
+const INFO_DURATION: Duration = Duration::from_secs(4);
+/// How long warnings stay up.
+const WARNING_DURATION: Duration = Duration::from_secs(8);
+/// Toasts shown at once. Later ones wait until one goes away.
+pub const MAX_VISIBLE: usize = 4;
+const TOAST_WIDTH: f64 = 340.0;
+const LINE_HEIGHT: f64 = 18.0;
+const PADDING: f64 = 8.0;
+/// Space between toasts and around the stack.
+const GAP: f64 = 8.0;
+const ACCENT_WIDTH: f64 = 3.0;
+const PROGRESS_HEIGHT: f64 = 6.0;
+const BUTTON_WIDTH: f64 = 60.0;
+/// Detail lines shown when expanded. Longer details are cut off, but
+/// copied in full.
+const MAX_DETAIL_LINES: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NotificationLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+impl NotificationLevel {
+    /// How long a toast of this level stays up, or `None` for errors, which
+    /// stay until dismissed.
+    pub fn duration(&self) -> Option<Duration> {
+        match self {
+            NotificationLevel::Info => Some(INFO_DURATION),
+            NotificationLevel::Warning => Some(WARNING_DURATION),
+            NotificationLevel::Error => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NotificationId(u64);
+
+/// `error` followed by the errors that caused it, one per line.
+pub fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut lines = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(cause) = source {
+        lines.push(format!("Caused by: {}", cause));
+        source = cause.source();
+    }
+    lines.join("\n")
+}
+
+/// Where an evaluation failed: each failed node's type and id, and why.
+pub fn failure_details(report: &EvaluationReport) -> Option<String> {
+    if report.failures.is_empty() {
+        return None;
+    }
+    let lines: Vec<_> = report.failures
+        .iter()
+        .map(|failure| format!("{} {}: {}", failure.type_name, failure.node.to_string(), failure.message))
+        .collect();
+    Some(lines.join("\n"))
+}
+
+enum Message {
+    Notify {
+        id: NotificationId,
+        level: NotificationLevel,
+        message: String,
+        details: Option<String>,
+    },
+    Progress {
+        id: NotificationId,
+        title: String,
+        cancelled: Arc<AtomicBool>,
+    },
+    Update {
+        id: NotificationId,
+        fraction: f32,
+        phase: String,
+    },
+    Finish {
+        id: NotificationId,
+    },
+}
+
+/// Posts notifications to a [`NotificationCenter`]. Clones post to the same
+/// center, and posting after the center is gone does nothing.
+#[derive(Clone)]
+pub struct Notifier {
+    sender: Sender<Message>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Notifier {
+    pub fn notify(&self, level: NotificationLevel, message: impl Into<String>, details: Option<String>) -> NotificationId {
+        let id = self.next_id();
+        self.send(Message::Notify { id, level, message: message.into(), details });
+        id
+    }
+
+    /// An error toast with `error`'s chain of causes as its details.
+    pub fn error(&self, message: impl Into<String>, error: &dyn std::error::Error) -> NotificationId {
+        self.notify(NotificationLevel::Error, message, Some(error_chain(error)))
+    }
+
+    /// A toast showing an operation's progress until the returned handle and
+    /// all its clones are dropped.
+    pub fn progress(&self, title: impl Into<String>) -> ProgressToast {
+        let id = self.next_id();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.send(Message::Progress { id, title: title.into(), cancelled: cancelled.clone() });
+        ProgressToast(Arc::new(ProgressInner { id, sender: self.sender.clone(), cancelled }))
+    }
+
+    fn next_id(&self) -> NotificationId {
+        NotificationId(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn send(&self, message: Message) {
+        // The center is only gone while the window closes
+        let _ = self.sender.send(message);
+    }
+}
+
+/// A progress toast, shown until the last clone is dropped. The operation
+/// it reports on should check [`ProgressToast::is_cancelled`] and stop.
+#[derive(Clone)]
+pub struct ProgressToast(Arc<ProgressInner>);
+
+struct ProgressInner {
+    id: NotificationId,
+    sender: Sender<Message>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ProgressToast {
+    pub fn id(&self) -> NotificationId {
+        self.0.id
+    }
+
+    /// Shows `fraction` of `phase` done, from 0 to 1.
+    pub fn update(&self, fraction: f32, phase: impl Into<String>) {
+        let _ = self.0.sender.send(Message::Update { id: self.0.id, fraction, phase: phase.into() });
+    }
+
+    /// Whether the toast's cancel button was pressed.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for ProgressInner {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Message::Finish { id: self.id });
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ToastProgress {
+    /// Completion of the current phase, from 0 to 1.
+    pub fraction: f32,
+    pub phase: Option<String>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ToastProgress {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub id: NotificationId,
+    pub level: NotificationLevel,
+    pub message: String,
+    pub details: Option<String>,
+    /// Whether the details are shown.
+    pub expanded: bool,
+    pub progress: Option<ToastProgress>,
+    // When it goes away by itself, set once it's shown
+    expires: Option<Instant>,
+}
+
+impl Notification {
+    /// When it goes away by itself. `None` while it's queued, and for
+    /// errors and progress, which stay until dismissed or finished.
+    pub fn expires(&self) -> Option<Instant> {
+        self.expires
+    }
+
+    /// The first line, e.g. "Opening art.artm: Reading 40%".
+    pub fn headline(&self) -> String {
+        match &self.progress {
+            Some(progress) if progress.is_cancelled() => format!("{}: Cancelling", self.message),
+            Some(ToastProgress { fraction, phase: Some(phase), .. }) => {
+                format!("{}: {} {:.0}%", self.message, phase, fraction.clamp(0.0, 1.0) * 100.0)
+            }
+            _ => self.message.clone(),
+        }
+    }
+
+    fn detail_lines(&self) -> Vec<&str> {
+        match &self.details {
+            Some(details) if self.expanded => details.lines().take(MAX_DETAIL_LINES).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn height(&self) -> f64 {
+        let mut height = PADDING * 2.0 + LINE_HEIGHT;
+        if self.progress.is_some() {
+            height += PROGRESS_HEIGHT + PADDING;
+        }
+        let details = self.detail_lines().len();
+        if details > 0 {
+            height += details as f64 * LINE_HEIGHT + PADDING;
+        }
+        height
+    }
+
+    fn buttons(&self) -> Vec<ToastButton> {
+        match &self.progress {
+            Some(progress) if progress.is_cancelled() => Vec::new(),
+            Some(_) => vec![ToastButton::Cancel],
+            None => {
+                let mut buttons = vec![ToastButton::Dismiss];
+                if self.details.is_some() {
+                    buttons.push(ToastButton::Details);
+                }
+                if self.expanded {
+                    buttons.push(ToastButton::Copy);
+                }
+                buttons
+            }
+        }
+    }
+
+    /// Its buttons in `rect`, right-aligned in the headline's row.
+    fn button_rects(&self, rect: Rect) -> Vec<(ToastButton, Rect)> {
+        let mut x = rect.x1 - PADDING;
+        self.buttons()
+            .into_iter()
+            .map(|button| {
+                let width = if button == ToastButton::Dismiss { LINE_HEIGHT } else { BUTTON_WIDTH };
+                let button_rect = Rect::new(x - width, rect.y0 + PADDING, x, rect.y0 + PADDING + LINE_HEIGHT);
+                x -= width + 4.0;
+                (button, button_rect)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToastButton {
+    Dismiss,
+    /// Shows or hides the details.
+    Details,
+    /// Copies the details.
+    Copy,
+    Cancel,
+}
+
+impl ToastButton {
+    fn label(&self, expanded: bool) -> &'static str {
+        match self {
+            ToastButton::Dismiss => "×",
+            ToastButton::Details if expanded => "Hide",
+            ToastButton::Details => "Details",
+            ToastButton::Copy => "Copy",
+            ToastButton::Cancel => "Cancel",
+        }
+    }
+}
+
+/// What a click on the toasts needs from the window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToastClick {
+    /// The toasts handled it themselves.
+    Handled,
+    /// Put this on the clipboard.
+    Copy(String),
+}
+
+/// The toasts on screen and those waiting for room.
+#[derive(Default)]
+pub struct NotificationCenter {
+    notifier: Notifier,
+    receiver: Receiver<Message>,
+    // Oldest first, so the newest is painted at the bottom
+    shown: Vec<Notification>,
+    queued: VecDeque<Notification>,
+}
+
+impl NotificationCenter {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            notifier: Notifier { sender, next_id: Arc::new(AtomicU64::new(0)) },
+            receiver,
+            shown: Vec::new(),
+            queued: VecDeque::new(),
+        }
+    }
+
+    /// A handle posting to this center, for anything that needs to report.
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
+    /// Picks up what was posted, drops expired toasts and shows queued ones
+    /// in their place. Returns whether the toasts need repainting.
+    pub fn update(&mut self, now: Instant) -> bool {
+        let mut changed = false;
+        while let Ok(message) = self.receiver.try_recv() {
+            changed |= self.receive(message, now);
+        }
+        let count = self.shown.len();
+        self.shown.retain(|notification| notification.expires.map_or(true, |expires| expires > now));
+        changed |= self.shown.len() != count;
+        while self.shown.len() < MAX_VISIBLE {
+            let Some(mut notification) = self.queued.pop_front() else {
+                break;
+            };
+            // The time a toast stays up starts once it's seen
+            notification.expires = expiry(&notification, now);
+            self.shown.push(notification);
+            changed = true;
+        }
+        changed
+    }
+
+    fn receive(&mut self, message: Message, now: Instant) -> bool {
+        match message {
+            Message::Notify { id, level, message, details } => {
+                // Posting the same message again, e.g. a render failing on
+                // every edit, refreshes its toast instead of stacking another
+                let shown = self.shown.iter_mut().map(|notification| (notification, true));
+                let queued = self.queued.iter_mut().map(|notification| (notification, false));
+                let mut all = shown.chain(queued);
+                if let Some((existing, visible)) = all.find(|(n, _)| n.progress.is_none() && n.level == level && n.message == message) {
+                    existing.details = details;
+                    if visible {
+                        existing.expires = expiry(existing, now);
+                    }
+                    return true;
+                }
+                self.queued.push_back(Notification {
+                    id,
+                    level,
+                    message,
+                    details,
+                    expanded: false,
+                    progress: None,
+                    expires: None,
+                });
+                true
+            }
+            Message::Progress { id, title, cancelled } => {
+                self.queued.push_back(Notification {
+                    id,
+                    level: NotificationLevel::Info,
+                    message: title,
+                    details: None,
+                    expanded: false,
+                    progress: Some(ToastProgress { fraction: 0.0, phase: None, cancelled }),
+                    expires: None,
+                });
+                true
+            }
+            Message::Update { id, fraction, phase } => match self.get_mut(id).and_then(|n| n.progress.as_mut()) {
+                Some(progress) => {
+                    progress.fraction = fraction;
+                    progress.phase = Some(phase);
+                    true
+                }
+                None => false,
+            },
+            Message::Finish { id } => self.dismiss(id),
+        }
+    }
+
+    fn get_mut(&mut self, id: NotificationId) -> Option<&mut Notification> {
+        self.shown.iter_mut().chain(self.queued.iter_mut()).find(|notification| notification.id == id)
+    }
+
+    /// The toasts on screen, oldest first.
+    pub fn shown(&self) -> &[Notification] {
+        &self.shown
+    }
+
+    /// How many toasts wait for room.
+    pub fn queued(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Takes a toast away, shown or queued. Returns whether there was one.
+    pub fn dismiss(&mut self, id: NotificationId) -> bool {
+        let count = self.shown.len() + self.queued.len();
+        self.shown.retain(|notification| notification.id != id);
+        self.queued.retain(|notification| notification.id != id);
+        self.shown.len() + self.queued.len() != count
+    }
+
+    /// Shows or hides a toast's details.
+    pub fn toggle_details(&mut self, id: NotificationId) {
+        if let Some(notification) = self.get_mut(id).filter(|n| n.details.is_some()) {
+            notification.expanded = !notification.expanded;
+        }
+    }
+
+    /// Asks the operation behind a progress toast to stop. The toast stays
+    /// until the operation drops its handle.
+    pub fn cancel(&mut self, id: NotificationId) {
+        if let Some(progress) = self.get_mut(id).and_then(|n| n.progress.as_ref()) {
+            progress.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Where each shown toast is in a window of `size`, newest at the
+    /// bottom.
+    pub fn toast_rects(&self, size: Size) -> Vec<Rect> {
+        let mut bottom = size.height - GAP;
+        let mut rects: Vec<_> = self.shown
+            .iter()
+            .rev()
+            .map(|notification| {
+                let rect = Rect::new(size.width - GAP - TOAST_WIDTH, bottom - notification.height(), size.width - GAP, bottom);
+                bottom = rect.y0 - GAP;
+                rect
+            })
+            .collect();
+        rects.reverse();
+        rects
+    }
+
+    /// A click at `point` in a window of `size`. Returns `None` if it
+    /// missed the toasts.
+    pub fn click(&mut self, point: Point, size: Size) -> Option<ToastClick> {
+        let (index, rect) = self.toast_rects(size).into_iter().enumerate().find(|(_, rect)| rect.contains(point))?;
+        let notification = &self.shown[index];
+        let id = notification.id;
+        let button = notification.button_rects(rect).into_iter().find(|(_, button)| button.contains(point));
+        match button.map(|(button, _)| button) {
+            Some(ToastButton::Dismiss) => {
+                self.dismiss(id);
+            }
+            Some(ToastButton::Details) => self.toggle_details(id),
+            Some(ToastButton::Copy) => {
+                let details = notification.details.clone().unwrap_or_default();
+                return Some(ToastClick::Copy(format!("{}\n{}", notification.message, details)));
+            }
+            Some(ToastButton::Cancel) => self.cancel(id),
+            None => {}
+        }
+        Some(ToastClick::Handled)
+    }
+
+    /// Paints the toasts over a window of `size` at `transform`.
+    pub fn paint(&self, builder: &mut SceneBuilder, transform: Affine, size: Size, theme: &Theme) {
+        for (notification, rect) in self.shown.iter().zip(self.toast_rects(size)) {
+            let accent = match notification.level {
+                NotificationLevel::Info => theme.selection,
+                NotificationLevel::Warning => theme.warning,
+                NotificationLevel::Error => theme.danger,
+            };
+            let card = rect.to_rounded_rect(4.0);
+            builder.fill(Fill::NonZero, transform, theme.popup_background.color(), None, &card);
+            let strip = Rect::new(rect.x0, rect.y0, rect.x0 + ACCENT_WIDTH, rect.y1);
+            builder.fill(Fill::NonZero, transform, accent.color(), None, &strip);
+
+            let x = rect.x0 + ACCENT_WIDTH + PADDING;
+            let mut y = rect.y0 + PADDING + LINE_HEIGHT;
+            draw_line(builder, transform, &notification.headline(), theme.text.color(), Point::new(x, y));
+            if let Some(progress) = &notification.progress {
+                let track = Rect::new(x, y + PADDING, rect.x1 - PADDING, y + PADDING + PROGRESS_HEIGHT);
+                let filled = track.with_size((track.width() * progress.fraction.clamp(0.0, 1.0) as f64, track.height()));
+                builder.fill(Fill::NonZero, transform, theme.control.color(), None, &track.to_rounded_rect(3.0));
+                builder.fill(Fill::NonZero, transform, theme.selection.color(), None, &filled.to_rounded_rect(3.0));
+                y = track.y1;
+            }
+            let details = notification.detail_lines();
+            if !details.is_empty() {
+                y += PADDING;
+                for line in details {
+                    y += LINE_HEIGHT;
+                    draw_line(builder, transform, line, theme.text_muted.color(), Point::new(x, y));
+                }
+            }
+
+            for (button, button_rect) in notification.button_rects(rect) {
+                if button != ToastButton::Dismiss {
+                    builder.fill(Fill::NonZero, transform, theme.control.color(), None, &button_rect.to_rounded_rect(4.0));
+                }
+                let label = button.label(notification.expanded);
+                draw_label(builder, transform, label, theme.text.color(), Point::new(button_rect.x0 + 6.0, button_rect.y1 - 5.0));
+            }
+        }
+    }
+}
+
+fn expiry(notification: &Notification, now: Instant) -> Option<Instant> {
+    if notification.progress.is_some() {
+        return None;
+    }
+    notification.level.duration().map(|duration| now + duration)
+}
+
+/// Draws a line of text with `point` on the line's bottom edge.
+fn draw_line(builder: &mut SceneBuilder, transform: Affine, text: &str, color: Color, point: Point) {
+    draw_label(builder, transform, text, color, Point::new(point.x, point.y - 5.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aurion_core::NodeError;
+    use meridian_document::DocumentError;
+
+    #[test]
+    fn test_levels_expire_or_persist() {
+        let mut center = NotificationCenter::new();
+        let notifier = center.notifier();
+        let start = Instant::now();
+        notifier.notify(NotificationLevel::Info, "Saved", None);
+        notifier.notify(NotificationLevel::Warning, "Layout not kept", None);
+        let error = notifier.notify(NotificationLevel::Error, "Couldn't save", None);
+        assert!(center.update(start));
+        assert_eq!(center.shown().len(), 3);
+        assert!(!center.update(start + Duration::from_secs(1)));
+
+        // Info goes first, then the warning; errors stay until dismissed
+        assert!(center.update(start + INFO_DURATION));
+        assert_eq!(center.shown().len(), 2);
+        center.update(start + WARNING_DURATION);
+        assert_eq!(center.shown().len(), 1);
+        center.update(start + Duration::from_secs(3600));
+        assert_eq!(center.shown()[0].id, error);
+        assert_eq!(center.shown()[0].expires(), None);
+        assert!(center.dismiss(error));
+        assert!(center.shown().is_empty());
+    }
+
+    #[test]
+    fn test_queued_toasts_wait_for_room() {
+        let mut center = NotificationCenter::new();
+        let notifier = center.notifier();
+        let start = Instant::now();
+        for i in 0..MAX_VISIBLE + 2 {
+            notifier.notify(NotificationLevel::Info, format!("Message {}", i), None);
+        }
+        center.update(start);
+        assert_eq!(center.shown().len(), MAX_VISIBLE);
+        assert_eq!(center.queued(), 2);
+
+        // Dismissing one shows the next, whose time starts then
+        let later = start + Duration::from_secs(2);
+        center.dismiss(center.shown()[0].id);
+        center.update(later);
+        assert_eq!(center.shown().len(), MAX_VISIBLE);
+        assert_eq!(center.queued(), 1);
+        assert_eq!(center.shown().last().unwrap().message, format!("Message {}", MAX_VISIBLE));
+        assert_eq!(center.shown().last().unwrap().expires(), Some(later + INFO_DURATION));
+
+        // The first ones expire together and make room for the rest
+        center.update(start + INFO_DURATION);
+        assert_eq!(center.queued(), 0);
+        assert_eq!(center.shown().len(), 2);
+
+        // The same message again refreshes its toast instead of stacking
+        let now = start + Duration::from_secs(5);
+        notifier.notify(NotificationLevel::Info, format!("Message {}", MAX_VISIBLE), None);
+        center.update(now);
+        assert_eq!(center.shown().len(), 2);
+        assert_eq!(center.shown()[0].expires(), Some(now + INFO_DURATION));
+    }
+
+    #[test]
+    fn test_details_and_progress() {
+        let mut center = NotificationCenter::new();
+        let notifier = center.notifier();
+        let size = Size::new(800.0, 600.0);
+        let error = DocumentError::from(NodeError::MissingInput("input".to_string()));
+        notifier.error("Couldn't render", &error);
+        center.update(Instant::now());
+        assert_eq!(
+            center.shown()[0].details.as_deref(),
+            Some("Node error: Missing required input: input\nCaused by: Missing required input: input")
+        );
+
+        // Details expand, then copy with the message
+        let details_button = |center: &NotificationCenter, button: ToastButton| {
+            let rect = center.toast_rects(size)[0];
+            let buttons = center.shown()[0].button_rects(rect);
+            buttons.into_iter().find(|(b, _)| *b == button).unwrap().1.center()
+        };
+        let collapsed = center.toast_rects(size)[0];
+        assert_eq!(center.click(details_button(&center, ToastButton::Details), size), Some(ToastClick::Handled));
+        assert!(center.shown()[0].expanded);
+        assert!(center.toast_rects(size)[0].height() > collapsed.height());
+        match center.click(details_button(&center, ToastButton::Copy), size) {
+            Some(ToastClick::Copy(text)) => assert!(text.starts_with("Couldn't render\nNode error"), "{}", text),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(center.click(Point::new(10.0, 10.0), size), None);
+
+        // Progress stays until its handle is dropped, and cancel reaches it
+        let progress = notifier.progress("Opening art.artm");
+        progress.update(0.4, "Reading");
+        center.update(Instant::now() + Duration::from_secs(60));
+        let toast = center.shown().last().unwrap();
+        assert_eq!(toast.headline(), "Opening art.artm: Reading 40%");
+        let cancel = center.shown().len() - 1;
+        let rect = center.toast_rects(size)[cancel];
+        let (_, cancel_rect) = toast.button_rects(rect)[0];
+        center.click(cancel_rect.center(), size);
+        assert!(progress.is_cancelled());
+        let clone = progress.clone();
+        drop(progress);
+        center.update(Instant::now());
+        assert_eq!(center.shown().len(), 2);
+        drop(clone);
+        center.update(Instant::now());
+        assert_eq!(center.shown().len(), 1);
+    }
+}
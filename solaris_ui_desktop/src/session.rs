@@ -0,0 +1,226 @@
+//! What the app remembers between runs: recently opened documents, the one
+//! open at shutdown, the window and panel layout and the theme. It's kept
+//! as JSON in the platform's config directory, saved on shutdown and every
+//! so often while the app runs.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use crate::layer_panel::PANEL_WIDTH;
+use crate::theme::{Theme, ThemePreset};
+
+/// Documents listed in File > Recent.
+pub const MAX_RECENT: usize = 10;
+/// How often the session is saved while the app runs, so a crash loses
+/// little.
+pub const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+const APP_DIR: &str = "solaris";
+const SESSION_FILE: &str = "session.json";
+
+/// Where the window was, in physical pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub maximized: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PanelLayout {
+    pub layer_panel_visible: bool,
+    pub layer_panel_width: f64,
+    pub node_editor_visible: bool,
+    pub node_editor_height: f64,
+}
+
+impl Default for PanelLayout {
+    fn default() -> Self {
+        Self {
+            layer_panel_visible: true,
+            layer_panel_width: PANEL_WIDTH,
+            node_editor_visible: true,
+            node_editor_height: 280.0,
+        }
+    }
+}
+
+/// The theme picked in the View menu.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThemeChoice {
+    Preset(ThemePreset),
+    /// A customized theme file, see [`Theme::load`].
+    File(PathBuf),
+}
+
+impl ThemeChoice {
+    pub fn load(&self) -> anyhow::Result<Theme> {
+        match self {
+            ThemeChoice::Preset(preset) => Ok(preset.theme()),
+            ThemeChoice::File(path) => Theme::load(path),
+        }
+    }
+}
+
+impl Default for ThemeChoice {
+    fn default() -> Self {
+        ThemeChoice::Preset(ThemePreset::Dark)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    /// Most recent first.
+    pub recent: Vec<PathBuf>,
+    /// The document open at the last shutdown.
+    pub last_document: Option<PathBuf>,
+    /// Whether to open [`Session::last_document`] again on startup.
+    pub reopen_last: bool,
+    pub window: Option<WindowGeometry>,
+    pub panels: PanelLayout,
+    pub theme: ThemeChoice,
+}
+
+impl Session {
+    /// The session file in the platform's config directory, e.g.
+    /// `~/.config/solaris/session.json` on Linux.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(APP_DIR).join(SESSION_FILE))
+    }
+
+    /// Reads the session at `path`. A missing file is a fresh session, and
+    /// one that can't be read is ignored with a warning: startup never
+    /// fails over it.
+    pub fn load(path: &Path) -> Self {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable session {}: {}", path.display(), e);
+                return Self::default();
+            }
+        };
+        match serde_json::from_str(&text) {
+            Ok(session) => session,
+            Err(e) => {
+                tracing::warn!("Ignoring corrupted session {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Writes the session to `path` through a temp file, so a crash midway
+    /// leaves the previous session intact.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Couldn't create {}", dir.display()))?;
+        }
+        let text = serde_json::to_string_pretty(self)?;
+        let temp = path.with_extension("json.tmp");
+        std::fs::write(&temp, text).with_context(|| format!("Couldn't write {}", temp.display()))?;
+        std::fs::rename(&temp, path).with_context(|| format!("Couldn't replace {}", path.display()))
+    }
+
+    /// Puts `path` first in the recent documents.
+    pub fn add_recent(&mut self, path: &Path) {
+        self.recent.retain(|recent| recent != path);
+        self.recent.insert(0, path.to_path_buf());
+        self.recent.truncate(MAX_RECENT);
+    }
+
+    /// Forgets documents that no longer exist. Returns whether any were
+    /// dropped.
+    pub fn prune_missing(&mut self) -> bool {
+        let count = self.recent.len();
+        self.recent.retain(|path| path.is_file());
+        let last_missing = self.last_document.as_ref().map_or(false, |path| !path.is_file());
+        if last_missing {
+            self.last_document = None;
+        }
+        self.recent.len() != count || last_missing
+    }
+
+    /// The document to open on startup, if reopening is on and it's still
+    /// there.
+    pub fn document_to_reopen(&self) -> Option<&Path> {
+        self.last_document.as_deref().filter(|path| self.reopen_last && path.is_file())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("solaris_session_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_session_round_trips() {
+        let dir = temp_dir("round_trip");
+        let path = dir.join("nested").join(SESSION_FILE);
+        let session = Session {
+            recent: vec![dir.join("b.artm"), dir.join("a.json")],
+            last_document: Some(dir.join("b.artm")),
+            reopen_last: true,
+            window: Some(WindowGeometry { x: -8, y: 40, width: 1280, height: 800, maximized: false }),
+            panels: PanelLayout { node_editor_visible: false, layer_panel_width: 300.0, ..PanelLayout::default() },
+            theme: ThemeChoice::File(dir.join("solarized.toml")),
+        };
+        session.save(&path).unwrap();
+        assert_eq!(Session::load(&path), session);
+
+        // Keys left out keep their defaults
+        fs::write(&path, r#"{"theme": {"preset": "Light"}, "panels": {"layer_panel_visible": false}}"#).unwrap();
+        let partial = Session::load(&path);
+        assert_eq!(partial.theme, ThemeChoice::Preset(ThemePreset::Light));
+        assert_eq!(partial.panels, PanelLayout { layer_panel_visible: false, ..PanelLayout::default() });
+        assert!(partial.recent.is_empty());
+
+        // Missing and corrupted files start a fresh session
+        fs::write(&path, "{\"recent\": [").unwrap();
+        assert_eq!(Session::load(&path), Session::default());
+        assert_eq!(Session::load(&dir.join("missing.json")), Session::default());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_recent_documents() {
+        let dir = temp_dir("recent");
+        let existing: Vec<_> = (0..MAX_RECENT + 2).map(|i| dir.join(format!("{}.artm", i))).collect();
+        for path in &existing {
+            fs::write(path, "").unwrap();
+        }
+        let mut session = Session::default();
+        for path in &existing {
+            session.add_recent(path);
+        }
+        session.add_recent(&existing[5]);
+        assert_eq!(session.recent.len(), MAX_RECENT);
+        assert_eq!(session.recent[0], existing[5]);
+        assert_eq!(session.recent[1], existing[MAX_RECENT + 1]);
+        assert_eq!(session.recent.iter().filter(|path| **path == existing[5]).count(), 1);
+
+        // Deleted documents are pruned, including the one to reopen
+        session.last_document = Some(existing[5].clone());
+        session.reopen_last = true;
+        assert_eq!(session.document_to_reopen(), Some(existing[5].as_path()));
+        fs::remove_file(&existing[5]).unwrap();
+        fs::remove_file(&existing[8]).unwrap();
+        assert_eq!(session.document_to_reopen(), None);
+        assert!(session.prune_missing());
+        assert_eq!(session.recent.len(), MAX_RECENT - 2);
+        assert!(!session.recent.contains(&existing[8]));
+        assert_eq!(session.last_document, None);
+        assert!(!session.prune_missing());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
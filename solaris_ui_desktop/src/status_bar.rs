@@ -0,0 +1,377 @@
+//! The status bar along the bottom of the main window: the last render's
+//! time and slowest node, the viewport's zoom and cursor, the canvas size,
+//! and a badge counting problems that opens a list of them.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use parking_lot::RwLock;
+use vello::{
+    SceneBuilder,
+    peniko::Fill,
+    kurbo::{Affine, Point, Rect},
+};
+use aurion_core::{EvaluationReport, NodeId};
+use meridian_document::{Document, DocumentEvent, LayerId, ListenerId};
+use crate::layer_panel::draw_label;
+use crate::node_editor::MenuEntry;
+use crate::theme::Theme;
+use crate::viewport::Viewport;
+
+pub const STATUS_BAR_HEIGHT: f64 = 24.0;
+const BADGE_WIDTH: f64 = 88.0;
+const ISSUE_ROW_HEIGHT: f64 = 28.0;
+const ISSUES_WIDTH: f64 = 420.0;
+const REVEAL_WIDTH: f64 = 64.0;
+
+/// e.g. "850 µs", "12.5 ms" or "1.25 s".
+pub fn format_duration(duration: Duration) -> String {
+    let micros = duration.as_micros();
+    if micros < 1000 {
+        format!("{} µs", micros)
+    } else if micros < 1_000_000 {
+        format!("{:.1} ms", micros as f64 / 1000.0)
+    } else {
+        format!("{:.2} s", duration.as_secs_f64())
+    }
+}
+
+/// The render readout, e.g. "Rendered in 12.5 ms, slowest Blur 9.0 ms".
+pub fn format_render(report: &EvaluationReport) -> String {
+    let total = format!("Rendered in {}", format_duration(report.total));
+    match report.slowest() {
+        Some(slowest) => format!("{}, slowest {} {}", total, type_label(slowest.type_name), format_duration(slowest.duration)),
+        None => total,
+    }
+}
+
+/// The cursor readout in whole canvas pixels, e.g. "412, 88".
+pub fn format_cursor(point: Point) -> String {
+    format!("{}, {}", point.x.floor(), point.y.floor())
+}
+
+/// e.g. "1920 × 1080 px".
+pub fn format_dimensions((width, height): (u32, u32)) -> String {
+    format!("{} × {} px", width, height)
+}
+
+/// The badge text, or `None` when there's nothing to report.
+pub fn format_issue_count(count: usize) -> Option<String> {
+    match count {
+        0 => None,
+        1 => Some("1 issue".to_string()),
+        count => Some(format!("{} issues", count)),
+    }
+}
+
+fn type_label(type_name: &'static str) -> &'static str {
+    MenuEntry { type_name, category: None }.label()
+}
+
+/// A problem listed under the badge, with the node it's about when known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusIssue {
+    pub layer: Option<LayerId>,
+    pub node: Option<NodeId>,
+    pub message: String,
+}
+
+impl StatusIssue {
+    /// The layer and node to show in the node editor, when both are known.
+    pub fn location(&self) -> Option<(LayerId, NodeId)> {
+        Some((self.layer.clone()?, self.node.clone()?))
+    }
+}
+
+/// Problems in the layer graphs found without evaluating them: inputs
+/// connected to nodes that no longer exist and nodes that fail their own
+/// validation.
+pub fn validation_issues(document: &Document) -> Vec<StatusIssue> {
+    let mut issues = Vec::new();
+    for layer_id in document.layers() {
+        let Some(layer) = document.get_layer(layer_id) else {
+            continue;
+        };
+        let layer = layer.read();
+        let graph = layer.node_graph();
+        for (id, node) in graph.iter_nodes() {
+            let node = node.read();
+            let mut problems = Vec::new();
+            if let Err(e) = node.validate() {
+                problems.push(e.to_string());
+            }
+            for (input, source) in node.inputs() {
+                if graph.get_node(source).is_none() {
+                    problems.push(format!("input '{}' is connected to a missing node", input));
+                }
+            }
+            for problem in problems {
+                issues.push(StatusIssue {
+                    layer: Some(layer_id.clone()),
+                    node: Some(id.clone()),
+                    message: format!("{} in {}: {}", type_label(node.data().type_name()), layer.name(), problem),
+                });
+            }
+        }
+    }
+    issues
+}
+
+pub struct StatusBar {
+    document: Arc<RwLock<Document>>,
+    listener: ListenerId,
+    // Set by graph edits, see `StatusBar::sync`
+    stale: Arc<AtomicBool>,
+    render: Option<String>,
+    failures: Vec<StatusIssue>,
+    validation: Vec<StatusIssue>,
+    issues_open: bool,
+}
+
+impl StatusBar {
+    pub fn new(document: Arc<RwLock<Document>>) -> Self {
+        let stale = Arc::new(AtomicBool::new(true));
+        let flag = stale.clone();
+        let listener = document.write().subscribe(move |event| {
+            if matches!(event, DocumentEvent::GraphChanged(_) | DocumentEvent::LayerAdded(_) | DocumentEvent::LayerRemoved(_)) {
+                flag.store(true, Ordering::Relaxed);
+            }
+        });
+        Self {
+            document,
+            listener,
+            stale,
+            render: None,
+            failures: Vec::new(),
+            validation: Vec::new(),
+            issues_open: false,
+        }
+    }
+
+    /// Validates the graphs again if one changed since the last call.
+    /// Returns whether it did.
+    pub fn sync(&mut self) -> bool {
+        if !self.stale.swap(false, Ordering::Relaxed) {
+            return false;
+        }
+        self.validation = validation_issues(&self.document.read());
+        true
+    }
+
+    /// Takes in a finished render: its time, slowest node and failures,
+    /// which replace the previous render's.
+    pub fn render_completed(&mut self, report: &EvaluationReport) {
+        self.render = Some(format_render(report));
+        let document = self.document.read();
+        self.failures = report
+            .failures
+            .iter()
+            .map(|failure| {
+                let layer = document.layers().find(|id| {
+                    document.get_layer(id).map_or(false, |layer| layer.read().node_graph().get_node(&failure.node).is_some())
+                });
+                StatusIssue {
+                    layer: layer.cloned(),
+                    node: Some(failure.node.clone()),
+                    message: format!("{} failed: {}", type_label(failure.type_name), failure.message),
+                }
+            })
+            .collect();
+    }
+
+    pub fn render_summary(&self) -> Option<&str> {
+        self.render.as_deref()
+    }
+
+    /// The last render's failures, then the validation problems.
+    pub fn issues(&self) -> Vec<&StatusIssue> {
+        self.failures.iter().chain(&self.validation).collect()
+    }
+
+    pub fn issues_open(&self) -> bool {
+        self.issues_open
+    }
+
+    pub fn close_issues(&mut self) {
+        self.issues_open = false;
+    }
+
+    /// The badge at the right end of a `width` wide bar, in bar coordinates.
+    pub fn badge_rect(&self, width: f64) -> Rect {
+        Rect::new(width - BADGE_WIDTH - 8.0, 4.0, width - 8.0, STATUS_BAR_HEIGHT - 4.0)
+    }
+
+    /// Each issue's row and its reveal button, listed upwards from the top
+    /// of the bar.
+    fn issue_rows(&self, width: f64) -> Vec<(Rect, Rect)> {
+        let count = self.issues().len();
+        (0..count)
+            .map(|i| {
+                let y = -ISSUE_ROW_HEIGHT * (count - i) as f64;
+                let row = Rect::new(width - ISSUES_WIDTH - 8.0, y, width - 8.0, y + ISSUE_ROW_HEIGHT);
+                let reveal = Rect::new(row.x1 - REVEAL_WIDTH - 4.0, row.y0 + 4.0, row.x1 - 4.0, row.y1 - 4.0);
+                (row, reveal)
+            })
+            .collect()
+    }
+
+    /// A click at `point`, in bar coordinates. The badge opens and closes
+    /// the issue list; an issue's reveal button returns where its node is.
+    pub fn click(&mut self, point: Point, width: f64) -> Option<(LayerId, NodeId)> {
+        if self.badge_rect(width).contains(point) && !self.issues().is_empty() {
+            self.issues_open = !self.issues_open;
+            return None;
+        }
+        if !self.issues_open {
+            return None;
+        }
+        let issues = self.issues();
+        let rows = self.issue_rows(width);
+        let hit = rows.iter().zip(&issues).find(|((_, reveal), _)| reveal.contains(point));
+        let location = hit.and_then(|(_, issue)| issue.location());
+        if location.is_some() {
+            self.issues_open = false;
+        }
+        location
+    }
+
+    /// Paints a `width` wide bar at `transform`, reading the zoom and
+    /// cursor from `viewport`.
+    pub fn paint(&self, builder: &mut SceneBuilder, transform: Affine, width: f64, viewport: &Viewport, theme: &Theme) {
+        let bar = Rect::new(0.0, 0.0, width, STATUS_BAR_HEIGHT);
+        builder.fill(Fill::NonZero, transform, theme.window_background.color(), None, &bar);
+
+        let text = theme.text_muted.color();
+        let baseline = STATUS_BAR_HEIGHT - 7.0;
+        let mut readouts = vec![viewport.zoom_label(), format_dimensions(self.document.read().size())];
+        if let Some(cursor) = viewport.cursor() {
+            readouts.push(format_cursor(cursor));
+        }
+        if let Some(render) = &self.render {
+            readouts.push(render.clone());
+        }
+        let mut x = 8.0;
+        for readout in readouts {
+            draw_label(builder, transform, &readout, text, Point::new(x, baseline));
+            // Text isn't measured yet, so the readouts get fixed slots
+            x += if x < 200.0 { 96.0 } else { 160.0 };
+        }
+
+        let issues = self.issues();
+        if let Some(label) = format_issue_count(issues.len()) {
+            let badge = self.badge_rect(width);
+            builder.fill(Fill::NonZero, transform, theme.danger.color(), None, &badge.to_rounded_rect(8.0));
+            draw_label(builder, transform, &label, theme.text_on_accent.color(), Point::new(badge.x0 + 10.0, baseline));
+        }
+        if !self.issues_open {
+            return;
+        }
+        for ((row, reveal), issue) in self.issue_rows(width).into_iter().zip(issues) {
+            builder.fill(Fill::NonZero, transform, theme.popup_background.color(), None, &row);
+            draw_label(builder, transform, &issue.message, text, Point::new(row.x0 + 8.0, row.y1 - 9.0));
+            if issue.location().is_some() {
+                builder.fill(Fill::NonZero, transform, theme.highlight.color(), None, &reveal.to_rounded_rect(4.0));
+                draw_label(builder, transform, "Reveal", theme.text.color(), Point::new(reveal.x0 + 8.0, reveal.y1 - 5.0));
+            }
+        }
+    }
+}
+
+impl Drop for StatusBar {
+    fn drop(&mut self) {
+        self.document.write().unsubscribe(self.listener);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aurion_core::{NodeFailure, NodeTiming};
+    use meridian_document::graph_commands::{AddNodeCommand, ConnectCommand};
+    use serde_json::json;
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(format_duration(Duration::from_micros(850)), "850 µs");
+        assert_eq!(format_duration(Duration::from_micros(12_540)), "12.5 ms");
+        assert_eq!(format_duration(Duration::from_millis(1250)), "1.25 s");
+
+        let mut report = EvaluationReport { total: Duration::from_millis(14), ..Default::default() };
+        assert_eq!(format_render(&report), "Rendered in 14.0 ms");
+        for (type_name, millis) in [("InvertNode", 2), ("BlurNode", 9)] {
+            report.timings.push(NodeTiming {
+                node: NodeId::new(),
+                type_name,
+                duration: Duration::from_millis(millis),
+                input_sizes: Vec::new(),
+                output_type: "Image",
+            });
+        }
+        assert_eq!(format_render(&report), "Rendered in 14.0 ms, slowest Blur 9.0 ms");
+
+        assert_eq!(format_cursor(Point::new(412.7, -0.5)), "412, -1");
+        assert_eq!(format_dimensions((1920, 1080)), "1920 × 1080 px");
+        assert_eq!(format_issue_count(0), None);
+        assert_eq!(format_issue_count(1).as_deref(), Some("1 issue"));
+        assert_eq!(format_issue_count(3).as_deref(), Some("3 issues"));
+    }
+
+    #[test]
+    fn test_issues_reveal_their_node() {
+        let mut document = Document::with_size(8, 8);
+        let layer = document.add_layer();
+        let command = AddNodeCommand::new(layer.clone(), "BlurNode", json!({}));
+        let blur = command.node_id().clone();
+        document.execute_command(Box::new(command)).unwrap();
+        // An unconnected input the blur requires would be an issue
+        let command = AddNodeCommand::new(layer.clone(), "ImageNode", json!({}));
+        let image = command.node_id().clone();
+        document.execute_command(Box::new(command)).unwrap();
+        document.execute_command(Box::new(ConnectCommand::new(layer.clone(), image, blur.clone(), "input"))).unwrap();
+        let document = Arc::new(RwLock::new(document));
+        let mut bar = StatusBar::new(document.clone());
+        assert!(bar.sync());
+        assert!(bar.issues().is_empty());
+        // Without issues the badge does nothing
+        assert_eq!(bar.click(bar.badge_rect(600.0).center(), 600.0), None);
+        assert!(!bar.issues_open());
+
+        // A dangling input is a validation issue once the graph changes
+        let missing = NodeId::new();
+        {
+            let document = document.read();
+            let layer = document.get_layer(&layer).unwrap();
+            let layer = layer.read();
+            layer.node_graph().get_node(&blur).unwrap().write().connect_input("input", missing);
+        }
+        assert!(!bar.sync());
+        document.write().execute_command(Box::new(AddNodeCommand::new(layer.clone(), "ImageNode", json!({})))).unwrap();
+        assert!(bar.sync());
+        assert_eq!(bar.issues().len(), 1);
+        assert!(bar.issues()[0].message.starts_with("Blur in "), "{}", bar.issues()[0].message);
+
+        // Render failures come first, found in their layer; ones outside
+        // the layer graphs can't be revealed
+        let report = EvaluationReport {
+            failures: vec![
+                NodeFailure { node: blur.clone(), type_name: "BlurNode", message: "no input".to_string() },
+                NodeFailure { node: NodeId::new(), type_name: "InvertNode", message: "no input".to_string() },
+            ],
+            ..Default::default()
+        };
+        bar.render_completed(&report);
+        let issues = bar.issues();
+        assert_eq!(issues.len(), 3);
+        assert_eq!(issues[0].message, "Blur failed: no input");
+        assert_eq!(issues[0].location(), Some((layer.clone(), blur.clone())));
+        assert_eq!(issues[1].location(), None);
+
+        assert_eq!(bar.click(bar.badge_rect(600.0).center(), 600.0), None);
+        assert!(bar.issues_open());
+        let rows = bar.issue_rows(600.0);
+        assert_eq!(rows[2].0.y1, 0.0);
+        assert_eq!(bar.click(rows[1].1.center(), 600.0), None);
+        assert_eq!(bar.click(rows[0].1.center(), 600.0), Some((layer, blur)));
+        assert!(!bar.issues_open());
+    }
+}
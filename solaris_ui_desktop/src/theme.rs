@@ -0,0 +1,286 @@
+//! The colors the editor, panels and viewport paint with. Themes are stored
+//! as TOML with colors written "#rrggbb" or "#rrggbbaa"; keys a file leaves
+//! out keep the dark theme's colors.
+
+use std::fmt;
+use std::path::Path;
+use anyhow::Context;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use vello::peniko::Color;
+
+/// A color as a theme file writes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThemeColor(pub [u8; 4]);
+
+impl ThemeColor {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self([r, g, b, 255])
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self([r, g, b, a])
+    }
+
+    pub fn color(&self) -> Color {
+        let [r, g, b, a] = self.0;
+        Color::rgba8(r, g, b, a)
+    }
+
+    /// Parses "#rrggbb" or "#rrggbbaa".
+    pub fn parse(text: &str) -> Option<Self> {
+        let hex = text.strip_prefix('#')?;
+        if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+        Some(Self([channel(0)?, channel(2)?, channel(4)?, alpha]))
+    }
+}
+
+impl fmt::Display for ThemeColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b, a] = self.0;
+        write!(f, "#{:02x}{:02x}{:02x}", r, g, b)?;
+        if a != 255 {
+            write!(f, "{:02x}", a)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for ThemeColor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ThemeColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        ThemeColor::parse(&text).ok_or_else(|| de::Error::custom(format!("invalid color \"{}\", expected #rrggbb or #rrggbbaa", text)))
+    }
+}
+
+/// The built-in themes, as listed in the View menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ThemePreset {
+    Dark,
+    Light,
+}
+
+impl ThemePreset {
+    pub const ALL: [ThemePreset; 2] = [ThemePreset::Dark, ThemePreset::Light];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThemePreset::Dark => "Dark",
+            ThemePreset::Light => "Light",
+        }
+    }
+
+    pub fn theme(&self) -> Theme {
+        match self {
+            ThemePreset::Dark => Theme::dark(),
+            ThemePreset::Light => Theme::light(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    /// Toolbar, status bar and node editor background.
+    pub window_background: ThemeColor,
+    /// Around the canvas in the viewport.
+    pub viewport_background: ThemeColor,
+    /// Where the canvas is while it has no render.
+    pub canvas_placeholder: ThemeColor,
+    pub canvas_shadow: ThemeColor,
+    pub panel_background: ThemeColor,
+    pub panel_row_selected: ThemeColor,
+    /// Menus, dialogs and the palette.
+    pub popup_background: ThemeColor,
+    pub popup_border: ThemeColor,
+    /// Minimap and readouts drawn over a view.
+    pub overlay_background: ThemeColor,
+    pub control: ThemeColor,
+    pub field_background: ThemeColor,
+    pub text: ThemeColor,
+    pub text_muted: ThemeColor,
+    /// Text on accent and danger fills.
+    pub text_on_accent: ThemeColor,
+    pub error_text: ThemeColor,
+    /// Selection outlines, focus and filled sliders.
+    pub selection: ThemeColor,
+    pub selection_fill: ThemeColor,
+    /// The highlighted row of a menu or list.
+    pub highlight: ThemeColor,
+    /// Destructive buttons and the issue badge.
+    pub danger: ThemeColor,
+    pub warning: ThemeColor,
+    pub node_header: ThemeColor,
+    pub node_body: ThemeColor,
+    pub node_body_bypassed: ThemeColor,
+    pub node_outline: ThemeColor,
+    pub output_badge: ThemeColor,
+    pub slot: ThemeColor,
+    pub slot_compatible: ThemeColor,
+    pub slot_incompatible: ThemeColor,
+    pub wire: ThemeColor,
+    pub wire_dragging: ThemeColor,
+    pub wire_rejected: ThemeColor,
+    pub grid: ThemeColor,
+    pub minimap_node: ThemeColor,
+    pub minimap_viewport: ThemeColor,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Self {
+            window_background: ThemeColor::rgb(30, 33, 40),
+            viewport_background: ThemeColor::rgb(40, 44, 52),
+            canvas_placeholder: ThemeColor::rgb(60, 60, 60),
+            canvas_shadow: ThemeColor::rgba(0, 0, 0, 140),
+            panel_background: ThemeColor::rgb(35, 38, 45),
+            panel_row_selected: ThemeColor::rgb(50, 53, 60),
+            popup_background: ThemeColor::rgb(45, 48, 56),
+            popup_border: ThemeColor::rgb(80, 84, 94),
+            overlay_background: ThemeColor::rgba(20, 22, 28, 220),
+            control: ThemeColor::rgb(60, 64, 74),
+            field_background: ThemeColor::rgb(25, 27, 32),
+            text: ThemeColor::rgb(200, 200, 200),
+            text_muted: ThemeColor::rgb(180, 185, 195),
+            text_on_accent: ThemeColor::rgb(255, 255, 255),
+            error_text: ThemeColor::rgb(230, 120, 120),
+            selection: ThemeColor::rgb(90, 160, 250),
+            selection_fill: ThemeColor::rgba(90, 160, 250, 40),
+            highlight: ThemeColor::rgb(60, 90, 140),
+            danger: ThemeColor::rgb(180, 60, 60),
+            warning: ThemeColor::rgb(225, 170, 60),
+            node_header: ThemeColor::rgb(68, 73, 84),
+            node_body: ThemeColor::rgb(55, 59, 68),
+            node_body_bypassed: ThemeColor::rgba(55, 59, 68, 110),
+            node_outline: ThemeColor::rgb(80, 84, 94),
+            output_badge: ThemeColor::rgb(240, 180, 60),
+            slot: ThemeColor::rgb(120, 125, 135),
+            slot_compatible: ThemeColor::rgb(80, 190, 110),
+            slot_incompatible: ThemeColor::rgb(220, 60, 60),
+            wire: ThemeColor::rgb(160, 165, 175),
+            wire_dragging: ThemeColor::rgb(200, 205, 215),
+            wire_rejected: ThemeColor::rgb(220, 60, 60),
+            grid: ThemeColor::rgba(255, 255, 255, 12),
+            minimap_node: ThemeColor::rgb(110, 115, 125),
+            minimap_viewport: ThemeColor::rgb(220, 220, 220),
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            window_background: ThemeColor::rgb(236, 237, 240),
+            viewport_background: ThemeColor::rgb(214, 217, 222),
+            canvas_placeholder: ThemeColor::rgb(190, 190, 190),
+            canvas_shadow: ThemeColor::rgba(0, 0, 0, 70),
+            panel_background: ThemeColor::rgb(246, 246, 248),
+            panel_row_selected: ThemeColor::rgb(222, 230, 244),
+            popup_background: ThemeColor::rgb(255, 255, 255),
+            popup_border: ThemeColor::rgb(196, 200, 208),
+            overlay_background: ThemeColor::rgba(255, 255, 255, 220),
+            control: ThemeColor::rgb(221, 224, 230),
+            field_background: ThemeColor::rgb(255, 255, 255),
+            text: ThemeColor::rgb(40, 42, 48),
+            text_muted: ThemeColor::rgb(100, 104, 112),
+            text_on_accent: ThemeColor::rgb(255, 255, 255),
+            error_text: ThemeColor::rgb(190, 40, 40),
+            selection: ThemeColor::rgb(40, 110, 220),
+            selection_fill: ThemeColor::rgba(40, 110, 220, 40),
+            highlight: ThemeColor::rgb(190, 210, 245),
+            danger: ThemeColor::rgb(200, 50, 50),
+            warning: ThemeColor::rgb(210, 140, 20),
+            node_header: ThemeColor::rgb(226, 229, 235),
+            node_body: ThemeColor::rgb(252, 252, 253),
+            node_body_bypassed: ThemeColor::rgba(252, 252, 253, 110),
+            node_outline: ThemeColor::rgb(180, 184, 192),
+            output_badge: ThemeColor::rgb(225, 150, 20),
+            slot: ThemeColor::rgb(150, 154, 162),
+            slot_compatible: ThemeColor::rgb(40, 160, 80),
+            slot_incompatible: ThemeColor::rgb(210, 50, 50),
+            wire: ThemeColor::rgb(120, 124, 132),
+            wire_dragging: ThemeColor::rgb(70, 74, 82),
+            wire_rejected: ThemeColor::rgb(210, 50, 50),
+            grid: ThemeColor::rgba(0, 0, 0, 14),
+            minimap_node: ThemeColor::rgb(170, 174, 182),
+            minimap_viewport: ThemeColor::rgb(60, 64, 72),
+        }
+    }
+
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("themes are plain tables of strings")
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+        Self::from_toml(&text).with_context(|| format!("Couldn't read the theme in {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_toml()).with_context(|| format!("Couldn't write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_round_trips_through_toml() {
+        for preset in ThemePreset::ALL {
+            let theme = preset.theme();
+            assert_eq!(Theme::from_toml(&theme.to_toml()).unwrap(), theme, "{}", preset.name());
+        }
+        let text = Theme::dark().to_toml();
+        assert!(text.contains("window_background = \"#1e2128\""), "{}", text);
+        assert!(text.contains("grid = \"#ffffff0c\""), "{}", text);
+    }
+
+    #[test]
+    fn test_missing_keys_fall_back_to_dark() {
+        let theme = Theme::from_toml("text = \"#102030\"\nunknown = \"#000000\"\n").unwrap();
+        assert_eq!(theme.text, ThemeColor::rgb(16, 32, 48));
+        assert_eq!(Theme { text: Theme::dark().text, ..theme }, Theme::dark());
+
+        assert_eq!(ThemeColor::parse("#10203080"), Some(ThemeColor::rgba(16, 32, 48, 128)));
+        for invalid in ["102030", "#12345", "#gg0000", "#1020304"] {
+            assert_eq!(ThemeColor::parse(invalid), None, "{}", invalid);
+        }
+        assert!(Theme::from_toml("text = \"red\"").is_err());
+    }
+
+    #[test]
+    fn test_painters_only_use_theme_colors() {
+        let painters = [
+            ("ui.rs", include_str!("ui.rs")),
+            ("viewport.rs", include_str!("viewport.rs")),
+            ("layer_panel.rs", include_str!("layer_panel.rs")),
+            ("status_bar.rs", include_str!("status_bar.rs")),
+            ("notifications.rs", include_str!("notifications.rs")),
+            ("transform_tool.rs", include_str!("transform_tool.rs")),
+            ("node_editor/editor.rs", include_str!("node_editor/editor.rs")),
+        ];
+        for (file, source) in painters {
+            for literal in ["Color::rgb", "Color::rgba", "Color::WHITE", "Color::BLACK"] {
+                assert!(!source.contains(literal), "{} paints with a {} literal instead of the theme", file, literal);
+            }
+        }
+    }
+}
@@ -0,0 +1,689 @@
+//! The Transform tool: a box with handles around the active layer on the
+//! canvas to move, scale and rotate it, and the tool options strip with its
+//! position, size and rotation as numbers.
+//!
+//! Hit testing and the transform math are plain functions of the layer's
+//! transform, its output size and the camera, the map from canvas pixels to
+//! the screen.
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use winit::keyboard::{Key, NamedKey};
+use vello::{
+    SceneBuilder,
+    peniko::{Fill, Stroke},
+    kurbo::{Affine, Circle, Line, Point, Rect, Vec2},
+};
+use meridian_document::{Document, DocumentError, LayerId, LayerTransform};
+use crate::layer_panel::draw_label;
+use crate::theme::Theme;
+
+const HANDLE_SIZE: f64 = 8.0;
+/// How far from a handle, in screen pixels, a press still grabs it.
+const HIT_RADIUS: f64 = 8.0;
+/// How far above the box the rotate handle sits, in screen pixels.
+const ROTATE_HANDLE_OFFSET: f64 = 24.0;
+/// Scales stop short of zero, which would collapse the layer.
+const MIN_SCALE: f32 = 0.01;
+const FIELD_LABEL_WIDTH: f64 = 20.0;
+const FIELD_WIDTH: f64 = 64.0;
+const FIELD_GAP: f64 = 12.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomRight,
+    BottomLeft,
+}
+
+impl Corner {
+    pub const ALL: [Corner; 4] = [Corner::TopLeft, Corner::TopRight, Corner::BottomRight, Corner::BottomLeft];
+
+    pub fn opposite(&self) -> Corner {
+        match self {
+            Corner::TopLeft => Corner::BottomRight,
+            Corner::TopRight => Corner::BottomLeft,
+            Corner::BottomRight => Corner::TopLeft,
+            Corner::BottomLeft => Corner::TopRight,
+        }
+    }
+
+    /// The corner of a `size` layer output, in its own pixels.
+    fn point(&self, size: (u32, u32)) -> Point {
+        let (width, height) = (size.0 as f64, size.1 as f64);
+        match self {
+            Corner::TopLeft => Point::new(0.0, 0.0),
+            Corner::TopRight => Point::new(width, 0.0),
+            Corner::BottomRight => Point::new(width, height),
+            Corner::BottomLeft => Point::new(0.0, height),
+        }
+    }
+}
+
+/// What a press on the transform box grabs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransformHandle {
+    /// Inside the box.
+    Move,
+    Scale(Corner),
+    Rotate,
+}
+
+/// Maps a `size` layer output's pixels to the canvas.
+fn layer_to_canvas(transform: &LayerTransform, size: (u32, u32)) -> Affine {
+    let affine = transform.to_affine(size.0, size.1);
+    Affine::new([affine.a, affine.b, affine.c, affine.d, affine.e, affine.f])
+}
+
+/// Where the pivot scale and rotation happen about is on the canvas.
+fn pivot_on_canvas(transform: &LayerTransform, size: (u32, u32)) -> Point {
+    let (pivot_x, pivot_y) = pivot_offset(transform, size);
+    Point::new(pivot_x + transform.x as f64, pivot_y + transform.y as f64)
+}
+
+/// The pivot in the layer output's pixels.
+fn pivot_offset(transform: &LayerTransform, size: (u32, u32)) -> (f64, f64) {
+    (transform.pivot[0] as f64 * size.0 as f64, transform.pivot[1] as f64 * size.1 as f64)
+}
+
+/// The box's corners on the canvas, in [`Corner::ALL`] order.
+pub fn box_corners(transform: &LayerTransform, size: (u32, u32)) -> [Point; 4] {
+    let to_canvas = layer_to_canvas(transform, size);
+    Corner::ALL.map(|corner| to_canvas * corner.point(size))
+}
+
+/// Where the rotate handle is on screen: above the middle of the box's top
+/// edge, the same distance away at any zoom.
+pub fn rotate_handle(transform: &LayerTransform, size: (u32, u32), camera: Affine) -> Point {
+    let [top_left, top_right, bottom_right, _] = box_corners(transform, size).map(|corner| camera * corner);
+    let top = top_left.midpoint(top_right);
+    let outward = top - top_left.midpoint(bottom_right);
+    let direction = if outward.hypot() > f64::EPSILON { outward.normalize() } else { Vec2::new(0.0, -1.0) };
+    top + direction * ROTATE_HANDLE_OFFSET
+}
+
+/// What a press at `point` on screen grabs. Handles keep their screen size
+/// at any zoom, so they're found in screen space.
+pub fn hit_test(transform: &LayerTransform, size: (u32, u32), camera: Affine, point: Point) -> Option<TransformHandle> {
+    let near = |handle: Point| (handle - point).hypot() <= HIT_RADIUS;
+    if near(rotate_handle(transform, size, camera)) {
+        return Some(TransformHandle::Rotate);
+    }
+    let corners = box_corners(transform, size);
+    if let Some(corner) = Corner::ALL.into_iter().zip(corners).find(|(_, corner)| near(camera * *corner)) {
+        return Some(TransformHandle::Scale(corner.0));
+    }
+    let to_screen = camera * layer_to_canvas(transform, size);
+    if to_screen.determinant().abs() < f64::EPSILON {
+        return None;
+    }
+    let local = to_screen.inverse() * point;
+    let inside = (0.0..=size.0 as f64).contains(&local.x) && (0.0..=size.1 as f64).contains(&local.y);
+    inside.then_some(TransformHandle::Move)
+}
+
+/// `start` after dragging `handle` from `from` to `to`, both on the canvas.
+/// Scaling keeps the opposite corner in place, and `uniform` keeps the
+/// aspect ratio. Rotating turns about the pivot.
+pub fn drag_transform(
+    start: &LayerTransform,
+    size: (u32, u32),
+    handle: TransformHandle,
+    from: Point,
+    to: Point,
+    uniform: bool,
+) -> LayerTransform {
+    match handle {
+        TransformHandle::Move => {
+            let delta = to - from;
+            LayerTransform { x: start.x + delta.x as f32, y: start.y + delta.y as f32, ..*start }
+        }
+        TransformHandle::Rotate => {
+            let pivot = pivot_on_canvas(start, size);
+            let turn = (to - pivot).atan2() - (from - pivot).atan2();
+            LayerTransform { rotation_degrees: normalize_degrees(start.rotation_degrees + turn.to_degrees() as f32), ..*start }
+        }
+        TransformHandle::Scale(corner) => scale_from_corner(start, size, corner, to - from, uniform),
+    }
+}
+
+fn scale_from_corner(start: &LayerTransform, size: (u32, u32), corner: Corner, delta: Vec2, uniform: bool) -> LayerTransform {
+    let to_canvas = layer_to_canvas(start, size);
+    let anchor = corner.opposite().point(size);
+    let anchor_on_canvas = to_canvas * anchor;
+    let dragged = to_canvas * corner.point(size) + delta;
+    // The dragged corner relative to the anchor, in the box's unrotated frame
+    let unrotate = Affine::rotate(-(start.rotation_degrees as f64).to_radians());
+    let local = (unrotate * (dragged - anchor_on_canvas).to_point()).to_vec2();
+    let span = corner.point(size) - anchor;
+
+    let (mut scale_x, mut scale_y) = (start.scale_x as f64, start.scale_y as f64);
+    if uniform {
+        // Follow the pointer along the box's diagonal
+        let scaled = Vec2::new(span.x * scale_x, span.y * scale_y);
+        let factor = if scaled.hypot2() > 0.0 { local.dot(scaled) / scaled.hypot2() } else { 1.0 };
+        scale_x *= factor;
+        scale_y *= factor;
+    } else {
+        if span.x != 0.0 {
+            scale_x = local.x / span.x;
+        }
+        if span.y != 0.0 {
+            scale_y = local.y / span.y;
+        }
+    }
+
+    // Move the box so the anchor lands back where it was
+    let scaled = LayerTransform {
+        x: 0.0,
+        y: 0.0,
+        scale_x: clamp_scale(scale_x as f32),
+        scale_y: clamp_scale(scale_y as f32),
+        ..*start
+    };
+    let moved = layer_to_canvas(&scaled, size) * anchor;
+    LayerTransform {
+        x: (anchor_on_canvas.x - moved.x) as f32,
+        y: (anchor_on_canvas.y - moved.y) as f32,
+        ..scaled
+    }
+}
+
+fn clamp_scale(scale: f32) -> f32 {
+    if scale.abs() < MIN_SCALE {
+        MIN_SCALE.copysign(scale)
+    } else {
+        scale
+    }
+}
+
+/// The same turn in (-180, 180].
+fn normalize_degrees(degrees: f32) -> f32 {
+    let degrees = degrees.rem_euclid(360.0);
+    if degrees > 180.0 {
+        degrees - 360.0
+    } else {
+        degrees
+    }
+}
+
+/// A number in the tool options strip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransformField {
+    X,
+    Y,
+    Width,
+    Height,
+    Rotation,
+}
+
+impl TransformField {
+    pub const ALL: [TransformField; 5] = [
+        TransformField::X,
+        TransformField::Y,
+        TransformField::Width,
+        TransformField::Height,
+        TransformField::Rotation,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TransformField::X => "X",
+            TransformField::Y => "Y",
+            TransformField::Width => "W",
+            TransformField::Height => "H",
+            TransformField::Rotation => "R",
+        }
+    }
+}
+
+/// A field's value for a `size` layer output: X and Y are the scaled box's
+/// top-left corner before rotation, W and H its size in canvas pixels, and
+/// rotation is in degrees.
+pub fn field_value(transform: &LayerTransform, size: (u32, u32), field: TransformField) -> f64 {
+    let (pivot_x, pivot_y) = pivot_offset(transform, size);
+    match field {
+        TransformField::X => transform.x as f64 + pivot_x * (1.0 - transform.scale_x as f64),
+        TransformField::Y => transform.y as f64 + pivot_y * (1.0 - transform.scale_y as f64),
+        TransformField::Width => size.0 as f64 * transform.scale_x as f64,
+        TransformField::Height => size.1 as f64 * transform.scale_y as f64,
+        TransformField::Rotation => transform.rotation_degrees as f64,
+    }
+}
+
+/// `transform` with `field` set to `value` and the other fields unchanged.
+pub fn with_field(transform: &LayerTransform, size: (u32, u32), field: TransformField, value: f64) -> LayerTransform {
+    let mut left = field_value(transform, size, TransformField::X);
+    let mut top = field_value(transform, size, TransformField::Y);
+    let mut result = *transform;
+    match field {
+        TransformField::X => left = value,
+        TransformField::Y => top = value,
+        TransformField::Width if size.0 > 0 => result.scale_x = clamp_scale((value / size.0 as f64) as f32),
+        TransformField::Height if size.1 > 0 => result.scale_y = clamp_scale((value / size.1 as f64) as f32),
+        TransformField::Rotation => result.rotation_degrees = normalize_degrees(value as f32),
+        TransformField::Width | TransformField::Height => {}
+    }
+    let (pivot_x, pivot_y) = pivot_offset(&result, size);
+    result.x = (left - pivot_x * (1.0 - result.scale_x as f64)) as f32;
+    result.y = (top - pivot_y * (1.0 - result.scale_y as f64)) as f32;
+    result
+}
+
+/// e.g. "12", "12.5" or "-30°".
+pub fn format_field(field: TransformField, value: f64) -> String {
+    let text = format!("{:.1}", value);
+    let text = match text.strip_suffix(".0") {
+        Some("-0") => "0".to_string(),
+        Some(whole) => whole.to_string(),
+        None => text,
+    };
+    if field == TransformField::Rotation {
+        format!("{}°", text)
+    } else {
+        text
+    }
+}
+
+/// The fields in the tool options strip, left to right.
+pub fn field_rects() -> Vec<(TransformField, Rect)> {
+    TransformField::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let x = 8.0 + i as f64 * (FIELD_LABEL_WIDTH + FIELD_WIDTH + FIELD_GAP) + FIELD_LABEL_WIDTH;
+            (*field, Rect::new(x, 8.0, x + FIELD_WIDTH, 32.0))
+        })
+        .collect()
+}
+
+struct TransformDrag {
+    layer: LayerId,
+    handle: TransformHandle,
+    size: (u32, u32),
+    original: LayerTransform,
+    // Where the press was on the canvas
+    from: Point,
+    current: LayerTransform,
+}
+
+pub struct TransformTool {
+    document: Arc<RwLock<Document>>,
+    layer: Option<LayerId>,
+    drag: Option<TransformDrag>,
+    // The field being typed into and its text so far
+    editing: Option<(TransformField, String)>,
+}
+
+impl TransformTool {
+    pub fn new(document: Arc<RwLock<Document>>) -> Self {
+        Self {
+            document,
+            layer: None,
+            drag: None,
+            editing: None,
+        }
+    }
+
+    /// The layer the handles are on.
+    pub fn layer(&self) -> Option<&LayerId> {
+        self.layer.as_ref()
+    }
+
+    /// Puts the handles on `layer`, dropping a drag on the previous one.
+    pub fn set_layer(&mut self, layer: Option<LayerId>) {
+        if self.layer != layer {
+            self.cancel();
+            self.layer = layer;
+        }
+    }
+
+    /// The layer's transform and output size, once it has rendered an
+    /// image. Mid-drag this is the previewed transform.
+    pub fn target(&self) -> Option<(LayerTransform, (u32, u32))> {
+        let layer = self.layer.as_ref()?;
+        let document = self.document.read();
+        let size = document.layer_output_size(layer)?;
+        let transform = document.get_layer(layer)?.read().transform();
+        Some((transform, size))
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Grabs the handle under `point`, on screen. Returns whether there was
+    /// one.
+    pub fn pointer_down(&mut self, point: Point, camera: Affine) -> bool {
+        let (Some(layer), Some((transform, size))) = (self.layer.clone(), self.target()) else {
+            return false;
+        };
+        let Some(handle) = hit_test(&transform, size, camera, point) else {
+            return false;
+        };
+        self.drag = Some(TransformDrag {
+            layer,
+            handle,
+            size,
+            original: transform,
+            from: camera.inverse() * point,
+            current: transform,
+        });
+        true
+    }
+
+    /// Drags the grabbed handle to `point`, previewing on the layer itself.
+    pub fn pointer_move(&mut self, point: Point, camera: Affine, uniform: bool) -> Result<(), DocumentError> {
+        let Some(drag) = self.drag.as_mut() else {
+            return Ok(());
+        };
+        let to = camera.inverse() * point;
+        let transform = drag_transform(&drag.original, drag.size, drag.handle, drag.from, to, uniform);
+        if transform == drag.current {
+            return Ok(());
+        }
+        drag.current = transform;
+        self.document.read().preview_layer_transform(&drag.layer, transform)
+    }
+
+    /// Ends the drag as a single undoable change, from the transform it
+    /// started at.
+    pub fn pointer_up(&mut self) -> Result<(), DocumentError> {
+        let Some(drag) = self.drag.take() else {
+            return Ok(());
+        };
+        if drag.current == drag.original {
+            return Ok(());
+        }
+        let mut document = self.document.write();
+        document.preview_layer_transform(&drag.layer, drag.original)?;
+        document.set_layer_transform(&drag.layer, drag.current)
+    }
+
+    /// Drops a drag, putting the layer back, and anything typed into a
+    /// field.
+    pub fn cancel(&mut self) {
+        self.editing = None;
+        if let Some(drag) = self.drag.take() {
+            if let Err(e) = self.document.read().preview_layer_transform(&drag.layer, drag.original) {
+                tracing::warn!("Couldn't put the layer back after a transform drag: {}", e);
+            }
+        }
+    }
+
+    /// The field being typed into.
+    pub fn editing(&self) -> Option<TransformField> {
+        self.editing.as_ref().map(|(field, _)| *field)
+    }
+
+    /// The text of each field: the layer's values, or what's typed into the
+    /// field being edited. Empty while there's nothing to transform.
+    pub fn fields(&self) -> Vec<(TransformField, String)> {
+        let Some((transform, size)) = self.target() else {
+            return Vec::new();
+        };
+        TransformField::ALL
+            .iter()
+            .map(|field| {
+                let text = match &self.editing {
+                    Some((editing, text)) if editing == field => text.clone(),
+                    _ => format_field(*field, field_value(&transform, size, *field)),
+                };
+                (*field, text)
+            })
+            .collect()
+    }
+
+    /// A click at `point` in the tool options strip: starts typing into the
+    /// field there, committing the one typed into before. Returns whether
+    /// it hit a field.
+    pub fn options_click(&mut self, point: Point) -> Result<bool, DocumentError> {
+        let Some((field, _)) = field_rects().into_iter().find(|(_, rect)| rect.contains(point)) else {
+            return Ok(false);
+        };
+        self.commit_field()?;
+        let Some((transform, size)) = self.target() else {
+            return Ok(false);
+        };
+        self.editing = Some((field, format_field(field, field_value(&transform, size, field))));
+        Ok(true)
+    }
+
+    /// Sets the typed value in one undoable change.
+    pub fn commit_field(&mut self) -> Result<(), DocumentError> {
+        let Some((field, text)) = self.editing.take() else {
+            return Ok(());
+        };
+        let (Some(layer), Some((transform, size))) = (self.layer.clone(), self.target()) else {
+            return Ok(());
+        };
+        let value = text.trim().trim_end_matches('°').trim().parse::<f64>().ok().filter(|value| value.is_finite());
+        let Some(value) = value else {
+            return Err(DocumentError::InvalidOperation(format!("\"{}\" isn't a number", text.trim())));
+        };
+        let transform = with_field(&transform, size, field, value);
+        self.document.write().set_layer_transform(&layer, transform)
+    }
+
+    /// Types into the field being edited: Enter commits and Escape cancels.
+    /// Escape also cancels a drag. Returns whether the tool took the key.
+    pub fn handle_key(&mut self, key: &Key) -> Result<bool, DocumentError> {
+        let Some((_, text)) = self.editing.as_mut() else {
+            if matches!(key, Key::Named(NamedKey::Escape)) && self.is_dragging() {
+                self.cancel();
+                return Ok(true);
+            }
+            return Ok(false);
+        };
+        match key {
+            Key::Named(NamedKey::Enter) => self.commit_field()?,
+            Key::Named(NamedKey::Escape) => self.editing = None,
+            Key::Named(NamedKey::Backspace) => {
+                text.pop();
+            }
+            Key::Character(typed) => text.push_str(typed),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// Paints the box and its handles over the canvas, `camera` mapping
+    /// canvas pixels to the view.
+    pub fn paint_handles(&self, builder: &mut SceneBuilder, camera: Affine, theme: &Theme) {
+        let Some((transform, size)) = self.target() else {
+            return;
+        };
+        let corners = box_corners(&transform, size).map(|corner| camera * corner);
+        let rotate = rotate_handle(&transform, size, camera);
+        let color = theme.selection.color();
+        for (i, corner) in corners.iter().enumerate() {
+            builder.stroke(&Stroke::new(1.0), Affine::IDENTITY, color, None, &Line::new(*corner, corners[(i + 1) % 4]));
+        }
+        builder.stroke(&Stroke::new(1.0), Affine::IDENTITY, color, None, &Line::new(corners[0].midpoint(corners[1]), rotate));
+        for corner in corners {
+            let handle = Rect::from_center_size(corner, (HANDLE_SIZE, HANDLE_SIZE));
+            builder.fill(Fill::NonZero, Affine::IDENTITY, theme.popup_background.color(), None, &handle);
+            builder.stroke(&Stroke::new(1.5), Affine::IDENTITY, color, None, &handle);
+        }
+        let handle = Circle::new(rotate, HANDLE_SIZE / 2.0);
+        builder.fill(Fill::NonZero, Affine::IDENTITY, theme.popup_background.color(), None, &handle);
+        builder.stroke(&Stroke::new(1.5), Affine::IDENTITY, color, None, &handle);
+    }
+
+    /// Paints the tool options strip at `transform`.
+    pub fn paint_options(&self, builder: &mut SceneBuilder, transform: Affine, theme: &Theme) {
+        for ((field, text), (_, rect)) in self.fields().into_iter().zip(field_rects()) {
+            let baseline = rect.y1 - 7.0;
+            draw_label(builder, transform, field.label(), theme.text_muted.color(), Point::new(rect.x0 - FIELD_LABEL_WIDTH + 4.0, baseline));
+            let shape = rect.to_rounded_rect(4.0);
+            builder.fill(Fill::NonZero, transform, theme.field_background.color(), None, &shape);
+            if self.editing() == Some(field) {
+                builder.stroke(&Stroke::new(1.0), transform, theme.selection.color(), None, &shape);
+            }
+            draw_label(builder, transform, &text, theme.text.color(), Point::new(rect.x0 + 6.0, baseline));
+        }
+    }
+}
+
+impl Drop for TransformTool {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+
+    const SIZE: (u32, u32) = (100, 50);
+
+    fn close(a: Point, b: Point) -> bool {
+        (a - b).hypot() < 1e-3
+    }
+
+    fn camera(zoom: f64) -> Affine {
+        Affine::translate((30.0, 20.0)) * Affine::scale(zoom)
+    }
+
+    #[test]
+    fn test_hit_testing_at_several_zooms() {
+        let placed = LayerTransform::translation(10.0, 5.0);
+        for zoom in [0.5, 1.0, 4.0] {
+            let camera = camera(zoom);
+            let [top_left, _, bottom_right, _] = box_corners(&placed, SIZE).map(|corner| camera * corner);
+            assert!(close(top_left, Point::new(30.0 + 10.0 * zoom, 20.0 + 5.0 * zoom)), "{}", zoom);
+            let hit = |point: Point| hit_test(&placed, SIZE, camera, point);
+
+            // Handles are the same size on screen whatever the zoom
+            assert_eq!(hit(top_left + Vec2::new(5.0, -5.0)), Some(TransformHandle::Scale(Corner::TopLeft)), "{}", zoom);
+            assert_eq!(hit(bottom_right + Vec2::new(-6.0, 0.0)), Some(TransformHandle::Scale(Corner::BottomRight)), "{}", zoom);
+            assert_eq!(hit(top_left + Vec2::new(-10.0, -10.0)), None, "{}", zoom);
+            assert_eq!(hit(top_left.midpoint(bottom_right)), Some(TransformHandle::Move), "{}", zoom);
+            assert_eq!(hit(bottom_right + Vec2::new(20.0, 20.0)), None, "{}", zoom);
+
+            let rotate = rotate_handle(&placed, SIZE, camera);
+            let top_middle = top_left.midpoint(Point::new(bottom_right.x, top_left.y));
+            assert!(close(rotate, top_middle - Vec2::new(0.0, ROTATE_HANDLE_OFFSET)), "{}", zoom);
+            assert_eq!(hit(rotate + Vec2::new(3.0, 3.0)), Some(TransformHandle::Rotate), "{}", zoom);
+        }
+
+        // A quarter turn about the center puts the top-left corner at the
+        // top-right, and the rotate handle to the right of the box
+        let turned = LayerTransform { rotation_degrees: 90.0, ..LayerTransform::IDENTITY };
+        let corners = box_corners(&turned, SIZE);
+        assert!(close(corners[0], Point::new(75.0, -25.0)), "{:?}", corners);
+        for zoom in [0.5, 2.0] {
+            let camera = camera(zoom);
+            assert_eq!(hit_test(&turned, SIZE, camera, camera * corners[0]), Some(TransformHandle::Scale(Corner::TopLeft)));
+            let rotate = rotate_handle(&turned, SIZE, camera);
+            let right_middle = (camera * corners[0]).midpoint(camera * corners[1]);
+            assert!(close(rotate, right_middle + Vec2::new(ROTATE_HANDLE_OFFSET, 0.0)), "{:?}", rotate);
+            // Inside the turned box but outside where it was
+            assert_eq!(hit_test(&turned, SIZE, camera, camera * Point::new(50.0, -10.0)), Some(TransformHandle::Move));
+        }
+    }
+
+    #[test]
+    fn test_drags_move_scale_and_rotate() {
+        let start = LayerTransform::IDENTITY;
+        let from = Point::new(20.0, 20.0);
+        let moved = drag_transform(&start, SIZE, TransformHandle::Move, from, Point::new(32.5, 10.0), false);
+        assert_eq!((moved.x, moved.y), (12.5, -10.0));
+
+        // Scaling keeps the opposite corner in place
+        let corner = Point::new(100.0, 50.0);
+        let free = drag_transform(&start, SIZE, TransformHandle::Scale(Corner::BottomRight), corner, Point::new(150.0, 50.0), false);
+        assert_eq!((free.scale_x, free.scale_y), (1.5, 1.0));
+        assert!(close(box_corners(&free, SIZE)[0], Point::ZERO));
+        let uniform = drag_transform(&start, SIZE, TransformHandle::Scale(Corner::BottomRight), corner, Point::new(150.0, 60.0), true);
+        assert!((uniform.scale_x - 1.44).abs() < 1e-5 && (uniform.scale_y - 1.44).abs() < 1e-5, "{:?}", uniform);
+        assert!(close(box_corners(&uniform, SIZE)[0], Point::ZERO));
+        // Dragging past the anchor flips instead of collapsing
+        let flipped = drag_transform(&start, SIZE, TransformHandle::Scale(Corner::BottomRight), corner, Point::new(-50.0, 50.0), false);
+        assert_eq!(flipped.scale_x, -0.5);
+
+        // A turned box scales along its own axes
+        let turned = LayerTransform { rotation_degrees: 90.0, ..LayerTransform::IDENTITY };
+        let corners = box_corners(&turned, SIZE);
+        let dragged = corners[2] + Vec2::new(0.0, 50.0);
+        let scaled = drag_transform(&turned, SIZE, TransformHandle::Scale(Corner::BottomRight), corners[2], dragged, false);
+        assert!((scaled.scale_x - 1.5).abs() < 1e-5 && (scaled.scale_y - 1.0).abs() < 1e-5, "{:?}", scaled);
+        let after = box_corners(&scaled, SIZE);
+        assert!(close(after[0], corners[0]) && close(after[2], dragged), "{:?}", after);
+
+        // Rotating turns about the pivot, here the center
+        let center = Point::new(50.0, 25.0);
+        let rotated = drag_transform(&start, SIZE, TransformHandle::Rotate, center + Vec2::new(40.0, 0.0), center + Vec2::new(0.0, 10.0), false);
+        assert!((rotated.rotation_degrees - 90.0).abs() < 1e-4);
+        let back = drag_transform(&rotated, SIZE, TransformHandle::Rotate, center + Vec2::new(0.0, 10.0), center + Vec2::new(-10.0, -0.001), false);
+        assert!((back.rotation_degrees - 180.0).abs() < 0.01 || (back.rotation_degrees + 180.0).abs() < 0.01, "{:?}", back);
+    }
+
+    #[test]
+    fn test_fields_follow_the_box() {
+        let transform = LayerTransform { x: 10.0, y: 4.0, scale_x: 2.0, rotation_degrees: -30.0, ..LayerTransform::IDENTITY };
+        let values: Vec<_> = TransformField::ALL.iter().map(|field| format_field(*field, field_value(&transform, SIZE, *field))).collect();
+        assert_eq!(values, ["-40", "4", "200", "50", "-30°"]);
+
+        // Setting one field leaves the others alone
+        for (field, value) in [(TransformField::X, 12.5), (TransformField::Width, 50.0), (TransformField::Rotation, 370.0)] {
+            let set = with_field(&transform, SIZE, field, value);
+            for other in TransformField::ALL {
+                let expected = match (other == field, field) {
+                    (true, TransformField::Rotation) => 10.0,
+                    (true, _) => value,
+                    (false, _) => field_value(&transform, SIZE, other),
+                };
+                assert!((field_value(&set, SIZE, other) - expected).abs() < 1e-4, "{:?} after setting {:?}", other, field);
+            }
+        }
+    }
+
+    #[test]
+    fn test_drag_previews_then_records_one_step() {
+        let mut document = Document::with_size(40, 20);
+        let asset = document.add_asset(DynamicImage::new_rgba8(40, 20));
+        let layer = document.add_asset_layer("Photo", asset);
+        document.render_composite().unwrap();
+        let document = Arc::new(RwLock::new(document));
+        let mut tool = TransformTool::new(document.clone());
+        let camera = camera(2.0);
+        assert!(!tool.pointer_down(camera * Point::new(20.0, 10.0), camera));
+        tool.set_layer(Some(layer.clone()));
+        let transform = || document.read().get_layer(&layer).unwrap().read().transform();
+
+        // 20 screen pixels at 200% is 10 canvas pixels
+        assert!(tool.pointer_down(camera * Point::new(20.0, 10.0), camera));
+        tool.pointer_move(camera * Point::new(30.0, 10.0), camera, false).unwrap();
+        assert_eq!(transform(), LayerTransform::translation(10.0, 0.0));
+        assert!(!document.read().can_undo());
+        tool.pointer_up().unwrap();
+        assert_eq!(transform(), LayerTransform::translation(10.0, 0.0));
+        document.write().undo().unwrap();
+        assert!(transform().is_identity());
+
+        // Escape puts a dragged layer back
+        assert!(tool.pointer_down(camera * Point::new(20.0, 10.0), camera));
+        tool.pointer_move(camera * Point::new(0.0, 0.0), camera, false).unwrap();
+        assert!(tool.handle_key(&Key::Named(NamedKey::Escape)).unwrap());
+        assert!(transform().is_identity());
+
+        // Typing a width scales from the left edge
+        let (width_field, width_rect) = field_rects()[2];
+        assert!(tool.options_click(width_rect.center()).unwrap());
+        assert_eq!(tool.editing(), Some(width_field));
+        tool.handle_key(&Key::Named(NamedKey::Backspace)).unwrap();
+        tool.handle_key(&Key::Named(NamedKey::Backspace)).unwrap();
+        tool.handle_key(&Key::Character("80".into())).unwrap();
+        tool.handle_key(&Key::Named(NamedKey::Enter)).unwrap();
+        let typed = transform();
+        assert_eq!(typed.scale_x, 2.0);
+        assert_eq!(tool.fields()[0].1, "0");
+        assert!(tool.options_click(field_rects()[4].1.center()).unwrap());
+        tool.handle_key(&Key::Character("x".into())).unwrap();
+        assert!(tool.handle_key(&Key::Named(NamedKey::Enter)).is_err());
+        assert_eq!(transform(), typed);
+    }
+}
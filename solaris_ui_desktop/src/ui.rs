@@ -1,17 +1,26 @@
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 use parking_lot::RwLock;
 use masonry::{Widget, WidgetCtx};
 use vello::{
-    kurbo::{Affine, Line, Rect},
-    peniko::{Fill, Color, Stroke},
+    kurbo::{Affine, Line, Point, Rect, Size},
+    peniko::{Fill, Stroke},
     Scene, SceneBuilder,
 };
-use meridian_document::Document;
+use winit::keyboard::Key;
+use meridian_document::{Document, DocumentError};
+use crate::files::{FileAction, FileMenu, NativeDialogs};
+use crate::layer_panel::LayerPanel;
+use crate::node_editor::NodeEditor;
+use crate::notifications::{failure_details, NotificationCenter, NotificationLevel, ToastClick};
+use crate::session::{Session, ThemeChoice, WindowGeometry, SESSION_SAVE_INTERVAL};
+use crate::status_bar::{StatusBar, STATUS_BAR_HEIGHT};
+use crate::theme::{Theme, ThemePreset};
+use crate::viewport::Viewport;
 
 pub struct UiState {
     selected_layer: Option<String>,
-    show_save_dialog: bool,
-    show_load_dialog: bool,
     show_node_creation_menu: bool,
 }
 
@@ -19,16 +28,236 @@ impl UiState {
     pub fn new() -> Self {
         Self {
             selected_layer: None,
-            show_save_dialog: false,
-            show_load_dialog: false,
             show_node_creation_menu: false,
         }
     }
 }
 
+/// An entry of the View menu.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViewAction {
+    Theme(ThemePreset),
+    /// A customized theme file, see [`Theme::load`].
+    LoadTheme(PathBuf),
+    ToggleLayerPanel,
+    ToggleNodeEditor,
+}
+
 pub struct MainUi {
     state: UiState,
     document: Arc<RwLock<Document>>,
+    layer_panel: LayerPanel,
+    viewport: Viewport,
+    node_editor: NodeEditor,
+    status_bar: StatusBar,
+    files: FileMenu<NativeDialogs>,
+    notifications: NotificationCenter,
+    theme: Theme,
+    session: Session,
+    // Where the session is kept, if anywhere
+    session_path: Option<PathBuf>,
+    session_saved: Instant,
+}
+
+impl MainUi {
+    /// The main window as the last session left it: its theme and panels,
+    /// and the document open then if reopening is on. `session_path` is
+    /// usually [`Session::default_path`]; with `None` nothing is kept.
+    pub fn new(session_path: Option<PathBuf>) -> Self {
+        let mut session = session_path.as_deref().map(Session::load).unwrap_or_default();
+        session.prune_missing();
+        let document = Arc::new(RwLock::new(Document::new()));
+        let notifications = NotificationCenter::new();
+        let mut ui = Self {
+            state: UiState::new(),
+            layer_panel: LayerPanel::new(document.clone()),
+            viewport: Viewport::new(document.clone()),
+            node_editor: NodeEditor::new(document.clone()),
+            status_bar: StatusBar::new(document.clone()),
+            files: FileMenu::new(document.clone(), NativeDialogs, notifications.notifier()),
+            document,
+            notifications,
+            theme: Theme::default(),
+            session: Session::default(),
+            session_path,
+            session_saved: Instant::now(),
+        };
+        match session.theme.load() {
+            Ok(theme) => ui.set_theme(theme),
+            Err(e) => {
+                tracing::warn!("Couldn't load the last session's theme: {:#}", e);
+                session.theme = ThemeChoice::default();
+            }
+        }
+        if let Some(path) = session.document_to_reopen().map(Path::to_path_buf) {
+            ui.files.open_path(&path);
+        }
+        ui.session = session;
+        ui
+    }
+
+    /// Runs a File menu entry, rebuilding the panels if it replaced the
+    /// document.
+    pub fn file_action(&mut self, action: FileAction) {
+        if self.files.run(action) {
+            self.document_replaced();
+        }
+        // Saving may have given the document a path
+        self.remember_document();
+    }
+
+    /// Opens an entry of File > Recent. One that no longer exists is
+    /// dropped from the list instead.
+    pub fn open_recent(&mut self, path: &Path) {
+        if path.is_file() {
+            self.files.open_path(path);
+        } else {
+            self.notifications.notifier().notify(NotificationLevel::Warning, format!("{} no longer exists", path.display()), None);
+            self.session.prune_missing();
+        }
+    }
+
+    /// The documents File > Recent lists, most recent first.
+    pub fn recent_documents(&self) -> &[PathBuf] {
+        &self.session.recent
+    }
+
+    /// Whether to open the current document again on the next startup.
+    pub fn set_reopen_last(&mut self, reopen: bool) {
+        self.session.reopen_last = reopen;
+    }
+
+    /// Remembers where the window is, for the next startup.
+    pub fn set_window_geometry(&mut self, geometry: WindowGeometry) {
+        self.session.window = Some(geometry);
+    }
+
+    /// The session as of the last change: what to restore the window to
+    /// before it's shown.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Saves the session, e.g. on a clean shutdown. Failures only warn,
+    /// since losing the session loses no work.
+    pub fn save_session(&mut self) {
+        self.session_saved = Instant::now();
+        let Some(path) = &self.session_path else {
+            return;
+        };
+        if let Err(e) = self.session.save(path) {
+            tracing::warn!("Couldn't save the session: {:#}", e);
+        }
+    }
+
+    fn remember_document(&mut self) {
+        let path = self.document.read().path().map(Path::to_path_buf);
+        if let Some(path) = &path {
+            self.session.add_recent(path);
+        }
+        self.session.last_document = path;
+    }
+
+    fn document_replaced(&mut self) {
+        self.document = self.files.document().clone();
+        self.layer_panel = LayerPanel::new(self.document.clone());
+        self.viewport = Viewport::new(self.document.clone());
+        self.node_editor = NodeEditor::new(self.document.clone());
+        self.status_bar = StatusBar::new(self.document.clone());
+        self.set_theme(self.theme.clone());
+        self.remember_document();
+    }
+
+    /// Runs a View menu entry. A theme file that can't be read leaves the
+    /// current theme in place.
+    pub fn view_action(&mut self, action: ViewAction) -> anyhow::Result<()> {
+        let choice = match action {
+            ViewAction::Theme(preset) => ThemeChoice::Preset(preset),
+            ViewAction::LoadTheme(path) => ThemeChoice::File(path),
+            ViewAction::ToggleLayerPanel => {
+                self.session.panels.layer_panel_visible = !self.session.panels.layer_panel_visible;
+                return Ok(());
+            }
+            ViewAction::ToggleNodeEditor => {
+                self.session.panels.node_editor_visible = !self.session.panels.node_editor_visible;
+                return Ok(());
+            }
+        };
+        self.set_theme(choice.load()?);
+        self.session.theme = choice;
+        Ok(())
+    }
+
+    /// Repaints every view with `theme`, no restart needed.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.viewport.set_theme(theme.clone());
+        self.node_editor.set_theme(theme.clone());
+        self.theme = theme;
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    /// A click on the status bar, `point` relative to its top-left corner.
+    /// Revealing an issue shows its node in the node editor.
+    pub fn status_bar_click(&mut self, point: Point, width: f64) {
+        if let Some((layer, node)) = self.status_bar.click(point, width) {
+            self.node_editor.reveal(&layer, &node);
+        }
+    }
+
+    /// A click at `point` in a window of `size`. Returns whether it hit a
+    /// toast, so the views under it shouldn't see it.
+    pub fn notifications_click(&mut self, point: Point, size: Size) -> bool {
+        match self.notifications.click(point, toast_area(size)) {
+            Some(ToastClick::Copy(text)) => {
+                let copied = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text));
+                if let Err(e) = copied {
+                    self.notifications.notifier().notify(NotificationLevel::Warning, "Couldn't copy the details", Some(e.to_string()));
+                }
+                true
+            }
+            Some(ToastClick::Handled) => true,
+            None => false,
+        }
+    }
+
+    /// A click at `point` in the tool options strip along the top. Returns
+    /// whether it hit a field.
+    pub fn tool_options_click(&mut self, point: Point) -> bool {
+        let clicked = self.viewport.transform_tool_mut().options_click(point);
+        self.report_tool_error(clicked).unwrap_or(false)
+    }
+
+    /// Types into the tool option being edited. Returns whether it took the
+    /// key.
+    pub fn tool_options_key(&mut self, key: &Key) -> bool {
+        let handled = self.viewport.transform_tool_mut().handle_key(key);
+        self.report_tool_error(handled).unwrap_or(true)
+    }
+
+    fn report_tool_error<T>(&self, result: Result<T, DocumentError>) -> Option<T> {
+        result.map_err(|e| self.notifications.notifier().error("Couldn't transform the layer", &e)).ok()
+    }
+
+    /// Posts the viewport's render failure, with the nodes that failed as
+    /// its details.
+    fn notify_render_failure(&self) {
+        if let Some(error) = self.viewport.error() {
+            let details = failure_details(self.viewport.last_report()).unwrap_or_else(|| error.to_string());
+            self.notifications.notifier().notify(NotificationLevel::Error, "Couldn't render the document", Some(details));
+        }
+    }
+
+    pub fn window_title(&self) -> String {
+        self.files.window_title()
+    }
+}
+
+/// Where toasts stack in a window of `size`: above the status bar.
+fn toast_area(size: Size) -> Size {
+    Size::new(size.width, size.height - STATUS_BAR_HEIGHT)
 }
 
 impl Widget for MainUi {
@@ -46,6 +275,12 @@ impl Widget for MainUi {
 
     fn layout(&mut self, ctx: &mut WidgetCtx) {
         let size = ctx.window_size();
+        if self.files.poll() {
+            self.document_replaced();
+        }
+        if self.session_saved.elapsed() >= SESSION_SAVE_INTERVAL {
+            self.save_session();
+        }
         let mut scene = Scene::new();
         let mut builder = SceneBuilder::for_scene(&mut scene);
 
@@ -54,57 +289,34 @@ impl Widget for MainUi {
         builder.fill(
             Fill::NonZero,
             Affine::IDENTITY,
-            Color::rgb8(30, 33, 40),
+            self.theme.window_background.color(),
             None,
             &Rect::new(0.0, 0.0, size.width, toolbar_height),
         );
 
-        // Draw layer panel background
-        let panel_width = 200.0;
-        builder.fill(
-            Fill::NonZero,
-            Affine::IDENTITY,
-            Color::rgb8(35, 38, 45),
-            None,
-            &Rect::new(0.0, toolbar_height, panel_width, size.height),
-        );
+        // The active layer's tool options go in the toolbar
+        self.layer_panel.sync();
+        self.viewport.set_active_layer(self.layer_panel.selected().cloned());
+        self.viewport.transform_tool().paint_options(&mut builder, Affine::IDENTITY, &self.theme);
 
-        // Draw layer list
-        let doc = self.document.read();
-        let mut y = toolbar_height + 10.0;
-        for layer_id in doc.layers() {
-            if let Some(layer) = doc.get_layer(layer_id) {
-                let layer = layer.read();
-                let is_selected = self.state.selected_layer.as_ref() == Some(&layer.name());
-                
-                // Draw layer item background
-                let item_height = 30.0;
-                let bg_color = if is_selected {
-                    Color::rgb8(50, 53, 60)
-                } else {
-                    Color::rgb8(35, 38, 45)
-                };
-                
-                builder.fill(
-                    Fill::NonZero,
-                    Affine::IDENTITY,
-                    bg_color,
-                    None,
-                    &Rect::new(5.0, y, panel_width - 5.0, y + item_height),
-                );
-
-                // Draw layer name
-                let text = layer.name();
-                let text_color = Color::rgb8(200, 200, 200);
-                builder.draw_text(
-                    &text,
-                    text_color,
-                    (10.0, y + item_height / 2.0),
-                );
-
-                y += item_height + 5.0;
-            }
+        // Draw layer panel
+        if self.session.panels.layer_panel_visible {
+            self.layer_panel.paint(&mut builder, Affine::translate((0.0, toolbar_height)), size.height - toolbar_height - STATUS_BAR_HEIGHT, &self.theme);
+        }
+
+        // Draw the status bar, after picking up a new render and any graph
+        // edits
+        if self.viewport.sync() {
+            self.status_bar.render_completed(self.viewport.last_report());
+            self.notify_render_failure();
         }
+        self.status_bar.sync();
+        let status_bar = Affine::translate((0.0, size.height - STATUS_BAR_HEIGHT));
+        self.status_bar.paint(&mut builder, status_bar, size.width, &self.viewport, &self.theme);
+
+        // Toasts go over everything above the status bar
+        self.notifications.update(Instant::now());
+        self.notifications.paint(&mut builder, Affine::IDENTITY, toast_area(size), &self.theme);
 
         ctx.set_scene(scene);
         ()
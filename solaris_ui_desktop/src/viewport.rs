@@ -1,15 +1,328 @@
+//! The canvas view: the composited document, panned with the middle button
+//! and zoomed with the wheel, with the Transform tool's handles on the
+//! active layer. The composite is rendered again only when a document event
+//! says it changed.
+
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use parking_lot::RwLock;
+use winit::event::MouseButton;
+use winit::keyboard::{Key, ModifiersState, NamedKey};
 use masonry::{Widget, WidgetCtx};
 use vello::{
-    kurbo::{Affine, Line, Rect},
-    peniko::{Fill, Color, Stroke},
+    kurbo::{Affine, Point, Rect, Size, Vec2},
+    peniko::{Blob, Fill, Format, Image},
     Scene, SceneBuilder,
 };
-use meridian_document::Document;
+use aurion_core::EvaluationReport;
+use meridian_document::{Document, DocumentEvent, LayerId, ListenerId};
+use crate::theme::Theme;
+use crate::transform_tool::TransformTool;
+
+const MIN_ZOOM: f64 = 0.05;
+const MAX_ZOOM: f64 = 32.0;
+/// Zoom change per wheel line.
+const WHEEL_ZOOM_STEP: f64 = 1.1;
+/// Space kept around the canvas when fitting it to the window.
+const FIT_MARGIN: f64 = 24.0;
+const SHADOW_OFFSET: f64 = 4.0;
+const SHADOW_BLUR: f64 = 12.0;
+
+/// Whether `event` changes what the composite looks like.
+fn changes_composite(event: &DocumentEvent) -> bool {
+    !matches!(
+        event,
+        DocumentEvent::ModifiedChanged(_) | DocumentEvent::SelectionChanged | DocumentEvent::UiLayoutChanged(_)
+    )
+}
 
 pub struct Viewport {
     document: Arc<RwLock<Document>>,
+    listener: ListenerId,
+    // Set by document events that change the composite, see `Viewport::sync`
+    stale: Arc<AtomicBool>,
+    composite: Option<Image>,
+    // Why the last render failed, shown in place of the canvas
+    error: Option<String>,
+    report: EvaluationReport,
+    // Screen position of the canvas' top-left corner
+    offset: Vec2,
+    zoom: f64,
+    size: Size,
+    // Where the middle button drag last was
+    pan: Option<Point>,
+    // The pointer in canvas coordinates, while it's over the view
+    cursor: Option<Point>,
+    // Whether the view still needs fitting to the first real window size
+    fit_pending: bool,
+    tool: TransformTool,
+    // Shift makes corner drags keep the aspect ratio
+    modifiers: ModifiersState,
+    theme: Theme,
+}
+
+impl Viewport {
+    pub fn new(document: Arc<RwLock<Document>>) -> Self {
+        let stale = Arc::new(AtomicBool::new(true));
+        let flag = stale.clone();
+        let listener = document.write().subscribe(move |event| {
+            if changes_composite(event) {
+                flag.store(true, Ordering::Relaxed);
+            }
+        });
+        Self {
+            document,
+            listener,
+            stale,
+            composite: None,
+            error: None,
+            report: EvaluationReport::default(),
+            offset: Vec2::ZERO,
+            zoom: 1.0,
+            size: Size::new(800.0, 600.0),
+            pan: None,
+            cursor: None,
+            fit_pending: true,
+            tool: TransformTool::new(document.clone()),
+            modifiers: ModifiersState::empty(),
+            theme: Theme::default(),
+        }
+    }
+
+    pub fn zoom(&self) -> f64 {
+        self.zoom
+    }
+
+    pub fn offset(&self) -> Vec2 {
+        self.offset
+    }
+
+    pub fn composite(&self) -> Option<&Image> {
+        self.composite.as_ref()
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Paints with `theme` from the next layout on.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// Puts the Transform tool's handles on `layer`.
+    pub fn set_active_layer(&mut self, layer: Option<LayerId>) {
+        self.tool.set_layer(layer);
+    }
+
+    pub fn transform_tool(&self) -> &TransformTool {
+        &self.tool
+    }
+
+    pub fn transform_tool_mut(&mut self) -> &mut TransformTool {
+        &mut self.tool
+    }
+
+    pub fn set_modifiers(&mut self, modifiers: ModifiersState) {
+        self.modifiers = modifiers;
+    }
+
+    /// What the last render evaluated and how long it took.
+    pub fn last_report(&self) -> &EvaluationReport {
+        &self.report
+    }
+
+    /// Where the pointer is on the canvas, while it's over the view.
+    pub fn cursor(&self) -> Option<Point> {
+        self.cursor
+    }
+
+    /// The zoom readout, e.g. "150%".
+    pub fn zoom_label(&self) -> String {
+        format!("{:.0}%", self.zoom * 100.0)
+    }
+
+    fn canvas_size(&self) -> Size {
+        let (width, height) = self.document.read().size();
+        Size::new(width as f64, height as f64)
+    }
+
+    /// Maps canvas pixels to the screen.
+    pub fn canvas_transform(&self) -> Affine {
+        Affine::translate(self.offset) * Affine::scale(self.zoom)
+    }
+
+    /// Where the canvas sits on screen.
+    pub fn canvas_rect(&self) -> Rect {
+        self.canvas_transform().transform_rect_bbox(self.canvas_size().to_rect())
+    }
+
+    pub fn screen_to_canvas(&self, point: Point) -> Point {
+        self.canvas_transform().inverse() * point
+    }
+
+    /// Resizes the view, fitting the canvas on the first call.
+    pub fn set_size(&mut self, size: Size) {
+        self.size = size;
+        if self.fit_pending {
+            self.fit_pending = false;
+            self.fit_to_window();
+        }
+    }
+
+    /// Renders the composite again if the document changed since the last
+    /// call. Returns whether it did, so the view needs repainting and
+    /// [`Viewport::last_report`] is new.
+    pub fn sync(&mut self) -> bool {
+        if !self.stale.swap(false, Ordering::Relaxed) {
+            return false;
+        }
+        self.render();
+        true
+    }
+
+    fn render(&mut self) {
+        let (rendered, report) = self.document.read().render_composite_report();
+        self.report = report;
+        match rendered {
+            Ok(image) => {
+                let image = image.to_rgba8();
+                let (width, height) = image.dimensions();
+                self.composite = Some(Image::new(Blob::new(Arc::new(image.into_raw())), Format::Rgba8, width, height));
+                self.error = None;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to render the document: {}", e);
+                self.error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Sets the zoom, keeping the canvas point under `anchor` in place.
+    pub fn zoom_about(&mut self, anchor: Point, zoom: f64) {
+        let fixed = self.screen_to_canvas(anchor);
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.offset = anchor.to_vec2() - fixed.to_vec2() * self.zoom;
+    }
+
+    /// Scales the canvas to fill the window, centered.
+    pub fn fit_to_window(&mut self) {
+        let canvas = self.canvas_size();
+        if canvas.is_empty() {
+            return;
+        }
+        let zoom = ((self.size.width - 2.0 * FIT_MARGIN) / canvas.width)
+            .min((self.size.height - 2.0 * FIT_MARGIN) / canvas.height);
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.center();
+    }
+
+    /// Shows the canvas at 100%, keeping the view's center in place.
+    pub fn actual_size(&mut self) {
+        self.zoom_about(self.size.to_rect().center(), 1.0);
+    }
+
+    fn center(&mut self) {
+        let canvas = self.canvas_size() * self.zoom;
+        self.offset = Vec2::new(self.size.width - canvas.width, self.size.height - canvas.height) / 2.0;
+    }
+
+    /// Ctrl+0 fits the canvas to the window, Ctrl+1 shows it at 100% and
+    /// Escape drops a transform drag. Returns whether the key was used.
+    pub fn handle_key(&mut self, key: &Key, modifiers: ModifiersState) -> bool {
+        if matches!(key, Key::Named(NamedKey::Escape)) && self.tool.is_dragging() {
+            self.tool.cancel();
+            return true;
+        }
+        let Key::Character(text) = key else {
+            return false;
+        };
+        if !modifiers.control_key() {
+            return false;
+        }
+        match text.as_str() {
+            "0" => self.fit_to_window(),
+            "1" => self.actual_size(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Zooms about the pointer by `lines` wheel lines, positive zooming in.
+    pub fn wheel(&mut self, point: Point, lines: f64) {
+        self.zoom_about(point, self.zoom * WHEEL_ZOOM_STEP.powf(lines));
+    }
+
+    /// Starts panning on a middle button press, or dragging a Transform
+    /// tool handle on a left press. Returns whether it did.
+    pub fn pointer_down(&mut self, point: Point, button: MouseButton) -> bool {
+        match button {
+            MouseButton::Middle => {
+                self.pan = Some(point);
+                true
+            }
+            MouseButton::Left => self.tool.pointer_down(point, self.canvas_transform()),
+            _ => false,
+        }
+    }
+
+    pub fn pointer_move(&mut self, point: Point) {
+        // Only while panning, so moves without a button don't start a pan
+        if let Some(last) = self.pan.as_mut() {
+            self.offset += point - *last;
+            *last = point;
+        }
+        if self.tool.is_dragging() {
+            if let Err(e) = self.tool.pointer_move(point, self.canvas_transform(), self.modifiers.shift_key()) {
+                tracing::warn!("Failed to preview the transform: {}", e);
+            }
+        }
+        self.cursor = Some(self.screen_to_canvas(point));
+    }
+
+    pub fn pointer_leave(&mut self) {
+        self.cursor = None;
+    }
+
+    pub fn pointer_up(&mut self) {
+        self.pan = None;
+        if let Err(e) = self.tool.pointer_up() {
+            tracing::warn!("Failed to transform the layer: {}", e);
+        }
+    }
+
+    fn paint(&self, builder: &mut SceneBuilder) {
+        let theme = &self.theme;
+        // The neutral background around the canvas
+        builder.fill(Fill::NonZero, Affine::IDENTITY, theme.viewport_background.color(), None, &self.size.to_rect());
+
+        let canvas = self.canvas_rect();
+        let shadow = canvas + Vec2::new(SHADOW_OFFSET, SHADOW_OFFSET);
+        builder.draw_blurred_rounded_rect(Affine::IDENTITY, shadow, theme.canvas_shadow.color(), 0.0, SHADOW_BLUR);
+
+        match (&self.composite, &self.error) {
+            (Some(image), None) => builder.draw_image(image, self.canvas_transform()),
+            (_, error) => {
+                builder.fill(Fill::NonZero, Affine::IDENTITY, theme.canvas_placeholder.color(), None, &canvas);
+                if let Some(error) = error {
+                    builder.draw_text(error, theme.error_text.color(), (canvas.x0 + 8.0, canvas.y0 + 16.0));
+                }
+            }
+        }
+
+        self.tool.paint_handles(builder, self.canvas_transform(), theme);
+
+        // Zoom readout in the bottom-left corner
+        let label = Rect::new(8.0, self.size.height - 28.0, 72.0, self.size.height - 8.0);
+        builder.fill(Fill::NonZero, Affine::IDENTITY, theme.overlay_background.color(), None, &label.to_rounded_rect(4.0));
+        builder.draw_text(&self.zoom_label(), theme.text.color(), (label.x0 + 8.0, label.center().y));
+    }
+}
+
+impl Drop for Viewport {
+    fn drop(&mut self) {
+        self.document.write().unsubscribe(self.listener);
+    }
 }
 
 impl Widget for Viewport {
@@ -22,85 +335,85 @@ impl Widget for Viewport {
     }
 
     fn event(&mut self, _ctx: &mut WidgetCtx, _event: &Self::Event) -> bool {
-        false
+        self.sync()
     }
 
     fn layout(&mut self, ctx: &mut WidgetCtx) {
-        let size = ctx.window_size();
+        self.set_size(ctx.window_size());
+        self.sync();
         let mut scene = Scene::new();
         let mut builder = SceneBuilder::for_scene(&mut scene);
+        self.paint(&mut builder);
+        ctx.set_scene(scene);
+    }
+}
 
-        // Draw background
-        builder.fill(
-            Fill::NonZero,
-            Affine::IDENTITY,
-            Color::rgb8(40, 44, 52),
-            None,
-            &Rect::new(0.0, 0.0, size.width, size.height),
-        );
-
-        // Draw grid
-        let grid_size = 100.0;
-        let grid_color = Color::rgba8(100, 100, 100, 100);
-        let stroke = Stroke::new(1.0);
-
-        // Vertical lines
-        for x in (0..(size.width as i32)).step_by(grid_size as usize) {
-            builder.stroke(
-                &stroke,
-                Affine::IDENTITY,
-                grid_color,
-                None,
-                &Line::new(
-                    (x as f64, 0.0),
-                    (x as f64, size.height),
-                ),
-            );
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+    use meridian_document::commands::{LayerProperty, SetLayerPropertyCommand};
+    use meridian_document::Selection;
 
-        // Horizontal lines
-        for y in (0..(size.height as i32)).step_by(grid_size as usize) {
-            builder.stroke(
-                &stroke,
-                Affine::IDENTITY,
-                grid_color,
-                None,
-                &Line::new(
-                    (0.0, y as f64),
-                    (size.width, y as f64),
-                ),
-            );
-        }
+    fn viewport_with_photo() -> (Viewport, Arc<RwLock<Document>>, meridian_document::LayerId) {
+        let mut document = Document::with_size(40, 20);
+        let asset = document.add_asset(DynamicImage::new_rgba8(40, 20));
+        let layer = document.add_asset_layer("Photo", asset);
+        let document = Arc::new(RwLock::new(document));
+        (Viewport::new(document.clone()), document, layer)
+    }
 
-        // Draw center marker
-        let center_x = size.width / 2.0;
-        let center_y = size.height / 2.0;
-        let marker_size = 20.0;
-        let marker_color = Color::WHITE;
-        let marker_stroke = Stroke::new(3.0);
-
-        builder.stroke(
-            &marker_stroke,
-            Affine::IDENTITY,
-            marker_color,
-            None,
-            &Line::new(
-                (center_x - marker_size, center_y),
-                (center_x + marker_size, center_y),
-            ),
-        );
-
-        builder.stroke(
-            &marker_stroke,
-            Affine::IDENTITY,
-            marker_color,
-            None,
-            &Line::new(
-                (center_x, center_y - marker_size),
-                (center_x, center_y + marker_size),
-            ),
-        );
+    #[test]
+    fn test_renders_only_after_composite_changes() {
+        let (mut viewport, document, layer) = viewport_with_photo();
+        assert!(viewport.sync());
+        assert_eq!(viewport.composite().map(|image| (image.width, image.height)), Some((40, 20)));
+        assert!(viewport.last_report().failures.is_empty());
+        assert!(!viewport.sync());
 
-        ctx.set_scene(scene);
+        document.write().set_selection(Selection::rect(0.0, 0.0, 4.0, 4.0));
+        assert!(!viewport.sync());
+
+        document.write()
+            .execute_command(Box::new(SetLayerPropertyCommand::new(layer, LayerProperty::Opacity(0.5))))
+            .unwrap();
+        assert!(viewport.sync());
+        document.write().undo().unwrap();
+        assert!(viewport.sync());
+        assert!(!viewport.sync());
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_zoom_keeps_the_point_under_the_cursor() {
+        let (mut viewport, _document, _) = viewport_with_photo();
+        viewport.set_size(Size::new(448.0, 248.0));
+        // Fitting leaves the margin on the constrained side, centered
+        assert_eq!(viewport.zoom(), 10.0);
+        assert_eq!(viewport.canvas_rect(), Rect::new(24.0, 24.0, 424.0, 224.0));
+        assert_eq!(viewport.zoom_label(), "1000%");
+
+        let cursor = Point::new(100.0, 50.0);
+        let under = viewport.screen_to_canvas(cursor);
+        viewport.wheel(cursor, -3.0);
+        assert!((viewport.screen_to_canvas(cursor) - under).hypot() < 1e-9);
+        assert!(viewport.zoom() < 10.0);
+
+        assert!(viewport.handle_key(&Key::Character("1".into()), ModifiersState::CONTROL));
+        assert_eq!(viewport.zoom(), 1.0);
+        assert!(!viewport.handle_key(&Key::Character("0".into()), ModifiersState::empty()));
+        assert!(viewport.handle_key(&Key::Character("0".into()), ModifiersState::CONTROL));
+        assert_eq!(viewport.zoom(), 10.0);
+
+        // Only the middle button pans
+        assert!(!viewport.pointer_down(Point::new(10.0, 10.0), MouseButton::Left));
+        assert!(viewport.pointer_down(Point::new(10.0, 10.0), MouseButton::Middle));
+        viewport.pointer_move(Point::new(30.0, 5.0));
+        viewport.pointer_up();
+        viewport.pointer_move(Point::new(90.0, 90.0));
+        assert_eq!(viewport.canvas_rect().origin(), Point::new(44.0, 19.0));
+        // 90 - 44 screen pixels at 1000% is 4.6 canvas pixels
+        assert!((viewport.cursor().unwrap() - Point::new(4.6, 7.1)).hypot() < 1e-9);
+        viewport.pointer_leave();
+        assert_eq!(viewport.cursor(), None);
+    }
+}